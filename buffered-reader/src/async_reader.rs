@@ -0,0 +1,321 @@
+use std::io;
+use std::fmt;
+use std::cmp;
+
+use std::io::{Error, ErrorKind};
+
+use futures::{Async, Poll};
+use tokio_io::AsyncRead;
+
+use super::*;
+
+/// Wraps an `AsyncRead`er.
+///
+/// Unlike the other `BufferedReader`s, this reader never blocks.  If
+/// the requested data is not yet available, the `BufferedReader`
+/// and `io::Read` methods return an error of kind
+/// `ErrorKind::WouldBlock`, and, as usual for `AsyncRead`ers, the
+/// current task is notified once more data is available.
+///
+/// Since most parsers can't resume after such an error, the data
+/// should first be buffered asynchronously using [`poll_fill`] or
+/// [`poll_fill_eof`].  The methods must be called from within a
+/// task.
+///
+/// [`poll_fill`]: #method.poll_fill
+/// [`poll_fill_eof`]: #method.poll_fill_eof
+pub struct AsyncReader<R: AsyncRead, C> {
+    reader: R,
+    buffer: Vec<u8>,
+    // The next byte to read in the buffer.
+    cursor: usize,
+    // Whether we saw an EOF.
+    saw_eof: bool,
+
+    // The user settable cookie.
+    cookie: C,
+}
+
+impl<R: AsyncRead, C> fmt::Display for AsyncReader<R, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AsyncReader")
+    }
+}
+
+impl<R: AsyncRead, C> fmt::Debug for AsyncReader<R, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AsyncReader")
+            .field("buffer data", &(self.buffer.len() - self.cursor))
+            .field("saw eof", &self.saw_eof)
+            .finish()
+    }
+}
+
+impl<R: AsyncRead> AsyncReader<R, ()> {
+    /// Instantiates a new async reader.  `reader` is the source to
+    /// wrap.
+    pub fn new(reader: R) -> Self {
+        Self::with_cookie(reader, ())
+    }
+}
+
+impl<R: AsyncRead, C> AsyncReader<R, C> {
+    /// Like `new()`, but sets a cookie, which can be retrieved using
+    /// the `cookie_ref` and `cookie_mut` methods, and set using
+    /// the `cookie_set` method.
+    pub fn with_cookie(reader: R, cookie: C) -> Self {
+        AsyncReader {
+            reader: reader,
+            buffer: Vec::new(),
+            cursor: 0,
+            saw_eof: false,
+            cookie: cookie,
+        }
+    }
+
+    /// Asynchronously buffers at least `amount` bytes.
+    ///
+    /// Returns `Async::Ready` with the number of buffered bytes
+    /// once at least `amount` bytes are buffered, or EOF has been
+    /// reached.
+    pub fn poll_fill(&mut self, amount: usize) -> Poll<usize, io::Error> {
+        match self.fill(amount) {
+            Ok(()) => Ok(Async::Ready(self.buffer.len() - self.cursor)),
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock =>
+                Ok(Async::NotReady),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously buffers all data until EOF.
+    ///
+    /// Returns `Async::Ready` with the number of buffered bytes
+    /// once EOF has been reached.  Afterwards, the reader won't
+    /// return `ErrorKind::WouldBlock` anymore.
+    pub fn poll_fill_eof(&mut self) -> Poll<usize, io::Error> {
+        while ! self.saw_eof {
+            let amount = self.buffer.len() - self.cursor + DEFAULT_BUF_SIZE;
+            if let Async::NotReady = self.poll_fill(amount)? {
+                return Ok(Async::NotReady);
+            }
+        }
+        Ok(Async::Ready(self.buffer.len() - self.cursor))
+    }
+
+    /// Returns whether EOF has been reached, i.e., whether all of
+    /// the data is buffered.
+    pub fn saw_eof(&self) -> bool {
+        self.saw_eof
+    }
+
+    /// Returns the buffered data that has not been consumed yet.
+    pub fn into_buffer(mut self) -> Vec<u8> {
+        self.buffer.drain(..self.cursor);
+        self.buffer
+    }
+
+    /// Reads from the source until at least `amount` bytes are
+    /// buffered, or EOF is reached.
+    ///
+    /// Returns an error of kind `ErrorKind::WouldBlock` if the
+    /// source is not ready.  Any data read so far is kept.
+    fn fill(&mut self, amount: usize) -> Result<(), io::Error> {
+        while ! self.saw_eof && self.buffer.len() - self.cursor < amount {
+            // Discard the data that has already been consumed.
+            if self.cursor > 0 {
+                self.buffer.drain(..self.cursor);
+                self.cursor = 0;
+            }
+
+            let len = self.buffer.len();
+            let want = cmp::max(amount - len, DEFAULT_BUF_SIZE);
+            self.buffer.resize(len + want, 0);
+
+            let result = self.reader.read(&mut self.buffer[len..]);
+            match result {
+                Ok(read) => {
+                    self.buffer.truncate(len + read);
+                    if read == 0 {
+                        self.saw_eof = true;
+                    }
+                },
+                Err(err) => {
+                    self.buffer.truncate(len);
+                    if err.kind() != ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the buffer.  Ensures that it contains at least
+    /// `amount` bytes, unless EOF is reached.
+    fn data_helper(&mut self, amount: usize, hard: bool, and_consume: bool)
+                   -> Result<&[u8], io::Error> {
+        self.fill(amount)?;
+
+        let amount_buffered = self.buffer.len() - self.cursor;
+        if hard && amount_buffered < amount {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "EOF"));
+        }
+
+        if and_consume {
+            let amount_consumed = cmp::min(amount_buffered, amount);
+            self.cursor += amount_consumed;
+            Ok(&self.buffer[self.cursor - amount_consumed..])
+        } else {
+            Ok(&self.buffer[self.cursor..])
+        }
+    }
+}
+
+impl<R: AsyncRead, C> io::Read for AsyncReader<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        return buffered_reader_generic_read_impl(self, buf);
+    }
+}
+
+impl<R: AsyncRead, C> AsyncRead for AsyncReader<R, C> {
+}
+
+impl<R: AsyncRead, C> BufferedReader<C> for AsyncReader<R, C> {
+    fn buffer(&self) -> &[u8] {
+        &self.buffer[self.cursor..]
+    }
+
+    fn data(&mut self, amount: usize) -> Result<&[u8], io::Error> {
+        return self.data_helper(amount, false, false);
+    }
+
+    fn data_hard(&mut self, amount: usize) -> Result<&[u8], io::Error> {
+        return self.data_helper(amount, true, false);
+    }
+
+    fn consume(&mut self, amount: usize) -> &[u8] {
+        // The caller can't consume more than is buffered!
+        assert!(amount <= self.buffer.len() - self.cursor,
+                "buffer contains just {} bytes, but you are trying to \
+                consume {} bytes.  Did you forget to call data()?",
+                self.buffer.len() - self.cursor, amount);
+
+        self.cursor += amount;
+        return &self.buffer[self.cursor - amount..];
+    }
+
+    fn data_consume(&mut self, amount: usize) -> Result<&[u8], io::Error> {
+        return self.data_helper(amount, false, true);
+    }
+
+    fn data_consume_hard(&mut self, amount: usize) -> Result<&[u8], io::Error> {
+        return self.data_helper(amount, true, true);
+    }
+
+    fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
+        None
+    }
+
+    fn get_ref(&self) -> Option<&BufferedReader<C>> {
+        None
+    }
+
+    fn into_inner<'b>(self: Box<Self>) -> Option<Box<BufferedReader<C> + 'b>>
+        where Self: 'b {
+        None
+    }
+
+    fn cookie_set(&mut self, cookie: C) -> C {
+        use std::mem;
+
+        mem::replace(&mut self.cookie, cookie)
+    }
+
+    fn cookie_ref(&self) -> &C {
+        &self.cookie
+    }
+
+    fn cookie_mut(&mut self) -> &mut C {
+        &mut self.cookie
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buffered_reader_async_test() {
+        let mut data : &[u8] = include_bytes!("buffered-reader-test.txt");
+        let mut bio = AsyncReader::new(&mut data);
+
+        buffered_reader_test_data_check(&mut bio);
+    }
+
+    // Returns the data in small chunks, and signals WouldBlock
+    // before each chunk.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        ready: bool,
+    }
+
+    impl<'a> io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+            if ! self.ready {
+                self.ready = true;
+                return Err(Error::new(ErrorKind::WouldBlock, "not ready"));
+            }
+            self.ready = false;
+
+            let n = cmp::min(cmp::min(buf.len(), 7), self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    impl<'a> AsyncRead for Trickle<'a> {
+    }
+
+    #[test]
+    fn would_block() {
+        let data : &[u8] = include_bytes!("buffered-reader-test.txt");
+        let mut bio = AsyncReader::new(Trickle { data: data, ready: false });
+
+        // Nothing is available yet.
+        let e = bio.data(5).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WouldBlock);
+
+        // Now, we get the first chunk.
+        assert_eq!(bio.data(5).unwrap(), &data[..7]);
+        assert_eq!(bio.buffer(), &data[..7]);
+
+        // Data that has been read is kept.
+        assert_eq!(bio.data(10).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(bio.data(10).unwrap(), &data[..14]);
+
+        // poll_fill only returns Ready once enough data is buffered.
+        let mut polls = 0;
+        loop {
+            polls += 1;
+            if let Async::Ready(n) = bio.poll_fill(20).unwrap() {
+                assert!(n >= 20);
+                break;
+            }
+        }
+        assert_eq!(polls, 2);
+        bio.consume(20);
+
+        // Buffer the rest.
+        while let Async::NotReady = bio.poll_fill_eof().unwrap() {
+        }
+        assert!(bio.saw_eof());
+        assert_eq!(bio.data_eof().unwrap(), &data[20..]);
+
+        let mut rest = Vec::new();
+        io::Read::read_to_end(&mut bio, &mut rest).unwrap();
+        assert_eq!(&rest[..], &data[20..]);
+        assert_eq!(bio.data(1).unwrap().len(), 0);
+    }
+}
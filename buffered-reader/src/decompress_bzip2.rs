@@ -4,12 +4,13 @@ use std::fmt;
 use bzip2::read::BzDecoder;
 
 use super::*;
+use decompression_limits::Limited;
 
 
 /// Decompresses the underlying `BufferedReader` using the bzip2
 /// algorithm.
 pub struct Bzip<R: BufferedReader<C>, C> {
-    reader: Generic<BzDecoder<R>, C>,
+    reader: Generic<Limited<BzDecoder<R>>, C>,
 }
 
 impl <R: BufferedReader<()>> Bzip<R, ()> {
@@ -27,9 +28,21 @@ impl <R: BufferedReader<C>, C> Bzip<R, C> {
     /// The cookie can be retrieved using the `cookie_ref` and
     /// `cookie_mut` methods, and set using the `cookie_set` method.
     pub fn with_cookie(reader: R, cookie: C) -> Self {
+        Self::with_cookie_and_limits(reader, cookie, Default::default())
+    }
+
+    /// Like `with_cookie()`, but bounds the resources used by the
+    /// decompressor.
+    ///
+    /// See [`DecompressionLimits`] for details.
+    ///
+    ///   [`DecompressionLimits`]: struct.DecompressionLimits.html
+    pub fn with_cookie_and_limits(reader: R, cookie: C,
+                                  limits: DecompressionLimits)
+                                  -> Self {
         Bzip {
             reader: Generic::with_cookie(
-                BzDecoder::new(reader), None, cookie),
+                Limited::new(BzDecoder::new(reader), limits), None, cookie),
         }
     }
 }
@@ -101,17 +114,17 @@ impl<R: BufferedReader<C>, C> BufferedReader<C> for Bzip<R, C> {
     }
 
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
-        Some(self.reader.reader.get_mut())
+        Some(self.reader.reader.inner.get_mut())
     }
 
     fn get_ref(&self) -> Option<&BufferedReader<C>> {
-        Some(self.reader.reader.get_ref())
+        Some(self.reader.reader.inner.get_ref())
     }
 
     fn into_inner<'b>(self: Box<Self>)
             -> Option<Box<BufferedReader<C> + 'b>> where Self: 'b {
         // Strip the outer box.
-        Some(Box::new(self.reader.reader.into_inner()))
+        Some(Box::new(self.reader.reader.inner.into_inner()))
     }
 
     fn cookie_set(&mut self, cookie: C) -> C {
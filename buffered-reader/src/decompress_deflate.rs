@@ -5,11 +5,12 @@ use flate2::read::DeflateDecoder;
 use flate2::read::ZlibDecoder;
 
 use super::*;
+use decompression_limits::Limited;
 
 /// Decompresses the underlying `BufferedReader` using the deflate
 /// algorithm.
 pub struct Deflate<R: BufferedReader<C>, C> {
-    reader: Generic<DeflateDecoder<R>, C>,
+    reader: Generic<Limited<DeflateDecoder<R>>, C>,
 }
 
 impl <R: BufferedReader<()>> Deflate<R, ()> {
@@ -27,9 +28,21 @@ impl <R: BufferedReader<C>, C> Deflate<R, C> {
     /// The cookie can be retrieved using the `cookie_ref` and
     /// `cookie_mut` methods, and set using the `cookie_set` method.
     pub fn with_cookie(reader: R, cookie: C) -> Self {
+        Self::with_cookie_and_limits(reader, cookie, Default::default())
+    }
+
+    /// Like `with_cookie()`, but bounds the resources used by the
+    /// decompressor.
+    ///
+    /// See [`DecompressionLimits`] for details.
+    ///
+    ///   [`DecompressionLimits`]: struct.DecompressionLimits.html
+    pub fn with_cookie_and_limits(reader: R, cookie: C,
+                                  limits: DecompressionLimits)
+                                  -> Self {
         Deflate {
             reader: Generic::with_cookie(
-                DeflateDecoder::new(reader), None, cookie),
+                Limited::new(DeflateDecoder::new(reader), limits), None, cookie),
         }
     }
 }
@@ -102,17 +115,17 @@ impl<R: BufferedReader<C>, C> BufferedReader<C>
     }
 
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
-        Some(self.reader.reader.get_mut())
+        Some(self.reader.reader.inner.get_mut())
     }
 
     fn get_ref(&self) -> Option<&BufferedReader<C>> {
-        Some(self.reader.reader.get_ref())
+        Some(self.reader.reader.inner.get_ref())
     }
 
     fn into_inner<'b>(self: Box<Self>)
             -> Option<Box<BufferedReader<C> + 'b>> where Self: 'b {
         // Strip the outer box.
-        Some(Box::new(self.reader.reader.into_inner()))
+        Some(Box::new(self.reader.reader.inner.into_inner()))
     }
 
     fn cookie_set(&mut self, cookie: C) -> C {
@@ -131,7 +144,7 @@ impl<R: BufferedReader<C>, C> BufferedReader<C>
 /// Decompresses the underlying `BufferedReader` using the zlib
 /// algorithm.
 pub struct Zlib<R: BufferedReader<C>, C> {
-    reader: Generic<ZlibDecoder<R>, C>,
+    reader: Generic<Limited<ZlibDecoder<R>>, C>,
 }
 
 impl <R: BufferedReader<()>> Zlib<R, ()> {
@@ -149,9 +162,21 @@ impl <R: BufferedReader<C>, C> Zlib<R, C> {
     /// The cookie can be retrieved using the `cookie_ref` and
     /// `cookie_mut` methods, and set using the `cookie_set` method.
     pub fn with_cookie(reader: R, cookie: C) -> Self {
+        Self::with_cookie_and_limits(reader, cookie, Default::default())
+    }
+
+    /// Like `with_cookie()`, but bounds the resources used by the
+    /// decompressor.
+    ///
+    /// See [`DecompressionLimits`] for details.
+    ///
+    ///   [`DecompressionLimits`]: struct.DecompressionLimits.html
+    pub fn with_cookie_and_limits(reader: R, cookie: C,
+                                  limits: DecompressionLimits)
+                                  -> Self {
         Zlib {
             reader: Generic::with_cookie(
-                ZlibDecoder::new(reader), None, cookie),
+                Limited::new(ZlibDecoder::new(reader), limits), None, cookie),
         }
     }
 }
@@ -224,17 +249,17 @@ impl<R: BufferedReader<C>, C> BufferedReader<C>
     }
 
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
-        Some(self.reader.reader.get_mut())
+        Some(self.reader.reader.inner.get_mut())
     }
 
     fn get_ref(&self) -> Option<&BufferedReader<C>> {
-        Some(self.reader.reader.get_ref())
+        Some(self.reader.reader.inner.get_ref())
     }
 
     fn into_inner<'b>(self: Box<Self>)
             -> Option<Box<BufferedReader<C> + 'b>> where Self: 'b {
         // Strip the outer box.
-        Some(Box::new(self.reader.reader.into_inner()))
+        Some(Box::new(self.reader.reader.inner.into_inner()))
     }
 
     fn cookie_set(&mut self, cookie: C) -> C {
@@ -316,4 +341,61 @@ mod test {
             }
         }
     }
+
+    // Makes sure that decompression bombs are defused.
+    #[test]
+    fn limits() {
+        use flate2::write::DeflateEncoder;
+        use flate2::Compression;
+        use std::io::prelude::*;
+
+        // 10 MiB of zeros compress to about 10 KiB.
+        let size = 10 * 1024 * 1024;
+        let mut bomb = Vec::new();
+        {
+            let mut encoder =
+                DeflateEncoder::new(&mut bomb, Compression::best());
+            encoder.write_all(&vec![0u8; size][..]).unwrap();
+            encoder.try_finish().unwrap();
+        }
+        assert!(bomb.len() < size / 100);
+
+        fn exceeded(e: io::Error) -> DecompressionLimitExceeded {
+            *e.get_ref().unwrap()
+                .downcast_ref::<DecompressionLimitExceeded>().unwrap()
+        }
+
+        // No limits.
+        let mut reader = Deflate::new(Generic::new(&bomb[..], None));
+        assert_eq!(reader.data_eof().unwrap().len(), size);
+
+        // Limit the size.
+        let mut reader = Deflate::with_cookie_and_limits(
+            Generic::new(&bomb[..], None), (),
+            DecompressionLimits {
+                max_size: Some(1024 * 1024),
+                max_ratio: None,
+            });
+        let e = reader.data_eof().unwrap_err();
+        assert_eq!(exceeded(e), DecompressionLimitExceeded::Size(1024 * 1024));
+
+        // A generous limit doesn't interfere.
+        let mut reader = Deflate::with_cookie_and_limits(
+            Generic::new(&bomb[..], None), (),
+            DecompressionLimits {
+                max_size: Some(size as u64),
+                max_ratio: None,
+            });
+        assert_eq!(reader.data_eof().unwrap().len(), size);
+
+        // Limit the ratio.
+        let mut reader = Deflate::with_cookie_and_limits(
+            Generic::new(&bomb[..], None), (),
+            DecompressionLimits {
+                max_size: None,
+                max_ratio: Some(100),
+            });
+        let e = reader.data_eof().unwrap_err();
+        assert_eq!(exceeded(e), DecompressionLimitExceeded::Ratio(100));
+    }
 }
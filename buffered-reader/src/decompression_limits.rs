@@ -0,0 +1,168 @@
+use std::io;
+use std::fmt;
+use std::cmp;
+use std::error;
+
+#[cfg(feature = "compression-deflate")]
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+#[cfg(feature = "compression-bzip2")]
+use bzip2::read::BzDecoder;
+
+/// The ratio is only enforced once this many bytes have been
+/// produced.
+///
+/// Small inputs can legitimately have a large compression ratio
+/// (e.g., a short run of zeros), and the decompressors read their
+/// input in chunks, which makes the ratio meaningless for the first
+/// few bytes.
+pub const RATIO_THRESHOLD: u64 = 1024 * 1024;
+
+/// Limits the resources used by a decompressor.
+///
+/// A small amount of compressed data can expand to an enormous
+/// amount of data (a so-called decompression bomb).  When processing
+/// untrusted data, it is prudent to bound the amount of data that a
+/// decompressor is allowed to produce.  By default, there are no
+/// limits.
+///
+/// If a limit is exceeded, the decompressor returns an `io::Error`
+/// of kind `io::ErrorKind::Other` wrapping a
+/// [`DecompressionLimitExceeded`].
+///
+///   [`DecompressionLimitExceeded`]: enum.DecompressionLimitExceeded.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecompressionLimits {
+    /// The maximum number of bytes the decompressor may produce.
+    pub max_size: Option<u64>,
+    /// The maximum ratio between the number of bytes produced and
+    /// the number of bytes consumed.
+    ///
+    /// This is only enforced once more than [`RATIO_THRESHOLD`]
+    /// bytes have been produced.
+    ///
+    ///   [`RATIO_THRESHOLD`]: constant.RATIO_THRESHOLD.html
+    pub max_ratio: Option<u64>,
+}
+
+/// Indicates that a decompressor exceeded its limits.
+///
+/// See [`DecompressionLimits`].
+///
+///   [`DecompressionLimits`]: struct.DecompressionLimits.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecompressionLimitExceeded {
+    /// The decompressed data exceeds the given size.
+    Size(u64),
+    /// The compression ratio exceeds the given ratio.
+    Ratio(u64),
+}
+
+impl fmt::Display for DecompressionLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecompressionLimitExceeded::Size(max) =>
+                write!(f, "Decompressed data exceeds {} bytes", max),
+            DecompressionLimitExceeded::Ratio(max) =>
+                write!(f, "Compression ratio exceeds {}:1", max),
+        }
+    }
+}
+
+impl error::Error for DecompressionLimitExceeded {}
+
+impl From<DecompressionLimitExceeded> for io::Error {
+    fn from(e: DecompressionLimitExceeded) -> Self {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+}
+
+/// Provides access to a decompressor's counters.
+pub(crate) trait Counters {
+    /// Returns the number of bytes consumed.
+    fn total_in(&self) -> u64;
+    /// Returns the number of bytes produced.
+    fn total_out(&self) -> u64;
+}
+
+#[cfg(feature = "compression-deflate")]
+impl<R: io::Read> Counters for DeflateDecoder<R> {
+    fn total_in(&self) -> u64 {
+        DeflateDecoder::total_in(self)
+    }
+    fn total_out(&self) -> u64 {
+        DeflateDecoder::total_out(self)
+    }
+}
+
+#[cfg(feature = "compression-deflate")]
+impl<R: io::Read> Counters for ZlibDecoder<R> {
+    fn total_in(&self) -> u64 {
+        ZlibDecoder::total_in(self)
+    }
+    fn total_out(&self) -> u64 {
+        ZlibDecoder::total_out(self)
+    }
+}
+
+#[cfg(feature = "compression-bzip2")]
+impl<R: io::Read> Counters for BzDecoder<R> {
+    fn total_in(&self) -> u64 {
+        BzDecoder::total_in(self)
+    }
+    fn total_out(&self) -> u64 {
+        BzDecoder::total_out(self)
+    }
+}
+
+/// Enforces `DecompressionLimits` on a decompressor.
+pub(crate) struct Limited<T: io::Read + Counters> {
+    pub(crate) inner: T,
+    limits: DecompressionLimits,
+}
+
+impl<T: io::Read + Counters> Limited<T> {
+    pub(crate) fn new(inner: T, limits: DecompressionLimits) -> Self {
+        Limited {
+            inner: inner,
+            limits: limits,
+        }
+    }
+
+    fn check(&self) -> Result<(), DecompressionLimitExceeded> {
+        let total_out = self.inner.total_out();
+
+        if let Some(max) = self.limits.max_size {
+            if total_out > max {
+                return Err(DecompressionLimitExceeded::Size(max));
+            }
+        }
+
+        if let Some(max) = self.limits.max_ratio {
+            if total_out > RATIO_THRESHOLD
+                && total_out > max.saturating_mul(self.inner.total_in())
+            {
+                return Err(DecompressionLimitExceeded::Ratio(max));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: io::Read + Counters> io::Read for Limited<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        // Don't decompress (much) more than we are allowed to: one
+        // byte more than the limit is enough to detect a violation.
+        let len = if let Some(max) = self.limits.max_size {
+            let allowance =
+                max.saturating_sub(self.inner.total_out()).saturating_add(1);
+            cmp::min(buf.len() as u64, allowance) as usize
+        } else {
+            buf.len()
+        };
+
+        let amount = self.inner.read(&mut buf[..len])?;
+        self.check()?;
+        Ok(amount)
+    }
+}
@@ -121,6 +121,10 @@ impl<'a, C> BufferedReader<C> for Dup<'a, C> {
         Ok(data)
     }
 
+    fn position(&self) -> Option<u64> {
+        self.reader.position().map(|p| p + self.cursor as u64)
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         Some(&mut self.reader)
     }
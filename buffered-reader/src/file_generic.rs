@@ -71,6 +71,14 @@ impl<C> BufferedReader<C> for File<C> {
         self.0.data_consume_hard(amount)
     }
 
+    fn position(&self) -> Option<u64> {
+        self.0.position()
+    }
+
+    fn seek(&mut self, position: u64) -> io::Result<()> {
+        self.0.seek_reader(position)
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         None
     }
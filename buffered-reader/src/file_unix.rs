@@ -204,6 +204,20 @@ impl<'a, C> BufferedReader<C> for File<'a, C> {
         }
     }
 
+    fn position(&self) -> Option<u64> {
+        match self.0 {
+            Imp::Generic(ref reader) => reader.position(),
+            Imp::MMAP { ref reader, .. } => reader.position(),
+        }
+    }
+
+    fn seek(&mut self, position: u64) -> io::Result<()> {
+        match self.0 {
+            Imp::Generic(ref mut reader) => reader.seek_reader(position),
+            Imp::MMAP { ref mut reader, .. } => reader.seek(position),
+        }
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         None
     }
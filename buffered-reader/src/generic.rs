@@ -18,6 +18,9 @@ pub struct Generic<T: io::Read, C> {
     // XXX: This is pub for the decompressors.  It would be better to
     // change this to some accessor method.
     pub reader: Box<T>,
+    // The number of bytes read from `reader`, or, after a seek, the
+    // position of `reader`.
+    reader_position: u64,
     // Whether we saw an EOF.
     saw_eof: bool,
     // The last error that we encountered, but have not yet returned.
@@ -74,6 +77,7 @@ impl<T: io::Read, C> Generic<T, C> {
                 if let Some(s) = preferred_chunk_size { s }
                 else { DEFAULT_BUF_SIZE },
             reader: Box::new(reader),
+            reader_position: 0,
             saw_eof: false,
             error: None,
             cookie: cookie,
@@ -141,6 +145,7 @@ impl<T: io::Read, C> Generic<T, C> {
 
             if amount_read > 0 {
                 // We read something.
+                self.reader_position += amount_read as u64;
 
                 if let Some(ref buffer) = self.buffer {
                     // We need to copy in the old data.
@@ -198,6 +203,24 @@ impl<T: io::Read, C> Generic<T, C> {
     }
 }
 
+impl<T: io::Read + io::Seek, C> Generic<T, C> {
+    /// Seeks the wrapped reader to `position`, and discards any
+    /// buffered data.
+    ///
+    /// This is used to implement `BufferedReader::seek` for readers
+    /// that wrap a `Generic` over a seekable source.
+    pub(crate) fn seek_reader(&mut self, position: u64)
+                              -> Result<(), io::Error> {
+        self.reader.seek(io::SeekFrom::Start(position))?;
+        self.buffer = None;
+        self.cursor = 0;
+        self.reader_position = position;
+        self.saw_eof = false;
+        self.error = None;
+        Ok(())
+    }
+}
+
 impl<T: io::Read, C> io::Read for Generic<T, C> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
         return buffered_reader_generic_read_impl(self, buf);
@@ -252,6 +275,10 @@ impl<T: io::Read, C> BufferedReader<C> for Generic<T, C> {
         return self.data_helper(amount, true, true);
     }
 
+    fn position(&self) -> Option<u64> {
+        Some(self.reader_position - self.buffer().len() as u64)
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         None
     }
@@ -227,6 +227,10 @@
 extern crate flate2;
 #[cfg(feature = "compression-bzip2")]
 extern crate bzip2;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio_io;
 extern crate libc;
 
 use std::io;
@@ -244,6 +248,10 @@ mod eof;
 mod decompress_deflate;
 #[cfg(feature = "compression-bzip2")]
 mod decompress_bzip2;
+#[cfg(any(feature = "compression-deflate", feature = "compression-bzip2"))]
+mod decompression_limits;
+#[cfg(feature = "async")]
+mod async_reader;
 
 pub use self::generic::Generic;
 pub use self::memory::Memory;
@@ -257,6 +265,14 @@ pub use self::decompress_deflate::Deflate;
 pub use self::decompress_deflate::Zlib;
 #[cfg(feature = "compression-bzip2")]
 pub use self::decompress_bzip2::Bzip;
+#[cfg(feature = "async")]
+pub use self::async_reader::AsyncReader;
+#[cfg(any(feature = "compression-deflate", feature = "compression-bzip2"))]
+pub use self::decompression_limits::{
+    DecompressionLimits,
+    DecompressionLimitExceeded,
+    RATIO_THRESHOLD,
+};
 
 // These are the different File implementations.  We
 // include the modules unconditionally, so that we catch bitrot early.
@@ -789,6 +805,34 @@ pub trait BufferedReader<C> : io::Read + fmt::Debug + fmt::Display {
         Ok(at_least_one_byte)
     }
 
+    /// Returns the position of the next byte in the source, if
+    /// known.
+    ///
+    /// The position is relative to the start of the source, or, if
+    /// the source is an `io::Read`er, relative to the point at
+    /// which it was wrapped.  Readers that wrap another
+    /// `BufferedReader` without transforming the data (e.g., a
+    /// `Limitor`) return the position of the wrapped reader.
+    /// Readers that transform the data (e.g., the decompressors)
+    /// don't know it, and return `None`.  This is also what the
+    /// default implementation does.
+    fn position(&self) -> Option<u64> {
+        None
+    }
+
+    /// Seeks to the given position in the source.
+    ///
+    /// `position` is interpreted as by `position()`.  Any buffered
+    /// data is discarded.
+    ///
+    /// Only readers that wrap seekable sources support this, i.e.,
+    /// `Memory` and `File`.  The default implementation returns an
+    /// error of kind `ErrorKind::Other`.
+    fn seek(&mut self, _position: u64) -> Result<(), io::Error> {
+        Err(io::Error::new(io::ErrorKind::Other,
+                           format!("{} does not support seeking", self)))
+    }
+
     /// Returns the underlying reader, if any.
     ///
     /// To allow this to work with `BufferedReader` traits, it is
@@ -924,6 +968,14 @@ impl <'a, C> BufferedReader<C> for Box<BufferedReader<C> + 'a> {
         return self.as_mut().drop_eof();
     }
 
+    fn position(&self) -> Option<u64> {
+        self.as_ref().position()
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), io::Error> {
+        self.as_mut().seek(position)
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         // Strip the outer box.
         self.as_mut().get_mut()
@@ -1121,4 +1173,61 @@ mod test {
         // Doesn't match (eof).
         assert!(reader.drop_through(b"def").is_err())
     }
+
+    #[test]
+    fn position_and_seek() {
+        use std::str;
+
+        fn check<R: BufferedReader<()>>(mut bio: R) {
+            // Each number is 4 bytes plus a newline character.
+            let line = |i: usize| format!("{:04}\n", i);
+
+            assert_eq!(bio.position(), Some(0));
+            bio.data_consume_hard(5).unwrap();
+            assert_eq!(bio.position(), Some(5));
+            bio.data(100).unwrap();
+            assert_eq!(bio.position(), Some(5));
+
+            bio.seek(5 * 1234).unwrap();
+            assert_eq!(bio.position(), Some(5 * 1234));
+            assert_eq!(str::from_utf8(&bio.data_consume_hard(5).unwrap()[..5])
+                       .unwrap(), line(1234));
+            assert_eq!(bio.position(), Some(5 * 1235));
+
+            // Seek backwards.
+            bio.seek(5 * 7).unwrap();
+            assert_eq!(str::from_utf8(&bio.data_hard(5).unwrap()[..5])
+                       .unwrap(), line(7));
+
+            // Readers that don't transform the data report the
+            // position of the source.
+            let mut bio = Limitor::new(Box::new(bio), 100);
+            assert_eq!(bio.position(), Some(5 * 7));
+            bio.data_consume_hard(5).unwrap();
+            assert_eq!(bio.position(), Some(5 * 8));
+            assert!(bio.seek(0).is_err());
+
+            let mut bio = Dup::new(Box::new(bio));
+            bio.data_consume_hard(10).unwrap();
+            assert_eq!(bio.position(), Some(5 * 10));
+            bio.rewind();
+            assert_eq!(bio.position(), Some(5 * 8));
+        }
+
+        let data : &[u8] = include_bytes!("buffered-reader-test.txt");
+        check(Memory::new(data));
+        assert!(Memory::new(data).seek(data.len() as u64 + 1).is_err());
+
+        let path : std::path::PathBuf = [env!("CARGO_MANIFEST_DIR"),
+                                         "src",
+                                         "buffered-reader-test.txt"]
+            .iter().collect();
+        check(File::open(&path).unwrap());
+
+        // A plain reader is not seekable, but we know the position.
+        let mut bio = Generic::new(data, None);
+        bio.data_consume_hard(5).unwrap();
+        assert_eq!(bio.position(), Some(5));
+        assert!(bio.seek(0).is_err());
+    }
 }
@@ -124,6 +124,10 @@ impl<'a, C> BufferedReader<C> for Limitor<'a, C> {
         self.limit == 0
     }
 
+    fn position(&self) -> Option<u64> {
+        self.reader.position()
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         Some(&mut self.reader)
     }
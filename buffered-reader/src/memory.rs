@@ -107,6 +107,19 @@ impl<'a, C> BufferedReader<C> for Memory<'a, C> {
         return Ok(self.consume(amount));
     }
 
+    fn position(&self) -> Option<u64> {
+        Some(self.cursor as u64)
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), io::Error> {
+        if position > self.buffer.len() as u64 {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "position is beyond the end of the buffer"));
+        }
+        self.cursor = position as usize;
+        Ok(())
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         None
     }
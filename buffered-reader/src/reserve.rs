@@ -126,6 +126,10 @@ impl<'a, C> BufferedReader<C> for Reserve<'a, C> {
         Ok(self.consume(amount))
     }
 
+    fn position(&self) -> Option<u64> {
+        self.reader.position()
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<C>> {
         Some(&mut self.reader)
     }
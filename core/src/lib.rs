@@ -26,6 +26,7 @@ extern crate failure;
 use std::fmt;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempdir::TempDir;
 
 /// A `Context` for Sequoia.
@@ -63,6 +64,7 @@ pub struct Context {
     lib: PathBuf,
     network_policy: NetworkPolicy,
     ipc_policy: IPCPolicy,
+    ipc_idle_timeout: Option<Duration>,
     ephemeral: bool,
     cleanup: bool,
 }
@@ -74,6 +76,7 @@ impl Clone for Context {
             lib: self.lib.clone(),
             network_policy: self.network_policy,
             ipc_policy: self.ipc_policy,
+            ipc_idle_timeout: self.ipc_idle_timeout,
             ephemeral: self.ephemeral,
             cleanup: false, // Prevent cleanup.
         }
@@ -113,6 +116,7 @@ impl Context {
             lib: prefix().join("lib").join("sequoia"),
             network_policy: NetworkPolicy::Encrypted,
             ipc_policy: IPCPolicy::Robust,
+            ipc_idle_timeout: None,
             ephemeral: false,
             cleanup: false,
         })
@@ -138,6 +142,13 @@ impl Context {
         &self.ipc_policy
     }
 
+    /// Returns the time after which idle background servers exit.
+    ///
+    /// `None` means that background servers run forever.
+    pub fn ipc_idle_timeout(&self) -> Option<Duration> {
+        self.ipc_idle_timeout
+    }
+
     /// Returns whether or not this is an ephemeral context.
     pub fn ephemeral(&self) -> bool {
         self.ephemeral
@@ -252,6 +263,23 @@ impl Config {
         ::std::mem::replace(&mut self.0.ipc_policy, policy)
     }
 
+    /// Sets the time after which idle background servers exit.
+    ///
+    /// A background server is idle if no client is connected to it.
+    /// Servers exit cleanly after being idle for `timeout`, and are
+    /// restarted on demand.  If `timeout` is `None`, background
+    /// servers run forever, which is the default.
+    pub fn ipc_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.set_ipc_idle_timeout(timeout);
+        self
+    }
+
+    /// Sets the time after which idle background servers exit.
+    pub fn set_ipc_idle_timeout(&mut self, timeout: Option<Duration>)
+                                -> Option<Duration> {
+        ::std::mem::replace(&mut self.0.ipc_idle_timeout, timeout)
+    }
+
     /// Makes this context ephemeral.
     pub fn ephemeral(mut self) -> Self {
         self.set_ephemeral();
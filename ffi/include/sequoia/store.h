@@ -107,6 +107,84 @@ typedef struct sq_log *sq_log_t;
 /*/
 void sq_log_free (sq_log_t log);
 
+/*/
+/// Describes a change to a key or binding.
+/*/
+typedef enum sq_event_kind {
+  /*/
+  /// The key changed, e.g. because an update was merged.
+  /*/
+  SQ_EVENT_KEY_UPDATED = 0,
+
+  /*/
+  /// A binding was added.
+  /*/
+  SQ_EVENT_BINDING_ADDED = 1,
+
+  /*/
+  /// A binding was bound to a different key.
+  /*/
+  SQ_EVENT_BINDING_ROTATED = 2,
+
+  /*/
+  /// A binding was deleted.
+  /*/
+  SQ_EVENT_BINDING_DELETED = 3,
+
+  /* Dummy value to make sure the enumeration has a defined size.  Do
+     not use this value.  */
+  SQ_EVENT_KIND_FORCE_WIDTH = INT_MAX,
+} sq_event_kind_t;
+
+/*/
+/// Represents a change to a key or binding.
+/*/
+struct sq_event {
+  /*/
+  /// Describes what changed.
+  /*/
+  sq_event_kind_t kind;
+
+  /*/
+  /// The fingerprint of the key concerned.
+  ///
+  /// If a binding was rotated, this is the new key.
+  /*/
+  pgp_fingerprint_t fingerprint;
+
+  /*/
+  /// The realm of the store containing the changed binding.
+  ///
+  /// `NULL` if the event is not about a binding.
+  /*/
+  char *realm;
+
+  /*/
+  /// The name of the store containing the changed binding.
+  ///
+  /// `NULL` if the event is not about a binding.
+  /*/
+  char *store;
+
+  /*/
+  /// The label of the changed binding.
+  ///
+  /// `NULL` if the event is not about a binding.
+  /*/
+  char *label;
+};
+typedef struct sq_event *sq_event_t;
+
+/*/
+/// Frees a sq_event_t.
+/*/
+void sq_event_free (sq_event_t event);
+
+/*/
+/// A subscription to changes.
+/*/
+typedef struct sq_subscription *sq_subscription_t;
+
 /*/
 /// Counter and timestamps.
 /*/
@@ -309,6 +387,44 @@ sq_binding_iter_t sq_store_iter (sq_context_t ctx, sq_store_t store);
 /*/
 sq_log_iter_t sq_store_log (sq_context_t ctx, sq_store_t store);
 
+/*/
+/// Subscribes to changes to the bindings in this store.
+///
+/// Events are reported for bindings being added, rotated, or
+/// deleted, and for changes to the keys they are bound to, for
+/// example if an update was merged.
+/*/
+sq_subscription_t sq_store_subscribe (sq_context_t ctx, sq_store_t store);
+
+/*/
+/// Subscribes to changes to keys in the common key pool.
+/*/
+sq_subscription_t sq_store_subscribe_pool (sq_context_t ctx);
+
+/*/
+/// Returns the next event, blocking until one is available.
+///
+/// Returns `NULL` if the connection to the store is lost.
+/*/
+sq_event_t sq_subscription_next (sq_subscription_t subscription);
+
+/*/
+/// Returns the next event, if one is available.
+///
+/// Returns `NULL` if no event is pending, or if the connection to
+/// the store is lost.  In the latter case, the error is stored in
+/// the context.
+/*/
+sq_event_t sq_subscription_try_next (sq_context_t ctx,
+                                     sq_subscription_t subscription);
+
+/*/
+/// Frees a sq_subscription_t.
+///
+/// This cancels the subscription.
+/*/
+void sq_subscription_free (sq_subscription_t subscription);
+
 /*/
 /// Returns the `sq_stats_t` of this binding.
 /*/
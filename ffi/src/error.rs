@@ -66,6 +66,8 @@ impl<'a> FromSequoiaError<'a> for Status {
                     Status::IndexOutOfRange,
                 &openpgp::Error::UnsupportedTPK(_) =>
                     Status::UnsupportedTPK,
+                &openpgp::Error::ResourceLimitExceeded(_) =>
+                    Status::ResourceLimitExceeded,
             }
         }
 
@@ -23,13 +23,14 @@
 //! ```
 
 
-use libc::{uint8_t, uint64_t, c_char};
+use libc::{uint8_t, uint64_t, c_char, c_int};
 use std::ptr;
 
 extern crate sequoia_openpgp as openpgp;
 
 use sequoia_store::{
     self, Store, StoreIter, Binding, BindingIter, Key, KeyIter, LogIter, Pool,
+    Subscription, EventKind,
 };
 
 use super::error::Status;
@@ -39,6 +40,7 @@ use ::openpgp::fingerprint::Fingerprint;
 use ::openpgp::keyid::KeyID;
 use ::openpgp::tpk::TPK;
 use RefRaw;
+use MoveFromRaw;
 use MoveIntoRaw;
 use MoveResultIntoRaw;
 use Maybe;
@@ -351,6 +353,68 @@ fn sq_store_log(ctx: *mut Context, store: *const Store)
     ffi_try_box!(store.log())
 }
 
+/// Subscribes to changes to the bindings in this store.
+///
+/// Events are reported for bindings being added, rotated, or
+/// deleted, and for changes to the keys they are bound to, for
+/// example if an update was merged.
+#[::ffi_catch_abort] #[no_mangle] pub extern "C"
+fn sq_store_subscribe(ctx: *mut Context, store: *const Store)
+                      -> *mut Subscription {
+    let ctx = ffi_param_ref_mut!(ctx);
+    ffi_make_fry_from_ctx!(ctx);
+    let store = ffi_param_ref!(store);
+
+    ffi_try_box!(store.subscribe())
+}
+
+/// Subscribes to changes to keys in the common key pool.
+#[::ffi_catch_abort] #[no_mangle] pub extern "C"
+fn sq_store_subscribe_pool(ctx: *mut Context) -> *mut Subscription {
+    let ctx = ffi_param_ref_mut!(ctx);
+    ffi_make_fry_from_ctx!(ctx);
+
+    ffi_try_box!(Pool::subscribe(&ctx.c))
+}
+
+/// Returns the next event, blocking until one is available.
+///
+/// Returns `NULL` if the connection to the store is lost.
+#[::ffi_catch_abort] #[no_mangle] pub extern "C"
+fn sq_subscription_next(subscription: *mut Subscription) -> *mut Event {
+    let subscription = ffi_param_ref_mut!(subscription);
+    match subscription.next() {
+        Some(e) => box_raw!(Event::new(e)),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Returns the next event, if one is available.
+///
+/// Returns `NULL` if no event is pending, or if the connection to
+/// the store is lost.  In the latter case, the error is stored in
+/// the context.
+#[::ffi_catch_abort] #[no_mangle] pub extern "C"
+fn sq_subscription_try_next(ctx: *mut Context,
+                            subscription: *mut Subscription)
+                            -> *mut Event {
+    let ctx = ffi_param_ref_mut!(ctx);
+    ffi_make_fry_from_ctx!(ctx);
+    let subscription = ffi_param_ref_mut!(subscription);
+    match ffi_try!(subscription.try_next()) {
+        Some(e) => box_raw!(Event::new(e)),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Frees a sq_subscription_t.
+///
+/// This cancels the subscription.
+#[::ffi_catch_abort] #[no_mangle] pub extern "C"
+fn sq_subscription_free(subscription: Option<&mut Subscription>) {
+    ffi_free!(subscription)
+}
+
 /// Frees a sq_binding_t.
 #[::ffi_catch_abort] #[no_mangle] pub extern "C"
 fn sq_binding_free(binding: Option<&mut Binding>) {
@@ -386,6 +450,21 @@ fn sq_log_free(log: Option<&mut Log>) {
     }
 }
 
+/// Frees a sq_event_t.
+#[::ffi_catch_abort] #[no_mangle] pub extern "C"
+fn sq_event_free(event: Option<&mut Event>) {
+    if let Some(event) = event {
+        let event = unsafe { Box::from_raw(event) };
+        drop(event.fingerprint.move_from_raw());
+        unsafe {
+            libc::free(event.realm as *mut libc::c_void);
+            libc::free(event.store as *mut libc::c_void);
+            libc::free(event.label as *mut libc::c_void);
+        }
+        drop(event)
+    }
+}
+
 /// Returns the `sq_stats_t` of this binding.
 #[::ffi_catch_abort] #[no_mangle] pub extern "C"
 fn sq_binding_stats(ctx: *mut Context, binding: *const Binding)
@@ -656,3 +735,58 @@ pub struct Log {
     /// May be `NULL`.
     pub error: *mut c_char,
 }
+
+/// Represents a change to a key or binding.
+#[repr(C)]
+pub struct Event {
+    /// Describes what changed.
+    ///
+    /// 0 means the key changed, 1 that a binding was added, 2 that a
+    /// binding was bound to a different key, and 3 that a binding was
+    /// deleted.
+    pub kind: c_int,
+
+    /// The fingerprint of the key concerned.
+    ///
+    /// If a binding was rotated, this is the new key.
+    pub fingerprint: *mut Fingerprint,
+
+    /// The realm of the store containing the changed binding.
+    ///
+    /// `NULL` if the event is not about a binding.
+    pub realm: *mut c_char,
+
+    /// The name of the store containing the changed binding.
+    ///
+    /// `NULL` if the event is not about a binding.
+    pub store: *mut c_char,
+
+    /// The label of the changed binding.
+    ///
+    /// `NULL` if the event is not about a binding.
+    pub label: *mut c_char,
+}
+
+impl Event {
+    fn new(e: sequoia_store::Event) -> Event {
+        let (realm, store, label) = match e.binding {
+            Some((realm, store, label)) =>
+                (ffi_return_string!(&realm), ffi_return_string!(&store),
+                 ffi_return_string!(&label)),
+            None => (ptr::null_mut(), ptr::null_mut(), ptr::null_mut()),
+        };
+
+        Event {
+            kind: match e.kind {
+                EventKind::KeyUpdated => 0,
+                EventKind::BindingAdded => 1,
+                EventKind::BindingRotated => 2,
+                EventKind::BindingDeleted => 3,
+            },
+            fingerprint: e.fingerprint.move_into_raw(),
+            realm: realm,
+            store: store,
+            label: label,
+        }
+    }
+}
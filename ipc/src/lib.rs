@@ -14,7 +14,15 @@
 //! authenticate us.  If the file does not exist, is malformed, or
 //! does not point to a usable server, we start a new one on demand.
 //!
-//! This design mimics Unix sockets, but works on Windows too.
+//! External servers listen on a Unix domain socket next to the
+//! rendezvous point.  Both the server and the client check that the
+//! process on the other end of the socket runs as the same user.  If
+//! the socket cannot be created, e.g. because the path is too long,
+//! we fall back to a TCP socket on localhost.  Internal servers
+//! always use TCP sockets.
+//!
+//! The client starting a server uses the connection it handed the
+//! cookie to the server with for its own requests.
 //!
 //! # External vs internal servers
 //!
@@ -31,43 +39,72 @@
 //!
 //! [IPCPolicy]: ../../sequoia_core/enum.IPCPolicy.html
 //!
+//! # Idle servers
+//!
+//! By default, servers run forever.  If an idle timeout is
+//! configured using [`Config::ipc_idle_timeout`], servers exit once
+//! no client has been connected to them for that long.  Before
+//! exiting, a server withdraws its address from the rendezvous
+//! point, so that the next client starts a new server.
+//!
+//! [`Config::ipc_idle_timeout`]: ../../sequoia_core/struct.Config.html#method.ipc_idle_timeout
+//!
+//! # Ephemeral contexts
+//!
+//! If the context is ephemeral and the IPC policy is
+//! `IPCPolicy::Internal`, we do not touch the disk.  Instead, the
+//! server is only announced within the current process, and all
+//! contexts in this process using the same home directory share it.
+//!
 //! # Note
 //!
 //! Windows support is currently not implemented, but should be
 //! straight forward.
 
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fs;
-use std::io::{self, Read, Write};
-use std::net::{SocketAddr, AddrParseError, TcpStream, TcpListener};
-use std::path::PathBuf;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpStream, TcpListener};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 extern crate capnp_rpc;
 #[macro_use] extern crate failure;
 extern crate fs2;
 extern crate futures;
+#[macro_use] extern crate lazy_static;
+extern crate libc;
 extern crate memsec;
 extern crate tokio_core;
 extern crate tokio_io;
+extern crate tokio_uds;
 
 use failure::Fallible as Result;
 use fs2::FileExt;
 use futures::{Future, Stream};
+use futures::future::{self, loop_fn, Loop};
 
 use tokio_core::net;
-use tokio_io::io::{ReadHalf, ReadExact};
-use tokio_io::AsyncRead;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::io::ReadExact;
+use tokio_io::{AsyncRead, AsyncWrite};
 
 use capnp_rpc::{RpcSystem, twoparty};
 use capnp_rpc::rpc_twoparty_capnp::Side;
 
 /* Unix-specific options.  */
-use std::os::unix::io::FromRawFd;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::{UnixStream, UnixListener};
 
 /* XXX: Implement Windows support.  */
 
 use std::process::{Command, Stdio};
-use std::os::unix::io::AsRawFd;
 
 use std::thread;
 
@@ -75,11 +112,16 @@ extern crate sequoia_core;
 
 use sequoia_core as core;
 
+/// The read half of a connection.
+///
+/// Depending on the transport, this is either a Unix domain socket
+/// or a TCP connection.
+pub type Reader = Box<AsyncRead>;
+
 /// Servers need to implement this trait.
 pub trait Handler {
     /// Called on every connection.
-    fn handle(&self,
-              network: twoparty::VatNetwork<ReadHalf<net::TcpStream>>)
+    fn handle(&self, network: twoparty::VatNetwork<Reader>)
               -> RpcSystem<Side>;
 }
 
@@ -99,6 +141,15 @@ pub struct Descriptor {
 
 const LOCALHOST: &str = "127.0.0.1";
 
+lazy_static! {
+    /// Servers announced within this process, indexed by their
+    /// rendezvous point.
+    ///
+    /// The values have the same format as the rendezvous point.
+    static ref PRIVATE_SERVERS: Mutex<HashMap<PathBuf, Vec<u8>>> =
+        Mutex::new(HashMap::new());
+}
+
 impl Descriptor {
     /// Create a descriptor given its rendezvous point, the path to
     /// the servers executable file, and a handler factory.
@@ -131,24 +182,40 @@ impl Descriptor {
     pub fn connect_with_policy(&self, handle: &tokio_core::reactor::Handle,
                                policy: core::IPCPolicy)
                    -> Result<RpcSystem<Side>> {
-        let do_connect =
-            move |cookie: Cookie, mut s: TcpStream| -> Result<RpcSystem<Side>> {
-            cookie.send(&mut s)?;
-
-            /* Tokioize.  */
-            let stream = net::TcpStream::from_stream(s, &handle)?;
-            stream.set_nodelay(true)?;
-            let (reader, writer) = stream.split();
-
-            let network =
-                Box::new(twoparty::VatNetwork::new(reader, writer,
-                                                   Side::Client,
-                                                   Default::default()));
-            let rpc_system = RpcSystem::new(network, None);
-
-            Ok(rpc_system)
+        let do_connect = move |c: Connection| -> Result<RpcSystem<Side>> {
+            let network = c.into_network(handle, Side::Client)?;
+            Ok(RpcSystem::new(Box::new(network), None))
         };
 
+        {
+            // First, look for a server announced within this process.
+            let mut servers = PRIVATE_SERVERS.lock().unwrap();
+            if let Some(c) = servers.get(&self.rendezvous).cloned() {
+                match connect_announced(c) {
+                    Ok(connection) => return do_connect(connection),
+                    // The server is gone.
+                    Err(_) => { servers.remove(&self.rendezvous); },
+                }
+            }
+
+            if policy == core::IPCPolicy::Internal && self.ctx.ephemeral() {
+                // Servers for ephemeral contexts are private to this
+                // process.  We only announce them within this
+                // process, hence we do not need the rendezvous point,
+                // and do not touch the disk.
+                let cookie = Cookie::new()?;
+                let addr = self.start(false)?;
+                let mut connection = addr.connect()?;
+                cookie.send(&mut connection)?;
+
+                let mut c = vec![];
+                cookie.send(&mut c)?;
+                c.extend_from_slice(&addr.to_bytes());
+                servers.insert(self.rendezvous.clone(), c);
+                return do_connect(connection);
+            }
+        }
+
         fs::create_dir_all(self.ctx.home())?;
         let mut file = fs::OpenOptions::new()
             .read(true)
@@ -162,23 +229,22 @@ impl Descriptor {
         file.read_to_end(&mut c)?;
 
         if let Some((cookie, a)) = Cookie::extract(c) {
-            let addr: ::std::result::Result<SocketAddr, AddrParseError> =
-                String::from_utf8_lossy(&a).parse();
-            if addr.is_err() {
-                /* Malformed.  Invalidate the cookie and try again.  */
-                file.set_len(0)?;
-                drop(file);
-                return self.connect(handle);
-            }
-
-            let stream = TcpStream::connect(addr.unwrap());
-            if let Ok(s) = stream {
-                do_connect(cookie, s)
+            let connection = Address::from_bytes(&a)
+                .ok_or_else(|| format_err!("Malformed address"))
+                .and_then(|addr| Ok(addr.connect()?))
+                .and_then(|mut c| {
+                    cookie.send(&mut c)?;
+                    Ok(c)
+                });
+
+            if let Ok(c) = connection {
+                do_connect(c)
             } else {
-                /* Failed to connect.  Invalidate the cookie and try again.  */
+                /* Malformed, or failed to connect.  Invalidate the
+                 * cookie and try again.  */
                 file.set_len(0)?;
                 drop(file);
-                self.connect(handle)
+                self.connect_with_policy(handle, policy)
             }
         } else {
             let cookie = Cookie::new()?;
@@ -205,45 +271,65 @@ impl Descriptor {
                     }
                 };
 
-                let mut stream = TcpStream::connect(addr)?;
-                cookie.send(&mut stream)?;
-
-                /* XXX: It'd be nice not to waste this connection.  */
-                drop(stream);
+                // The first client tells the server its cookie.  The
+                // server then treats us like any other client, so we
+                // can use this connection.
+                let mut connection = addr.connect()?;
+                cookie.send(&mut connection)?;
 
                 if *external {
                     /* Write connection information to file.  */
                     file.set_len(0)?;
+                    file.seek(SeekFrom::Start(0))?;
                     cookie.send(&mut file)?;
-                    write!(file, "{}:{}", LOCALHOST, addr.port())?;
+                    file.write_all(&addr.to_bytes())?;
                 }
                 drop(file);
 
-                return do_connect(cookie, TcpStream::connect(addr)?);
+                return do_connect(connection);
             }
             unreachable!();
         }
     }
 
-    /// Try to create a TCP socket, bind it to a random port on
-    /// localhost.
-    fn listen(&self) -> Result<TcpListener> {
-        let port = OsRng::new()?.next_u32() as u16;
-        Ok(TcpListener::bind((LOCALHOST, port))?)
+    /// Returns the path of the Unix domain socket for external
+    /// servers.
+    fn socket(&self) -> PathBuf {
+        self.rendezvous.with_extension("socket")
+    }
+
+    /// Creates a listening socket.
+    ///
+    /// External servers listen on a Unix domain socket, unless it
+    /// cannot be created.  In that case, and for internal servers,
+    /// we listen on a random port on localhost.
+    fn listen(&self, external: bool) -> Result<Listener> {
+        if external {
+            let path = self.socket();
+            // We hold the lock on the rendezvous point, and the
+            // rendezvous point does not point to a usable server.
+            // Hence, the socket is stale.
+            let _ = fs::remove_file(&path);
+            if let Ok(l) = UnixListener::bind(&path) {
+                return Ok(Listener::Unix(l));
+            }
+        }
+
+        loop {
+            let port = OsRng::new()?.next_u32() as u16;
+            if let Ok(l) = TcpListener::bind((LOCALHOST, port)) {
+                return Ok(Listener::Tcp(l));
+            }
+        }
     }
 
     /// Start the service, either as an external process or as a
     /// thread.
-    fn start(&self, external: bool) -> Result<SocketAddr> {
-        /* Listen on a random port on localhost.  */
-        let mut listener = self.listen();
-        while listener.is_err() {
-            listener = self.listen();
-        }
-        let listener = listener.unwrap();
-        let addr = listener.local_addr()?;
+    fn start(&self, external: bool) -> Result<Address> {
+        let listener = self.listen(external)?;
+        let addr = listener.address()?;
 
-        /* Start the server, connect to it, and send the cookie.  */
+        /* Start the server.  */
         if external {
             self.fork(listener)?;
         } else {
@@ -253,26 +339,34 @@ impl Descriptor {
         Ok(addr)
     }
 
-    fn fork(&self, l: TcpListener) -> Result<()> {
-        // Convert to raw fd, then forget l so that it will not be
-        // closed when it is dropped.
-        let fd = l.as_raw_fd();
-        ::std::mem::forget(l);
+    fn fork(&self, l: Listener) -> Result<()> {
+        // Convert to raw fd, so that l will not be closed when it is
+        // dropped.
+        let fd = l.into_raw_fd();
 
-        Command::new(&self.executable.clone().into_os_string())
+        let mut command =
+            Command::new(&self.executable.clone().into_os_string());
+        command
             .arg("--home")
             .arg(self.ctx.home().to_string_lossy().into_owned())
             .arg("--lib")
             .arg(self.ctx.home().to_string_lossy().into_owned())
             .arg("--ephemeral")
-            .arg(format!("{}", self.ctx.ephemeral()))
+            .arg(format!("{}", self.ctx.ephemeral()));
+        if let Some(timeout) = self.ctx.ipc_idle_timeout() {
+            command
+                .arg("--idle-timeout-ms")
+                .arg(format!("{}", timeout.as_secs() * 1000
+                             + timeout.subsec_millis() as u64));
+        }
+        command
             // l will be closed here if the exec fails.
             .stdin(unsafe { Stdio::from_raw_fd(fd) })
             .spawn()?;
         Ok(())
     }
 
-    fn spawn(&self, l: TcpListener) -> Result<()> {
+    fn spawn(&self, l: Listener) -> Result<()> {
         let descriptor = self.clone();
         thread::spawn(move || -> Result<()> {
             Ok(Server::new(descriptor)
@@ -290,6 +384,10 @@ pub struct Server {
     descriptor: Descriptor,
 }
 
+/// Accepted connections that still need to be authenticated.
+type PendingConnection =
+    Box<Future<Item = twoparty::VatNetwork<Reader>, Error = io::Error>>;
+
 impl Server {
     /// Creates a new server for the descriptor.
     pub fn new(descriptor: Descriptor) -> Result<Self> {
@@ -304,11 +402,13 @@ impl Server {
         use std::env::args;
         let args: Vec<String> = args().collect();
 
-        if args.len() != 7 || args[1] != "--home"
-            || args[3] != "--lib" || args[5] != "--ephemeral" {
+        if (args.len() != 7 && args.len() != 9) || args[1] != "--home"
+            || args[3] != "--lib" || args[5] != "--ephemeral"
+            || (args.len() == 9 && args[7] != "--idle-timeout-ms") {
                 return Err(format_err!(
                     "Usage: {} --home <HOMEDIR> --lib <LIBDIR> \
-                     --ephemeral true|false", args[0]));
+                     --ephemeral true|false \
+                     [--idle-timeout-ms <MILLISECONDS>]", args[0]));
             }
 
         let mut cfg = core::Context::configure()
@@ -324,13 +424,27 @@ impl Server {
                 args[6]));
         }
 
+        if args.len() == 9 {
+            if let Ok(timeout) = args[8].parse() {
+                cfg.set_ipc_idle_timeout(
+                    Some(Duration::from_millis(timeout)));
+            } else {
+                return Err(format_err!(
+                    "Expected milliseconds for --idle-timeout-ms, got: {}",
+                    args[8]));
+            }
+        }
+
         cfg.build()
     }
 
     /// Turns this process into a server.
     ///
     /// External servers must call this early on.  Expects 'stdin' to
-    /// be a listening TCP socket.
+    /// be a listening Unix domain socket or TCP socket.
+    ///
+    /// Returns once the server has been idle for the duration
+    /// configured using `Config::ipc_idle_timeout`.
     ///
     /// # Example
     ///
@@ -352,45 +466,426 @@ impl Server {
     /// }
     /// ```
     pub fn serve(&mut self) -> Result<()> {
-        self.serve_listener(unsafe { TcpListener::from_raw_fd(0) })
+        self.serve_listener(unsafe { Listener::from_raw_fd(0) })
     }
 
-    fn serve_listener(&mut self, l: TcpListener) -> Result<()> {
+    fn serve_listener(&mut self, l: Listener) -> Result<()> {
         /* The first client tells us our cookie.  */
-        let mut i = l.accept()?;
-        let cookie = Cookie::receive(&mut i.0)?;
-        /* XXX: It'd be nice to recycle this connection.  */
-        drop(i);
+        let mut first = l.accept()?;
+        let cookie = Rc::new(Cookie::receive(&mut first)?);
+        let address = l.address()?;
 
-        let handler = (self.descriptor.factory)(self.descriptor.clone(), self.core.handle())?;
+        let handle = self.core.handle();
+        let handler =
+            (self.descriptor.factory)(self.descriptor.clone(), handle.clone())?;
+        let clients = Rc::new(Clients::new(handler, handle.clone()));
+
+        /* The first client stays connected.  */
+        Clients::serve(&clients, first.into_network(&handle, Side::Server)?);
 
         /* Tokioize.  */
-        let handle = self.core.handle();
-        let a = l.local_addr()?;
-        let socket = tokio_core::net::TcpListener::from_listener(l, &a, &handle).unwrap();
-
-        let done = socket.incoming().and_then(|(socket, _addr)| {
-            let _ = socket.set_nodelay(true);
-            Cookie::receive_async(socket)
-        }).and_then(|(socket, buf)| {
-            if Cookie::from(&buf).map(|c| c == cookie).unwrap_or(false) {
-                Ok(socket)
-            } else {
-                Err(io::Error::new(io::ErrorKind::BrokenPipe, "Bad cookie."))
+        let incoming: Box<Stream<Item = PendingConnection, Error = io::Error>>
+            = match l {
+                Listener::Unix(l) => {
+                    let cookie = cookie.clone();
+                    let l = tokio_uds::UnixListener::from_std(
+                        l, &handle.new_tokio_handle())?;
+                    Box::new(l.incoming().map(move |socket|
+                                              -> PendingConnection {
+                        if let Err(e) = check_peer(&socket) {
+                            return Box::new(future::err(e));
+                        }
+                        Box::new(authenticate(socket, cookie.clone())
+                                 .map(|s| network(s, Side::Server)))
+                    }))
+                },
+                Listener::Tcp(l) => {
+                    let cookie = cookie.clone();
+                    let a = l.local_addr()?;
+                    let l = net::TcpListener::from_listener(l, &a, &handle)?;
+                    Box::new(l.incoming().map(move |(socket, _addr)|
+                                              -> PendingConnection {
+                        let _ = socket.set_nodelay(true);
+                        Box::new(authenticate(socket, cookie.clone())
+                                 .map(|s| network(s, Side::Server)))
+                    }))
+                },
+            };
+
+        let accept = {
+            let clients = clients.clone();
+            incoming.for_each(move |pending| {
+                let clients = clients.clone();
+                handle.spawn(pending
+                             .map(move |network| {
+                                 Clients::serve(&clients, network)
+                             })
+                             .map_err(|e| println!("error: {:?}", e)));
+                Ok(())
+            })
+        };
+
+        let idle: Box<Future<Item = (), Error = io::Error>> =
+            match self.descriptor.context().ipc_idle_timeout() {
+                Some(timeout) =>
+                    Box::new(idle_shutdown(
+                        clients, timeout, self.descriptor.rendezvous.clone(),
+                        cookie, address, self.core.handle())),
+                None => Box::new(future::empty()),
+            };
+
+        Ok(self.core.run(accept.select(idle)
+                         .map(|_| ())
+                         .map_err(|(e, _)| e))?)
+    }
+}
+
+/// Serves clients and keeps track of them.
+struct Clients {
+    handler: Box<Handler>,
+    handle: Handle,
+    /// The number of connected clients.
+    connected: Cell<usize>,
+    /// When the last client disconnected.
+    idle_since: Cell<Instant>,
+}
+
+impl Clients {
+    fn new(handler: Box<Handler>, handle: Handle) -> Self {
+        Clients {
+            handler: handler,
+            handle: handle,
+            connected: Cell::new(0),
+            idle_since: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Serves a client.
+    fn serve(clients: &Rc<Self>, network: twoparty::VatNetwork<Reader>) {
+        clients.connected.set(clients.connected.get() + 1);
+
+        let c = clients.clone();
+        clients.handle.spawn(
+            clients.handler.handle(network)
+                .map_err(|e| println!("error: {:?}", e))
+                .then(move |r| {
+                    c.connected.set(c.connected.get() - 1);
+                    if c.connected.get() == 0 {
+                        c.idle_since.set(Instant::now());
+                    }
+                    r
+                }));
+    }
+
+    /// Returns how long no client has been connected.
+    fn idle(&self) -> Option<Duration> {
+        if self.connected.get() == 0 {
+            Some(self.idle_since.get().elapsed())
+        } else {
+            None
+        }
+    }
+}
+
+/// Gives clients that connected right before we withdrew our address
+/// from the rendezvous point a chance to be served.
+fn withdraw_grace_period() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// Returns a future that completes once the server has been idle for
+/// `timeout`.
+///
+/// Before completing, we withdraw our address from the rendezvous
+/// point, and then wait for the clients that raced with us.
+fn idle_shutdown(clients: Rc<Clients>, timeout: Duration,
+                 rendezvous: PathBuf, cookie: Rc<Cookie>, address: Address,
+                 handle: Handle)
+                 -> Box<Future<Item = (), Error = io::Error>> {
+    Box::new(loop_fn(false, move |withdrawn: bool| {
+        let wait = match clients.idle() {
+            // Somebody is connected.  Check again later.
+            None => timeout,
+            Some(idle) => {
+                let timeout = if withdrawn {
+                    withdraw_grace_period()
+                } else {
+                    timeout
+                };
+
+                if idle < timeout {
+                    timeout - idle
+                } else if withdrawn {
+                    return future::Either::A(future::ok(Loop::Break(())));
+                } else {
+                    // Errors only mean that our address has not been
+                    // advertised.
+                    let _ = withdraw(&rendezvous, &cookie, &address);
+                    clients.idle_since.set(Instant::now());
+                    return future::Either::A(future::ok(
+                        Loop::Continue(true)));
+                }
+            },
+        };
+
+        future::Either::B(future::result(Timeout::new(wait, &handle))
+                          .flatten()
+                          .map(move |_| Loop::Continue(withdrawn)))
+    }))
+}
+
+/// Withdraws our address from the rendezvous point.
+///
+/// Afterwards, no new client will connect to us.
+fn withdraw(rendezvous: &Path, cookie: &Cookie, address: &Address)
+            -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(rendezvous)?;
+    file.lock_exclusive()?;
+
+    let mut c = vec![];
+    file.read_to_end(&mut c)?;
+
+    if let Some((c, _)) = Cookie::extract(c) {
+        // Make sure that the rendezvous point refers to us, and not
+        // to a server that replaced us.
+        if c == *cookie {
+            file.set_len(0)?;
+            if let &Address::Unix(ref path) = address {
+                fs::remove_file(path)?;
             }
-        }).for_each(|socket| {
-            let (reader, writer) = socket.split();
+        }
+    }
+    Ok(())
+}
 
-            let network =
-                twoparty::VatNetwork::new(reader, writer,
-                                          Side::Server, Default::default());
+/// Connects to a server announced using `c`.
+///
+/// `c` has the same format as the rendezvous point.
+fn connect_announced(c: Vec<u8>) -> Result<Connection> {
+    let (cookie, a) = Cookie::extract(c)
+        .ok_or_else(|| format_err!("Malformed announcement"))?;
+    let mut connection = Address::from_bytes(&a)
+        .ok_or_else(|| format_err!("Malformed address"))?
+        .connect()?;
+    cookie.send(&mut connection)?;
+    Ok(connection)
+}
 
-            let rpc_system = handler.handle(network);
-            handle.spawn(rpc_system.map_err(|e| println!("error: {:?}", e)));
-            Ok(())
-        });
+/// The address of a server.
+#[derive(Clone, Debug, PartialEq)]
+enum Address {
+    /// A Unix domain socket.
+    Unix(PathBuf),
+    /// A TCP socket on localhost.
+    Tcp(SocketAddr),
+}
+
+/// Prefix of Unix domain socket addresses in the rendezvous point.
+const UNIX_PREFIX: &[u8] = b"unix:";
 
-        Ok(self.core.run(done)?)
+impl Address {
+    /// Parses an address stored in a rendezvous point.
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.starts_with(UNIX_PREFIX) {
+            Some(Address::Unix(
+                OsStr::from_bytes(&buf[UNIX_PREFIX.len()..]).into()))
+        } else {
+            String::from_utf8_lossy(buf).parse().ok().map(Address::Tcp)
+        }
+    }
+
+    /// Serializes the address for the rendezvous point.
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            &Address::Unix(ref path) => {
+                let mut buf = UNIX_PREFIX.to_vec();
+                buf.extend_from_slice(path.as_os_str().as_bytes());
+                buf
+            },
+            &Address::Tcp(ref addr) =>
+                format!("{}:{}", LOCALHOST, addr.port()).into_bytes(),
+        }
+    }
+
+    /// Connects to the server.
+    fn connect(&self) -> io::Result<Connection> {
+        match self {
+            &Address::Unix(ref path) => {
+                let s = UnixStream::connect(path)?;
+                check_peer(&s)?;
+                Ok(Connection::Unix(s))
+            },
+            &Address::Tcp(ref addr) =>
+                Ok(Connection::Tcp(TcpStream::connect(addr)?)),
+        }
+    }
+}
+
+/// A listening socket.
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    /// Returns the address clients connect to.
+    fn address(&self) -> Result<Address> {
+        match self {
+            &Listener::Unix(ref l) => Ok(Address::Unix(
+                l.local_addr()?.as_pathname()
+                    .ok_or_else(|| format_err!("Unnamed socket"))?
+                    .into())),
+            &Listener::Tcp(ref l) => Ok(Address::Tcp(l.local_addr()?)),
+        }
+    }
+
+    /// Accepts a connection.
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            &Listener::Unix(ref l) => {
+                let (s, _) = l.accept()?;
+                check_peer(&s)?;
+                Ok(Connection::Unix(s))
+            },
+            &Listener::Tcp(ref l) => Ok(Connection::Tcp(l.accept()?.0)),
+        }
+    }
+}
+
+impl FromRawFd for Listener {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        let l = UnixListener::from_raw_fd(fd);
+        // This fails if fd is not a Unix domain socket.
+        if l.local_addr().is_ok() {
+            Listener::Unix(l)
+        } else {
+            Listener::Tcp(TcpListener::from_raw_fd(l.into_raw_fd()))
+        }
+    }
+}
+
+impl IntoRawFd for Listener {
+    fn into_raw_fd(self) -> RawFd {
+        match self {
+            Listener::Unix(l) => l.into_raw_fd(),
+            Listener::Tcp(l) => l.into_raw_fd(),
+        }
+    }
+}
+
+/// A connection between a client and a server.
+enum Connection {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    /// Tokioizes the connection.
+    fn into_network(self, handle: &Handle, side: Side)
+                    -> Result<twoparty::VatNetwork<Reader>> {
+        match self {
+            Connection::Unix(s) => {
+                let s = tokio_uds::UnixStream::from_std(
+                    s, &handle.new_tokio_handle())?;
+                Ok(network(s, side))
+            },
+            Connection::Tcp(s) => {
+                let s = net::TcpStream::from_stream(s, handle)?;
+                s.set_nodelay(true)?;
+                Ok(network(s, side))
+            },
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut Connection::Unix(ref mut s) => s.read(buf),
+            &mut Connection::Tcp(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut Connection::Unix(ref mut s) => s.write(buf),
+            &mut Connection::Tcp(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut Connection::Unix(ref mut s) => s.flush(),
+            &mut Connection::Tcp(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// Creates a capnp network on top of the given socket.
+fn network<S>(socket: S, side: Side) -> twoparty::VatNetwork<Reader>
+    where S: AsyncRead + AsyncWrite + 'static
+{
+    let (reader, writer) = socket.split();
+    twoparty::VatNetwork::new(Box::new(reader) as Reader, writer,
+                              side, Default::default())
+}
+
+/// Checks the cookie sent by a client.
+fn authenticate<S>(socket: S, cookie: Rc<Cookie>)
+                   -> Box<Future<Item = S, Error = io::Error>>
+    where S: AsyncRead + 'static
+{
+    Box::new(Cookie::receive_async(socket).and_then(move |(socket, buf)| {
+        if Cookie::from(&buf).map(|c| c == *cookie).unwrap_or(false) {
+            Ok(socket)
+        } else {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Bad cookie."))
+        }
+    }))
+}
+
+/// Checks that the process on the other end of the Unix domain
+/// socket runs as the same user as we do.
+fn check_peer<S: AsRawFd>(socket: &S) -> io::Result<()> {
+    let uid = peer_uid(socket.as_raw_fd())?;
+    if uid == unsafe { libc::geteuid() } {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                           format!("Peer is running as user {}", uid)))
+    }
+}
+
+/// Returns the effective user ID of the peer.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(fd: RawFd) -> io::Result<libc::uid_t> {
+    let mut cred: libc::ucred = unsafe { ::std::mem::zeroed() };
+    let mut len = ::std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let r = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_PEERCRED,
+                         &mut cred as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if r == 0 {
+        Ok(cred.uid)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Returns the effective user ID of the peer.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(fd: RawFd) -> io::Result<libc::uid_t> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } == 0 {
+        Ok(uid)
+    } else {
+        Err(io::Error::last_os_error())
     }
 }
 
@@ -441,8 +936,7 @@ impl Cookie {
     }
 
     /// Asynchronously read a cookie from 'socket'.
-    fn receive_async(socket: net::TcpStream) -> ReadExact<net::TcpStream,
-                                                          Vec<u8>> {
+    fn receive_async<S: AsyncRead>(socket: S) -> ReadExact<S, Vec<u8>> {
         let buf = vec![0; COOKIE_SIZE];
         tokio_io::io::read_exact(socket, buf)
     }
@@ -467,3 +961,185 @@ impl PartialEq for Cookie {
             }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The number of connections handled by `NullHandler`s.
+    static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Serves nothing, but keeps the connection open until the client
+    /// hangs up.
+    struct NullHandler;
+
+    impl Handler for NullHandler {
+        fn handle(&self, network: twoparty::VatNetwork<Reader>)
+                  -> RpcSystem<Side> {
+            HANDLED.fetch_add(1, Ordering::SeqCst);
+            RpcSystem::new(Box::new(network), None)
+        }
+    }
+
+    fn null_handler(_: Descriptor, _: Handle) -> Result<Box<Handler>> {
+        Ok(Box::new(NullHandler))
+    }
+
+    /// Advertises the server at `address` in the rendezvous point.
+    fn advertise(rendezvous: &Path, cookie: &Cookie, address: &Address) {
+        let mut file = fs::File::create(rendezvous).unwrap();
+        cookie.send(&mut file).unwrap();
+        file.write_all(&address.to_bytes()).unwrap();
+    }
+
+    #[test]
+    fn idle_shutdown_grace_period() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let rendezvous = ctx.home().join("rendezvous");
+        let socket = ctx.home().join("rendezvous.socket");
+        let address = Address::Unix(socket.clone());
+        let cookie = Rc::new(Cookie::new().unwrap());
+        advertise(&rendezvous, &cookie, &address);
+        fs::File::create(&socket).unwrap();
+
+        let mut core = tokio_core::reactor::Core::new().unwrap();
+        let clients = Rc::new(Clients::new(Box::new(NullHandler),
+                                           core.handle()));
+        let timeout = Duration::from_millis(100);
+        let race = Duration::from_millis(500);
+        let start = Instant::now();
+
+        // A client that raced with the withdrawal connects during the
+        // grace period, and stays for a while.
+        let raced = {
+            let clients = clients.clone();
+            let rendezvous = rendezvous.clone();
+            let handle = core.handle();
+            Timeout::new(timeout * 2, &handle).unwrap()
+                .and_then(move |_| {
+                    // By now, our address has been withdrawn.
+                    assert_eq!(fs::metadata(&rendezvous).unwrap().len(), 0);
+                    clients.connected.set(1);
+                    Timeout::new(race, &handle).unwrap()
+                        .map(move |_| {
+                            clients.connected.set(0);
+                            clients.idle_since.set(Instant::now());
+                        })
+                })
+        };
+        core.handle().spawn(raced.map_err(|e| panic!("{}", e)));
+
+        let shutdown = idle_shutdown(clients, timeout, rendezvous.clone(),
+                                     cookie, address, core.handle());
+        core.run(shutdown).unwrap();
+
+        // We exit once the grace period has passed after the last
+        // client disconnected.
+        assert!(start.elapsed()
+                >= timeout * 2 + race + withdraw_grace_period());
+        assert_eq!(fs::metadata(&rendezvous).unwrap().len(), 0);
+        assert!(! socket.exists());
+    }
+
+    #[test]
+    fn withdraw_replaced() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let rendezvous = ctx.home().join("rendezvous");
+        let socket = ctx.home().join("rendezvous.socket");
+        let address = Address::Unix(socket.clone());
+        fs::File::create(&socket).unwrap();
+
+        // Another server replaced us.  Don't withdraw its address.
+        advertise(&rendezvous, &Cookie::new().unwrap(), &address);
+        withdraw(&rendezvous, &Cookie::new().unwrap(), &address).unwrap();
+        assert!(fs::metadata(&rendezvous).unwrap().len() > 0);
+        assert!(socket.exists());
+    }
+
+    #[test]
+    fn unix_socket_round_trip() {
+        let ctx = core::Context::configure().ephemeral()
+            .ipc_idle_timeout(Some(Duration::from_millis(100)))
+            .build().unwrap();
+        let descriptor = Descriptor::new(&ctx, ctx.home().join("rendezvous"),
+                                         PathBuf::from("/nonexistent"),
+                                         null_handler);
+        let listener = descriptor.listen(true).unwrap();
+        let address = listener.address().unwrap();
+        assert_eq!(address, Address::Unix(descriptor.socket()));
+
+        let cookie = Cookie::new().unwrap();
+        advertise(&descriptor.rendezvous, &cookie, &address);
+
+        let server = {
+            let descriptor = descriptor.clone();
+            thread::spawn(move || -> Result<()> {
+                Server::new(descriptor)?.serve_listener(listener)
+            })
+        };
+
+        // Waits until the server handled `n` connections.
+        let wait_for = |n| {
+            let start = Instant::now();
+            while HANDLED.load(Ordering::SeqCst) < n {
+                assert!(start.elapsed() < Duration::from_secs(10));
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let core = tokio_core::reactor::Core::new().unwrap();
+
+        // The first client tells the server its cookie, and then uses
+        // the same connection for its requests.
+        let mut first = address.connect().unwrap();
+        cookie.send(&mut first).unwrap();
+        let first = first.into_network(&core.handle(), Side::Client).unwrap();
+        wait_for(1);
+
+        // Other clients must authenticate.
+        let mut second = address.connect().unwrap();
+        cookie.send(&mut second).unwrap();
+        let second = second.into_network(&core.handle(), Side::Client)
+            .unwrap();
+        wait_for(2);
+
+        // Clients with a bad cookie are disconnected.
+        let mut bad = address.connect().unwrap();
+        Cookie::new().unwrap().send(&mut bad).unwrap();
+        let mut buf = Vec::new();
+        let _ = bad.read_to_end(&mut buf);
+        assert!(buf.is_empty());
+        assert_eq!(HANDLED.load(Ordering::SeqCst), 2);
+
+        // Once all clients hung up, the server withdraws its address
+        // and exits.
+        drop(first);
+        drop(second);
+        server.join().unwrap().unwrap();
+        assert_eq!(fs::metadata(&descriptor.rendezvous).unwrap().len(), 0);
+        assert!(! descriptor.socket().exists());
+    }
+
+    #[test]
+    fn address_roundtrip() {
+        for a in &[Address::Unix(PathBuf::from("/tmp/sequoia/store.socket")),
+                   Address::Tcp(format!("{}:1234", LOCALHOST).parse()
+                                .unwrap())] {
+            assert_eq!(Address::from_bytes(&a.to_bytes()).as_ref(), Some(a));
+        }
+        assert_eq!(Address::from_bytes(b"bogus"), None);
+    }
+
+    #[test]
+    fn peer_credentials() {
+        let (a, b) = UnixStream::pair().unwrap();
+        check_peer(&a).unwrap();
+        check_peer(&b).unwrap();
+
+        // TCP sockets have no credentials.
+        let l = TcpListener::bind((LOCALHOST, 0)).unwrap();
+        let s = TcpStream::connect(l.local_addr().unwrap()).unwrap();
+        assert!(check_peer(&s).is_err());
+    }
+}
@@ -37,7 +37,7 @@ pub struct KeyServer {
     uri: Url,
 }
 
-const DNS_WORKER: usize = 4;
+pub(crate) const DNS_WORKER: usize = 4;
 
 impl KeyServer {
     /// Returns a handle for the given URI.
@@ -1,8 +1,10 @@
 //! For accessing keys over the network.
 //!
-//! Currently, this module provides access to keyservers providing the [HKP] protocol.
+//! Currently, this module provides access to keyservers providing the [HKP] protocol,
+//! and to [Web Key Directories].
 //!
 //! [HKP]: https://tools.ietf.org/html/draft-shaw-openpgp-hkp-00
+//! [Web Key Directories]: wkd/index.html
 //!
 //! # Example
 //!
@@ -42,6 +44,7 @@ extern crate http;
 extern crate hyper;
 extern crate hyper_tls;
 extern crate native_tls;
+extern crate nettle;
 extern crate tokio_core;
 extern crate tokio_io;
 #[macro_use]
@@ -62,6 +65,7 @@ use sequoia_core::Context;
 
 pub mod async;
 use async::url2uri;
+pub mod wkd;
 
 /// For accessing keyservers using HKP.
 pub struct KeyServer {
@@ -148,6 +152,9 @@ pub enum Error {
     /// A given keyserver URI was malformed.
     #[fail(display = "Malformed URI; expected hkp: or hkps:")]
     MalformedUri,
+    /// A given email address was malformed.
+    #[fail(display = "Malformed email address")]
+    MalformedEmail,
     /// The server provided malformed data.
     #[fail(display = "Malformed response from server")]
     MalformedResponse,
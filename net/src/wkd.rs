@@ -0,0 +1,162 @@
+//! Asynchronously access Web Key Directories.
+//!
+//! A [Web Key Directory] publishes the keys of the users of a mail
+//! domain over https.  Keys are looked up using an email address.
+//!
+//! [Web Key Directory]: https://tools.ietf.org/html/draft-koch-openpgp-webkey-service-07
+
+use failure;
+use futures::{future, Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::{Client, StatusCode};
+use hyper_tls::HttpsConnector;
+use nettle::Hash;
+use tokio_core::reactor::Handle;
+use url::Url;
+
+use openpgp::TPK;
+use openpgp::constants::HashAlgorithm;
+use openpgp::parse::Parse;
+use sequoia_core::{Context, NetworkPolicy};
+
+use super::{Error, Result};
+use async::{url2uri, DNS_WORKER};
+
+/// Returns the URL of the key for the given email address.
+///
+/// If `advanced` is true, returns the URL used by the advanced
+/// method, which uses the `openpgpkey` subdomain of the mail domain.
+/// Otherwise, returns the URL used by the direct method.
+pub fn url(email: &str, advanced: bool) -> Result<Url> {
+    let mut parts = email.rsplitn(2, '@');
+    let domain = parts.next().unwrap().to_lowercase();
+    let local = parts.next().ok_or(Error::MalformedEmail)?;
+    if domain.is_empty() || local.is_empty() {
+        return Err(Error::MalformedEmail.into());
+    }
+
+    let mut url: Url = if advanced {
+        format!("https://openpgpkey.{}/.well-known/openpgpkey/{}/hu/{}",
+                domain, domain, encode_local_part(local)?)
+    } else {
+        format!("https://{}/.well-known/openpgpkey/hu/{}",
+                domain, encode_local_part(local)?)
+    }.parse().map_err(Error::from)?;
+    url.query_pairs_mut().append_pair("l", local);
+    Ok(url)
+}
+
+/// Retrieves the key for the given email address.
+///
+/// Tries the advanced method first, then the direct method.  Fails
+/// with `Error::NotFound` if the returned key does not have a user
+/// id with the given email address.
+pub fn get(ctx: &Context, email: &str, _handle: &Handle)
+           -> Box<Future<Item=TPK, Error=failure::Error> + 'static> {
+    if let Err(e) = ctx.network_policy().assert(NetworkPolicy::Encrypted) {
+        return Box::new(future::err(e.into()));
+    }
+
+    let (advanced, direct) = match (url(email, true), url(email, false)) {
+        (Ok(a), Ok(d)) => (a, d),
+        (Err(e), _) | (_, Err(e)) => return Box::new(future::err(e)),
+    };
+
+    let client = match HttpsConnector::new(DNS_WORKER) {
+        Ok(c) => Client::builder().build(c),
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+
+    let email = email.to_lowercase();
+    Box::new(fetch(&client, advanced)
+             .or_else(move |_| fetch(&client, direct))
+             .and_then(move |tpk| {
+                 let has_email = tpk.userids().any(|u| {
+                     u.userid().address().ok()
+                         .and_then(|a| a)
+                         .map(|a| a.to_lowercase() == email)
+                         .unwrap_or(false)
+                 });
+                 if has_email {
+                     Ok(tpk)
+                 } else {
+                     Err(Error::NotFound.into())
+                 }
+             }))
+}
+
+/// Retrieves the key from the given URL.
+fn fetch(client: &Client<HttpsConnector<HttpConnector>>, url: Url)
+         -> Box<Future<Item=TPK, Error=failure::Error> + 'static> {
+    Box::new(client.get(url2uri(url))
+             .from_err()
+             .and_then(|res| {
+                 let status = res.status();
+                 res.into_body().concat2().from_err()
+                     .and_then(move |body| match status {
+                         StatusCode::OK =>
+                             future::done(TPK::from_bytes(body.as_ref())),
+                         StatusCode::NOT_FOUND =>
+                             future::err(Error::NotFound.into()),
+                         n => future::err(Error::HttpStatus(n).into()),
+                     })
+             }))
+}
+
+/// Hashes and encodes the local part of an email address.
+///
+/// The local part is mapped to lower case, hashed using SHA-1, and
+/// encoded using z-base-32.
+fn encode_local_part(local: &str) -> Result<String> {
+    let mut hash = HashAlgorithm::SHA1.context()?;
+    hash.update(local.to_lowercase().as_bytes());
+    let mut digest = vec![0; hash.digest_size()];
+    hash.digest(&mut digest);
+    Ok(zbase32(&digest))
+}
+
+/// Encodes the given data using z-base-32.
+fn zbase32(data: &[u8]) -> String {
+    const ALPHABET: &'static [u8] = b"ybndrfg8ejkmcpqxot1uwisza345h769";
+
+    let mut s = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &b in data {
+        buffer = ((buffer << 8) | b as u32) & 0xfff;
+        bits += 8;
+        while bits >= 5 {
+            s.push(ALPHABET[((buffer >> (bits - 5)) & 31) as usize] as char);
+            bits -= 5;
+        }
+    }
+    if bits > 0 {
+        s.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        // Test vector from the draft.
+        assert_eq!(encode_local_part("Joe.Doe").unwrap(),
+                   "iy9q119eutrkn8s1mk4r39qejnbu3n5q");
+
+        assert_eq!(
+            url("Joe.Doe@Example.ORG", false).unwrap().as_str(),
+            "https://example.org/.well-known/openpgpkey/hu/\
+             iy9q119eutrkn8s1mk4r39qejnbu3n5q?l=Joe.Doe");
+        assert_eq!(
+            url("Joe.Doe@Example.ORG", true).unwrap().as_str(),
+            "https://openpgpkey.example.org/.well-known/openpgpkey/\
+             example.org/hu/iy9q119eutrkn8s1mk4r39qejnbu3n5q?l=Joe.Doe");
+
+        assert!(url("example.org", false).is_err());
+        assert!(url("@example.org", false).is_err());
+        assert!(url("joe@", false).is_err());
+    }
+}
@@ -122,6 +122,11 @@ typedef enum pgp_status {
   /*/
   PGP_STATUS_UNSUPPORTED_TPK = -24,
 
+  /*/
+  /// A resource limit was exceeded.
+  /*/
+  PGP_STATUS_RESOURCE_LIMIT_EXCEEDED = -28,
+
   /* Dummy value to make sure the enumeration has a defined size.  Do
      not use this value.  */
   PGP_STATUS_FORCE_WIDTH = INT_MAX,
@@ -136,6 +136,9 @@ pub enum Status {
     /// TPK not supported.
     UnsupportedTPK = -24,
 
+    /// A resource limit was exceeded.
+    ResourceLimitExceeded = -28,
+
     // XXX: Skipping ManipulatedMessage = -25
     // XXX: Skipping UnsupportedAEADAlgorithm = -26
     // XXX: Skipping MissingSessionKey = -27
@@ -176,6 +179,7 @@ pub extern "C" fn pgp_status_to_string(status: Status) -> *const c_char {
         MalformedMessage => "Malformed message\x00",
         IndexOutOfRange => "Index out of range\x00",
         UnsupportedTPK => "TPK not supported\x00",
+        ResourceLimitExceeded => "Resource limit exceeded\x00",
     }.as_bytes().as_ptr() as *const c_char
 }
 
@@ -223,6 +227,8 @@ impl<'a> From<&'a failure::Error> for Status {
                     Status::IndexOutOfRange,
                 &openpgp::Error::UnsupportedTPK(_) =>
                     Status::UnsupportedTPK,
+                &openpgp::Error::ResourceLimitExceeded(_) =>
+                    Status::ResourceLimitExceeded,
             }
         }
 
@@ -117,7 +117,7 @@ fn pgp_message_layer_encryption(v: *const MessageLayer,
                                  -> bool
 {
     use self::stream::MessageLayer::*;
-    if let Encryption { sym_algo, aead_algo } = v.ref_raw() {
+    if let Encryption { sym_algo, aead_algo, .. } = v.ref_raw() {
         if let Some(mut p) = sym_algo_r {
             *unsafe { p.as_mut() } = (*sym_algo).into();
         }
@@ -113,10 +113,15 @@ impl VerificationHelper for Helper {
             match layer {
                 MessageLayer::Compression { algo } =>
                     eprintln!("Compressed using {}", algo),
-                MessageLayer::Encryption { sym_algo, aead_algo } =>
+                MessageLayer::Encryption {
+                    sym_algo, aead_algo, integrity_protected,
+                } =>
                     if let Some(aead_algo) = aead_algo {
                         eprintln!("Encrypted and protected using {}/{}",
                                   sym_algo, aead_algo);
+                    } else if ! integrity_protected {
+                        eprintln!("Encrypted using {}, \
+                                   WITHOUT integrity protection", sym_algo);
                     } else {
                         eprintln!("Encrypted using {}", sym_algo);
                     },
@@ -0,0 +1,66 @@
+/// Compares the performance of the TPKParser and the
+/// ParallelTPKParser on a generated packet dump.
+///
+/// Note that to achieve reasonable performance, you need to compile
+/// Sequoia and this program with optimizations:
+///
+///     % cargo run -p sequoia-openpgp --example parallel-tpk-parser \
+///           --release -- [<number-of-keys> [<threads>]]
+
+use std::env;
+use std::time::{Duration, Instant};
+
+extern crate sequoia_openpgp as openpgp;
+use openpgp::tpk::{TPKBuilder, TPKParser, ParallelTPKParser};
+use openpgp::parse::Parse;
+use openpgp::serialize::Serialize;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 3 {
+        panic!("Compares the TPKParser with the ParallelTPKParser.\n\n\
+                Usage: {} [<number-of-keys> [<threads>]]\n", args[0]);
+    }
+    let keys: usize = args.get(1).map(|n| n.parse().expect("Invalid number"))
+        .unwrap_or(1000);
+
+    // Generate the packet dump.
+    eprintln!("Generating {} keys...", keys);
+    let mut dump = Vec::new();
+    for i in 0..keys {
+        let (tpk, _) = TPKBuilder::new()
+            .add_userid(&*format!("<{}@example.org>", i))
+            .add_userid(&*format!("<{}@example.net>", i))
+            .add_signing_subkey()
+            .add_encryption_subkey()
+            .generate().unwrap();
+        tpk.serialize(&mut dump).unwrap();
+    }
+    eprintln!("The dump is {} bytes large.", dump.len());
+
+    let start = Instant::now();
+    let n = TPKParser::from_bytes(&dump).unwrap()
+        .map(|tpk| tpk.unwrap())
+        .count();
+    assert_eq!(n, keys);
+    let sequential = start.elapsed();
+    eprintln!("TPKParser: {} keys in {:.2}s",
+              n, seconds(sequential));
+
+    let mut parser = ParallelTPKParser::from_bytes(&dump).unwrap();
+    if let Some(threads) = args.get(2) {
+        parser = parser.threads(threads.parse().expect("Invalid number"));
+    }
+    let start = Instant::now();
+    let n = parser
+        .map(|tpk| tpk.unwrap())
+        .count();
+    assert_eq!(n, keys);
+    let parallel = start.elapsed();
+    eprintln!("ParallelTPKParser: {} keys in {:.2}s ({:.1}x speedup)",
+              n, seconds(parallel), seconds(sequential) / seconds(parallel));
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
@@ -1,6 +1,10 @@
 /// Collects statistics about the SKS packet dump using the openpgp
 /// crate, Sequoia's low-level API.
 ///
+/// The TPKs are parsed and canonicalized using several threads.
+/// Hence, the statistics only cover the packets of valid TPKs, and
+/// packet sizes include the packet headers.
+///
 /// Note that to achieve reasonable performance, you need to compile
 /// Sequoia and this program with optimizations:
 ///
@@ -11,8 +15,10 @@ use std::env;
 extern crate sequoia_openpgp as openpgp;
 use openpgp::Packet;
 use openpgp::constants::SignatureType;
-use openpgp::packet::{user_attribute, BodyLength, Tag};
-use openpgp::parse::{Parse, PacketParserResult, PacketParser};
+use openpgp::packet::{user_attribute, Tag};
+use openpgp::parse::Parse;
+use openpgp::serialize::SerializeInto;
+use openpgp::tpk::ParallelTPKParser;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -44,7 +50,6 @@ fn main() {
 
     // Per-TPK statistics.
     let mut tpk_count = 0;
-    let mut tpk = PerTPK::min();
     let mut tpk_min = PerTPK::max();
     let mut tpk_max = PerTPK::min();
 
@@ -56,95 +61,88 @@ fn main() {
     // For each input file, create a parser.
     for input in &args[1..] {
         eprintln!("Parsing {}...", input);
-        let mut ppr = PacketParser::from_file(input)
+        let parser = ParallelTPKParser::from_file(input)
             .expect("Failed to create reader");
 
-        // Iterate over all packets.
-        while let PacketParserResult::Some(pp) = ppr {
-            // While the packet is in the parser, get some data for later.
-            let size = match pp.header().length {
-                BodyLength::Full(n) => Some(n),
-                _ => None,
-            };
-
-            // Get the packet and advance the parser.
-            let (packet, tmp) = pp.next().expect("Failed to get next packet");
-            ppr = tmp;
-
-            packet_count += 1;
-            if let Some(n) = size {
-                packet_size += n as usize;
-            }
-            let i = u8::from(packet.tag()) as usize;
-            tags_count[i] += 1;
-
-            match packet {
-                // If a new TPK starts, update TPK statistics.
-                Packet::PublicKey(_) | Packet::SecretKey(_) => {
-                    if tpk_count > 0 {
-                        tpk.update_min_max(&mut tpk_min, &mut tpk_max);
-                    }
-                    tpk_count += 1;
-                    tpk = PerTPK::min();
+        // Iterate over all TPKs.
+        for tpk in parser {
+            let tpk = match tpk {
+                Ok(tpk) => tpk,
+                Err(e) => {
+                    eprintln!("Parsing TPK failed: {}", e);
+                    continue;
                 },
+            };
 
-                Packet::Signature(ref sig) => {
-                    sigs_count[u8::from(sig.sigtype()) as usize] += 1;
-                    tpk.sigs[u8::from(sig.sigtype()) as usize] += 1;
-
-                    for (_offset, len, sub) in sig.hashed_area().iter()
-                        .chain(sig.unhashed_area().iter())
-                    {
-                        use openpgp::packet::signature::subpacket::*;
-                        let i = u8::from(sub.tag) as usize;
-                        sigs_subpacket_tags_count[i] += 1;
-                        if let SubpacketValue::Unknown(_) = sub.value {
-                            sigs_subpacket_tags_unknown
-                                [u8::from(sub.tag) as usize] += 1;
-                        } else {
-                            sigs_subpacket_tags_size_bytes[i] += len;
-                            sigs_subpacket_tags_size_count[i] += 1;
-                            let len = len as u32;
-                            if len < sigs_subpacket_tags_size_min[i] {
-                                sigs_subpacket_tags_size_min[i] = len;
-                            }
-                            if len > sigs_subpacket_tags_size_max[i] {
-                                sigs_subpacket_tags_size_max[i] = len;
+            tpk_count += 1;
+            let mut per_tpk = PerTPK::min();
+
+            for packet in tpk.into_packets() {
+                let size = packet.serialized_len();
+
+                packet_count += 1;
+                packet_size += size;
+                let i = u8::from(packet.tag()) as usize;
+                tags_count[i] += 1;
+
+                match packet {
+                    Packet::Signature(ref sig) => {
+                        sigs_count[u8::from(sig.sigtype()) as usize] += 1;
+                        per_tpk.sigs[u8::from(sig.sigtype()) as usize] += 1;
+
+                        for (_offset, len, sub) in sig.hashed_area().iter()
+                            .chain(sig.unhashed_area().iter())
+                        {
+                            use openpgp::packet::signature::subpacket::*;
+                            let i = u8::from(sub.tag) as usize;
+                            sigs_subpacket_tags_count[i] += 1;
+                            if let SubpacketValue::Unknown(_) = sub.value {
+                                sigs_subpacket_tags_unknown
+                                    [u8::from(sub.tag) as usize] += 1;
+                            } else {
+                                sigs_subpacket_tags_size_bytes[i] += len;
+                                sigs_subpacket_tags_size_count[i] += 1;
+                                let len = len as u32;
+                                if len < sigs_subpacket_tags_size_min[i] {
+                                    sigs_subpacket_tags_size_min[i] = len;
+                                }
+                                if len > sigs_subpacket_tags_size_max[i] {
+                                    sigs_subpacket_tags_size_max[i] = len;
+                                }
                             }
                         }
-                    }
-                },
-
-                Packet::UserAttribute(ref ua) => {
-                    use user_attribute::Subpacket;
-                    use user_attribute::Image;
-                    for subpacket in ua.subpackets() {
-                        match subpacket {
-                            Ok(Subpacket::Image(i)) => match i {
-                                Image::JPEG(_) =>
-                                    ua_image_count[1] += 1,
-                                Image::Private(n, _) =>
-                                    ua_image_count[n as usize] += 1,
-                                Image::Unknown(n, _) =>
-                                    ua_image_count[n as usize] += 1,
-                            },
-                            Ok(Subpacket::Unknown(n, _)) =>
-                                ua_unknown_count[n as usize] += 1,
-                            Err(_) => ua_invalid_count += 1,
+                    },
+
+                    Packet::UserAttribute(ref ua) => {
+                        use user_attribute::Subpacket;
+                        use user_attribute::Image;
+                        for subpacket in ua.subpackets() {
+                            match subpacket {
+                                Ok(Subpacket::Image(i)) => match i {
+                                    Image::JPEG(_) =>
+                                        ua_image_count[1] += 1,
+                                    Image::Private(n, _) =>
+                                        ua_image_count[n as usize] += 1,
+                                    Image::Unknown(n, _) =>
+                                        ua_image_count[n as usize] += 1,
+                                },
+                                Ok(Subpacket::Unknown(n, _)) =>
+                                    ua_unknown_count[n as usize] += 1,
+                                Err(_) => ua_invalid_count += 1,
+                            }
                         }
-                    }
-                },
+                    },
 
-                _ => (),
-            }
+                    _ => (),
+                }
 
-            if let Packet::Unknown(_) = packet {
-                tags_unknown[i] += 1;
-            } else {
-                // Only record size statistics of packets we successfully
-                // parsed.
-                if let Some(n) = size {
-                    tags_size_bytes[i] += n as usize;
+                if let Packet::Unknown(_) = packet {
+                    tags_unknown[i] += 1;
+                } else {
+                    // Only record size statistics of packets we
+                    // successfully parsed.
+                    let n = size as u32;
+                    tags_size_bytes[i] += size;
                     tags_size_count[i] += 1;
                     if n < tags_size_min[i] {
                         tags_size_min[i] = n;
@@ -153,14 +151,14 @@ fn main() {
                         tags_size_max[i] = n;
                     }
 
-                    tpk.bytes += n as usize;
+                    per_tpk.bytes += size;
+                    per_tpk.packets += 1;
+                    per_tpk.tags[i] += 1;
                 }
-
-                tpk.packets += 1;
-                tpk.tags[i] += 1;
             }
+
+            per_tpk.update_min_max(&mut tpk_min, &mut tpk_max);
         }
-        tpk.update_min_max(&mut tpk_min, &mut tpk_max);
     }
 
     // Print statistics.
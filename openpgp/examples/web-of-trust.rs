@@ -10,7 +10,7 @@
 use std::env;
 
 extern crate sequoia_openpgp as openpgp;
-use openpgp::tpk::TPKParser;
+use openpgp::tpk::ParallelTPKParser;
 use openpgp::parse::Parse;
 
 fn main() {
@@ -30,7 +30,7 @@ fn main() {
     // For each input file, create a parser.
     for input in &args[1..] {
         eprintln!("Parsing {}...", input);
-        let mut parser = TPKParser::from_file(input)
+        let parser = ParallelTPKParser::from_file(input)
             .expect("Failed to create reader");
 
         for tpk in parser {
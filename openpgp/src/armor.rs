@@ -26,7 +26,8 @@
 //! ```
 
 extern crate base64;
-use buffered_reader::BufferedReader;
+use buffered_reader::{BufferedReader, buffered_reader_generic_read_impl};
+use std::fmt;
 use std::io::{Cursor, Read, Write};
 use std::io::{Result, Error, ErrorKind};
 use std::path::Path;
@@ -38,6 +39,7 @@ use packet::prelude::*;
 use packet::BodyLength;
 use packet::ctb::{CTBNew, CTBOld};
 use serialize::SerializeInto;
+use parse::Cookie;
 
 /// The encoded output stream must be represented in lines of no more
 /// than 76 characters each (see (see [RFC 4880, section
@@ -45,6 +47,9 @@ use serialize::SerializeInto;
 /// 64.
 const LINE_LENGTH: usize = 64;
 
+/// The maximum line length permitted by RFC 4880.
+const MAX_LINE_LENGTH: usize = 76;
+
 const LINE_ENDING: &str = "\n";
 
 /// Specifies the type of data (see [RFC 4880, section 6.2]).
@@ -147,12 +152,180 @@ impl Kind {
     }
 }
 
+/// A builder for configuring an armor [`Writer`].
+///
+/// By default, the writer emits 64 character lines, no armor
+/// headers, and a checksum.
+///
+/// [`Writer`]: struct.Writer.html
+///
+/// # Example
+///
+/// ```
+/// # use std::io::Write;
+/// # extern crate sequoia_openpgp as openpgp;
+/// # use openpgp::armor::{WriterBuilder, Kind};
+/// # use std::io::{self, Result};
+/// # fn main() { f().unwrap(); }
+/// # fn f() -> Result<()> {
+/// let mut buffer = io::Cursor::new(vec![]);
+/// {
+///     let mut writer = WriterBuilder::new(Kind::File)
+///         .comment("Hello")
+///         .line_length(8)
+///         .checksum(false)
+///         .build(&mut buffer)?;
+///     writer.write_all(b"Hello world!")?;
+/// }
+/// assert_eq!(
+///     String::from_utf8_lossy(buffer.get_ref()),
+///     "-----BEGIN PGP ARMORED FILE-----
+/// Comment: Hello
+///
+/// SGVsbG8g
+/// d29ybGQh
+/// -----END PGP ARMORED FILE-----
+/// ");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct WriterBuilder {
+    kind: Kind,
+    headers: Vec<(String, String)>,
+    line_length: usize,
+    checksum: bool,
+}
+
+impl WriterBuilder {
+    /// Returns a new builder for the given type of data.
+    pub fn new(kind: Kind) -> Self {
+        WriterBuilder {
+            kind: kind,
+            headers: Vec::new(),
+            line_length: LINE_LENGTH,
+            checksum: true,
+        }
+    }
+
+    /// Adds an armor header.
+    ///
+    /// Headers are emitted in the order they are added.  A key may
+    /// be added multiple times.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+        where K: Into<String>, V: Into<String>
+    {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds a `Comment` header.
+    pub fn comment<S: Into<String>>(self, comment: S) -> Self {
+        self.header("Comment", comment)
+    }
+
+    /// Adds a `Version` header.
+    pub fn version<S: Into<String>>(self, version: S) -> Self {
+        self.header("Version", version)
+    }
+
+    /// Adds a `Charset` header.
+    ///
+    /// This describes the character set that the plaintext is in,
+    /// e.g., `UTF-8`.
+    pub fn charset<S: Into<String>>(self, charset: S) -> Self {
+        self.header("Charset", charset)
+    }
+
+    /// Sets the number of base64 characters per line.
+    ///
+    /// The line length must be a multiple of 4, and may be at most
+    /// 76 characters (see [RFC 4880, section 6.3]).  The default is
+    /// 64.
+    ///
+    /// [RFC 4880, section 6.3]: https://tools.ietf.org/html/rfc4880#section-6.3
+    pub fn line_length(mut self, line_length: usize) -> Self {
+        self.line_length = line_length;
+        self
+    }
+
+    /// Controls whether the CRC-24 checksum is emitted.
+    ///
+    /// The checksum is emitted by default.  Note: readers in
+    /// `ReaderMode::Strict` reject armored data without a checksum.
+    pub fn checksum(mut self, enabled: bool) -> Self {
+        self.checksum = enabled;
+        self
+    }
+
+    /// Finishes configuring the `Writer`.
+    ///
+    /// Fails if the line length is invalid, or a header can't be
+    /// represented.
+    pub fn build<W: Write>(self, inner: W) -> Result<Writer<W>> {
+        if self.line_length == 0 || self.line_length % 4 != 0
+            || self.line_length > MAX_LINE_LENGTH
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid line length {}: must be a multiple of 4 \
+                         between 4 and {}",
+                        self.line_length, MAX_LINE_LENGTH)));
+        }
+
+        for &(ref key, ref value) in self.headers.iter() {
+            if key.is_empty()
+                || key.chars().any(|c| c.is_whitespace() || c == ':')
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid armor header key: {:?}", key)));
+            }
+            if value.contains(|c| c == '\r' || c == '\n') {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid value for armor header {:?}: {:?}",
+                            key, value)));
+            }
+        }
+
+        let mut w = Writer {
+            sink: inner,
+            kind: self.kind,
+            stash: Vec::<u8>::with_capacity(2),
+            column: 0,
+            line_length: self.line_length,
+            checksum: self.checksum,
+            crc: CRC::new(),
+            epilogue: Vec::with_capacity(128),
+            dirty: false,
+            finalized: false,
+        };
+
+        {
+            let mut cur = Cursor::new(&mut w.epilogue);
+            write!(&mut cur, "{}{}", self.kind.begin(), LINE_ENDING)?;
+
+            for h in self.headers.iter() {
+                write!(&mut cur, "{}: {}{}", h.0, h.1, LINE_ENDING)?;
+            }
+
+            // A blank line separates the headers from the body.
+            write!(&mut cur, "{}", LINE_ENDING)?;
+        }
+
+        Ok(w)
+    }
+}
+
 /// A filter that applies ASCII Armor to the data written to it.
 pub struct Writer<W: Write> {
     sink: W,
     kind: Kind,
     stash: Vec<u8>,
     column: usize,
+    line_length: usize,
+    checksum: bool,
     crc: CRC,
     epilogue: Vec<u8>,
     dirty: bool,
@@ -162,6 +335,11 @@ pub struct Writer<W: Write> {
 impl<W: Write> Writer<W> {
     /// Constructs a new filter for the given type of data.
     ///
+    /// To change the line length, or to omit the checksum, use a
+    /// [`WriterBuilder`].
+    ///
+    /// [`WriterBuilder`]: struct.WriterBuilder.html
+    ///
     /// # Example
     ///
     /// ```
@@ -191,30 +369,9 @@ impl<W: Write> Writer<W> {
     /// # }
     /// ```
     pub fn new(inner: W, kind: Kind, headers: &[(&str, &str)]) -> Result<Self> {
-        let mut w = Writer {
-            sink: inner,
-            kind: kind,
-            stash: Vec::<u8>::with_capacity(2),
-            column: 0,
-            crc: CRC::new(),
-            epilogue: Vec::with_capacity(128),
-            dirty: false,
-            finalized: false,
-        };
-
-        {
-            let mut cur = Cursor::new(&mut w.epilogue);
-            write!(&mut cur, "{}{}", kind.begin(), LINE_ENDING)?;
-
-            for h in headers {
-                write!(&mut cur, "{}: {}{}", h.0, h.1, LINE_ENDING)?;
-            }
-
-            // A blank line separates the headers from the body.
-            write!(&mut cur, "{}", LINE_ENDING)?;
-        }
-
-        Ok(w)
+        headers.iter()
+            .fold(WriterBuilder::new(kind), |b, h| b.header(h.0, h.1))
+            .build(inner)
     }
 
     fn write_epilogue(&mut self) -> Result<()> {
@@ -255,17 +412,21 @@ impl<W: Write> Writer<W> {
             write!(self.sink, "{}", LINE_ENDING)?;
         }
 
-        let crc = self.crc.finalize();
-        let bytes: [u8; 3] = [
-            (crc >> 16) as u8,
-            (crc >>  8) as u8,
-            (crc >>  0) as u8,
-        ];
+        if self.checksum {
+            let crc = self.crc.finalize();
+            let bytes: [u8; 3] = [
+                (crc >> 16) as u8,
+                (crc >>  8) as u8,
+                (crc >>  0) as u8,
+            ];
+
+            write!(self.sink, "={}{}",
+                   base64::encode_config(&bytes, base64::STANDARD_NO_PAD),
+                   LINE_ENDING)?;
+        }
 
-        // CRC and footer.
-        write!(self.sink, "={}{}{}{}",
-               base64::encode_config(&bytes, base64::STANDARD_NO_PAD),
-               LINE_ENDING, self.kind.end(), LINE_ENDING)?;
+        // Footer.
+        write!(self.sink, "{}{}", self.kind.end(), LINE_ENDING)?;
 
         self.finalized = true;
         Ok(())
@@ -273,8 +434,8 @@ impl<W: Write> Writer<W> {
 
     /// Inserts a line break if necessary.
     fn linebreak(&mut self) -> Result<()> {
-        assert!(self.column <= LINE_LENGTH);
-        if self.column == LINE_LENGTH {
+        assert!(self.column <= self.line_length);
+        if self.column == self.line_length {
             write!(self.sink, "{}", LINE_ENDING)?;
             self.column = 0;
         }
@@ -340,7 +501,7 @@ impl<W: Write> Write for Writer<W> {
         written += input.len();
         let mut enc = encoded.as_bytes();
         while enc.len() > 0 {
-            let n = min(LINE_LENGTH - self.column, enc.len());
+            let n = min(self.line_length - self.column, enc.len());
             self.sink.write_all(&enc[..n])?;
             enc = &enc[n..];
             self.column += n;
@@ -391,19 +552,82 @@ pub enum ReaderMode {
     /// on large text files.  It is primarily appropriate when reading
     /// text that the user cut and pasted into a text area.
     VeryTolerant,
+
+    /// Makes the armor reader reject malformed armor.
+    ///
+    /// In this mode, the armor header line must be on a line by
+    /// itself, the armor headers must be of the form `Key: Value`
+    /// and be followed by an empty line, the body may only contain
+    /// base64 data, and the checksum must be present and correct.
+    /// Any violation results in an error describing the problem.
+    ///
+    /// If a Kind is specified, then only ASCII Armor blocks with the
+    /// appropriate header are recognized.
+    ///
+    /// This mode is appropriate when checking that armored data
+    /// is well-formed.
+    Strict(Option<Kind>),
+}
+
+impl ReaderMode {
+    /// Returns the kind of data that is expected, if any.
+    fn expected_kind(&self) -> Option<Kind> {
+        match self {
+            &ReaderMode::Tolerant(kind) => kind,
+            &ReaderMode::Strict(kind) => kind,
+            &ReaderMode::VeryTolerant => None,
+        }
+    }
+
+    /// Returns whether this is strict mode.
+    fn is_strict(&self) -> bool {
+        if let &ReaderMode::Strict(_) = self {
+            true
+        } else {
+            false
+        }
+    }
 }
 
 /// A filter that strips ASCII Armor from a stream of data.
+///
+/// The reader implements both `io::Read` and `BufferedReader`.  It
+/// is safe to mix the two interfaces.
 pub struct Reader<'a> {
-    source: Box<'a + BufferedReader<()>>,
+    source: Box<'a + BufferedReader<Cookie>>,
     kind: Option<Kind>,
     mode: ReaderMode,
+    // The decoded data.
     buffer: Vec<u8>,
+    // The next byte to return in the buffer.
+    cursor: usize,
     crc: CRC,
     expect_crc: Option<u32>,
     initialized: bool,
     headers: Vec<(String, String)>,
     finalized: bool,
+    multi_block: bool,
+
+    // The user settable cookie.
+    cookie: Cookie,
+}
+
+impl<'a> fmt::Display for Reader<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "armor::Reader")
+    }
+}
+
+impl<'a> fmt::Debug for Reader<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("armor::Reader")
+            .field("kind", &self.kind)
+            .field("mode", &self.mode)
+            .field("buffer data", &(self.buffer.len() - self.cursor))
+            .field("finalized", &self.finalized)
+            .field("source", &self.source)
+            .finish()
+    }
 }
 
 impl Default for ReaderMode {
@@ -488,7 +712,8 @@ impl<'a> Reader<'a> {
               M: Into<Option<ReaderMode>>
     {
         Self::from_buffered_reader(
-            Box::new(buffered_reader::Generic::new(inner, None)),
+            Box::new(buffered_reader::Generic::with_cookie(
+                inner, None, Default::default())),
             mode)
     }
 
@@ -498,7 +723,8 @@ impl<'a> Reader<'a> {
               M: Into<Option<ReaderMode>>
     {
         Self::from_buffered_reader(
-            Box::new(buffered_reader::Generic::new(reader, None)),
+            Box::new(buffered_reader::Generic::with_cookie(
+                reader, None, Default::default())),
             mode)
     }
 
@@ -508,7 +734,8 @@ impl<'a> Reader<'a> {
               M: Into<Option<ReaderMode>>
     {
         Ok(Self::from_buffered_reader(
-            Box::new(buffered_reader::File::open(path)?),
+            Box::new(buffered_reader::File::with_cookie(
+                path, Default::default())?),
             mode))
     }
 
@@ -517,26 +744,144 @@ impl<'a> Reader<'a> {
         where M: Into<Option<ReaderMode>>
     {
         Self::from_buffered_reader(
-            Box::new(buffered_reader::Memory::new(bytes)),
+            Box::new(buffered_reader::Memory::with_cookie(
+                bytes, Default::default())),
             mode)
     }
 
-    pub(crate) fn from_buffered_reader<C: 'a, M>(
-        inner: Box<'a + BufferedReader<C>>, mode: M) -> Self
+    pub(crate) fn from_buffered_reader<M>(
+        inner: Box<'a + BufferedReader<Cookie>>, mode: M) -> Self
         where M: Into<Option<ReaderMode>>
     {
         let mode = mode.into().unwrap_or(Default::default());
 
         Reader {
-            source: Box::new(buffered_reader::Generic::new(inner, None)),
+            source: inner,
             kind: None,
             mode: mode,
             buffer: Vec::<u8>::with_capacity(1024),
+            cursor: 0,
             crc: CRC::new(),
             expect_crc: None,
             headers: Vec::new(),
             initialized: false,
             finalized: false,
+            multi_block: false,
+            cookie: Default::default(),
+        }
+    }
+
+    /// Controls whether the reader continues with the next armored
+    /// block once the current one ends.
+    ///
+    /// By default, the reader only returns the data of the first
+    /// armored block, and signals EOF when it reaches its footer.
+    /// Files frequently contain several concatenated blocks, e.g., a
+    /// keyring consisting of several `PGP PUBLIC KEY BLOCK`s.  If
+    /// enabled, the reader returns the data of all blocks (of the
+    /// requested kind, if any) one after the other, skipping any
+    /// text between them.  Then, [`kind`] and [`headers`] describe
+    /// the block that is currently being read.
+    ///
+    /// To process the blocks individually, use [`next_block`]
+    /// instead.
+    ///
+    /// [`kind`]: #method.kind
+    /// [`headers`]: #method.headers
+    /// [`next_block`]: #method.next_block
+    pub fn multi_block(mut self, enabled: bool) -> Self {
+        self.multi_block = enabled;
+        self
+    }
+
+    /// Advances to the next armored block.
+    ///
+    /// Skips the rest of the current block (checking its CRC sum,
+    /// if any), and looks for the next block's Armor Header Line.
+    /// Returns `false` if there are no more blocks.  Otherwise, the
+    /// reader returns the new block's data, and [`kind`] and
+    /// [`headers`] describe it.
+    ///
+    /// [`kind`]: #method.kind
+    /// [`headers`]: #method.headers
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::io::Read;
+    /// # extern crate sequoia_openpgp as openpgp;
+    /// # use openpgp::armor::{Reader, ReaderMode, Kind};
+    /// # use std::io::{self, Result};
+    /// # fn main() { f().unwrap(); }
+    /// # fn f() -> Result<()> {
+    /// let data =
+    ///     "-----BEGIN PGP ARMORED FILE-----
+    ///
+    ///      SGVsbG8gd29ybGQh
+    ///      =s4Gu
+    ///      -----END PGP ARMORED FILE-----
+    ///
+    ///      -----BEGIN PGP ARMORED FILE-----
+    ///      Comment: The second block
+    ///
+    ///      SGVsbG8gd29ybGQh
+    ///      =s4Gu
+    ///      -----END PGP ARMORED FILE-----";
+    ///
+    /// let mut reader = Reader::from_bytes(data.as_bytes(), None);
+    ///
+    /// let mut blocks = 0;
+    /// loop {
+    ///     let mut content = String::new();
+    ///     reader.read_to_string(&mut content)?;
+    ///     assert_eq!(content, "Hello world!");
+    ///     assert_eq!(reader.kind(), Some(Kind::File));
+    ///     blocks += 1;
+    ///
+    ///     if ! reader.next_block()? {
+    ///         break;
+    ///     }
+    /// }
+    /// assert_eq!(blocks, 2);
+    /// assert_eq!(reader.kind(), None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn next_block(&mut self) -> Result<bool> {
+        // Skip the rest of the current block.
+        loop {
+            self.buffer.clear();
+            self.cursor = 0;
+            if self.decode(4096)? == 0 {
+                break;
+            }
+        }
+
+        self.start_next_block()
+    }
+
+    /// Looks for the next armored block.
+    ///
+    /// Unlike `next_block`, this doesn't discard any buffered data.
+    fn start_next_block(&mut self) -> Result<bool> {
+        self.kind = None;
+        self.crc = CRC::new();
+        self.expect_crc = None;
+        self.headers.clear();
+        self.initialized = false;
+        self.finalized = false;
+
+        match self.initialize() {
+            Ok(()) => Ok(true),
+            // We only hit EOF while looking for the Armor Header
+            // Line.
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                // There is nothing more to read.
+                self.initialized = true;
+                self.finalized = true;
+                Ok(false)
+            },
+            Err(e) => Err(e),
         }
     }
 
@@ -646,10 +991,7 @@ impl<'a> Reader<'a> {
                 if input[0] == '-' as u8 {
                     // Possible ASCII-armor header.
                     if let Some(kind) = Kind::detect(&input) {
-                        let mut expected_kind = None;
-                        if let ReaderMode::Tolerant(Some(kind)) = self.mode {
-                            expected_kind = Some(kind);
-                        }
+                        let expected_kind = self.mode.expected_kind();
 
                         if expected_kind == None {
                             // Found any!
@@ -685,13 +1027,22 @@ impl<'a> Reader<'a> {
         // We consumed the header above, but not any trailing
         // whitespace and the trailing new line.  We do that now.
         // Other data between the header and the new line are not
-        // allowed.  But, instead of failing, we try to recover, by
-        // stopping at the first non-whitespace character.
+        // allowed.  But, unless we are in strict mode, instead of
+        // failing, we try to recover, by stopping at the first
+        // non-whitespace character.
         let n = {
             let line = self.source.read_to('\n' as u8)?;
-            line.iter().position(|&c| {
+            let n = line.iter().position(|&c| {
                 !c.is_ascii_whitespace()
-            }).unwrap_or(line.len())
+            }).unwrap_or(line.len());
+
+            if n < line.len() && self.mode.is_strict() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unexpected data after Armor Header Line: {:?}",
+                            String::from_utf8_lossy(&line[n..]))));
+            }
+            n
         };
         self.source.consume(n);
 
@@ -705,9 +1056,21 @@ impl<'a> Reader<'a> {
             n = line.len();
             lines += 1;
 
+            if n == 0 && self.mode.is_strict() {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Reached EOF while reading the armor headers"));
+            }
+
             let line = str::from_utf8(line);
             // Ignore---don't error out---lines that are not valid UTF8.
             if line.is_err() {
+                if self.mode.is_strict() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Armor header line {} is not valid UTF-8",
+                                lines)));
+                }
                 continue;
             }
 
@@ -730,6 +1093,17 @@ impl<'a> Reader<'a> {
 
             /* Process headers.  */
             let key_value = line.splitn(2, ": ").collect::<Vec<&str>>();
+            if self.mode.is_strict() && line.trim_start().len() > 0
+                && (key_value.len() == 1
+                    || key_value[0].is_empty()
+                    || key_value[0].chars().any(|c| c.is_whitespace()))
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Malformed armor header line {}: {:?}",
+                            lines, line)));
+            }
+
             if key_value.len() == 1 {
                 if line.trim_start().len() == 0 {
                     // Empty line.
@@ -875,6 +1249,24 @@ fn is_base64_char(b: &u8) -> bool {
     b.is_ascii_alphanumeric() || *b == '+' as u8 || *b == '/' as u8
 }
 
+/// Checks that the armored data only contains base64 characters,
+/// padding, and whitespace.
+///
+/// The data may end with the start of the footer, so dashes are
+/// accepted too.  If they occur anywhere else, decoding fails.
+fn check_base64(bytes: &[u8]) -> Result<()> {
+    if let Some(c) = bytes.iter().find(|c| {
+        ! (is_base64_char(c) || c.is_ascii_whitespace()
+           || **c == b'=' || **c == b'-')
+    }) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid character in armored data: {:?}",
+                    *c as char)));
+    }
+    Ok(())
+}
+
 /// Checks whether the given slice looks like an armor footer.  If so,
 /// returns the size of the footer.
 fn is_footer(buf: &[u8], reference: &[u8]) -> Option<usize> {
@@ -920,39 +1312,27 @@ fn find_footer(buf: &[u8], kind: Kind) -> Option<(usize, usize)> {
     None
 }
 
-impl<'a> Read for Reader<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+impl<'a> Reader<'a> {
+    /// Decodes more data from the current armored block.
+    ///
+    /// The decoded data is appended to the buffer.  Tries to decode
+    /// about `amount` bytes.  Returns the number of bytes that
+    /// were decoded; 0 means that the end of the block was reached.
+    fn decode(&mut self, amount: usize) -> Result<usize> {
         self.initialize()?;
 
-        /* How much did we get?  */
-        let mut read = 0;
-
-        // First, use what we have in the buffer.
-        let amount = min(buf.len(), self.buffer.len());
-        &mut buf[..amount].copy_from_slice(&self.buffer[..amount]);
-        self.buffer.drain(..amount);
-        read += amount;
-
-        // If we could satisfy the read from the buffer, we're done.
-        if read == buf.len() {
-            return Ok(read);
-        }
-        assert_eq!(self.buffer.len(), 0);
-
-        // Our buffer is drained.  If we are finalized, nothing more
-        // can be read.
+        // If we are finalized, nothing more can be read.
         if self.finalized {
-            return Ok(read);
+            return Ok(0);
         }
 
         let (consumed, decoded) = {
-            // Try to get enough bytes to fill buf, account for bytes
-            // filled using our buffer, round up, and add enough for
-            // the footer.
+            // Try to get enough bytes to decode `amount` bytes, round
+            // up, and add enough for the footer.
             //
             // Later, we may have to get some more until we have a
             // multiple of four non-whitespace ASCII characters.
-            let mut want = (buf.len() - read + 2) / 3 * 4
+            let mut want = (amount + 2) / 3 * 4
                 + self.kind.map(|k| k.footer_max_len()).unwrap_or(46);
 
             // Keep track of how much we got last time to detect
@@ -998,7 +1378,15 @@ impl<'a> Read for Reader<'a> {
                 // Check if we see the footer.  If so, we're almost done.
                 if let Some(kind) = self.kind {
                     if let Some((n, end)) = find_footer(&raw, kind) {
+                        if self.mode.is_strict() {
+                            check_base64(&raw[..n])?;
+                        }
                         self.expect_crc = Reader::finalize(&raw[n..], self.kind)?;
+                        if self.expect_crc.is_none() && self.mode.is_strict() {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                "Armor checksum is missing"));
+                        }
                         self.finalized = true;
                         match base64::decode_config(&raw[..n], base64::MIME) {
                             Ok(d) => break (end, d),
@@ -1008,6 +1396,10 @@ impl<'a> Read for Reader<'a> {
                     }
                 }
 
+                if self.mode.is_strict() {
+                    check_base64(&raw)?;
+                }
+
                 // See how many valid characters we got.
                 let n = &raw.iter().filter(
                     |c| ! (**c).is_ascii_whitespace()).count();
@@ -1038,33 +1430,121 @@ impl<'a> Read for Reader<'a> {
         self.source.consume(consumed);
         self.crc.update(&decoded);
 
-        /* Check how much we got vs how much was requested.  */
-        if decoded.len() <= (buf.len() - read) {
-            &mut buf[read..read + decoded.len()].copy_from_slice(&decoded);
-            read += decoded.len();
-        } else {
-            // We got more than we wanted, spill the surplus into our
-            // buffer.
-            let spill = decoded.len() - (buf.len() - read);
+        // If we are finalized, we may have found a crc sum.
+        if let Some(crc) = self.expect_crc {
+            if self.crc.finalize() != crc {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Bad CRC sum: expected {:06X}, computed {:06X}",
+                            crc, self.crc.finalize())));
+            }
+        }
 
-            &mut buf[read..read + decoded.len() - spill].copy_from_slice(
-                &decoded[..decoded.len() - spill]);
-            read += decoded.len() - spill;
+        // Discard the data that has already been consumed.
+        self.buffer.drain(..self.cursor);
+        self.cursor = 0;
+        self.buffer.extend_from_slice(&decoded);
 
-            self.buffer.extend_from_slice(&decoded[decoded.len() - spill..]);
-        }
+        Ok(decoded.len())
+    }
 
-        /* If we are finalized, we may have found a crc sum.  */
-        if let Some(crc) = self.expect_crc {
-            if self.crc.finalize() != crc {
-                return Err(Error::new(ErrorKind::InvalidInput, "Bad CRC sum."));
+    /// Returns the buffer.  Ensures that it contains at least
+    /// `amount` bytes, unless EOF is reached.
+    fn data_helper(&mut self, amount: usize, hard: bool, and_consume: bool)
+                   -> Result<&[u8]> {
+        while self.buffer.len() - self.cursor < amount {
+            let missing = amount - (self.buffer.len() - self.cursor);
+            if self.decode(missing)? == 0 {
+                // The current block is exhausted, continue with the
+                // next one, if any.
+                if self.multi_block && self.start_next_block()? {
+                    continue;
+                }
+                break;
             }
         }
-        Ok(read)
+
+        let available = self.buffer.len() - self.cursor;
+        if hard && available < amount {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "EOF"));
+        }
+
+        if and_consume {
+            let consumed = min(available, amount);
+            self.cursor += consumed;
+            Ok(&self.buffer[self.cursor - consumed..])
+        } else {
+            Ok(&self.buffer[self.cursor..])
+        }
+    }
+}
+
+impl<'a> Read for Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        buffered_reader_generic_read_impl(self, buf)
     }
 }
 
-// XXX: impl BufferedReader for Reader
+impl<'a> BufferedReader<Cookie> for Reader<'a> {
+    fn buffer(&self) -> &[u8] {
+        &self.buffer[self.cursor..]
+    }
+
+    fn data(&mut self, amount: usize) -> Result<&[u8]> {
+        self.data_helper(amount, false, false)
+    }
+
+    fn data_hard(&mut self, amount: usize) -> Result<&[u8]> {
+        self.data_helper(amount, true, false)
+    }
+
+    fn consume(&mut self, amount: usize) -> &[u8] {
+        // The caller can't consume more than is buffered!
+        assert!(amount <= self.buffer.len() - self.cursor,
+                "buffer contains just {} bytes, but you are trying to \
+                 consume {} bytes.  Did you forget to call data()?",
+                self.buffer.len() - self.cursor, amount);
+
+        self.cursor += amount;
+        &self.buffer[self.cursor - amount..]
+    }
+
+    fn data_consume(&mut self, amount: usize) -> Result<&[u8]> {
+        self.data_helper(amount, false, true)
+    }
+
+    fn data_consume_hard(&mut self, amount: usize) -> Result<&[u8]> {
+        self.data_helper(amount, true, true)
+    }
+
+    fn get_mut(&mut self) -> Option<&mut BufferedReader<Cookie>> {
+        Some(&mut self.source)
+    }
+
+    fn get_ref(&self) -> Option<&BufferedReader<Cookie>> {
+        Some(&self.source)
+    }
+
+    fn into_inner<'b>(self: Box<Self>)
+            -> Option<Box<BufferedReader<Cookie> + 'b>>
+            where Self: 'b {
+        Some(self.source)
+    }
+
+    fn cookie_set(&mut self, cookie: Cookie) -> Cookie {
+        use std::mem;
+
+        mem::replace(&mut self.cookie, cookie)
+    }
+
+    fn cookie_ref(&self) -> &Cookie {
+        &self.cookie
+    }
+
+    fn cookie_mut(&mut self) -> &mut Cookie {
+        &mut self.cookie
+    }
+}
 
 const CRC24_INIT: u32 = 0xB704CE;
 const CRC24_POLY: u32 = 0x1864CFB;
@@ -1212,8 +1692,157 @@ mod test {
                -----END PGP ARMORED FILE-----\n"[..]);
     }
 
+    #[test]
+    fn writer_builder() {
+        use super::WriterBuilder;
+
+        let payload = vec![0xAB; 60];
+
+        let mut buf = Vec::new();
+        {
+            let mut w = WriterBuilder::new(Kind::Signature)
+                .version("Sequoia")
+                .comment("First")
+                .comment("Second")
+                .charset("UTF-8")
+                .header("Hash", "SHA256")
+                .line_length(76)
+                .checksum(false)
+                .build(&mut buf).unwrap();
+            w.write_all(&payload).unwrap();
+        }
+        let armored = String::from_utf8(buf.clone()).unwrap();
+        let lines = armored.lines().collect::<Vec<_>>();
+        assert_eq!(&lines[..6],
+                   &["-----BEGIN PGP SIGNATURE-----",
+                     "Version: Sequoia",
+                     "Comment: First",
+                     "Comment: Second",
+                     "Charset: UTF-8",
+                     "Hash: SHA256"]);
+        assert_eq!(lines[6], "");
+        // 60 bytes are 80 base64 characters.
+        assert_eq!(lines[7].len(), 76);
+        assert_eq!(lines[8].len(), 4);
+        // No checksum.
+        assert_eq!(lines[9], "-----END PGP SIGNATURE-----");
+        assert_eq!(lines.len(), 10);
+
+        let mut r = Reader::new(Cursor::new(&buf),
+                                ReaderMode::Tolerant(Some(Kind::Signature)));
+        assert_eq!(r.headers().unwrap().len(), 5);
+        let mut dearmored = Vec::new();
+        r.read_to_end(&mut dearmored).unwrap();
+        assert_eq!(dearmored, payload);
+
+        // Strict mode requires a checksum.
+        let mut r = Reader::new(Cursor::new(&buf),
+                                ReaderMode::Strict(Some(Kind::Signature)));
+        let mut dearmored = Vec::new();
+        assert!(r.read_to_end(&mut dearmored).is_err());
+
+        // Invalid line lengths.
+        for &l in &[0, 3, 62, 80] {
+            assert!(WriterBuilder::new(Kind::File).line_length(l)
+                    .build(Vec::new()).is_err());
+        }
+
+        // Invalid headers.
+        assert!(WriterBuilder::new(Kind::File).header("", "x")
+                .build(Vec::new()).is_err());
+        assert!(WriterBuilder::new(Kind::File).header("A B", "x")
+                .build(Vec::new()).is_err());
+        assert!(WriterBuilder::new(Kind::File).header("A:", "x")
+                .build(Vec::new()).is_err());
+        assert!(WriterBuilder::new(Kind::File).comment("x\ny")
+                .build(Vec::new()).is_err());
+    }
+
     use super::{Reader, ReaderMode};
 
+    #[test]
+    fn dearmor_strict() {
+        fn dearmor(armored: &str) -> ::std::io::Result<Vec<u8>> {
+            let mut r = Reader::new(Cursor::new(armored.as_bytes()),
+                                    ReaderMode::Strict(None));
+            let mut dearmored = Vec::new();
+            r.read_to_end(&mut dearmored)?;
+            Ok(dearmored)
+        }
+
+        // Well-formed.
+        assert_eq!(dearmor("-----BEGIN PGP ARMORED FILE-----\n\
+                            Comment: Some Header\n\
+                            \n\
+                            NdWx\n\
+                            =oSKZ\n\
+                            -----END PGP ARMORED FILE-----\n").unwrap(),
+                   &TEST_BIN[3][..]);
+
+        // Bad CRC.
+        let e = dearmor("-----BEGIN PGP ARMORED FILE-----\n\
+                         \n\
+                         NdWx\n\
+                         =oSKY\n\
+                         -----END PGP ARMORED FILE-----\n").unwrap_err();
+        assert!(e.to_string().contains("Bad CRC sum"), "{}", e);
+
+        // Missing CRC.
+        let e = dearmor("-----BEGIN PGP ARMORED FILE-----\n\
+                         \n\
+                         NdWx\n\
+                         -----END PGP ARMORED FILE-----\n").unwrap_err();
+        assert!(e.to_string().contains("checksum is missing"), "{}", e);
+
+        // Garbage after the armor header line.
+        let e = dearmor("-----BEGIN PGP ARMORED FILE----- junk\n\
+                         \n\
+                         NdWx\n\
+                         =oSKZ\n\
+                         -----END PGP ARMORED FILE-----\n").unwrap_err();
+        assert!(e.to_string().contains("after Armor Header Line"), "{}", e);
+
+        // Malformed header line.
+        let e = dearmor("-----BEGIN PGP ARMORED FILE-----\n\
+                         Comment Some Header\n\
+                         \n\
+                         NdWx\n\
+                         =oSKZ\n\
+                         -----END PGP ARMORED FILE-----\n").unwrap_err();
+        assert!(e.to_string().contains("Malformed armor header line 1"),
+                "{}", e);
+
+        // Missing empty line.
+        let e = dearmor("-----BEGIN PGP ARMORED FILE-----\n\
+                         NdWx\n\
+                         =oSKZ\n\
+                         -----END PGP ARMORED FILE-----\n").unwrap_err();
+        assert!(e.to_string().contains("Malformed armor header line 1"),
+                "{}", e);
+
+        // Invalid character in the body.
+        let e = dearmor("-----BEGIN PGP ARMORED FILE-----\n\
+                         \n\
+                         Nd*Wx\n\
+                         =oSKZ\n\
+                         -----END PGP ARMORED FILE-----\n").unwrap_err();
+        assert!(e.to_string().contains("Invalid character"), "{}", e);
+
+        // The tolerant reader ignores malformed header lines (except
+        // for the first one), and missing checksums.
+        let mut r = Reader::new(
+            Cursor::new(&b"-----BEGIN PGP ARMORED FILE-----\n\
+                           Comment: Fine\n\
+                           Comment Some Header\n\
+                           \n\
+                           NdWx\n\
+                           -----END PGP ARMORED FILE-----\n"[..]),
+            ReaderMode::Tolerant(None));
+        let mut dearmored = Vec::new();
+        r.read_to_end(&mut dearmored).unwrap();
+        assert_eq!(&dearmored[..], TEST_BIN[3]);
+    }
+
     #[test]
     fn dearmor_robust() {
         for (i, reference) in LITERAL_BIN.iter().enumerate() {
@@ -1381,6 +2010,87 @@ mod test {
         }
     }
 
+    #[test]
+    fn dearmor_next_block() {
+        // Concatenate the armored test vectors, with some text in
+        // between.
+        let mut concatenated: Vec<u8> = "Some\ntext\n".into();
+        for asc in TEST_ASC.iter() {
+            concatenated.extend_from_slice(asc);
+            concatenated.extend_from_slice(b"\n- more text -\n\n");
+        }
+        concatenated.extend_from_slice(
+            include_bytes!("../tests/data/armor/test-3.with-headers.asc"));
+
+        let mut r = Reader::new(Cursor::new(&concatenated),
+                                ReaderMode::Tolerant(None));
+        for bin in TEST_BIN.iter() {
+            assert!(r.headers().unwrap().is_empty());
+            let mut dearmored = Vec::<u8>::new();
+            r.read_to_end(&mut dearmored).unwrap();
+            assert_eq!(r.kind(), Some(Kind::File));
+            assert_eq!(&dearmored, bin);
+
+            assert!(r.next_block().unwrap());
+        }
+
+        // The last block has headers.
+        assert_eq!(r.kind(), Some(Kind::File));
+        assert_eq!(r.headers().unwrap(),
+                   &[("Comment".into(), "Some Header".into()),
+                     ("Comment".into(), "Another one".into())]);
+
+        // Skip it without reading it.
+        assert!(! r.next_block().unwrap());
+        assert_eq!(r.kind(), None);
+        assert!(r.headers().unwrap().is_empty());
+        let mut buf = [0; 5];
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+        assert!(! r.next_block().unwrap());
+    }
+
+    #[test]
+    fn dearmor_multi_block() {
+        let mut concatenated = Vec::new();
+        let mut expected = Vec::new();
+        for (bin, asc) in TEST_BIN.iter().zip(TEST_ASC.iter()) {
+            concatenated.extend_from_slice(asc);
+            concatenated.extend_from_slice(b"\nSome text.\n");
+            expected.extend_from_slice(bin);
+        }
+
+        // By default, we only get the first block.
+        let mut r = Reader::new(Cursor::new(&concatenated),
+                                ReaderMode::Tolerant(Some(Kind::File)));
+        let mut dearmored = Vec::<u8>::new();
+        r.read_to_end(&mut dearmored).unwrap();
+        assert_eq!(&dearmored, &TEST_BIN[0]);
+
+        let mut r = Reader::new(Cursor::new(&concatenated),
+                                ReaderMode::Tolerant(Some(Kind::File)))
+            .multi_block(true);
+        let mut dearmored = Vec::<u8>::new();
+        r.read_to_end(&mut dearmored).unwrap();
+        assert_eq!(dearmored, expected);
+
+        // Blocks of other kinds are skipped, and there are no
+        // messages.
+        let mut r = Reader::new(Cursor::new(&concatenated),
+                                ReaderMode::Tolerant(Some(Kind::Message)))
+            .multi_block(true);
+        let mut dearmored = Vec::<u8>::new();
+        assert!(r.read_to_end(&mut dearmored).is_err());
+
+        // A bad CRC sum in a later block is detected.
+        let mut bad = concatenated.clone();
+        bad.extend_from_slice(
+            include_bytes!("../tests/data/armor/test-0.bad-crc.asc"));
+        let mut r = Reader::new(Cursor::new(&bad), None)
+            .multi_block(true);
+        let mut dearmored = Vec::<u8>::new();
+        assert!(r.read_to_end(&mut dearmored).is_err());
+    }
+
     quickcheck! {
         fn roundtrip(kind: Kind, payload: Vec<u8>) -> bool {
             if payload.is_empty() {
@@ -1409,4 +2119,94 @@ mod test {
             payload == recovered && payload == recovered_any
         }
     }
+
+    quickcheck! {
+        fn roundtrip_builder(kind: Kind, payload: Vec<u8>, width: u8,
+                             checksum: bool) -> bool {
+            use super::WriterBuilder;
+
+            if payload.is_empty() {
+                // See above.
+                return true;
+            }
+
+            let line_length = (width as usize % 19 + 1) * 4;
+            let mut encoded = Vec::new();
+            WriterBuilder::new(kind)
+                .comment("Quickcheck")
+                .line_length(line_length)
+                .checksum(checksum)
+                .build(&mut encoded).unwrap()
+                .write_all(&payload)
+                .unwrap();
+
+            let mut recovered = Vec::new();
+            Reader::new(Cursor::new(&encoded),
+                        ReaderMode::Tolerant(Some(kind)))
+                .read_to_end(&mut recovered)
+                .unwrap();
+
+            let mut recovered_strict = Vec::new();
+            let strict = Reader::new(Cursor::new(&encoded),
+                                     ReaderMode::Strict(Some(kind)))
+                .read_to_end(&mut recovered_strict);
+
+            payload == recovered
+                && if checksum {
+                    strict.is_ok() && payload == recovered_strict
+                } else {
+                    strict.is_err()
+                }
+        }
+    }
+
+    quickcheck! {
+        fn roundtrip_buffered_reader(kind: Kind, payload: Vec<u8>,
+                                     chunks: Vec<u8>) -> bool {
+            use buffered_reader::BufferedReader;
+
+            if payload.is_empty() {
+                // See above.
+                return true;
+            }
+
+            let mut encoded = Vec::new();
+            Writer::new(&mut encoded, kind, &[]).unwrap()
+                .write_all(&payload)
+                .unwrap();
+
+            // Read using data_eof.
+            let mut r = Reader::from_bytes(&encoded,
+                                           ReaderMode::Tolerant(Some(kind)));
+            let recovered = r.data_eof().unwrap().to_vec();
+            if r.kind() != Some(kind) {
+                return false;
+            }
+
+            // Read in chunks of random sizes, mixing the
+            // BufferedReader and Read interfaces.
+            let mut r = Reader::from_bytes(&encoded, ReaderMode::VeryTolerant);
+            let mut recovered_chunked = Vec::new();
+            for (i, chunk) in chunks.iter().cycle()
+                .take(payload.len() + 1).enumerate()
+            {
+                let n = *chunk as usize;
+                if i % 2 == 0 {
+                    let data = r.data_consume(n).unwrap();
+                    let n = ::std::cmp::min(n, data.len());
+                    recovered_chunked.extend_from_slice(&data[..n]);
+                } else {
+                    let mut buf = vec![0; n];
+                    let n = r.read(&mut buf).unwrap();
+                    recovered_chunked.extend_from_slice(&buf[..n]);
+                }
+            }
+            let rest = r.data_eof().unwrap().len();
+            recovered_chunked.extend_from_slice(r.consume(rest));
+
+            payload == recovered
+                && payload == recovered_chunked
+                && r.data(1).unwrap().is_empty()
+        }
+    }
 }
@@ -25,6 +25,7 @@ use Error;
 use Result;
 use Packet;
 use packet::SKESK;
+use packet::UserID;
 use TPK;
 use parse::{
     Parse,
@@ -36,6 +37,7 @@ use serialize::stream::{
 };
 use constants::DataFormat;
 use crypto::Password;
+use rfc2822::AddressList;
 
 /// Version of Autocrypt to use. `Autocrypt::default()` always returns the
 /// latest version.
@@ -101,9 +103,51 @@ impl AutocryptHeader {
                              -> Result<Self>
         where P: Into<Option<&'a str>>
     {
+        Ok(AutocryptHeader {
+            header_type: AutocryptHeaderType::Sender,
+            key: Some(Self::minimize(tpk, addr)?),
+            attributes: vec![
+                Attribute {
+                    critical: true,
+                    key: "addr".into(),
+                    value: addr.into(),
+                },
+                Attribute {
+                    critical: true,
+                    key: "prefer-encrypt".into(),
+                    value: prefer_encrypt.into()
+                        .unwrap_or("nopreference").into(),
+                },
+            ],
+        })
+    }
+
+    /// Creates a new "Autocrypt-Gossip" header.
+    ///
+    /// When sending an encrypted message to more than one recipient,
+    /// an "Autocrypt-Gossip" header should be added to the headers
+    /// of the encrypted MIME part for each recipient.  See [Section
+    /// 2.7 of the Autocrypt Level 1 specification].
+    ///
+    ///   [Section 2.7 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#key-gossip
+    pub fn new_gossip(tpk: &TPK, addr: &str) -> Result<Self> {
+        Ok(AutocryptHeader {
+            header_type: AutocryptHeaderType::Gossip,
+            key: Some(Self::minimize(tpk, addr)?),
+            attributes: vec![
+                Attribute {
+                    critical: true,
+                    key: "addr".into(),
+                    value: addr.into(),
+                },
+            ],
+        })
+    }
+
+    /// Minimizes `tpk` for inclusion in a header for `addr`.
+    fn minimize(tpk: &TPK, addr: &str) -> Result<TPK> {
         use packet::Tag;
 
-        // Minimize TPK.
         let mut acc = Vec::new();
 
         // The primary key and the most recent selfsig.
@@ -143,25 +187,55 @@ impl AutocryptHeader {
             }
         }
 
-        let cleaned_tpk = TPK::from_packet_pile(acc.into())?;
+        TPK::from_packet_pile(acc.into())
+    }
 
-        Ok(AutocryptHeader {
-            header_type: AutocryptHeaderType::Sender,
-            key: Some(cleaned_tpk),
-            attributes: vec![
-                Attribute {
-                    critical: true,
-                    key: "addr".into(),
-                    value: addr.into(),
-                },
-                Attribute {
-                    critical: true,
-                    key: "prefer-encrypt".into(),
-                    value: prefer_encrypt.into()
-                        .unwrap_or("nopreference").into(),
+    /// Parses the value of an Autocrypt header.
+    fn parse(header_type: AutocryptHeaderType, value: &str) -> Self {
+        let mut header = AutocryptHeader::empty(header_type);
+
+        for pair in value.split(';') {
+            let pair = pair
+                .splitn(2, |c| c == '=')
+                .collect::<Vec<&str>>();
+
+            let (key, value) : (String, String) = if pair.len() == 1 {
+                // No value...
+                (pair[0].trim_matches(' ').into(), "".into())
+            } else {
+                (pair[0].trim_matches(' ').into(),
+                 pair[1].trim_matches(' ').into())
+            };
+
+            if key == "" {
+                // Trailing separator.
+                continue;
+            }
+
+            if key == "keydata" {
+                if let Ok(decoded) = base64::decode(
+                    &value.replace(" ", "")[..]) {
+                    if let Ok(tpk) = TPK::from_bytes(&decoded[..]) {
+                        header.key = Some(tpk);
+                    }
+                }
+            }
+
+            // Attributes starting with an underscore are not
+            // critical.
+            let critical = ! key.starts_with('_');
+            header.attributes.push(Attribute {
+                critical: critical,
+                key: if critical {
+                    key
+                } else {
+                    key[1..].to_string()
                 },
-            ],
-        })
+                value: value,
+            });
+        }
+
+        header
     }
 
     /// Looks up an attribute.
@@ -182,6 +256,18 @@ pub struct AutocryptHeaders {
     /// The value in the from header.
     pub from: Option<String>,
 
+    /// The addresses in the to header.
+    ///
+    /// This includes the members of groups.  If the header can't be
+    /// parsed, it is ignored.
+    pub to: Vec<String>,
+
+    /// The addresses in the cc header.
+    ///
+    /// This includes the members of groups.  If the header can't be
+    /// parsed, it is ignored.
+    pub cc: Vec<String>,
+
     /// Any autocrypt headers.
     pub headers: Vec<AutocryptHeader>,
 }
@@ -190,6 +276,8 @@ impl AutocryptHeaders {
     fn empty() -> Self {
         AutocryptHeaders {
             from: None,
+            to: Vec::new(),
+            cc: Vec::new(),
             headers: Vec::new(),
         }
     }
@@ -201,8 +289,11 @@ impl AutocryptHeaders {
 
         let mut next_line = lines.next();
         while let Some(line) = next_line {
-            // Return any error.
+            // Return any error.  Lines may end in CRLF.
             let mut line = line?;
+            if line.ends_with('\r') {
+                line.pop();
+            }
 
             if line == "" {
                 // End of headers.
@@ -218,7 +309,7 @@ impl AutocryptHeaders {
             // See https://tools.ietf.org/html/rfc5322#section-2.2.3
             while let Some(Ok(nl)) = next_line {
                 if nl.len() > 0 && (&nl[0..1] == " " || &nl[0..1] == "\t") {
-                    line.push_str(&nl[..]);
+                    line.push_str(nl.trim_end_matches('\r'));
                     next_line = lines.next();
                 } else {
                     // Put it back.
@@ -228,58 +319,76 @@ impl AutocryptHeaders {
             }
 
             const AUTOCRYPT : &str = "Autocrypt: ";
+            const AUTOCRYPT_GOSSIP : &str = "Autocrypt-Gossip: ";
             const FROM : &str = "From: ";
+            const TO : &str = "To: ";
+            const CC : &str = "Cc: ";
 
             if line.starts_with(FROM) {
                 headers.from
                     = Some(line[FROM.len()..].trim_matches(' ').into());
-            } else if line.starts_with(AUTOCRYPT) {
-                let ac_value = &line[AUTOCRYPT.len()..];
-
-                let mut header = AutocryptHeader::empty(
-                    AutocryptHeaderType::Sender);
-
-                for pair in ac_value.split(';') {
-                    let pair = pair
-                        .splitn(2, |c| c == '=')
-                        .collect::<Vec<&str>>();
-
-                    let (key, value) : (String, String) = if pair.len() == 1 {
-                        // No value...
-                        (pair[0].trim_matches(' ').into(), "".into())
-                    } else {
-                        (pair[0].trim_matches(' ').into(),
-                         pair[1].trim_matches(' ').into())
-                    };
-
-                    if key == "keydata" {
-                        if let Ok(decoded) = base64::decode(
-                            &value.replace(" ", "")[..]) {
-                            if let Ok(tpk) = TPK::from_bytes(&decoded[..]) {
-                                header.key = Some(tpk);
-                            }
-                        }
-                    }
-
-                    let critical = key.len() >= 1 && &key[0..1] == "_";
-                    header.attributes.push(Attribute {
-                        critical: critical,
-                        key: if critical {
-                            key[1..].to_string()
-                        } else {
-                            key
-                        },
-                        value: value,
-                    });
+            } else if line.starts_with(TO) {
+                if let Ok(list) = AddressList::parse(&line[TO.len()..]) {
+                    headers.to.extend(list.mailboxes().into_iter()
+                                      .map(|m| m.address().to_string()));
                 }
-
-                headers.headers.push(header);
+            } else if line.starts_with(CC) {
+                if let Ok(list) = AddressList::parse(&line[CC.len()..]) {
+                    headers.cc.extend(list.mailboxes().into_iter()
+                                      .map(|m| m.address().to_string()));
+                }
+            } else if line.starts_with(AUTOCRYPT) {
+                headers.headers.push(AutocryptHeader::parse(
+                    AutocryptHeaderType::Sender, &line[AUTOCRYPT.len()..]));
+            } else if line.starts_with(AUTOCRYPT_GOSSIP) {
+                headers.headers.push(AutocryptHeader::parse(
+                    AutocryptHeaderType::Gossip,
+                    &line[AUTOCRYPT_GOSSIP.len()..]));
             }
         }
 
         return Ok(headers)
     }
 
+    /// Returns the canonicalized addresses of the recipients.
+    ///
+    /// These are the addresses in the to and cc headers.  Malformed
+    /// addresses are ignored.
+    pub fn recipients(&self) -> Vec<String> {
+        self.to.iter().chain(self.cc.iter())
+            .filter_map(|m| canonicalize(m))
+            .collect()
+    }
+
+    /// Returns the valid Autocrypt-Gossip headers.
+    ///
+    /// Autocrypt-Gossip headers are transmitted in the headers of
+    /// the encrypted MIME part.  Hence, `self` should be the parsed
+    /// headers of the decrypted message body.  `recipients` are the
+    /// canonicalized addresses of the message's recipients, see
+    /// `AutocryptHeaders::recipients`.
+    ///
+    /// Headers without a key, headers whose `addr` attribute does not
+    /// match any recipient, and headers with unknown critical
+    /// attributes are ignored.  See [Section 2.7 of the Autocrypt
+    /// Level 1 specification].
+    ///
+    ///   [Section 2.7 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#key-gossip
+    pub fn gossip<S: AsRef<str>>(&self, recipients: &[S])
+                                 -> Vec<&AutocryptHeader> {
+        self.headers.iter().filter(|h| {
+            h.header_type == AutocryptHeaderType::Gossip
+                && h.key.is_some()
+                && h.get("addr").and_then(|a| canonicalize(&a.value))
+                    .map(|a| recipients.iter().any(|r| r.as_ref() == a))
+                    .unwrap_or(false)
+                && h.attributes.iter().all(|a| ! a.critical || match &a.key[..] {
+                    "addr" | "keydata" => true,
+                    _ => false,
+                })
+        }).collect()
+    }
+
     /// Parses an autocrypt header.
     ///
     /// `data` should be all of a mail's headers.
@@ -306,6 +415,15 @@ impl AutocryptHeaders {
     }
 }
 
+/// Canonicalizes an email address.
+///
+/// See [Section 2.1 of the Autocrypt Level 1 specification].
+///
+///   [Section 2.1 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#e-mail-address-canonicalization
+fn canonicalize(mailbox: &str) -> Option<String> {
+    UserID::from(mailbox).address_normalized().ok().and_then(|a| a)
+}
+
 /// Holds an Autocrypt Setup Message.
 ///
 /// An [Autocrypt Setup Message] is used to transfer a private key from
@@ -924,6 +1042,11 @@ In the light of the Efail vulnerability I am asking myself if it's
 
         assert_eq!(ac.from,
                    Some("Vincent Breitmoser <look@my.amazin.horse>".into()));
+        assert_eq!(ac.to.len(), 4);
+        assert_eq!(ac.recipients(),
+                   vec!["gnupg-devel@gnupg.org", "sks-devel@nongnu.org",
+                        "autocrypt@lists.mayfirst.org",
+                        "openpgp-email@enigmail.net"]);
 
         // We expect exactly one Autocrypt header.
         assert_eq!(ac.headers.len(), 1);
@@ -1098,4 +1221,89 @@ In the light of the Efail vulnerability I am asking myself if it's
         assert_eq!(tpk.userids().next().unwrap().userid().value(),
                    &b"Testy McTestface <testy@example.org>"[..]);
     }
+
+    #[test]
+    fn autocrypt_header_attributes() {
+        let tpk = TPK::from_bytes(::tests::key("testy.pgp")).unwrap();
+
+        let mut buf = Vec::new();
+        write!(&mut buf, "Autocrypt: addr=testy@example.org; \
+                          _frob=1; keydata=").unwrap();
+        write!(&mut buf, "{}\r\n",
+               base64::encode(&tpk.to_vec().unwrap())).unwrap();
+
+        let ac = AutocryptHeaders::from_bytes(&buf).unwrap();
+        assert_eq!(ac.headers.len(), 1);
+        let header = &ac.headers[0];
+
+        // Attributes starting with an underscore are not critical,
+        // and the underscore is stripped.
+        assert_eq!(header.get("addr"), Some(&Attribute {
+            critical: true,
+            key: "addr".into(),
+            value: "testy@example.org".into(),
+        }));
+        assert_eq!(header.get("frob"), Some(&Attribute {
+            critical: false,
+            key: "frob".into(),
+            value: "1".into(),
+        }));
+        assert!(header.get("_frob").is_none());
+
+        // Serializing the header round trips.
+        let mut buf = Vec::new();
+        write!(&mut buf, "Autocrypt: ").unwrap();
+        header.serialize(&mut buf).unwrap();
+        let s = String::from_utf8(buf.clone()).unwrap();
+        assert!(s.contains(" _frob=1; "));
+        assert_eq!(s.matches("keydata=").count(), 1);
+
+        let ac2 = AutocryptHeaders::from_bytes(&buf).unwrap();
+        assert_eq!(ac2.headers.len(), 1);
+        assert_eq!(ac2.headers[0].attributes, header.attributes);
+        assert_eq!(ac2.headers[0].key, header.key);
+    }
+
+    #[test]
+    fn autocrypt_gossip() {
+        let tpk = TPK::from_bytes(::tests::key("testy.pgp")).unwrap();
+
+        let mut buf = Vec::new();
+        write!(&mut buf, "To: \"McTestface, Testy\" <Testy@Example.org>,\r\n \
+                          bob@example.org (Bob, the builder)\r\n").unwrap();
+        write!(&mut buf, "Cc: Friends: carol@example.org, \
+                          Dave (Dave, Jr.) <dave@example.org>;\r\n").unwrap();
+        for addr in &["testy@example.org", "mallory@example.org"] {
+            let header = AutocryptHeader::new_gossip(&tpk, addr).unwrap();
+            write!(&mut buf, "Autocrypt-Gossip: ").unwrap();
+            header.serialize(&mut buf).unwrap();
+            write!(&mut buf, "\r\n").unwrap();
+        }
+        write!(&mut buf, "Autocrypt-Gossip: addr=bob@example.org\r\n").unwrap();
+        write!(&mut buf, "Autocrypt-Gossip: addr=testy@example.org; \
+                          frob=1; _ignored=1; keydata=").unwrap();
+        write!(&mut buf, "{}\r\n",
+               base64::encode(&tpk.to_vec().unwrap())).unwrap();
+
+        let ac = AutocryptHeaders::from_bytes(&buf).unwrap();
+        assert_eq!(ac.to.len(), 2);
+        assert_eq!(ac.cc, vec!["carol@example.org", "dave@example.org"]);
+        assert_eq!(ac.headers.len(), 4);
+        assert!(ac.headers.iter()
+                .all(|h| h.header_type == AutocryptHeaderType::Gossip));
+
+        let recipients = ac.recipients();
+        assert_eq!(recipients, vec!["testy@example.org", "bob@example.org",
+                                    "carol@example.org", "dave@example.org"]);
+
+        // Only the first header is valid: the second is for an
+        // address that is not a recipient, the third has no key,
+        // and the fourth has an unknown critical attribute.
+        let gossip = ac.gossip(&recipients);
+        assert_eq!(gossip.len(), 1);
+        assert_eq!(gossip[0].get("addr").unwrap().value,
+                   "testy@example.org");
+        assert_eq!(gossip[0].key.as_ref().unwrap().fingerprint(),
+                   tpk.fingerprint());
+    }
 }
@@ -4,9 +4,11 @@ use HashAlgorithm;
 use packet::UserID;
 use packet::UserAttribute;
 use packet::Key;
-use packet::key::Key4;
+use packet::Literal;
+use packet::key::{Key4, Key5};
 use packet::Signature;
-use packet::signature::{self, Signature4};
+use packet::signature::{self, Signature4, Signature5};
+use SignatureType;
 use Error;
 use Result;
 use conversions::Time;
@@ -213,15 +215,79 @@ impl Hash for Key4 {
     }
 }
 
+impl Hash for Key5 {
+    /// Update the Hash with a hash of the key.
+    fn hash<H: nettle::Hash + Write>(&self, hash: &mut H) {
+        // We hash 14 bytes plus the MPIs.  But, the len doesn't
+        // include the tag (1 byte) or the length (4 bytes).
+        let mpis_len = self.mpis().serialized_len();
+        let len = (15 - 5) + mpis_len;
+
+        let mut header : Vec<u8> = Vec::with_capacity(15);
+
+        // Tag.
+        header.push(0x9A);
+
+        // Length (big endian).
+        header.push(((len >> 24) & 0xFF) as u8);
+        header.push(((len >> 16) & 0xFF) as u8);
+        header.push(((len >> 8) & 0xFF) as u8);
+        header.push((len & 0xFF) as u8);
+
+        // Version.
+        header.push(5);
+
+        // Creation time.
+        let creation_time = self.creation_time().to_pgp()
+            .unwrap_or(0);
+        header.push((creation_time >> 24) as u8);
+        header.push((creation_time >> 16) as u8);
+        header.push((creation_time >> 8) as u8);
+        header.push((creation_time >> 0) as u8);
+
+        // Algorithm.
+        header.push(self.pk_algo().into());
+
+        // Length of the public key material (big endian).
+        header.push(((mpis_len >> 24) & 0xFF) as u8);
+        header.push(((mpis_len >> 16) & 0xFF) as u8);
+        header.push(((mpis_len >> 8) & 0xFF) as u8);
+        header.push((mpis_len & 0xFF) as u8);
+
+        hash.update(&header[..]);
+
+        // MPIs.
+        self.mpis().hash(hash);
+    }
+}
+
+impl Hash for Key {
+    /// Update the Hash with a hash of the key.
+    fn hash<H: nettle::Hash + Write>(&self, hash: &mut H) {
+        match self {
+            Key::V4(key) => key.hash(hash),
+            Key::V5(key) => key.hash(hash),
+        }
+    }
+}
+
 impl Hash for Signature {
     /// Adds the `Signature` to the provided hash context.
     fn hash<H: nettle::Hash + Write>(&self, hash: &mut H) {
         match self {
             Signature::V4(sig) => sig.hash(hash),
+            Signature::V5(sig) => sig.hash(hash),
         }
     }
 }
 
+impl Hash for Signature5 {
+    /// Adds the `Signature` to the provided hash context.
+    fn hash<H: nettle::Hash + Write>(&self, hash: &mut H) {
+        self.fields.hash(hash);
+    }
+}
+
 impl Hash for Signature4 {
     /// Adds the `Signature` to the provided hash context.
     fn hash<H: nettle::Hash + Write>(&self, hash: &mut H) {
@@ -231,8 +297,28 @@ impl Hash for Signature4 {
 
 impl Hash for signature::Builder {
     /// Adds the `Signature` to the provided hash context.
+    ///
+    /// Version 5 document signatures are hashed as if they were
+    /// detached signatures.  Use `hash_literal` for signatures over
+    /// literal data packets.
     fn hash<H: nettle::Hash + Write>(&self, hash: &mut H) {
-        // A version 4 signature packet is laid out as follows:
+        self.hash_literal(hash, None)
+    }
+}
+
+impl signature::Builder {
+    /// Adds the `Signature` made over `literal` to the provided hash
+    /// context.
+    ///
+    /// Version 5 document signatures also hash the literal data
+    /// packet's format, filename, and date.  If `literal` is `None`,
+    /// i.e. for detached and cleartext signatures, six zero octets
+    /// are hashed instead.  For other signatures, `literal` is
+    /// ignored.
+    pub fn hash_literal<H>(&self, hash: &mut H, literal: Option<&Literal>)
+        where H: nettle::Hash + Write
+    {
+        // A version 4 or 5 signature packet is laid out as follows:
         //
         //   version - 1 byte                    \
         //   sigtype - 1 byte                     \
@@ -242,10 +328,11 @@ impl Hash for signature::Builder {
         //   hashed_area                         _/
         //   ...                                 <- Not included in the hash
 
+        let version = self.version();
         let mut header = [0u8; 6];
 
         // Version.
-        header[0] = 4;
+        header[0] = version;
         header[1] = self.sigtype().into();
         header[2] = self.pk_algo().into();
         header[3] = self.hash_algo().into();
@@ -259,30 +346,74 @@ impl Hash for signature::Builder {
 
         hash.update(&self.hashed_area().data[..]);
 
-        // A version 4 signature trailer is:
-        //
-        //   version - 1 byte
-        //   0xFF (constant) - 1 byte
-        //   amount - 4 bytes (big endian)
+        // Version 5 document signatures also hash the literal data
+        // packet's format, filename, and date:
         //
-        // The amount field is the amount of hashed from this
-        // packet (this excludes the message content, and this
-        // trailer).
+        //   format - 1 byte
+        //   filename_len - 1 byte
+        //   filename - filename_len bytes
+        //   date - 4 bytes (big endian)
         //
-        // See https://tools.ietf.org/html/rfc4880#section-5.2.4
-        let mut trailer = [0u8; 6];
+        // See https://tools.ietf.org/html/draft-ietf-openpgp-rfc4880bis-07#section-5.2.4
+        if version == 5 && (self.sigtype() == SignatureType::Binary
+                            || self.sigtype() == SignatureType::Text) {
+            if let Some(literal) = literal {
+                let filename = literal.filename().unwrap_or(&b""[..]);
+                let date = literal.date().and_then(|d| d.to_pgp().ok())
+                    .unwrap_or(0);
+                hash.update(&[literal.format().into(),
+                              filename.len() as u8][..]);
+                hash.update(filename);
+                hash.update(&[(date >> 24) as u8, (date >> 16) as u8,
+                              (date >> 8) as u8, date as u8][..]);
+            } else {
+                hash.update(&[0u8; 6][..]);
+            }
+        }
 
-        trailer[0] = 0x4;
-        trailer[1] = 0xff;
         // The signature packet's length, not including the previous
         // two bytes and the length.
         let len = header.len() + self.hashed_area().data.len();
-        trailer[2] = (len >> 24) as u8;
-        trailer[3] = (len >> 16) as u8;
-        trailer[4] = (len >> 8) as u8;
-        trailer[5] = len as u8;
 
-        hash.update(&trailer[..]);
+        if version == 5 {
+            // A version 5 signature trailer is:
+            //
+            //   version - 1 byte
+            //   0xFF (constant) - 1 byte
+            //   amount - 8 bytes (big endian)
+            let mut trailer = [0u8; 10];
+
+            trailer[0] = 0x5;
+            trailer[1] = 0xff;
+            let len = len as u64;
+            for i in 0..8 {
+                trailer[2 + i] = (len >> (8 * (7 - i))) as u8;
+            }
+
+            hash.update(&trailer[..]);
+        } else {
+            // A version 4 signature trailer is:
+            //
+            //   version - 1 byte
+            //   0xFF (constant) - 1 byte
+            //   amount - 4 bytes (big endian)
+            //
+            // The amount field is the amount of hashed from this
+            // packet (this excludes the message content, and this
+            // trailer).
+            //
+            // See https://tools.ietf.org/html/rfc4880#section-5.2.4
+            let mut trailer = [0u8; 6];
+
+            trailer[0] = 0x4;
+            trailer[1] = 0xff;
+            trailer[2] = (len >> 24) as u8;
+            trailer[3] = (len >> 16) as u8;
+            trailer[4] = (len >> 8) as u8;
+            trailer[5] = len as u8;
+
+            hash.update(&trailer[..]);
+        }
     }
 }
 
@@ -12,8 +12,11 @@ use HashAlgorithm;
 use crypto::Password;
 use crypto::SessionKey;
 
+use std::cmp;
 use std::fmt;
+use std::time::{Duration, Instant};
 
+use argon2;
 use nettle::{Hash, Yarrow, Random};
 use quickcheck::{Arbitrary, Gen};
 use rand::Rng;
@@ -48,12 +51,157 @@ pub enum S2K {
         /// Number of bytes to hash.
         iterations: u32,
     },
+    /// Memory-hard key derivation using Argon2id.
+    ///
+    /// Argon2 must only be used with AEAD.  Since we protect secret
+    /// keys using CFB (S2K usage octet 254), it can only be used to
+    /// encrypt session keys.  See [Section 3.7.1.4 of the crypto
+    /// refresh] and [RFC 9106].
+    ///
+    ///   [Section 3.7.1.4 of the crypto refresh]: https://datatracker.ietf.org/doc/html/draft-ietf-openpgp-crypto-refresh#section-3.7.1.4
+    ///   [RFC 9106]: https://tools.ietf.org/html/rfc9106
+    Argon2 {
+        /// Public salt value mixed into the password.
+        salt: [u8; 16],
+        /// Number of passes (`t`).
+        passes: u8,
+        /// Degree of parallelism (`p`).
+        parallelism: u8,
+        /// The amount of memory to use is `2^memory_exponent` KiB
+        /// (encoded `m`).
+        memory_exponent: u8,
+    },
+    /// GnuPG's S2K extensions.
+    ///
+    /// GnuPG uses the private S2K type 101 to mark secret keys that
+    /// do not carry any secret key material.  These keys are stubs
+    /// that are created, for instance, by `gpg
+    /// --export-secret-subkeys`, or that refer to a key stored on a
+    /// smartcard.  No key can be derived from these specifiers.
+    GNU {
+        /// Hash algorithm.  This is not used by GnuPG, and is usually
+        /// zero.
+        hash: HashAlgorithm,
+        /// The extension.
+        extension: GNUExtension,
+    },
     /// Private S2K algorithm
     Private(u8),
     /// Unknown S2K algorithm
     Unknown(u8),
 }
 
+/// GnuPG's S2K extensions.
+///
+/// See [`S2K::GNU`] and [Section 5.5.2 of GnuPG's DETAILS].
+///
+///   [`S2K::GNU`]: enum.S2K.html#variant.GNU
+///   [Section 5.5.2 of GnuPG's DETAILS]: https://git.gnupg.org/cgi-bin/gitweb.cgi?p=gnupg.git;a=blob;f=doc/DETAILS
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GNUExtension {
+    /// The secret key material is not available (`gnu-dummy`).
+    Dummy,
+    /// The secret key material is stored on a smartcard
+    /// (`gnu-divert-to-card`).
+    DivertToCard {
+        /// The smartcard's serial number.
+        serial: CardSerial,
+    },
+    /// Unknown extension.
+    Unknown(u8),
+}
+
+impl GNUExtension {
+    /// Returns the GnuPG mode of this extension.
+    pub(crate) fn mode(&self) -> u8 {
+        match self {
+            &GNUExtension::Dummy => 1,
+            &GNUExtension::DivertToCard { .. } => 2,
+            &GNUExtension::Unknown(u) => u,
+        }
+    }
+}
+
+impl fmt::Display for GNUExtension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &GNUExtension::Dummy => f.write_str("gnu-dummy"),
+            &GNUExtension::DivertToCard { ref serial } =>
+                write!(f, "gnu-divert-to-card (serial {})",
+                       ::conversions::to_hex(serial, false)),
+            &GNUExtension::Unknown(u) =>
+                write!(f, "unknown GnuPG extension {}", u),
+        }
+    }
+}
+
+/// The serial number of a smartcard.
+///
+/// GnuPG limits serial numbers to 16 bytes.  This is stored inline,
+/// so that `S2K` remains `Copy`.  Dereferences to the serial number.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CardSerial {
+    len: u8,
+    // Bytes beyond `len` are zero.
+    serial: [u8; 16],
+}
+
+impl CardSerial {
+    /// Returns a `CardSerial` for the given serial number.
+    ///
+    /// Returns an error if `serial` is longer than 16 bytes.
+    pub fn new(serial: &[u8]) -> Result<Self> {
+        if serial.len() > 16 {
+            return Err(Error::InvalidArgument(
+                format!("Card serial number too long: {} bytes",
+                        serial.len())).into());
+        }
+
+        let mut s = CardSerial { len: serial.len() as u8, serial: [0; 16] };
+        s.serial[..serial.len()].copy_from_slice(serial);
+        Ok(s)
+    }
+}
+
+impl ::std::ops::Deref for CardSerial {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.serial[..self.len as usize]
+    }
+}
+
+impl fmt::Debug for CardSerial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CardSerial")
+            .field(&::conversions::to_hex(self, false))
+            .finish()
+    }
+}
+
+/// The largest supported Argon2 memory exponent.
+///
+/// Deriving a key allocates `2^memory_exponent` KiB.  Since the
+/// parameters are read from, e.g., SKESK packets, we limit the memory
+/// to 4 GiB, which is twice the amount recommended by the crypto
+/// refresh.
+const ARGON2_MAX_MEMORY_EXPONENT: u8 = 22;
+
+/// The minimum number of bytes hashed by `S2K::default`.
+const DEFAULT_MIN_ITERATIONS: u32 = 26214400;
+
+lazy_static! {
+    /// The number of bytes hashed by `S2K::default`.
+    ///
+    /// This is calibrated once so that deriving a key takes about
+    /// 100ms, but we never go below `DEFAULT_MIN_ITERATIONS`.
+    static ref DEFAULT_ITERATIONS: u32 =
+        S2K::calibrate_iterations(HashAlgorithm::SHA256,
+                                  Duration::from_millis(100))
+        .map(|i| cmp::max(i, DEFAULT_MIN_ITERATIONS))
+        .unwrap_or(DEFAULT_MIN_ITERATIONS);
+}
+
 // XXX: Check defaults.
 impl Default for S2K {
     fn default() -> Self {
@@ -62,16 +210,152 @@ impl Default for S2K {
         S2K::Iterated {
             hash: HashAlgorithm::SHA256,
             salt: salt,
-            iterations: 26214400, // XXX: Calibrate somehow.
+            iterations: *DEFAULT_ITERATIONS,
         }
     }
 }
 
 impl S2K {
+    /// Returns an iterated and salted S2K with a random salt.
+    ///
+    /// The iteration count is calibrated so that deriving a key
+    /// takes about `target` on this machine.  The count is clamped
+    /// to the range of encodable iteration counts (see
+    /// `nearest_iteration_count`).
+    pub fn new_iterated(hash: HashAlgorithm, target: Duration)
+                        -> Result<Self> {
+        let mut salt = [0u8; 8];
+        Yarrow::default().random(&mut salt);
+        Ok(S2K::Iterated {
+            hash: hash,
+            salt: salt,
+            iterations: Self::calibrate_iterations(hash, target)?,
+        })
+    }
+
+    /// Returns an Argon2 S2K with a random salt.
+    ///
+    /// `passes` is the number of passes over the memory,
+    /// `parallelism` the number of lanes, and the memory used is
+    /// `2^memory_exponent` KiB.  The crypto refresh recommends
+    /// either 1 pass, 4 lanes, and 2 GiB of memory (i.e., a
+    /// `memory_exponent` of 21), or, if that much memory is not
+    /// available, 3 passes, 4 lanes, and 64 MiB of memory (a
+    /// `memory_exponent` of 16).
+    ///
+    /// Argon2 must not be used to protect secret keys, see
+    /// `S2K::Argon2`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `passes` or `parallelism`
+    /// is zero, if `memory_exponent` exceeds 22 (i.e., more than 4
+    /// GiB of memory), or if the memory is less than 8 KiB per lane.
+    pub fn new_argon2(passes: u8, parallelism: u8, memory_exponent: u8)
+                      -> Result<Self> {
+        Self::check_argon2_parameters(passes, parallelism, memory_exponent)?;
+
+        let mut salt = [0u8; 16];
+        Yarrow::default().random(&mut salt);
+        Ok(S2K::Argon2 {
+            salt: salt,
+            passes: passes,
+            parallelism: parallelism,
+            memory_exponent: memory_exponent,
+        })
+    }
+
+    fn check_argon2_parameters(passes: u8, parallelism: u8,
+                               memory_exponent: u8)
+                               -> Result<()> {
+        if passes == 0 {
+            return Err(Error::InvalidArgument(
+                "Argon2: number of passes must be at least 1".into()).into());
+        }
+        if parallelism == 0 {
+            return Err(Error::InvalidArgument(
+                "Argon2: parallelism must be at least 1".into()).into());
+        }
+        if memory_exponent > ARGON2_MAX_MEMORY_EXPONENT {
+            return Err(Error::InvalidArgument(
+                format!("Argon2: memory exponent {} too large",
+                        memory_exponent)).into());
+        }
+        if (1u64 << memory_exponent) < 8 * parallelism as u64 {
+            return Err(Error::InvalidArgument(
+                format!("Argon2: 2^{} KiB of memory is too little for \
+                         {} lanes", memory_exponent, parallelism)).into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of this S2K with a fresh random salt.
+    ///
+    /// S2K specifiers without a salt are returned as-is.
+    pub(crate) fn with_fresh_salt(&self) -> Self {
+        let mut s2k = *self;
+        match s2k {
+            S2K::Salted { ref mut salt, .. }
+            | S2K::Iterated { ref mut salt, .. } =>
+                Yarrow::default().random(salt),
+            S2K::Argon2 { ref mut salt, .. } =>
+                Yarrow::default().random(salt),
+            _ => (),
+        }
+        s2k
+    }
+
+    /// Returns the number of bytes to hash so that deriving a key
+    /// using the iterated and salted S2K takes about `target`.
+    fn calibrate_iterations(hash: HashAlgorithm, target: Duration)
+                            -> Result<u32> {
+        // Measure how long it takes to hash a sample.
+        const SAMPLE_SIZE: usize = 1 << 20;
+        let chunk = vec![0u8; 64 * 1024];
+        let mut ctx = hash.context()?;
+        let mut digest = vec![0u8; ctx.digest_size()];
+
+        let start = Instant::now();
+        for _ in 0..SAMPLE_SIZE / chunk.len() {
+            ctx.update(&chunk);
+        }
+        ctx.digest(&mut digest);
+        let elapsed = start.elapsed();
+
+        let nanos = |d: Duration|
+            d.as_secs().saturating_mul(1_000_000_000)
+            .saturating_add(d.subsec_nanos() as u64);
+        let bytes = (SAMPLE_SIZE as u64)
+            .saturating_mul(nanos(target)) / cmp::max(nanos(elapsed), 1);
+
+        Ok(S2K::nearest_iteration_count(
+            cmp::min(bytes, usize::max_value() as u64) as usize))
+    }
+
     /// Convert the string to a key using the S2K's paramters.
     pub fn derive_key(&self, string: &Password, key_size: usize)
     -> Result<SessionKey> {
         match self {
+            &S2K::Argon2 { ref salt, passes, parallelism, memory_exponent } => {
+                Self::check_argon2_parameters(passes, parallelism,
+                                              memory_exponent)?;
+
+                let config = argon2::Config {
+                    variant: argon2::Variant::Argon2id,
+                    version: argon2::Version::Version13,
+                    mem_cost: 1 << memory_exponent,
+                    time_cost: passes as u32,
+                    lanes: parallelism as u32,
+                    thread_mode: argon2::ThreadMode::Parallel,
+                    secret: &[],
+                    ad: &[],
+                    hash_length: key_size as u32,
+                };
+
+                let key = argon2::hash_raw(string, salt, &config)?;
+                Ok(key.into())
+            },
             &S2K::Simple { hash } | &S2K::Salted { hash, .. }
             | &S2K::Iterated { hash, .. } => {
                 let mut hash = hash.context()?;
@@ -116,7 +400,9 @@ impl S2K {
                                 hash.update(&data[0..tail]);
                             }
                         }
-                        &S2K::Unknown(_) | &S2K::Private(_) => unreachable!(),
+                        &S2K::Argon2 { .. } | &S2K::GNU { .. }
+                            | &S2K::Unknown(_) | &S2K::Private(_) =>
+                            unreachable!(),
                     }
 
                     hash.digest(data);
@@ -125,12 +411,28 @@ impl S2K {
 
                 Ok(ret.into())
             }
+            &S2K::GNU { ref extension, .. } =>
+                Err(Error::InvalidOperation(
+                    format!("Cannot derive a key from a {} stub",
+                            extension)).into()),
             &S2K::Unknown(u) | &S2K::Private(u) =>
                 Err(Error::MalformedPacket(
                         format!("Unknown S2K type {:#x}", u)).into()),
         }
     }
 
+    /// Returns whether this is one of GnuPG's S2K extensions.
+    ///
+    /// Secret keys using these extensions are stubs that do not
+    /// carry any secret key material.
+    pub fn is_gnu_extension(&self) -> bool {
+        if let &S2K::GNU { .. } = self {
+            true
+        } else {
+            false
+        }
+    }
+
     /// Not all iteration counts are encodable as Iterated and Salted S2K. This function returns
     /// an encodabled iteration count larger or equal `iters`.
     /// # Note
@@ -228,6 +530,15 @@ impl fmt::Display for S2K {
                     salt[4], salt[5], salt[6], salt[7],
                     iterations))
             }
+            S2K::Argon2 { ref salt, passes, parallelism, memory_exponent } =>
+                f.write_fmt(
+                    format_args!("Argon2 S2K with salt {}, {} passes, \
+                                  {} lanes and 2^{} KiB of memory",
+                                 ::conversions::to_hex(salt, false),
+                                 passes, parallelism, memory_exponent)),
+            S2K::GNU { ref extension, .. } =>
+                f.write_fmt(format_args!("GnuPG S2K extension {}",
+                                         extension)),
             S2K::Private(u) =>
                 f.write_fmt(format_args!("Private/Experimental S2K {}", u)),
             S2K::Unknown(u) => f.write_fmt(format_args!("Unknown S2K {}", u)),
@@ -237,7 +548,7 @@ impl fmt::Display for S2K {
 
 impl Arbitrary for S2K {
     fn arbitrary<G: Gen>(g: &mut G) -> Self {
-        match g.gen_range(0, 5) {
+        match g.gen_range(0, 7) {
             0 => S2K::Simple{ hash: HashAlgorithm::arbitrary(g) },
             1 => S2K::Salted{
                 hash: HashAlgorithm::arbitrary(g),
@@ -249,7 +560,29 @@ impl Arbitrary for S2K {
                 iterations: S2K::nearest_iteration_count(g.gen()),
             },
             3 => S2K::Private(g.gen_range(100, 111)),
-            4 => S2K::Unknown(g.gen_range(4, 100)),
+            4 => S2K::Unknown(g.gen_range(5, 100)),
+            5 => S2K::GNU {
+                hash: HashAlgorithm::arbitrary(g),
+                extension: match g.gen_range(0, 3) {
+                    0 => GNUExtension::Dummy,
+                    1 => {
+                        let len = g.gen_range(0, 17);
+                        GNUExtension::DivertToCard {
+                            serial: CardSerial::new(
+                                &(0..len).map(|_| g.gen())
+                                    .collect::<Vec<u8>>()).unwrap(),
+                        }
+                    },
+                    2 => GNUExtension::Unknown(g.gen_range(3, 255)),
+                    _ => unreachable!(),
+                },
+            },
+            6 => S2K::Argon2 {
+                salt: g.gen(),
+                passes: g.gen(),
+                parallelism: g.gen(),
+                memory_exponent: g.gen(),
+            },
             _ => unreachable!(),
         }
     }
@@ -394,6 +727,181 @@ mod tests {
         }
     }
 
+    #[test]
+    fn argon2_derive_key() {
+        // Computed using an independent implementation of Argon2id.
+        struct Test<'a> {
+            passes: u8,
+            parallelism: u8,
+            memory_exponent: u8,
+            key_hex: &'a str,
+        };
+
+        let tests = [
+            Test {
+                passes: 1, parallelism: 1, memory_exponent: 3,
+                key_hex: "6DEB08EB7CAAFE366F2F9EC06D8DBEBD",
+            },
+            Test {
+                passes: 3, parallelism: 4, memory_exponent: 6,
+                key_hex: "6197896F5EF12176AC61DE8960638A04\
+                          6D0BEFB06E462949D0BDE218509E0FBC",
+            },
+            Test {
+                passes: 2, parallelism: 2, memory_exponent: 10,
+                key_hex: "A59B2C9DCC324F26F5013AC2A480C1A0\
+                          402AEB6F09D6C7AB",
+            },
+        ];
+
+        let mut salt = [0u8; 16];
+        for (i, b) in salt.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        for test in tests.iter() {
+            let s2k = S2K::Argon2 {
+                salt: salt,
+                passes: test.passes,
+                parallelism: test.parallelism,
+                memory_exponent: test.memory_exponent,
+            };
+            let key = s2k.derive_key(&"password".into(),
+                                     test.key_hex.len() / 2).unwrap();
+            assert_eq!(to_hex(&key[..], false), test.key_hex);
+        }
+    }
+
+    #[test]
+    fn argon2_rfc9106() {
+        // The parameters of the Argon2id test vector in Section 5.3
+        // of RFC 9106, without the secret value and associated data,
+        // which OpenPGP doesn't use.  Computed using an independent
+        // implementation of Argon2id.
+        let s2k = S2K::Argon2 {
+            salt: [0x02; 16],
+            passes: 3,
+            parallelism: 4,
+            memory_exponent: 5,
+        };
+        let key = s2k.derive_key(&vec![0x01; 32].into(), 32).unwrap();
+        assert_eq!(to_hex(&key[..], false),
+                   "03AAB965C12001C9D7D0D2DE33192C04\
+                    94B684BB148196D73C1DF1ACAF6D0C2E");
+    }
+
+    #[test]
+    fn argon2_skesk() {
+        use packet::SKESK;
+
+        // A v4 SKESK using Argon2 with AES-128, created using an
+        // independent implementation.
+        let mut pp = PacketParser::from_bytes(::tests::message(
+            "s2k/mode-4-argon2-small-aes128-password-password.gpg"))
+            .unwrap().unwrap();
+        if let Packet::SKESK(SKESK::V4(ref skesk)) = pp.packet {
+            assert_eq!(skesk.s2k(), &S2K::Argon2 {
+                salt: [0x25, 0xaf, 0x50, 0x5e, 0x71, 0xf5, 0x4c, 0x96,
+                       0x24, 0x31, 0x0a, 0xa5, 0x32, 0x78, 0x68, 0xb2],
+                passes: 3,
+                parallelism: 4,
+                memory_exponent: 5,
+            });
+
+            let (algo, sk) = skesk.decrypt(&"password".into()).unwrap();
+            assert_eq!(algo, SymmetricAlgorithm::AES128);
+            assert_eq!(to_hex(&sk[..], false),
+                       "9E12E3ADF7C6F98846CFE4EDC64FE384");
+        } else {
+            panic!("Wrong packet!");
+        }
+
+        let (_, ppr) = pp.next().unwrap();
+        assert!(ppr.is_none());
+    }
+
+    #[test]
+    fn argon2_hostile_skesk() {
+        use packet::SKESK;
+
+        // A v4 SKESK using Argon2 with 2^31 KiB of memory.  Parsing
+        // it works, but we refuse to derive the key before allocating
+        // anything.
+        let mut raw = vec![0xc3, 22, 4, 7, 4];
+        raw.extend_from_slice(&[0x42; 16][..]);
+        raw.extend_from_slice(&[1, 1, 31]);
+        let pp = PacketParser::from_bytes(&raw).unwrap().unwrap();
+        if let Packet::SKESK(SKESK::V4(ref skesk)) = pp.packet {
+            assert_match!(&S2K::Argon2 { memory_exponent: 31, .. }
+                          = skesk.s2k());
+            assert!(skesk.decrypt(&"password".into()).is_err());
+        } else {
+            panic!("Wrong packet!");
+        }
+    }
+
+    #[test]
+    fn argon2_parameters() {
+        assert!(S2K::new_argon2(3, 4, 16).is_ok());
+        assert!(S2K::new_argon2(0, 4, 16).is_err());
+        assert!(S2K::new_argon2(3, 0, 16).is_err());
+        assert!(S2K::new_argon2(3, 4, 32).is_err());
+        assert!(S2K::new_argon2(1, 4, 22).is_ok());
+        assert!(S2K::new_argon2(1, 4, 23).is_err());
+        // Argon2 needs at least 8 KiB per lane.
+        assert!(S2K::new_argon2(1, 1, 3).is_ok());
+        assert!(S2K::new_argon2(1, 2, 3).is_err());
+
+        // Different S2Ks use different salts.
+        assert!(S2K::new_argon2(3, 4, 16).unwrap()
+                != S2K::new_argon2(3, 4, 16).unwrap());
+    }
+
+    #[test]
+    fn card_serial() {
+        let serial = CardSerial::new(&[1, 2, 3]).unwrap();
+        assert_eq!(&serial[..], &[1, 2, 3][..]);
+        assert!(CardSerial::new(&[0; 16][..]).is_ok());
+        assert!(CardSerial::new(&[0; 17][..]).is_err());
+
+        // The parser rejects serial numbers that are too long.
+        let mut raw = vec![101, 0];
+        raw.extend_from_slice(b"GNU");
+        raw.extend_from_slice(&[2, 17]);
+        raw.extend_from_slice(&[0; 17][..]);
+        assert!(S2K::from_bytes(&raw).is_err());
+        raw[6] = 16;
+        raw.pop();
+        assert_eq!(S2K::from_bytes(&raw).unwrap(), S2K::GNU {
+            hash: HashAlgorithm::from(0),
+            extension: GNUExtension::DivertToCard {
+                serial: CardSerial::new(&[0; 16][..]).unwrap(),
+            },
+        });
+    }
+
+    #[test]
+    fn iterated_calibration() {
+        let fast = S2K::new_iterated(HashAlgorithm::SHA256,
+                                     Duration::from_millis(0)).unwrap();
+        let slow = S2K::new_iterated(HashAlgorithm::SHA256,
+                                     Duration::from_secs(3600)).unwrap();
+        match (fast, slow) {
+            (S2K::Iterated { iterations: fast, .. },
+             S2K::Iterated { iterations: slow, .. }) => {
+                assert_eq!(fast, 1024);
+                assert_eq!(slow, 0x3e00000);
+            },
+            _ => panic!("Expected iterated S2Ks"),
+        }
+
+        if let S2K::Iterated { iterations, .. } = S2K::default() {
+            assert!(iterations >= DEFAULT_MIN_ITERATIONS);
+        } else {
+            panic!("Expected an iterated S2K");
+        }
+    }
+
     quickcheck! {
         fn s2k_roundtrip(s2k: S2K) -> bool {
             use serialize::SerializeInto;
@@ -426,7 +934,7 @@ mod tests {
     quickcheck! {
         fn s2k_parse(s2k: S2K) -> bool {
             match s2k {
-                S2K::Unknown(u) => (u > 3 && u < 100) || u == 2 || u > 110,
+                S2K::Unknown(u) => (u > 4 && u < 100) || u == 2 || u > 110,
                 S2K::Private(u) => u >= 100 && u <= 110,
                 _ => true
             }
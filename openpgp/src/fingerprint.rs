@@ -25,6 +25,10 @@ impl Fingerprint {
             let mut fp : [u8; 20] = Default::default();
             fp.copy_from_slice(raw);
             Fingerprint::V4(fp)
+        } else if raw.len() == 32 {
+            let mut fp : [u8; 32] = Default::default();
+            fp.copy_from_slice(raw);
+            Fingerprint::V5(fp)
         } else {
             Fingerprint::Invalid(raw.to_vec().into_boxed_slice())
         }
@@ -52,6 +56,7 @@ impl Fingerprint {
     pub fn as_slice(&self) -> &[u8] {
         match self {
             &Fingerprint::V4(ref fp) => fp,
+            &Fingerprint::V5(ref fp) => fp,
             &Fingerprint::Invalid(ref fp) => fp,
         }
     }
@@ -72,15 +77,18 @@ impl Fingerprint {
     fn convert_to_string(&self, pretty: bool) -> String {
         let raw = match self {
             &Fingerprint::V4(ref fp) => &fp[..],
+            &Fingerprint::V5(ref fp) => &fp[..],
             &Fingerprint::Invalid(ref fp) => &fp[..],
         };
 
-        // We currently only handle V4 fingerprints, which look like:
+        // V4 fingerprints look like:
         //
         //   8F17 7771 18A3 3DDA 9BA4  8E62 AACB 3243 6300 52D9
         //
-        // Since we have no idea how to format an invalid fingerprint,
-        // just format it like a V4 fingerprint and hope for the best.
+        // There is no established format for V5 fingerprints, so we
+        // group them the same way.  Since we have no idea how to
+        // format an invalid fingerprint, we do the same and hope for
+        // the best.
 
         let mut output = Vec::with_capacity(
             // Each byte results in to hex characters.
@@ -121,11 +129,28 @@ impl Fingerprint {
         String::from_utf8(output).unwrap()
     }
 
+    /// Returns the version of the key this fingerprint belongs to.
+    ///
+    /// Returns `None` for invalid fingerprints.
+    pub fn version(&self) -> Option<u8> {
+        match self {
+            &Fingerprint::V4(_) => Some(4),
+            &Fingerprint::V5(_) => Some(5),
+            &Fingerprint::Invalid(_) => None,
+        }
+    }
+
     /// Converts the fingerprint to a key ID.
+    ///
+    /// The key ID of a version 4 key consists of the low-order 64
+    /// bits of its fingerprint, whereas the key ID of a version 5 key
+    /// consists of the high-order 64 bits.
     pub fn to_keyid(&self) -> KeyID {
         match self {
             &Fingerprint::V4(ref fp) =>
                 KeyID::from_bytes(&fp[fp.len() - 8..]),
+            &Fingerprint::V5(ref fp) =>
+                KeyID::from_bytes(&fp[..8]),
             &Fingerprint::Invalid(ref fp) => {
                 if fp.len() < 8 {
                     KeyID::from_bytes(&[0; 8])
@@ -187,4 +212,13 @@ Charlie Delta Echo Foxtrot Zero One Two Three Four Five Six Seven";
 
         assert_eq!(fpr.to_icao(), expected);
     }
+
+    #[test]
+    fn v5() {
+        let hex = "19347BC9872464025F99DF3EC2E0000ED9884892E1F7B3EA4C94009159569B54";
+        let fpr = Fingerprint::from_hex(hex).unwrap();
+        assert_eq!(fpr.version(), Some(5));
+        assert_eq!(fpr.to_hex(), hex);
+        assert_eq!(fpr.to_keyid(), KeyID::from_hex("19347BC987246402").unwrap());
+    }
 }
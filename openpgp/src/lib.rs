@@ -47,6 +47,7 @@ extern crate failure;
 
 extern crate buffered_reader;
 
+extern crate argon2;
 extern crate memsec;
 extern crate nettle;
 
@@ -57,6 +58,11 @@ extern crate bzip2;
 
 extern crate subprocess;
 
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio_io;
+
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
@@ -64,6 +70,9 @@ extern crate quickcheck;
 #[cfg(not(test))]
 extern crate quickcheck;
 
+#[cfg(test)]
+extern crate tempfile;
+
 extern crate rand;
 
 extern crate time;
@@ -73,6 +82,8 @@ extern crate sequoia_rfc2822 as rfc2822;
 #[macro_use] extern crate lazy_static;
 
 extern crate idna;
+
+extern crate num_cpus;
 
 #[macro_use]
 mod macros;
@@ -107,6 +118,7 @@ macro_rules! assert_match {
 #[macro_use]
 pub mod armor;
 pub mod autocrypt;
+pub mod pgp_mime;
 pub mod conversions;
 pub mod crypto;
 
@@ -235,6 +247,13 @@ pub enum Error {
     /// Index out of range.
     #[fail(display = "Index out of range")]
     IndexOutOfRange,
+
+    /// A resource limit was exceeded.
+    ///
+    /// See `PacketParserBuilder` for the limits that can be
+    /// configured.
+    #[fail(display = "Resource limit exceeded: {}", _0)]
+    ResourceLimitExceeded(String),
 }
 
 /// The OpenPGP packets that Sequoia understands.
@@ -285,6 +304,11 @@ pub enum Packet {
     PKESK(packet::PKESK),
     /// Symmetric key encrypted data packet.
     SKESK(packet::SKESK),
+    /// Symmetrically encrypted data packet.
+    ///
+    /// This packet is only produced by the parser if explicitly
+    /// requested, because it is not integrity protected.
+    SED(packet::SED),
     /// Symmetric key encrypted, integrity protected data packet.
     SEIP(packet::SEIP),
     /// Modification detection code packet.
@@ -317,6 +341,7 @@ impl Packet {
             &Packet::CompressedData(_) => Tag::CompressedData,
             &Packet::PKESK(_) => Tag::PKESK,
             &Packet::SKESK(_) => Tag::SKESK,
+            &Packet::SED(_) => Tag::SED,
             &Packet::SEIP(_) => Tag::SEIP,
             &Packet::MDC(_) => Tag::MDC,
             &Packet::AED(_) => Tag::AED,
@@ -348,6 +373,7 @@ impl Packet {
             &Packet::CompressedData(_) => Some(Tag::CompressedData),
             &Packet::PKESK(_) => Some(Tag::PKESK),
             &Packet::SKESK(_) => Some(Tag::SKESK),
+            &Packet::SED(_) => Some(Tag::SED),
             &Packet::SEIP(_) => Some(Tag::SEIP),
             &Packet::MDC(_) => Some(Tag::MDC),
             &Packet::AED(_) => Some(Tag::AED),
@@ -467,13 +493,16 @@ pub struct Message {
 ///
 /// A fingerprint uniquely identifies a public key.  For more details
 /// about how a fingerprint is generated, see [Section 12.2 of RFC
-/// 4880].
+/// 4880], and [Section 12.2 of RFC 4880bis] for version 5 keys.
 ///
 ///   [Section 12.2 of RFC 4880]: https://tools.ietf.org/html/rfc4880#section-12.2
+///   [Section 12.2 of RFC 4880bis]: https://tools.ietf.org/html/draft-ietf-openpgp-rfc4880bis-07#section-12.2
 #[derive(PartialEq, Eq, Clone, Hash)]
 pub enum Fingerprint {
     /// 20 byte SHA-1 hash.
     V4([u8;20]),
+    /// 32 byte SHA-256 hash.
+    V5([u8;32]),
     /// Used for holding fingerprints that we don't understand.  For
     /// instance, we don't grok v3 fingerprints.  And, it is possible
     /// that the Issuer subpacket contains the wrong number of bytes.
@@ -490,6 +519,9 @@ pub enum Fingerprint {
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum KeyID {
     /// Lower 8 byte SHA-1 hash.
+    ///
+    /// This variant is also used for the key IDs of version 5 keys,
+    /// which are the upper 8 bytes of the SHA-256 fingerprint.
     V4([u8;8]),
     /// Used for holding fingerprints that we don't understand.  For
     /// instance, we don't grok v3 fingerprints.  And, it is possible
@@ -19,6 +19,8 @@ pub enum Token {
     SKESK,
     /// An PK-ESK packet.
     PKESK,
+    /// A SED packet.
+    SED,
     /// A SEIP packet.
     SEIP,
     /// An MDC packet.
@@ -31,8 +33,8 @@ pub enum Token {
     /// A Signature packet.
     SIG,
 
-    /// The end of a container (either a Compressed Data packet or a
-    /// SEIP packet).
+    /// The end of a container (either a Compressed Data packet or an
+    /// encryption container).
     Pop,
 
     /// A container's unparsed content.
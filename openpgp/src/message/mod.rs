@@ -221,6 +221,7 @@ impl MessageValidator {
             Tag::CompressedData => Token::CompressedData,
             Tag::SKESK => Token::SKESK,
             Tag::PKESK => Token::PKESK,
+            Tag::SED => Token::SED,
             Tag::SEIP => Token::SEIP,
             Tag::MDC => Token::MDC,
             Tag::AED => Token::AED,
@@ -366,7 +367,8 @@ impl Message {
             }
 
             match packet {
-                Packet::CompressedData(_) | Packet::SEIP(_) | Packet::AED(_) =>
+                Packet::CompressedData(_) | Packet::SED(_) | Packet::SEIP(_)
+                    | Packet::AED(_) =>
                 {
                     // If a container's content is not unpacked, then
                     // we treat the content as an opaque message.
@@ -532,6 +534,23 @@ mod tests {
                 result: true,
             },
 
+            TestVector {
+                s: &[SED, Literal, Pop],
+                result: true,
+            },
+            TestVector {
+                s: &[SKESK, SED, CompressedData, Literal, Pop, Pop],
+                result: true,
+            },
+            TestVector {
+                s: &[SED, Literal, MDC, Pop],
+                result: false,
+            },
+            TestVector {
+                s: &[SKESK, SED, OpaqueContent, Pop],
+                result: true,
+            },
+
             TestVector {
                 s: &[OPS, Literal, SIG],
                 result: true,
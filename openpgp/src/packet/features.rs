@@ -3,6 +3,7 @@
 pub struct Features{
     mdc: bool,
     aead: bool,
+    v5: bool,
     unknown: Box<[u8]>,
 }
 
@@ -11,6 +12,7 @@ impl Default for Features {
         Features{
             mdc: false,
             aead: false,
+            v5: false,
             unknown: Default::default(),
         }
     }
@@ -23,19 +25,21 @@ impl Features {
             .map(|x| x & FEATURE_FLAG_MDC != 0).unwrap_or(false);
         let aead = bits.get(0)
             .map(|x| x & FEATURE_FLAG_AEAD != 0).unwrap_or(false);
+        let v5 = bits.get(0)
+            .map(|x| x & FEATURE_FLAG_V5 != 0).unwrap_or(false);
         let unk = if bits.is_empty() {
             Box::default()
         } else {
             let mut cpy = Vec::from(bits);
 
-            cpy[0] &= (FEATURE_FLAG_MDC | FEATURE_FLAG_AEAD) ^ 0xff;
+            cpy[0] &= (FEATURE_FLAG_MDC | FEATURE_FLAG_AEAD | FEATURE_FLAG_V5) ^ 0xff;
 
             while cpy.last().cloned() == Some(0) { cpy.pop(); }
             cpy.into_boxed_slice()
         };
 
         Features{
-            mdc: mdc, aead: aead, unknown: unk
+            mdc: mdc, aead: aead, v5: v5, unknown: unk
         }
     }
 
@@ -44,6 +48,7 @@ impl Features {
         Features{
             mdc: true,
             aead: true,
+            v5: true,
             unknown: Default::default(),
         }
     }
@@ -58,6 +63,7 @@ impl Features {
 
         if self.mdc { ret[0] |= FEATURE_FLAG_MDC; }
         if self.aead { ret[0] |= FEATURE_FLAG_AEAD; }
+        if self.v5 { ret[0] |= FEATURE_FLAG_V5; }
 
         ret
     }
@@ -83,6 +89,17 @@ impl Features {
         self.aead = v;
         self
     }
+
+    /// Whether or not version 5 keys are supported.
+    pub fn supports_v5(&self) -> bool {
+        self.v5
+    }
+
+    /// Sets whether or not version 5 keys are supported.
+    pub fn set_v5(mut self, v: bool) -> Self {
+        self.v5 = v;
+        self
+    }
 }
 
 /// Modification Detection (packets 18 and 19).
@@ -91,3 +108,7 @@ const FEATURE_FLAG_MDC: u8 = 0x01;
 /// AEAD Encrypted Data Packet (packet 20) and version 5 Symmetric-Key
 /// Encrypted Session Key Packets (packet 3).
 const FEATURE_FLAG_AEAD: u8 = 0x02;
+
+/// Version 5 Public-Key Packet format and corresponding new
+/// fingerprint format.
+const FEATURE_FLAG_V5: u8 = 0x04;
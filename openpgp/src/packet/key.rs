@@ -6,6 +6,7 @@ use std::cmp::Ordering;
 use time;
 
 use nettle::Hash as NettleHash;
+use nettle::hash::Sha256;
 use nettle::hash::insecure_do_not_use::Sha1;
 
 use Error;
@@ -17,7 +18,7 @@ use PublicKeyAlgorithm;
 use SymmetricAlgorithm;
 use HashAlgorithm;
 use constants::Curve;
-use crypto::s2k::S2K;
+use crypto::s2k::{S2K, GNUExtension};
 use Result;
 use conversions::Time;
 use crypto::Password;
@@ -559,9 +560,17 @@ impl Key4 {
     ///
     /// Fails if the secret key is missing, or encrypted.
     pub fn into_keypair(mut self) -> Result<KeyPair> {
-        use packet::key::SecretKey;
+        let secret = self.take_unencrypted_secret()?;
+        KeyPair::new(self.into(), secret)
+    }
+
+    /// Removes and returns the unencrypted secret key material.
+    fn take_unencrypted_secret(&mut self) -> Result<mpis::SecretKey> {
         let secret = match self.set_secret(None) {
             Some(SecretKey::Unencrypted { mpis }) => mpis,
+            Some(ref s) if s.is_stub() =>
+                return Err(Error::InvalidArgument(
+                    "secret key is a stub".into()).into()),
             Some(SecretKey::Encrypted { .. }) =>
                 return Err(Error::InvalidArgument(
                     "secret key is encrypted".into()).into()),
@@ -570,7 +579,7 @@ impl Key4 {
                     "no secret key".into()).into()),
         };
 
-        KeyPair::new(self.into(), secret)
+        Ok(secret)
     }
 }
 
@@ -580,6 +589,178 @@ impl From<Key4> for super::Key {
     }
 }
 
+/// Holds a version 5 public key, public subkey, private key or
+/// private subkey packet.
+///
+/// Version 5 keys carry the same information as version 4 keys, but
+/// their fingerprint is computed using SHA-256, and the key material
+/// is prefixed with its length.  See [Section 5.5 of RFC 4880bis] for
+/// details.
+///
+///   [Section 5.5 of RFC 4880bis]: https://tools.ietf.org/html/draft-ietf-openpgp-rfc4880bis-07#section-5.5
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct Key5 {
+    /// Common fields.
+    pub(crate) key4: Key4,
+}
+
+impl fmt::Debug for Key5 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Key5")
+            .field("fingerprint", &self.fingerprint())
+            .field("creation_time",
+                   &format!("{}", self.creation_time().rfc3339()))
+            .field("pk_algo", &self.pk_algo())
+            .field("mpis", self.mpis())
+            .field("secret", &self.secret())
+            .finish()
+    }
+}
+
+impl fmt::Display for Key5 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fingerprint())
+    }
+}
+
+impl From<Key4> for Key5 {
+    fn from(key4: Key4) -> Self {
+        Key5 { key4: key4 }
+    }
+}
+
+impl Key5 {
+    /// Creates a new OpenPGP version 5 key packet.
+    pub fn new(creation_time: time::Tm, pk_algo: PublicKeyAlgorithm,
+               mpis: mpis::PublicKey, secret: Option<SecretKey>)
+               -> Result<Self>
+    {
+        Ok(Key4::new(creation_time, pk_algo, mpis, secret)?.into())
+    }
+
+    /// Generates a new RSA key with a public modulos of size `bits`.
+    ///
+    /// See [`Key4::generate_rsa`] for details.
+    ///
+    /// [`Key4::generate_rsa`]: struct.Key4.html#method.generate_rsa
+    pub fn generate_rsa(bits: usize) -> Result<Self> {
+        Ok(Key4::generate_rsa(bits)?.into())
+    }
+
+    /// Generates a new ECC key over `curve`.
+    ///
+    /// See [`Key4::generate_ecc`] for details.
+    ///
+    /// [`Key4::generate_ecc`]: struct.Key4.html#method.generate_ecc
+    pub fn generate_ecc(for_signing: bool, curve: Curve) -> Result<Self> {
+        Ok(Key4::generate_ecc(for_signing, curve)?.into())
+    }
+
+    /// Gets the key packet's creation time field.
+    pub fn creation_time(&self) -> &time::Tm {
+        self.key4.creation_time()
+    }
+
+    /// Sets the key packet's creation time field.
+    pub fn set_creation_time(&mut self, timestamp: time::Tm) -> time::Tm {
+        self.key4.set_creation_time(timestamp)
+    }
+
+    /// Gets the public key algorithm.
+    pub fn pk_algo(&self) -> PublicKeyAlgorithm {
+        self.key4.pk_algo()
+    }
+
+    /// Sets the public key algorithm.
+    pub fn set_pk_algo(&mut self, pk_algo: PublicKeyAlgorithm) -> PublicKeyAlgorithm {
+        self.key4.set_pk_algo(pk_algo)
+    }
+
+    /// Gets the key packet's MPIs.
+    pub fn mpis(&self) -> &mpis::PublicKey {
+        self.key4.mpis()
+    }
+
+    /// Gets a mutable reference to the key packet's MPIs.
+    pub fn mpis_mut(&mut self) -> &mut mpis::PublicKey {
+        self.key4.mpis_mut()
+    }
+
+    /// Sets the key packet's MPIs.
+    pub fn set_mpis(&mut self, mpis: mpis::PublicKey) -> mpis::PublicKey {
+        self.key4.set_mpis(mpis)
+    }
+
+    /// Gets the key packet's SecretKey.
+    pub fn secret(&self) -> Option<&SecretKey> {
+        self.key4.secret()
+    }
+
+    /// Gets a mutable reference to the key packet's SecretKey.
+    pub fn secret_mut(&mut self) -> Option<&mut SecretKey> {
+        self.key4.secret_mut()
+    }
+
+    /// Sets the key packet's SecretKey.
+    ///
+    /// Returns the old value.
+    pub fn set_secret(&mut self, secret: Option<SecretKey>)
+        -> Option<SecretKey>
+    {
+        self.key4.set_secret(secret)
+    }
+
+    /// Computes and returns the key's fingerprint as per Section
+    /// 12.2 of RFC 4880bis.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let mut h = Sha256::default();
+
+        self.hash(&mut h);
+
+        let mut digest = vec![0u8; h.digest_size()];
+        h.digest(&mut digest);
+        Fingerprint::from_bytes(digest.as_slice())
+    }
+
+    /// Computes and returns the key's key ID as per Section 12.2 of
+    /// RFC 4880bis.
+    pub fn keyid(&self) -> KeyID {
+        self.fingerprint().to_keyid()
+    }
+
+    /// Convert the `Key` struct to a `Packet`.
+    pub fn into_packet(self, tag: Tag) -> Result<Packet> {
+        match tag {
+            Tag::PublicKey => Ok(Packet::PublicKey(self.into())),
+            Tag::PublicSubkey => Ok(Packet::PublicSubkey(self.into())),
+            Tag::SecretKey => Ok(Packet::SecretKey(self.into())),
+            Tag::SecretSubkey => Ok(Packet::SecretSubkey(self.into())),
+            _ => Err(Error::InvalidArgument(
+                format!("Expected Tag::PublicKey, Tag::PublicSubkey, \
+                         Tag::SecretKey, or Tag::SecretSubkey. \
+                         Got: Tag::{:?}",
+                        tag)).into()),
+        }
+    }
+
+    /// Creates a new key pair from a Key packet with an unencrypted
+    /// secret key.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the secret key is missing, or encrypted.
+    pub fn into_keypair(mut self) -> Result<KeyPair> {
+        let secret = self.key4.take_unencrypted_secret()?;
+        KeyPair::new(self.into(), secret)
+    }
+}
+
+impl From<Key5> for super::Key {
+    fn from(p: Key5) -> Self {
+        super::Key::V5(p)
+    }
+}
+
 /// Holds the secret potion of a OpenPGP secret key or secret subkey packet.
 ///
 /// This type allows postponing the decryption of the secret key until we need to use it.
@@ -644,18 +825,40 @@ impl SecretKey {
     }
 
     /// Encrypts this secret key using `password`.
+    ///
+    /// The key used to encrypt the secret key is derived from
+    /// `password` using `S2K::default()`.
     pub fn encrypt(&self, password: &Password)
                    -> Result<(S2K, SymmetricAlgorithm, Box<[u8]>)> {
+        self.encrypt_with_s2k(password, S2K::default())
+    }
+
+    /// Encrypts this secret key using `password` and `s2k`.
+    ///
+    /// This can be used to tune the key derivation, e.g. using
+    /// `S2K::new_iterated(HashAlgorithm::SHA256, target)?`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `s2k` is an Argon2 S2K.
+    /// Argon2 must only be used with AEAD, but secret keys are
+    /// encrypted using CFB.
+    pub fn encrypt_with_s2k(&self, password: &Password, s2k: S2K)
+                            -> Result<(S2K, SymmetricAlgorithm, Box<[u8]>)> {
         use std::io::Write;
         use crypto::symmetric::Encryptor;
         use nettle::{Random, Yarrow};
 
+        if let S2K::Argon2 { .. } = s2k {
+            return Err(Error::InvalidArgument(
+                "Argon2 must only be used with AEAD".into()).into());
+        }
+
         match self {
             &SecretKey::Encrypted { .. } =>
                 Err(Error::InvalidOperation("Key is already encrypted".into())
                     .into()),
             &SecretKey::Unencrypted { ref mpis } => {
-                let s2k = S2K::default();
                 let cipher = SymmetricAlgorithm::AES256;
                 let key = s2k.derive_key(password, cipher.key_size()?)?;
 
@@ -677,7 +880,13 @@ impl SecretKey {
 
     /// Encrypts this secret key using `password`.
     pub fn encrypt_in_place(&mut self, password: &Password) -> Result<()> {
-        let (s2k, cipher, esk) = self.encrypt(password)?;
+        self.encrypt_in_place_with_s2k(password, S2K::default())
+    }
+
+    /// Encrypts this secret key using `password` and `s2k`.
+    pub fn encrypt_in_place_with_s2k(&mut self, password: &Password, s2k: S2K)
+                                     -> Result<()> {
+        let (s2k, cipher, esk) = self.encrypt_with_s2k(password, s2k)?;
         *self = SecretKey::Encrypted {
             s2k: s2k,
             algorithm: cipher,
@@ -688,12 +897,62 @@ impl SecretKey {
     }
 
     /// Returns true if this secret key is encrypted.
+    ///
+    /// Note: GnuPG's stubs (see [`is_stub`]) are also considered to
+    /// be encrypted.
+    ///
+    ///   [`is_stub`]: #method.is_stub
     pub fn is_encrypted(&self) -> bool {
         match self {
             &SecretKey::Encrypted { .. } => true,
             &SecretKey::Unencrypted { .. } => false,
         }
     }
+
+    /// Returns true if this is one of GnuPG's stubs.
+    ///
+    /// Stubs do not carry any secret key material.  Either the
+    /// secret key material is missing altogether (see
+    /// [`is_gnu_dummy`]), or it is stored on a smartcard (see
+    /// [`card_serial`]).
+    ///
+    ///   [`is_gnu_dummy`]: #method.is_gnu_dummy
+    ///   [`card_serial`]: #method.card_serial
+    pub fn is_stub(&self) -> bool {
+        match self {
+            &SecretKey::Encrypted { ref s2k, .. } => s2k.is_gnu_extension(),
+            &SecretKey::Unencrypted { .. } => false,
+        }
+    }
+
+    /// Returns true if the secret key material is not available.
+    ///
+    /// GnuPG creates these stubs, for instance, when exporting
+    /// secret subkeys without the primary key using `gpg
+    /// --export-secret-subkeys`.
+    pub fn is_gnu_dummy(&self) -> bool {
+        match self {
+            &SecretKey::Encrypted {
+                s2k: S2K::GNU { extension: GNUExtension::Dummy, .. }, ..
+            } => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the serial number of the smartcard holding the secret
+    /// key material, if any.
+    pub fn card_serial(&self) -> Option<&[u8]> {
+        match self {
+            &SecretKey::Encrypted {
+                s2k: S2K::GNU {
+                    extension: GNUExtension::DivertToCard { ref serial },
+                    ..
+                },
+                ..
+            } => Some(&serial[..]),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -705,7 +964,7 @@ mod tests {
     use packet::key::SecretKey;
     use super::*;
     use PacketPile;
-    use serialize::SerializeKey;
+    use serialize::{SerializeKey, SerializeKeyInto};
     use parse::Parse;
 
     #[test]
@@ -797,6 +1056,118 @@ mod tests {
         }
     }
 
+    #[test]
+    fn v5_roundtrip() {
+        use constants::Curve::*;
+
+        let keys = vec![NistP256, Ed25519].into_iter().map(|cv| {
+            Key5::generate_ecc(true, cv).unwrap()
+        }).chain(vec![Cv25519].into_iter().map(|cv| {
+            Key5::generate_ecc(false, cv).unwrap()
+        })).chain(vec![2048].into_iter().map(|b| {
+            Key5::generate_rsa(b).unwrap()
+        }));
+
+        for key in keys {
+            let fp = key.fingerprint();
+            assert_eq!(fp.version(), Some(5));
+            assert_eq!(key.keyid().as_slice(), &fp.as_slice()[..8]);
+            assert!(fp != key.key4.fingerprint());
+
+            let mut encrypted = key.clone();
+            encrypted.secret_mut().unwrap()
+                .encrypt_in_place(&"foobarbaz".into()).unwrap();
+
+            for key in vec![key.clone(), encrypted] {
+                let mut b = Vec::new();
+                key.serialize(&mut b, Tag::SecretKey).unwrap();
+                assert_eq!(b.len(), key.serialized_len(Tag::SecretKey));
+
+                let pp = PacketPile::from_bytes(&b).unwrap();
+                if let Some(Packet::SecretKey(Key::V5(ref parsed_key))) =
+                    pp.path_ref(&[0])
+                {
+                    assert_eq!(&key, parsed_key);
+                    assert_eq!(parsed_key.fingerprint(), fp);
+                } else {
+                    panic!("bad packet: {:?}", pp.path_ref(&[0]));
+                }
+            }
+
+            let mut b = Vec::new();
+            key.serialize(&mut b, Tag::PublicKey).unwrap();
+
+            let pp = PacketPile::from_bytes(&b).unwrap();
+            if let Some(Packet::PublicKey(ref parsed_key)) = pp.path_ref(&[0])
+            {
+                assert_eq!(parsed_key.version(), 5);
+                assert!(parsed_key.secret().is_none());
+                assert_eq!(parsed_key.fingerprint(), fp);
+            } else {
+                panic!("bad packet: {:?}", pp.path_ref(&[0]));
+            }
+        }
+    }
+
+    #[test]
+    fn v5_test_vector() {
+        use crypto::mpis::MPI;
+
+        // The sample v5 key from RFC 4880bis, Appendix A.
+        let packet = [
+            0x98, 0x37, 0x05, 0x5c, 0x91, 0xf4, 0xe4, 0x16,
+            0x00, 0x00, 0x00, 0x2d, 0x09, 0x2b, 0x06, 0x01,
+            0x04, 0x01, 0xda, 0x47, 0x0f, 0x01, 0x01, 0x07,
+            0x40, 0x58, 0x59, 0x95, 0x57, 0x15, 0x56, 0xdc,
+            0x1f, 0xfb, 0x6d, 0x71, 0x35, 0x03, 0xd7, 0xf9,
+            0xe7, 0x0c, 0x24, 0x90, 0x4b, 0xd0, 0xc3, 0xdd,
+            0x7e, 0x3e, 0xf9, 0x8a, 0xec, 0x7e, 0x9b, 0x2f,
+            0x10,
+        ];
+        let body = &packet[2..];
+
+        let pp = PacketPile::from_bytes(&packet[..]).unwrap();
+        let mut key = if let Some(Packet::PublicKey(Key::V5(ref key))) =
+            pp.path_ref(&[0])
+        {
+            key.clone()
+        } else {
+            panic!("bad packet: {:?}", pp.path_ref(&[0]));
+        };
+        assert_eq!(key.pk_algo(), PublicKeyAlgorithm::EdDSA);
+        assert_eq!(key.fingerprint(), Fingerprint::from_hex(
+            "19347BC9872464025F99DF3EC2E0000ED9884892E1F7B3EA4C94009159569B54"
+        ).unwrap());
+        assert_eq!(key.keyid(), KeyID::from_hex("19347BC987246402").unwrap());
+
+        // We use new-style CTBs.
+        let mut b = vec![0xc6, 0x37];
+        b.extend_from_slice(body);
+        assert_eq!(b, key.to_vec(Tag::PublicKey).unwrap());
+
+        // Unencrypted secret key material is prefixed with its length,
+        // and not followed by a checksum.
+        let scalar = [0x42; 32];
+        key.set_secret(Some(SecretKey::Unencrypted {
+            mpis: mpis::SecretKey::EdDSA { scalar: MPI::new(&scalar) },
+        }));
+        let mut b = vec![0xc5, 0x37 + 1 + 4 + 2 + 32];
+        b.extend_from_slice(body);
+        b.extend_from_slice(&[0, 0, 0, 0, 34, 0x00, 0xff]);
+        b.extend_from_slice(&scalar);
+        assert_eq!(b, key.to_vec(Tag::SecretKey).unwrap());
+        assert_eq!(b.len(), key.serialized_len(Tag::SecretKey));
+
+        let pp = PacketPile::from_bytes(&b).unwrap();
+        if let Some(Packet::SecretKey(Key::V5(ref parsed_key))) =
+            pp.path_ref(&[0])
+        {
+            assert_eq!(&key, parsed_key);
+        } else {
+            panic!("bad packet: {:?}", pp.path_ref(&[0]));
+        }
+    }
+
     #[test]
     fn encryption_roundtrip() {
         use packet::key::SecretKey;
@@ -860,6 +1231,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn gnu_stubs() {
+        let serial = [0xd2, 0x76, 0x00, 0x01, 0x24, 0x01, 0x02, 0x01,
+                      0x00, 0x06, 0x01, 0x23, 0x45, 0x67, 0x00, 0x00];
+        let tests = [
+            ("testy-gnu-dummy-private.pgp", None),
+            ("testy-divert-to-card-private.pgp", Some(&serial[..])),
+        ];
+
+        for &(filename, serial) in tests.iter() {
+            let pp = PacketPile::from_bytes(::tests::key(filename)).unwrap();
+            let key = if let Some(Packet::SecretKey(Key::V4(ref key))) =
+                pp.path_ref(&[0])
+            {
+                key.clone()
+            } else {
+                panic!("bad packet: {:?}", pp.path_ref(&[0]));
+            };
+
+            {
+                let secret = key.secret().unwrap();
+                assert!(secret.is_stub());
+                assert_eq!(secret.is_gnu_dummy(), serial.is_none());
+                assert_eq!(secret.card_serial(), serial);
+                assert!(secret.decrypt(key.pk_algo(), &"".into()).is_err());
+            }
+            assert!(key.clone().into_keypair().is_err());
+
+            // Make sure the stub survives a roundtrip.
+            let mut b = Vec::new();
+            key.serialize(&mut b, Tag::SecretKey).unwrap();
+
+            let pp = PacketPile::from_bytes(&b).unwrap();
+            if let Some(Packet::SecretKey(Key::V4(ref parsed_key))) =
+                pp.path_ref(&[0])
+            {
+                assert_eq!(&key, parsed_key);
+            } else {
+                panic!("bad packet: {:?}", pp.path_ref(&[0]));
+            }
+        }
+    }
+
+    #[test]
+    fn secret_encryption_argon2() {
+        use serialize::Serialize;
+
+        // Argon2 must only be used with AEAD (S2K usage octet 253),
+        // but we encrypt secret keys using CFB (254).
+        let key = Key4::generate_ecc(true, Curve::Ed25519).unwrap();
+        let password = Password::from("foobarbaz");
+        let s2k = S2K::new_argon2(1, 1, 10).unwrap();
+
+        let mut encrypted_key = key.clone();
+        assert!(encrypted_key.secret_mut().unwrap()
+                .encrypt_in_place_with_s2k(&password, s2k).is_err());
+        assert_eq!(key, encrypted_key);
+
+        // Nor do we emit or accept such keys.
+        let mut encrypted_key = key.clone();
+        encrypted_key.set_secret(Some(SecretKey::Encrypted {
+            s2k: s2k,
+            algorithm: SymmetricAlgorithm::AES256,
+            ciphertext: vec![0; 64].into_boxed_slice(),
+        }));
+        let mut b = Vec::new();
+        assert!(encrypted_key.serialize(&mut b, Tag::SecretKey).is_err());
+
+        let mut body = Vec::new();
+        key.serialize(&mut body, Tag::PublicKey).unwrap();
+        let mut body = body.split_off(2);
+        body.push(254);
+        body.push(SymmetricAlgorithm::AES256.into());
+        s2k.serialize(&mut body).unwrap();
+        body.extend_from_slice(&[0; 64][..]);
+        let mut b = vec![0xc5, body.len() as u8];
+        b.append(&mut body);
+        let pp = PacketPile::from_bytes(&b).unwrap();
+        assert_match!(Some(Packet::Unknown(_)) = pp.path_ref(&[0]));
+    }
+
     #[test]
     fn import_cv25519() {
         use crypto::{ecdh, SessionKey};
@@ -42,6 +42,8 @@ mod literal;
 pub use self::literal::Literal;
 mod compressed_data;
 pub use self::compressed_data::CompressedData;
+mod sed;
+pub use self::sed::SED;
 pub mod seip;
 pub mod skesk;
 pub mod pkesk;
@@ -63,6 +65,8 @@ impl<'a> Deref for Packet {
         match self {
             &Packet::Unknown(ref packet) => &packet.common,
             &Packet::Signature(Signature::V4(ref packet)) => &packet.common,
+            &Packet::Signature(Signature::V5(ref packet)) =>
+                &packet.signature4.common,
             &Packet::OnePassSig(ref packet) => &packet.common,
             &Packet::PublicKey(ref packet) => &packet.common,
             &Packet::PublicSubkey(ref packet) => &packet.common,
@@ -77,6 +81,7 @@ impl<'a> Deref for Packet {
             &Packet::PKESK(ref packet) => &packet.common,
             &Packet::SKESK(SKESK::V4(ref packet)) => &packet.common,
             &Packet::SKESK(SKESK::V5(ref packet)) => &packet.skesk4.common,
+            &Packet::SED(ref packet) => &packet.common,
             &Packet::SEIP(ref packet) => &packet.common,
             &Packet::MDC(ref packet) => &packet.common,
             &Packet::AED(AED::V1(ref packet)) => &packet.common,
@@ -90,6 +95,8 @@ impl<'a> DerefMut for Packet {
             &mut Packet::Unknown(ref mut packet) => &mut packet.common,
             &mut Packet::Signature(Signature::V4(ref mut packet)) =>
                 &mut packet.common,
+            &mut Packet::Signature(Signature::V5(ref mut packet)) =>
+                &mut packet.signature4.common,
             &mut Packet::OnePassSig(ref mut packet) => &mut packet.common,
             &mut Packet::PublicKey(ref mut packet) => &mut packet.common,
             &mut Packet::PublicSubkey(ref mut packet) => &mut packet.common,
@@ -104,6 +111,7 @@ impl<'a> DerefMut for Packet {
             &mut Packet::PKESK(ref mut packet) => &mut packet.common,
             &mut Packet::SKESK(SKESK::V4(ref mut packet)) => &mut packet.common,
             &mut Packet::SKESK(SKESK::V5(ref mut packet)) => &mut packet.skesk4.common,
+            &mut Packet::SED(ref mut packet) => &mut packet.common,
             &mut Packet::SEIP(ref mut packet) => &mut packet.common,
             &mut Packet::MDC(ref mut packet) => &mut packet.common,
             &mut Packet::AED(AED::V1(ref mut packet)) => &mut packet.common,
@@ -612,6 +620,8 @@ fn packet_path_iter() {
 pub enum Signature {
     /// Signature packet version 4.
     V4(self::signature::Signature4),
+    /// Signature packet version 5.
+    V5(self::signature::Signature5),
 }
 
 impl Signature {
@@ -619,6 +629,7 @@ impl Signature {
     pub fn version(&self) -> u8 {
         match self {
             &Signature::V4(_) => 4,
+            &Signature::V5(_) => 5,
         }
     }
 }
@@ -629,22 +640,24 @@ impl From<Signature> for Packet {
     }
 }
 
-// Trivial forwarder for singleton enum.
+// Version 5 signatures share the fields of version 4 signatures.
 impl Deref for Signature {
     type Target = signature::Signature4;
 
     fn deref(&self) -> &Self::Target {
         match self {
             Signature::V4(sig) => sig,
+            Signature::V5(sig) => sig,
         }
     }
 }
 
-// Trivial forwarder for singleton enum.
+// Version 5 signatures share the fields of version 4 signatures.
 impl DerefMut for Signature {
     fn deref_mut(&mut self) -> &mut Self::Target {
         match self {
             Signature::V4(ref mut sig) => sig,
+            Signature::V5(ref mut sig) => sig,
         }
     }
 }
@@ -782,12 +795,15 @@ impl From<SKESK> for Packet {
 pub enum Key {
     /// Key packet version 4.
     V4(self::key::Key4),
+    /// Key packet version 5.
+    V5(self::key::Key5),
 }
 
 impl fmt::Display for Key {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Key::V4(k) => k.fmt(f),
+            Key::V5(k) => k.fmt(f),
         }
     }
 }
@@ -797,9 +813,26 @@ impl Key {
     pub fn version(&self) -> u8 {
         match self {
             Key::V4(_) => 4,
+            Key::V5(_) => 5,
+        }
+    }
+
+    /// Computes and returns the key's fingerprint.
+    ///
+    /// Version 4 keys use SHA-1 fingerprints, version 5 keys use
+    /// SHA-256 fingerprints.
+    pub fn fingerprint(&self) -> ::Fingerprint {
+        match self {
+            Key::V4(k) => k.fingerprint(),
+            Key::V5(k) => k.fingerprint(),
         }
     }
 
+    /// Computes and returns the key's key ID.
+    pub fn keyid(&self) -> ::KeyID {
+        self.fingerprint().to_keyid()
+    }
+
     /// Compares the public bits of two keys.
     ///
     /// This returns Ordering::Equal if the public MPIs, version,
@@ -807,9 +840,13 @@ impl Key {
     /// does not consider the packet's encoding, packet's tag or the
     /// secret key material.
     pub fn public_cmp(a: &Self, b: &Self) -> ::std::cmp::Ordering {
-        match (a, b) {
-            (Key::V4(a), Key::V4(b)) => self::key::Key4::public_cmp(a, b),
+        match a.version().cmp(&b.version()) {
+            ::std::cmp::Ordering::Equal => (),
+            o => return o,
         }
+
+        // Version 5 keys share the fields of version 4 keys.
+        self::key::Key4::public_cmp(a, b)
     }
 
     /// Creates a new key pair from a Key packet with an unencrypted
@@ -821,6 +858,7 @@ impl Key {
     pub fn into_keypair(self) -> Result<::crypto::KeyPair> {
         match self {
             Key::V4(p) => p.into_keypair(),
+            Key::V5(p) => p.into_keypair(),
         }
     }
 
@@ -828,26 +866,31 @@ impl Key {
     pub fn into_packet(self, tag: Tag) -> Result<Packet> {
         match self {
             Key::V4(p) => p.into_packet(tag),
+            Key::V5(p) => p.into_packet(tag),
         }
     }
 }
 
-// Trivial forwarder for singleton enum.
+// Version 5 keys share the fields of version 4 keys.  Note: the
+// `Key4`'s fingerprint and key ID are those of a version 4 key, use
+// `Key::fingerprint` and `Key::keyid` instead.
 impl Deref for Key {
     type Target = self::key::Key4;
 
     fn deref(&self) -> &Self::Target {
         match self {
             Key::V4(ref p) => p,
+            Key::V5(ref p) => &p.key4,
         }
     }
 }
 
-// Trivial forwarder for singleton enum.
+// Version 5 keys share the fields of version 4 keys.
 impl DerefMut for Key {
     fn deref_mut(&mut self) -> &mut Self::Target {
         match self {
             Key::V4(ref mut p) => p,
+            Key::V5(ref mut p) => &mut p.key4,
         }
     }
 }
@@ -8,11 +8,13 @@ pub use packet::{
     Signature,
     signature,
     signature::Signature4,
+    signature::Signature5,
     OnePassSig,
     one_pass_sig::OnePassSig3,
     Key,
     KeyFlags,
     key::Key4,
+    key::Key5,
     key::SecretKey,
     Marker,
     Trust,
@@ -26,6 +28,7 @@ pub use packet::{
     SKESK,
     skesk::SKESK4,
     skesk::SKESK5,
+    SED,
     SEIP,
     seip::SEIP1,
     MDC,
@@ -0,0 +1,59 @@
+//! Symmetrically Encrypted Data packets.
+//!
+//! An encrypted data packet is a container.  See [Section 5.7 of RFC
+//! 4880] for details.
+//!
+//! This packet type offers no integrity protection, and has been
+//! superseded by the [SEIP] packet.  It is only parsed if explicitly
+//! requested using [`PacketParserBuilder::allow_legacy_sed`].
+//!
+//! [Section 5.7 of RFC 4880]: https://tools.ietf.org/html/rfc4880#section-5.7
+//! [SEIP]: ../seip/index.html
+//! [`PacketParserBuilder::allow_legacy_sed`]: ../../parse/struct.PacketParserBuilder.html#method.allow_legacy_sed
+
+use std::ops::{Deref, DerefMut};
+use packet::{self, Common};
+use Packet;
+
+/// Holds a symmetrically encrypted data packet.
+///
+/// An encrypted data packet is a container.  See [Section 5.7 of RFC
+/// 4880] for details.
+///
+/// [Section 5.7 of RFC 4880]: https://tools.ietf.org/html/rfc4880#section-5.7
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct SED {
+    /// CTB packet header fields.
+    pub(crate) common: packet::Common,
+}
+
+impl SED {
+    /// Creates a new SED packet.
+    pub fn new() -> Self {
+        Self {
+            common: Default::default(),
+        }
+    }
+}
+
+impl From<SED> for Packet {
+    fn from(s: SED) -> Self {
+        Packet::SED(s)
+    }
+}
+
+// Allow transparent access of common fields.
+impl<'a> Deref for SED {
+    type Target = Common;
+
+    fn deref(&self) -> &Self::Target {
+        &self.common
+    }
+}
+
+// Allow transparent access of common fields.
+impl<'a> DerefMut for SED {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.common
+    }
+}
@@ -1,7 +1,7 @@
 //! Types for signatures.
 
 use std::fmt;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
 
 use constants::Curve;
 use Error;
@@ -19,6 +19,7 @@ use packet::Key;
 use KeyID;
 use packet::UserID;
 use packet::UserAttribute;
+use packet::Literal;
 use Packet;
 use packet;
 use packet::signature::subpacket::SubpacketArea;
@@ -35,9 +36,13 @@ const TRACE : bool = false;
 ///
 /// This is the mutable version of a `Signature4` packet.  To convert
 /// it to one, use `sign_hash(..)`.
+///
+/// The version of the resulting signature is determined by the
+/// signing key: version 5 keys create version 5 signatures, all
+/// other keys create version 4 signatures.
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub struct Builder {
-    /// Version of the signature packet. Must be 4.
+    /// Version of the signature packet. Must be 4 or 5.
     version: u8,
     /// Type of signature.
     sigtype: SignatureType,
@@ -118,6 +123,7 @@ impl Builder {
     pub fn sign_primary_key_binding(mut self, signer: &mut Signer,
                                     algo: HashAlgorithm)
                                     -> Result<Signature> {
+        self.version = signer.public().version();
         self.pk_algo = signer.public().pk_algo();
         self.hash_algo = algo;
         let digest =
@@ -134,6 +140,7 @@ impl Builder {
     pub fn sign_userid_binding(mut self, signer: &mut Signer,
                                key: &Key, userid: &UserID, algo: HashAlgorithm)
                                -> Result<Signature> {
+        self.version = signer.public().version();
         self.pk_algo = signer.public().pk_algo();
         self.hash_algo = algo;
         let digest = Signature::userid_binding_hash(&self, key, userid)?;
@@ -149,6 +156,7 @@ impl Builder {
     pub fn sign_subkey_binding(mut self, signer: &mut Signer,
                                primary: &Key, subkey: &Key, algo: HashAlgorithm)
                                -> Result<Signature> {
+        self.version = signer.public().version();
         self.pk_algo = signer.public().pk_algo();
         self.hash_algo = algo;
         let digest = Signature::subkey_binding_hash(&self, primary, subkey)?;
@@ -165,6 +173,7 @@ impl Builder {
                                        key: &Key, ua: &UserAttribute,
                                        algo: HashAlgorithm)
                                        -> Result<Signature> {
+        self.version = signer.public().version();
         self.pk_algo = signer.public().pk_algo();
         self.hash_algo = algo;
         let digest =
@@ -178,13 +187,33 @@ impl Builder {
     /// The Signature's public-key algorithm field is set to the
     /// algorithm used by `signer`, the hash-algorithm field is set to
     /// `hash_algo`.
-    pub fn sign_hash(mut self, signer: &mut Signer,
-                     hash_algo: HashAlgorithm, mut hash: Box<nettle::Hash>)
+    pub fn sign_hash(self, signer: &mut Signer,
+                     hash_algo: HashAlgorithm, hash: Box<nettle::Hash>)
                      -> Result<Signature> {
+        self.sign_hash_literal(signer, hash_algo, hash, None)
+    }
+
+    /// Signs `hash` of the body of `literal` using `signer`.
+    ///
+    /// Like `sign_hash`, but version 5 signatures made by `signer`
+    /// also cover the literal data packet's metadata.  Use this to
+    /// create inline signatures.
+    pub fn sign_literal_hash(self, signer: &mut Signer,
+                             hash_algo: HashAlgorithm,
+                             hash: Box<nettle::Hash>, literal: &Literal)
+                             -> Result<Signature> {
+        self.sign_hash_literal(signer, hash_algo, hash, Some(literal))
+    }
+
+    fn sign_hash_literal(mut self, signer: &mut Signer,
+                         hash_algo: HashAlgorithm, mut hash: Box<nettle::Hash>,
+                         literal: Option<&Literal>)
+                         -> Result<Signature> {
         // Fill out some fields, then hash the packet.
+        self.version = signer.public().version();
         self.pk_algo = signer.public().pk_algo();
         self.hash_algo = hash_algo;
-        self.hash(&mut hash);
+        self.hash_literal(&mut hash, literal);
 
         // Compute the digest.
         let mut digest = vec![0u8; hash.digest_size()];
@@ -206,6 +235,7 @@ impl Builder {
         hash.update(msg);
 
         // Fill out some fields, then hash the packet.
+        self.version = signer.public().version();
         self.pk_algo = signer.public().pk_algo();
         self.hash_algo = hash_algo;
         self.hash(&mut hash);
@@ -236,6 +266,7 @@ impl From<Signature> for Builder {
     fn from(sig: Signature) -> Self {
         match sig {
             Signature::V4(sig) => sig.into(),
+            Signature::V5(sig) => sig.signature4.into(),
         }
     }
 }
@@ -250,6 +281,7 @@ impl<'a> From<&'a Signature> for &'a Builder {
     fn from(sig: &'a Signature) -> Self {
         match sig {
             Signature::V4(ref sig) => sig.into(),
+            Signature::V5(ref sig) => (&sig.signature4).into(),
         }
     }
 }
@@ -690,7 +722,7 @@ impl Signature4 {
         }
 
         let mut backsig_ok = false;
-        if let Some(Packet::Signature(super::Signature::V4(backsig))) =
+        if let Some(Packet::Signature(backsig)) =
             self.embedded_signature()
         {
             if backsig.sigtype() != SignatureType::PrimaryKeyBinding {
@@ -923,7 +955,72 @@ impl From<Signature4> for Packet {
 
 impl From<Signature4> for super::Signature {
     fn from(s: Signature4) -> Self {
-        super::Signature::V4(s)
+        if s.version() == 5 {
+            super::Signature::V5(Signature5 { signature4: s })
+        } else {
+            super::Signature::V4(s)
+        }
+    }
+}
+
+/// Holds a version 5 signature packet.
+///
+/// Version 5 signature packets have the same fields as version 4
+/// signature packets.  They differ in how the signature is computed:
+/// the trailer includes a 64 bit length, and document signatures
+/// also cover the signed literal data packet's metadata.  See
+/// [Section 5.2 of RFC 4880bis] for details.
+///
+///   [Section 5.2 of RFC 4880bis]: https://tools.ietf.org/html/draft-ietf-openpgp-rfc4880bis-07#section-5.2
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct Signature5 {
+    /// Common fields.
+    pub(crate) signature4: Signature4,
+}
+
+impl Deref for Signature5 {
+    type Target = Signature4;
+
+    fn deref(&self) -> &Self::Target {
+        &self.signature4
+    }
+}
+
+impl DerefMut for Signature5 {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.signature4
+    }
+}
+
+impl Signature5 {
+    /// Creates a new version 5 signature packet.
+    ///
+    /// If you want to sign something, consider using the [`Builder`]
+    /// interface.
+    ///
+    /// [`Builder`]: struct.Builder.html
+    pub fn new(sigtype: SignatureType, pk_algo: PublicKeyAlgorithm,
+               hash_algo: HashAlgorithm, hashed_area: SubpacketArea,
+               unhashed_area: SubpacketArea,
+               hash_prefix: [u8; 2],
+               mpis: mpis::Signature) -> Self {
+        let mut sig = Signature4::new(sigtype, pk_algo, hash_algo,
+                                      hashed_area, unhashed_area,
+                                      hash_prefix, mpis);
+        sig.fields.version = 5;
+        Signature5 { signature4: sig }
+    }
+}
+
+impl From<Signature5> for Packet {
+    fn from(s: Signature5) -> Self {
+        Packet::Signature(s.into())
+    }
+}
+
+impl From<Signature5> for super::Signature {
+    fn from(s: Signature5) -> Self {
+        super::Signature::V5(s)
     }
 }
 
@@ -1008,6 +1105,19 @@ mod test {
                 data: "signed-1-notarized-by-ed25519.pgp",
                 good: 1,
             },
+            // Version 5 signatures, created by an independent
+            // implementation of RFC 4880bis.
+            Test {
+                key: "v5-ed25519.pgp",
+                data: "signed-1-v5-ed25519.pgp",
+                good: 1,
+            },
+            // The literal data packet's metadata is signed.
+            Test {
+                key: "v5-ed25519.pgp",
+                data: "signed-1-v5-ed25519-bad-filename.pgp",
+                good: 0,
+            },
             // Check with the wrong key.
             Test {
                 key: &"neal.pgp"[..],
@@ -1147,6 +1257,40 @@ mod test {
         };
     }
 
+    #[test]
+    fn sign_message_v5() {
+        use time;
+        use constants::Curve;
+        use packet::key::Key5;
+        use serialize::SerializeInto;
+
+        let key: Key = Key5::generate_ecc(true, Curve::Ed25519)
+            .unwrap().into();
+        let msg = b"Hello, World";
+
+        let mut pair = key.clone().into_keypair().unwrap();
+        let sig = Builder::new(SignatureType::Binary)
+            .set_signature_creation_time(time::now()).unwrap()
+            .set_issuer_fingerprint(key.fingerprint()).unwrap()
+            .set_issuer(key.keyid()).unwrap()
+            .sign_message(&mut pair, HashAlgorithm::SHA512, msg).unwrap();
+
+        assert_eq!(sig.version(), 5);
+        assert_eq!(sig.issuer_fingerprint(), Some(key.fingerprint()));
+        assert!(sig.verify_message(&key, msg).unwrap());
+
+        // A version 4 signature with the same fields doesn't verify.
+        let mut sig4 = sig.clone();
+        sig4.fields.version = 4;
+        assert!(! sig4.verify_message(&key, msg).unwrap());
+
+        // Roundtrip.
+        let sig_ = Signature::from_bytes(&sig.to_vec().unwrap()).unwrap();
+        assert_eq!(sig, sig_);
+        assert_eq!(sig_.version(), 5);
+        assert!(sig_.verify_message(&key, msg).unwrap());
+    }
+
     #[test]
     fn verify_message() {
         let tpk = TPK::from_bytes(::tests::key(
@@ -784,7 +784,7 @@ impl<'a> SubpacketValue<'a> {
             Features(f) => f.as_vec().len(),
             SignatureTarget { ref digest, .. } => 1 + 1 + digest.len(),
             EmbeddedSignature(p) => match p {
-                &Packet::Signature(Signature::V4(ref sig)) => {
+                &Packet::Signature(ref sig) => {
                     let mut w = Vec::new();
                     sig.serialize_naked(&mut w).unwrap();
                     w.len()
@@ -794,12 +794,14 @@ impl<'a> SubpacketValue<'a> {
             },
             IssuerFingerprint(ref fp) => match fp {
                 Fingerprint::V4(_) => 1 + 20,
+                Fingerprint::V5(_) => 1 + 32,
                 // Educated guess for unknown versions.
                 Fingerprint::Invalid(_) => 1 + fp.as_slice().len(),
             },
             PreferredAEADAlgorithms(ref p) => p.len(),
             IntendedRecipient(ref fp) => match fp {
                 Fingerprint::V4(_) => 1 + 20,
+                Fingerprint::V5(_) => 1 + 32,
                 // Educated guess for unknown versions.
                 Fingerprint::Invalid(_) => 1 + fp.as_slice().len(),
             },
@@ -1098,7 +1100,7 @@ impl<'a> From<SubpacketRaw<'a>> for Subpacket<'a> {
             SubpacketTag::IssuerFingerprint => {
                 let version = raw.value.get(0);
                 if let Some(version) = version {
-                    if *version == 4 {
+                    if *version == 4 || *version == 5 {
                         Some(SubpacketValue::IssuerFingerprint(
                             Fingerprint::from_bytes(&raw.value[1..])))
                     } else {
@@ -1117,7 +1119,7 @@ impl<'a> From<SubpacketRaw<'a>> for Subpacket<'a> {
             SubpacketTag::IntendedRecipient => {
                 let version = raw.value.get(0);
                 if let Some(version) = version {
-                    if *version == 4 {
+                    if *version == 4 || *version == 5 {
                         Some(SubpacketValue::IntendedRecipient(
                             Fingerprint::from_bytes(&raw.value[1..])))
                     } else {
@@ -2596,6 +2598,11 @@ fn accessors() {
     let sig_ =
         sig.clone().sign_hash(&mut keypair, hash_algo, hash.clone()).unwrap();
     assert_eq!(sig_.features(), feats);
+    let feats = Features::default().set_v5(true);
+    sig = sig.set_features(&feats).unwrap();
+    let sig_ =
+        sig.clone().sign_hash(&mut keypair, hash_algo, hash.clone()).unwrap();
+    assert_eq!(sig_.features(), feats);
 
     let digest = vec![0; hash_algo.context().unwrap().digest_size()];
     sig = sig.set_signature_target(pk_algo, hash_algo, &digest).unwrap();
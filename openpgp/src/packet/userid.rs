@@ -117,6 +117,44 @@ impl Clone for UserID {
 }
 
 impl UserID {
+    /// Constructs a user ID from its components.
+    ///
+    /// The result is an RFC 2822 name-addr of the form `Name
+    /// (Comment) <address>`.  The name is quoted and the comment
+    /// escaped as necessary.  The address must be a valid RFC 2822
+    /// addr-spec, and is used as is.
+    ///
+    /// This returns an error if the components can't be recovered
+    /// from the resulting user ID using `UserID::name`,
+    /// `UserID::comment`, and `UserID::address`.  This happens, for
+    /// instance, if a component contains a line break.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate sequoia_openpgp as openpgp;
+    /// # use openpgp::Result;
+    /// use openpgp::packet::UserID;
+    ///
+    /// # fn main() { f().unwrap(); }
+    /// # fn f() -> Result<()> {
+    /// let u = UserID::from_components(Some("Henry Ford, Sr."), Some("CEO"),
+    ///                                 "henry@ford.com")?;
+    /// assert_eq!(u.value(), &b"\"Henry Ford, Sr.\" (CEO) <henry@ford.com>"[..]);
+    /// assert_eq!(u.name()?, Some("Henry Ford, Sr.".into()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_components(name: Option<&str>, comment: Option<&str>,
+                           address: &str)
+        -> Result<Self>
+    {
+        let s = NameAddr::escaped(name, comment, address)
+            .context(format!("Invalid user ID components: {:?}, {:?}, {:?}",
+                             name, comment, address))?;
+        Ok(UserID::from(&s[..]))
+    }
+
     /// Gets the user ID packet's value.
     pub fn value(&self) -> &[u8] {
         self.value.as_slice()
@@ -148,6 +186,8 @@ impl UserID {
 
     /// Treats the user ID as an RFC 2822 name-addr and extracts the
     /// display name, if any.
+    ///
+    /// RFC 2047 encoded-words are decoded.
     pub fn name(&self) -> Result<Option<String>> {
         self.do_parse()?;
         match *self.parsed.borrow() {
@@ -159,6 +199,8 @@ impl UserID {
 
     /// Treats the user ID as an RFC 2822 name-addr and extracts the
     /// first comment, if any.
+    ///
+    /// RFC 2047 encoded-words are decoded.
     pub fn comment(&self) -> Result<Option<String>> {
         self.do_parse()?;
         match *self.parsed.borrow() {
@@ -170,6 +212,10 @@ impl UserID {
 
     /// Treats the user ID as an RFC 2822 name-addr and extracts the
     /// address, if any.
+    ///
+    /// As permitted by RFC 6532, the address may contain UTF-8.  It
+    /// is returned verbatim, see `address_normalized` for comparing
+    /// addresses.
     pub fn address(&self) -> Result<Option<String>> {
         self.do_parse()?;
         match *self.parsed.borrow() {
@@ -191,6 +237,10 @@ impl UserID {
     /// normalization] on the domain, and lowercasing the local part in
     /// the so-called [empty locale].
     ///
+    /// This returns an error if the local part contains an `@`, which
+    /// is possible if it is a quoted string.  Since the quotes are
+    /// not preserved, the normalized address would be ambiguous.
+    ///
     /// Note: this normalization procedure is the same as the
     /// normalization procedure recommended by [Autocrypt].
     ///
@@ -202,10 +252,16 @@ impl UserID {
             e @ Err(_) => e,
             Ok(None) => Ok(None),
             Ok(Some(address)) => {
-                let mut iter = address.split('@');
-                let localpart = iter.next().expect("Invalid email address");
+                let mut iter = address.rsplitn(2, '@');
                 let domain = iter.next().expect("Invalid email address");
-                assert!(iter.next().is_none(), "Invalid email address");
+                let localpart = iter.next().expect("Invalid email address");
+
+                // A quoted local part may contain '@'.
+                if localpart.contains('@') {
+                    return Err(failure::format_err!(
+                        "Ambiguous address: local part contains '@': {:?}",
+                        address));
+                }
 
                 // Normalize Unicode in domains.
                 let domain = idna::domain_to_ascii(domain)
@@ -329,6 +385,33 @@ mod tests {
         c("@old-world.org", false, None, None, None);
     }
 
+    #[test]
+    fn from_components() {
+        fn c(name: Option<&str>, comment: Option<&str>, address: &str) {
+            let u = UserID::from_components(name, comment, address).unwrap();
+            assert_eq!(u.name().unwrap().as_ref().map(|s| &s[..]), name);
+            assert_eq!(u.comment().unwrap().as_ref().map(|s| &s[..]),
+                       comment);
+            assert_eq!(u.address().unwrap().as_ref().map(|s| &s[..]),
+                       Some(address));
+        }
+
+        c(Some("Henry Ford"), Some("CEO"), "henry@ford.com");
+        c(None, None, "henry@ford.com");
+        c(None, Some("CEO"), "henry@ford.com");
+        c(Some("Ford, Henry"), None, "henry@ford.com");
+        c(Some("Henry \"Model T\" Ford"), Some(":-)"), "henry@ford.com");
+        c(Some("Henry Ford"), Some("CEO (retired"), "henry@ford.com");
+        c(Some("<loki@bar.com>"), None, "foo@bar.com");
+
+        assert!(UserID::from_components(
+            Some("Henry\nFord"), None, "henry@ford.com").is_err());
+        assert!(UserID::from_components(
+            Some("Henry Ford"), None, "henry@@ford.com").is_err());
+        assert!(UserID::from_components(
+            Some("Henry Ford"), None, "<henry@ford.com>").is_err());
+    }
+
     #[test]
     fn address_normalized() {
         fn c(value: &str, expected: &str) {
@@ -342,5 +425,24 @@ mod tests {
         c("Henry Ford (CEO) <Henry@Ford.com>", "henry@ford.com");
         c("hans@bücher.tld", "hans@xn--bcher-kva.tld");
         c("hANS@bücher.tld", "hans@xn--bcher-kva.tld");
+        c("=?UTF-8?Q?H=C3=A4nsel?= <Hänsel@Bücher.tld>",
+          "hänsel@xn--bcher-kva.tld");
+
+        // The quotes disappear, so a local part containing '@' would
+        // be ambiguous.
+        assert!(UserID::from("\"Hans@Home\"@bücher.tld")
+                .address_normalized().is_err());
+    }
+
+    #[test]
+    fn encoded_words() {
+        let u = UserID::from(
+            "=?UTF-8?Q?Andr=C3=A9?= (=?ISO-8859-1?Q?Ing=E9nieur?=) \
+             <andré@bücher.tld>");
+        assert_eq!(u.name().unwrap(), Some("André".into()));
+        assert_eq!(u.comment().unwrap(), Some("Ingénieur".into()));
+        assert_eq!(u.address().unwrap(), Some("andré@bücher.tld".into()));
+        assert_eq!(u.address_normalized().unwrap(),
+                   Some("andré@xn--bcher-kva.tld".into()));
     }
 }
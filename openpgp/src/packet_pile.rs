@@ -238,7 +238,8 @@ impl PacketPile {
             let p = &mut tmp.packets[i];
             if p.children.is_none() {
                 match p {
-                    Packet::CompressedData(_) | Packet::SEIP(_) => {
+                    Packet::CompressedData(_) | Packet::SED(_)
+                        | Packet::SEIP(_) => {
                         // We have a container with no children.
                         // That's okay.  We can create the container.
                         p.children = Some(Container::new());
@@ -196,6 +196,10 @@ impl<R: BufferedReader<Cookie>>
         result
     }
 
+    fn position(&self) -> Option<u64> {
+        self.reader.position()
+    }
+
     fn get_mut(&mut self) -> Option<&mut BufferedReader<Cookie>> {
         Some(&mut self.reader)
     }
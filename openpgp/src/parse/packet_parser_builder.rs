@@ -1,4 +1,5 @@
 use std::io;
+use std::mem;
 use std::path::Path;
 
 use buffered_reader::BufferedReader;
@@ -38,6 +39,7 @@ pub enum Dearmor {
 pub struct PacketParserBuilder<'a> {
     bio: Box<'a + BufferedReader<Cookie>>,
     dearmor: Dearmor,
+    multi_block: bool,
     settings: PacketParserSettings,
 }
 
@@ -78,6 +80,7 @@ impl<'a> PacketParserBuilder<'a> {
         Ok(PacketParserBuilder {
             bio: bio,
             dearmor: Dearmor::Auto,
+            multi_block: false,
             settings: PacketParserSettings::default(),
         })
     }
@@ -118,12 +121,221 @@ impl<'a> PacketParserBuilder<'a> {
         self
     }
 
+    /// Controls whether legacy SED packets are parsed.
+    ///
+    /// Symmetrically Encrypted Data packets (see [Section 5.7 of RFC
+    /// 4880]) are not integrity protected, and decrypting them
+    /// exposes the caller to ciphertext manipulation.  By default,
+    /// they are parsed into `Packet::Unknown`.  If enabled, they are
+    /// parsed into `Packet::SED`, and can be decrypted using
+    /// `PacketParser::decrypt`.  This is only useful for reading old
+    /// data, e.g. messages produced by PGP 2.x.
+    ///
+    ///   [Section 5.7 of RFC 4880]: https://tools.ietf.org/html/rfc4880#section-5.7
+    pub fn allow_legacy_sed(mut self, enable: bool) -> Self {
+        self.settings.allow_legacy_sed = enable;
+        self
+    }
+
+    /// Sets the maximum number of bytes a decompressor may produce.
+    ///
+    /// This limit applies to each compressed data packet.  If it is
+    /// exceeded, parsing fails with `Error::ResourceLimitExceeded`
+    /// (or, when reading the content using `io::Read`, an
+    /// `io::Error` wrapping a
+    /// `buffered_reader::DecompressionLimitExceeded`).  The default
+    /// is no limit.
+    pub fn max_decompressed_size<T>(mut self, value: T) -> Self
+        where T: Into<Option<u64>>
+    {
+        self.settings.max_decompressed_size = value.into();
+        self
+    }
+
+    /// Sets the maximum compression ratio.
+    ///
+    /// A decompressor may produce at most `value` times as many bytes
+    /// as it consumed.  To avoid false positives for short inputs,
+    /// this is only enforced once more than
+    /// `buffered_reader::RATIO_THRESHOLD` bytes have been produced.
+    /// Violations are reported like for `max_decompressed_size`.
+    /// The default is [`DEFAULT_MAX_DECOMPRESSION_RATIO`].  Use
+    /// `None` to disable the limit.
+    ///
+    ///   [`DEFAULT_MAX_DECOMPRESSION_RATIO`]: constant.DEFAULT_MAX_DECOMPRESSION_RATIO.html
+    pub fn max_decompression_ratio<T>(mut self, value: T) -> Self
+        where T: Into<Option<u64>>
+    {
+        self.settings.max_decompression_ratio = value.into();
+        self
+    }
+
+    /// Sets the maximum number of bytes the `PacketParser` may
+    /// buffer.
+    ///
+    /// This bounds the total amount of data that is buffered when
+    /// unread content is buffered (see `buffer_unread_content`), or
+    /// when a map is created (see `map`).  If it is exceeded, parsing
+    /// fails with `Error::ResourceLimitExceeded`.  The default is no
+    /// limit.
+    pub fn max_buffered_bytes<T>(mut self, value: T) -> Self
+        where T: Into<Option<u64>>
+    {
+        self.settings.max_buffered_bytes = value.into();
+        self
+    }
+
+    /// Sets the maximum number of signatures per TPK.
+    ///
+    /// This counts the signature packets following a primary key
+    /// packet, until the next primary key packet.  If it is
+    /// exceeded, parsing fails with `Error::ResourceLimitExceeded`.
+    /// The default is no limit.
+    pub fn max_signatures_per_tpk<T>(mut self, value: T) -> Self
+        where T: Into<Option<usize>>
+    {
+        self.settings.max_signatures_per_tpk = value.into();
+        self
+    }
+
     /// How to treat the input stream.
+    ///
+    /// If the input is dearmored, only the first armored block is
+    /// read by default.  See `dearmor_multi_block` for reading
+    /// several concatenated blocks.
     pub fn dearmor(mut self, mode: Dearmor) -> Self {
         self.dearmor = mode;
         self
     }
 
+    /// Controls whether all armored blocks are read.
+    ///
+    /// If enabled, and the input is dearmored, the armored blocks are
+    /// read one after the other, skipping any text between them,
+    /// i.e., the result is as if the blocks' contents were
+    /// concatenated.  This is useful for keyrings, which are often
+    /// distributed as a sequence of armored keys.  By default, only
+    /// the first block is read.
+    pub fn dearmor_multi_block(mut self, enabled: bool) -> Self {
+        self.multi_block = enabled;
+        self
+    }
+
+    /// Returns the kind and the headers of the armored block.
+    ///
+    /// Returns `None` if the input is not dearmored.  If several
+    /// blocks are read (see `dearmor_multi_block`), this describes
+    /// the first one.  This does not consume any input.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate sequoia_openpgp as openpgp;
+    /// # use openpgp::Result;
+    /// # use openpgp::armor::Kind;
+    /// # use openpgp::parse::{Parse, PacketParserResult, PacketParserBuilder};
+    /// # f(include_bytes!("../../tests/data/keys/testy.asc"));
+    /// #
+    /// # fn f(data: &[u8]) -> Result<()> {
+    /// let mut ppb = PacketParserBuilder::from_bytes(data)?;
+    /// let (kind, _headers) = ppb.armor_header()?.unwrap();
+    /// assert_eq!(kind, Kind::PublicKey);
+    ///
+    /// let ppr = ppb.finalize()?;
+    /// assert!(if let PacketParserResult::Some(_) = ppr { true } else { false });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn armor_header(&mut self)
+        -> Result<Option<(armor::Kind, Vec<(String, String)>)>>
+    {
+        if ! self.should_dearmor() {
+            return Ok(None);
+        }
+
+        // Look at the header using a `Dup` so that nothing is
+        // consumed.
+        let bio = mem::replace(
+            &mut self.bio,
+            Box::new(buffered_reader::EOF::with_cookie(Cookie::default())));
+        let mut reader = armor::Reader::from_buffered_reader(
+            Box::new(buffered_reader::Dup::with_cookie(bio, Cookie::default())),
+            None);
+        let headers = reader.headers().map(|h| h.to_vec());
+        let kind = reader.kind();
+        self.bio = Box::new(reader).into_inner().unwrap()
+            .into_inner().unwrap();
+        let headers = headers?;
+        Ok(kind.map(|kind| (kind, headers)))
+    }
+
+    /// Returns whether the input is dearmored.
+    ///
+    /// This resolves `Dearmor::Auto` by looking at the first packet's
+    /// header.
+    fn should_dearmor(&mut self) -> bool {
+        if self.dearmor == Dearmor::Auto {
+            let bio = mem::replace(
+                &mut self.bio,
+                Box::new(buffered_reader::EOF::with_cookie(Cookie::default())));
+            let mut reader = buffered_reader::Dup::with_cookie(
+                bio, Cookie::default());
+            let header = packet::Header::parse(&mut reader);
+            self.bio = Box::new(reader).into_inner().unwrap();
+            let binary = if let Ok(header) = header {
+                // If the header is invalid, better try an ASCII
+                // armor decoder.
+                header.valid(false).is_ok()
+            } else {
+                // Failed to parse the header: better try an ASCII
+                // armor decoder.
+                false
+            };
+            self.dearmor =
+                if binary { Dearmor::Disabled } else { Dearmor::Enabled };
+        }
+
+        self.dearmor == Dearmor::Enabled
+    }
+
+    /// Starts parsing at the given position in the input.
+    ///
+    /// `offset` is usually the position of a packet as returned by
+    /// [`PacketParser::offset`].  This only works if the input is
+    /// seekable, i.e., if the `PacketParserBuilder` was created using
+    /// `from_file` or `from_bytes`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate sequoia_openpgp as openpgp;
+    /// # use openpgp::Result;
+    /// # use openpgp::TPK;
+    /// # use openpgp::parse::{Parse, PacketParser, PacketParserBuilder};
+    /// # f(include_bytes!("../../tests/data/keys/testy.pgp"));
+    /// #
+    /// # fn f(keyring: &[u8]) -> Result<()> {
+    /// // Remember where the first TPK starts.
+    /// let offset = PacketParser::from_bytes(keyring)?.unwrap().offset();
+    /// assert_eq!(offset, Some(0));
+    ///
+    /// // Later, jump directly to it.
+    /// let ppr = PacketParserBuilder::from_bytes(keyring)?
+    ///     .seek(offset.unwrap())?
+    ///     .finalize()?;
+    /// let tpk = TPK::from_packet_parser(ppr)?;
+    /// # assert_eq!(tpk.fingerprint(),
+    /// #            TPK::from_bytes(keyring)?.fingerprint());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`PacketParser::offset`]: struct.PacketParser.html#method.offset
+    pub fn seek(mut self, offset: u64) -> Result<Self> {
+        self.bio.seek(offset)?;
+        Ok(self)
+    }
+
     /// Finishes configuring the `PacketParser` and returns an
     /// `Option<PacketParser>`.
     ///
@@ -147,39 +359,14 @@ impl<'a> PacketParserBuilder<'a> {
         -> Result<PacketParserResult<'a>>
         where Self: 'a
     {
-        let state = PacketParserState::new(self.settings);
-
-        let dearmor = match self.dearmor {
-            Dearmor::Enabled => true,
-            Dearmor::Disabled => false,
-            Dearmor::Auto => {
-                let mut reader = buffered_reader::Dup::with_cookie(
-                    self.bio, Cookie::default());
-                let header = packet::Header::parse(&mut reader);
-                self.bio = Box::new(reader).into_inner().unwrap();
-                if let Ok(header) = header {
-                    if let Err(_) = header.valid(false) {
-                        // Invalid header: better try an ASCII armor
-                        // decoder.
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    // Failed to parse the header: better try an ASCII
-                    // armor decoder.
-                    true
-                }
-            }
-        };
-
-        if dearmor {
-            self.bio = Box::new(buffered_reader::Generic::with_cookie(
-                armor::Reader::from_buffered_reader(self.bio, None),
-                None,
-                Default::default()));
+        if self.should_dearmor() {
+            self.bio = Box::new(
+                armor::Reader::from_buffered_reader(self.bio, None)
+                    .multi_block(self.multi_block));
         }
 
+        let state = PacketParserState::new(self.settings);
+
         // Parse the first packet.
         match PacketParser::parse(Box::new(self.bio), state, vec![ 0 ])? {
             ParserResult::Success(mut pp) => {
@@ -247,4 +434,84 @@ mod tests {
             .finalize();
         assert_match!(Ok(PacketParserResult::Some(ref _pp)) = ppr);
     }
+
+    #[test]
+    fn multi_block() {
+        use Packet;
+
+        fn count_keys(ppb: PacketParserBuilder) -> usize {
+            let mut keys = 0;
+            let mut ppr = ppb.finalize().unwrap();
+            while let PacketParserResult::Some(pp) = ppr {
+                if let Packet::PublicKey(_) = pp.packet {
+                    keys += 1;
+                }
+                ppr = pp.recurse().unwrap().1;
+            }
+            keys
+        }
+
+        let mut keyring = Vec::new();
+        keyring.extend_from_slice(::tests::key("testy.asc"));
+        keyring.extend_from_slice(b"\nSome text.\n");
+        keyring.extend_from_slice(::tests::key("testy.asc"));
+
+        // By default, only the first block is read.
+        let mut ppb = PacketParserBuilder::from_bytes(&keyring).unwrap();
+        let (kind, headers) = ppb.armor_header().unwrap().unwrap();
+        assert_eq!(kind, armor::Kind::PublicKey);
+        assert!(headers.is_empty());
+        assert_eq!(count_keys(ppb), 1);
+
+        let ppb = PacketParserBuilder::from_bytes(&keyring).unwrap()
+            .dearmor_multi_block(true);
+        assert_eq!(count_keys(ppb), 2);
+
+        // Binary data has no armor header.
+        let mut ppb = PacketParserBuilder::from_bytes(
+            ::tests::key("testy.pgp")).unwrap();
+        assert!(ppb.armor_header().unwrap().is_none());
+        assert_eq!(count_keys(ppb), 1);
+    }
+
+    #[test]
+    fn seek() {
+        use Packet;
+        use TPK;
+
+        let mut keyring = Vec::new();
+        for f in &["testy.pgp", "neal.pgp", "dkg.gpg"] {
+            keyring.extend_from_slice(::tests::key(f));
+        }
+
+        // Record the offsets of the primary keys.
+        let mut offsets = Vec::new();
+        let mut ppr = PacketParser::from_bytes(&keyring).unwrap();
+        while let PacketParserResult::Some(pp) = ppr {
+            if let Packet::PublicKey(ref key) = pp.packet {
+                offsets.push((key.fingerprint(), pp.offset().unwrap()));
+            }
+            ppr = pp.recurse().unwrap().1;
+        }
+        assert_eq!(offsets.len(), 3);
+        assert_eq!(offsets[0].1, 0);
+        assert_eq!(offsets[1].1, ::tests::key("testy.pgp").len() as u64);
+
+        // Jump to each TPK.
+        for (fp, offset) in offsets.into_iter().rev() {
+            let ppr = PacketParserBuilder::from_bytes(&keyring).unwrap()
+                .seek(offset).unwrap()
+                .finalize().unwrap();
+            let tpk = TPK::from_packet_parser(ppr).unwrap();
+            assert_eq!(tpk.fingerprint(), fp);
+        }
+
+        // The offset is unknown for armored data, and we can't seek
+        // in a plain reader.
+        let ppr = PacketParser::from_bytes(
+            ::tests::key("testy.asc")).unwrap();
+        assert_eq!(ppr.unwrap().offset(), None);
+        assert!(PacketParserBuilder::from_reader(&keyring[..]).unwrap()
+                .seek(0).is_err());
+    }
 }
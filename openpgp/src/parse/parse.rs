@@ -19,7 +19,7 @@ use {
     Result,
     CTB,
     BodyLength,
-    crypto::s2k::S2K,
+    crypto::s2k::{S2K, GNUExtension, CardSerial},
     Error,
     Header,
     packet::signature::Signature4,
@@ -104,6 +104,17 @@ pub trait Parse<'a, T> {
 /// So, this should be more than enough.
 const MAX_RECURSION_DEPTH : u8 = 16;
 
+/// The default maximum compression ratio.
+///
+/// A decompressor may produce at most this many times as many bytes
+/// as it consumed.  This is slightly above the maximum ratio that
+/// DEFLATE can achieve (about 1032:1), so it mostly rejects
+/// compression bombs using BZip2.  Note that the limit applies to
+/// each compressed data packet individually, so nested compressed
+/// data packets can exceed it.  Use `max_decompressed_size` to bound
+/// the absolute size.
+pub const DEFAULT_MAX_DECOMPRESSION_RATIO: u64 = 1100;
+
 // Used to parse an OpenPGP packet's header (note: in this case, the
 // header means a Packet's fixed data, not the OpenPGP framing
 // information, such as the CTB, and length information).
@@ -243,6 +254,7 @@ impl<'a> PacketHeaderParser<'a> {
             // `total_out` does not account for the body.
             //
             // XXX avoid the extra copy.
+            self.state.check_buffer_limit(&mut self.reader)?;
             let body = self.reader.steal_eof()?;
             if body.len() > 0 {
                 self.field("body", body.len());
@@ -281,6 +293,7 @@ impl<'a> PacketHeaderParser<'a> {
             decrypted: true,
             finished: false,
             map: self.map,
+            offset: None,
             state: self.state,
         })
     }
@@ -453,6 +466,11 @@ pub(crate) struct SignatureGroup {
 
     /// Maps hash algorithms to hash contexts.
     pub(crate) hashes: HashMap<HashAlgorithm, Box<nettle::Hash>>,
+
+    /// The signed literal data packet, if any.
+    ///
+    /// Version 5 signatures also hash the packet's metadata.
+    literal: Option<Literal>,
 }
 
 impl fmt::Debug for SignatureGroup {
@@ -463,6 +481,7 @@ impl fmt::Debug for SignatureGroup {
         f.debug_struct("Cookie")
             .field("ops_count", &self.ops_count)
             .field("hashes", &algos)
+            .field("literal", &self.literal)
             .finish()
     }
 }
@@ -472,6 +491,7 @@ impl Default for SignatureGroup {
         SignatureGroup {
             ops_count: 0,
             hashes: HashMap::new(),
+            literal: None,
         }
     }
 }
@@ -481,6 +501,7 @@ impl SignatureGroup {
     fn clear(&mut self) {
         self.ops_count = 0;
         self.hashes.clear();
+        self.literal = None;
     }
 }
 
@@ -586,6 +607,33 @@ impl Cookie {
         }
     }
 
+    // Records the literal data packet signed by the signature hashers
+    // at the given level.
+    fn literal(reader: &mut BufferedReader<Cookie>, literal: &Literal,
+               level: isize) {
+        let mut reader : Option<&mut BufferedReader<Cookie>>
+            = Some(reader);
+        while let Some(r) = reader {
+            {
+                let cookie = r.cookie_mut();
+                if let Some(br_level) = cookie.level {
+                    if br_level < level {
+                        break;
+                    }
+                    if br_level == level
+                        && cookie.hashes_for == HashesFor::Signature {
+                        for group in cookie.sig_groups.iter_mut() {
+                            group.literal = Some(literal.clone());
+                        }
+                    }
+                } else {
+                    break;
+                }
+            }
+            reader = r.get_mut();
+        }
+    }
+
     // A helpful debugging aid to pretty print a Buffered Reader
     // stack.
     #[allow(dead_code)]
@@ -683,6 +731,24 @@ struct PacketParserSettings {
 
     // Whether or not to create a map.
     map: bool,
+
+    // Whether to parse legacy SED packets, which are not integrity
+    // protected.
+    allow_legacy_sed: bool,
+
+    // The maximum number of bytes a decompressor may produce.
+    max_decompressed_size: Option<u64>,
+
+    // The maximum ratio between a decompressor's output and input.
+    max_decompression_ratio: Option<u64>,
+
+    // The maximum number of bytes buffered by the parser, when
+    // buffering unread content or creating a map.
+    max_buffered_bytes: Option<u64>,
+
+    // The maximum number of signature packets following a primary
+    // key.
+    max_signatures_per_tpk: Option<usize>,
 }
 
 // The default `PacketParser` settings.
@@ -692,6 +758,11 @@ impl Default for PacketParserSettings {
             max_recursion_depth: MAX_RECURSION_DEPTH,
             buffer_unread_content: false,
             map: false,
+            allow_legacy_sed: false,
+            max_decompressed_size: None,
+            max_decompression_ratio: Some(DEFAULT_MAX_DECOMPRESSION_RATIO),
+            max_buffered_bytes: None,
+            max_signatures_per_tpk: None,
         }
     }
 }
@@ -701,6 +772,12 @@ impl S2K {
     fn parse<'a>(php: &mut PacketHeaderParser<'a>) -> Result<Self>
     {
         let s2k = php.parse_u8("s2k_type")?;
+
+        // GnuPG's extensions use the private S2K type 101 followed
+        // by a hash algorithm and the magic string "GNU".
+        let gnu = s2k == 101
+            && php.reader.data(4)?.get(1..4) == Some(&b"GNU"[..]);
+
         let ret = match s2k {
             0 => S2K::Simple {
                 hash: HashAlgorithm::from(php.parse_u8("s2k_hash_algo")?),
@@ -714,6 +791,39 @@ impl S2K {
                 salt: Self::read_salt(php)?,
                 iterations: S2K::decode_count(php.parse_u8("s2k_count")?),
             },
+            4 => S2K::Argon2 {
+                salt: {
+                    let mut b = [0u8; 16];
+                    b.copy_from_slice(&php.parse_bytes("s2k_salt", 16)?);
+                    b
+                },
+                passes: php.parse_u8("s2k_argon2_t")?,
+                parallelism: php.parse_u8("s2k_argon2_p")?,
+                memory_exponent: php.parse_u8("s2k_argon2_m")?,
+            },
+            101 if gnu => {
+                let hash = HashAlgorithm::from(php.parse_u8("s2k_hash_algo")?);
+                php.parse_bytes("s2k_gnu_magic", 3)?;
+                let extension = match php.parse_u8("s2k_gnu_mode")? {
+                    1 => GNUExtension::Dummy,
+                    2 => {
+                        let len = php.parse_u8("s2k_card_serial_len")?;
+                        if len > 16 {
+                            return Err(Error::MalformedPacket(
+                                format!("Card serial number too long: \
+                                         {} bytes", len)).into());
+                        }
+                        GNUExtension::DivertToCard {
+                            serial: CardSerial::new(
+                                &php.parse_bytes("s2k_card_serial",
+                                                 len as usize)?)?,
+                        }
+                    },
+                    u => GNUExtension::Unknown(u),
+                };
+
+                S2K::GNU { hash: hash, extension: extension }
+            },
             100...110 => S2K::Private(s2k),
             u => S2K::Unknown(u),
         };
@@ -850,7 +960,7 @@ impl Signature {
         let version = php_try!(php.parse_u8("version"));
 
         match version {
-            4 => Signature4::parse(php),
+            4 | 5 => Signature4::parse(php, version),
             _ => {
                 t!("Ignoring version {} packet.", version);
                 php.fail("unknown version")
@@ -867,7 +977,10 @@ impl Signature {
 
 impl Signature4 {
     // Parses a signature packet.
-    fn parse<'a>(mut php: PacketHeaderParser<'a>)
+    //
+    // Version 5 signatures share the layout of version 4 signatures,
+    // hence this is also used to parse them.
+    fn parse<'a>(mut php: PacketHeaderParser<'a>, version: u8)
         -> Result<PacketParser<'a>>
     {
         let indent = php.recursion_depth();
@@ -895,12 +1008,22 @@ impl Signature4 {
             crypto::mpis::Signature::parse(pk_algo, &mut php));
 
         let hash_algo = hash_algo.into();
-        let mut pp = php.ok(Packet::Signature(Signature4::new(
-            sigtype.into(), pk_algo.into(), hash_algo,
-            SubpacketArea::new(hashed_area),
-            SubpacketArea::new(unhashed_area),
-            [hash_prefix1, hash_prefix2],
-            mpis).into()))?;
+        let sig: Signature = if version == 5 {
+            Signature5::new(
+                sigtype.into(), pk_algo.into(), hash_algo,
+                SubpacketArea::new(hashed_area),
+                SubpacketArea::new(unhashed_area),
+                [hash_prefix1, hash_prefix2],
+                mpis).into()
+        } else {
+            Signature4::new(
+                sigtype.into(), pk_algo.into(), hash_algo,
+                SubpacketArea::new(hashed_area),
+                SubpacketArea::new(unhashed_area),
+                [hash_prefix1, hash_prefix2],
+                mpis).into()
+        };
+        let mut pp = php.ok(Packet::Signature(sig))?;
 
         // Locate the corresponding HashedReader and extract the
         // computed hash.
@@ -931,7 +1054,9 @@ impl Signature4 {
                         {
                             t!("popped a {:?} HashedReader", hash_algo);
                             computed_hash = Some((cookie.signature_level(),
-                                                  hash_algo, hash.clone()));
+                                                  hash_algo, hash.clone(),
+                                                  cookie.sig_group().literal
+                                                  .clone()));
                         }
 
                         if cookie.sig_group_unused() {
@@ -945,9 +1070,9 @@ impl Signature4 {
             }
         }
 
-        if let Some((level, algo, mut hash)) = computed_hash {
+        if let Some((level, algo, mut hash, literal)) = computed_hash {
             if let Packet::Signature(ref mut sig) = pp.packet {
-                sig.hash(&mut hash);
+                sig.hash_literal(&mut hash, literal.as_ref());
 
                 let mut digest = vec![0u8; hash.digest_size()];
                 hash.digest(&mut digest);
@@ -994,7 +1119,7 @@ impl Signature4 {
         let pk_algo : PublicKeyAlgorithm = data[2].into();
         let hash_algo : HashAlgorithm = data[3].into();
 
-        if version == 4
+        if (version == 4 || version == 5)
             && !destructures_to!(SignatureType::Unknown(_) = sigtype)
             && !destructures_to!(PublicKeyAlgorithm::Unknown(_) = pk_algo)
             && !destructures_to!(HashAlgorithm::Unknown(_) = hash_algo)
@@ -1341,7 +1466,7 @@ impl Key {
         let version = php_try!(php.parse_u8("version"));
 
         match version {
-            4 => Key4::parse(php),
+            4 | 5 => Key4::parse(php, version),
             _ => php.fail("unknown version"),
         }
     }
@@ -1356,9 +1481,14 @@ impl Key {
 impl Key4 {
     /// Parses the body of a public key, public subkey, secret key or
     /// secret subkey packet.
-    fn parse<'a>(mut php: PacketHeaderParser<'a>) -> Result<PacketParser<'a>> {
+    ///
+    /// Version 5 keys share the fields of version 4 keys, but prefix
+    /// the key material with its length.  Hence, this is also used
+    /// to parse them.
+    fn parse<'a>(mut php: PacketHeaderParser<'a>, version: u8)
+                 -> Result<PacketParser<'a>> {
         use std::io::Cursor;
-        use serialize::Serialize;
+        use serialize::{Serialize, SerializeInto};
 
         make_php_try!(php);
         let tag = php.header.ctb.tag;
@@ -1369,23 +1499,49 @@ impl Key4 {
 
         let creation_time = php_try!(php.parse_be_u32("creation_time"));
         let pk_algo: PublicKeyAlgorithm = php_try!(php.parse_u8("pk_algo")).into();
+        let public_len = if version == 5 {
+            Some(php_try!(php.parse_be_u32("public_len")))
+        } else {
+            None
+        };
         let mpis = php_try!(PublicKey::parse(pk_algo, &mut php));
+        if let Some(len) = public_len {
+            if mpis.serialized_len() != len as usize {
+                return php.fail("wrong public key material length");
+            }
+        }
+
         let secret = if tag == Tag::SecretKey || tag == Tag::SecretSubkey {
             let s2k_usage = php_try!(php.parse_u8("s2k_usage"));
             let sec = match s2k_usage {
                 // Unencrypted
                 0 => {
+                    let secret_len = if version == 5 {
+                        Some(php_try!(php.parse_be_u32("secret_len")))
+                    } else {
+                        None
+                    };
                     let sec = php_try!(
                         crypto::mpis::SecretKey::parse(pk_algo, &mut php));
-                    let their_chksum = php_try!(php.parse_be_u16("checksum"));
-                    let mut cur = Cursor::new(Vec::default());
-
-                    sec.serialize(&mut cur)?;
-                    let our_chksum: usize = cur.into_inner()
-                        .into_iter().map(|x| x as usize).sum();
-
-                    if our_chksum as u16 & 0xffff != their_chksum {
-                        return php.fail("wrong secret key checksum");
+                    if let Some(len) = secret_len {
+                        if sec.serialized_len() != len as usize {
+                            return php.fail(
+                                "wrong secret key material length");
+                        }
+                    }
+                    // Version 5 keys don't have a checksum.
+                    if version == 4 {
+                        let their_chksum =
+                            php_try!(php.parse_be_u16("checksum"));
+                        let mut cur = Cursor::new(Vec::default());
+
+                        sec.serialize(&mut cur)?;
+                        let our_chksum: usize = cur.into_inner()
+                            .into_iter().map(|x| x as usize).sum();
+
+                        if our_chksum as u16 & 0xffff != their_chksum {
+                            return php.fail("wrong secret key checksum");
+                        }
                     }
 
                     SecretKey::Unencrypted{ mpis: sec }
@@ -1396,9 +1552,34 @@ impl Key4 {
                 }
                 // Encrypted, S2K & SHA-1 checksum
                 254 => {
+                    let fields_len = if version == 5 {
+                        Some(php_try!(php.parse_u8("s2k_fields_len")))
+                    } else {
+                        None
+                    };
                     let sk: SymmetricAlgorithm = php_try!(php.parse_u8("sym_algo")).into();
                     let s2k = php_try!(S2K::parse(&mut php));
-                    let mut cipher = php_try!(php.parse_bytes_eof("encrypted_mpis"));
+                    if let S2K::Argon2 { .. } = s2k {
+                        // Argon2 must only be used with AEAD.
+                        return php.fail("Argon2 used without AEAD");
+                    }
+                    let cipher = if let Some(len) = fields_len {
+                        // Version 5 keys store the IV separately from
+                        // the encrypted secret key material.
+                        let iv_len = match (len as usize)
+                            .checked_sub(1 + s2k.serialized_len())
+                        {
+                            Some(l) => l,
+                            None => return php.fail("invalid S2K fields length"),
+                        };
+                        let mut cipher = php_try!(php.parse_bytes("iv", iv_len));
+                        let secret_len = php_try!(php.parse_be_u32("secret_len"));
+                        cipher.append(&mut php_try!(
+                            php.parse_bytes("encrypted_mpis", secret_len as usize)));
+                        cipher
+                    } else {
+                        php_try!(php.parse_bytes_eof("encrypted_mpis"))
+                    };
 
                     SecretKey::Encrypted{
                         s2k: s2k,
@@ -1406,9 +1587,29 @@ impl Key4 {
                         ciphertext: cipher.into_boxed_slice(),
                     }
                 }
-                // Encrypted, S2K & mod 65536 checksum: unsupported
+                // Encrypted, S2K & mod 65536 checksum: unsupported,
+                // unless it is one of GnuPG's stubs, which do not
+                // carry any secret key material, and hence no
+                // checksum.
                 255 => {
-                    return php.fail("unsupported secret key encryption");
+                    if version == 5 {
+                        php_try!(php.parse_u8("s2k_fields_len"));
+                    }
+                    let sk: SymmetricAlgorithm = php_try!(php.parse_u8("sym_algo")).into();
+                    let s2k = php_try!(S2K::parse(&mut php));
+                    if ! s2k.is_gnu_extension() {
+                        return php.fail("unsupported secret key encryption");
+                    }
+                    if version == 5 {
+                        php_try!(php.parse_be_u32("secret_len"));
+                    }
+                    let cipher = php_try!(php.parse_bytes_eof("encrypted_mpis"));
+
+                    SecretKey::Encrypted{
+                        s2k: s2k,
+                        algorithm: sk,
+                        ciphertext: cipher.into_boxed_slice(),
+                    }
                 }
                  _ => unreachable!()
             };
@@ -1420,8 +1621,13 @@ impl Key4 {
             unimplemented!()
         };
 
-        let key = php_try!(Key4::new(time::Tm::from_pgp(creation_time),
-                                     pk_algo, mpis, secret));
+        let key4 = php_try!(Key4::new(time::Tm::from_pgp(creation_time),
+                                      pk_algo, mpis, secret));
+        let key: Key = if version == 5 {
+            Key5::from(key4).into()
+        } else {
+            key4.into()
+        };
 
         let tag = php.header.ctb.tag;
         php.ok(match tag {
@@ -1461,7 +1667,7 @@ impl Key4 {
         let version = data[0];
         let pk_algo : PublicKeyAlgorithm = data[5].into();
 
-        if version == 4
+        if (version == 4 || version == 5)
             && !destructures_to!(PublicKeyAlgorithm::Unknown(_) = pk_algo)
         {
             Ok(())
@@ -1679,7 +1885,10 @@ impl Literal {
                 .expect("length checked above");
         }
         literal.set_date(Some(time::Tm::from_pgp(date)));
-        let mut pp = php.ok(Packet::Literal(literal))?;
+        let mut pp = php.ok(Packet::Literal(literal.clone()))?;
+
+        // Version 5 signatures also hash the metadata.
+        Cookie::literal(pp.mut_reader(), &literal, recursion_depth - 1);
 
         // Enable hashing of the body.
         Cookie::hashing(pp.mut_reader(), Hashing::Enabled,
@@ -1787,6 +1996,13 @@ impl CompressedData {
         t!("Pushing a decompressor for {}, recursion depth = {:?}.",
            algo, recursion_depth);
 
+        #[cfg(any(feature = "compression-deflate",
+                  feature = "compression-bzip2"))]
+        let limits = buffered_reader::DecompressionLimits {
+            max_size: pp.state.settings.max_decompressed_size,
+            max_ratio: pp.state.settings.max_decompression_ratio,
+        };
+
         let reader = pp.take_reader();
         let reader = match algo {
             CompressionAlgorithm::Uncompressed => {
@@ -1800,16 +2016,16 @@ impl CompressedData {
             },
             #[cfg(feature = "compression-deflate")]
             CompressionAlgorithm::Zip =>
-                Box::new(buffered_reader::Deflate::with_cookie(
-                    reader, Cookie::new(recursion_depth))),
+                Box::new(buffered_reader::Deflate::with_cookie_and_limits(
+                    reader, Cookie::new(recursion_depth), limits)),
             #[cfg(feature = "compression-deflate")]
             CompressionAlgorithm::Zlib =>
-                Box::new(buffered_reader::Zlib::with_cookie(
-                    reader, Cookie::new(recursion_depth))),
+                Box::new(buffered_reader::Zlib::with_cookie_and_limits(
+                    reader, Cookie::new(recursion_depth), limits)),
             #[cfg(feature = "compression-bzip2")]
             CompressionAlgorithm::BZip2 =>
-                Box::new(buffered_reader::Bzip::with_cookie(
-                    reader, Cookie::new(recursion_depth))),
+                Box::new(buffered_reader::Bzip::with_cookie_and_limits(
+                    reader, Cookie::new(recursion_depth), limits)),
             _ => unreachable!(), // Validated above.
         };
         pp.set_reader(reader);
@@ -2031,6 +2247,15 @@ fn skesk_parser_test() {
     }
 }
 
+impl SED {
+    /// Parses the body of a SED packet.
+    fn parse<'a>(php: PacketHeaderParser<'a>) -> Result<PacketParser<'a>> {
+        // There is no header, the packet's body is the ciphertext.
+        php.ok(SED::new().into())
+            .map(|pp| pp.set_decrypted(false))
+    }
+}
+
 impl SEIP {
     /// Parses the body of a SEIP packet.
     fn parse<'a>(mut php: PacketHeaderParser<'a>) -> Result<PacketParser<'a>> {
@@ -2436,6 +2661,13 @@ struct PacketParserState {
 
     // Whether this is the first packet in the packet sequence.
     first_packet: bool,
+
+    // The number of bytes buffered so far.
+    buffered_bytes: u64,
+
+    // The number of signature packets since the last primary key, or
+    // None, if we haven't seen a primary key.
+    signatures_in_tpk: Option<usize>,
 }
 
 impl PacketParserState {
@@ -2446,10 +2678,93 @@ impl PacketParserState {
             keyring_validator: Default::default(),
             tpk_validator: Default::default(),
             first_packet: true,
+            buffered_bytes: 0,
+            signatures_in_tpk: None,
+        }
+    }
+
+    /// Makes sure that buffering the rest of `reader` does not exceed
+    /// the limit on the number of buffered bytes.
+    ///
+    /// On success, the remaining data is accounted for.
+    fn check_buffer_limit<R>(&mut self, reader: &mut R) -> Result<()>
+        where R: BufferedReader<Cookie> + ?Sized
+    {
+        let max = if let Some(max) = self.settings.max_buffered_bytes {
+            max
+        } else {
+            return Ok(());
+        };
+
+        let allowance = cmp::min(max.saturating_sub(self.buffered_bytes),
+                                 (std::usize::MAX - 1) as u64) as usize;
+
+        // Don't just read allowance + 1 bytes at once, the
+        // implementation might try to actually allocate a buffer that
+        // large.  Instead, try with increasingly larger amounts.
+        let mut amount = cmp::min(4096, allowance + 1);
+        let len = loop {
+            let len = reader.data(amount)?.len();
+            if len > allowance {
+                return Err(Error::ResourceLimitExceeded(
+                    format!("Buffering more than {} bytes", max)).into());
+            }
+            if len < amount {
+                break len;
+            }
+            amount = cmp::min(amount.saturating_mul(2), allowance + 1);
+        };
+
+        self.buffered_bytes += len as u64;
+        Ok(())
+    }
+
+    /// Enforces the limit on the number of signatures per TPK.
+    fn count_signatures(&mut self, tag: Tag) -> Result<()> {
+        match tag {
+            Tag::PublicKey | Tag::SecretKey =>
+                self.signatures_in_tpk = Some(0),
+            Tag::Signature => if let Some(ref mut count) =
+                self.signatures_in_tpk
+            {
+                *count += 1;
+                if let Some(max) = self.settings.max_signatures_per_tpk {
+                    if *count > max {
+                        return Err(Error::ResourceLimitExceeded(
+                            format!("More than {} signatures in TPK", max))
+                                   .into());
+                    }
+                }
+            },
+            _ => (),
         }
+        Ok(())
     }
 }
 
+/// Maps an error indicating that a decompressor exceeded its limits
+/// to `Error::ResourceLimitExceeded`.
+///
+/// The decompressors are `BufferedReader`s, hence they report this
+/// condition using an `io::Error`.
+fn map_limit_exceeded(e: failure::Error) -> failure::Error {
+    #[cfg(any(feature = "compression-deflate",
+              feature = "compression-bzip2"))]
+    {
+        let limit = e.downcast_ref::<io::Error>()
+            .and_then(|e| e.get_ref())
+            .and_then(|e| e.downcast_ref::<
+                      buffered_reader::DecompressionLimitExceeded>())
+            .map(|l| l.to_string());
+
+        if let Some(limit) = limit {
+            return Error::ResourceLimitExceeded(limit).into();
+        }
+    }
+
+    e
+}
+
 /// A low-level OpenPGP message parser.
 ///
 /// A `PacketParser` provides a low-level, iterator-like interface to
@@ -2527,6 +2842,9 @@ pub struct PacketParser<'a> {
     /// A map of this packet.
     map: Option<map::Map>,
 
+    // The position of the packet's framing in the input, if known.
+    offset: Option<u64>,
+
     state: PacketParserState,
 }
 
@@ -2547,6 +2865,7 @@ impl<'a> std::fmt::Debug for PacketParser<'a> {
             .field("content_was_read", &self.content_was_read)
             .field("settings", &self.state.settings)
             .field("map", &self.map)
+            .field("offset", &self.offset)
             .finish()
     }
 }
@@ -2989,6 +3308,10 @@ impl <'a> PacketParser<'a> {
             return Ok(ParserResult::EOF((bio, state, path)));
         }
 
+        // Remember where the packet starts.  This is only known if
+        // the reader stack doesn't transform the data.
+        let offset = bio.position();
+
         // When computing a hash for a signature, most of the
         // signature packet should not be included in the hash.  That
         // is:
@@ -3135,6 +3458,8 @@ impl <'a> PacketParser<'a> {
             Tag::Literal =>             Literal::parse(parser),
             Tag::CompressedData =>      CompressedData::parse(parser),
             Tag::SKESK =>               SKESK::parse(parser),
+            Tag::SED if parser.state.settings.allow_legacy_sed =>
+                                        SED::parse(parser),
             Tag::SEIP =>                SEIP::parse(parser),
             Tag::MDC =>                 MDC::parse(parser),
             Tag::PKESK =>               PKESK::parse(parser),
@@ -3149,6 +3474,8 @@ impl <'a> PacketParser<'a> {
         }
 
         result.state.first_packet = false;
+        result.state.count_signatures(tag)?;
+        result.offset = offset;
 
         t!(" -> {:?}, path: {:?}, level: {:?}.",
            result.packet.tag(), result.path, result.cookie_ref().level);
@@ -3254,7 +3581,8 @@ impl <'a> PacketParser<'a> {
 
             let recursion_depth = self.recursion_depth();
 
-            let ppr = PacketParser::parse(reader, self.state, self.path)?;
+            let ppr = PacketParser::parse(reader, self.state, self.path)
+                .map_err(map_limit_exceeded)?;
             match ppr {
                 ParserResult::EOF((reader_, state_, path_)) => {
                     // We got EOF on the current container.  The
@@ -3326,8 +3654,8 @@ impl <'a> PacketParser<'a> {
 
         match self.packet {
             // Packets that recurse.
-            Packet::CompressedData(_) | Packet::SEIP(_) | Packet::AED(_)
-                if self.decrypted =>
+            Packet::CompressedData(_) | Packet::SED(_) | Packet::SEIP(_)
+                | Packet::AED(_) if self.decrypted =>
             {
                 if self.recursion_depth() as u8
                     >= self.state.settings.max_recursion_depth
@@ -3353,7 +3681,8 @@ impl <'a> PacketParser<'a> {
                     path.push(0);
 
                     match PacketParser::parse(self.reader, self.state,
-                                              path.clone())?
+                                              path.clone())
+                        .map_err(map_limit_exceeded)?
                     {
                         ParserResult::Success(mut pp) => {
                             t!("Recursed into the {:?} packet, got a {:?}.",
@@ -3385,7 +3714,8 @@ impl <'a> PacketParser<'a> {
                 | Packet::Marker(_) | Packet::Trust(_)
                 | Packet::UserID(_) | Packet::UserAttribute(_)
                 | Packet::Literal(_) | Packet::PKESK(_) | Packet::SKESK(_)
-                | Packet::SEIP(_) | Packet::MDC(_) | Packet::AED(_) => {
+                | Packet::SED(_) | Packet::SEIP(_) | Packet::MDC(_)
+                | Packet::AED(_) => {
                 // Drop through.
                 t!("A {:?} packet is not a container, not recursing.",
                    self.packet.tag());
@@ -3432,6 +3762,7 @@ impl <'a> PacketParser<'a> {
     /// # return Ok(());
     /// # }
     pub fn buffer_unread_content(&mut self) -> Result<&[u8]> {
+        self.state.check_buffer_limit(&mut self.reader)?;
         let mut rest = self.steal_eof()?;
         if rest.len() > 0 {
             if let Some(mut body) = self.packet.body.take() {
@@ -3469,13 +3800,14 @@ impl <'a> PacketParser<'a> {
                self.packet.tag(), recursion_depth,
                self.data_eof().unwrap().len());
 
-            self.buffer_unread_content()?.len() > 0
+            self.buffer_unread_content()
+                .map_err(map_limit_exceeded)?.len() > 0
         } else {
             t!("({:?} at depth {}): dropping {} bytes of unread content",
                self.packet.tag(), recursion_depth,
                self.data_eof().unwrap().len());
 
-            self.drop_eof()?
+            self.drop_eof().map_err(|e| map_limit_exceeded(e.into()))?
         };
 
         if unread_content {
@@ -3502,6 +3834,22 @@ impl <'a> PacketParser<'a> {
         &self.header
     }
 
+    /// Returns the position of the current packet in the input.
+    ///
+    /// This is the position of the packet's framing, i.e., of its
+    /// CTB.  It can be passed to [`PacketParserBuilder::seek`] to
+    /// resume parsing at this packet, e.g., to look up a TPK in a
+    /// large keyring using an index.
+    ///
+    /// The position is only known if the data has not been
+    /// transformed.  In particular, it is `None` for ASCII armored
+    /// input, and for packets in compressed or encrypted containers.
+    ///
+    /// [`PacketParserBuilder::seek`]: struct.PacketParserBuilder.html#method.seek
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
     /// Returns a reference to the map (if any is written).
     pub fn map(&self) -> Option<&map::Map> {
         self.map.as_ref()
@@ -3789,6 +4137,54 @@ impl<'a> PacketParser<'a> {
                 Ok(())
             },
 
+            Packet::SED(_) => {
+                // Get the first blocksize plus two bytes and check
+                // whether we can decrypt them using the provided key.
+                // Don't actually comsume them in case we can't.
+                let bl = algo.block_size()?;
+
+                {
+                    let mut dec = Decryptor::new(
+                        algo, key, &self.data_hard(bl + 2)?[..bl + 2])?;
+                    let mut header = vec![ 0u8; bl + 2 ];
+                    dec.read(&mut header)?;
+
+                    if !(header[bl - 2] == header[bl]
+                         && header[bl - 1] == header[bl + 1]) {
+                        return Err(Error::InvalidSessionKey(
+                            format!(
+                                "Last two 16-bit quantities don't match: {}",
+                                ::conversions::to_hex(&header[..], false)))
+                                   .into());
+                    }
+                }
+
+                // Ok, we can decrypt the data.  Unlike SEIP packets,
+                // SED packets use OpenPGP's CFB variant: after the
+                // prefix, the cipher is resynchronized using
+                // ciphertext[2..bl + 2] as the IV (see Section 13.9
+                // of RFC 4880).  We get the same effect by skipping
+                // the first two bytes of the ciphertext, decrypting
+                // the rest with the usual all-zero IV, and discarding
+                // the first block of plaintext.
+                let mut reader = self.take_reader();
+
+                // These can't fail, because we read the header above.
+                reader.data_consume_hard(2).unwrap();
+                let mut reader = BufferedReaderDecryptor::with_cookie(
+                    algo, key, reader, Cookie::default()).unwrap();
+                reader.cookie_mut().level = Some(self.recursion_depth());
+
+                t!("Pushing Decryptor, level {:?}.", reader.cookie_ref().level);
+
+                reader.data_consume_hard(bl).unwrap();
+
+                self.reader = Box::new(reader);
+                self.decrypted = true;
+
+                Ok(())
+            },
+
             Packet::AED(AED::V1(aed)) => {
                 // Get the first chunk and check whether we can
                 // decrypt it using the provided key.  Don't actually
@@ -4099,6 +4495,184 @@ mod test {
         }}
     }
 
+    #[test]
+    fn decrypt_legacy_sed() {
+        let tests = [
+            ("encrypted-sed-aes128-password-123.gpg",
+             SymmetricAlgorithm::AES128,
+             "0F7E95CD51855CD01C65CC3B0A5705AB"),
+            ("encrypted-sed-cast5-password-123.gpg",
+             SymmetricAlgorithm::CAST5,
+             "24E59BE8BB5B178E99E4450FE999F59A"),
+        ];
+
+        for &(filename, algo, key_hex) in tests.iter() {
+            // By default, SED packets are not parsed.
+            let ppr = PacketParser::from_bytes(
+                ::tests::message(filename)).unwrap();
+            let ppr = consume_until(
+                ppr, false, &[ Tag::SED ][..], &[ Tag::SKESK ][..]);
+            if let PacketParserResult::Some(ref pp) = ppr {
+                assert_match!(Packet::Unknown(_) = pp.packet);
+            } else {
+                panic!("Expected a SED packet.  Got: {:?}", ppr);
+            }
+
+            // Unless the caller opts in.
+            let ppr = PacketParserBuilder::from_bytes(
+                ::tests::message(filename)).unwrap()
+                .allow_legacy_sed(true)
+                .finalize().unwrap();
+            let mut ppr = consume_until(
+                ppr, false, &[ Tag::SED ][..], &[ Tag::SKESK ][..]);
+            if let PacketParserResult::Some(ref mut pp) = ppr {
+                assert_match!(Packet::SED(_) = pp.packet);
+
+                let key: SessionKey =
+                    ::conversions::from_hex(key_hex, false).unwrap().into();
+                let mut wrong_key = key.to_vec();
+                wrong_key[0] ^= 1;
+                assert!(pp.decrypt(algo, &wrong_key.into()).is_err());
+                pp.decrypt(algo, &key).unwrap();
+            } else {
+                panic!("Expected a SED packet.  Got: {:?}", ppr);
+            }
+
+            let mut ppr = consume_until(
+                ppr, true, &[ Tag::Literal ][..], &[][..]);
+            if let PacketParserResult::Some(ref mut pp) = ppr {
+                let mut content = Vec::new();
+                pp.read_to_end(&mut content).unwrap();
+                assert_eq!(&content[..], ::tests::manifesto());
+            } else {
+                panic!("Expected a Literal packet.  Got: {:?}", ppr);
+            }
+
+            let ppr = consume_until(
+                ppr, true, &[][..], &[][..]);
+            if let PacketParserResult::EOF(eof) = ppr {
+                assert!(eof.is_message().is_ok());
+            } else {
+                unreachable!();
+            }
+        }
+    }
+
+    // Parses the given packet sequence, buffering all content, and
+    // returns the size of the literal data.
+    fn parse_with_limits<'a>(builder: PacketParserBuilder<'a>)
+                             -> Result<usize> {
+        let mut ppr = builder.buffer_unread_content().finalize()?;
+        let mut len = 0;
+        while let PacketParserResult::Some(pp) = ppr {
+            let (packet, ppr_) = pp.recurse()?;
+            ppr = ppr_;
+            if let Packet::Literal(l) = packet {
+                len = l.body().map(|b| b.len()).unwrap_or(0);
+            }
+        }
+        Ok(len)
+    }
+
+    fn limit_exceeded(r: Result<usize>) -> bool {
+        if let Err(e) = r {
+            if let Some(&Error::ResourceLimitExceeded(_)) =
+                e.downcast_ref::<Error>()
+            {
+                return true;
+            }
+            panic!("Unexpected error: {}", e);
+        }
+        false
+    }
+
+    #[cfg(all(feature = "compression-deflate",
+              feature = "compression-bzip2"))]
+    #[test]
+    fn decompression_limits() {
+        const MIB: u64 = 1024 * 1024;
+
+        // These compress 16 MiB of zeros.
+        for filename in ["compression-bomb-zip.pgp",
+                         "compression-bomb-zlib.pgp",
+                         "compression-bomb-bzip2.pgp"].iter() {
+            let msg = ::tests::message(filename);
+            let builder = || PacketParserBuilder::from_bytes(msg).unwrap();
+
+            assert_eq!(parse_with_limits(
+                builder().max_decompression_ratio(None)).unwrap(),
+                       (16 * MIB) as usize);
+            assert_eq!(parse_with_limits(
+                builder().max_decompression_ratio(None)
+                    .max_decompressed_size(17 * MIB)).unwrap(),
+                       (16 * MIB) as usize);
+            assert!(limit_exceeded(parse_with_limits(
+                builder().max_decompressed_size(MIB))));
+            assert!(limit_exceeded(parse_with_limits(
+                builder().max_decompression_ratio(100))));
+            assert!(limit_exceeded(parse_with_limits(
+                builder().max_buffered_bytes(MIB))));
+        }
+
+        // By default, DEFLATE is accepted, but the BZip2 bomb is
+        // rejected.
+        for filename in ["compression-bomb-zip.pgp",
+                         "compression-bomb-zlib.pgp"].iter() {
+            let msg = ::tests::message(filename);
+            assert_eq!(parse_with_limits(
+                PacketParserBuilder::from_bytes(msg).unwrap()).unwrap(),
+                       (16 * MIB) as usize);
+        }
+        assert!(limit_exceeded(parse_with_limits(
+            PacketParserBuilder::from_bytes(
+                ::tests::message("compression-bomb-bzip2.pgp")).unwrap())));
+
+        // This is a compressed data packet containing a compressed
+        // data packet containing 1 GiB of zeros.  Don't try to parse
+        // it without limits.
+        let msg = ::tests::message("compression-bomb-nested.pgp");
+        assert!(limit_exceeded(parse_with_limits(
+            PacketParserBuilder::from_bytes(msg).unwrap()
+                .max_decompressed_size(32 * MIB))));
+        assert!(limit_exceeded(parse_with_limits(
+            PacketParserBuilder::from_bytes(msg).unwrap()
+                .max_decompression_ratio(100))));
+    }
+
+    #[test]
+    fn buffer_limits() {
+        let msg = ::tests::message("literal-mode-b.gpg");
+        assert!(parse_with_limits(
+            PacketParserBuilder::from_bytes(msg).unwrap()
+                .max_buffered_bytes(1024)).is_ok());
+        assert!(limit_exceeded(parse_with_limits(
+            PacketParserBuilder::from_bytes(msg).unwrap()
+                .max_buffered_bytes(1))));
+
+        // The limit also applies to maps.
+        let key = ::tests::key("neal.pgp");
+        let r = (|| -> Result<usize> {
+            let mut ppr = PacketParserBuilder::from_bytes(key)?
+                .map(true).max_buffered_bytes(1024).finalize()?;
+            while let PacketParserResult::Some(pp) = ppr {
+                ppr = pp.next()?.1;
+            }
+            Ok(0)
+        })();
+        assert!(limit_exceeded(r));
+    }
+
+    #[test]
+    fn signature_limits() {
+        let key = ::tests::key("neal.pgp");
+        assert!(parse_with_limits(
+            PacketParserBuilder::from_bytes(key).unwrap()
+                .max_signatures_per_tpk(1000)).is_ok());
+        assert!(limit_exceeded(parse_with_limits(
+            PacketParserBuilder::from_bytes(key).unwrap()
+                .max_signatures_per_tpk(1))));
+    }
+
     #[test]
     fn message_validator() {
         for test in DECRYPT_TESTS.iter() {
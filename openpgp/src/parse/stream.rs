@@ -15,6 +15,24 @@ use std::io::{self, Read};
 use std::path::Path;
 
 use buffered_reader::BufferedReader;
+#[cfg(feature = "async")]
+use buffered_reader::AsyncReader;
+#[cfg(feature = "async")]
+use failure;
+#[cfg(feature = "async")]
+use futures::{Async, Future, Poll};
+#[cfg(feature = "async")]
+use futures::sync::oneshot;
+#[cfg(feature = "async")]
+use std::panic;
+#[cfg(feature = "async")]
+use std::sync::{Arc, Mutex, mpsc};
+#[cfg(feature = "async")]
+use std::thread;
+#[cfg(feature = "async")]
+use num_cpus;
+#[cfg(feature = "async")]
+use tokio_io::AsyncRead;
 use {
     Error,
     Fingerprint,
@@ -50,6 +68,7 @@ use parse::{
     PacketParser,
     PacketParserBuilder,
     PacketParserResult,
+    DEFAULT_MAX_DECOMPRESSION_RATIO,
 };
 
 /// Whether to trace execution by default (on stderr).
@@ -181,10 +200,12 @@ impl<'a> MessageStructure<'a> {
     }
 
     fn new_encryption_layer(&mut self, sym_algo: SymmetricAlgorithm,
-                            aead_algo: Option<AEADAlgorithm>) {
+                            aead_algo: Option<AEADAlgorithm>,
+                            integrity_protected: bool) {
         self.0.push(MessageLayer::Encryption {
             sym_algo: sym_algo,
             aead_algo: aead_algo,
+            integrity_protected: integrity_protected,
         })
     }
 
@@ -234,6 +255,14 @@ pub enum MessageLayer<'a> {
         sym_algo: SymmetricAlgorithm,
         /// AEAD algorithm used, if any.
         aead_algo: Option<AEADAlgorithm>,
+        /// Whether the container is integrity protected.
+        ///
+        /// This is only false for legacy SED packets, which are only
+        /// decrypted if [`DecryptionHelper::allow_legacy_sed`]
+        /// returns true.
+        ///
+        /// [`DecryptionHelper::allow_legacy_sed`]: trait.DecryptionHelper.html#method.allow_legacy_sed
+        integrity_protected: bool,
     },
     /// Represents a signature group.
     SignatureGroup {
@@ -276,11 +305,13 @@ impl IMessageStructure {
     }
 
     fn new_encryption_layer(&mut self, sym_algo: SymmetricAlgorithm,
-                            aead_algo: Option<AEADAlgorithm>) {
+                            aead_algo: Option<AEADAlgorithm>,
+                            integrity_protected: bool) {
         self.insert_missing_signature_group();
         self.layers.push(IMessageLayer::Encryption {
             sym_algo: sym_algo,
             aead_algo: aead_algo,
+            integrity_protected: integrity_protected,
         });
     }
 
@@ -358,6 +389,7 @@ enum IMessageLayer {
     Encryption {
         sym_algo: SymmetricAlgorithm,
         aead_algo: Option<AEADAlgorithm>,
+        integrity_protected: bool,
     },
     SignatureGroup {
         sigs: Vec<Signature>,
@@ -370,6 +402,29 @@ pub trait VerificationHelper {
     /// Retrieves the TPKs containing the specified keys.
     fn get_public_keys(&mut self, &[KeyID]) -> Result<Vec<TPK>>;
 
+    /// Returns the maximum number of bytes a decompressor may
+    /// produce.
+    ///
+    /// See [`PacketParserBuilder::max_decompressed_size`].  The
+    /// default implementation returns `None`, i.e., no limit.
+    ///
+    /// [`PacketParserBuilder::max_decompressed_size`]: ../struct.PacketParserBuilder.html#method.max_decompressed_size
+    fn max_decompressed_size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the maximum compression ratio.
+    ///
+    /// See [`PacketParserBuilder::max_decompression_ratio`].  The
+    /// default implementation returns
+    /// [`DEFAULT_MAX_DECOMPRESSION_RATIO`].
+    ///
+    /// [`PacketParserBuilder::max_decompression_ratio`]: ../struct.PacketParserBuilder.html#method.max_decompression_ratio
+    /// [`DEFAULT_MAX_DECOMPRESSION_RATIO`]: ../constant.DEFAULT_MAX_DECOMPRESSION_RATIO.html
+    fn max_decompression_ratio(&self) -> Option<u64> {
+        Some(DEFAULT_MAX_DECOMPRESSION_RATIO)
+    }
+
     /// Conveys the message structure.
     ///
     /// The message structure contains the results of signature
@@ -467,7 +522,10 @@ impl<'a, H: VerificationHelper> Verifier<'a, H> {
                                        helper: H, t: time::Tm)
                                        -> Result<Verifier<'a, H>>
     {
-        let mut ppr = PacketParser::from_buffered_reader(bio)?;
+        let mut ppr = PacketParserBuilder::from_buffered_reader(bio)?
+            .max_decompressed_size(helper.max_decompressed_size())
+            .max_decompression_ratio(helper.max_decompression_ratio())
+            .finalize()?;
 
         let mut v = Verifier {
             helper: helper,
@@ -1112,6 +1170,19 @@ pub trait DecryptionHelper {
         false
     }
 
+    /// Controls whether legacy SED packets are decrypted.
+    ///
+    /// Symmetrically Encrypted Data packets are not integrity
+    /// protected, hence an attacker can modify the plaintext without
+    /// this being detected.  If this function returns true, they are
+    /// decrypted anyway, and the corresponding
+    /// `MessageLayer::Encryption` has `integrity_protected` set to
+    /// false.  Only enable this when reading old data.  The default
+    /// implementation returns false.
+    fn allow_legacy_sed(&self) -> bool {
+        false
+    }
+
     /// Inspects the message.
     ///
     /// Called once per packet.  Can be used to dump packets in
@@ -1211,7 +1282,11 @@ impl<'a, H: VerificationHelper + DecryptionHelper> Decryptor<'a, H> {
         tracer!(TRACE, "Decryptor::from_buffered_reader", 0);
 
         let mut ppr = PacketParserBuilder::from_buffered_reader(bio)?
-            .map(helper.mapping()).finalize()?;
+            .map(helper.mapping())
+            .allow_legacy_sed(helper.allow_legacy_sed())
+            .max_decompressed_size(helper.max_decompressed_size())
+            .max_decompression_ratio(helper.max_decompression_ratio())
+            .finalize()?;
 
         let mut v = Decryptor {
             helper: helper,
@@ -1239,12 +1314,13 @@ impl<'a, H: VerificationHelper + DecryptionHelper> Decryptor<'a, H> {
             match pp.packet {
                 Packet::CompressedData(ref p) =>
                     v.structure.new_compression_layer(p.algorithm()),
-                Packet::SEIP(_) | Packet::AED(_) => {
+                Packet::SED(_) | Packet::SEIP(_) | Packet::AED(_) => {
                     saw_content = true;
 
                     // Get the symmetric algorithm from the decryption
                     // proxy function.  This is necessary because we
-                    // cannot get the algorithm from the SEIP packet.
+                    // cannot get the algorithm from the SED or SEIP
+                    // packet.
                     let mut sym_algo = None;
                     {
                         let decryption_proxy = |algo, secret: &SessionKey| {
@@ -1272,6 +1348,11 @@ impl<'a, H: VerificationHelper + DecryptionHelper> Decryptor<'a, H> {
                             Some(p.aead())
                         } else {
                             None
+                        },
+                        if let Packet::SED(_) = pp.packet {
+                            false
+                        } else {
+                            true
                         });
                 },
                 Packet::OnePassSig(ref ops) => {
@@ -1316,6 +1397,10 @@ impl<'a, H: VerificationHelper + DecryptionHelper> Decryptor<'a, H> {
                 Packet::MDC(ref mdc) => if ! mdc.valid() {
                     return Err(Error::ManipulatedMessage.into());
                 },
+                // Legacy SED packets are only parsed if the helper
+                // opted in (see `DecryptionHelper::allow_legacy_sed`).
+                Packet::Unknown(ref u) if u.tag() == Tag::SED =>
+                    return Err(Error::UnsupportedPacketType(Tag::SED).into()),
                 _ => (),
             }
 
@@ -1423,8 +1508,11 @@ impl<'a, H: VerificationHelper + DecryptionHelper> Decryptor<'a, H> {
             match layer {
                 IMessageLayer::Compression { algo } =>
                     results.new_compression_layer(algo),
-                IMessageLayer::Encryption { sym_algo, aead_algo } =>
-                    results.new_encryption_layer(sym_algo, aead_algo),
+                IMessageLayer::Encryption {
+                    sym_algo, aead_algo, integrity_protected,
+                } =>
+                    results.new_encryption_layer(sym_algo, aead_algo,
+                                                 integrity_protected),
                 IMessageLayer::SignatureGroup { sigs, .. } => {
                     results.new_signature_group();
                     for sig in sigs.into_iter() {
@@ -1537,6 +1625,378 @@ impl<'a, H: VerificationHelper + DecryptionHelper> io::Read for Decryptor<'a, H>
     }
 }
 
+/// A message processed by an [`AsyncVerifier`] or
+/// [`AsyncDecryptor`].
+///
+/// The content has been verified, i.e. the message has been
+/// processed completely, and the helper has checked its structure.
+///
+/// [`AsyncVerifier`]: struct.AsyncVerifier.html
+/// [`AsyncDecryptor`]: struct.AsyncDecryptor.html
+#[cfg(feature = "async")]
+pub struct Processed<H> {
+    helper: H,
+    content: Vec<u8>,
+}
+
+#[cfg(feature = "async")]
+impl<H> Processed<H> {
+    /// Returns a reference to the helper.
+    pub fn helper_ref(&self) -> &H {
+        &self.helper
+    }
+
+    /// Recovers the helper.
+    pub fn into_helper(self) -> H {
+        self.helper
+    }
+
+    /// Returns the content of the message.
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Recovers the content of the message.
+    pub fn into_content(self) -> Vec<u8> {
+        self.content
+    }
+}
+
+/// Processes a buffered message.
+#[cfg(feature = "async")]
+type Process<H> = fn(Vec<u8>, H, time::Tm) -> Result<Processed<H>>;
+
+/// The state of an `AsyncVerifier` or `AsyncDecryptor`.
+#[cfg(feature = "async")]
+enum AsyncState<R: AsyncRead, H> {
+    /// Reading the message.
+    Reading(AsyncReader<R, Cookie>, H),
+    /// Waiting for the worker pool to process the message.
+    Processing(oneshot::Receiver<Result<Processed<H>>>),
+    /// The result has been returned.
+    Done,
+}
+
+/// A job for the worker pool.
+///
+/// This is an `FnMut` so that it can be called through the box.
+/// Each job is only run once.
+#[cfg(feature = "async")]
+type Job = Box<FnMut() + Send>;
+
+/// A fixed set of worker threads processing buffered messages.
+///
+/// All `AsyncVerifier`s and `AsyncDecryptor`s share one pool with
+/// one worker per CPU.  If more messages are waiting to be processed
+/// than there are workers, they are queued.
+#[cfg(feature = "async")]
+struct WorkerPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+#[cfg(feature = "async")]
+impl WorkerPool {
+    /// Starts the worker threads.
+    fn new(threads: usize) -> Self {
+        let (jobs, job_queue) = mpsc::channel::<Job>();
+        let job_queue = Arc::new(Mutex::new(job_queue));
+
+        for _ in 0..threads {
+            let job_queue = job_queue.clone();
+            thread::spawn(move || {
+                loop {
+                    let job = job_queue.lock().unwrap().recv();
+                    let mut job = match job {
+                        Ok(job) => job,
+                        // The pool is gone.
+                        Err(_) => break,
+                    };
+
+                    // A panicking job drops its result sender, which
+                    // the future reports as an error.  Keep the
+                    // worker alive.
+                    let _ = panic::catch_unwind(
+                        panic::AssertUnwindSafe(|| job()));
+                }
+            });
+        }
+
+        WorkerPool {
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    /// Queues `f`, and returns a receiver for its result.
+    fn run<T, F>(&self, f: F) -> oneshot::Receiver<T>
+        where T: Send + 'static, F: FnOnce() -> T + Send + 'static
+    {
+        let (sender, receiver) = oneshot::channel();
+        let mut job = Some(move || {
+            // If the future was dropped, nobody is interested in
+            // the result.
+            let _ = sender.send(f());
+        });
+        self.jobs.lock().unwrap()
+            .send(Box::new(move || if let Some(f) = job.take() { f() }))
+            .expect("workers are alive as long as the pool");
+        receiver
+    }
+}
+
+#[cfg(feature = "async")]
+lazy_static! {
+    /// The worker pool used by `AsyncVerifier` and `AsyncDecryptor`.
+    static ref WORKER_POOL: WorkerPool = WorkerPool::new(num_cpus::get());
+}
+
+/// Drives `AsyncVerifier` and `AsyncDecryptor`.
+///
+/// The message is read from the `AsyncRead`er without blocking, and
+/// buffered in memory, up to `limit` bytes.  Only once the whole
+/// message has been read, it is processed on the shared
+/// `WorkerPool`, so that the executor does not stall while the
+/// message is parsed, decrypted, and the signatures are verified.
+#[cfg(feature = "async")]
+struct AsyncProcessor<R: AsyncRead, H> {
+    state: AsyncState<R, H>,
+    limit: usize,
+    time: time::Tm,
+    process: Process<H>,
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead, H: Send + 'static> AsyncProcessor<R, H> {
+    fn new(reader: R, helper: H, time: time::Tm, process: Process<H>)
+           -> Self {
+        AsyncProcessor {
+            state: AsyncState::Reading(
+                AsyncReader::with_cookie(reader, Default::default()),
+                helper),
+            limit: BUFFER_SIZE,
+            time: time,
+            process: process,
+        }
+    }
+
+    fn poll(&mut self) -> Poll<Processed<H>, failure::Error> {
+        loop {
+            let next = match self.state {
+                AsyncState::Reading(ref mut reader, _) => {
+                    // Read whatever is available, one chunk at a
+                    // time, checking the limit as we go.
+                    loop {
+                        let buffered = reader.buffer().len();
+                        if buffered > self.limit {
+                            return Err(Error::ResourceLimitExceeded(
+                                format!("Message exceeds the buffer limit \
+                                         of {} bytes", self.limit)).into());
+                        }
+                        if reader.saw_eof() {
+                            break;
+                        }
+                        if let Async::NotReady =
+                            reader.poll_fill(buffered + 1)?
+                        {
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                    None
+                },
+                AsyncState::Processing(ref mut receiver) =>
+                    match receiver.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(result)) => Some(result),
+                        Err(oneshot::Canceled) =>
+                            return Err(Error::InvalidOperation(
+                                "Worker panicked while processing the message"
+                                    .into()).into()),
+                    },
+                AsyncState::Done =>
+                    panic!("future polled after completion"),
+            };
+
+            match ::std::mem::replace(&mut self.state, AsyncState::Done) {
+                AsyncState::Reading(reader, helper) => {
+                    let message = reader.into_buffer();
+                    let (process, time) = (self.process, self.time);
+                    self.state = AsyncState::Processing(
+                        WORKER_POOL.run(
+                            move || process(message, helper, time)));
+                },
+                AsyncState::Processing(_) =>
+                    return next.unwrap().map(Async::Ready),
+                AsyncState::Done => unreachable!(),
+            }
+        }
+    }
+}
+
+/// A future that verifies a message read from an `AsyncRead`er.
+///
+/// This is returned by [`Verifier::from_async_reader`].
+///
+/// **The whole message is buffered in memory.**  Unlike the
+/// `Verifier`, which processes the message in constant space, this
+/// future reads the complete message from the `AsyncRead`er before
+/// parsing it, because the `PacketParser` cannot resume after the
+/// reader signaled that it would block.  Messages larger than the
+/// buffer limit are rejected as soon as the limit is exceeded.  By
+/// default, the limit is 25 megabytes, the amount of data the
+/// `Verifier` buffers before verifying the signatures.  Use
+/// [`buffer_limit`] to change it.
+///
+/// Reading the message does not block.  Once it has been read, it
+/// is handed to the usual `Verifier`, which runs on a pool of worker
+/// threads shared by all `AsyncVerifier`s and `AsyncDecryptor`s, so
+/// that parsing the message and verifying the signatures does not
+/// stall the executor.  The pool has one thread per CPU; if more
+/// messages are ready, they wait for a free worker.  The future
+/// resolves to the verified content.
+///
+/// [`Verifier::from_async_reader`]: struct.Verifier.html#method.from_async_reader
+/// [`buffer_limit`]: #method.buffer_limit
+#[cfg(feature = "async")]
+pub struct AsyncVerifier<R: AsyncRead, H: VerificationHelper>(
+    AsyncProcessor<R, H>);
+
+#[cfg(feature = "async")]
+impl<H: VerificationHelper + Send + 'static> Verifier<'static, H> {
+    /// Creates a future that reads the message from the given
+    /// `AsyncRead`er, and resolves to the verified content.
+    ///
+    /// The whole message is buffered in memory before it is
+    /// verified.  See [`AsyncVerifier`] for details.
+    ///
+    /// Signature verifications are done relative to time `t`, or the
+    /// current time, if `t` is `None`.
+    ///
+    /// [`AsyncVerifier`]: struct.AsyncVerifier.html
+    pub fn from_async_reader<R, T>(reader: R, helper: H, t: T)
+                                   -> AsyncVerifier<R, H>
+        where R: AsyncRead + 'static, T: Into<Option<time::Tm>>
+    {
+        AsyncVerifier(AsyncProcessor::new(
+            reader, helper, t.into().unwrap_or_else(time::now_utc),
+            Self::process))
+    }
+
+    /// Verifies the buffered message.
+    fn process(message: Vec<u8>, helper: H, t: time::Tm)
+               -> Result<Processed<H>> {
+        let mut v = Verifier::from_bytes(&message, helper, t)?;
+        let mut content = Vec::new();
+        v.read_to_end(&mut content)?;
+        Ok(Processed { helper: v.into_helper(), content: content })
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R, H> AsyncVerifier<R, H>
+    where R: AsyncRead, H: VerificationHelper
+{
+    /// Sets the maximum size of the message in bytes.
+    ///
+    /// As the whole message is buffered, this bounds the memory
+    /// used by this future.
+    pub fn buffer_limit(mut self, limit: usize) -> Self {
+        self.0.limit = limit;
+        self
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R, H> Future for AsyncVerifier<R, H>
+    where R: AsyncRead + 'static, H: VerificationHelper + Send + 'static
+{
+    type Item = Processed<H>;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+
+/// A future that decrypts and verifies a message read from an
+/// `AsyncRead`er.
+///
+/// This is returned by [`Decryptor::from_async_reader`].
+///
+/// **The whole message is buffered in memory**, up to the buffer
+/// limit, which defaults to 25 megabytes.  Once it has been read
+/// without blocking, it is handed to the usual `Decryptor` on the
+/// shared pool of worker threads.  The future resolves to the
+/// decrypted and verified content.
+///
+/// See [`AsyncVerifier`] for why the message is buffered
+/// completely, and how the worker pool is used.
+///
+/// [`Decryptor::from_async_reader`]: struct.Decryptor.html#method.from_async_reader
+/// [`AsyncVerifier`]: struct.AsyncVerifier.html
+#[cfg(feature = "async")]
+pub struct AsyncDecryptor<R, H>(AsyncProcessor<R, H>)
+    where R: AsyncRead, H: VerificationHelper + DecryptionHelper;
+
+#[cfg(feature = "async")]
+impl<H> Decryptor<'static, H>
+    where H: VerificationHelper + DecryptionHelper + Send + 'static
+{
+    /// Creates a future that reads the message from the given
+    /// `AsyncRead`er, and resolves to the decrypted and verified
+    /// content.
+    ///
+    /// The whole message is buffered in memory before it is
+    /// decrypted.  See [`AsyncDecryptor`] for details.
+    ///
+    /// Signature verifications are done relative to time `t`, or the
+    /// current time, if `t` is `None`.
+    ///
+    /// [`AsyncDecryptor`]: struct.AsyncDecryptor.html
+    pub fn from_async_reader<R, T>(reader: R, helper: H, t: T)
+                                   -> AsyncDecryptor<R, H>
+        where R: AsyncRead + 'static, T: Into<Option<time::Tm>>
+    {
+        AsyncDecryptor(AsyncProcessor::new(
+            reader, helper, t.into().unwrap_or_else(time::now_utc),
+            Self::process))
+    }
+
+    /// Decrypts and verifies the buffered message.
+    fn process(message: Vec<u8>, helper: H, t: time::Tm)
+               -> Result<Processed<H>> {
+        let mut v = Decryptor::from_bytes(&message, helper, t)?;
+        let mut content = Vec::new();
+        v.read_to_end(&mut content)?;
+        Ok(Processed { helper: v.into_helper(), content: content })
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R, H> AsyncDecryptor<R, H>
+    where R: AsyncRead, H: VerificationHelper + DecryptionHelper
+{
+    /// Sets the maximum size of the message in bytes.
+    ///
+    /// As the whole message is buffered, this bounds the memory
+    /// used by this future.
+    pub fn buffer_limit(mut self, limit: usize) -> Self {
+        self.0.limit = limit;
+        self
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R, H> Future for AsyncDecryptor<R, H>
+    where R: AsyncRead + 'static,
+          H: VerificationHelper + DecryptionHelper + Send + 'static
+{
+    type Item = Processed<H>;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use failure;
@@ -1693,6 +2153,37 @@ mod test {
 
     /// Tests the order of signatures given to
     /// VerificationHelper::check().
+    #[cfg(feature = "compression-deflate")]
+    #[test]
+    fn decompression_limits() {
+        struct VHelper(Option<u64>);
+        impl VerificationHelper for VHelper {
+            fn get_public_keys(&mut self, _ids: &[KeyID]) -> Result<Vec<TPK>> {
+                Ok(Vec::new())
+            }
+
+            fn max_decompressed_size(&self) -> Option<u64> {
+                self.0
+            }
+
+            fn check(&mut self, _: &MessageStructure) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        // This compresses 16 MiB of zeros.
+        let msg = ::tests::message("compression-bomb-zip.pgp");
+        let read = |limit| -> Result<usize> {
+            let mut v = Verifier::from_bytes(msg, VHelper(limit), None)?;
+            let mut content = Vec::new();
+            v.read_to_end(&mut content)?;
+            Ok(content.len())
+        };
+
+        assert_eq!(read(None).unwrap(), 16 * 1024 * 1024);
+        assert!(read(Some(1024 * 1024)).is_err());
+    }
+
     #[test]
     fn verifier_levels() {
         struct VHelper(());
@@ -1917,4 +2408,154 @@ mod test {
         assert!(v.helper_ref().unknown == 0);
         assert!(v.helper_ref().error == 0);
     }
+
+    #[test]
+    fn decrypt_legacy_sed() {
+        struct Helper {
+            allow_legacy_sed: bool,
+            integrity_protected: Option<bool>,
+        }
+        impl VerificationHelper for Helper {
+            fn get_public_keys(&mut self, _ids: &[KeyID]) -> Result<Vec<TPK>> {
+                Ok(Vec::new())
+            }
+            fn check(&mut self, structure: &MessageStructure) -> Result<()> {
+                for layer in structure.iter() {
+                    if let MessageLayer::Encryption {
+                        integrity_protected, ..
+                    } = layer {
+                        self.integrity_protected = Some(*integrity_protected);
+                    }
+                }
+                Ok(())
+            }
+        }
+        impl DecryptionHelper for Helper {
+            fn allow_legacy_sed(&self) -> bool {
+                self.allow_legacy_sed
+            }
+            fn decrypt<D>(&mut self, _: &[PKESK], skesks: &[SKESK],
+                          mut decrypt: D) -> Result<Option<Fingerprint>>
+                where D: FnMut(SymmetricAlgorithm, &SessionKey) -> Result<()>
+            {
+                skesks[0].decrypt(&"123".into())
+                    .and_then(|(algo, session_key)| decrypt(algo, &session_key))
+                    .map(|_| None)
+            }
+        }
+
+        for f in ["encrypted-sed-aes128-password-123.gpg",
+                  "encrypted-sed-cast5-password-123.gpg"].iter() {
+            // By default, SED packets are rejected.
+            let h = Helper {
+                allow_legacy_sed: false,
+                integrity_protected: None,
+            };
+            assert!(Decryptor::from_bytes(::tests::message(f), h, None)
+                    .is_err());
+
+            // But the caller may opt in.
+            let h = Helper {
+                allow_legacy_sed: true,
+                integrity_protected: None,
+            };
+            let mut v =
+                Decryptor::from_bytes(::tests::message(f), h, None).unwrap();
+            let mut content = Vec::new();
+            v.read_to_end(&mut content).unwrap();
+            assert_eq!(&content[..], ::tests::manifesto());
+            assert_eq!(v.helper_ref().integrity_protected, Some(false));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_verifier() {
+        use std::sync::Arc;
+        use futures::executor::{self, Notify};
+
+        // Returns the data in small chunks, and signals WouldBlock
+        // before each chunk.
+        struct Trickle {
+            data: &'static [u8],
+            ready: bool,
+        }
+
+        impl io::Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if ! self.ready {
+                    self.ready = true;
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock,
+                                              "not ready"));
+                }
+                self.ready = false;
+
+                let n = cmp::min(cmp::min(buf.len(), 100), self.data.len());
+                buf[..n].copy_from_slice(&self.data[..n]);
+                self.data = &self.data[n..];
+                Ok(n)
+            }
+        }
+
+        impl AsyncRead for Trickle {
+        }
+
+        struct Noop;
+        impl Notify for Noop {
+            fn notify(&self, _: usize) {}
+        }
+
+        // Polls the future until it is ready.
+        fn run<F: Future>(f: F) -> ::std::result::Result<F::Item, F::Error> {
+            let notify = Arc::new(Noop);
+            let mut task = executor::spawn(f);
+            loop {
+                if let Async::Ready(v) = task.poll_future_notify(&notify, 0)? {
+                    return Ok(v);
+                }
+            }
+        }
+
+        let keys = [
+            "neal.pgp",
+            "emmelie-dorothea-dina-samantha-awina-ed25519.pgp"
+        ].iter()
+         .map(|f| TPK::from_bytes(::tests::key(f)).unwrap())
+         .collect::<Vec<_>>();
+        let reference = ::tests::manifesto();
+        let f = "messages/signed-1-notarized-by-ed25519.pgp";
+        let r = VHelper::new(2, 0, 0, 0, keys.clone());
+
+        // Test Verifier.
+        let h = VHelper::new(0, 0, 0, 0, keys.clone());
+        let reader = Trickle { data: ::tests::file(f), ready: false };
+        let v = run(Verifier::from_async_reader(reader, h, ::frozen_time()))
+            .unwrap();
+        assert_eq!(v.helper_ref(), &r);
+        assert_eq!(reference, v.content());
+
+        // Test Decryptor.
+        let h = VHelper::new(0, 0, 0, 0, keys.clone());
+        let reader = Trickle { data: ::tests::file(f), ready: false };
+        let v = run(Decryptor::from_async_reader(reader, h, ::frozen_time()))
+            .unwrap();
+        assert_eq!(v.helper_ref(), &r);
+        assert_eq!(reference, &v.into_content()[..]);
+
+        // Errors are propagated.
+        let h = VHelper::new(0, 0, 0, 0, keys.clone());
+        let reader = Trickle { data: ::tests::file("keys/neal.pgp"),
+                               ready: false };
+        assert!(run(Verifier::from_async_reader(reader, h, ::frozen_time()))
+                .is_err());
+
+        // Messages exceeding the limit are rejected while reading.
+        let h = VHelper::new(0, 0, 0, 0, keys.clone());
+        let reader = Trickle { data: ::tests::file(f), ready: false };
+        let e = run(Verifier::from_async_reader(reader, h, ::frozen_time())
+                    .buffer_limit(200))
+            .unwrap_err();
+        assert_match!(Error::ResourceLimitExceeded(_)
+                      = e.downcast::<Error>().unwrap());
+    }
 }
@@ -0,0 +1,674 @@
+//! PGP/MIME.
+//!
+//! This module implements the composition and parsing of the
+//! `multipart/encrypted` and `multipart/signed` MIME structures
+//! defined in [RFC 3156].
+//!
+//! [RFC 3156]: https://tools.ietf.org/html/rfc3156
+//!
+//! # Scope
+//!
+//! This is not a general purpose MIME library.  The caller is
+//! responsible for assembling the MIME part that is to be protected,
+//! and for interpreting the MIME part that is recovered.  This module
+//! only deals with the outermost PGP/MIME layer.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate sequoia_openpgp as openpgp;
+//! # use std::io::Read;
+//! # use openpgp::{Result, TPK};
+//! # use openpgp::parse::Parse;
+//! # use openpgp::parse::stream::*;
+//! use openpgp::pgp_mime::{self, Entity};
+//!
+//! # fn main() { f().unwrap(); }
+//! # fn f() -> Result<()> {
+//! # let tsk = TPK::from_bytes(include_bytes!(
+//! #     "../tests/data/keys/testy-new-private.pgp"))?;
+//! # let mut keypair = tsk.keys_valid().signing_capable().nth(0).unwrap().2
+//! #     .clone().into_keypair()?;
+//! let part = b"Content-Type: text/plain\n\nHello World!\n";
+//!
+//! let mut message = Vec::new();
+//! pgp_mime::sign(&mut message, part, vec![&mut keypair], None)?;
+//!
+//! if let Entity::Signed(signed) = pgp_mime::parse(&message)? {
+//!     // Note: the signed part is in canonical form.
+//!     assert_eq!(signed.content(),
+//!                &b"Content-Type: text/plain\r\n\r\nHello World!\r\n"[..]);
+//! #   struct Helper<'a>(&'a TPK);
+//! #   impl<'a> VerificationHelper for Helper<'a> {
+//! #       fn get_public_keys(&mut self, _: &[openpgp::KeyID])
+//! #                          -> Result<Vec<TPK>> {
+//! #           Ok(vec![self.0.clone()])
+//! #       }
+//! #       fn check(&mut self, _: &MessageStructure) -> Result<()> {
+//! #           Ok(())
+//! #       }
+//! #   }
+//! #   let helper = Helper(&tsk);
+//!     let mut verifier = signed.verifier(helper)?;
+//!     let mut content = Vec::new();
+//!     verifier.read_to_end(&mut content)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{self, Write};
+use std::str;
+
+use nettle::{Random, Yarrow};
+
+use armor;
+use constants::{DataFormat, HashAlgorithm};
+use crypto::{self, Password};
+use Error;
+use Packet;
+use PacketPile;
+use Result;
+use TPK;
+use parse::Parse;
+use parse::stream::{
+    Decryptor, DecryptionHelper, DetachedVerifier, Verifier,
+    VerificationHelper,
+};
+use serialize::stream::{
+    EncryptionMode, Encryptor, LiteralWriter, Message, Signer,
+};
+
+/// The line ending used in generated MIME structures.
+const CRLF: &str = "\r\n";
+
+/// Converts `data` to canonical form.
+///
+/// MIME parts must be signed in canonical form, i.e., with all line
+/// endings converted to CRLF.  See [Section 5 of RFC 3156].
+///
+///   [Section 5 of RFC 3156]: https://tools.ietf.org/html/rfc3156#section-5
+pub fn canonicalize(data: &[u8]) -> Vec<u8> {
+    let mut canonical = Vec::with_capacity(data.len());
+    let mut previous = None;
+    for &b in data {
+        if b == b'\n' && previous != Some(b'\r') {
+            canonical.push(b'\r');
+        }
+        canonical.push(b);
+        previous = Some(b);
+    }
+    canonical
+}
+
+/// Returns the value of the `micalg` parameter for `algo`.
+///
+/// See [Section 5 of RFC 3156].
+///
+///   [Section 5 of RFC 3156]: https://tools.ietf.org/html/rfc3156#section-5
+pub fn micalg(algo: HashAlgorithm) -> Result<&'static str> {
+    match algo {
+        HashAlgorithm::MD5 => Ok("pgp-md5"),
+        HashAlgorithm::SHA1 => Ok("pgp-sha1"),
+        HashAlgorithm::RipeMD => Ok("pgp-ripemd160"),
+        HashAlgorithm::SHA256 => Ok("pgp-sha256"),
+        HashAlgorithm::SHA384 => Ok("pgp-sha384"),
+        HashAlgorithm::SHA512 => Ok("pgp-sha512"),
+        HashAlgorithm::SHA224 => Ok("pgp-sha224"),
+        a => Err(Error::UnsupportedHashAlgorithm(a).into()),
+    }
+}
+
+/// Returns the hash algorithm named by the `micalg` parameter.
+///
+/// The parameter is case-insensitive.
+pub fn hash_algo_from_micalg(micalg: &str) -> Result<HashAlgorithm> {
+    match &micalg.to_lowercase()[..] {
+        "pgp-md5" => Ok(HashAlgorithm::MD5),
+        "pgp-sha1" => Ok(HashAlgorithm::SHA1),
+        "pgp-ripemd160" => Ok(HashAlgorithm::RipeMD),
+        "pgp-sha256" => Ok(HashAlgorithm::SHA256),
+        "pgp-sha384" => Ok(HashAlgorithm::SHA384),
+        "pgp-sha512" => Ok(HashAlgorithm::SHA512),
+        "pgp-sha224" => Ok(HashAlgorithm::SHA224),
+        _ => Err(Error::InvalidArgument(
+            format!("Unknown micalg: {:?}", micalg)).into()),
+    }
+}
+
+/// Generates a random boundary.
+fn boundary() -> String {
+    let mut rng = Yarrow::default();
+    let mut nonce = [0; 12];
+    rng.random(&mut nonce);
+    format!("=-={}=-=", ::conversions::to_hex(&nonce, false))
+}
+
+/// Encrypts `part` and writes a `multipart/encrypted` entity to
+/// `sink`.
+///
+/// `part` is the MIME part to encrypt, including its headers.  The
+/// entity's `Content-Type` header is written, but any other headers
+/// (e.g. `From` or `Subject`) must be written by the caller.  See
+/// [Section 4 of RFC 3156].
+///
+///   [Section 4 of RFC 3156]: https://tools.ietf.org/html/rfc3156#section-4
+pub fn encrypt<W: io::Write>(sink: &mut W, part: &[u8],
+                             passwords: &[&Password], recipients: &[&TPK])
+                             -> Result<()> {
+    let boundary = boundary();
+    write!(sink, "Content-Type: multipart/encrypted; \
+                  protocol=\"application/pgp-encrypted\";{}\
+                  \tboundary=\"{}\"{}{}",
+           CRLF, boundary, CRLF, CRLF)?;
+    write!(sink, "This is an OpenPGP/MIME encrypted message \
+                  (RFC 4880 and 3156){}", CRLF)?;
+    write!(sink, "--{}{}", boundary, CRLF)?;
+    write!(sink, "Content-Type: application/pgp-encrypted{}", CRLF)?;
+    write!(sink, "Content-Description: PGP/MIME version identification{}",
+           CRLF)?;
+    write!(sink, "{}Version: 1{}{}", CRLF, CRLF, CRLF)?;
+    write!(sink, "--{}{}", boundary, CRLF)?;
+    write!(sink, "Content-Type: application/octet-stream; \
+                  name=\"encrypted.asc\"{}", CRLF)?;
+    write!(sink, "Content-Description: OpenPGP encrypted message{}", CRLF)?;
+    write!(sink, "Content-Disposition: inline; \
+                  filename=\"encrypted.asc\"{}{}", CRLF, CRLF)?;
+
+    {
+        let w = armor::Writer::new(&mut *sink, armor::Kind::Message, &[])?;
+        let m = Message::new(w);
+        let w = Encryptor::new(m, passwords, recipients,
+                               EncryptionMode::ForTransport, None)?;
+        let mut w = LiteralWriter::new(w, DataFormat::Binary, None, None)?;
+        w.write_all(&canonicalize(part))?;
+        w.finalize()?;
+    }
+
+    write!(sink, "{}--{}--{}", CRLF, boundary, CRLF)?;
+    Ok(())
+}
+
+/// Signs `part` and writes a `multipart/signed` entity to `sink`.
+///
+/// `part` is the MIME part to sign, including its headers.  It is
+/// converted to canonical form before it is signed (see
+/// `canonicalize`), and is included in canonical form.  The `micalg`
+/// parameter is derived from the hash algorithm of the generated
+/// signatures.  The entity's `Content-Type` header is written, but
+/// any other headers must be written by the caller.  See [Section 5
+/// of RFC 3156].
+///
+///   [Section 5 of RFC 3156]: https://tools.ietf.org/html/rfc3156#section-5
+pub fn sign<W, H>(sink: &mut W, part: &[u8],
+                  mut signers: Vec<&mut dyn crypto::Signer>, hash_algo: H)
+                  -> Result<()>
+    where W: io::Write, H: Into<Option<HashAlgorithm>>
+{
+    let part = canonicalize(part);
+
+    // Create the detached signature.
+    let mut signature = Vec::new();
+    {
+        let signers = signers.iter_mut()
+            .map(|s| &mut **s as &mut dyn crypto::Signer)
+            .collect();
+        let m = Message::new(&mut signature);
+        let mut w = Signer::detached(m, signers, hash_algo)?;
+        w.write_all(&part)?;
+        w.finalize()?;
+    }
+
+    // Derive micalg from the signatures.
+    let mut hash_algo = None;
+    for p in PacketPile::from_bytes(&signature)?.children() {
+        if let &Packet::Signature(ref sig) = p {
+            if hash_algo.is_some() && hash_algo != Some(sig.hash_algo()) {
+                return Err(Error::InvalidOperation(
+                    "Signatures use different hash algorithms".into())
+                           .into());
+            }
+            hash_algo = Some(sig.hash_algo());
+        }
+    }
+    let micalg = micalg(hash_algo.ok_or_else(|| Error::InvalidOperation(
+        "No signature was generated".into()))?)?;
+
+    let boundary = boundary();
+    write!(sink, "Content-Type: multipart/signed; micalg={};{}\
+                  \tprotocol=\"application/pgp-signature\";{}\
+                  \tboundary=\"{}\"{}{}",
+           micalg, CRLF, CRLF, boundary, CRLF, CRLF)?;
+    write!(sink, "This is an OpenPGP/MIME signed message \
+                  (RFC 4880 and 3156){}", CRLF)?;
+    write!(sink, "--{}{}", boundary, CRLF)?;
+    sink.write_all(&part)?;
+    write!(sink, "{}--{}{}", CRLF, boundary, CRLF)?;
+    write!(sink, "Content-Type: application/pgp-signature; \
+                  name=\"signature.asc\"{}", CRLF)?;
+    write!(sink, "Content-Description: OpenPGP digital signature{}", CRLF)?;
+    write!(sink, "Content-Disposition: attachment; \
+                  filename=\"signature.asc\"{}{}", CRLF, CRLF)?;
+    {
+        let mut w = armor::Writer::new(&mut *sink, armor::Kind::Signature,
+                                       &[])?;
+        w.write_all(&signature)?;
+        w.finalize()?;
+    }
+    write!(sink, "{}--{}--{}", CRLF, boundary, CRLF)?;
+    Ok(())
+}
+
+/// A parsed PGP/MIME entity.
+#[derive(Debug)]
+pub enum Entity {
+    /// A `multipart/encrypted` entity.
+    Encrypted(Encrypted),
+    /// A `multipart/signed` entity.
+    Signed(Signed),
+}
+
+/// A parsed `multipart/encrypted` entity.
+#[derive(Debug)]
+pub struct Encrypted {
+    ciphertext: Vec<u8>,
+}
+
+impl Encrypted {
+    /// Returns the encrypted OpenPGP message.
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+
+    /// Returns a `Decryptor` for the encrypted OpenPGP message.
+    ///
+    /// The decrypted data is the protected MIME part.
+    pub fn decryptor<'a, H>(&'a self, helper: H) -> Result<Decryptor<'a, H>>
+        where H: VerificationHelper + DecryptionHelper
+    {
+        Decryptor::from_bytes(&self.ciphertext, helper, None)
+    }
+}
+
+/// A parsed `multipart/signed` entity.
+#[derive(Debug)]
+pub struct Signed {
+    micalg: Option<HashAlgorithm>,
+    content: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Signed {
+    /// Returns the hash algorithm named by the `micalg` parameter.
+    ///
+    /// Returns `None` if the parameter is missing or unknown.
+    pub fn micalg(&self) -> Option<HashAlgorithm> {
+        self.micalg
+    }
+
+    /// Returns the signed MIME part in canonical form.
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Returns the detached signature.
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Returns a `Verifier` for the signed MIME part.
+    ///
+    /// The verifier returns the signed MIME part in canonical form.
+    pub fn verifier<'a, H>(&'a self, helper: H) -> Result<Verifier<'a, H>>
+        where H: VerificationHelper
+    {
+        DetachedVerifier::from_bytes(&self.signature, &self.content,
+                                     helper, None)
+    }
+}
+
+/// Parses a PGP/MIME entity.
+///
+/// `entity` must start with the entity's headers, which must include
+/// a `Content-Type` header.  Usually, this is the whole mail.  If the
+/// entity is neither a `multipart/encrypted` nor a `multipart/signed`
+/// entity using the OpenPGP protocol, `Error::MalformedMessage` is
+/// returned.
+pub fn parse(entity: &[u8]) -> Result<Entity> {
+    let (headers, body) = split_headers(entity)?;
+    let (content_type, parameters) = content_type(&headers)?;
+    let parameter = |name: &str| parameters.iter()
+        .find(|&&(ref k, _)| k == name)
+        .map(|&(_, ref v)| &v[..]);
+    let protocol = parameter("protocol").map(|p| p.to_lowercase());
+    let boundary = parameter("boundary").ok_or_else(|| Error::MalformedMessage(
+        "Missing boundary parameter".into()))?;
+
+    match (&content_type[..], protocol.as_ref().map(|p| &p[..])) {
+        ("multipart/encrypted", Some("application/pgp-encrypted")) => {
+            let parts = split_multipart(body, boundary)?;
+            if parts.len() != 2 {
+                return Err(Error::MalformedMessage(
+                    format!("Expected two parts, found {}", parts.len()))
+                           .into());
+            }
+
+            let (headers, control) = split_headers(parts[0])?;
+            if content_type(&headers)?.0 != "application/pgp-encrypted" {
+                return Err(Error::MalformedMessage(
+                    "First part must be application/pgp-encrypted".into())
+                           .into());
+            }
+            if ! str::from_utf8(control).map(|c| c.lines()
+                                             .any(|l| l.trim() == "Version: 1"))
+                .unwrap_or(false)
+            {
+                return Err(Error::MalformedMessage(
+                    "Missing or unsupported version".into()).into());
+            }
+
+            let (headers, ciphertext) = split_headers(parts[1])?;
+            if content_type(&headers)?.0 != "application/octet-stream" {
+                return Err(Error::MalformedMessage(
+                    "Second part must be application/octet-stream".into())
+                           .into());
+            }
+
+            Ok(Entity::Encrypted(Encrypted {
+                ciphertext: ciphertext.to_vec(),
+            }))
+        },
+        ("multipart/signed", Some("application/pgp-signature")) => {
+            let parts = split_multipart(body, boundary)?;
+            if parts.len() != 2 {
+                return Err(Error::MalformedMessage(
+                    format!("Expected two parts, found {}", parts.len()))
+                           .into());
+            }
+
+            let (headers, signature) = split_headers(parts[1])?;
+            if content_type(&headers)?.0 != "application/pgp-signature" {
+                return Err(Error::MalformedMessage(
+                    "Second part must be application/pgp-signature".into())
+                           .into());
+            }
+
+            Ok(Entity::Signed(Signed {
+                micalg: parameter("micalg")
+                    .and_then(|m| hash_algo_from_micalg(m).ok()),
+                content: canonicalize(parts[0]),
+                signature: signature.to_vec(),
+            }))
+        },
+        (t, p) => Err(Error::MalformedMessage(
+            format!("Not a PGP/MIME entity: {} (protocol: {:?})", t, p))
+                      .into()),
+    }
+}
+
+/// Returns the line starting at `pos`, including the line ending.
+fn line_at(data: &[u8], pos: usize) -> &[u8] {
+    let end = data[pos..].iter().position(|&b| b == b'\n')
+        .map(|i| pos + i + 1).unwrap_or(data.len());
+    &data[pos..end]
+}
+
+/// Strips the line ending from `line`.
+fn chomp(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    if end > 0 && line[end - 1] == b'\n' {
+        end -= 1;
+        if end > 0 && line[end - 1] == b'\r' {
+            end -= 1;
+        }
+    }
+    &line[..end]
+}
+
+/// Splits a MIME entity into its (unfolded) headers and its body.
+fn split_headers(entity: &[u8]) -> Result<(Vec<(String, String)>, &[u8])> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut pos = 0;
+    while pos < entity.len() {
+        let line = line_at(entity, pos);
+        pos += line.len();
+
+        let line = str::from_utf8(chomp(line)).map_err(
+            |_| Error::MalformedMessage("Header is not UTF-8".into()))?;
+        if line == "" {
+            // End of headers.
+            return Ok((headers, &entity[pos..]));
+        }
+
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // Folded header.
+            match headers.last_mut() {
+                Some(&mut (_, ref mut value)) => value.push_str(line),
+                None => return Err(Error::MalformedMessage(
+                    "Continuation line without header".into()).into()),
+            }
+        } else if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_lowercase(),
+                          line[i + 1..].trim().to_string()));
+        } else {
+            return Err(Error::MalformedMessage(
+                format!("Malformed header: {:?}", line)).into());
+        }
+    }
+
+    // No body.
+    Ok((headers, &entity[entity.len()..]))
+}
+
+/// Returns the MIME type and parameters of the `Content-Type` header.
+///
+/// The type and the parameter names are lowercased.  The default
+/// type is `text/plain`.
+fn content_type(headers: &[(String, String)])
+                -> Result<(String, Vec<(String, String)>)> {
+    let value = match headers.iter().find(|&&(ref k, _)| k == "content-type") {
+        Some(&(_, ref v)) => v,
+        None => return Ok(("text/plain".into(), Vec::new())),
+    };
+
+    // Split at semicolons that are not quoted.
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            field.push(c);
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = ! quoted;
+        } else if c == ';' && ! quoted {
+            fields.push(field.trim().to_string());
+            field.clear();
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field.trim().to_string());
+
+    let mut fields = fields.into_iter();
+    let mime_type = fields.next().expect("at least one field").to_lowercase();
+    let parameters = fields.filter_map(|f| {
+        let i = f.find('=')?;
+        Some((f[..i].trim().to_lowercase(), f[i + 1..].trim().to_string()))
+    }).collect();
+
+    Ok((mime_type, parameters))
+}
+
+/// Splits the body of a multipart entity into its parts.
+///
+/// The line ending preceding a delimiter belongs to the delimiter,
+/// hence it is not included in the parts.  See [Section 5.1.1 of
+/// RFC 2046].
+///
+///   [Section 5.1.1 of RFC 2046]: https://tools.ietf.org/html/rfc2046#section-5.1.1
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Result<Vec<&'a [u8]>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let line = line_at(body, pos);
+        let end = pos + line.len();
+
+        if line.starts_with(&delimiter) {
+            let rest = chomp(&line[delimiter.len()..]);
+            let close = rest.starts_with(b"--");
+            if close || rest.iter().all(|&b| b == b' ' || b == b'\t') {
+                if let Some(start) = start {
+                    let mut part_end = pos;
+                    if part_end > start && body[part_end - 1] == b'\n' {
+                        part_end -= 1;
+                        if part_end > start && body[part_end - 1] == b'\r' {
+                            part_end -= 1;
+                        }
+                    }
+                    parts.push(&body[start..part_end]);
+                }
+
+                if close {
+                    return Ok(parts);
+                }
+                start = Some(end);
+            }
+        }
+
+        pos = end;
+    }
+
+    Err(Error::MalformedMessage("Missing close delimiter".into()).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use crypto::SessionKey;
+    use constants::SymmetricAlgorithm;
+    use packet::{PKESK, SKESK};
+    use parse::stream::MessageStructure;
+    use {Fingerprint, KeyID};
+
+    struct Helper(TPK);
+
+    impl VerificationHelper for Helper {
+        fn get_public_keys(&mut self, _: &[KeyID]) -> Result<Vec<TPK>> {
+            Ok(vec![self.0.clone()])
+        }
+
+        fn check(&mut self, _: &MessageStructure) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl DecryptionHelper for Helper {
+        fn decrypt<D>(&mut self, _: &[PKESK], skesks: &[SKESK],
+                      mut decrypt: D) -> Result<Option<Fingerprint>>
+            where D: FnMut(SymmetricAlgorithm, &SessionKey) -> Result<()>
+        {
+            skesks[0].decrypt(&"streng geheim".into())
+                .and_then(|(algo, session_key)| decrypt(algo, &session_key))
+                .map(|_| None)
+        }
+    }
+
+    const PART: &[u8] = b"Content-Type: text/plain\n\nHello World!\n";
+
+    #[test]
+    fn canonical_form() {
+        assert_eq!(canonicalize(b"a\nb\r\nc\rd\n\n"), b"a\r\nb\r\nc\rd\r\n\r\n");
+        assert_eq!(canonicalize(b""), b"");
+    }
+
+    #[test]
+    fn micalg_roundtrip() {
+        for &a in &[HashAlgorithm::MD5, HashAlgorithm::SHA1,
+                    HashAlgorithm::RipeMD, HashAlgorithm::SHA256,
+                    HashAlgorithm::SHA384, HashAlgorithm::SHA512,
+                    HashAlgorithm::SHA224] {
+            assert_eq!(hash_algo_from_micalg(micalg(a).unwrap()).unwrap(), a);
+        }
+        assert_eq!(hash_algo_from_micalg("PGP-SHA256").unwrap(),
+                   HashAlgorithm::SHA256);
+        assert!(micalg(HashAlgorithm::Unknown(100)).is_err());
+        assert!(hash_algo_from_micalg("sha-256").is_err());
+    }
+
+    #[test]
+    fn signed() {
+        let tsk = TPK::from_bytes(::tests::key("testy-new-private.pgp"))
+            .unwrap();
+        let mut keypair = tsk.keys_valid().signing_capable().nth(0).unwrap().2
+            .clone().into_keypair().unwrap();
+
+        let mut message = Vec::new();
+        sign(&mut message, PART, vec![&mut keypair], HashAlgorithm::SHA256)
+            .unwrap();
+        assert!(message.starts_with(
+            b"Content-Type: multipart/signed; micalg=pgp-sha256;"));
+
+        let signed = match parse(&message).unwrap() {
+            Entity::Signed(s) => s,
+            e => panic!("Expected a signed entity, got {:?}", e),
+        };
+        assert_eq!(signed.micalg(), Some(HashAlgorithm::SHA256));
+        assert_eq!(signed.content(), &canonicalize(PART)[..]);
+
+        let mut verifier = signed.verifier(Helper(tsk.clone())).unwrap();
+        let mut content = Vec::new();
+        verifier.read_to_end(&mut content).unwrap();
+        assert_eq!(content, canonicalize(PART));
+
+        // Converting the line endings in transit must not break the
+        // signature.
+        let message = String::from_utf8(message).unwrap()
+            .replace("\r\n", "\n");
+        let signed = match parse(message.as_bytes()).unwrap() {
+            Entity::Signed(s) => s,
+            e => panic!("Expected a signed entity, got {:?}", e),
+        };
+        assert_eq!(signed.content(), &canonicalize(PART)[..]);
+        let mut verifier = signed.verifier(Helper(tsk)).unwrap();
+        let mut content = Vec::new();
+        verifier.read_to_end(&mut content).unwrap();
+    }
+
+    #[test]
+    fn encrypted() {
+        let tpk = TPK::from_bytes(::tests::key("testy-new.pgp")).unwrap();
+
+        let mut message = Vec::new();
+        encrypt(&mut message, PART, &[&"streng geheim".into()], &[])
+            .unwrap();
+
+        let encrypted = match parse(&message).unwrap() {
+            Entity::Encrypted(e) => e,
+            e => panic!("Expected an encrypted entity, got {:?}", e),
+        };
+
+        let mut decryptor = encrypted.decryptor(Helper(tpk)).unwrap();
+        let mut content = Vec::new();
+        decryptor.read_to_end(&mut content).unwrap();
+        assert_eq!(content, canonicalize(PART));
+    }
+
+    #[test]
+    fn not_pgp_mime() {
+        assert_match!(Err(_) = parse(PART));
+        assert_match!(Err(_) = parse(b"Content-Type: multipart/signed; \
+                                       protocol=\"application/pkcs7-signature\"; \
+                                       boundary=x\r\n\r\n--x\r\n\r\n--x--\r\n"));
+        // Missing close delimiter.
+        assert_match!(Err(_) = parse(b"Content-Type: multipart/signed; \
+                                       protocol=\"application/pgp-signature\"; \
+                                       boundary=x\r\n\r\n--x\r\n\r\n--x\r\n"));
+    }
+}
@@ -21,7 +21,7 @@ pub use self::tpk::TSK;
 use self::partial_body::PartialBodyFilter;
 pub mod writer;
 pub mod stream;
-use crypto::s2k::S2K;
+use crypto::s2k::{S2K, GNUExtension};
 use packet::signature::subpacket::{
     Subpacket, SubpacketValue, SubpacketLengthTrait,
 };
@@ -438,6 +438,7 @@ impl SerializeInto for Fingerprint {
     fn serialized_len(&self) -> usize {
         match self {
             Fingerprint::V4(_) => 20,
+            Fingerprint::V5(_) => 32,
             Fingerprint::Invalid(ref fp) => fp.len(),
         }
     }
@@ -810,6 +811,20 @@ impl Serialize for S2K {
                 w.write_all(&salt[..])?;
                 w.write_all(&[S2K::encode_count(iterations)?])?;
             }
+            &S2K::Argon2{ ref salt, passes, parallelism, memory_exponent } => {
+                w.write_all(&[4])?;
+                w.write_all(&salt[..])?;
+                w.write_all(&[passes, parallelism, memory_exponent])?;
+            }
+            &S2K::GNU{ hash, ref extension } => {
+                w.write_all(&[101, hash.into()])?;
+                w.write_all(b"GNU")?;
+                w.write_all(&[extension.mode()])?;
+                if let &GNUExtension::DivertToCard { ref serial } = extension {
+                    w.write_all(&[serial.len() as u8])?;
+                    w.write_all(serial)?;
+                }
+            }
             &S2K::Private(s2k) | &S2K::Unknown(s2k) => {
                 w.write_all(&[s2k])?;
             }
@@ -825,6 +840,11 @@ impl SerializeInto for S2K {
             &S2K::Simple{ .. } => 2,
             &S2K::Salted{ .. } => 2 + 8,
             &S2K::Iterated{ .. } => 2 + 8 + 1,
+            &S2K::Argon2{ .. } => 1 + 16 + 3,
+            &S2K::GNU{ ref extension, .. } => 2 + 3 + 1 + match extension {
+                &GNUExtension::DivertToCard { ref serial } => 1 + serial.len(),
+                _ => 0,
+            },
             &S2K::Private(_) | &S2K::Unknown(_) => 1,
         }
     }
@@ -965,6 +985,10 @@ impl<'a> Serialize for SubpacketValue<'a> {
                     o.write_all(&[4])?;
                     o.write_all(fp.as_slice())?;
                 },
+                Fingerprint::V5(_) => {
+                    o.write_all(&[5])?;
+                    o.write_all(fp.as_slice())?;
+                },
                 _ => return Err(Error::InvalidArgument(
                     "Unknown kind of fingerprint".into()).into()),
             }
@@ -977,6 +1001,10 @@ impl<'a> Serialize for SubpacketValue<'a> {
                     o.write_all(&[4])?;
                     o.write_all(fp.as_slice())?;
                 },
+                Fingerprint::V5(_) => {
+                    o.write_all(&[5])?;
+                    o.write_all(fp.as_slice())?;
+                },
                 _ => return Err(Error::InvalidArgument(
                     "Unknown kind of fingerprint".into()).into()),
             }
@@ -1020,12 +1048,14 @@ impl<'a> SerializeInto for SubpacketValue<'a> {
                 _ => 0,
             },
             IssuerFingerprint(ref fp) => match fp {
-                Fingerprint::V4(_) => 1 + fp.serialized_len(),
+                Fingerprint::V4(_) | Fingerprint::V5(_) =>
+                    1 + fp.serialized_len(),
                 _ => 0,
             },
             PreferredAEADAlgorithms(ref p) => p.len(),
             IntendedRecipient(ref fp) => match fp {
-                Fingerprint::V4(_) => 1 + fp.serialized_len(),
+                Fingerprint::V4(_) | Fingerprint::V5(_) =>
+                    1 + fp.serialized_len(),
                 _ => 0,
             },
             Unknown(ref raw) => raw.len(),
@@ -1042,6 +1072,7 @@ impl Serialize for Signature {
     fn serialize(&self, o: &mut dyn std::io::Write) -> Result<()> {
         match self {
             &Signature::V4(ref s) => s.serialize(o),
+            &Signature::V5(ref s) => s.serialize(o),
         }
     }
 }
@@ -1050,12 +1081,14 @@ impl SerializeInto for Signature {
     fn serialized_len(&self) -> usize {
         match self {
             &Signature::V4(ref s) => s.serialized_len(),
+            &Signature::V5(ref s) => s.serialized_len(),
         }
     }
 
     fn serialize_into(&self, buf: &mut [u8]) -> Result<usize> {
         match self {
             &Signature::V4(ref s) => s.serialize_into(buf),
+            &Signature::V5(ref s) => s.serialize_into(buf),
         }
     }
 }
@@ -1071,7 +1104,8 @@ impl Serialize for Signature4 {
     /// # Errors
     ///
     /// Returns [`Error::InvalidArgument`] if invoked on a
-    /// non-version 4 signature, or if either the hashed-area or the
+    /// signature that is neither version 4 nor version 5, or if
+    /// either the hashed-area or the
     /// unhashed-area exceeds the size limit of 2^16.
     ///
     /// [`Error::InvalidArgument`]: ../../enum.Error.html#variant.InvalidArgument
@@ -1120,16 +1154,17 @@ impl Signature4 {
     /// # Errors
     ///
     /// Returns [`Error::InvalidArgument`] if invoked on a
-    /// non-version 4 signature, or if either the hashed-area or the
+    /// signature that is neither version 4 nor version 5, or if
+    /// either the hashed-area or the
     /// unhashed-area exceeds the size limit of 2^16.
     ///
     /// [`Error::InvalidArgument`]: ../enum.Error.html#variant.InvalidArgument
     pub(crate) fn serialize_naked(&self, o: &mut dyn std::io::Write)
                                   -> Result<()> {
-        if self.version() != 4 {
+        if self.version() != 4 && self.version() != 5 {
             return Err(Error::InvalidArgument(
                 "Don't know how to serialize \
-                 non-version 4 packets.".into()).into());
+                 non-version 4 or 5 packets.".into()).into());
         }
         write_byte(o, self.version())?;
         write_byte(o, self.sigtype().into())?;
@@ -1223,6 +1258,7 @@ impl SerializeKey for Key {
     fn serialize(&self, o: &mut io::Write, tag: Tag) -> Result<()> {
         match self {
             &Key::V4(ref p) => p.serialize(o, tag),
+            &Key::V5(ref p) => p.serialize(o, tag),
         }
     }
 }
@@ -1231,12 +1267,14 @@ impl SerializeKeyInto for Key {
     fn serialized_len(&self, tag: Tag) -> usize {
         match self {
             &Key::V4(ref p) => p.serialized_len(tag),
+            &Key::V5(ref p) => p.serialized_len(tag),
         }
     }
 
     fn serialize_into(&self, buf: &mut [u8], tag: Tag) -> Result<usize> {
         match self {
-            &Key::V4(ref p) => p.serialize_into(buf, tag)
+            &Key::V4(ref p) => p.serialize_into(buf, tag),
+            &Key::V5(ref p) => p.serialize_into(buf, tag),
         }
     }
 }
@@ -1251,6 +1289,15 @@ impl SerializeKey for Key4 {
             (tag == Tag::SecretKey || tag == Tag::SecretSubkey)
             && self.secret().is_some();
 
+        // Argon2 must only be used with AEAD, but we encrypt secret
+        // keys using CFB.
+        if let (true, Some(&SecretKey::Encrypted {
+            s2k: S2K::Argon2 { .. }, ..
+        })) = (have_secret_key, self.secret()) {
+            return Err(Error::InvalidArgument(
+                "Argon2 must only be used with AEAD".into()).into());
+        }
+
         // Only emit packets with the SecretKey or SecretSubkey tags
         // if we have secrets.
         let tag = match tag {
@@ -1290,8 +1337,9 @@ impl SerializeKey for Key4 {
                     algorithm,
                     ref ciphertext,
                 } => {
-                    // S2K usage.
-                    write_byte(o, 254)?;
+                    // S2K usage.  Like GnuPG, we use 255 for stubs,
+                    // which do not carry any secret key material.
+                    write_byte(o, if s2k.is_gnu_extension() { 255 } else { 254 })?;
                     write_byte(o, algorithm.into())?;
                     s2k.serialize(o)?;
                     o.write_all(ciphertext)?;
@@ -1352,6 +1400,148 @@ impl SerializeKeyInto for Key4 {
     }
 }
 
+impl SerializeKey for Key5 {
+    fn serialize(&self, o: &mut io::Write, tag: Tag) -> Result<()> {
+        assert!(tag == Tag::PublicKey
+                || tag == Tag::PublicSubkey
+                || tag == Tag::SecretKey
+                || tag == Tag::SecretSubkey);
+        let have_secret_key =
+            (tag == Tag::SecretKey || tag == Tag::SecretSubkey)
+            && self.secret().is_some();
+
+        // Argon2 must only be used with AEAD, but we encrypt secret
+        // keys using CFB.
+        if let (true, Some(&SecretKey::Encrypted {
+            s2k: S2K::Argon2 { .. }, ..
+        })) = (have_secret_key, self.secret()) {
+            return Err(Error::InvalidArgument(
+                "Argon2 must only be used with AEAD".into()).into());
+        }
+
+        // Only emit packets with the SecretKey or SecretSubkey tags
+        // if we have secrets.
+        let tag = match tag {
+            Tag::SecretKey    if ! have_secret_key => Tag::PublicKey,
+            Tag::SecretSubkey if ! have_secret_key => Tag::PublicSubkey,
+            t => t,
+        };
+
+        let len = self.net_len(tag);
+
+        CTB::new(tag).serialize(o)?;
+        BodyLength::Full(len as u32).serialize(o)?;
+
+        write_byte(o, 5)?; // Version.
+        write_be_u32(o, self.creation_time().to_pgp()?)?;
+        write_byte(o, self.pk_algo().into())?;
+        write_be_u32(o, self.mpis().serialized_len() as u32)?;
+        self.mpis().serialize(o)?;
+
+        if have_secret_key {
+            match self.secret().unwrap() {
+                &SecretKey::Unencrypted { ref mpis } => {
+                    // S2K usage.
+                    write_byte(o, 0)?;
+
+                    // Unlike version 4 keys, version 5 keys don't
+                    // have a checksum.
+                    write_be_u32(o, mpis.serialized_len() as u32)?;
+                    mpis.serialize(o)?;
+                },
+                &SecretKey::Encrypted {
+                    ref s2k,
+                    algorithm,
+                    ref ciphertext,
+                } => {
+                    let iv_len = Key5::iv_len(s2k, algorithm, ciphertext);
+
+                    // S2K usage.  Like GnuPG, we use 255 for stubs,
+                    // which do not carry any secret key material.
+                    write_byte(o, if s2k.is_gnu_extension() { 255 } else { 254 })?;
+                    // The size of the following fields up to and
+                    // including the IV.
+                    write_byte(o, (1 + s2k.serialized_len() + iv_len) as u8)?;
+                    write_byte(o, algorithm.into())?;
+                    s2k.serialize(o)?;
+                    o.write_all(&ciphertext[..iv_len])?;
+                    write_be_u32(o, (ciphertext.len() - iv_len) as u32)?;
+                    o.write_all(&ciphertext[iv_len..])?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Key5 {
+    fn net_len(&self, tag: Tag) -> usize {
+        let have_secret_key =
+            (tag == Tag::SecretKey || tag == Tag::SecretSubkey)
+            && self.secret().is_some();
+
+        1 // Version.
+            + 4 // Creation time.
+            + 1 // PK algo.
+            + 4 // Public key material size.
+            + self.mpis().serialized_len()
+            + if have_secret_key {
+                1 + match self.secret().as_ref().unwrap() {
+                    &SecretKey::Unencrypted { ref mpis } =>
+                        4 // Secret key material size.
+                        + mpis.serialized_len(),
+                    &SecretKey::Encrypted {
+                        ref s2k,
+                        ref ciphertext,
+                        ..
+                    } => 1 // Size of the following fields.
+                        + 1 // Symmetric algorithm.
+                        + s2k.serialized_len()
+                        + 4 // Secret key material size.
+                        + ciphertext.len(),
+                }
+            } else {
+                0
+            }
+    }
+
+    /// Returns the length of the IV that prefixes `ciphertext`.
+    ///
+    /// Version 5 keys store the IV separately from the encrypted
+    /// secret key material.  Stubs don't have an IV.
+    fn iv_len(s2k: &S2K, algorithm: SymmetricAlgorithm, ciphertext: &[u8])
+              -> usize {
+        if s2k.is_gnu_extension() {
+            0
+        } else {
+            cmp::min(algorithm.block_size().unwrap_or(0), ciphertext.len())
+        }
+    }
+}
+
+impl SerializeKeyInto for Key5 {
+    fn serialized_len(&self, tag: Tag) -> usize {
+        // gross_len() by foot.
+        let net = self.net_len(tag);
+        1 // CTB
+            + BodyLength::Full(net as u32).serialized_len()
+            + net
+    }
+
+    fn serialize_into(&self, buf: &mut [u8], tag: Tag) -> Result<usize> {
+        if buf.len() != self.serialized_len(tag) {
+            return Err(Error::InvalidArgument(
+                format!("Invalid buffer size, expected {}, got {}",
+                        self.serialized_len(tag), buf.len())).into());
+        }
+
+        let mut cursor = ::std::io::Cursor::new(buf);
+        self.serialize(&mut cursor, tag)?;
+        Ok(cursor.position() as usize)
+    }
+}
+
 impl Serialize for Marker {
     fn serialize(&self, o: &mut dyn std::io::Write) -> Result<()> {
         CTB::new(Tag::Marker).serialize(o)?;
@@ -1842,6 +2032,55 @@ impl SerializeInto for SKESK5 {
     }
 }
 
+impl Serialize for SED {
+    /// Writes a serialized version of the specified `SED`
+    /// packet to `o`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if this packet has children.
+    /// We do not support creating SED packets, because they are not
+    /// integrity protected.
+    fn serialize(&self, o: &mut dyn std::io::Write) -> Result<()> {
+        if let Some(ref _children) = self.common.children {
+            return Err(Error::InvalidOperation(
+                "Cannot encrypt using SED packets".into())
+                       .into());
+        } else {
+            let body_len =
+                self.common.body.as_ref().map(|b| b.len()).unwrap_or(0);
+
+            CTB::new(Tag::SED).serialize(o)?;
+            BodyLength::Full(body_len as u32).serialize(o)?;
+            if let Some(ref body) = self.common.body {
+                o.write_all(&body[..])?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl NetLength for SED {
+    fn net_len(&self) -> usize {
+        self.common.body.as_ref().map(|b| b.len()).unwrap_or(0)
+    }
+}
+
+impl SerializeInto for SED {
+    fn serialized_len(&self) -> usize {
+        if self.common.children.is_some() {
+            0 // XXX
+        } else {
+            self.gross_len()
+        }
+    }
+
+    fn serialize_into(&self, buf: &mut [u8]) -> Result<usize> {
+        generic_serialize_into(self, buf)
+    }
+}
+
 impl Serialize for SEIP {
     /// Writes a serialized version of the specified `SEIP`
     /// packet to `o`.
@@ -2027,6 +2266,7 @@ impl Serialize for Packet {
             &Packet::CompressedData(ref p) => p.serialize(o),
             &Packet::PKESK(ref p) => p.serialize(o),
             &Packet::SKESK(ref p) => p.serialize(o),
+            &Packet::SED(ref p) => p.serialize(o),
             &Packet::SEIP(ref p) => p.serialize(o),
             &Packet::MDC(ref p) => p.serialize(o),
             &Packet::AED(ref p) => p.serialize(o),
@@ -2053,6 +2293,7 @@ impl SerializeInto for Packet {
             &Packet::CompressedData(ref p) => p.serialized_len(),
             &Packet::PKESK(ref p) => p.serialized_len(),
             &Packet::SKESK(ref p) => p.serialized_len(),
+            &Packet::SED(ref p) => p.serialized_len(),
             &Packet::SEIP(ref p) => p.serialized_len(),
             &Packet::MDC(ref p) => p.serialized_len(),
             &Packet::AED(ref p) => p.serialized_len(),
@@ -2112,7 +2353,13 @@ impl Serialize for autocrypt::AutocryptHeader {
         }
 
         for attr in self.attributes.iter() {
-            write!(o, "{}={}; ", attr.key, attr.value)?;
+            // The key data is emitted below.
+            if attr.key == "keydata" {
+                continue;
+            }
+            write!(o, "{}{}={}; ",
+                   if attr.critical { "" } else { "_" },
+                   attr.key, attr.value)?;
         }
 
         let mut buf = Vec::new();
@@ -54,7 +54,13 @@ pub struct Cookie {
 #[derive(Debug)]
 enum Private {
     Nothing,
-    Signer,
+    Signer {
+        /// The literal data packet being signed, if any.
+        ///
+        /// Set by the `LiteralWriter`, as version 5 signatures also
+        /// cover the packet's metadata.
+        literal: Option<Literal>,
+    },
 }
 
 impl Cookie {
@@ -400,7 +406,9 @@ impl<'a> Signer<'a> {
             hash: hash_algo.context()?,
             cookie: Cookie {
                 level: level,
-                private: Private::Signer,
+                private: Private::Signer {
+                    literal: None,
+                },
             },
         })))
     }
@@ -428,7 +436,13 @@ impl<'a> Signer<'a> {
                 }
 
                 // Compute the signature.
-                let sig = sig.sign_hash(*signer, HashAlgorithm::SHA512, hash)?;
+                let sig = match self.cookie.private {
+                    Private::Signer { literal: Some(ref literal) } =>
+                        sig.sign_literal_hash(*signer, HashAlgorithm::SHA512,
+                                              hash, literal)?,
+                    _ =>
+                        sig.sign_hash(*signer, HashAlgorithm::SHA512, hash)?,
+                };
 
                 // And emit the packet.
                 sig.serialize(sink)?;
@@ -559,9 +573,10 @@ impl<'a> LiteralWriter<'a> {
     ///
     /// `format`, `filename`, and `date` will be emitted as part of
     /// the literal packets headers.  Note that these headers will not
-    /// be authenticated by signatures (but will be authenticated by a
-    /// SEIP/MDC container), and are therefore unreliable and should
-    /// not be trusted.
+    /// be authenticated by version 4 signatures (but will be
+    /// authenticated by version 5 signatures and by a SEIP/MDC
+    /// container), and are therefore unreliable and should not be
+    /// trusted.
     ///
     /// If `date` is `None`, then the earliest representable time will
     /// be used as a dummy value.
@@ -600,6 +615,14 @@ impl<'a> LiteralWriter<'a> {
             let stack = inner.pop()?;
             // We know a signer has an inner stackable.
             let stack = stack.unwrap();
+
+            // Version 5 signatures also cover the metadata.
+            if let Private::Signer { ref mut literal } =
+                inner.cookie_mut().private
+            {
+                *literal = Some(template.clone());
+            }
+
             signature_writer = Some(inner);
             inner = stack;
         }
@@ -1391,6 +1414,53 @@ mod test {
         assert_eq!(good, 2);
     }
 
+    #[test]
+    fn signature_v5() {
+        use constants::Curve;
+        use packet::key::Key5;
+
+        let key: Key = Key5::generate_ecc(true, Curve::Ed25519)
+            .unwrap().into();
+        let mut keypair = key.clone().into_keypair().unwrap();
+
+        let mut o = vec![];
+        {
+            let m = Message::new(&mut o);
+            let signer = Signer::new(
+                m, vec![&mut keypair as &mut dyn crypto::Signer], None)
+                .unwrap();
+            let mut ls = LiteralWriter::new(signer, T, Some(b"important"),
+                                            Some(time::now()))
+                .unwrap();
+            ls.write_all(b"Tis, tis, tis.  Tis is important.").unwrap();
+            let signer = ls.finalize_one().unwrap().unwrap();
+            let _ = signer.finalize_one().unwrap().unwrap();
+        }
+
+        // Version 5 signatures cover the literal data packet's
+        // metadata.
+        let mut tampered = o.clone();
+        let offset = tampered.windows(9).position(|w| w == b"important")
+            .unwrap();
+        tampered[offset] = b'I';
+
+        for (message, expected) in &[(o, true), (tampered, false)] {
+            let mut ppr = PacketParser::from_bytes(message).unwrap();
+            let mut good = 0;
+            while let PacketParserResult::Some(pp) = ppr {
+                if let Packet::Signature(ref sig) = pp.packet {
+                    assert_eq!(sig.version(), 5);
+                    assert_eq!(sig.verify(&key).unwrap(), *expected);
+                    good += 1;
+                }
+
+                // Get the next packet.
+                ppr = pp.recurse().unwrap().1;
+            }
+            assert_eq!(good, 1);
+        }
+    }
+
     #[test]
     fn encryptor() {
         let passwords: [Password; 2] = ["streng geheim".into(),
@@ -3,7 +3,7 @@ use time;
 use packet;
 use packet::{Features, KeyFlags};
 use packet::Key;
-use packet::key::Key4;
+use packet::key::{Key4, Key5};
 use Result;
 use packet::Signature;
 use packet::signature;
@@ -11,6 +11,7 @@ use TPK;
 use Error;
 use conversions::Time;
 use crypto::Password;
+use crypto::s2k::S2K;
 use autocrypt::Autocrypt;
 use constants::{
     HashAlgorithm,
@@ -42,10 +43,10 @@ impl Default for CipherSuite {
 }
 
 impl CipherSuite {
-    fn generate_key(self, flags: &KeyFlags) -> Result<Key> {
+    fn generate_key(self, flags: &KeyFlags, version: u8) -> Result<Key> {
         use constants::Curve;
 
-        match self {
+        let key = match self {
             CipherSuite::RSA2k =>
                 Key4::generate_rsa(2048),
             CipherSuite::RSA3k =>
@@ -82,7 +83,14 @@ impl CipherSuite {
                             .into()),
                 }
             },
-        }.map(|key| key.into())
+        }?;
+
+        match version {
+            4 => Ok(key.into()),
+            5 => Ok(Key5::from(key).into()),
+            _ => Err(Error::InvalidArgument(
+                format!("Unsupported key version: {}", version)).into()),
+        }
     }
 }
 
@@ -102,7 +110,9 @@ pub struct TPKBuilder {
     userids: Vec<packet::UserID>,
     user_attributes: Vec<packet::UserAttribute>,
     password: Option<Password>,
+    s2k: Option<S2K>,
     expiration: Option<time::Duration>,
+    version: u8,
 }
 
 impl TPKBuilder {
@@ -125,7 +135,9 @@ impl TPKBuilder {
             userids: vec![],
             user_attributes: vec![],
             password: None,
+            s2k: None,
             expiration: None,
+            version: 4,
         }
     }
 
@@ -154,7 +166,9 @@ impl TPKBuilder {
             userids: userids.into_iter().map(|x| x.into()).collect(),
             user_attributes: vec![],
             password: None,
+            s2k: None,
             expiration: Some(time::Duration::weeks(3 * 52)),
+            version: 4,
         }
     }
 
@@ -191,7 +205,9 @@ impl TPKBuilder {
             userids: vec![],
             user_attributes: vec![],
             password: None,
+            s2k: None,
             expiration: Some(time::Duration::weeks(3 * 52)),
+            version: 4,
         };
 
         if let Some(userid) = userid {
@@ -215,6 +231,18 @@ impl TPKBuilder {
         self
     }
 
+    /// Adds a new user ID constructed from its components.
+    ///
+    /// See `UserID::from_components` for details.  This fails if the
+    /// components don't form a valid user ID.
+    pub fn add_userid_from_components(self, name: Option<&str>,
+                                      comment: Option<&str>, address: &str)
+        -> Result<Self>
+    {
+        Ok(self.add_userid(
+            packet::UserID::from_components(name, comment, address)?))
+    }
+
     /// Adds a new user attribute.
     pub fn add_user_attribute<'a, U>(mut self, ua: U) -> Self
         where U: Into<packet::UserAttribute>
@@ -261,6 +289,36 @@ impl TPKBuilder {
         self
     }
 
+    /// Sets the S2K used to derive the keys encrypting the secret
+    /// keys from the password.
+    ///
+    /// Each secret key is encrypted using a fresh salt.  A value of
+    /// None means `S2K::default()`.  This has no effect unless a
+    /// password is set using `TPKBuilder::set_password`.
+    ///
+    /// Argon2 must not be used to protect secret keys, hence
+    /// `TPKBuilder::generate` fails if `s2k` is an Argon2 S2K.
+    pub fn set_s2k<T>(mut self, s2k: T) -> Self
+        where T: Into<Option<S2K>>
+    {
+        self.s2k = s2k.into();
+        self
+    }
+
+    /// Sets the version of the generated keys.
+    ///
+    /// Version 4 keys, which are the default, are understood by all
+    /// OpenPGP implementations.  Version 5 keys use SHA-256
+    /// fingerprints, and the self-signatures are version 5
+    /// signatures.  See [Section 5.5.2 of RFC 4880bis] for details.
+    /// Other versions cause `TPKBuilder::generate` to fail.
+    ///
+    ///   [Section 5.5.2 of RFC 4880bis]: https://tools.ietf.org/html/draft-ietf-openpgp-rfc4880bis-07#section-5.5.2
+    pub fn set_key_version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
     /// Sets the expiration time.
     ///
     /// A value of None means never.
@@ -292,7 +350,10 @@ impl TPKBuilder {
         packets.push(Packet::PublicKey({
             let mut primary = primary.clone();
             if let Some(ref password) = self.password {
-                primary.secret_mut().unwrap().encrypt_in_place(password)?;
+                let s2k = self.s2k.as_ref().map(|s| s.with_fresh_salt())
+                    .unwrap_or_default();
+                primary.secret_mut().unwrap()
+                    .encrypt_in_place_with_s2k(password, s2k)?;
             }
             primary
         }));
@@ -320,10 +381,14 @@ impl TPKBuilder {
         // sign subkeys
         for blueprint in self.subkeys {
             let flags = &blueprint.flags;
-            let mut subkey = self.ciphersuite.generate_key(flags)?;
+            let mut subkey =
+                self.ciphersuite.generate_key(flags, self.version)?;
 
             if let Some(ref password) = self.password {
-                subkey.secret_mut().unwrap().encrypt_in_place(password)?;
+                let s2k = self.s2k.as_ref().map(|s| s.with_fresh_salt())
+                    .unwrap_or_default();
+                subkey.secret_mut().unwrap()
+                    .encrypt_in_place_with_s2k(password, s2k)?;
             }
 
             let mut builder =
@@ -379,7 +444,7 @@ impl TPKBuilder {
         use SignatureType;
 
         let key = self.ciphersuite.generate_key(
-            &KeyFlags::default().set_certify(true))?;
+            &KeyFlags::default().set_certify(true), self.version)?;
         let sig = signature::Builder::new(SignatureType::DirectKey)
             .set_features(&Features::sequoia())?
             .set_key_flags(&self.primary.flags)?
@@ -404,6 +469,25 @@ mod tests {
     use packet::signature::subpacket::{SubpacketTag, Subpacket, SubpacketValue};
     use constants::PublicKeyAlgorithm;
 
+    #[test]
+    fn userid_from_components() {
+        let (tpk, _) = TPKBuilder::new()
+            .set_cipher_suite(CipherSuite::Cv25519)
+            .add_userid_from_components(Some("Test, Jr."), Some("(1)"),
+                                        "test1@example.com").unwrap()
+            .generate().unwrap();
+
+        let uid = tpk.userids().next().unwrap().userid();
+        assert_eq!(uid.value(),
+                   &b"\"Test, Jr.\" (\\(1\\)) <test1@example.com>"[..]);
+        assert_eq!(uid.name().unwrap(), Some("Test, Jr.".into()));
+        assert_eq!(uid.comment().unwrap(), Some("(1)".into()));
+
+        assert!(TPKBuilder::new()
+                .add_userid_from_components(None, None, "test1@@example.com")
+                .is_err());
+    }
+
     #[test]
     fn all_opts() {
         let (tpk, _) = TPKBuilder::new()
@@ -587,6 +671,73 @@ mod tests {
         assert!(tpk.primary().secret().unwrap().is_encrypted());
     }
 
+    #[test]
+    fn encrypted_secrets_s2k() {
+        use packet::key::SecretKey;
+
+        let (tpk,_) = TPKBuilder::new()
+            .set_cipher_suite(CipherSuite::Cv25519)
+            .add_encryption_subkey()
+            .set_password(Some(String::from("streng geheim").into()))
+            .set_s2k(S2K::Iterated {
+                hash: HashAlgorithm::SHA256,
+                salt: [0; 8],
+                iterations: 65536,
+            })
+            .generate().unwrap();
+
+        let salts = tpk.keys_all().unfiltered().map(|(_, _, key)| {
+            match key.secret() {
+                Some(&SecretKey::Encrypted {
+                    s2k: S2K::Iterated { salt, iterations: 65536, .. }, ..
+                }) => salt,
+                s => panic!("unexpected secret: {:?}", s),
+            }
+        }).collect::<Vec<_>>();
+        assert_eq!(salts.len(), 2);
+        assert!(salts[0] != salts[1]);
+
+        // Argon2 must only be used with AEAD.
+        assert!(TPKBuilder::new()
+                .set_password(Some(String::from("streng geheim").into()))
+                .set_s2k(S2K::new_argon2(1, 1, 10).unwrap())
+                .generate().is_err());
+    }
+
+    #[test]
+    fn v5_keys() {
+        use serialize::Serialize;
+        use parse::Parse;
+
+        let (tpk, rev) = TPKBuilder::general_purpose(
+            CipherSuite::Cv25519, Some("foo@example.org"))
+            .add_signing_subkey()
+            .set_key_version(5)
+            .generate().unwrap();
+
+        assert_eq!(tpk.primary().version(), 5);
+        assert_eq!(tpk.fingerprint().version(), Some(5));
+        assert_eq!(rev.version(), 5);
+        assert_eq!(tpk.userids().count(), 1);
+        assert_eq!(tpk.subkeys().count(), 2);
+        for (sig, _, key) in tpk.keys_all().unfiltered() {
+            assert_eq!(key.version(), 5);
+            assert_eq!(sig.unwrap().version(), 5);
+            assert_eq!(sig.unwrap().issuer_fingerprint(),
+                       Some(tpk.fingerprint()));
+        }
+
+        // The self-signatures still verify after a roundtrip.
+        let mut buf = Vec::new();
+        tpk.serialize(&mut buf).unwrap();
+        let tpk_ = TPK::from_bytes(&buf).unwrap();
+        assert_eq!(tpk, tpk_);
+        assert_eq!(tpk_.userids().count(), 1);
+        assert_eq!(tpk_.subkeys().count(), 2);
+
+        assert!(TPKBuilder::new().set_key_version(3).generate().is_err());
+    }
+
     #[test]
     fn all_ciphersuites() {
         use self::CipherSuite::*;
@@ -0,0 +1,531 @@
+//! Indices for large keyrings.
+//!
+//! Finding a TPK in a large keyring, like an SKS dump or a
+//! distribution's keyring, requires parsing the whole keyring.  A
+//! `KeyringIndex` maps fingerprints, key IDs and email addresses to
+//! the positions of the TPKs in the keyring, so that only the
+//! relevant TPKs need to be parsed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use {
+    Error,
+    Fingerprint,
+    KeyID,
+    Packet,
+    Result,
+    TPK,
+};
+use packet::UserID;
+use parse::{Parse, PacketParserBuilder, PacketParserResult};
+use super::TPKParser;
+
+/// The first line of a saved index.
+const MAGIC: &str = "# Sequoia keyring index, version 2";
+
+/// An index over a keyring.
+///
+/// The index maps the fingerprints and key IDs of all primary keys
+/// and subkeys, and the normalized email addresses of all User IDs to
+/// the positions of the TPKs in the keyring.  Lookups only parse the
+/// TPKs that match.
+///
+/// Building the index requires parsing the whole keyring once.  The
+/// index can then be saved to a file using [`save`], and loaded using
+/// [`open`].  If the keyring changes, the index must be rebuilt.
+/// Changes are detected using the keyring's size and modification
+/// time.
+///
+/// [`save`]: #method.save
+/// [`open`]: #method.open
+///
+/// # Examples
+///
+/// The index can be used to look up keys for the streaming
+/// [`Verifier`]:
+///
+/// [`Verifier`]: ../parse/stream/struct.Verifier.html
+///
+/// ```no_run
+/// # extern crate sequoia_openpgp as openpgp;
+/// # use openpgp::{KeyID, Result, TPK};
+/// # use openpgp::parse::stream::*;
+/// use openpgp::tpk::KeyringIndex;
+///
+/// struct Helper {
+///     index: KeyringIndex,
+/// }
+///
+/// impl VerificationHelper for Helper {
+///     fn get_public_keys(&mut self, ids: &[KeyID]) -> Result<Vec<TPK>> {
+///         self.index.get_public_keys(ids)
+///     }
+///
+///     fn check(&mut self, _: &MessageStructure) -> Result<()> {
+///         // Check the signatures here.
+///         Ok(())
+///     }
+/// }
+///
+/// # fn main() { f().unwrap(); }
+/// # fn f() -> Result<()> {
+/// let index = KeyringIndex::build("keyring.pgp")?;
+/// index.save("keyring.pgp.index")?;
+///
+/// // Later.
+/// let index = KeyringIndex::open("keyring.pgp", "keyring.pgp.index")?;
+/// let mut v = Verifier::from_file("message.pgp", Helper { index: index },
+///                                 None)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct KeyringIndex {
+    keyring: PathBuf,
+    // The size and modification time of the keyring when the index
+    // was built.  Used to detect stale indices.  The modification
+    // time is not available on all platforms.
+    keyring_size: u64,
+    keyring_mtime: Option<Duration>,
+
+    fingerprints: HashMap<Fingerprint, Vec<u64>>,
+    keyids: HashMap<KeyID, Vec<u64>>,
+    emails: HashMap<String, Vec<u64>>,
+}
+
+// Adds `offset` to `key`'s entry, unless it is already there.
+fn insert<K: Hash + Eq>(map: &mut HashMap<K, Vec<u64>>, key: K, offset: u64) {
+    let offsets = map.entry(key).or_insert_with(Vec::new);
+    if offsets.last() != Some(&offset) {
+        offsets.push(offset);
+    }
+}
+
+impl KeyringIndex {
+    /// Returns an empty index for `keyring`.
+    fn new(keyring: &Path) -> Result<Self> {
+        let metadata = fs::metadata(keyring)?;
+        Ok(KeyringIndex {
+            keyring: keyring.to_path_buf(),
+            keyring_size: metadata.len(),
+            keyring_mtime: metadata.modified().ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok()),
+            fingerprints: HashMap::new(),
+            keyids: HashMap::new(),
+            emails: HashMap::new(),
+        })
+    }
+
+    /// Builds an index for the given keyring.
+    ///
+    /// The keyring must be a binary keyring; the positions of the
+    /// TPKs in an ASCII armored keyring are not known.  TPKs that
+    /// can't be parsed are skipped.  If the keyring is corrupted,
+    /// the TPK at that point is skipped, and indexing resumes at the
+    /// next packet that looks like a primary key.
+    pub fn build<P: AsRef<Path>>(keyring: P) -> Result<Self> {
+        let mut index = KeyringIndex::new(keyring.as_ref())?;
+
+        let mut offset = 0;
+        while let Some(corrupted) = index.index_from(offset)? {
+            match next_primary_key(&index.keyring, corrupted)? {
+                Some(next) => offset = next,
+                None => break,
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Indexes the TPKs starting at `offset`.
+    ///
+    /// Returns the position of the last packet before the keyring
+    /// became unparsable, or `None` if the end of the keyring was
+    /// reached.  The TPK that was being read at that point is not
+    /// indexed.  If the first packet is not a primary key, `offset`
+    /// is returned.
+    fn index_from(&mut self, offset: u64) -> Result<Option<u64>> {
+        // The position and packets of the current TPK.
+        let mut tpk: Option<(u64, Vec<Packet>)> = None;
+
+        let ppr = PacketParserBuilder::from_file(&self.keyring)?
+            .seek(offset)?
+            .finalize();
+        let mut ppr = match ppr {
+            Ok(ppr) => ppr,
+            Err(_) => return Ok(Some(offset)),
+        };
+        while let PacketParserResult::Some(pp) = ppr {
+            let position = pp.offset();
+            let (packet, ppr_) = match pp.next() {
+                Ok(r) => r,
+                Err(_) => return Ok(Some(position.unwrap_or(offset))),
+            };
+            ppr = ppr_;
+
+            match packet {
+                Packet::PublicKey(_) | Packet::SecretKey(_) => {
+                    // A new TPK starts.
+                    if let Some((position, packets)) = tpk.take() {
+                        self.add(position, packets);
+                    }
+
+                    let position = position.ok_or_else(
+                        || Error::InvalidArgument(
+                            "Can't index an ASCII armored keyring".into()))?;
+                    tpk = Some((position, vec![packet]));
+                },
+                // We didn't start at a primary key, most likely
+                // because we resynchronized on a byte that only
+                // looked like one.
+                _ if tpk.is_none() => return Ok(Some(offset)),
+                _ => if let Some((_, ref mut packets)) = tpk {
+                    packets.push(packet);
+                },
+            }
+        }
+
+        if let Some((position, packets)) = tpk {
+            self.add(position, packets);
+        }
+
+        Ok(None)
+    }
+
+    /// Indexes the TPK at `offset`.
+    fn add(&mut self, offset: u64, packets: Vec<Packet>) {
+        for tpk in TPKParser::from_iter(packets.into_iter()) {
+            let tpk = match tpk {
+                Ok(tpk) => tpk,
+                // Skip invalid TPKs.
+                Err(_) => continue,
+            };
+
+            for (_, _, key) in tpk.keys_all() {
+                insert(&mut self.fingerprints, key.fingerprint(), offset);
+                insert(&mut self.keyids, key.keyid(), offset);
+            }
+
+            for binding in tpk.userids() {
+                if let Ok(Some(address)) = binding.userid().address_normalized() {
+                    insert(&mut self.emails, address, offset);
+                }
+            }
+        }
+    }
+
+    /// Loads an index for `keyring` from the file `index`.
+    ///
+    /// Returns an error if the index is out of date, i.e., if the
+    /// keyring's size or modification time changed since the index
+    /// was built.
+    pub fn open<P, Q>(keyring: P, index: Q) -> Result<Self>
+        where P: AsRef<Path>, Q: AsRef<Path>
+    {
+        let mut idx = KeyringIndex::new(keyring.as_ref())?;
+
+        let malformed = |n: usize| Error::InvalidArgument(
+            format!("Malformed keyring index, line {}", n + 1));
+
+        let mut lines =
+            io::BufReader::new(fs::File::open(index)?).lines().enumerate();
+        match lines.next() {
+            Some((_, Ok(ref line))) if line == MAGIC => (),
+            _ => return Err(
+                Error::InvalidArgument("Not a keyring index".into()).into()),
+        }
+
+        let mut keyring_size = None;
+        let mut keyring_mtime = None;
+        for (n, line) in lines {
+            let line = line?;
+            let mut fields = line.splitn(2, ' ');
+            let kind = fields.next().unwrap();
+            let rest = fields.next().ok_or_else(|| malformed(n))?;
+
+            if kind == "keyring-size" {
+                keyring_size =
+                    Some(rest.parse::<u64>().map_err(|_| malformed(n))?);
+                continue;
+            }
+
+            if kind == "keyring-mtime" {
+                let mut fields = rest.splitn(2, '.');
+                let secs = fields.next().unwrap().parse::<u64>()
+                    .map_err(|_| malformed(n))?;
+                let nanos = fields.next().ok_or_else(|| malformed(n))?
+                    .parse::<u32>().map_err(|_| malformed(n))?;
+                keyring_mtime = Some(Duration::new(secs, nanos));
+                continue;
+            }
+
+            // The value may contain spaces, the offset doesn't.
+            let mut fields = rest.rsplitn(2, ' ');
+            let offset = fields.next().unwrap().parse::<u64>()
+                .map_err(|_| malformed(n))?;
+            let value = fields.next().ok_or_else(|| malformed(n))?;
+
+            match kind {
+                "fingerprint" => {
+                    let fp = Fingerprint::from_hex(value)?;
+                    insert(&mut idx.keyids, fp.to_keyid(), offset);
+                    insert(&mut idx.fingerprints, fp, offset);
+                },
+                "email" => insert(&mut idx.emails, value.to_string(), offset),
+                _ => return Err(malformed(n).into()),
+            }
+        }
+
+        if keyring_size != Some(idx.keyring_size)
+            || keyring_mtime != idx.keyring_mtime
+        {
+            return Err(Error::InvalidOperation(
+                "Keyring index is out of date".into()).into());
+        }
+
+        Ok(idx)
+    }
+
+    /// Saves the index to the file `index`.
+    pub fn save<P: AsRef<Path>>(&self, index: P) -> Result<()> {
+        let mut lines = Vec::new();
+        for (fp, offsets) in self.fingerprints.iter() {
+            for offset in offsets {
+                lines.push(format!("fingerprint {} {}", fp.to_hex(), offset));
+            }
+        }
+        for (email, offsets) in self.emails.iter() {
+            // Line breaks would corrupt the index.
+            if email.contains(|c: char| c == '\n' || c == '\r') {
+                continue;
+            }
+            for offset in offsets {
+                lines.push(format!("email {} {}", email, offset));
+            }
+        }
+        lines.sort();
+
+        let mut sink = io::BufWriter::new(fs::File::create(index)?);
+        writeln!(sink, "{}", MAGIC)?;
+        writeln!(sink, "keyring-size {}", self.keyring_size)?;
+        if let Some(mtime) = self.keyring_mtime {
+            writeln!(sink, "keyring-mtime {}.{:09}",
+                     mtime.as_secs(), mtime.subsec_nanos())?;
+        }
+        for line in lines {
+            writeln!(sink, "{}", line)?;
+        }
+        sink.flush()?;
+        Ok(())
+    }
+
+    /// Returns the path of the indexed keyring.
+    pub fn keyring(&self) -> &Path {
+        &self.keyring
+    }
+
+    /// Returns the TPKs containing a key with the given fingerprint.
+    pub fn lookup_fingerprint(&self, fp: &Fingerprint) -> Result<Vec<TPK>> {
+        self.lookup(self.fingerprints.get(fp), |tpk| {
+            tpk.keys_all().any(|(_, _, key)| key.fingerprint() == *fp)
+        })
+    }
+
+    /// Returns the TPKs containing a key with the given key ID.
+    pub fn lookup_keyid(&self, id: &KeyID) -> Result<Vec<TPK>> {
+        self.lookup(self.keyids.get(id), |tpk| {
+            tpk.keys_all().any(|(_, _, key)| key.keyid() == *id)
+        })
+    }
+
+    /// Returns the TPKs with a User ID containing the given email
+    /// address.
+    ///
+    /// The address is normalized as by
+    /// `UserID::address_normalized`.
+    pub fn lookup_email(&self, email: &str) -> Result<Vec<TPK>> {
+        let email = UserID::from(format!("<{}>", email).into_bytes())
+            .address_normalized()?
+            .ok_or_else(|| Error::InvalidArgument(
+                format!("Not an email address: {}", email)))?;
+
+        self.lookup(self.emails.get(&email), |tpk| {
+            tpk.userids().any(|binding| {
+                binding.userid().address_normalized().ok()
+                    == Some(Some(email.clone()))
+            })
+        })
+    }
+
+    /// Returns the TPKs containing any of the given keys.
+    ///
+    /// This can be used to implement
+    /// [`VerificationHelper::get_public_keys`].
+    ///
+    /// [`VerificationHelper::get_public_keys`]: ../parse/stream/trait.VerificationHelper.html#tymethod.get_public_keys
+    pub fn get_public_keys(&self, ids: &[KeyID]) -> Result<Vec<TPK>> {
+        let mut tpks: Vec<TPK> = Vec::new();
+        for id in ids {
+            for tpk in self.lookup_keyid(id)? {
+                if ! tpks.iter().any(|t| t.fingerprint() == tpk.fingerprint()) {
+                    tpks.push(tpk);
+                }
+            }
+        }
+        Ok(tpks)
+    }
+
+    /// Parses the TPKs at `offsets`, and makes sure that they match.
+    fn lookup<F>(&self, offsets: Option<&Vec<u64>>, matches: F)
+                 -> Result<Vec<TPK>>
+        where F: Fn(&TPK) -> bool
+    {
+        let mut tpks = Vec::new();
+        for offset in offsets.into_iter().flat_map(|o| o.iter()) {
+            let ppr = PacketParserBuilder::from_file(&self.keyring)?
+                .seek(*offset)?
+                .finalize()?;
+            let tpk = TPK::from_packet_parser(ppr)?;
+            if ! matches(&tpk) {
+                return Err(Error::InvalidOperation(
+                    "Keyring index is out of date".into()).into());
+            }
+            tpks.push(tpk);
+        }
+        Ok(tpks)
+    }
+}
+
+/// Returns the position of the first byte after `after` that looks
+/// like the start of a Public-Key or Secret-Key packet.
+fn next_primary_key(keyring: &Path, after: u64) -> Result<Option<u64>> {
+    let mut file = fs::File::open(keyring)?;
+    file.seek(io::SeekFrom::Start(after + 1))?;
+
+    for (i, b) in io::BufReader::new(file).bytes().enumerate() {
+        match b? {
+            // Old and new format CTBs.
+            0x94...0x9b | 0xc5 | 0xc6 => return Ok(Some(after + 1 + i as u64)),
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use tempfile::TempDir;
+
+    #[test]
+    fn keyring_index() {
+        let dir = TempDir::new().unwrap();
+        let keyring = dir.path().join("keyring.pgp");
+        let index_path = dir.path().join("keyring.pgp.index");
+
+        let mut data = Vec::new();
+        for f in &["testy.pgp", "neal.pgp", "dkg.gpg"] {
+            data.extend_from_slice(::tests::key(f));
+        }
+        fs::write(&keyring, &data).unwrap();
+
+        let testy = TPK::from_bytes(::tests::key("testy.pgp")).unwrap();
+        let neal = TPK::from_bytes(::tests::key("neal.pgp")).unwrap();
+
+        for &reload in &[false, true] {
+            let index = if reload {
+                KeyringIndex::open(&keyring, &index_path).unwrap()
+            } else {
+                let index = KeyringIndex::build(&keyring).unwrap();
+                index.save(&index_path).unwrap();
+                index
+            };
+
+            // Primary key.
+            let r = index.lookup_fingerprint(&neal.fingerprint()).unwrap();
+            assert_eq!(r.len(), 1);
+            assert_eq!(r[0].fingerprint(), neal.fingerprint());
+
+            // Subkey.
+            let subkey = testy.subkeys().next().unwrap().subkey();
+            let r = index.lookup_keyid(&subkey.keyid()).unwrap();
+            assert_eq!(r.len(), 1);
+            assert_eq!(r[0].fingerprint(), testy.fingerprint());
+
+            // Email, normalized.
+            let r = index.lookup_email("TESTY@example.org").unwrap();
+            assert_eq!(r.len(), 1);
+            assert_eq!(r[0].fingerprint(), testy.fingerprint());
+            assert!(index.lookup_email("nobody").is_err());
+
+            let r = index.get_public_keys(
+                &[testy.primary().keyid(), subkey.keyid(),
+                  neal.primary().keyid(), KeyID::wildcard()]).unwrap();
+            assert_eq!(r.len(), 2);
+
+            assert_eq!(index.lookup_email("nobody@example.org").unwrap()
+                       .len(), 0);
+        }
+
+        // Rewriting the keyring with the same size invalidates the
+        // index.  Wait until the modification time changes, its
+        // granularity depends on the file system.
+        let mtime = fs::metadata(&keyring).unwrap().modified().unwrap();
+        let mut reordered = Vec::new();
+        for f in &["neal.pgp", "testy.pgp", "dkg.gpg"] {
+            reordered.extend_from_slice(::tests::key(f));
+        }
+        assert_eq!(reordered.len(), data.len());
+        while fs::metadata(&keyring).unwrap().modified().unwrap() == mtime {
+            thread::sleep(Duration::from_millis(10));
+            fs::write(&keyring, &reordered).unwrap();
+        }
+        assert!(KeyringIndex::open(&keyring, &index_path).is_err());
+
+        // So does appending to it.
+        let index = KeyringIndex::build(&keyring).unwrap();
+        index.save(&index_path).unwrap();
+        assert!(KeyringIndex::open(&keyring, &index_path).is_ok());
+        reordered.extend_from_slice(::tests::key("testy-new.pgp"));
+        fs::write(&keyring, &reordered).unwrap();
+        assert!(KeyringIndex::open(&keyring, &index_path).is_err());
+    }
+
+    #[test]
+    fn keyring_index_corrupted() {
+        let dir = TempDir::new().unwrap();
+        let keyring = dir.path().join("keyring.pgp");
+
+        // More junk than the packet parser skips on its own.
+        let mut data = Vec::new();
+        data.extend_from_slice(::tests::key("testy.pgp"));
+        data.extend_from_slice(&[0; 40 * 1024][..]);
+        data.extend_from_slice(::tests::key("neal.pgp"));
+        data.extend_from_slice(::tests::key("dkg.gpg"));
+        fs::write(&keyring, &data).unwrap();
+
+        let testy = TPK::from_bytes(::tests::key("testy.pgp")).unwrap();
+        let neal = TPK::from_bytes(::tests::key("neal.pgp")).unwrap();
+        let dkg = TPK::from_bytes(::tests::key("dkg.gpg")).unwrap();
+
+        let index = KeyringIndex::build(&keyring).unwrap();
+
+        // The TPK followed by the junk can't be read, and is
+        // skipped.
+        assert_eq!(index.lookup_fingerprint(&testy.fingerprint()).unwrap()
+                   .len(), 0);
+
+        // Indexing resumes after the junk.
+        for tpk in &[neal, dkg] {
+            let r = index.lookup_fingerprint(&tpk.fingerprint()).unwrap();
+            assert_eq!(r.len(), 1);
+            assert_eq!(r[0].fingerprint(), tpk.fingerprint());
+        }
+    }
+}
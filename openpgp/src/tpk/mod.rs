@@ -32,7 +32,7 @@ use {
     KeyID,
     Fingerprint,
 };
-use parse::{Parse, PacketParserResult, PacketParser};
+use parse::{Parse, PacketParserResult, PacketParser, PacketParserBuilder};
 use serialize::SerializeInto;
 use constants::ReasonForRevocation;
 
@@ -40,10 +40,14 @@ mod lexer;
 mod grammar;
 mod builder;
 mod bindings;
+mod keyring_index;
+mod parallel;
 
 use self::lexer::Lexer;
 pub use self::lexer::Token;
 pub use self::builder::{TPKBuilder, CipherSuite};
+pub use self::keyring_index::KeyringIndex;
+pub use self::parallel::ParallelTPKParser;
 
 use lalrpop_util::ParseError;
 
@@ -926,7 +930,9 @@ impl<'a> Iterator for KeyIter<'a> {
             }
 
             if let Some(want_secret) = self.secret {
-                if key.secret().is_some() {
+                // GnuPG's dummy stubs do not carry any secret key
+                // material, but keys diverted to a smartcard do.
+                if key.secret().map(|s| ! s.is_gnu_dummy()).unwrap_or(false) {
                     // We have a secret.
                     if ! want_secret {
                         t!("Have a secret... skipping.");
@@ -947,6 +953,11 @@ impl<'a> Iterator for KeyIter<'a> {
                             t!("Unencrypted secret... skipping.");
                             continue;
                         }
+                    } else if secret.is_stub() {
+                        if want_unencrypted_secret {
+                            t!("Secret is a stub... skipping.");
+                            continue;
+                        }
                     } else {
                         if want_unencrypted_secret {
                             t!("Encrypted secret... skipping.");
@@ -1088,8 +1099,15 @@ impl<'a> KeyIter<'a> {
 
     /// If not None, filters by whether a key has a secret.
     ///
+    /// Keys whose secret is one of GnuPG's `gnu-dummy` stubs (see
+    /// [`SecretKey::is_gnu_dummy`]) are considered to not have a
+    /// secret.  Keys whose secret is stored on a smartcard are
+    /// considered to have one.
+    ///
     /// If you call this function multiple times, only the last value
     /// is used.
+    ///
+    ///   [`SecretKey::is_gnu_dummy`]: ../packet/key/enum.SecretKey.html#method.is_gnu_dummy
     pub fn secret<T>(mut self, secret: T) -> Self
         where T: Into<Option<bool>>
     {
@@ -1100,8 +1118,13 @@ impl<'a> KeyIter<'a> {
     /// If not None, filters by whether a key has an unencrypted
     /// secret.
     ///
+    /// GnuPG's stubs (see [`SecretKey::is_stub`]) are never
+    /// considered to be unencrypted secrets.
+    ///
     /// If you call this function multiple times, only the last value
     /// is used.
+    ///
+    ///   [`SecretKey::is_stub`]: ../packet/key/enum.SecretKey.html#method.is_stub
     pub fn unencrypted_secret<T>(mut self, unencrypted_secret: T) -> Self
         where T: Into<Option<bool>>
     {
@@ -1186,22 +1209,33 @@ impl<'a> TPKParser<'a, vec::IntoIter<Packet>> {
     }
 }
 
+/// If the input is armored, all armored blocks are read, as keyrings
+/// are often distributed as a sequence of armored keys.
 impl<'a> Parse<'a, TPKParser<'a, vec::IntoIter<Packet>>>
     for TPKParser<'a, vec::IntoIter<Packet>>
 {
     /// Initializes a `TPKParser` from a `Read`er.
     fn from_reader<R: 'a + io::Read>(reader: R) -> Result<Self> {
-        Ok(Self::from_packet_parser(PacketParser::from_reader(reader)?))
+        Ok(Self::from_packet_parser(
+            PacketParserBuilder::from_reader(reader)?
+                .dearmor_multi_block(true)
+                .finalize()?))
     }
 
     /// Initializes a `TPKParser` from a `File`.
     fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(Self::from_packet_parser(PacketParser::from_file(path)?))
+        Ok(Self::from_packet_parser(
+            PacketParserBuilder::from_file(path)?
+                .dearmor_multi_block(true)
+                .finalize()?))
     }
 
     /// Initializes a `TPKParser` from a byte string.
     fn from_bytes(data: &'a [u8]) -> Result<Self> {
-        Ok(Self::from_packet_parser(PacketParser::from_bytes(data)?))
+        Ok(Self::from_packet_parser(
+            PacketParserBuilder::from_bytes(data)?
+                .dearmor_multi_block(true)
+                .finalize()?))
     }
 }
 
@@ -1366,36 +1400,32 @@ impl<'a, I: Iterator<Item=Packet>> TPKParser<'a, I> {
                 let mut other_revs = vec![];
 
                 for sig in sigs.into_iter() {
-                    match sig {
-                        Signature::V4(sig) => {
-                            let sigtype = sig.sigtype();
-
-                            let is_selfsig =
-                                sig.issuer_fingerprint()
-                                .map(|fp| fp == *primary)
-                                .unwrap_or(false)
-                                || sig.issuer()
-                                .map(|keyid| keyid == *primary_keyid)
-                                .unwrap_or(false);
-
-                            use self::SignatureType::*;
-                            if sigtype == KeyRevocation
-                                || sigtype == SubkeyRevocation
-                                || sigtype == CertificateRevocation
-                            {
-                                if is_selfsig {
-                                    self_revs.push(sig.into());
-                                } else {
-                                    other_revs.push(sig.into());
-                                }
-                            } else {
-                                if is_selfsig {
-                                    selfsigs.push(sig.into());
-                                } else {
-                                    certifications.push(sig.into());
-                                }
-                            }
-                        },
+                    let sigtype = sig.sigtype();
+
+                    let is_selfsig =
+                        sig.issuer_fingerprint()
+                        .map(|fp| fp == *primary)
+                        .unwrap_or(false)
+                        || sig.issuer()
+                        .map(|keyid| keyid == *primary_keyid)
+                        .unwrap_or(false);
+
+                    use self::SignatureType::*;
+                    if sigtype == KeyRevocation
+                        || sigtype == SubkeyRevocation
+                        || sigtype == CertificateRevocation
+                    {
+                        if is_selfsig {
+                            self_revs.push(sig);
+                        } else {
+                            other_revs.push(sig);
+                        }
+                    } else {
+                        if is_selfsig {
+                            selfsigs.push(sig);
+                        } else {
+                            certifications.push(sig);
+                        }
                     }
                 }
 
@@ -1838,7 +1868,7 @@ impl TPK {
 
     /// Returns whether or not the TPK has expired.
     pub fn expired(&self) -> bool {
-        if let Some(Signature::V4(sig)) = self.primary_key_signature() {
+        if let Some(sig) = self.primary_key_signature() {
             sig.key_expired(self.primary())
         } else {
             false
@@ -1847,7 +1877,7 @@ impl TPK {
 
     /// Returns whether or not the key is expired at the given time.
     pub fn expired_at(&self, tm: time::Tm) -> bool {
-        if let Some(Signature::V4(sig)) = self.primary_key_signature() {
+        if let Some(sig) = self.primary_key_signature() {
             sig.key_expired_at(self.primary(), tm)
         } else {
             false
@@ -3416,6 +3446,35 @@ mod test {
         assert_eq!(tpks, &[ true, false, false, true ]);
     }
 
+    #[test]
+    fn armored_keyring() {
+        use std::io::Write;
+        use armor;
+
+        let testy = TPK::from_bytes(::tests::key("testy.asc")).unwrap();
+        let dkg = TPK::from_bytes(::tests::key("dkg.gpg")).unwrap();
+
+        // Several armored keys, with some text in between, as is
+        // common in distribution keyrings and email attachments.
+        let mut keyring = Vec::new();
+        keyring.extend_from_slice(::tests::key("testy.asc"));
+        keyring.extend_from_slice(b"\nThis is dkg's key:\n\n");
+        {
+            let mut w = armor::Writer::new(&mut keyring,
+                                           armor::Kind::PublicKey,
+                                           &[]).unwrap();
+            w.write_all(::tests::key("dkg.gpg")).unwrap();
+        }
+        keyring.extend_from_slice(b"\n");
+        keyring.extend_from_slice(::tests::key("testy.asc"));
+
+        let tpks = TPKParser::from_bytes(&keyring[..]).unwrap()
+            .map(|tpkr| tpkr.unwrap().fingerprint())
+            .collect::<Vec<_>>();
+        assert_eq!(tpks, &[ testy.fingerprint(), dkg.fingerprint(),
+                            testy.fingerprint() ]);
+    }
+
     #[test]
     fn merge_with_incomplete_update() {
         let tpk = TPK::from_bytes(::tests::key("about-to-expire.expired.pgp"))
@@ -3948,6 +4007,27 @@ Pu1xwz57O4zo1VYf6TqHJzVC3OMvMUM2hhdecMUe5x6GorNaj6g=
         assert_eq!(tpk.subkeys().len(), 2);
     }
 
+    #[test]
+    fn key_iter_gnu_stubs() {
+        // The primary key is a gnu-dummy stub.
+        let tpk = TPK::from_bytes(
+            ::tests::key("testy-gnu-dummy-private.pgp")).unwrap();
+        assert!(tpk.primary().secret().unwrap().is_gnu_dummy());
+        assert_eq!(tpk.keys_all().secret(true).count(), 1);
+        assert_eq!(tpk.keys_all().secret(false).count(), 1);
+        assert_eq!(tpk.keys_all().unencrypted_secret(true).count(), 1);
+        assert_eq!(tpk.keys_all().unencrypted_secret(false).count(), 1);
+
+        // The primary key is stored on a smartcard.
+        let tpk = TPK::from_bytes(
+            ::tests::key("testy-divert-to-card-private.pgp")).unwrap();
+        assert!(tpk.primary().secret().unwrap().card_serial().is_some());
+        assert_eq!(tpk.keys_all().secret(true).count(), 2);
+        assert_eq!(tpk.keys_all().secret(false).count(), 0);
+        assert_eq!(tpk.keys_all().unencrypted_secret(true).count(), 1);
+        assert_eq!(tpk.keys_all().unencrypted_secret(false).count(), 1);
+    }
+
     #[test]
     fn signature_order() {
         let neal = TPK::from_bytes(::tests::key("neal.pgp")).unwrap();
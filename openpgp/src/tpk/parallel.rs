@@ -0,0 +1,381 @@
+//! Parses TPKs using several threads.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem;
+use std::panic;
+use std::path::Path;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+use num_cpus;
+
+use {
+    Packet,
+    Result,
+    TPK,
+    packet::Tag,
+};
+use parse::{Parse, PacketParserResult, PacketParser, PacketParserBuilder};
+use super::TPKParser;
+
+/// A batch of packets starting with a primary key, tagged with its
+/// position in the packet stream.
+type Job = (usize, Vec<Packet>);
+
+/// The TPKs parsed from a `Job`.
+type JobResult = (usize, Vec<Result<TPK>>);
+
+/// Parses TPKs using several threads.
+///
+/// Like `TPKParser`, this parses a keyring, and returns the TPKs in
+/// the order they appear in the packet stream.  But, whereas
+/// `TPKParser` validates the TPKs one after the other, this parser
+/// splits the packet stream at the primary keys, and validates the
+/// TPKs, which involves checking the self-signatures, using several
+/// worker threads.  This makes processing large keyrings, like SKS
+/// dumps, much faster on multi-core systems.
+///
+/// Reading the packets is still done by the calling thread.  To
+/// limit the memory consumption, only a few TPKs per worker are
+/// queued.
+///
+/// Unlike `TPKParser`, this parser does not support filtering the
+/// unvalidated TPKs.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate sequoia_openpgp as openpgp;
+/// # use openpgp::Result;
+/// # use openpgp::parse::Parse;
+/// use openpgp::tpk::ParallelTPKParser;
+///
+/// # fn main() { f().unwrap(); }
+/// # fn f() -> Result<()> {
+/// # let keyring = include_bytes!("../../tests/data/keys/testy.pgp");
+/// for tpk in ParallelTPKParser::from_bytes(keyring)?.threads(4) {
+///     match tpk {
+///         Ok(tpk) => println!("{}", tpk.primary().fingerprint()),
+///         Err(err) => eprintln!("Error reading keyring: {}", err),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ParallelTPKParser<'a> {
+    source: Option<PacketParser<'a>>,
+    // The packets of the TPK that is currently being read.
+    packets: Vec<Packet>,
+
+    threads: usize,
+    workers: Vec<thread::JoinHandle<()>>,
+    jobs: Option<mpsc::SyncSender<Job>>,
+    results: Option<mpsc::Receiver<JobResult>>,
+
+    // The sequence number of the next job.
+    next_job: usize,
+    // The sequence number of the next job whose results we return.
+    next_result: usize,
+    // Results that arrived out of order.
+    pending: HashMap<usize, Vec<Result<TPK>>>,
+    // Results that are ready to be returned.
+    ready: VecDeque<Result<TPK>>,
+}
+
+/// If the input is armored, all armored blocks are read, as keyrings
+/// are often distributed as a sequence of armored keys.
+impl<'a> Parse<'a, ParallelTPKParser<'a>> for ParallelTPKParser<'a> {
+    /// Initializes a `ParallelTPKParser` from a `Read`er.
+    fn from_reader<R: 'a + io::Read>(reader: R) -> Result<Self> {
+        Ok(Self::from_packet_parser(
+            PacketParserBuilder::from_reader(reader)?
+                .dearmor_multi_block(true)
+                .finalize()?))
+    }
+
+    /// Initializes a `ParallelTPKParser` from a `File`.
+    fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::from_packet_parser(
+            PacketParserBuilder::from_file(path)?
+                .dearmor_multi_block(true)
+                .finalize()?))
+    }
+
+    /// Initializes a `ParallelTPKParser` from a byte string.
+    fn from_bytes(data: &'a [u8]) -> Result<Self> {
+        Ok(Self::from_packet_parser(
+            PacketParserBuilder::from_bytes(data)?
+                .dearmor_multi_block(true)
+                .finalize()?))
+    }
+}
+
+impl<'a> ParallelTPKParser<'a> {
+    /// Initializes a `ParallelTPKParser` from a `PacketParser`.
+    ///
+    /// By default, one worker thread per CPU is used.
+    pub fn from_packet_parser(ppr: PacketParserResult<'a>) -> Self {
+        let source = if let PacketParserResult::Some(pp) = ppr {
+            Some(pp)
+        } else {
+            None
+        };
+
+        ParallelTPKParser {
+            source: source,
+            packets: vec![],
+            threads: num_cpus::get(),
+            workers: vec![],
+            jobs: None,
+            results: None,
+            next_job: 0,
+            next_result: 0,
+            pending: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Sets the number of worker threads.
+    ///
+    /// This must be called before the first TPK is read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero, or if the workers have already
+    /// been started.
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0);
+        assert!(self.workers.is_empty(), "Workers already started");
+        self.threads = threads;
+        self
+    }
+
+    /// Returns the maximum number of jobs in flight.
+    fn max_jobs(&self) -> usize {
+        2 * self.threads
+    }
+
+    /// Starts the worker threads.
+    fn start(&mut self) {
+        let (jobs, job_queue) = mpsc::sync_channel::<Job>(self.max_jobs());
+        let (result_sink, results) = mpsc::channel::<JobResult>();
+        let job_queue = Arc::new(Mutex::new(job_queue));
+
+        for _ in 0..self.threads {
+            let job_queue = job_queue.clone();
+            let result_sink = result_sink.clone();
+            self.workers.push(thread::spawn(move || {
+                loop {
+                    let job = job_queue.lock().unwrap().recv();
+                    let (seq, packets) = match job {
+                        Ok(job) => job,
+                        // The parser is gone.
+                        Err(_) => break,
+                    };
+
+                    // Make sure that the parser learns about panics.
+                    // Otherwise, it would wait for the result
+                    // forever.
+                    let tpks = panic::catch_unwind(
+                        panic::AssertUnwindSafe(|| {
+                            TPKParser::from_iter(packets.into_iter())
+                                .collect::<Vec<_>>()
+                        }))
+                        .unwrap_or_else(|_| vec![Err(format_err!(
+                            "Worker panicked while parsing a TPK"))]);
+
+                    if result_sink.send((seq, tpks)).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+
+        self.jobs = Some(jobs);
+        self.results = Some(results);
+    }
+
+    /// Queues a job.
+    fn queue(&mut self, packets: Vec<Packet>) {
+        let seq = self.next_job;
+        self.next_job += 1;
+        self.jobs.as_ref().expect("workers started").send((seq, packets))
+            .expect("workers are alive as long as the parser");
+    }
+
+    /// Reads the packets of the next TPK, and queues them.
+    ///
+    /// Returns false if there is nothing left to read.
+    fn read_tpk(&mut self) -> bool {
+        loop {
+            let pp = match self.source.take() {
+                Some(pp) => pp,
+                None => {
+                    if self.packets.is_empty() {
+                        return false;
+                    }
+                    let packets = mem::replace(&mut self.packets, vec![]);
+                    self.queue(packets);
+                    return true;
+                },
+            };
+
+            match pp.next() {
+                Ok((packet, ppr)) => {
+                    if let PacketParserResult::Some(pp) = ppr {
+                        self.source = Some(pp);
+                    }
+
+                    // Split the stream at primary keys, just like
+                    // `TPKParser` does.
+                    let tag = packet.tag();
+                    if (tag == Tag::PublicKey || tag == Tag::SecretKey)
+                        && ! self.packets.is_empty()
+                    {
+                        let packets =
+                            mem::replace(&mut self.packets, vec![packet]);
+                        self.queue(packets);
+                        return true;
+                    }
+
+                    self.packets.push(packet);
+                },
+                Err(err) => {
+                    // Like `TPKParser`, we report the error, and stop
+                    // reading.  The error is ordered like any other
+                    // result.  The packets read so far are only a
+                    // part of a TPK, drop them.
+                    self.packets.clear();
+                    self.pending.insert(self.next_job, vec![Err(err)]);
+                    self.next_job += 1;
+                    return true;
+                },
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for ParallelTPKParser<'a> {
+    type Item = Result<TPK>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.workers.is_empty() {
+            self.start();
+        }
+
+        loop {
+            if let Some(r) = self.ready.pop_front() {
+                return Some(r);
+            }
+
+            if let Some(rs) = self.pending.remove(&self.next_result) {
+                self.next_result += 1;
+                self.ready.extend(rs);
+                continue;
+            }
+
+            // Keep the workers busy.
+            let mut eof = false;
+            while self.next_job - self.next_result < self.max_jobs() {
+                if ! self.read_tpk() {
+                    eof = true;
+                    break;
+                }
+            }
+
+            if self.next_result == self.next_job {
+                if eof {
+                    return None;
+                }
+                // The next result was an error from the packet
+                // parser.
+                continue;
+            }
+
+            if self.pending.contains_key(&self.next_result) {
+                continue;
+            }
+
+            let (seq, rs) = self.results.as_ref().expect("workers started")
+                .recv().expect("workers are alive as long as the parser");
+            self.pending.insert(seq, rs);
+        }
+    }
+}
+
+impl<'a> Drop for ParallelTPKParser<'a> {
+    fn drop(&mut self) {
+        // Closing the queue makes the workers exit.
+        self.jobs = None;
+        self.results = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serialize::Serialize;
+
+    #[test]
+    fn parallel_tpk_parser() {
+        // Some junk, a few TPKs, some of them multiple times, and a
+        // packet that doesn't belong into a keyring.
+        let mut keyring = Vec::new();
+        let mut expected = Vec::new();
+        for f in &["testy.pgp", "neal.pgp", "dkg.gpg", "testy.pgp",
+                   "emmelie-dorothea-dina-samantha-awina-ed25519.pgp",
+                   "testy-new.pgp", "neal.pgp"] {
+            keyring.extend_from_slice(::tests::key(f));
+        }
+        let sig = PacketParser::from_bytes(::tests::message("sig.gpg"))
+            .unwrap().unwrap().packet;
+        sig.serialize(&mut keyring).unwrap();
+        keyring.extend_from_slice(::tests::key("dkg.gpg"));
+
+        for tpk in TPKParser::from_bytes(&keyring).unwrap() {
+            expected.push(tpk.map_err(|e| e.to_string()));
+        }
+        assert!(expected.iter().any(|r| r.is_err()));
+
+        for threads in 1..5 {
+            let tpks = ParallelTPKParser::from_bytes(&keyring).unwrap()
+                .threads(threads)
+                .map(|r| r.map_err(|e| e.to_string()))
+                .collect::<Vec<_>>();
+            assert_eq!(tpks, expected);
+        }
+
+        // Dropping the parser early doesn't hang.
+        let mut parser = ParallelTPKParser::from_bytes(&keyring).unwrap()
+            .threads(2);
+        assert!(parser.next().unwrap().is_ok());
+        drop(parser);
+
+        // Empty input.
+        assert_eq!(ParallelTPKParser::from_bytes(b"").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn parse_error() {
+        // Two TPKs followed by more junk than the packet parser is
+        // willing to skip.
+        let testy = TPK::from_bytes(::tests::key("testy.pgp")).unwrap();
+        let mut keyring = Vec::new();
+        keyring.extend_from_slice(::tests::key("testy.pgp"));
+        keyring.extend_from_slice(::tests::key("neal.pgp"));
+        keyring.extend_from_slice(&vec![0; 40 * 1024]);
+
+        let tpks = ParallelTPKParser::from_bytes(&keyring).unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(tpks.len(), 2);
+        assert_eq!(tpks[0].as_ref().unwrap().fingerprint(),
+                   testy.fingerprint());
+        // The packets read before the error are not returned as a
+        // TPK.
+        assert!(tpks[1].is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use failure;
+
+use lexer;
+use grammar;
+use component::Component;
+use encoded_word;
+use parse_error_downcast;
+use {AddrSpec, NameAddr, Result};
+
+/// A parsed [RFC 2822 `mailbox`].
+///
+/// A `mailbox` is either a [`name-addr`] or a bare [`addr-spec`]:
+///
+/// ```text
+/// First Last (Comment) <email@example.org>
+/// email@example.org
+/// ```
+///
+/// [RFC 2822 `mailbox`]: https://tools.ietf.org/html/rfc2822#section-3.4
+/// [`name-addr`]: struct.NameAddr.html
+/// [`addr-spec`]: struct.AddrSpec.html
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Mailbox {
+    /// A `name-addr`.
+    NameAddr(NameAddr),
+    /// A bare `addr-spec`.
+    AddrSpec(AddrSpec),
+}
+
+impl Mailbox {
+    /// Parses a string that allegedly contains an [RFC 2822
+    /// `mailbox`].
+    ///
+    /// [RFC 2822 `mailbox`]: https://tools.ietf.org/html/rfc2822#section-3.4
+    pub fn parse<S>(input: S) -> Result<Self>
+        where S: AsRef<str>
+    {
+        let lexer = lexer::Lexer::new(input.as_ref());
+        match grammar::MailboxParser::new().parse(lexer) {
+            Ok(m) => Ok(m.into()),
+            Err(err) => Err(parse_error_downcast(err).into()),
+        }
+    }
+
+    /// Returns the [display name], if any.
+    ///
+    /// [display name]: https://tools.ietf.org/html/rfc2822#section-3.4
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Mailbox::NameAddr(na) => na.name(),
+            Mailbox::AddrSpec(_) => None,
+        }
+    }
+
+    /// Returns the first comment, if any.
+    ///
+    /// Comments in bare `addr-spec`s are ignored.
+    pub fn comment(&self) -> Option<&str> {
+        match self {
+            Mailbox::NameAddr(na) => na.comment(),
+            Mailbox::AddrSpec(_) => None,
+        }
+    }
+
+    /// Returns the address.
+    pub fn address(&self) -> &str {
+        match self {
+            // A name-addr always has an address.
+            Mailbox::NameAddr(na) => na.address().expect("has address"),
+            Mailbox::AddrSpec(a) => a.address(),
+        }
+    }
+}
+
+/// A parsed [RFC 2822 `group`].
+///
+/// `group`s are of the form:
+///
+/// ```text
+/// Friends: alice@example.org, Bob <bob@example.org>;
+/// ```
+///
+/// The list of mailboxes may be empty.
+///
+/// [RFC 2822 `group`]: https://tools.ietf.org/html/rfc2822#section-3.4
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Group {
+    name: String,
+    mailboxes: Vec<Mailbox>,
+}
+
+impl Group {
+    /// Returns the group's [display name].
+    ///
+    /// [RFC 2047] encoded-words are decoded.
+    ///
+    /// [display name]: https://tools.ietf.org/html/rfc2822#section-3.4
+    /// [RFC 2047]: https://tools.ietf.org/html/rfc2047
+    pub fn name(&self) -> &str {
+        &self.name[..]
+    }
+
+    /// Returns the group's members.
+    pub fn mailboxes(&self) -> &[Mailbox] {
+        &self.mailboxes[..]
+    }
+}
+
+/// A parsed [RFC 2822 `address`].
+///
+/// [RFC 2822 `address`]: https://tools.ietf.org/html/rfc2822#section-3.4
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Address {
+    /// A single mailbox.
+    Mailbox(Mailbox),
+    /// A named group of mailboxes.
+    Group(Group),
+}
+
+/// A parsed [RFC 2822 `mailbox-list`].
+///
+/// This is the content of, e.g., the `From` header.  The list must
+/// contain at least one mailbox.  As permitted by the obsolete
+/// syntax, empty list elements are ignored.
+///
+/// [RFC 2822 `mailbox-list`]: https://tools.ietf.org/html/rfc2822#section-3.4
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MailboxList {
+    mailboxes: Vec<Mailbox>,
+}
+
+impl MailboxList {
+    /// Parses a string that allegedly contains an [RFC 2822
+    /// `mailbox-list`].
+    ///
+    /// [RFC 2822 `mailbox-list`]: https://tools.ietf.org/html/rfc2822#section-3.4
+    pub fn parse<S>(input: S) -> Result<Self>
+        where S: AsRef<str>
+    {
+        let lexer = lexer::Lexer::new(input.as_ref());
+        let mailboxes: Vec<Mailbox> =
+            match grammar::MailboxListParser::new().parse(lexer) {
+                Ok(l) => l.into_iter().map(|m| m.into()).collect(),
+                Err(err) => return Err(parse_error_downcast(err).into()),
+            };
+
+        if mailboxes.is_empty() {
+            return Err(failure::err_msg("Empty mailbox-list"));
+        }
+
+        Ok(MailboxList {
+            mailboxes,
+        })
+    }
+
+    /// Returns the mailboxes.
+    pub fn mailboxes(&self) -> &[Mailbox] {
+        &self.mailboxes[..]
+    }
+}
+
+/// A parsed [RFC 2822 `address-list`].
+///
+/// This is the content of, e.g., the `To` and `Cc` headers.  Each
+/// element is either a mailbox or a group.  As permitted by the
+/// obsolete syntax, empty list elements are ignored.
+///
+/// [RFC 2822 `address-list`]: https://tools.ietf.org/html/rfc2822#section-3.4
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AddressList {
+    addresses: Vec<Address>,
+}
+
+impl AddressList {
+    /// Parses a string that allegedly contains an [RFC 2822
+    /// `address-list`].
+    ///
+    /// [RFC 2822 `address-list`]: https://tools.ietf.org/html/rfc2822#section-3.4
+    pub fn parse<S>(input: S) -> Result<Self>
+        where S: AsRef<str>
+    {
+        let lexer = lexer::Lexer::new(input.as_ref());
+        let addresses: Vec<Address> =
+            match grammar::AddressListParser::new().parse(lexer) {
+                Ok(l) => l.into_iter().map(|a| a.into()).collect(),
+                Err(err) => return Err(parse_error_downcast(err).into()),
+            };
+
+        if addresses.is_empty() {
+            return Err(failure::err_msg("Empty address-list"));
+        }
+
+        Ok(AddressList {
+            addresses,
+        })
+    }
+
+    /// Returns the addresses.
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses[..]
+    }
+
+    /// Returns all mailboxes, including the members of any groups.
+    pub fn mailboxes(&self) -> Vec<&Mailbox> {
+        let mut mailboxes = Vec::new();
+        for a in self.addresses.iter() {
+            match a {
+                Address::Mailbox(m) => mailboxes.push(m),
+                Address::Group(g) => mailboxes.extend(g.mailboxes.iter()),
+            }
+        }
+        mailboxes
+    }
+}
+
+// A mailbox as recognized by the grammar.
+//
+// The components are those of the corresponding `name-addr` or
+// `addr-spec`.  Encoded-words are decoded when converting it into a
+// `Mailbox`.
+#[derive(Debug)]
+pub(crate) enum MailboxParts {
+    NameAddr(Vec<Component>),
+    AddrSpec(Vec<Component>),
+}
+
+impl From<MailboxParts> for Mailbox {
+    fn from(m: MailboxParts) -> Self {
+        match m {
+            MailboxParts::NameAddr(c) => Mailbox::NameAddr(NameAddr {
+                components: encoded_word::decode_components(c),
+            }),
+            MailboxParts::AddrSpec(c) => Mailbox::AddrSpec(AddrSpec {
+                components: encoded_word::decode_components(c),
+            }),
+        }
+    }
+}
+
+// An address as recognized by the grammar.
+//
+// A group consists of the components of its display name and its
+// mailboxes.
+#[derive(Debug)]
+pub(crate) enum AddressParts {
+    Mailbox(MailboxParts),
+    Group(Vec<Component>, Vec<MailboxParts>),
+}
+
+impl From<AddressParts> for Address {
+    fn from(a: AddressParts) -> Self {
+        match a {
+            AddressParts::Mailbox(m) => Address::Mailbox(m.into()),
+            AddressParts::Group(name, mailboxes) => {
+                // A display name always has text.  Its encoded-words
+                // have already been decoded by the grammar.
+                let name = name.into_iter()
+                    .filter_map(|c| match c {
+                        Component::Text(t) => Some(t),
+                        _ => None,
+                    })
+                    .next()
+                    .expect("display name has text");
+
+                Address::Group(Group {
+                    name,
+                    mailboxes: mailboxes.into_iter()
+                        .map(|m| m.into()).collect(),
+                })
+            },
+        }
+    }
+}
@@ -21,6 +21,11 @@ pub enum Component {
     Address(String),
     // White space.
     WS,
+    // A decoded RFC 2047 encoded-word.
+    //
+    // This is only used while parsing a phrase: the phrase
+    // production turns it into a Text.
+    EncodedWord(String),
 }
 
 impl From<Component> for Vec<Component> {
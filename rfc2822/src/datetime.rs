@@ -0,0 +1,298 @@
+use std::fmt;
+
+use failure;
+
+use lexer;
+use grammar;
+use parse_error_downcast;
+use Result;
+
+const DAY_NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// The fields of a date-time as recognized by the grammar.
+//
+// The lexer doesn't distinguish between letters and digits so the
+// grammar can't validate the individual fields.  That is done by
+// `DateTime::parse`.
+#[derive(Debug)]
+pub(crate) struct DateTimeParts {
+    pub(crate) day_of_week: Option<String>,
+    pub(crate) day: String,
+    pub(crate) month: String,
+    pub(crate) year: String,
+    pub(crate) hour: String,
+    pub(crate) minute: String,
+    pub(crate) second: Option<String>,
+    pub(crate) zone: String,
+}
+
+/// A parsed [RFC 2822 `date-time`].
+///
+/// `date-time`s are of the form:
+///
+/// ```text
+/// Mon, 18 Jun 2018 19:21:10 +0200
+/// ```
+///
+/// The obsolete syntax is also recognized.  In particular, two and
+/// three digit years are interpreted as described in [Section 4.3],
+/// and the obsolete alphabetic time zones are converted to offsets.
+/// The military time zones are treated as `-0000`, i.e., the offset
+/// is unknown.
+///
+/// The day of the week is optional.  If present, it must be a valid
+/// day name, but it is not checked against the date.
+///
+/// [RFC 2822 `date-time`]: https://tools.ietf.org/html/rfc2822#section-3.3
+/// [Section 4.3]: https://tools.ietf.org/html/rfc2822#section-4.3
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DateTime {
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    offset: i32,
+}
+
+impl DateTime {
+    /// Creates a `DateTime`.
+    ///
+    /// `offset` is the time zone's offset from UTC in minutes.
+    pub fn new(year: i32, month: u8, day: u8,
+               hour: u8, minute: u8, second: u8, offset: i32)
+        -> Result<Self>
+    {
+        if year < 0 || year > 9999 {
+            return Err(failure::err_msg(
+                format!("Year out of range: {}", year)));
+        }
+        if month < 1 || month > 12 {
+            return Err(failure::err_msg(
+                format!("Month out of range: {}", month)));
+        }
+        if day < 1 || day > days_in_month(year, month) {
+            return Err(failure::err_msg(
+                format!("Day out of range: {}", day)));
+        }
+        if hour > 23 {
+            return Err(failure::err_msg(
+                format!("Hour out of range: {}", hour)));
+        }
+        if minute > 59 {
+            return Err(failure::err_msg(
+                format!("Minute out of range: {}", minute)));
+        }
+        // Allow for leap seconds.
+        if second > 60 {
+            return Err(failure::err_msg(
+                format!("Second out of range: {}", second)));
+        }
+        if offset <= -24 * 60 || offset >= 24 * 60 {
+            return Err(failure::err_msg(
+                format!("Time zone offset out of range: {}", offset)));
+        }
+
+        Ok(DateTime {
+            year, month, day, hour, minute, second, offset,
+        })
+    }
+
+    /// Parses a string that allegedly contains an [RFC 2822
+    /// `date-time`].
+    ///
+    /// [RFC 2822 `date-time`]: https://tools.ietf.org/html/rfc2822#section-3.3
+    pub fn parse<S>(input: S) -> Result<Self>
+        where S: AsRef<str>
+    {
+        let lexer = lexer::Lexer::new(input.as_ref());
+        let p = match grammar::DateTimeParser::new().parse(lexer) {
+            Ok(p) => p,
+            Err(err) => return Err(parse_error_downcast(err).into()),
+        };
+
+        if let Some(ref d) = p.day_of_week {
+            if ! DAY_NAMES.iter().any(|n| n.eq_ignore_ascii_case(d)) {
+                return Err(failure::err_msg(
+                    format!("Invalid day of week: {:?}", d)));
+            }
+        }
+
+        let day = number(&p.day, 1, 2, "day")?;
+        let month = MONTH_NAMES.iter()
+            .position(|n| n.eq_ignore_ascii_case(&p.month))
+            .ok_or_else(|| failure::err_msg(
+                format!("Invalid month: {:?}", p.month)))?
+            + 1;
+
+        let year = number(&p.year, 2, 4, "year")?;
+        let year = match p.year.len() {
+            // obs-year: "Where a two or three digit year occurs in a
+            // date, the year is to be interpreted as follows: If a
+            // two digit year is encountered whose value is between
+            // 00 and 49, the year is interpreted by adding 2000 [...].
+            // If a two digit year is encountered with a value between
+            // 50 and 99, or any three digit year is encountered, the
+            // year is interpreted by adding 1900."
+            2 if year < 50 => year + 2000,
+            2 | 3 => year + 1900,
+            _ => year,
+        };
+
+        let hour = number(&p.hour, 2, 2, "hour")?;
+        let minute = number(&p.minute, 2, 2, "minute")?;
+        let second = if let Some(ref s) = p.second {
+            number(s, 2, 2, "second")?
+        } else {
+            0
+        };
+
+        let offset = zone(&p.zone)?;
+
+        Self::new(year as i32, month as u8, day as u8,
+                  hour as u8, minute as u8, second as u8, offset)
+    }
+
+    /// Returns the year.
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    /// Returns the month (1 through 12).
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    /// Returns the day of the month (1 through 31).
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    /// Returns the hour (0 through 23).
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    /// Returns the minute (0 through 59).
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    /// Returns the second (0 through 60).
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    /// Returns the time zone's offset from UTC in minutes.
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// Returns the day of the week (0 is Sunday, 6 is Saturday).
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday.
+        let days = days_from_civil(self.year, self.month, self.day);
+        (((days % 7) + 7 + 4) % 7) as u8
+    }
+
+    /// Returns the time as seconds since the Unix epoch.
+    pub fn timestamp(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 24 * 60 * 60
+            + self.hour as i64 * 60 * 60
+            + self.minute as i64 * 60
+            + self.second as i64
+            - self.offset as i64 * 60
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(f, "{}, {} {} {:04} {:02}:{:02}:{:02} {}{:02}{:02}",
+               DAY_NAMES[self.weekday() as usize],
+               self.day, MONTH_NAMES[self.month as usize - 1], self.year,
+               self.hour, self.minute, self.second,
+               sign, offset / 60, offset % 60)
+    }
+}
+
+// Parses a decimal number with between min and max digits.
+fn number(s: &str, min: usize, max: usize, what: &str) -> Result<u32> {
+    if s.len() < min || s.len() > max
+        || ! s.chars().all(|c| c.is_ascii_digit())
+    {
+        return Err(failure::err_msg(
+            format!("Invalid {}: {:?}", what, s)));
+    }
+
+    Ok(s.parse()?)
+}
+
+// Converts a zone to an offset in minutes.
+fn zone(z: &str) -> Result<i32> {
+    if z.starts_with('+') || z.starts_with('-') {
+        let n = number(&z[1..], 4, 4, "zone")? as i32;
+        if n % 100 > 59 {
+            return Err(failure::err_msg(
+                format!("Invalid zone: {:?}", z)));
+        }
+        let offset = n / 100 * 60 + n % 100;
+        return Ok(if z.starts_with('-') { -offset } else { offset });
+    }
+
+    // obs-zone
+    let offset = match &z.to_ascii_uppercase()[..] {
+        "UT" | "GMT" => 0,
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        // "Other multi-character (usually between 3 and 5)
+        // alphabetic time zones have been used in Internet messages.
+        // Any such time zone whose meaning is not known SHOULD be
+        // considered equivalent to "-0000" unless there is a priori
+        // knowledge [...]."  The military zones are defined
+        // incorrectly in RFC 822, so they are also considered
+        // equivalent to "-0000".
+        m if ((m.len() == 1 && m != "J") || (m.len() >= 3 && m.len() <= 5))
+            && m.chars().all(|c| c.is_ascii_alphabetic()) => 0,
+        _ => return Err(failure::err_msg(
+            format!("Invalid zone: {:?}", z))),
+    };
+
+    Ok(offset * 60)
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Returns the number of days since 1970-01-01.
+//
+// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let y = if month <= 2 { year as i64 - 1 } else { year as i64 };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
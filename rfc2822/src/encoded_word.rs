@@ -0,0 +1,188 @@
+use base64;
+
+use component::Component;
+
+// Decodes any RFC 2047 encoded-words in the comments.
+//
+// Encoded-words in the display name are decoded by the phrase
+// production (see decode_atom).  The address is never decoded:
+// encoded-words are not allowed in an addr-spec.
+pub(crate) fn decode_components(components: Vec<Component>)
+    -> Vec<Component>
+{
+    components.into_iter()
+        .map(|c| match c {
+            Component::Comment(t) => Component::Comment(decode(&t)),
+            c => c,
+        })
+        .collect()
+}
+
+// Decodes an atom in a phrase, if it is an encoded-word.
+//
+// RFC 2047 only allows encoded-words as atoms in a phrase, in
+// particular "an 'encoded-word' MUST NOT appear within a
+// 'quoted-string'".
+pub(crate) fn decode_atom(atom: Component) -> Component {
+    match atom {
+        Component::Text(t) => match decode_word(&t) {
+            Some(decoded) => Component::EncodedWord(decoded),
+            None => Component::Text(t),
+        },
+        c => c,
+    }
+}
+
+// Finishes decoding a phrase's encoded-words.
+//
+// "Any 'linear-white-space' that separates a pair of adjacent
+// 'encoded-word's is ignored."  The decoded encoded-words are turned
+// into Texts.
+pub(crate) fn decode_phrase(components: Vec<Component>) -> Vec<Component> {
+    let mut r: Vec<Component> = Vec::with_capacity(components.len());
+    let mut iter = components.into_iter().peekable();
+    while let Some(c) = iter.next() {
+        if let Component::WS = c {
+            if destructures_to!(Some(Component::EncodedWord(_)) = r.last())
+                && destructures_to!(
+                    Some(Component::EncodedWord(_)) = iter.peek())
+            {
+                continue;
+            }
+        }
+        r.push(c);
+    }
+
+    r.into_iter()
+        .map(|c| match c {
+            Component::EncodedWord(t) => Component::Text(t),
+            c => c,
+        })
+        .collect()
+}
+
+// Decodes the encoded-words in `s`.
+//
+// "Any 'linear-white-space' that separates a pair of adjacent
+// 'encoded-word's is ignored."  Encoded-words that we can't decode,
+// e.g., because they are malformed or use an unsupported charset,
+// are left as is.
+pub(crate) fn decode(s: &str) -> String {
+    // Fast path.
+    if ! s.contains("=?") {
+        return s.to_string();
+    }
+
+    // Appends the current word (and the preceding whitespace) to r.
+    fn flush(r: &mut String, ws: &mut String, word: &mut String,
+             last_encoded: &mut bool) {
+        if word.is_empty() {
+            return;
+        }
+
+        if let Some(decoded) = decode_word(word) {
+            if ! *last_encoded {
+                r.push_str(ws);
+            }
+            r.push_str(&decoded);
+            *last_encoded = true;
+        } else {
+            r.push_str(ws);
+            r.push_str(word);
+            *last_encoded = false;
+        }
+        ws.clear();
+        word.clear();
+    }
+
+    let mut r = String::with_capacity(s.len());
+    // The whitespace preceding the current word.
+    let mut ws = String::new();
+    let mut word = String::new();
+    // Whether the last word was an encoded-word.
+    let mut last_encoded = false;
+
+    for c in s.chars() {
+        if c == ' ' || c == '\t' {
+            flush(&mut r, &mut ws, &mut word, &mut last_encoded);
+            ws.push(c);
+        } else {
+            word.push(c);
+        }
+    }
+    flush(&mut r, &mut ws, &mut word, &mut last_encoded);
+    r.push_str(&ws);
+
+    r
+}
+
+// Decodes a single encoded-word.
+//
+// encoded-word = "=?" charset "?" encoding "?" encoded-text "?="
+fn decode_word(word: &str) -> Option<String> {
+    if ! (word.len() > 4 && word.starts_with("=?") && word.ends_with("?=")) {
+        return None;
+    }
+
+    let mut parts = word[2..word.len() - 2].splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let text = parts.next()?;
+    if text.contains('?') {
+        return None;
+    }
+
+    // RFC 2231 allows a language to be appended to the charset,
+    // e.g., "US-ASCII*EN".
+    let charset = charset.splitn(2, '*').next()?;
+
+    let bytes = match &encoding.to_ascii_uppercase()[..] {
+        "B" => base64_decode(text)?,
+        "Q" => q_decode(text)?,
+        _ => return None,
+    };
+
+    match &charset.to_ascii_uppercase()[..] {
+        "UTF-8" | "UTF8" => String::from_utf8(bytes).ok(),
+        "US-ASCII" if bytes.iter().all(|b| b.is_ascii()) =>
+            String::from_utf8(bytes).ok(),
+        // The first 256 Unicode code points are ISO-8859-1.
+        "ISO-8859-1" | "LATIN1" =>
+            Some(bytes.into_iter().map(|b| b as char).collect()),
+        _ => None,
+    }
+}
+
+// The "Q" encoding is similar to quoted-printable, but "_" stands
+// for a space.
+fn q_decode(text: &str) -> Option<Vec<u8>> {
+    let mut r = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'_' => r.push(b' '),
+            b'=' => {
+                let hi = hex_digit(bytes.next()?)?;
+                let lo = hex_digit(bytes.next()?)?;
+                r.push(hi << 4 | lo);
+            },
+            b => r.push(b),
+        }
+    }
+    Some(r)
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Decodes base64.  The padding is optional.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    base64::decode_config(text.trim_end_matches('='), base64::STANDARD_NO_PAD)
+        .ok()
+}
@@ -0,0 +1,230 @@
+use failure;
+
+use {AddressList, DateTime, MailboxList, Result};
+
+/// A parsed [RFC 2822 header field].
+///
+/// Header fields are of the form:
+///
+/// ```text
+/// Subject: This is a test
+///  with a folded body
+/// ```
+///
+/// The body is unfolded, i.e., every CRLF that is immediately
+/// followed by whitespace is removed, and leading and trailing
+/// whitespace is stripped.  Bare LFs are treated like CRLFs.
+///
+/// The body is not interpreted.  For structured header fields, use
+/// [`address_list`], [`mailbox_list`], or [`date_time`].
+///
+/// [RFC 2822 header field]: https://tools.ietf.org/html/rfc2822#section-2.2
+/// [`address_list`]: #method.address_list
+/// [`mailbox_list`]: #method.mailbox_list
+/// [`date_time`]: #method.date_time
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HeaderField {
+    name: String,
+    body: String,
+}
+
+impl HeaderField {
+    /// Parses a string that allegedly contains a single [RFC 2822
+    /// header field].
+    ///
+    /// A trailing line ending is ignored.
+    ///
+    /// [RFC 2822 header field]: https://tools.ietf.org/html/rfc2822#section-2.2
+    pub fn parse<S>(input: S) -> Result<Self>
+        where S: AsRef<str>
+    {
+        let input = input.as_ref();
+        let input = chomp(input);
+
+        let colon = input.find(':').ok_or_else(
+            || failure::err_msg("Header field lacks a colon"))?;
+        let name = &input[..colon];
+
+        // field-name      =       1*ftext
+        // ftext           =       %d33-57 /               ; Any character except
+        //                         %d59-126                ;  controls, SP, and
+        //                                                 ;  ":".
+        //
+        // As per obs-optional, there may be whitespace between the
+        // name and the colon.
+        let name = name.trim_end_matches(|c: char| c == ' ' || c == '\t');
+        if name.is_empty() || ! name.chars().all(|c| c > ' ' && c <= '~') {
+            return Err(failure::err_msg(
+                format!("Invalid header field name: {:?}", name)));
+        }
+
+        Ok(HeaderField {
+            name: name.into(),
+            body: unfold(&input[colon + 1..])?,
+        })
+    }
+
+    /// Returns the field's name.
+    pub fn name(&self) -> &str {
+        &self.name[..]
+    }
+
+    /// Returns the field's unfolded body.
+    pub fn body(&self) -> &str {
+        &self.body[..]
+    }
+
+    /// Returns whether the field's name is `name`.
+    ///
+    /// Field names are compared case-insensitively.
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    /// Parses the body as an [`address-list`].
+    ///
+    /// This is appropriate for, e.g., the `To` and `Cc` fields.
+    ///
+    /// [`address-list`]: struct.AddressList.html
+    pub fn address_list(&self) -> Result<AddressList> {
+        AddressList::parse(&self.body)
+    }
+
+    /// Parses the body as a [`mailbox-list`].
+    ///
+    /// This is appropriate for, e.g., the `From` field.
+    ///
+    /// [`mailbox-list`]: struct.MailboxList.html
+    pub fn mailbox_list(&self) -> Result<MailboxList> {
+        MailboxList::parse(&self.body)
+    }
+
+    /// Parses the body as a [`date-time`].
+    ///
+    /// This is appropriate for, e.g., the `Date` field.
+    ///
+    /// [`date-time`]: struct.DateTime.html
+    pub fn date_time(&self) -> Result<DateTime> {
+        DateTime::parse(&self.body)
+    }
+}
+
+/// A parsed [RFC 2822 header].
+///
+/// The header is the sequence of header fields at the start of a
+/// message.  It is terminated by an empty line, or the end of the
+/// input.  Anything following the empty line, i.e., the message's
+/// body, is ignored.
+///
+/// [RFC 2822 header]: https://tools.ietf.org/html/rfc2822#section-2.2
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Header {
+    fields: Vec<HeaderField>,
+}
+
+impl Header {
+    /// Parses a string that allegedly starts with an [RFC 2822
+    /// header].
+    ///
+    /// [RFC 2822 header]: https://tools.ietf.org/html/rfc2822#section-2.2
+    pub fn parse<S>(input: S) -> Result<Self>
+        where S: AsRef<str>
+    {
+        let input = input.as_ref();
+        let mut fields = Vec::new();
+
+        // The start of the current field, if any.
+        let mut start: Option<usize> = None;
+        let mut offset = 0;
+        loop {
+            let end = input[offset..].find('\n')
+                .map(|i| offset + i + 1)
+                .unwrap_or(input.len());
+            let line = &input[offset..end];
+
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // A continuation line.
+                if start.is_none() {
+                    return Err(failure::err_msg(
+                        "Header starts with a continuation line"));
+                }
+            } else {
+                if let Some(start) = start.take() {
+                    fields.push(HeaderField::parse(&input[start..offset])?);
+                }
+
+                if chomp(line).is_empty() {
+                    // The end of the header, or the end of the input.
+                    break;
+                }
+                start = Some(offset);
+            }
+
+            offset = end;
+        }
+
+        Ok(Header {
+            fields,
+        })
+    }
+
+    /// Returns the header fields in the order they appeared.
+    pub fn fields(&self) -> &[HeaderField] {
+        &self.fields[..]
+    }
+
+    /// Returns the first field with the given name.
+    ///
+    /// Field names are compared case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&HeaderField> {
+        self.fields.iter().find(|f| f.is(name))
+    }
+
+    /// Returns all fields with the given name.
+    ///
+    /// Field names are compared case-insensitively.
+    pub fn get_all(&self, name: &str) -> Vec<&HeaderField> {
+        self.fields.iter().filter(|f| f.is(name)).collect()
+    }
+}
+
+// Strips a trailing line ending.
+fn chomp(s: &str) -> &str {
+    if s.ends_with("\r\n") {
+        &s[..s.len() - 2]
+    } else if s.ends_with('\n') {
+        &s[..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+// Unfolds a field body.
+//
+// "Unfolding is accomplished by simply removing any CRLF that is
+// immediately followed by WSP."
+fn unfold(body: &str) -> Result<String> {
+    let mut s = String::with_capacity(body.len());
+    let mut lines = body.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        let line = if line.ends_with('\r') {
+            &line[..line.len() - 1]
+        } else {
+            line
+        };
+
+        if line.contains('\r') {
+            return Err(failure::err_msg("Bare CR in header field"));
+        }
+        s.push_str(line);
+
+        if let Some(next) = lines.peek() {
+            if ! (next.starts_with(' ') || next.starts_with('\t')) {
+                return Err(failure::err_msg(
+                    "Line break not followed by whitespace"));
+            }
+        }
+    }
+
+    Ok(s.trim_matches(|c: char| c == ' ' || c == '\t').into())
+}
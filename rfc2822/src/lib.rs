@@ -1,24 +1,33 @@
-//! This crates provides support for parsing a subset of [RFC 2822].
-//! In particular, it exports functions that parse a string according
-//! to the [`name-addr`] production, and the [`addr-spec`] production.
+//! This crates provides support for parsing [RFC 2822].  In
+//! particular, it exports functions that parse a string according to
+//! the [`name-addr`], [`addr-spec`], [`mailbox`], [`mailbox-list`],
+//! [`address-list`], and [`date-time`] productions.  It also parses
+//! [`message headers`] into their fields, and unfolds the fields'
+//! bodies.
 //!
-//! This crate does not yet parse [RFC 2822] [`dates and times`] or
-//! [`message headers`].  Given the infrastructure, adding support for
-//! these productions should be straightforward.  However, the main
-//! user of this crate is [Sequoia], an [OpenPGP implementation], and
-//! it only uses this crate to parse [User IDs], which usually include
-//! an [RFC 2822] mail [`name-addr`].  If you require this
-//! functionality, please feel free to open an [issue].
+//! [RFC 2047] encoded-words in display names and comments are
+//! decoded, except in quoted strings.  As permitted by [RFC 6532], UTF-8 is accepted everywhere,
+//! including in addresses.
+//!
+//! The main user of this crate is [Sequoia], an [OpenPGP
+//! implementation].  It uses this crate to parse [User IDs], which
+//! usually include an [RFC 2822] mail [`name-addr`], and to parse the
+//! headers of mails, e.g., for [Autocrypt].
 //!
 //!   [RFC 2822]: https://tools.ietf.org/html/rfc2822
+//!   [RFC 2047]: https://tools.ietf.org/html/rfc2047
+//!   [RFC 6532]: https://tools.ietf.org/html/rfc6532
 //!   [`name-addr`]: https://tools.ietf.org/html/rfc2822#section-3.4
 //!   [`addr-spec`]: https://tools.ietf.org/html/rfc2822#section-3.4.1
-//!   [`dates and times`]: https://tools.ietf.org/html/rfc2822#section-3.3
-//!   [`message headers`]: https://tools.ietf.org/html/rfc2822#section-3.6
+//!   [`mailbox`]: https://tools.ietf.org/html/rfc2822#section-3.4
+//!   [`mailbox-list`]: https://tools.ietf.org/html/rfc2822#section-3.4
+//!   [`address-list`]: https://tools.ietf.org/html/rfc2822#section-3.4
+//!   [`date-time`]: https://tools.ietf.org/html/rfc2822#section-3.3
+//!   [`message headers`]: https://tools.ietf.org/html/rfc2822#section-2.2
 //!   [Sequoia]: https://sequoia-pgp.org/
 //!   [OpenPGP implementation]: https://tools.ietf.org/html/rfc4880
 //!   [User IDs]: https://tools.ietf.org/html/rfc4880#section-5.11
-//!   [issue]: https://gitlab.com/sequoia-pgp/sequoia/issues
+//!   [Autocrypt]: https://autocrypt.org/
 //!
 //! # Examples
 //!
@@ -57,7 +66,49 @@
 //! assert!(AddrSpec::parse("<pippy@jerome-horwitz.k12.oh.us>")
 //!        .is_err());
 //! ```
+//!
+//! Parsing an [`address-list`], which may contain groups:
+//!
+//! ```
+//! use sequoia_rfc2822::{Address, AddressList};
+//!
+//! let list = AddressList::parse(
+//!     "Pippy <pippy@example.org>, Friends: alice@example.org, \
+//!      \"Bob, Jr.\" <bob@example.org>;")
+//!     .expect("Valid address-list");
+//! assert_eq!(list.addresses().len(), 2);
+//! if let Address::Group(ref g) = list.addresses()[1] {
+//!     assert_eq!(g.name(), "Friends");
+//!     assert_eq!(g.mailboxes()[1].name(), Some("Bob, Jr."));
+//! } else {
+//!     panic!("Expected a group");
+//! }
+//! assert_eq!(list.mailboxes().iter().map(|m| m.address())
+//!            .collect::<Vec<_>>(),
+//!            vec!["pippy@example.org", "alice@example.org",
+//!                 "bob@example.org"]);
+//! ```
+//!
+//! Parsing a header:
+//!
+//! ```
+//! use sequoia_rfc2822::Header;
+//!
+//! let header = Header::parse(
+//!     "From: Pippy <pippy@example.org>\r\n\
+//!      Date: Mon, 18 Jun 2018\r\n\
+//!      \x20 19:21:10 +0200\r\n\
+//!      \r\n\
+//!      Body.\r\n")
+//!     .expect("Valid header");
+//! assert_eq!(header.fields().len(), 2);
+//!
+//! let date = header.get("date").unwrap();
+//! assert_eq!(date.body(), "Mon, 18 Jun 2018  19:21:10 +0200");
+//! assert_eq!(date.date_time().unwrap().timestamp(), 1529342470);
+//! ```
 
+extern crate base64;
 extern crate failure;
 extern crate lalrpop_util;
 
@@ -75,6 +126,22 @@ use component::{
     Component
 };
 mod lexer;
+mod encoded_word;
+mod address;
+pub use address::{
+    Address,
+    AddressList,
+    Group,
+    Mailbox,
+    MailboxList,
+};
+mod datetime;
+pub use datetime::DateTime;
+mod header;
+pub use header::{
+    Header,
+    HeaderField,
+};
 
 // We expose a number of productions for testing purposes.
 // Unfortunately, lalrpop doesn't understand the #[cfg(test)]
@@ -132,6 +199,58 @@ fn parse_error_downcast<'a>(e: ParseError<usize, lexer::Token<'a>, Error>)
     }
 }
 
+// Returns whether c is an atext character.
+//
+// As an extension, we consider all non-ASCII characters to be atext.
+fn is_atext(c: char) -> bool {
+    match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' => true,
+        '!' | '#' | '$' | '%' | '&' | '\'' | '*' | '+' | '-' | '/' | '='
+            | '?' | '^' | '_' | '`' | '{' | '|' | '}' | '~' => true,
+        c => ! c.is_ascii(),
+    }
+}
+
+// Quotes a display name, if necessary.
+//
+// A display name is a phrase, i.e., a sequence of atoms and quoted
+// strings.  If the name is a sequence of atoms separated by single
+// spaces, it is returned as is.  Otherwise, it is turned into a
+// quoted string.  Names that look like they contain RFC 2047
+// encoded-words are quoted too, since encoded-words are not decoded
+// in quoted strings.
+fn escape_display_name(name: &str) -> String {
+    if ! name.is_empty()
+        && ! name.contains("=?")
+        && name.split(' ').all(|w| ! w.is_empty() && w.chars().all(is_atext))
+    {
+        return name.into();
+    }
+
+    let mut s = String::with_capacity(name.len() + 2);
+    s.push('"');
+    for c in name.chars() {
+        if c == '"' || c == '\\' {
+            s.push('\\');
+        }
+        s.push(c);
+    }
+    s.push('"');
+    s
+}
+
+// Escapes the characters that are not allowed in a comment.
+fn escape_comment(comment: &str) -> String {
+    let mut s = String::with_capacity(comment.len());
+    for c in comment.chars() {
+        if c == '(' || c == ')' || c == '\\' {
+            s.push('\\');
+        }
+        s.push(c);
+    }
+    s
+}
+
 /// A parsed RFC 2822 `addr-spec`.
 ///
 /// The address must not include angle brackets.  That is, this parser
@@ -184,7 +303,7 @@ impl AddrSpec {
     {
         let lexer = lexer::Lexer::new(input.as_ref());
         let components = match grammar::AddrSpecParser::new().parse(lexer) {
-            Ok(components) => components,
+            Ok(components) => encoded_word::decode_components(components),
             Err(err) => return Err(parse_error_downcast(err).into()),
         };
 
@@ -232,59 +351,85 @@ pub struct NameAddr {
 
 impl NameAddr {
     /// Create an RFC 2822 `name-addr`.
-    ///
-    /// Not yet exported as this function does *not* do any escaping.
     #[allow(dead_code)]
     fn new<S>(name: Option<S>, comment: Option<S>, address: Option<S>)
         -> Result<Self>
-        where S: AsRef<str> + Eq + std::fmt::Debug,
+        where S: AsRef<str>,
     {
-        let mut s = if let Some(ref name) = name {
-            String::from(name.as_ref())
-        } else {
-            String::new()
-        };
+        let address = address.ok_or_else(
+            || failure::err_msg("A name-addr requires an address"))?;
+        Self::parse(Self::escaped(name, comment, address)?)
+    }
 
-        if let Some(ref comment) = comment {
-            if name.is_some() {
-                s.push(' ');
-            }
-            s.push_str(&format!("({})", comment.as_ref())[..]);
-        }
+    /// Returns the string representation of an [RFC 2822
+    /// `name-addr`] with the given components.
+    ///
+    /// The display name is quoted, and the comment is escaped, as
+    /// necessary.  The address is used as is, and must be a valid
+    /// [`addr-spec`].  The result is of the form:
+    ///
+    /// ```text
+    /// Name (Comment) <email@example.org>
+    /// ```
+    ///
+    /// To make sure the result can be used, it is parsed, and the
+    /// parsed components are compared to the given ones.  If they
+    /// don't match, e.g., because the name includes characters that
+    /// can't be expressed in a display name, this returns an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sequoia_rfc2822::NameAddr;
+    ///
+    /// assert_eq!(NameAddr::escaped(Some("Pippy P. Poopypants"),
+    ///                              Some("Prof. (retired)"),
+    ///                              "pippy@jerome-horwitz.k12.oh.us")
+    ///            .unwrap(),
+    ///            "\"Pippy P. Poopypants\" (Prof. \\(retired\\)) \
+    ///             <pippy@jerome-horwitz.k12.oh.us>");
+    /// ```
+    ///
+    /// [RFC 2822 `name-addr`]: https://tools.ietf.org/html/rfc2822#section-3.4
+    /// [`addr-spec`]: struct.AddrSpec.html
+    pub fn escaped<S>(name: Option<S>, comment: Option<S>, address: S)
+        -> Result<String>
+        where S: AsRef<str>,
+    {
+        let name = name.as_ref().map(|n| n.as_ref());
+        let comment = comment.as_ref().map(|c| c.as_ref());
+        let address = address.as_ref();
 
-        if let Some(ref address) = address {
-            if name.is_some() || comment.is_some() {
-                s.push(' ');
-            }
-            s.push_str(&format!("<{}>", address.as_ref())[..]);
+        let mut s = String::new();
+        if let Some(name) = name {
+            s.push_str(&escape_display_name(name));
+            s.push(' ');
         }
-
-        // Make sure the input is valid.
-        let na = match Self::parse(s) {
-            Err(err) => return Err(err.into()),
-            Ok(na) => na,
-        };
-
-        if let Some(name_reparsed) = na.name() {
-            assert!(name.is_some());
-            assert_eq!(name_reparsed, name.unwrap().as_ref());
-        } else {
-            assert!(name.is_none());
+        if let Some(comment) = comment {
+            s.push('(');
+            s.push_str(&escape_comment(comment));
+            s.push_str(") ");
         }
-        if let Some(comment_reparsed) = na.comment() {
-            assert!(comment.is_some());
-            assert_eq!(comment_reparsed, comment.unwrap().as_ref());
-        } else {
-            assert!(comment.is_none());
+        s.push('<');
+        s.push_str(address);
+        s.push('>');
+
+        // Make sure the result is valid, and that it round trips.
+        let na = Self::parse(&s)?;
+        if na.name() != name {
+            return Err(failure::err_msg(
+                format!("Display name {:?} does not round trip", name)));
         }
-        if let Some(address_reparsed) = na.address() {
-            assert!(address.is_some());
-            assert_eq!(address_reparsed, address.unwrap().as_ref());
-        } else {
-            assert!(address.is_none());
+        if na.comment() != comment {
+            return Err(failure::err_msg(
+                format!("Comment {:?} does not round trip", comment)));
+        }
+        if na.address() != Some(address) {
+            return Err(failure::err_msg(
+                format!("Address {:?} does not round trip", address)));
         }
 
-        Ok(na)
+        Ok(s)
     }
 
     /// Parses a string that allegedly contains an [RFC 2822
@@ -296,7 +441,7 @@ impl NameAddr {
     {
         let lexer = lexer::Lexer::new(input.as_ref());
         let components = match grammar::NameAddrParser::new().parse(lexer) {
-            Ok(components) => components,
+            Ok(components) => encoded_word::decode_components(components),
             Err(err) => return Err(parse_error_downcast(err).into()),
         };
 
@@ -307,7 +452,10 @@ impl NameAddr {
 
     /// Returns the [display name].
     ///
+    /// [RFC 2047] encoded-words are decoded.
+    ///
     /// [display name]: https://tools.ietf.org/html/rfc2822#section-3.4
+    /// [RFC 2047]: https://tools.ietf.org/html/rfc2047
     pub fn name(&self) -> Option<&str> {
         for c in self.components.iter() {
             if let Component::Text(t) = c {
@@ -318,6 +466,10 @@ impl NameAddr {
     }
 
     /// Returns the first comment.
+    ///
+    /// [RFC 2047] encoded-words are decoded.
+    ///
+    /// [RFC 2047]: https://tools.ietf.org/html/rfc2047
     pub fn comment(&self) -> Option<&str> {
         for c in self.components.iter() {
             if let Component::Comment(t) = c {
@@ -996,6 +1148,37 @@ mod tests {
         c("Mr. Meaner", "(Gym Teacher)", "kenny@jerome-horwitz.k12.us");
     }
 
+    #[test]
+    fn name_addr_escaped() {
+        fn c(name: Option<&str>, comment: Option<&str>, address: &str,
+             expected: Option<&str>)
+        {
+            let got = NameAddr::escaped(name, comment, address).ok();
+            assert_eq!(got.as_ref().map(|s| &s[..]), expected);
+        }
+
+        c(Some("Alice"), None, "alice@example.org",
+          Some("Alice <alice@example.org>"));
+        c(None, None, "alice@example.org", Some("<alice@example.org>"));
+        c(None, Some("Work"), "alice@example.org",
+          Some("(Work) <alice@example.org>"));
+        c(Some("Bob, Jr."), Some("x (y) \\ z"), "bob@example.org",
+          Some("\"Bob, Jr.\" (x \\(y\\) \\\\ z) <bob@example.org>"));
+        c(Some("The \"Bob\""), None, "bob@example.org",
+          Some("\"The \\\"Bob\\\"\" <bob@example.org>"));
+        c(Some("Émile Zola"), None, "émile@zola.fr",
+          Some("Émile Zola <émile@zola.fr>"));
+
+        // Runs of whitespace are folded.
+        c(Some("Alice  Lovelace"), None, "alice@example.org", None);
+        // Line breaks are not allowed.
+        c(Some("Alice\r\nLovelace"), None, "alice@example.org", None);
+        c(Some("Alice"), Some("\n"), "alice@example.org", None);
+        // The address is not escaped.
+        c(Some("Alice"), None, "alice@@example.org", None);
+        c(Some("Alice"), None, "<alice@example.org>", None);
+    }
+
     #[test]
     fn addr_spec_api() {
         fn c(email: &str, ok: bool)
@@ -1016,5 +1199,225 @@ mod tests {
         c("example@foo.com", true);
         c("<example@foo.com>", false);
         c("example@@foo.com", false);
+        c("first.last@foo.com", true);
+        c(".example@foo.com", false);
+        c("example.@foo.com", false);
+        c("first..last@foo.com", false);
+        c("first last@foo.com", false);
+        c("\"first\"\"last\"@foo.com", false);
+    }
+
+    #[test]
+    fn mailbox_api() {
+        let m = Mailbox::parse("Foo Bar (comment) <foo@example.org>").unwrap();
+        assert_eq!(m.name(), Some("Foo Bar"));
+        assert_eq!(m.comment(), Some("comment"));
+        assert_eq!(m.address(), "foo@example.org");
+
+        let m = Mailbox::parse(" <foo@example.org>").unwrap();
+        assert_eq!(m.name(), None);
+        assert_eq!(m.address(), "foo@example.org");
+
+        let m = Mailbox::parse("(comment) \"foo bar\"@example.org").unwrap();
+        if let Mailbox::NameAddr(_) = m {
+            panic!("Expected an addr-spec");
+        }
+        assert_eq!(m.name(), None);
+        assert_eq!(m.comment(), None);
+        assert_eq!(m.address(), "foo bar@example.org");
+
+        assert!(Mailbox::parse("").is_err());
+        assert!(Mailbox::parse("Foo Bar").is_err());
+        assert!(Mailbox::parse("Foo foo@example.org").is_err());
+        assert!(Mailbox::parse("foo@example.org, bar@example.org").is_err());
+    }
+
+    #[test]
+    fn encoded_words() {
+        fn c(input: &str, name: Option<&str>, comment: Option<&str>,
+             address: &str) {
+            let na = NameAddr::parse(input).unwrap();
+            assert_eq!(na.name(), name);
+            assert_eq!(na.comment(), comment);
+            assert_eq!(na.address(), Some(address));
+        }
+
+        c("=?UTF-8?Q?Andr=C3=A9_Lef=C3=A8vre?= <andre@example.org>",
+          Some("André Lefèvre"), None, "andre@example.org");
+        c("=?utf-8?b?QW5kcsOp?= (=?ISO-8859-1?Q?Lef=E8vre?=) \
+           <andre@example.org>",
+          Some("André"), Some("Lefèvre"), "andre@example.org");
+        // Whitespace between adjacent encoded-words is ignored, but
+        // not between encoded-words and other words.
+        c("=?UTF-8?Q?Andr=C3=A9?= \r\n =?UTF-8?Q?_Lef=C3=A8vre?= Jr. \
+           <andre@example.org>",
+          Some("André Lefèvre Jr."), None, "andre@example.org");
+        // Unpadded base64, and a language.
+        c("=?UTF-8*fr?B?QW5kcsOpIExlZsOodnJl?= <andre@example.org>",
+          Some("André Lefèvre"), None, "andre@example.org");
+        // Malformed encoded-words and unknown charsets are left as is.
+        c("=?UTF-8?X?Andr=C3=A9?= =?KOI8-R?Q?abc?= =?UTF-8?Q?=C3?= \
+           <andre@example.org>",
+          Some("=?UTF-8?X?Andr=C3=A9?= =?KOI8-R?Q?abc?= =?UTF-8?Q?=C3?="),
+          None, "andre@example.org");
+        // Encoded-words are not decoded in addresses.
+        c("<=?UTF-8?Q?a?=@example.org>", None, None,
+          "=?UTF-8?Q?a?=@example.org");
+        // Nor in quoted strings.
+        c("\"=?UTF-8?Q?Andr=C3=A9?=\" =?UTF-8?Q?Lef=C3=A8vre?= \
+           <andre@example.org>",
+          Some("=?UTF-8?Q?Andr=C3=A9?= Lefèvre"), None, "andre@example.org");
+        c("\"=?UTF-8?Q?Andr=C3=A9?= =?UTF-8?Q?_Lef=C3=A8vre?=\" \
+           <andre@example.org>",
+          Some("=?UTF-8?Q?Andr=C3=A9?= =?UTF-8?Q?_Lef=C3=A8vre?="), None,
+          "andre@example.org");
+        // Names that look like encoded-words are quoted.
+        let n = NameAddr::escaped(Some("=?UTF-8?Q?a?="), None,
+                                  "andre@example.org").unwrap();
+        assert_eq!(n, "\"=?UTF-8?Q?a?=\" <andre@example.org>");
+        // RFC 6532: UTF-8 everywhere.
+        c("André Lefèvre (Ingénieur) <andré@bücher.example>",
+          Some("André Lefèvre"), Some("Ingénieur"), "andré@bücher.example");
+
+        let list = AddressList::parse(
+            "=?UTF-8?Q?Fr=C3=BCnde?=: andre@example.org;").unwrap();
+        if let Address::Group(ref g) = list.addresses()[0] {
+            assert_eq!(g.name(), "Fründe");
+        } else {
+            panic!("Expected a group");
+        }
+    }
+
+    #[test]
+    fn address_list_api() {
+        // Returns the mailboxes' addresses, and the names of the groups.
+        fn c(input: &str) -> Option<(Vec<String>, Vec<String>)> {
+            let list = AddressList::parse(input).ok()?;
+            let addresses = list.mailboxes().iter()
+                .map(|m| m.address().to_string()).collect();
+            let groups = list.addresses().iter()
+                .filter_map(|a| if let Address::Group(g) = a {
+                    Some(g.name().to_string())
+                } else {
+                    None
+                })
+                .collect();
+            Some((addresses, groups))
+        }
+
+        fn v(s: &[&str]) -> Vec<String> {
+            s.iter().map(|s| s.to_string()).collect()
+        }
+
+        assert_eq!(c("foo@example.org"),
+                   Some((v(&["foo@example.org"]), v(&[]))));
+        assert_eq!(c("Foo <foo@example.org>, bar@example.org"),
+                   Some((v(&["foo@example.org", "bar@example.org"]),
+                         v(&[]))));
+        // Separators in quoted strings, comments, and domain literals.
+        assert_eq!(c("\"Foo, Bar: Baz;\" <foo@example.org>, \
+                      bar@example.org (a, b: c;), baz@[1,2]"),
+                   Some((v(&["foo@example.org", "bar@example.org",
+                             "baz@[1,2]"]),
+                         v(&[]))));
+        // Groups.
+        assert_eq!(c("undisclosed-recipients:;"),
+                   Some((v(&[]), v(&["undisclosed-recipients"]))));
+        assert_eq!(c("foo@example.org, Friends: bar@example.org, \
+                      Baz <baz@example.org>; (comment), qux@example.org"),
+                   Some((v(&["foo@example.org", "bar@example.org",
+                             "baz@example.org", "qux@example.org"]),
+                         v(&["Friends"]))));
+        // obs-addr-list allows empty elements.
+        assert_eq!(c(" , foo@example.org, (comment) ,"),
+                   Some((v(&["foo@example.org"]), v(&[]))));
+
+        assert_eq!(c(""), None);
+        assert_eq!(c("foo@example.org;"), None);
+        assert_eq!(c("Friends: foo@example.org"), None);
+        assert_eq!(c("A: B: foo@example.org;;"), None);
+        assert_eq!(c("Friends:; foo@example.org"), None);
+        assert_eq!(c("\"foo@example.org"), None);
+        assert_eq!(c("foo@example.org bar@example.org"), None);
+    }
+
+    #[test]
+    fn mailbox_list_api() {
+        let list = MailboxList::parse(
+            "Foo (comment) <foo@example.org>, bar@example.org").unwrap();
+        assert_eq!(list.mailboxes().len(), 2);
+        assert_eq!(list.mailboxes()[0].name(), Some("Foo"));
+        assert_eq!(list.mailboxes()[0].comment(), Some("comment"));
+        assert_eq!(list.mailboxes()[1].name(), None);
+        assert_eq!(list.mailboxes()[1].address(), "bar@example.org");
+
+        assert!(MailboxList::parse("").is_err());
+        assert!(MailboxList::parse("Friends: foo@example.org;").is_err());
+    }
+
+    #[test]
+    fn date_time_api() {
+        fn c(input: &str) -> Option<(String, i64)> {
+            DateTime::parse(input).ok()
+                .map(|d| (d.to_string(), d.timestamp()))
+        }
+
+        assert_eq!(c("Mon, 18 Jun 2018 19:21:10 +0200"),
+                   Some(("Mon, 18 Jun 2018 19:21:10 +0200".into(),
+                         1529342470)));
+        // The day of the week and the seconds are optional.
+        assert_eq!(c("18 Jun 2018 19:21 +0200"),
+                   Some(("Mon, 18 Jun 2018 19:21:00 +0200".into(),
+                         1529342460)));
+        // Obsolete syntax: comments, two digit years, alphabetic zones.
+        assert_eq!(c(" (x) Mon (y) , 1 (z) jan  70 00:00:00 GMT (UTC)"),
+                   Some(("Thu, 1 Jan 1970 00:00:00 +0000".into(), 0)));
+        assert_eq!(c("1 Mar 00 12:00 EST"),
+                   Some(("Wed, 1 Mar 2000 12:00:00 -0500".into(),
+                         951930000)));
+        assert_eq!(c("29 Feb 2000 12:00 -0130"),
+                   Some(("Tue, 29 Feb 2000 12:00:00 -0130".into(),
+                         951831000)));
+
+        assert_eq!(c("29 Feb 1900 12:00 +0000"), None);
+        assert_eq!(c("Foo, 18 Jun 2018 19:21:10 +0200"), None);
+        assert_eq!(c("18 Foo 2018 19:21:10 +0200"), None);
+        assert_eq!(c("18 Jun 2018 24:21:10 +0200"), None);
+        assert_eq!(c("18 Jun 2018 19:21:10 +0260"), None);
+        assert_eq!(c("18 Jun 2018 19:21:10"), None);
+        assert_eq!(c("18 Jun 2018 19-21-10 +0200"), None);
+    }
+
+    #[test]
+    fn header_api() {
+        let header = Header::parse(
+            "From: Foo <foo@example.org>\r\n\
+             To: bar@example.org,\r\n\
+             \tFriends: baz@example.org;\r\n\
+             Subject : Folded\n\
+             \x20 subject\n\
+             to: qux@example.org\r\n\
+             \r\n\
+             Not: a header\r\n").unwrap();
+
+        assert_eq!(header.fields().iter().map(|f| f.name())
+                   .collect::<Vec<_>>(),
+                   vec!["From", "To", "Subject", "to"]);
+        assert_eq!(header.get("from").unwrap().mailbox_list().unwrap()
+                   .mailboxes()[0].address(), "foo@example.org");
+        assert_eq!(header.get("To").unwrap().body(),
+                   "bar@example.org,\tFriends: baz@example.org;");
+        assert_eq!(header.get("To").unwrap().address_list().unwrap()
+                   .mailboxes().len(), 2);
+        assert_eq!(header.get("subject").unwrap().body(), "Folded  subject");
+        assert_eq!(header.get_all("TO").len(), 2);
+        assert!(header.get("Not").is_none());
+
+        assert!(Header::parse(" Foo: bar\r\n").is_err());
+        assert!(Header::parse("Foo bar\r\n").is_err());
+        assert!(HeaderField::parse("Fo o: bar").is_err());
+        assert!(HeaderField::parse(": bar").is_err());
+        assert!(HeaderField::parse("Foo: bar\r\nbaz").is_err());
+        assert_eq!(HeaderField::parse("Foo:").unwrap().body(), "");
     }
 }
@@ -6,6 +6,7 @@ use rand::Rng;
 use lexer;
 use grammar;
 use component::{Component, components_merge};
+use {Address, AddressList, DateTime, Header};
 
 // We put each type of token in its own struct, which contains exactly
 // one element, a String (e.g., 'Foo(String)').
@@ -1079,3 +1080,319 @@ token!(DText, g, {
         _ => unreachable!(),
     }
 });
+
+// Returns a random mailbox, and its address.
+//
+// mailbox         =       name-addr / addr-spec
+fn mailbox_arbitrary<G: Gen>(g: &mut G) -> (String, String) {
+    let input = match g.gen_range(0, 2) {
+        0 => NameAddr::arbitrary(g).to_input(),
+        1 => AddrSpec::arbitrary(g).to_input(),
+        _ => unreachable!(),
+    };
+
+    let address = input.c.iter()
+        .filter_map(|c| if let Component::Address(a) = c {
+            Some(a.clone())
+        } else {
+            None
+        })
+        .next()
+        .expect("a mailbox has an address");
+    (input.s, address)
+}
+
+// address-list    =       (address *("," address)) / obs-addr-list
+// address         =       mailbox / group
+// group           =       display-name ":" [mailbox-list / CFWS] ";"
+//                         [CFWS]
+//
+// We don't use the production! macro, because an address list is
+// not parsed into components.
+#[derive(Debug, Clone)]
+struct AddressListInput {
+    // For each address, the group's name (if it is a group) and the
+    // addresses of the mailboxes.
+    expected: Vec<(Option<String>, Vec<String>)>,
+    s: String,
+}
+
+impl Arbitrary for AddressListInput {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut expected = Vec::new();
+        let mut elements = Vec::new();
+
+        for _ in 0..g.gen_range(1, 4) {
+            if g.gen_range(0, 3) == 0 {
+                let name = DisplayName::arbitrary(g).to_input();
+                let text = name.c.iter()
+                    .filter_map(|c| if let Component::Text(t) = c {
+                        Some(t.clone())
+                    } else {
+                        None
+                    })
+                    .next()
+                    .expect("a display name has text");
+
+                let mut addresses = Vec::new();
+                let mut members = Vec::new();
+                for _ in 0..g.gen_range(0, 3) {
+                    let (s, a) = mailbox_arbitrary(g);
+                    members.push(s);
+                    addresses.push(a);
+                }
+
+                let mut s = format!("{}:", name.s);
+                if members.is_empty() {
+                    if let Some(cfws) = Option::<CFWS>::arbitrary(g) {
+                        s.push_str(&cfws.input());
+                    }
+                } else {
+                    s.push_str(&members.join(","));
+                }
+                s.push(';');
+                if let Some(cfws) = Option::<CFWS>::arbitrary(g) {
+                    s.push_str(&cfws.input());
+                }
+
+                elements.push(s);
+                expected.push((Some(text), addresses));
+            } else {
+                let (s, a) = mailbox_arbitrary(g);
+                elements.push(s);
+                expected.push((None, vec![ a ]));
+            }
+        }
+
+        AddressListInput {
+            expected,
+            s: elements.join(","),
+        }
+    }
+}
+
+quickcheck! {
+    fn address_list_roundtrip(t: AddressListInput) -> bool {
+        let list = match AddressList::parse(&t.s) {
+            Ok(list) => list,
+            Err(err) => {
+                eprintln!("Parsing: {:?}: {:?}", t, err);
+                return false;
+            },
+        };
+
+        let got : Vec<(Option<String>, Vec<String>)> = list.addresses().iter()
+            .map(|a| match a {
+                Address::Mailbox(m) => (None, vec![ m.address().to_string() ]),
+                Address::Group(g) =>
+                    (Some(g.name().to_string()),
+                     g.mailboxes().iter()
+                         .map(|m| m.address().to_string())
+                         .collect()),
+            })
+            .collect();
+
+        if got == t.expected {
+            true
+        } else {
+            eprintln!("     Got: {:?}\nExpected: {:?}", got, t.expected);
+            false
+        }
+    }
+}
+
+// date-time       =       [ day-of-week "," ] date FWS time [CFWS]
+//
+// We generate the obsolete syntax, i.e., CFWS instead of FWS, two
+// digit years, and alphabetic zones.
+#[derive(Debug, Clone)]
+struct DateTimeInput {
+    expected: DateTime,
+    s: String,
+}
+
+impl Arbitrary for DateTimeInput {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        lazy_static! {
+            static ref DAYS : Vec<&'static str> = vec![
+                "Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"
+            ];
+            static ref MONTHS : Vec<&'static str> = vec![
+                "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
+            ];
+            static ref ZONES : Vec<(&'static str, i32)> = vec![
+                ("UT", 0), ("GMT", 0), ("EST", -5 * 60), ("EDT", -4 * 60),
+                ("CST", -6 * 60), ("CDT", -5 * 60), ("MST", -7 * 60),
+                ("MDT", -6 * 60), ("PST", -8 * 60), ("PDT", -7 * 60),
+                ("Z", 0), ("A", 0),
+            ];
+        }
+
+        fn cfws<G: Gen>(g: &mut G) -> String {
+            CFWS::arbitrary(g).input()
+        }
+        fn cfws_opt<G: Gen>(g: &mut G) -> String {
+            Option::<CFWS>::arbitrary(g).map(|c| c.input())
+                .unwrap_or_default()
+        }
+        fn case<G: Gen>(g: &mut G, s: &str) -> String {
+            if bool::arbitrary(g) { s.to_lowercase() } else { s.into() }
+        }
+
+        let year = g.gen_range(1900, 2100);
+        let month = g.gen_range(1, 13);
+        let hour = g.gen_range(0, 24);
+        let minute = g.gen_range(0, 60);
+        let second = if bool::arbitrary(g) { Some(g.gen_range(0, 61)) } else { None };
+        let (zone, offset) = if bool::arbitrary(g) {
+            let offset : i32 = g.gen_range(-24 * 60 + 1, 24 * 60);
+            (format!("{}{:02}{:02}", if offset < 0 { '-' } else { '+' },
+                     offset.abs() / 60, offset.abs() % 60),
+             offset)
+        } else {
+            let (z, o) = ZONES[g.gen_range(0, ZONES.len())];
+            (case(g, z), o)
+        };
+
+        let expected = loop {
+            if let Ok(d) = DateTime::new(year, month, g.gen_range(1, 32),
+                                         hour, minute, second.unwrap_or(0),
+                                         offset) {
+                break d;
+            }
+        };
+
+        let mut s = cfws_opt(g);
+        if bool::arbitrary(g) {
+            s.push_str(&case(g, DAYS[expected.weekday() as usize]));
+            s.push_str(&cfws_opt(g));
+            s.push(',');
+            s.push_str(&cfws_opt(g));
+        }
+
+        s.push_str(&expected.day().to_string());
+        s.push_str(&cfws(g));
+        s.push_str(&case(g, MONTHS[month as usize - 1]));
+        s.push_str(&cfws(g));
+        if year >= 1950 && year < 2050 && bool::arbitrary(g) {
+            s.push_str(&format!("{:02}", year % 100));
+        } else {
+            s.push_str(&year.to_string());
+        }
+        s.push_str(&cfws(g));
+        s.push_str(&format!("{:02}:{:02}", hour, minute));
+        if let Some(second) = second {
+            s.push_str(&format!(":{:02}", second));
+        }
+        s.push_str(&cfws(g));
+        s.push_str(&zone);
+        s.push_str(&cfws_opt(g));
+
+        DateTimeInput {
+            expected,
+            s,
+        }
+    }
+}
+
+quickcheck! {
+    fn date_time_roundtrip(t: DateTimeInput) -> bool {
+        match DateTime::parse(&t.s) {
+            Ok(got) => {
+                if got != t.expected {
+                    eprintln!("Parsing: {:?}\n     Got: {:?}", t, got);
+                    return false;
+                }
+            },
+            Err(err) => {
+                eprintln!("Parsing: {:?}: {:?}", t, err);
+                return false;
+            },
+        }
+
+        // The canonical form should also round trip.
+        match DateTime::parse(t.expected.to_string()) {
+            Ok(got) => got == t.expected,
+            Err(err) => {
+                eprintln!("Parsing: {}: {:?}", t.expected, err);
+                false
+            },
+        }
+    }
+}
+
+// field           =       field-name ":" unstructured CRLF
+// field-name      =       1*ftext
+//
+// The body is folded at random whitespace.
+#[derive(Debug, Clone)]
+struct HeaderFieldInput {
+    name: String,
+    body: String,
+    s: String,
+}
+
+impl Arbitrary for HeaderFieldInput {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        // ftext           =       %d33-57 /               ; Any character except
+        //                         %d59-126                ;  controls, SP, and
+        //                                                 ;  ":".
+        let name = (0..g.gen_range(1, 10))
+            .map(|_| loop {
+                let c = g.gen_range(33u8, 127u8) as char;
+                if c != ':' {
+                    break c;
+                }
+            })
+            .collect::<String>();
+
+        let body = (0..g.gen_range(0, 10))
+            .map(|_| Text::arbitrary(g).to_string())
+            .collect::<String>();
+
+        let mut s = format!("{}:", name);
+        for c in body.chars() {
+            if (c == ' ' || c == '\t') && g.gen_range(0, 3) == 0 {
+                s.push_str("\r\n");
+            }
+            s.push(c);
+        }
+        s.push_str("\r\n");
+
+        HeaderFieldInput {
+            name,
+            body: body.trim_matches(|c: char| c == ' ' || c == '\t').into(),
+            s,
+        }
+    }
+}
+
+quickcheck! {
+    fn header_roundtrip(fields: Vec<HeaderFieldInput>) -> bool {
+        let mut s = fields.iter().map(|f| &f.s[..]).collect::<String>();
+        s.push_str("\r\nBody: not a header field\r\n");
+
+        let header = match Header::parse(&s) {
+            Ok(header) => header,
+            Err(err) => {
+                eprintln!("Parsing: {:?}: {:?}", s, err);
+                return false;
+            },
+        };
+
+        let got = header.fields().iter()
+            .map(|f| (f.name(), f.body()))
+            .collect::<Vec<_>>();
+        let expected = fields.iter()
+            .map(|f| (&f.name[..], &f.body[..]))
+            .collect::<Vec<_>>();
+
+        if got == expected {
+            true
+        } else {
+            eprintln!("     Got: {:?}\nExpected: {:?}", got, expected);
+            false
+        }
+    }
+}
@@ -0,0 +1,664 @@
+//! Autocrypt peer state.
+//!
+//! This module keeps track of the Autocrypt state of communication
+//! peers (see [Section 2.3 of the Autocrypt Level 1 specification]),
+//! and implements the recommendation algorithm (see [Section 2.4]).
+//! The state is stored by the background service, i.e., it
+//! persists across sessions.
+//!
+//! [Section 2.3 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#updating-autocrypt-peer-state
+//! [Section 2.4]: https://autocrypt.org/level1.html#provide-a-recommendation-for-message-encryption
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate sequoia_openpgp as openpgp;
+//! # extern crate sequoia_core;
+//! # extern crate sequoia_store;
+//! # extern crate time;
+//! # use openpgp::autocrypt::AutocryptHeaders;
+//! # use sequoia_core::{Context, NetworkPolicy, IPCPolicy};
+//! # use sequoia_store::Result;
+//! # use sequoia_store::autocrypt::{Peers, PreferEncrypt, Recommendation};
+//! # fn main() { f().unwrap(); }
+//! # fn f() -> Result<()> {
+//! # let ctx = Context::configure()
+//! #     .network_policy(NetworkPolicy::Offline)
+//! #     .ipc_policy(IPCPolicy::Internal)
+//! #     .ephemeral().build()?;
+//! let peers = Peers::open(&ctx)?;
+//!
+//! // Process incoming mail.
+//! let headers = AutocryptHeaders::from_bytes(
+//!     b"From: Alice <alice@example.org>\r\n\r\n")?;
+//! peers.process(&headers, time::now_utc().to_timespec())?;
+//!
+//! // We don't know Alice's key.
+//! assert_eq!(peers.recommend(PreferEncrypt::Mutual,
+//!                            &["alice@example.org"], false)?,
+//!            Recommendation::Disable);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cell::RefCell;
+use std::cmp;
+use std::fmt;
+use std::rc::Rc;
+
+use capnp::capability::Promise;
+use capnp;
+use failure;
+use futures::Future;
+use time::{self, Duration, Timespec};
+use tokio_core::reactor::Core;
+
+use openpgp::{self, TPK};
+use openpgp::autocrypt::{
+    AutocryptHeader, AutocryptHeaderType, AutocryptHeaders,
+};
+use openpgp::packet::{KeyFlags, UserID};
+use openpgp::parse::Parse;
+use openpgp::serialize::Serialize;
+use openpgp::RevocationStatus;
+use sequoia_core::Context;
+
+use store_protocol_capnp::node;
+
+use super::{Error, Result, Store, from_unix};
+
+/// The encryption preference of a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreferEncrypt {
+    /// The peer has no preference.
+    NoPreference,
+    /// The peer prefers to encrypt if all recipients also do.
+    Mutual,
+}
+
+impl PreferEncrypt {
+    /// Parses the value of the `prefer-encrypt` attribute.
+    fn from_attribute(value: Option<&str>) -> Self {
+        match value {
+            Some("mutual") => PreferEncrypt::Mutual,
+            _ => PreferEncrypt::NoPreference,
+        }
+    }
+}
+
+impl From<PreferEncrypt> for node::AutocryptPreferEncrypt {
+    fn from(p: PreferEncrypt) -> Self {
+        match p {
+            PreferEncrypt::NoPreference =>
+                node::AutocryptPreferEncrypt::NoPreference,
+            PreferEncrypt::Mutual =>
+                node::AutocryptPreferEncrypt::Mutual,
+        }
+    }
+}
+
+impl From<node::AutocryptPreferEncrypt> for PreferEncrypt {
+    fn from(p: node::AutocryptPreferEncrypt) -> Self {
+        match p {
+            node::AutocryptPreferEncrypt::NoPreference =>
+                PreferEncrypt::NoPreference,
+            node::AutocryptPreferEncrypt::Mutual =>
+                PreferEncrypt::Mutual,
+        }
+    }
+}
+
+/// Whether or not to encrypt a message.
+///
+/// See [Section 2.4 of the Autocrypt Level 1 specification].
+///
+/// [Section 2.4 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#provide-a-recommendation-for-message-encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recommendation {
+    /// Encryption is not possible.
+    ///
+    /// The user interface should not offer to encrypt.
+    Disable,
+    /// Encryption is possible, but it is likely to cause trouble.
+    ///
+    /// The user interface may offer to encrypt, but should warn the
+    /// user.
+    Discourage,
+    /// Encryption is possible.
+    ///
+    /// The user interface should offer to encrypt, but not enable
+    /// encryption by default.
+    Available,
+    /// Encryption should be enabled by default.
+    Encrypt,
+}
+
+/// The Autocrypt state of a peer.
+#[derive(Debug)]
+pub struct Peer {
+    /// The peer's canonicalized address.
+    pub addr: String,
+
+    /// The effective date of the most recent message seen from this
+    /// peer.
+    pub last_seen: Option<Timespec>,
+
+    /// The effective date of the most recent message with a valid
+    /// Autocrypt header seen from this peer.
+    pub autocrypt_timestamp: Option<Timespec>,
+
+    /// The key from the most recent valid Autocrypt header.
+    pub public_key: Option<TPK>,
+
+    /// The encryption preference from the most recent valid
+    /// Autocrypt header.
+    pub prefer_encrypt: PreferEncrypt,
+
+    /// The effective date of the most recent message with an
+    /// Autocrypt-Gossip header for this peer.
+    pub gossip_timestamp: Option<Timespec>,
+
+    /// The key from the most recent Autocrypt-Gossip header.
+    pub gossip_key: Option<TPK>,
+}
+
+impl Peer {
+    /// Returns the key to encrypt to, if any.
+    ///
+    /// This is the key from the Autocrypt header, or, if we have
+    /// not seen one, the gossiped key.
+    pub fn encryption_key(&self) -> Option<&TPK> {
+        self.public_key.as_ref().or(self.gossip_key.as_ref())
+    }
+
+    /// Computes the preliminary recommendation for this peer.
+    ///
+    /// See [Section 2.4.1 of the Autocrypt Level 1 specification].
+    ///
+    /// [Section 2.4.1 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#preliminary-recommendation
+    pub fn preliminary_recommendation(&self) -> Recommendation {
+        let key = match self.encryption_key() {
+            Some(key) => key,
+            None => return Recommendation::Disable,
+        };
+
+        if ! usable_for_encryption(key) {
+            return Recommendation::Disable;
+        }
+
+        if self.public_key.is_none() {
+            // We only have a gossiped key.
+            return Recommendation::Discourage;
+        }
+
+        if let (Some(autocrypt), Some(seen)) =
+            (self.autocrypt_timestamp, self.last_seen)
+        {
+            // The peer may have switched to a client that does not
+            // support Autocrypt.
+            if seen - autocrypt > Duration::days(35) {
+                return Recommendation::Discourage;
+            }
+        }
+
+        Recommendation::Available
+    }
+
+    /// Computes the recommendation for this peer.
+    ///
+    /// `own` is the user's own encryption preference, and
+    /// `reply_to_encrypted` indicates whether the message being
+    /// composed is a reply to an encrypted message.
+    pub fn recommendation(&self, own: PreferEncrypt, reply_to_encrypted: bool)
+                          -> Recommendation {
+        match self.preliminary_recommendation() {
+            Recommendation::Available
+                if own == PreferEncrypt::Mutual
+                && self.prefer_encrypt == PreferEncrypt::Mutual =>
+                Recommendation::Encrypt,
+            Recommendation::Available | Recommendation::Discourage
+                if reply_to_encrypted =>
+                Recommendation::Encrypt,
+            r => r,
+        }
+    }
+}
+
+/// Returns whether `tpk` can be used to encrypt messages.
+fn usable_for_encryption(tpk: &TPK) -> bool {
+    if let RevocationStatus::Revoked(_) = tpk.revocation_status() {
+        return false;
+    }
+
+    tpk.keys_valid()
+        .key_flags(KeyFlags::default().set_encrypt_for_transport(true))
+        .next().is_some()
+}
+
+/// Combines the recommendations for the individual recipients.
+///
+/// See [Section 2.4.3 of the Autocrypt Level 1 specification].
+///
+/// [Section 2.4.3 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#recommendations-for-messages-to-multiple-addresses
+fn combine<I>(recommendations: I) -> Recommendation
+    where I: Iterator<Item=Recommendation>
+{
+    let mut all_encrypt = true;
+    let mut any_discourage = false;
+    let mut empty = true;
+
+    for r in recommendations {
+        empty = false;
+        match r {
+            Recommendation::Disable => return Recommendation::Disable,
+            Recommendation::Discourage => any_discourage = true,
+            _ => (),
+        }
+        all_encrypt &= r == Recommendation::Encrypt;
+    }
+
+    if empty {
+        Recommendation::Disable
+    } else if all_encrypt {
+        Recommendation::Encrypt
+    } else if any_discourage {
+        Recommendation::Discourage
+    } else {
+        Recommendation::Available
+    }
+}
+
+/// Canonicalizes an email address.
+///
+/// See [Section 2.1 of the Autocrypt Level 1 specification].
+///
+/// [Section 2.1 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#e-mail-address-canonicalization
+fn canonicalize(addr: &str) -> Result<String> {
+    UserID::from(addr).address_normalized()?
+        .ok_or_else(|| openpgp::Error::InvalidArgument(
+            format!("Not an email address: {:?}", addr)).into())
+}
+
+/// Returns the valid Autocrypt header for `addr`, if any.
+///
+/// If there is more than one valid header, none is returned.
+fn sender_header<'a>(headers: &'a AutocryptHeaders, addr: &str)
+                     -> Option<&'a AutocryptHeader> {
+    let mut valid = headers.headers.iter().filter(|h| {
+        h.header_type == AutocryptHeaderType::Sender
+            && h.key.is_some()
+            && h.get("addr").and_then(|a| canonicalize(&a.value).ok())
+                .map(|a| a == addr).unwrap_or(false)
+            && h.attributes.iter().all(|a| ! a.critical || match &a.key[..] {
+                "addr" | "prefer-encrypt" | "keydata" => true,
+                _ => false,
+            })
+    });
+
+    let header = valid.next();
+    if valid.next().is_some() {
+        None
+    } else {
+        header
+    }
+}
+
+/// Keeps track of the Autocrypt state of communication peers.
+pub struct Peers {
+    core: Rc<RefCell<Core>>,
+    node: node::Client,
+}
+
+impl fmt::Debug for Peers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Peers {{ }}")
+    }
+}
+
+impl Peers {
+    /// Opens the Autocrypt peer state.
+    pub fn open(c: &Context) -> Result<Self> {
+        let (core, node) = Store::connect(c)?;
+        Ok(Peers{core: Rc::new(RefCell::new(core)), node: node})
+    }
+
+    /// Updates the peer state from an incoming message.
+    ///
+    /// `headers` are the message's headers, and `date` is the value
+    /// of its `Date` header.  Dates in the future are clamped to the
+    /// current time.
+    ///
+    /// Note: messages that are known to be spam, or that were sent
+    /// by a mailing list, should not be processed.
+    pub fn process(&self, headers: &AutocryptHeaders, date: Timespec)
+                   -> Result<()> {
+        let from = headers.from.as_ref().ok_or_else(
+            || openpgp::Error::InvalidArgument("No From header".into()))?;
+        let addr = canonicalize(from)?;
+        let date = cmp::min(date, time::now_utc().to_timespec());
+
+        let mut request = self.node.autocrypt_update_request();
+        request.get().set_addr(&addr);
+        request.get().set_effective_date(date.sec);
+        if let Some(header) = sender_header(headers, &addr) {
+            let mut blob = vec![];
+            header.key.as_ref().expect("checked above").serialize(&mut blob)?;
+            request.get().set_key(&blob);
+            request.get().set_prefer_encrypt(PreferEncrypt::from_attribute(
+                header.get("prefer-encrypt").map(|a| &a.value[..])).into());
+        }
+        make_request_map!(self.core.borrow_mut(), request, |_| Ok(()))
+    }
+
+    /// Updates the peer state from an Autocrypt-Gossip header.
+    ///
+    /// `date` is the effective date of the message containing the
+    /// gossip.  Dates in the future are clamped to the current time.
+    pub fn process_gossip(&self, addr: &str, tpk: &TPK, date: Timespec)
+                          -> Result<()> {
+        let addr = canonicalize(addr)?;
+        let date = cmp::min(date, time::now_utc().to_timespec());
+
+        let mut blob = vec![];
+        tpk.serialize(&mut blob)?;
+
+        let mut request = self.node.autocrypt_update_gossip_request();
+        request.get().set_addr(&addr);
+        request.get().set_effective_date(date.sec);
+        request.get().set_key(&blob);
+        make_request_map!(self.core.borrow_mut(), request, |_| Ok(()))
+    }
+
+    /// Updates the peer state from the Autocrypt-Gossip headers of
+    /// a decrypted message.
+    ///
+    /// `headers` are the headers of the message, and `inner` are
+    /// the headers of the decrypted MIME part.  Only gossip about
+    /// recipients of the message is considered, see
+    /// `AutocryptHeaders::gossip`.  `date` is the effective date of
+    /// the message.
+    pub fn process_gossip_headers(&self, headers: &AutocryptHeaders,
+                                  inner: &AutocryptHeaders, date: Timespec)
+                                  -> Result<()> {
+        for header in inner.gossip(&headers.recipients()) {
+            let addr = &header.get("addr").expect("checked above").value;
+            let tpk = header.key.as_ref().expect("checked above");
+            self.process_gossip(addr, tpk, date)?;
+        }
+        Ok(())
+    }
+
+    /// Returns Autocrypt-Gossip headers for a message to the given
+    /// recipients.
+    ///
+    /// Gossip is only useful if there is more than one recipient.
+    /// Recipients for which no key is known are skipped.  The
+    /// headers should be added to the encrypted MIME part.
+    pub fn gossip_headers(&self, recipients: &[&str])
+                          -> Result<Vec<AutocryptHeader>> {
+        let mut headers = Vec::new();
+        if recipients.len() < 2 {
+            return Ok(headers);
+        }
+
+        for recipient in recipients {
+            let peer = match self.peer(recipient) {
+                Ok(peer) => peer,
+                Err(e) => match e.downcast::<Error>() {
+                    Ok(Error::NotFound) => continue,
+                    Ok(e) => return Err(e.into()),
+                    Err(e) => return Err(e),
+                },
+            };
+            if let Some(tpk) = peer.encryption_key() {
+                headers.push(AutocryptHeader::new_gossip(tpk, &peer.addr)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Returns the state of the given peer.
+    pub fn peer(&self, addr: &str) -> Result<Peer> {
+        let addr = canonicalize(addr)?;
+        let mut request = self.node.autocrypt_peer_request();
+        request.get().set_addr(&addr);
+        make_request_map!(
+            self.core.borrow_mut(), request,
+            |r: node::autocrypt_peer::Reader| Ok(Peer {
+                addr: r.get_addr()?.into(),
+                last_seen: from_unix(r.get_last_seen()),
+                autocrypt_timestamp: from_unix(r.get_autocrypt_timestamp()),
+                public_key: if r.has_public_key() {
+                    Some(TPK::from_bytes(r.get_public_key()?)?)
+                } else {
+                    None
+                },
+                prefer_encrypt: r.get_prefer_encrypt()?.into(),
+                gossip_timestamp: from_unix(r.get_gossip_timestamp()),
+                gossip_key: if r.has_gossip_key() {
+                    Some(TPK::from_bytes(r.get_gossip_key()?)?)
+                } else {
+                    None
+                },
+            }))
+    }
+
+    /// Computes a recommendation for encrypting a message to the
+    /// given recipients.
+    ///
+    /// `own` is the user's own encryption preference, and
+    /// `reply_to_encrypted` indicates whether the message being
+    /// composed is a reply to an encrypted message.
+    pub fn recommend(&self, own: PreferEncrypt, recipients: &[&str],
+                     reply_to_encrypted: bool)
+                     -> Result<Recommendation> {
+        let mut recommendations = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            let r = match self.peer(recipient) {
+                Ok(peer) => peer.recommendation(own, reply_to_encrypted),
+                Err(e) => match e.downcast::<Error>() {
+                    // We know nothing about this peer.
+                    Ok(Error::NotFound) => Recommendation::Disable,
+                    Ok(e) => return Err(e.into()),
+                    Err(e) => return Err(e),
+                },
+            };
+            recommendations.push(r);
+        }
+
+        Ok(combine(recommendations.into_iter()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use sequoia_core::{NetworkPolicy, IPCPolicy};
+
+    macro_rules! bytes {
+        ( $x:expr ) => { include_bytes!(concat!("../../openpgp/tests/data/keys/", $x)) };
+    }
+
+    fn context() -> Context {
+        Context::configure()
+            .ephemeral()
+            .network_policy(NetworkPolicy::Offline)
+            .ipc_policy(IPCPolicy::Internal)
+            .build().unwrap()
+    }
+
+    /// Returns the headers of a message from Testy.
+    fn message(prefer_encrypt: Option<&str>) -> AutocryptHeaders {
+        let mut buf = Vec::new();
+        write!(&mut buf, "From: Testy McTestface <Testy@example.org>\r\n")
+            .unwrap();
+        if let Some(prefer_encrypt) = prefer_encrypt {
+            let tpk = TPK::from_bytes(bytes!("testy-new.pgp")).unwrap();
+            let header = AutocryptHeader::new_sender(
+                &tpk, "testy@example.org", prefer_encrypt).unwrap();
+            write!(&mut buf, "Autocrypt: ").unwrap();
+            header.serialize(&mut buf).unwrap();
+            write!(&mut buf, "\r\n").unwrap();
+        }
+        write!(&mut buf, "\r\n").unwrap();
+        AutocryptHeaders::from_bytes(&buf).unwrap()
+    }
+
+    #[test]
+    fn peer_state() {
+        let ctx = context();
+        let peers = Peers::open(&ctx).unwrap();
+        let testy = "testy@example.org";
+        let now = time::now_utc().to_timespec();
+        let t0 = Timespec::new(now.sec, 0) - Duration::days(100);
+
+        assert_match!(Error::NotFound
+                      = peers.peer(testy).err().unwrap()
+                      .downcast::<Error>().unwrap());
+        assert_eq!(peers.recommend(PreferEncrypt::Mutual, &[testy], false)
+                   .unwrap(), Recommendation::Disable);
+
+        // A message without an Autocrypt header.
+        peers.process(&message(None), t0).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert_eq!(peer.last_seen, Some(t0));
+        assert!(peer.autocrypt_timestamp.is_none());
+        assert!(peer.public_key.is_none());
+        assert_eq!(peer.preliminary_recommendation(), Recommendation::Disable);
+
+        // A message with an Autocrypt header.
+        let t1 = t0 + Duration::days(1);
+        peers.process(&message(Some("mutual")), t1).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert_eq!(peer.last_seen, Some(t1));
+        assert_eq!(peer.autocrypt_timestamp, Some(t1));
+        assert_eq!(peer.prefer_encrypt, PreferEncrypt::Mutual);
+        assert_eq!(peer.public_key.unwrap().fingerprint().to_hex(),
+                   "39D100AB67D5BD8C04010205FB3751F1587DAEF1");
+        assert_eq!(peers.recommend(PreferEncrypt::Mutual, &[testy], false)
+                   .unwrap(), Recommendation::Encrypt);
+        assert_eq!(peers.recommend(PreferEncrypt::NoPreference, &[testy], false)
+                   .unwrap(), Recommendation::Available);
+        assert_eq!(peers.recommend(PreferEncrypt::NoPreference, &[testy], true)
+                   .unwrap(), Recommendation::Encrypt);
+
+        // An older message is ignored.
+        peers.process(&message(Some("nopreference")), t0).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert_eq!(peer.autocrypt_timestamp, Some(t1));
+        assert_eq!(peer.prefer_encrypt, PreferEncrypt::Mutual);
+
+        // Much later, a message without an Autocrypt header.
+        let t2 = t1 + Duration::days(36);
+        peers.process(&message(None), t2).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert_eq!(peer.last_seen, Some(t2));
+        assert_eq!(peer.autocrypt_timestamp, Some(t1));
+        assert!(peer.public_key.is_some());
+        assert_eq!(peers.recommend(PreferEncrypt::NoPreference, &[testy], false)
+                   .unwrap(), Recommendation::Discourage);
+        assert_eq!(peers.recommend(PreferEncrypt::Mutual, &[testy], false)
+                   .unwrap(), Recommendation::Discourage);
+        assert_eq!(peers.recommend(PreferEncrypt::Mutual, &[testy], true)
+                   .unwrap(), Recommendation::Encrypt);
+
+        // The state persists.
+        drop(peers);
+        let peers = Peers::open(&ctx).unwrap();
+        assert_eq!(peers.peer(testy).unwrap().last_seen, Some(t2));
+    }
+
+    #[test]
+    fn gossip() {
+        let ctx = context();
+        let peers = Peers::open(&ctx).unwrap();
+        let testy = "testy@example.org";
+        let tpk = TPK::from_bytes(bytes!("testy-new.pgp")).unwrap();
+        let now = time::now_utc().to_timespec();
+        let t0 = Timespec::new(now.sec, 0) - Duration::days(1);
+
+        peers.process_gossip(testy, &tpk, t0).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert!(peer.public_key.is_none());
+        assert_eq!(peer.gossip_timestamp, Some(t0));
+        assert_eq!(peer.encryption_key().unwrap().fingerprint(),
+                   tpk.fingerprint());
+        assert_eq!(peers.recommend(PreferEncrypt::Mutual, &[testy], false)
+                   .unwrap(), Recommendation::Discourage);
+        assert_eq!(peers.recommend(PreferEncrypt::Mutual, &[testy], true)
+                   .unwrap(), Recommendation::Encrypt);
+
+        // One unknown recipient disables encryption.
+        assert_eq!(peers.recommend(PreferEncrypt::Mutual,
+                                   &[testy, "mallory@example.org"], true)
+                   .unwrap(), Recommendation::Disable);
+
+        // Gossip with the same effective date replaces the key.
+        let other = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
+        peers.process_gossip(testy, &other, t0).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert_eq!(peer.gossip_timestamp, Some(t0));
+        assert_eq!(peer.gossip_key.unwrap().fingerprint(),
+                   other.fingerprint());
+
+        // Older gossip is ignored.
+        peers.process_gossip(testy, &tpk, t0 - Duration::days(1)).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert_eq!(peer.gossip_timestamp, Some(t0));
+        assert_eq!(peer.gossip_key.unwrap().fingerprint(),
+                   other.fingerprint());
+    }
+
+    #[test]
+    fn gossip_headers() {
+        let ctx = context();
+        let peers = Peers::open(&ctx).unwrap();
+        let testy = "testy@example.org";
+        let bob = "bob@example.org";
+        let tpk = TPK::from_bytes(bytes!("testy-new.pgp")).unwrap();
+        let now = time::now_utc().to_timespec();
+        let t0 = Timespec::new(now.sec, 0) - Duration::days(1);
+
+        // We have a key for Testy, but not for Bob.
+        peers.process(&message(Some("mutual")), t0).unwrap();
+        assert_eq!(peers.gossip_headers(&[testy]).unwrap().len(), 0);
+        let gossip = peers.gossip_headers(&[testy, bob]).unwrap();
+        assert_eq!(gossip.len(), 1);
+        assert_eq!(gossip[0].header_type, AutocryptHeaderType::Gossip);
+        assert_eq!(gossip[0].get("addr").unwrap().value, testy);
+
+        // Round-trip the gossip through a second store.
+        let mut buf = Vec::new();
+        write!(&mut buf, "From: {}\r\n", bob).unwrap();
+        write!(&mut buf, "To: Testy <{}>, {}\r\n\r\n", testy, bob).unwrap();
+        let outer = AutocryptHeaders::from_bytes(&buf).unwrap();
+
+        let mut buf = Vec::new();
+        for header in gossip.iter() {
+            write!(&mut buf, "Autocrypt-Gossip: ").unwrap();
+            header.serialize(&mut buf).unwrap();
+            write!(&mut buf, "\r\n").unwrap();
+        }
+        write!(&mut buf, "\r\n").unwrap();
+        let inner = AutocryptHeaders::from_bytes(&buf).unwrap();
+
+        let ctx = context();
+        let peers = Peers::open(&ctx).unwrap();
+        peers.process_gossip_headers(&outer, &inner, t0).unwrap();
+        let peer = peers.peer(testy).unwrap();
+        assert!(peer.public_key.is_none());
+        assert_eq!(peer.gossip_timestamp, Some(t0));
+        assert_eq!(peer.gossip_key.unwrap().fingerprint(), tpk.fingerprint());
+    }
+
+    #[test]
+    fn combine_recommendations() {
+        use super::Recommendation::*;
+        assert_eq!(combine(vec![].into_iter()), Disable);
+        assert_eq!(combine(vec![Encrypt, Encrypt].into_iter()), Encrypt);
+        assert_eq!(combine(vec![Encrypt, Available].into_iter()), Available);
+        assert_eq!(combine(vec![Encrypt, Discourage].into_iter()), Discourage);
+        assert_eq!(combine(vec![Encrypt, Disable].into_iter()), Disable);
+    }
+}
@@ -0,0 +1,112 @@
+//! Autocrypt peer state for the backend.
+//!
+//! This implements the peer state update algorithms described in
+//! [Section 2.3 of the Autocrypt Level 1 specification].  Keys are
+//! stored in the common key pool, the peer state merely refers to
+//! them.
+//!
+//! [Section 2.3 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#updating-autocrypt-peer-state
+
+use super::{
+    ID, Storage, Result, node, TPK, KeyServer, log,
+};
+use super::storage::AutocryptPeer;
+
+/// Stores `tpk` in the common key pool, returning its id.
+fn store_key(s: &Storage, tpk: TPK) -> Result<ID> {
+    let key_id = s.key_lookup_or_create(&tpk.fingerprint())?;
+    KeyServer::merge(s, key_id, tpk)?;
+    Ok(key_id)
+}
+
+/// Updates the peer state from a message sent by `addr`.
+///
+/// `key` is the key and the encryption preference from the
+/// message's Autocrypt header, or `None` if the message did not carry
+/// a valid Autocrypt header.
+pub fn update(s: &Storage, addr: &str, effective_date: i64,
+              key: Option<(TPK, node::AutocryptPreferEncrypt)>)
+              -> Result<()> {
+    let mut peer = s.autocrypt_peer(addr)?.unwrap_or_default();
+
+    // Ignore messages older than the current Autocrypt state.
+    if peer.autocrypt_timestamp.map(|t| effective_date < t).unwrap_or(false) {
+        return Ok(());
+    }
+
+    if peer.last_seen.map(|t| effective_date > t).unwrap_or(true) {
+        peer.last_seen = Some(effective_date);
+    }
+
+    let (tpk, prefer_encrypt) = match key {
+        Some(k) => k,
+        None => return s.autocrypt_set_peer(addr, &peer),
+    };
+
+    let fingerprint = tpk.fingerprint();
+    let key_id = store_key(s, tpk)?;
+    let public_key = peer.public_key;
+
+    peer.autocrypt_timestamp = Some(effective_date);
+    peer.public_key = Some(key_id);
+    peer.prefer_encrypt = prefer_encrypt;
+    s.autocrypt_set_peer(addr, &peer)?;
+
+    if public_key != Some(key_id) {
+        log::info(s, log::Refers::to().key(key_id), addr,
+                  &format!("New Autocrypt key {}", fingerprint.to_keyid()))?;
+    }
+    Ok(())
+}
+
+/// Updates the peer state from an Autocrypt-Gossip header.
+pub fn update_gossip(s: &Storage, addr: &str, effective_date: i64,
+                     tpk: TPK) -> Result<()> {
+    let mut peer = s.autocrypt_peer(addr)?.unwrap_or_default();
+
+    // Ignore gossip older than the current gossip state.
+    if peer.gossip_timestamp.map(|t| effective_date < t).unwrap_or(false) {
+        return Ok(());
+    }
+
+    peer.gossip_timestamp = Some(effective_date);
+    peer.gossip_key = Some(store_key(s, tpk)?);
+    s.autocrypt_set_peer(addr, &peer)
+}
+
+/// Returns the key with the given id as a blob.
+fn key_blob(s: &Storage, id: Option<ID>) -> Result<Option<Vec<u8>>> {
+    if let Some(id) = id {
+        s.key_tpk(id)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the state of the given peer.
+pub fn peer(s: &Storage, addr: &str, mut peer: node::autocrypt_peer::Builder)
+            -> Result<()> {
+    let state: AutocryptPeer = match s.autocrypt_peer(addr)? {
+        Some(state) => state,
+        None => return Err(::Error::NotFound.into()),
+    };
+
+    peer.set_addr(addr);
+    if let Some(t) = state.last_seen {
+        peer.set_last_seen(t);
+    }
+    if let Some(t) = state.autocrypt_timestamp {
+        peer.set_autocrypt_timestamp(t);
+    }
+    if let Some(key) = key_blob(s, state.public_key)? {
+        peer.set_public_key(&key);
+    }
+    peer.set_prefer_encrypt(state.prefer_encrypt);
+    if let Some(t) = state.gossip_timestamp {
+        peer.set_gossip_timestamp(t);
+    }
+    if let Some(key) = key_blob(s, state.gossip_key)? {
+        peer.set_gossip_key(&key);
+    }
+    Ok(())
+}
@@ -1,12 +1,15 @@
 //! Logging for the backend.
+//!
+//! Log entries are trimmed by the housekeeping loop, see
+//! `Storage::log_trim`.
 
-// XXX: Implement log levels and trim the log.
-
+use LogLevel;
 use super::{
-    ID, Timestamp, Connection, Rc, Result, node,
+    ID, Timestamp, Storage, Rc, Result, node,
     StoreServer, BindingServer, KeyServer,
-    Promise, capnp, capnp_rpc
+    Promise, capnp, capnp_rpc, Handle, Subscribers,
 };
+use super::storage::LogEntry;
 
 /// Models entries referring to other objects.
 pub struct Refers {
@@ -40,31 +43,48 @@ impl Refers {
     }
 }
 
-/// Writes a log message to the log.
-pub fn message(c: &Rc<Connection>, refers: Refers,
-               slug: &str, message: &str)
+/// Writes a debug message to the log.
+pub fn debug(s: &Storage, refers: Refers,
+             slug: &str, message: &str)
+             -> Result<ID> {
+    log(s, LogLevel::Debug, refers, slug, message, None)
+}
+
+/// Writes an informational message to the log.
+pub fn info(s: &Storage, refers: Refers,
+            slug: &str, message: &str)
+            -> Result<ID> {
+    log(s, LogLevel::Info, refers, slug, message, None)
+}
+
+/// Writes a warning to the log.
+pub fn warning(s: &Storage, refers: Refers,
+               slug: &str, message: &str, error: &str)
                -> Result<ID> {
-    log(c, refers, slug, message, None)
+    log(s, LogLevel::Warning, refers, slug, message, Some(error))
 }
 
 /// Writes an error message to the log.
-pub fn error(c: &Rc<Connection>, refers: Refers,
+pub fn error(s: &Storage, refers: Refers,
              slug: &str, message: &str, error: &str)
              -> Result<ID> {
-    log(c, refers, slug, message, Some(error))
+    log(s, LogLevel::Error, refers, slug, message, Some(error))
 }
 
 /// Writes a log message to the log.
-fn log(c: &Rc<Connection>, refers: Refers,
+fn log(s: &Storage, level: LogLevel, refers: Refers,
        slug: &str, message: &str, error: Option<&str>)
        -> Result<ID> {
-    c.execute("INSERT INTO log
-                   (timestamp, level, store, binding, key, slug, message, error)
-                   VALUES (?1, 0, ?2, ?3, ?4, ?5, ?6, ?7)",
-              &[&Timestamp::now(),
-                &refers.store, &refers.binding, &refers.key,
-                &slug, &message, &error])?;
-    Ok(c.last_insert_rowid().into())
+    s.log_append(LogEntry {
+        timestamp: Timestamp::now(),
+        level: level,
+        store: refers.store,
+        binding: refers.binding,
+        key: refers.key,
+        slug: slug.into(),
+        message: message.into(),
+        error: error.map(|e| e.into()),
+    })
 }
 
 /// Selects log entries to iterate over.
@@ -77,14 +97,21 @@ pub enum Selector {
 
 /// Iterator for log entries.
 pub struct IterServer {
-    c: Rc<Connection>,
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
     selector: Selector,
+    min_level: LogLevel,
     n: ID,
 }
 
 impl IterServer {
-    pub fn new(c: Rc<Connection>, selector: Selector) -> Self {
-        IterServer{c: c, selector: selector, n: ID::max()}
+    pub fn new(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>,
+               selector: Selector,
+               min_level: LogLevel)
+               -> Self {
+        IterServer{s: s, h: h, subscribers: subscribers, selector: selector,
+                   min_level: min_level, n: ID::max()}
     }
 }
 
@@ -94,99 +121,37 @@ impl node::log_iter::Server for IterServer {
             mut results: node::log_iter::NextResults)
             -> Promise<(), capnp::Error> {
         bind_results!(results);
-
-        let (
-            id, timestamp,
-            store, binding, key,
-            slug, message, error
-        ): (
-            ID, Timestamp,
-            Option<ID>, Option<ID>, Option<ID>,
-            String, String, Option<String>
-        ) = sry!(match self.selector {
-            Selector::All =>
-                self.c.query_row(
-                    "SELECT id, timestamp,
-                            store, binding, key,
-                            slug, message, error
-                         FROM log
-                         WHERE id < ?1
-                         ORDER BY id DESC LIMIT 1",
-                    &[&self.n],
-                    |row| (row.get(0), row.get(1),
-                           row.get(2), row.get(3), row.get(4),
-                           row.get(5), row.get(6), row.get(7))),
-
-            Selector::Store(store) =>
-                self.c.query_row(
-                    "SELECT id, timestamp,
-                            store, binding, key,
-                            slug, message, error
-                         FROM log
-                         WHERE id < ?1
-                           AND (store = ?2
-                                OR binding IN (SELECT id FROM bindings WHERE store = ?2)
-                                OR key IN (SELECT key FROM bindings WHERE store = ?2))
-                         ORDER BY id DESC LIMIT 1",
-                    &[&self.n, &store],
-                    |row| (row.get(0), row.get(1),
-                           row.get(2), row.get(3), row.get(4),
-                           row.get(5), row.get(6), row.get(7))),
-
-            Selector::Binding(binding) =>
-                self.c.query_row(
-                    "SELECT id, timestamp,
-                            store, binding, key,
-                            slug, message, error
-                         FROM log
-                         WHERE id < ?1
-                           AND (binding = ?2
-                                OR key IN (SELECT key FROM bindings WHERE id = ?2))
-                         ORDER BY id DESC LIMIT 1",
-                    &[&self.n, &binding],
-                    |row| (row.get(0), row.get(1),
-                           row.get(2), row.get(3), row.get(4),
-                           row.get(5), row.get(6), row.get(7))),
-
-            Selector::Key(key) =>
-                self.c.query_row(
-                    "SELECT id, timestamp,
-                            store, binding, key,
-                            slug, message, error
-                         FROM log
-                         WHERE id < ?1
-                           AND key = ?2
-                         ORDER BY id DESC LIMIT 1",
-                    &[&self.n, &key],
-                    |row| (row.get(0), row.get(1),
-                           row.get(2), row.get(3), row.get(4),
-                           row.get(5), row.get(6), row.get(7))),
-        });
+        let (id, log) = sry!(self.s.log_next(&self.selector, self.min_level,
+                                             self.n));
 
         let mut entry = pry!(results.get().get_result()).init_ok();
-        entry.set_timestamp(timestamp.unix());
+        entry.set_timestamp(log.timestamp.unix());
+        entry.set_level(log.level.into());
 
-        if let Some(store) = store {
+        if let Some(store) = log.store {
             entry.set_store(node::store::ToClient::new(
-                StoreServer::new(self.c.clone(), store))
+                StoreServer::new(self.s.clone(), self.h.clone(),
+                                 self.subscribers.clone(), store))
                             .into_client::<capnp_rpc::Server>());
         }
 
-        if let Some(binding) = binding {
+        if let Some(binding) = log.binding {
             entry.set_binding(node::binding::ToClient::new(
-                BindingServer::new(self.c.clone(), binding))
+                BindingServer::new(self.s.clone(), self.h.clone(),
+                                   self.subscribers.clone(), binding))
                             .into_client::<capnp_rpc::Server>());
         }
 
-        if let Some(key) = key {
+        if let Some(key) = log.key {
             entry.set_key(node::key::ToClient::new(
-                KeyServer::new(self.c.clone(), key))
+                KeyServer::new(self.s.clone(), self.h.clone(),
+                               self.subscribers.clone(), key))
                             .into_client::<capnp_rpc::Server>());
         }
 
-        entry.set_slug(&slug);
-        entry.set_message(&message);
-        if let Some(error) = error {
+        entry.set_slug(&log.slug);
+        entry.set_message(&log.message);
+        if let Some(error) = log.error {
             entry.set_error(&error);
         }
 
@@ -5,6 +5,7 @@ use std::cmp;
 use std::fmt;
 use std::io;
 use std::rc::Rc;
+use std::str;
 use time::Duration;
 
 use capnp::capability::Promise;
@@ -15,15 +16,11 @@ use futures::Future;
 use futures::future::{self, loop_fn, Loop};
 use rand::distributions::{Distribution, Uniform};
 use rand::thread_rng;
-use rusqlite::Connection;
 use rusqlite;
 use tokio_core::reactor::{Handle, Timeout};
-use tokio_core;
-use tokio_io::io::ReadHalf;
 
 use openpgp::{self, TPK, KeyID, Fingerprint};
 use openpgp::parse::Parse;
-use openpgp::serialize::Serialize;
 use sequoia_core as core;
 use sequoia_net as net;
 use sequoia_ipc as ipc;
@@ -36,8 +33,19 @@ use super::Result;
 pub mod support;
 use self::support::{ID, Timestamp};
 
+// Storage backends.
+pub mod storage;
+use self::storage::{Storage, Stats, UpdateStatus, Usage};
+
 // Logging.
-mod log;
+pub mod log;
+
+// Autocrypt peer state.
+mod autocrypt;
+
+// Change notifications.
+mod subscription;
+use self::subscription::Subscribers;
 
 /* Configuration and policy.  */
 
@@ -51,6 +59,22 @@ fn refresh_interval() -> Duration {
     Duration::weeks(1)
 }
 
+/// Maximum age of log entries.
+///
+/// Older entries are removed by the housekeeping.
+fn log_max_age() -> Duration {
+    Duration::weeks(12)
+}
+
+/// Maximum number of log entries kept per key.
+///
+/// If there are only a few keys, they are updated much more often
+/// than once per refresh interval, so the age limit alone does not
+/// bound the size of the log.
+fn log_max_entries_per_key() -> usize {
+    64
+}
+
 /// Returns a value from the uniform distribution over [0, 2*d).
 ///
 /// This function is used to randomize key refresh times.
@@ -83,8 +107,7 @@ impl Backend {
 }
 
 impl ipc::Handler for Backend {
-    fn handle(&self,
-              network: twoparty::VatNetwork<ReadHalf<tokio_core::net::TcpStream>>)
+    fn handle(&self, network: twoparty::VatNetwork<ipc::Reader>)
               -> RpcSystem<Side> {
         RpcSystem::new(Box::new(network), Some(self.store.clone().client))
     }
@@ -94,44 +117,25 @@ impl ipc::Handler for Backend {
 
 struct NodeServer {
     _descriptor: ipc::Descriptor,
-    c: Rc<Connection>,
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
 }
 
 impl NodeServer {
     fn new(descriptor: ipc::Descriptor, handle: Handle) -> Result<Self> {
-        let mut db_path = descriptor.context().home().to_path_buf();
-        db_path.push("public-key-store.sqlite");
-
-        let c = Connection::open(db_path)?;
-        c.execute_batch("PRAGMA secure_delete = true;")?;
-        c.execute_batch("PRAGMA foreign_keys = true;")?;
-        let server = NodeServer {
+        let subscribers = Subscribers::new(handle.clone(),
+                                           descriptor.context().home());
+        let s: Rc<Storage> = Rc::new(subscription::Notifying::new(
+            storage::open(descriptor.context())?, subscribers.clone()));
+        KeyServer::start_housekeeping(s.clone(), handle.clone(),
+                                      subscribers.clone())?;
+        Ok(NodeServer {
             _descriptor: descriptor,
-            c: Rc::new(c),
-        };
-        server.init()?;
-
-        KeyServer::start_housekeeping(server.c.clone(), handle)?;
-        Ok(server)
-    }
-
-    /// Initializes or migrates the database.
-    fn init(&self) -> Result<()> {
-        let v = self.c.query_row(
-            "SELECT version FROM version WHERE id=1",
-            &[], |row| row.get(0));
-
-        if let Ok(v) = v {
-            match v {
-                1 => return Ok(()),
-                _ => unimplemented!(),
-            }
-        }
-
-        self.c.execute_batch(DB_SCHEMA_1)?;
-        log::message(&self.c, log::Refers::to(), "server",
-                     "Created database version 1")?;
-        Ok(())
+            s: s,
+            h: handle,
+            subscribers: subscribers,
+        })
     }
 }
 
@@ -143,9 +147,8 @@ impl node::Server for NodeServer {
         bind_results!(results);
         let params = pry!(params.get());
 
-        // XXX maybe check ephemeral and use in-core sqlite db
-
-        let store = sry!(StoreServer::open(self.c.clone(),
+        let store = sry!(StoreServer::open(self.s.clone(), self.h.clone(),
+                                           self.subscribers.clone(),
                                            pry!(params.get_realm()),
                                            pry!(params.get_network_policy()).into(),
                                            pry!(params.get_name())));
@@ -160,7 +163,8 @@ impl node::Server for NodeServer {
             -> Promise<(), capnp::Error> {
         bind_results!(results);
         let prefix = pry!(pry!(params.get()).get_realm_prefix());
-        let iter = StoreIterServer::new(self.c.clone(), prefix);
+        let iter = StoreIterServer::new(self.s.clone(), self.h.clone(),
+                                        self.subscribers.clone(), prefix);
         pry!(pry!(results.get().get_result()).set_ok(
             node::store_iter::ToClient::new(iter).into_client::<capnp_rpc::Server>()));
         Promise::ok(())
@@ -171,18 +175,23 @@ impl node::Server for NodeServer {
                  mut results: node::IterKeysResults)
                  -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let iter = KeyIterServer::new(self.c.clone());
+        let iter = KeyIterServer::new(self.s.clone(), self.h.clone(),
+                                      self.subscribers.clone());
         pry!(pry!(results.get().get_result()).set_ok(
             node::key_iter::ToClient::new(iter).into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
 
     fn log(&mut self,
-           _: node::LogParams,
+           params: node::LogParams,
            mut results: node::LogResults)
            -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let iter = log::IterServer::new(self.c.clone(), log::Selector::All);
+        let min_level = pry!(pry!(params.get()).get_min_level()).into();
+        let iter = log::IterServer::new(self.s.clone(), self.h.clone(),
+                                        self.subscribers.clone(),
+                                        log::Selector::All,
+                                        min_level);
         pry!(pry!(results.get().get_result()).set_ok(
             node::log_iter::ToClient::new(iter).into_client::<capnp_rpc::Server>()));
         Promise::ok(())
@@ -195,11 +204,12 @@ impl node::Server for NodeServer {
         bind_results!(results);
         let new = sry!(TPK::from_bytes(&pry!(pry!(params.get()).get_key())));
         let fp = new.fingerprint();
-        let key_id = sry!(KeyServer::lookup_or_create(&self.c, &fp));
-        let key = KeyServer::new(self.c.clone(), key_id);
-        sry!(key.merge(new));
+        let key_id = sry!(self.s.key_lookup_or_create(&fp));
+        sry!(KeyServer::merge(&*self.s, key_id, new));
         pry!(pry!(results.get().get_result())
-             .set_ok(node::key::ToClient::new(key)
+             .set_ok(node::key::ToClient::new(
+                 KeyServer::new(self.s.clone(), self.h.clone(),
+                                self.subscribers.clone(), key_id))
                      .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
@@ -211,11 +221,12 @@ impl node::Server for NodeServer {
         bind_results!(results);
         let keyid = pry!(params.get()).get_keyid();
         let keyid = KeyID::new(keyid);
-        let key_id = sry!(KeyServer::lookup_by_id(&self.c, &keyid));
+        let key_id = sry!(self.s.key_lookup_by_keyid(&keyid));
 
         pry!(pry!(results.get().get_result()).set_ok(
             node::key::ToClient::new(
-                KeyServer::new(self.c.clone(), key_id))
+                KeyServer::new(self.s.clone(), self.h.clone(),
+                               self.subscribers.clone(), key_id))
                 .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
@@ -227,11 +238,12 @@ impl node::Server for NodeServer {
         bind_results!(results);
         let fingerprint = pry!(pry!(params.get()).get_fingerprint());
         let fingerprint = sry!(Fingerprint::from_hex(fingerprint));
-        let key_id = sry!(KeyServer::lookup(&self.c, &fingerprint));
+        let key_id = sry!(self.s.key_lookup(&fingerprint));
 
         pry!(pry!(results.get().get_result()).set_ok(
             node::key::ToClient::new(
-                KeyServer::new(self.c.clone(), key_id))
+                KeyServer::new(self.s.clone(), self.h.clone(),
+                               self.subscribers.clone(), key_id))
                 .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
@@ -242,82 +254,104 @@ impl node::Server for NodeServer {
                           -> Promise<(), capnp::Error> {
         bind_results!(results);
         let keyid = pry!(params.get()).get_keyid();
-
-        let key_id: ID = sry!(
-            self.c.query_row(
-                "SELECT key FROM key_by_keyid
-                 WHERE key_by_keyid.keyid = ?1",
-                &[&(keyid as i64)], |row| row.get(0)));
+        let keyid = KeyID::new(keyid);
+        let key_id = sry!(self.s.key_lookup_by_subkeyid(&keyid));
 
         pry!(pry!(results.get().get_result()).set_ok(
             node::key::ToClient::new(
-                KeyServer::new(self.c.clone(), key_id))
+                KeyServer::new(self.s.clone(), self.h.clone(),
+                               self.subscribers.clone(), key_id))
                 .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
-}
 
-struct StoreServer {
-    c: Rc<Connection>,
-    id: ID,
-}
+    fn autocrypt_update(&mut self,
+                        params: node::AutocryptUpdateParams,
+                        mut results: node::AutocryptUpdateResults)
+                        -> Promise<(), capnp::Error> {
+        bind_results!(results);
+        let params = pry!(params.get());
+        let key = if params.has_key() {
+            Some((sry!(TPK::from_bytes(pry!(params.get_key()))),
+                  pry!(params.get_prefer_encrypt())))
+        } else {
+            None
+        };
 
-impl Query for StoreServer {
-    fn table_name() -> &'static str {
-        "stores"
+        sry!(autocrypt::update(&*self.s, pry!(params.get_addr()),
+                               params.get_effective_date(), key));
+        Promise::ok(())
     }
 
-    fn id(&self) -> ID {
-        self.id
+    fn autocrypt_update_gossip(&mut self,
+                               params: node::AutocryptUpdateGossipParams,
+                               mut results: node::AutocryptUpdateGossipResults)
+                               -> Promise<(), capnp::Error> {
+        bind_results!(results);
+        let params = pry!(params.get());
+        let key = sry!(TPK::from_bytes(pry!(params.get_key())));
+
+        sry!(autocrypt::update_gossip(&*self.s, pry!(params.get_addr()),
+                                      params.get_effective_date(), key));
+        Promise::ok(())
     }
 
-    fn connection(&self) -> Rc<Connection> {
-        self.c.clone()
+    fn autocrypt_peer(&mut self,
+                      params: node::AutocryptPeerParams,
+                      mut results: node::AutocryptPeerResults)
+                      -> Promise<(), capnp::Error> {
+        bind_results!(results);
+        let addr = pry!(pry!(params.get()).get_addr());
+        sry!(autocrypt::peer(&*self.s, addr,
+                             pry!(results.get().get_result()).init_ok()));
+        Promise::ok(())
     }
 
-    fn slug(&self) -> String {
-        self.c.query_row(
-            "SELECT realm, name FROM stores WHERE id = ?1",
-            &[&self.id], |row| -> String {
-                format!("{}:{}", row.get::<_, String>(0), row.get::<_, String>(1))
-            })
-            .unwrap_or(
-                format!("{}::{}", Self::table_name(), self.id())
-            )
+    fn subscribe(&mut self,
+                 params: node::SubscribeParams,
+                 mut results: node::SubscribeResults)
+                 -> Promise<(), capnp::Error> {
+        bind_results!(results);
+        let subscriber = pry!(pry!(params.get()).get_subscriber());
+        let subscription =
+            Subscribers::subscribe(&self.subscribers, None, subscriber);
+        pry!(pry!(results.get().get_result()).set_ok(
+            node::subscription::ToClient::new(subscription)
+                .into_client::<capnp_rpc::Server>()));
+        Promise::ok(())
     }
 }
 
+struct StoreServer {
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
+    id: ID,
+}
+
 impl StoreServer {
-    fn new(c: Rc<Connection>, id: ID) -> StoreServer {
-        StoreServer{c: c, id: id}
+    fn new(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>, id: ID)
+           -> StoreServer {
+        StoreServer{s: s, h: h, subscribers: subscribers, id: id}
     }
 
-    fn open(c: Rc<Connection>, realm: &str, policy: core::NetworkPolicy, name: &str)
+    fn open(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>,
+            realm: &str, policy: core::NetworkPolicy, name: &str)
            -> Result<Self> {
-        // We cannot implement ToSql and friends for
-        // core::NetworkPolicy, hence we need to do it by foot.
-        let p: u8 = (&policy).into();
-
-        c.execute(
-            "INSERT OR IGNORE INTO stores (realm, network_policy, name) VALUES (?1, ?2, ?3)",
-            &[&realm, &p, &name])?;
-        let (id, store_policy): (ID, i64) = c.query_row(
-            "SELECT id, network_policy FROM stores WHERE realm = ?1 AND name = ?2",
-            &[&realm, &name], |row| (row.get(0), row.get(1)))?;
-
-        // We cannot implement FromSql and friends for
-        // core::NetworkPolicy, hence we need to do it by foot.
-        if store_policy < 0 || store_policy > 3 {
-            return Err(super::Error::ProtocolError.into());
-        }
-        let store_policy = core::NetworkPolicy::from(store_policy as u8);
+        let (id, store_policy) = s.store_open(realm, name, policy)?;
 
         if store_policy != policy {
             return Err(core::Error::NetworkPolicyViolation(store_policy)
                        .into());
         }
 
-        Ok(Self::new(c, id))
+        Ok(Self::new(s, h, subscribers, id))
+    }
+
+    fn slug(&self) -> String {
+        self.s.store_info(self.id)
+            .map(|s| format!("{}:{}", s.realm, s.name))
+            .unwrap_or(format!("stores::{}", self.id))
     }
 }
 
@@ -333,12 +367,13 @@ impl node::store::Server for StoreServer {
                       .map_err(|_| node::Error::MalformedFingerprint));
         let label = pry!(params.get_label());
 
-        let (binding_id, key_id, created) = sry!(
-            BindingServer::lookup_or_create(&self.c, self.id, label, &fp));
+        let key_id = sry!(self.s.key_lookup_or_create(&fp));
+        let (binding_id, created) = sry!(
+            self.s.binding_lookup_or_create(self.id, label, key_id));
 
         if created {
-            sry!(log::message(
-                &self.c,
+            sry!(log::info(
+                &*self.s,
                 log::Refers::to().store(self.id).binding(binding_id).key(key_id),
                 &self.slug(),
                 &format!("New binding {} -> {}", label, fp.to_keyid())));
@@ -347,7 +382,8 @@ impl node::store::Server for StoreServer {
 
         pry!(pry!(results.get().get_result()).set_ok(
             node::binding::ToClient::new(
-                BindingServer::new(self.c.clone(), binding_id))
+                BindingServer::new(self.s.clone(), self.h.clone(),
+                                   self.subscribers.clone(), binding_id))
                 .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
@@ -358,15 +394,12 @@ impl node::store::Server for StoreServer {
               -> Promise<(), capnp::Error> {
         bind_results!(results);
         let label = pry!(pry!(params.get()).get_label());
-
-        let binding_id: ID = sry!(
-            self.c.query_row(
-                "SELECT id FROM bindings WHERE store = ?1 AND label = ?2",
-                &[&self.id, &label], |row| row.get(0)));
+        let binding_id = sry!(self.s.binding_lookup(self.id, label));
 
         pry!(pry!(results.get().get_result()).set_ok(
             node::binding::ToClient::new(
-                BindingServer::new(self.c.clone(), binding_id))
+                BindingServer::new(self.s.clone(), self.h.clone(),
+                                   self.subscribers.clone(), binding_id))
                 .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
@@ -377,17 +410,14 @@ impl node::store::Server for StoreServer {
                           -> Promise<(), capnp::Error> {
         bind_results!(results);
         let keyid = pry!(params.get()).get_keyid();
-
-        let binding_id: ID = sry!(
-            self.c.query_row(
-                "SELECT bindings.id FROM bindings
-                 JOIN key_by_keyid on bindings.key = key_by_keyid.key
-                 WHERE key_by_keyid.keyid = ?1",
-                &[&(keyid as i64)], |row| row.get(0)));
+        let keyid = KeyID::new(keyid);
+        let binding_id = sry!(self.s.binding_lookup_by_subkeyid(self.id,
+                                                                &keyid));
 
         pry!(pry!(results.get().get_result()).set_ok(
             node::binding::ToClient::new(
-                BindingServer::new(self.c.clone(), binding_id))
+                BindingServer::new(self.s.clone(), self.h.clone(),
+                                   self.subscribers.clone(), binding_id))
                 .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
@@ -397,8 +427,7 @@ impl node::store::Server for StoreServer {
               mut results: node::store::DeleteResults)
               -> Promise<(), capnp::Error> {
         bind_results!(results);
-        sry!(self.c.execute("DELETE FROM stores WHERE id = ?1",
-                                     &[&self.id]));
+        sry!(self.s.store_delete(self.id));
         Promise::ok(())
     }
 
@@ -407,109 +436,80 @@ impl node::store::Server for StoreServer {
             mut results: node::store::IterResults)
             -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let iter = BindingIterServer::new(self.c.clone(), self.id);
+        let iter = BindingIterServer::new(self.s.clone(), self.h.clone(),
+                                          self.subscribers.clone(), self.id);
         pry!(pry!(results.get().get_result()).set_ok(
             node::binding_iter::ToClient::new(iter).into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
 
     fn log(&mut self,
-           _: node::store::LogParams,
+           params: node::store::LogParams,
            mut results: node::store::LogResults)
            -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let iter = log::IterServer::new(self.c.clone(), log::Selector::Store(self.id));
+        let min_level = pry!(pry!(params.get()).get_min_level()).into();
+        let iter = log::IterServer::new(self.s.clone(), self.h.clone(),
+                                        self.subscribers.clone(),
+                                        log::Selector::Store(self.id),
+                                        min_level);
         pry!(pry!(results.get().get_result()).set_ok(
             node::log_iter::ToClient::new(iter).into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
+
+    fn subscribe(&mut self,
+                 params: node::store::SubscribeParams,
+                 mut results: node::store::SubscribeResults)
+                 -> Promise<(), capnp::Error> {
+        bind_results!(results);
+        let subscriber = pry!(pry!(params.get()).get_subscriber());
+        let subscription =
+            Subscribers::subscribe(&self.subscribers, Some(self.id),
+                                   subscriber);
+        pry!(pry!(results.get().get_result()).set_ok(
+            node::subscription::ToClient::new(subscription)
+                .into_client::<capnp_rpc::Server>()));
+        Promise::ok(())
+    }
 }
 
 struct BindingServer {
-    c: Rc<Connection>,
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
     id: ID,
 }
 
 impl BindingServer {
-    fn new(c: Rc<Connection>, id: ID) -> Self {
+    fn new(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>, id: ID)
+           -> Self {
         BindingServer {
-            c: c,
+            s: s,
+            h: h,
+            subscribers: subscribers,
             id: id,
         }
     }
 
-    fn key_id(&mut self) -> Result<ID> {
-        self.query("key").map(|id| id.into())
-    }
-
-
-    /// Looks up a binding, creating a binding if necessary.
-    ///
-    /// On success, the id of the binding and the key is returned, and
-    /// whether or not the entry was just created.
-    fn lookup_or_create(c: &Connection, store: ID, label: &str, fp: &Fingerprint)
-                        -> Result<(ID, ID, bool)> {
-        let key_id = KeyServer::lookup_or_create(c, fp)?;
-        if let Ok((binding, key)) = c.query_row(
-            "SELECT id, key FROM bindings WHERE store = ?1 AND label = ?2",
-            &[&store, &label], |row| -> (ID, ID) {(row.get(0), row.get(1))}) {
-            if key == key_id {
-                Ok((binding, key_id, false))
-            } else {
-                Err(super::Error::Conflict.into())
-            }
-        } else {
-            let r = c.execute(
-                "INSERT INTO bindings (store, label, key, created)
-                 VALUES (?, ?, ?, ?)",
-                &[&store, &label, &key_id, &Timestamp::now()]);
-
-            // Some other mutator might race us to the insertion.
-            match r {
-                Err(rusqlite::Error::SqliteFailure(f, _)) => match f.code {
-                    // We lost.  Retry the lookup.
-                    rusqlite::ErrorCode::ConstraintViolation => {
-                        let (binding, key): (ID, ID) = c.query_row(
-                            "SELECT id, key FROM bindings WHERE store = ?1 AND label = ?2",
-                            &[&store, &label], |row| (row.get(0), row.get(1)))?;
-                        if key == key_id {
-                            Ok((binding, key_id, false))
-                        } else {
-                            Err(node::Error::Conflict)
-                        }
-                    },
-                    // Raise otherwise.
-                    _ => Err(node::Error::SystemError),
-                },
-                Err(_) => Err(node::Error::SystemError),
-                Ok(_) => Ok((ID::from(c.last_insert_rowid()), key_id, true)),
-            }.map_err(|e| e.into())
-        }
-    }
-}
-
-impl Query for BindingServer {
-    fn table_name() -> &'static str {
-        "bindings"
+    fn key_id(&self) -> Result<ID> {
+        self.s.binding_info(self.id).map(|b| b.key)
     }
 
-    fn id(&self) -> ID {
-        self.id
+    /// Returns the statistics of this binding, and the update
+    /// status of its key.
+    fn current_stats(&self) -> Result<(Stats, UpdateStatus)> {
+        Ok((self.s.binding_stats(self.id)?,
+            self.s.key_update_status(self.key_id()?)?))
     }
 
-    fn connection(&self) -> Rc<Connection> {
-        self.c.clone()
-    }
-
-    fn slug(&self) -> String {
-        self.c.query_row(
-            "SELECT label FROM bindings WHERE id = ?1",
-            &[&self.id], |row| -> String {
-                row.get(0)
-            })
-            .unwrap_or(
-                format!("{}::{}", Self::table_name(), self.id())
-            )
+    /// Records a use of this binding, and returns its statistics.
+    fn register(&mut self, usage: Usage, stats: node::stats::Builder)
+                -> Result<()> {
+        self.s.binding_register(self.id, usage, Timestamp::now())?;
+        let (current, status) = self.current_stats()?;
+        set_stats(stats, current, status);
+        Ok(())
     }
 }
 
@@ -519,7 +519,8 @@ impl node::binding::Server for BindingServer {
              mut results: node::binding::StatsResults)
              -> Promise<(), capnp::Error> {
         bind_results!(results);
-        sry!(self.query_stats(pry!(results.get().get_result()).init_ok()));
+        let (stats, status) = sry!(self.current_stats());
+        set_stats(pry!(results.get().get_result()).init_ok(), stats, status);
         Promise::ok(())
     }
 
@@ -532,7 +533,9 @@ impl node::binding::Server for BindingServer {
 
         pry!(pry!(results.get().get_result()).set_ok(
             node::key::ToClient::new(
-                KeyServer::new(self.c.clone(), key)).into_client::<capnp_rpc::Server>()));
+                KeyServer::new(self.s.clone(), self.h.clone(),
+                               self.subscribers.clone(), key))
+                .into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
 
@@ -544,55 +547,23 @@ impl node::binding::Server for BindingServer {
         let force = pry!(params.get()).get_force();
 
         // This is the key to import.
-        let mut new = sry!(TPK::from_bytes(&pry!(pry!(params.get()).get_key())));
+        let new = sry!(TPK::from_bytes(&pry!(pry!(params.get()).get_key())));
 
-        // Check in the database for the current key.
+        // Check for conflicts with the current key.
         let key_id = sry!(self.key_id());
-        let (fingerprint, key): (String, Option<Vec<u8>>)
-            = sry!(self.c.query_row(
-                "SELECT fingerprint, key FROM keys WHERE id = ?1",
-                &[&key_id],
-                |row| (row.get(0), row.get_checked(1).ok())));
-
-        // If we found one, convert it to TPK.
-        let current = if let Some(current) = key {
-            let current = sry!(TPK::from_bytes(&current));
-            if current.fingerprint().to_hex() != fingerprint {
-                // Inconsistent database.
-                fail!(node::Error::SystemError);
-            }
-            Some(current)
-        } else {
-            None
-        };
-
-        // Check for conflicts.
-        if new.fingerprint().to_hex() != fingerprint {
+        if new.fingerprint() != sry!(self.s.key_fingerprint(key_id)) {
             if force {
                 // Update binding, and retry.
                 let key_id =
-                    sry!(KeyServer::lookup_or_create(
-                        &self.c, &new.fingerprint()));
-                sry!(self.c.execute("UPDATE bindings SET key = ?1 WHERE id = ?2",
-                                    &[&key_id, &self.id]));
+                    sry!(self.s.key_lookup_or_create(&new.fingerprint()));
+                sry!(self.s.binding_set_key(self.id, key_id));
                 return self.import(params, results);
             } else {
                 fail!(node::Error::Conflict);
             }
         }
 
-        if current.is_some() {
-            new = sry!(current.unwrap().merge(new));
-        }
-
-        // Write key back to the database.
-        let mut blob = vec![];
-        sry!(new.serialize(&mut blob));
-
-        sry!(self.c.execute("UPDATE keys SET key = ?1 WHERE id = ?2",
-                            &[&blob, &key_id]));
-        sry!(KeyServer::reindex_subkeys(&self.c, key_id, &new));
-
+        let blob = sry!(KeyServer::merge(&*self.s, key_id, new));
         pry!(pry!(results.get().get_result()).set_ok(&blob[..]));
         Promise::ok(())
     }
@@ -602,8 +573,7 @@ impl node::binding::Server for BindingServer {
               mut results: node::binding::DeleteResults)
               -> Promise<(), capnp::Error> {
         bind_results!(results);
-        sry!(self.c.execute("DELETE FROM bindings WHERE id = ?1",
-                                     &[&self.id]));
+        sry!(self.s.binding_delete(self.id));
         Promise::ok(())
     }
 
@@ -612,25 +582,8 @@ impl node::binding::Server for BindingServer {
                            mut results: node::binding::RegisterEncryptionResults)
                            -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let now = Timestamp::now();
-        let key = sry!(self.key_id());
-
-        sry!(self.c
-             .execute("UPDATE bindings
-                       SET encryption_count = encryption_count + 1,
-                           encryption_first = coalesce(encryption_first, ?2),
-                           encryption_last = ?2
-                       WHERE id = ?1",
-                      &[&self.id, &now]));
-        sry!(self.c
-             .execute("UPDATE keys
-                       SET encryption_count = encryption_count + 1,
-                           encryption_first = coalesce(encryption_first, ?2),
-                           encryption_last = ?2
-                       WHERE id = ?1",
-                      &[&key, &now]));
-
-        sry!(self.query_stats( pry!(results.get().get_result()).init_ok()));
+        sry!(self.register(Usage::Encryption,
+                           pry!(results.get().get_result()).init_ok()));
         Promise::ok(())
     }
 
@@ -639,34 +592,21 @@ impl node::binding::Server for BindingServer {
                              mut results: node::binding::RegisterVerificationResults)
                              -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let now = Timestamp::now();
-        let key = sry!(self.key_id());
-
-        sry!(self.c
-             .execute("UPDATE bindings
-                       SET verification_count = verification_count + 1,
-                           verification_first = coalesce(verification_first, ?2),
-                           verification_last = ?2
-                       WHERE id = ?1",
-                      &[&self.id, &now]));
-        sry!(self.c
-             .execute("UPDATE keys
-                       SET verification_count = verification_count + 1,
-                           verification_first = coalesce(verification_first, ?2),
-                           verification_last = ?2
-                       WHERE id = ?1",
-                      &[&key, &now]));
-
-        sry!(self.query_stats( pry!(results.get().get_result()).init_ok()));
+        sry!(self.register(Usage::Verification,
+                           pry!(results.get().get_result()).init_ok()));
         Promise::ok(())
     }
 
     fn log(&mut self,
-           _: node::binding::LogParams,
+           params: node::binding::LogParams,
            mut results: node::binding::LogResults)
            -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let iter = log::IterServer::new(self.c.clone(), log::Selector::Binding(self.id));
+        let min_level = pry!(pry!(params.get()).get_min_level()).into();
+        let iter = log::IterServer::new(self.s.clone(), self.h.clone(),
+                                        self.subscribers.clone(),
+                                        log::Selector::Binding(self.id),
+                                        min_level);
         pry!(pry!(results.get().get_result()).set_ok(
             node::log_iter::ToClient::new(iter).into_client::<capnp_rpc::Server>()));
         Promise::ok(())
@@ -677,274 +617,330 @@ impl node::binding::Server for BindingServer {
            mut results: node::binding::LabelResults)
            -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let label = sry!(self.c.query_row(
-            "SELECT label FROM bindings WHERE id = ?1",
-            &[&self.id], |row| -> String {
-                row.get(0)
-            }));
+        let label = sry!(self.s.binding_info(self.id)).label;
 
         pry!(pry!(results.get().get_result()).set_ok(label.as_str()));
         Promise::ok(())
     }
+
+    fn refresh(&mut self,
+               params: node::binding::RefreshParams,
+               mut results: node::binding::RefreshResults)
+               -> Promise<(), capnp::Error> {
+        bind_results!(results);
+        let now = pry!(params.get()).get_now();
+
+        // Honor the network policy of the store.
+        let binding = sry!(self.s.binding_info(self.id));
+        let network_policy = sry!(self.s.store_info(binding.store))
+            .network_policy;
+
+        let this = BindingServer::new(self.s.clone(), self.h.clone(),
+                                      self.subscribers.clone(), self.id);
+        Promise::from_future(
+            KeyServer::new(self.s.clone(), self.h.clone(),
+                           self.subscribers.clone(), binding.key)
+                .refresh(network_policy, now)
+                .and_then(move |_| this.current_stats())
+                .then(move |r| {
+                    let mut result = results.get().get_result()?;
+                    match r {
+                        Ok((stats, status)) =>
+                            set_stats(result.init_ok(), stats, status),
+                        Err(e) => result.set_err(e.into()),
+                    }
+                    Ok(())
+                }))
+    }
+}
+
+/// A source of key updates.
+#[derive(Debug, PartialEq)]
+enum Source {
+    /// The key server the key prefers.
+    KeyServer(String),
+    /// The Web Key Directory publishing the given address.
+    WKD(String),
+    /// The SKS keyserver pool.
+    Pool,
+}
+
+impl Source {
+    /// Returns the sources to query for updates of the given key.
+    ///
+    /// The key server the key prefers comes first, then the Web Key
+    /// Directories of the key's email addresses, and finally the SKS
+    /// keyserver pool.
+    fn for_tpk(tpk: Option<&TPK>) -> Vec<Source> {
+        let mut sources = Vec::new();
+        if let Some(tpk) = tpk {
+            let uri = tpk.primary_key_signature()
+                .and_then(|sig| sig.preferred_key_server())
+                .and_then(|uri| str::from_utf8(uri).ok());
+            if let Some(uri) = uri {
+                sources.push(Source::KeyServer(uri.into()));
+            }
+
+            for binding in tpk.userids() {
+                if let Ok(Some(address)) = binding.userid().address() {
+                    sources.push(Source::WKD(address));
+                }
+            }
+        }
+        sources.push(Source::Pool);
+        sources
+    }
 }
 
 struct KeyServer {
-    c: Rc<Connection>,
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
     id: ID,
 }
 
 impl KeyServer {
-    fn new(c: Rc<Connection>, id: ID) -> Self {
+    fn new(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>, id: ID)
+           -> Self {
         KeyServer {
-            c: c,
+            s: s,
+            h: h,
+            subscribers: subscribers,
             id: id,
         }
     }
 
-    /// Looks up a key by fingerprint.
-    ///
-    /// On success, the id of the key is returned.
-    fn lookup(c: &Connection, fp: &Fingerprint) -> Result<ID> {
-        let fp = fp.to_hex();
-        Ok(c.query_row(
-            "SELECT id FROM keys WHERE fingerprint = ?1",
-            &[&fp], |row| row.get(0))?)
+    /// Returns the statistics of this key, and its update status.
+    fn current_stats(&self) -> Result<(Stats, UpdateStatus)> {
+        Ok((self.s.key_stats(self.id)?, self.s.key_update_status(self.id)?))
     }
 
-    /// Looks up a key by keyid.
+    /// Returns the network policy to use when updating this key.
     ///
-    /// On success, the id of the key is returned.
-    fn lookup_by_id(c: &Connection, keyid: &KeyID) -> Result<ID> {
-        let keyid = format!("%{}", keyid.to_hex());
-        Ok(c.query_row(
-            "SELECT id FROM keys WHERE fingerprint LIKE ?1",
-            &[&keyid], |row| row.get(0))?)
-    }
+    /// This is the strictest policy of the stores binding the key.
+    /// Keys that are not bound in any store are not updated, hence
+    /// the policy for them is `Offline`.
+    fn network_policy(&self) -> core::NetworkPolicy {
+        let mut policy = None;
+        let mut after = ID::null();
+        while let Ok((store, info)) = self.s.store_next("", after) {
+            after = store;
+            if policy.map(|p| info.network_policy >= p).unwrap_or(false) {
+                continue;
+            }
 
-    /// Looks up a fingerprint, creating a key if necessary.
-    ///
-    /// On success, the id of the key is returned.
-    fn lookup_or_create(c: &Connection, fp: &Fingerprint) -> Result<ID> {
-        let fp = fp.to_hex();
-        if let Ok(x) = c.query_row(
-            "SELECT id FROM keys WHERE fingerprint = ?1",
-            &[&fp], |row| row.get(0)) {
-            Ok(x)
-        } else {
-            let r = c.execute(
-                "INSERT INTO keys (fingerprint, created, update_at) VALUES (?1, ?2, ?2)",
-                &[&fp, &Timestamp::now()]);
-
-            // Some other mutator might race us to the insertion.
-            match r {
-                Err(rusqlite::Error::SqliteFailure(f, e)) => match f.code {
-                    // We lost.  Retry the lookup.
-                    rusqlite::ErrorCode::ConstraintViolation =>
-                        c.query_row(
-                            "SELECT id FROM keys WHERE fingerprint = ?1",
-                            &[&fp], |row| row.get(0)),
-                    // Raise otherwise.
-                    _ => Err(rusqlite::Error::SqliteFailure(f, e)),
-                },
-                Err(e) => Err(e),
-                Ok(_) => Ok(c.last_insert_rowid().into()),
-            }.map_err(|e| e.into())
+            let mut n = ID::null();
+            while let Ok((binding, b)) = self.s.binding_next(store, n) {
+                n = binding;
+                if b.key == self.id {
+                    policy = Some(info.network_policy);
+                    break;
+                }
+            }
         }
+        policy.unwrap_or(core::NetworkPolicy::Offline)
     }
 
-    /// Merges other into this key updating the database.
+    /// Merges other into the given key updating the storage.
     ///
     /// Returnes the merged key as blob.
-    fn merge(&self, other: TPK) -> Result<Vec<u8>> {
+    fn merge(s: &Storage, id: ID, other: TPK) -> Result<Vec<u8>> {
         let mut new = other;
 
-        // Get the current key from the database.
-        let (fingerprint, key): (String, Option<Vec<u8>>)
-            = self.c.query_row(
-                "SELECT fingerprint, key FROM keys WHERE id = ?1",
-                &[&self.id],
-                |row| (row.get(0), row.get_checked(1).ok()))?;
+        // Get the current key from the storage.
+        let fingerprint = s.key_fingerprint(id)?;
+        if new.fingerprint() != fingerprint {
+            return Err(node::Error::Conflict.into());
+        }
 
         // If there was a key stored there, merge it.
-        if let Some(current) = key {
+        if let Some(current) = s.key_tpk(id)? {
             let current = TPK::from_bytes(&current)?;
 
-            if current.fingerprint().to_hex() != fingerprint {
-                // Inconsistent database.
+            if current.fingerprint() != fingerprint {
+                // Inconsistent storage.
                 return Err(node::Error::SystemError.into());
             }
 
-            if current.fingerprint() != new.fingerprint() {
-                return Err(node::Error::Conflict.into());
-            }
-
             new = current.merge(new)?;
         }
 
-        // Write key back to the database.
-        let mut blob = vec![];
-        new.serialize(&mut blob)?;
-
-        self.c.execute("UPDATE keys SET key = ?1 WHERE id = ?2",
-                       &[&blob, &self.id])?;
-        KeyServer::reindex_subkeys(&self.c, self.id, &new)?;
-
-        Ok(blob)
+        s.key_set_tpk(id, &new)
     }
 
-    /// Keeps the mapping of (sub)KeyIDs to keys up-to-date.
-    fn reindex_subkeys(c: &Connection, key_id: ID, tpk: &TPK) -> Result<()> {
-        for (_, _, key) in tpk.keys_all() {
-            let keyid = key.keyid().as_u64()
-                .expect("computed keyid is valid");
-
-            let r = c.execute(
-                "INSERT INTO key_by_keyid (keyid, key) VALUES (?1, ?2)",
-                &[&(keyid as i64), &key_id]);
-
-            // The mapping might already be present.  This is not an error.
-            match r {
-                Err(rusqlite::Error::SqliteFailure(f, e)) => match f.code {
-                    // Already present.
-                    rusqlite::ErrorCode::ConstraintViolation =>
-                        Ok(()),
-                    // Raise otherwise.
-                    _ => Err(rusqlite::Error::SqliteFailure(f, e)),
-                },
-                Err(e) => Err(e),
-                Ok(_) => Ok(()),
-            }?;
-        }
-        Ok(())
+    fn slug(&self) -> String {
+        self.s.key_fingerprint(self.id)
+            .map(|fp| fp.to_keyid().to_string())
+            .unwrap_or(format!("keys::{}", self.id))
     }
 
     /// Records a successful key update.
     fn success(&self, message: &str, next: Duration) -> Result<()> {
-        log::message(&self.c, log::Refers::to().key(self.id),
-                     &self.slug(), message)?;
-        self.c.execute("UPDATE keys
-                        SET updated = ?2, update_at = ?3
-                        WHERE id = ?1",
-                       &[&self.id, &Timestamp::now(),
-                         &(Timestamp::now() + next)])?;
-        Ok(())
+        log::debug(&*self.s, log::Refers::to().key(self.id),
+                   &self.slug(), message)?;
+        self.s.key_update_succeeded(self.id, Timestamp::now(),
+                                    Timestamp::now() + next)
     }
 
     /// Records an unsuccessful key update.
+    ///
+    /// The update is retried later, hence this is only a warning.
+    fn warning(&self, message: &str, error: &str, next: Duration)
+               -> Result<()> {
+        log::warning(&*self.s, log::Refers::to().key(self.id),
+                     &self.slug(), message, error)?;
+        self.s.key_update_failed(self.id, &format!("{}: {}", message, error),
+                                 Timestamp::now() + next)
+    }
+
+    /// Records a key update that could not be merged.
     fn error(&self, message: &str, error: &str, next: Duration) -> Result<()> {
-        log::error(&self.c, log::Refers::to().key(self.id),
+        log::error(&*self.s, log::Refers::to().key(self.id),
                    &self.slug(), message, error)?;
-        self.c.execute("UPDATE keys
-                        SET update_at = ?2
-                        WHERE id = ?1",
-                       &[&self.id,
-                         &(Timestamp::now() + next)])?;
-        Ok(())
+        self.s.key_update_failed(self.id, &format!("{}: {}", message, error),
+                                 Timestamp::now() + next)
     }
 
-    /// Returns when the next key using the given policy should be updated.
-    fn next_update_at(c: &Rc<Connection>, network_policy: core::NetworkPolicy)
-                      -> Option<Timestamp> {
-        let network_policy_u8 = u8::from(&network_policy);
-
-        // Select the key that was updated least recently.
-        c.query_row(
-            "SELECT keys.update_at FROM keys
-                 JOIN bindings on keys.id = bindings.key
-                 JOIN stores on stores.id = bindings.store
-                 WHERE stores.network_policy = ?1
-                 ORDER BY keys.update_at LIMIT 1",
-            &[&network_policy_u8], |row| -> Timestamp {row.get(0)}).ok()
-    }
-
-    /// Returns the number of keys using the given policy.
-    fn need_update(c: &Rc<Connection>, network_policy: core::NetworkPolicy)
-                   -> Result<i32> {
-        let network_policy_u8 = u8::from(&network_policy);
-
-        let count: i64 = c.query_row(
-            "SELECT COUNT(*) FROM keys
-                 JOIN bindings on keys.id = bindings.key
-                 JOIN stores on stores.id = bindings.store
-                 WHERE stores.network_policy >= ?1",
-            &[&network_policy_u8], |row| row.get(0))?;
-        assert!(count >= 0);
-        Ok(count as i32)
-    }
-
-    /// Helper for `update`.
-    fn update_helper(c: &Rc<Connection>, handle: &Handle,
-                     network_policy: core::NetworkPolicy)
-                     -> Result<(KeyServer,
-                                openpgp::KeyID,
-                                net::async::KeyServer)> {
-        assert!(network_policy != core::NetworkPolicy::Offline);
-        let network_policy_u8 = u8::from(&network_policy);
+    /// Returns the interval between two key updates.
+    fn update_interval(s: &Storage, network_policy: core::NetworkPolicy)
+                       -> Duration {
+        match s.key_need_update(network_policy) {
+            Ok(c) if c > 0 => refresh_interval() / c,
+            _ => min_sleep_time(),
+        }
+    }
 
-        // Select the key that was updated least recently.
-        let (id, fingerprint): (ID, String) = c.query_row(
-            "SELECT keys.id, keys.fingerprint FROM keys
-                 JOIN bindings on keys.id = bindings.key
-                 JOIN stores on stores.id = bindings.store
-                 WHERE stores.network_policy >= ?1
-                   AND keys.update_at < ?2
-                 ORDER BY keys.update_at LIMIT 1",
-            &[&network_policy_u8, &Timestamp::now()], |row| (row.get(0),
-                                                             row.get(1)))?;
-        let fingerprint = openpgp::Fingerprint::from_hex(&fingerprint)
-            .map_err(|_| node::Error::SystemError)?;
+    /// Returns a future fetching the key.
+    ///
+    /// The sources returned by `Source::for_tpk` are tried in order.
+    /// If all of them fail, the error of the last one is returned.
+    fn fetch(&self, network_policy: core::NetworkPolicy)
+             -> Result<Box<Future<Item=TPK, Error=failure::Error> + 'static>> {
+        let fingerprint = self.s.key_fingerprint(self.id)?;
+        let keyid = fingerprint.to_keyid();
+        let tpk = self.s.key_tpk(self.id)?
+            .and_then(|blob| TPK::from_bytes(&blob).ok());
 
         let ctx = core::Context::configure()
             .network_policy(network_policy).build()?;
-        let keyserver = net::async::KeyServer::sks_pool(&ctx, handle)?;
 
-        Ok((KeyServer::new(c.clone(), id),
-            fingerprint.to_keyid(),
-            keyserver))
+        let mut sources: Vec<Box<Future<Item=TPK, Error=failure::Error>>> =
+            Vec::new();
+        for source in Source::for_tpk(tpk.as_ref()) {
+            match source {
+                Source::KeyServer(uri) => {
+                    // Malformed URIs and those violating the network
+                    // policy are ignored.
+                    if let Ok(mut ks) =
+                        net::async::KeyServer::new(&ctx, &uri, &self.h)
+                    {
+                        sources.push(ks.get(&keyid));
+                    }
+                },
+                Source::WKD(address) => {
+                    // The directory may publish a different key.
+                    let fingerprint = fingerprint.clone();
+                    sources.push(Box::new(
+                        net::wkd::get(&ctx, &address, &self.h)
+                            .and_then(move |t| {
+                                if t.fingerprint() == fingerprint {
+                                    Ok(t)
+                                } else {
+                                    Err(net::Error::NotFound.into())
+                                }
+                            })));
+                },
+                Source::Pool => {
+                    let mut keyserver =
+                        net::async::KeyServer::sks_pool(&ctx, &self.h)?;
+                    sources.push(keyserver.get(&keyid));
+                },
+            }
+        }
+
+        let last = sources.pop().expect("the pool is always a source");
+        Ok(sources.into_iter().rev()
+           .fold(last, |next, source| Box::new(source.or_else(move |_| next))))
     }
 
-    /// Updates the key that was least recently updated.
-    fn update(c: &Rc<Connection>, handle: &Handle,
-              network_policy: core::NetworkPolicy)
-              -> Box<Future<Item=Duration, Error=failure::Error> + 'static> {
-        let (key, id, mut keyserver)
-            = match Self::update_helper(c, handle, network_policy) {
-            Ok((key, id, keyserver)) => (key, id, keyserver),
-            Err(e) => return Box::new(future::err(e.into())),
+    /// Updates the key, and records the outcome.
+    ///
+    /// The next update is scheduled after `next`, or after half of
+    /// that if the update failed.
+    fn update(self, network_policy: core::NetworkPolicy, next: Duration)
+              -> Box<Future<Item=(), Error=failure::Error> + 'static> {
+        let fetch = match self.fetch(network_policy) {
+            Ok(f) => f,
+            Err(e) => return Box::new(future::err(e)),
         };
 
-        let c = c.clone();
+        Box::new(fetch.then(move |tpk| {
+            match tpk.map(|t| KeyServer::merge(&*self.s, self.id, t)) {
+                Err(e) =>
+                    self.warning("Update unsuccessful",
+                                 &format!("{:?}", e), next / 2),
+                Ok(Err(e)) =>
+                    self.error("Merging update failed",
+                               &format!("{:?}", e), next),
+                Ok(Ok(_)) =>
+                    self.success("Update successful", next),
+            }
+        }))
+    }
+
+    /// Updates the key that was least recently updated.
+    ///
+    /// Returns the time to wait before the next update.
+    fn update_next(s: &Rc<Storage>, handle: &Handle,
+                   subscribers: &Rc<Subscribers>,
+                   network_policy: core::NetworkPolicy)
+                   -> Box<Future<Item=Duration, Error=failure::Error> + 'static> {
+        assert!(network_policy != core::NetworkPolicy::Offline);
+
         let now = Timestamp::now();
-        let at = Self::next_update_at(&c, network_policy)
+        let at = s.key_next_update_at(network_policy)
             .unwrap_or(now + min_sleep_time());
+        if at > now {
+            return Box::new(future::ok(cmp::max(min_sleep_time(), at - now)));
+        }
+
+        // Select the key that was updated least recently.
+        let id = match s.key_to_update(network_policy, now) {
+            Ok((id, _)) => id,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let next = Self::update_interval(&**s, network_policy);
+        Box::new(KeyServer::new(s.clone(), handle.clone(), subscribers.clone(),
+                                id)
+                 .update(network_policy, next)
+                 .then(move |_| Ok(next)))
+    }
 
-        if at <= now {
-            Box::new(
-                keyserver.get(&id)
-                    .then(move |tpk| {
-                        let next = Self::need_update(&c, network_policy)
-                            .map(|c| refresh_interval() / c)
-                            .unwrap_or(min_sleep_time());
-
-                        if let Err(e) = tpk.map(|t| key.merge(t)) {
-                            key.error("Update unsuccessful",
-                                      &format!("{:?}", e), next / 2)
-                                .unwrap_or(());
-                        } else {
-                            key.success("Update successful", next)
-                                .unwrap_or(());
-                        }
-
-                        future::ok(next)
-                    }))
+    /// Refreshes the key.
+    ///
+    /// If `now` is true, the key is updated right away.  Otherwise,
+    /// it is scheduled to be updated by the housekeeping as soon as
+    /// possible.
+    fn refresh(self, network_policy: core::NetworkPolicy, now: bool)
+               -> Box<Future<Item=(), Error=failure::Error> + 'static> {
+        if let Err(e) = network_policy.assert(core::NetworkPolicy::Encrypted) {
+            return Box::new(future::err(e.into()));
+        }
+
+        if now {
+            let next = Self::update_interval(&*self.s, network_policy);
+            self.update(network_policy, next)
         } else {
-            assert!(at > now);
-            Box::new(future::ok(cmp::max(min_sleep_time(), at - now)))
+            Box::new(future::done(
+                self.s.key_schedule_update(self.id, Timestamp::now())))
         }
     }
 
     /// Starts the periodic housekeeping.
-    fn start_housekeeping(c: Rc<Connection>, handle: Handle) -> Result<()> {
+    fn start_housekeeping(s: Rc<Storage>, handle: Handle,
+                          subscribers: Rc<Subscribers>)
+                          -> Result<()> {
         let h0 = handle.clone();
 
         let forever = loop_fn(0, move |_| {
@@ -953,7 +949,13 @@ impl KeyServer {
 
             let h1 = h0.clone();
 
-            Self::update(&c, &h0, network_policy)
+            // Apply the log retention policy.  Errors are not fatal,
+            // we will try again next time.
+            s.log_trim(Timestamp::now() - log_max_age(),
+                       log_max_entries_per_key())
+                .unwrap_or(());
+
+            Self::update_next(&s, &h0, &subscribers, network_policy)
                 .then(move |d| {
                     let d = d.unwrap_or(min_sleep_time());
                      Timeout::new(
@@ -975,39 +977,14 @@ impl KeyServer {
     }
 }
 
-impl Query for KeyServer {
-    fn table_name() -> &'static str {
-        "keys"
-    }
-
-    fn id(&self) -> ID {
-        self.id
-    }
-
-    fn connection(&self) -> Rc<Connection> {
-        self.c.clone()
-    }
-
-    fn slug(&self) -> String {
-        self.c.query_row(
-            "SELECT fingerprint FROM keys WHERE id = ?1",
-            &[&self.id], |row| -> String { row.get(0) })
-            .ok()
-            .and_then(|fp| Fingerprint::from_hex(&fp).ok())
-            .map(|fp| fp.to_keyid().to_string())
-            .unwrap_or(
-                format!("{}::{}", Self::table_name(), self.id())
-            )
-    }
-}
-
 impl node::key::Server for KeyServer {
     fn stats(&mut self,
              _: node::key::StatsParams,
              mut results: node::key::StatsResults)
              -> Promise<(), capnp::Error> {
         bind_results!(results);
-        sry!(self.query_stats( pry!(results.get().get_result()).init_ok()));
+        let (stats, status) = sry!(self.current_stats());
+        set_stats(pry!(results.get().get_result()).init_ok(), stats, status);
         Promise::ok(())
     }
 
@@ -1016,11 +993,7 @@ impl node::key::Server for KeyServer {
            mut results: node::key::TpkResults)
            -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let key: Vec<u8> = sry!(
-            self.c.query_row(
-                "SELECT key FROM keys WHERE id = ?1",
-                &[&self.id],
-                |row| row.get_checked(0).unwrap_or(vec![])));
+        let key = sry!(self.s.key_tpk(self.id)).unwrap_or(vec![]);
         pry!(pry!(results.get().get_result()).set_ok(key.as_slice()));
         Promise::ok(())
     }
@@ -1031,95 +1004,95 @@ impl node::key::Server for KeyServer {
               -> Promise<(), capnp::Error> {
         bind_results!(results);
         let new = sry!(TPK::from_bytes(&pry!(pry!(params.get()).get_key())));
-        let blob = sry!(self.merge(new));
+        let blob = sry!(KeyServer::merge(&*self.s, self.id, new));
         pry!(pry!(results.get().get_result()).set_ok(&blob[..]));
         Promise::ok(())
     }
 
     fn log(&mut self,
-           _: node::key::LogParams,
+           params: node::key::LogParams,
            mut results: node::key::LogResults)
            -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let iter = log::IterServer::new(self.c.clone(), log::Selector::Key(self.id));
+        let min_level = pry!(pry!(params.get()).get_min_level()).into();
+        let iter = log::IterServer::new(self.s.clone(), self.h.clone(),
+                                        self.subscribers.clone(),
+                                        log::Selector::Key(self.id),
+                                        min_level);
         pry!(pry!(results.get().get_result()).set_ok(
             node::log_iter::ToClient::new(iter).into_client::<capnp_rpc::Server>()));
         Promise::ok(())
     }
+
+    fn refresh(&mut self,
+               params: node::key::RefreshParams,
+               mut results: node::key::RefreshResults)
+               -> Promise<(), capnp::Error> {
+        let now = pry!(params.get()).get_now();
+
+        // Honor the strictest network policy of the stores binding
+        // the key.
+        let key = KeyServer::new(self.s.clone(), self.h.clone(),
+                                 self.subscribers.clone(), self.id);
+        let network_policy = key.network_policy();
+        let this = KeyServer::new(self.s.clone(), self.h.clone(),
+                                  self.subscribers.clone(), self.id);
+        Promise::from_future(
+            key.refresh(network_policy, now)
+                .and_then(move |_| this.current_stats())
+                .then(move |r| {
+                    let mut result = results.get().get_result()?;
+                    match r {
+                        Ok((stats, status)) =>
+                            set_stats(result.init_ok(), stats, status),
+                        Err(e) => result.set_err(e.into()),
+                    }
+                    Ok(())
+                }))
+    }
 }
 
-/// Common code for BindingServer and KeyServer.
-trait Query {
-    fn table_name() -> &'static str;
-    fn id(&self) -> ID;
-    fn connection(&self) -> Rc<Connection>;
-    fn slug(&self) -> String;
-
-    fn query(&mut self, column: &str) -> Result<i64> {
-        self.connection().query_row(
-            &format!("SELECT {} FROM {} WHERE id = ?1", column, Self::table_name()),
-            &[&self.id()], |row| row.get(0)).map_err(|e| e.into())
-    }
-
-    fn query_stats(&mut self, mut stats: node::stats::Builder) -> Result<()> {
-        let (
-            created, updated,
-            encryption_count, encryption_first, encryption_last,
-            verification_count, verification_first, verification_last,
-        ): (i64, Option<i64>,
-            i64, Option<i64>, Option<i64>,
-            i64, Option<i64>, Option<i64>)
-            = self.connection().query_row(
-                &format!("SELECT
-                          created,
-                          updated,
-                          encryption_count,
-                          encryption_first,
-                          encryption_last,
-                          verification_count,
-                          verification_first,
-                          verification_last
-                          FROM {0}
-                          WHERE id = ?1", Self::table_name()),
-                &[&self.id()], |row| (row.get(0), row.get(1),
-                                      row.get(2), row.get(3), row.get(4),
-                                      row.get(5), row.get(6), row.get(7)))?;
-        macro_rules! set_some {
-            ( $object: ident) => {
-                macro_rules! set {
-                    ($setter: ident, $value: expr ) => {{
-                        if let Some(value) = $value {
-                            $object.$setter(value);
-                        }
-                    }}
-                }
+/// Copies statistics and the key's update status into the given
+/// builder.
+fn set_stats(mut builder: node::stats::Builder, stats: Stats,
+             status: UpdateStatus) {
+    macro_rules! set {
+        ($setter: ident, $value: expr ) => {{
+            if let Some(value) = $value {
+                builder.$setter(value.unix());
             }
-        }
+        }}
+    }
 
-        set_some!(stats);
-        stats.set_created(created);
-        set!(set_updated, updated);
-        stats.set_encryption_count(encryption_count);
-        set!(set_encryption_first, encryption_first);
-        set!(set_encryption_last, encryption_last);
-        stats.set_verification_count(verification_count);
-        set!(set_verification_first, verification_first);
-        set!(set_verification_last, verification_last);
-        Ok(())
+    builder.set_created(stats.created.unix());
+    set!(set_updated, stats.updated);
+    builder.set_encryption_count(stats.encryption.count);
+    set!(set_encryption_first, stats.encryption.first);
+    set!(set_encryption_last, stats.encryption.last);
+    builder.set_verification_count(stats.verification.count);
+    set!(set_verification_first, stats.verification.first);
+    set!(set_verification_last, stats.verification.last);
+    builder.set_update_at(status.update_at.unix());
+    if let Some(ref error) = status.last_error {
+        builder.set_last_error(error);
     }
 }
 
 /* Iterators.  */
 
 struct StoreIterServer {
-    c: Rc<Connection>,
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
     prefix: String,
     n: ID,
 }
 
 impl StoreIterServer {
-    fn new(c: Rc<Connection>, prefix: &str) -> Self {
-        StoreIterServer{c: c, prefix: String::from(prefix) + "%", n: ID::null()}
+    fn new(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>,
+           prefix: &str) -> Self {
+        StoreIterServer{s: s, h: h, subscribers: subscribers,
+                        prefix: String::from(prefix), n: ID::null()}
     }
 }
 
@@ -1129,41 +1102,34 @@ impl node::store_iter::Server for StoreIterServer {
             mut results: node::store_iter::NextResults)
             -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let (id, realm, name, network_policy): (ID, String, String, i64) =
-            sry!(self.c.query_row(
-                 "SELECT id, realm, name, network_policy FROM stores
-                      WHERE id > ?1 AND realm like ?2
-                      ORDER BY id LIMIT 1",
-                &[&self.n, &self.prefix],
-                |row| (row.get(0), row.get(1), row.get(2), row.get(3))));
-
-        // We cannot implement FromSql and friends for
-        // core::NetworkPolicy, hence we need to do it by foot.
-        if network_policy < 0 || network_policy > 3 {
-            fail!(node::Error::SystemError);
-        }
-        let network_policy = core::NetworkPolicy::from(network_policy as u8);
+        let (id, store) = sry!(self.s.store_next(&self.prefix, self.n));
 
         let mut entry = pry!(results.get().get_result()).init_ok();
-        entry.set_realm(&realm);
-        entry.set_name(&name);
-        entry.set_network_policy(network_policy.into());
+        entry.set_realm(&store.realm);
+        entry.set_name(&store.name);
+        entry.set_network_policy(store.network_policy.into());
         entry.set_store(node::store::ToClient::new(
-            StoreServer::new(self.c.clone(), id)).into_client::<capnp_rpc::Server>());
+            StoreServer::new(self.s.clone(), self.h.clone(),
+                             self.subscribers.clone(), id))
+                .into_client::<capnp_rpc::Server>());
         self.n = id;
         Promise::ok(())
     }
 }
 
 struct BindingIterServer {
-    c: Rc<Connection>,
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
     store_id: ID,
     n: ID,
 }
 
 impl BindingIterServer {
-    fn new(c: Rc<Connection>, store_id: ID) -> Self {
-        BindingIterServer{c: c, store_id: store_id, n: ID::null()}
+    fn new(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>,
+           store_id: ID) -> Self {
+        BindingIterServer{s: s, h: h, subscribers: subscribers,
+                          store_id: store_id, n: ID::null()}
     }
 }
 
@@ -1173,33 +1139,31 @@ impl node::binding_iter::Server for BindingIterServer {
             mut results: node::binding_iter::NextResults)
             -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let (id, label, fingerprint): (ID, String, String) =
-            sry!(self.c.query_row(
-                 "SELECT bindings.id, bindings.label, keys.fingerprint FROM bindings
-                      JOIN keys ON bindings.key = keys.id
-                      WHERE bindings.id > ?1 AND bindings.store = ?2
-                      ORDER BY bindings.id LIMIT 1",
-                &[&self.n, &self.store_id],
-                |row| (row.get(0), row.get(1), row.get(2))));
+        let (id, binding) = sry!(self.s.binding_next(self.store_id, self.n));
+        let fingerprint = sry!(self.s.key_fingerprint(binding.key));
 
         let mut entry = pry!(results.get().get_result()).init_ok();
-        entry.set_label(&label);
-        entry.set_fingerprint(&fingerprint);
+        entry.set_label(&binding.label);
+        entry.set_fingerprint(&fingerprint.to_hex());
         entry.set_binding(node::binding::ToClient::new(
-            BindingServer::new(self.c.clone(), id)).into_client::<capnp_rpc::Server>());
+            BindingServer::new(self.s.clone(), self.h.clone(),
+                               self.subscribers.clone(), id))
+                .into_client::<capnp_rpc::Server>());
         self.n = id;
         Promise::ok(())
     }
 }
 
 struct KeyIterServer {
-    c: Rc<Connection>,
+    s: Rc<Storage>,
+    h: Handle,
+    subscribers: Rc<Subscribers>,
     n: ID,
 }
 
 impl KeyIterServer {
-    fn new(c: Rc<Connection>) -> Self {
-        KeyIterServer{c: c, n: ID::null()}
+    fn new(s: Rc<Storage>, h: Handle, subscribers: Rc<Subscribers>) -> Self {
+        KeyIterServer{s: s, h: h, subscribers: subscribers, n: ID::null()}
     }
 }
 
@@ -1209,18 +1173,14 @@ impl node::key_iter::Server for KeyIterServer {
             mut results: node::key_iter::NextResults)
             -> Promise<(), capnp::Error> {
         bind_results!(results);
-        let (id, fingerprint): (ID, String) =
-            sry!(self.c.query_row(
-                 "SELECT id, fingerprint FROM keys
-                      WHERE keys.id > ?1
-                      ORDER BY id LIMIT 1",
-                &[&self.n],
-                |row| (row.get(0), row.get(1))));
+        let (id, fingerprint) = sry!(self.s.key_next(self.n));
 
         let mut entry = pry!(results.get().get_result()).init_ok();
-        entry.set_fingerprint(&fingerprint);
+        entry.set_fingerprint(&fingerprint.to_hex());
         entry.set_key(node::key::ToClient::new(
-            KeyServer::new(self.c.clone(), id)).into_client::<capnp_rpc::Server>());
+            KeyServer::new(self.s.clone(), self.h.clone(),
+                           self.subscribers.clone(), id))
+                .into_client::<capnp_rpc::Server>());
         self.n = id;
         Promise::ok(())
     }
@@ -1347,84 +1307,6 @@ impl From<io::Error> for node::Error {
 }
 
 
-/* Database schemata and migrations.  */
-
-/* Version 1.  */
-const DB_SCHEMA_1: &'static str = "
-CREATE TABLE version (
-    id INTEGER PRIMARY KEY,
-    version INTEGER);
-
-INSERT INTO version (id, version) VALUES (1, 1);
-
-CREATE TABLE stores (
-    id INTEGER PRIMARY KEY,
-    realm TEXT NOT NULL,
-    network_policy INTEGER NOT NULL,
-    name TEXT NOT NULL,
-    UNIQUE (realm, name));
-
-CREATE TABLE bindings (
-    id INTEGER PRIMARY KEY,
-    store INTEGER NOT NULL,
-    label TEXT NOT NULL,
-    key INTEGER NOT NULL,
-
-    created INTEGER NOT NULL,
-    updated INTEGER NULL,
-
-    encryption_count DEFAULT 0,
-    encryption_first INTEGER NULL,
-    encryption_last INTEGER NULL,
-    verification_count DEFAULT 0,
-    verification_first INTEGER NULL,
-    verification_last INTEGER NULL,
-
-    UNIQUE(store, label),
-    FOREIGN KEY (store) REFERENCES stores(id) ON DELETE CASCADE,
-    FOREIGN KEY (key) REFERENCES keys(id) ON DELETE CASCADE);
-
-CREATE TABLE keys (
-    id INTEGER PRIMARY KEY,
-    fingerprint TEXT NOT NULL,
-    key BLOB,
-
-    created INTEGER NOT NULL,
-    updated INTEGER NULL,
-    update_at INTEGER NOT NULL,
-
-    encryption_count DEFAULT 0,
-    encryption_first INTEGER NULL,
-    encryption_last INTEGER NULL,
-    verification_count DEFAULT 0,
-    verification_first INTEGER NULL,
-    verification_last INTEGER NULL,
-
-    UNIQUE (fingerprint));
-
-CREATE TABLE key_by_keyid (
-    id INTEGER PRIMARY KEY,
-    keyid INTEGER NOT NULL,
-    key INTEGER NOT NULL,
-
-    UNIQUE(keyid, key),
-    FOREIGN KEY (key) REFERENCES keys(id) ON DELETE CASCADE);
-
-CREATE TABLE log (
-    id INTEGER PRIMARY KEY,
-    timestamp INTEGER NOT NULL,
-    level INTEGER NOT NULL,
-    store INTEGER NULL,
-    binding INTEGER NULL,
-    key INTEGER NULL,
-    slug TEXT NOT NULL,
-    message TEXT NOT NULL,
-    error TEXT NULL,
-    FOREIGN KEY (store) REFERENCES stores(id) ON DELETE CASCADE,
-    FOREIGN KEY (binding) REFERENCES bindings(id) ON DELETE CASCADE,
-    FOREIGN KEY (key) REFERENCES keys(id) ON DELETE CASCADE);
-";
-
 /* Miscellaneous.  */
 
 impl<'a> From<&'a core::NetworkPolicy> for node::NetworkPolicy {
@@ -1454,3 +1336,67 @@ impl From<node::NetworkPolicy> for core::NetworkPolicy {
         }
     }
 }
+
+impl From<node::EventKind> for ::EventKind {
+    fn from(kind: node::EventKind) -> Self {
+        match kind {
+            node::EventKind::KeyUpdated     => ::EventKind::KeyUpdated,
+            node::EventKind::BindingAdded   => ::EventKind::BindingAdded,
+            node::EventKind::BindingRotated => ::EventKind::BindingRotated,
+            node::EventKind::BindingDeleted => ::EventKind::BindingDeleted,
+        }
+    }
+}
+
+impl From<::LogLevel> for node::LogLevel {
+    fn from(level: ::LogLevel) -> Self {
+        match level {
+            ::LogLevel::Debug   => node::LogLevel::Debug,
+            ::LogLevel::Info    => node::LogLevel::Info,
+            ::LogLevel::Warning => node::LogLevel::Warning,
+            ::LogLevel::Error   => node::LogLevel::Error,
+        }
+    }
+}
+
+impl From<node::LogLevel> for ::LogLevel {
+    fn from(level: node::LogLevel) -> Self {
+        match level {
+            node::LogLevel::Debug   => ::LogLevel::Debug,
+            node::LogLevel::Info    => ::LogLevel::Info,
+            node::LogLevel::Warning => ::LogLevel::Warning,
+            node::LogLevel::Error   => ::LogLevel::Error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use openpgp::constants::SignatureType;
+    use openpgp::packet::{UserID, signature};
+    use openpgp::tpk::TPKBuilder;
+
+    #[test]
+    fn source_order() {
+        assert_eq!(Source::for_tpk(None), vec![Source::Pool]);
+
+        let (tpk, _) = TPKBuilder::new().generate().unwrap();
+        assert_eq!(Source::for_tpk(Some(&tpk)), vec![Source::Pool]);
+
+        let mut keypair = tpk.primary().clone().into_keypair().unwrap();
+        let userid = UserID::from("Alice <alice@example.org>");
+        let builder =
+            signature::Builder::new(SignatureType::PositiveCertificate)
+            .set_preferred_key_server(b"hkps://keys.example.org").unwrap();
+        let binding = userid.bind(&mut keypair, &tpk, builder, None, None)
+            .unwrap();
+        let tpk = tpk.merge_packets(vec![userid.into(), binding.into()])
+            .unwrap();
+
+        assert_eq!(Source::for_tpk(Some(&tpk)),
+                   vec![Source::KeyServer("hkps://keys.example.org".into()),
+                        Source::WKD("alice@example.org".into()),
+                        Source::Pool]);
+    }
+}
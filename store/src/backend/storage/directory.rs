@@ -0,0 +1,1564 @@
+//! Stores keys and bindings in a directory.
+//!
+//! Every key is stored in its own file `keys/<FINGERPRINT>.asc`, and
+//! every store in its own file `stores/<realm>/<name>`, listing the
+//! store's bindings.  Both are plain text files, hence the directory
+//! can be kept in a version control system, or shared using a
+//! network file system.
+//!
+//! A store file looks like this:
+//!
+//! ```text
+//! realm org.sequoia-pgp.contacts
+//! name default
+//! network-policy Encrypted
+//! binding 3E8877C877274692975189F5D03F6F865226FE8B Mister%20B.
+//! ```
+//!
+//! Realms, names, and labels are percent-encoded.
+//!
+//! Everything else the store needs to remember is kept in the
+//! directory `state`: usage statistics, update schedules, and the
+//! reasons updates failed in `state/keys` and `state/bindings`, the
+//! Autocrypt peer state in `state/autocrypt`, and the log in
+//! `state/log`.  The log is only appended to, until it is trimmed.
+//!
+//! The directory is kept in memory.  Changes are made while holding
+//! the lock file `.lock`, and written through to the directory.
+//! Every change increments the counter in `.serial`.  Before every
+//! lookup and every change, we check whether the counter changed,
+//! and if so, read the files that changed since we last looked at
+//! them.  Hence, changes made by other processes, or by other hosts
+//! sharing the directory, are picked up.  Programs changing the files
+//! by other means, e.g. by checking out a different version, need to
+//! remove `.serial` for the changes to be picked up.  Removing a key
+//! file removes the TPK, but the key is kept as long as bindings
+//! refer to it.
+//!
+//! Changes to keys and bindings picked up from the directory are
+//! reported using `Storage::take_changes`.
+
+use failure;
+use fs2::FileExt;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openpgp::{TPK, KeyID, Fingerprint, armor};
+use openpgp::parse::Parse;
+use openpgp::serialize::Serialize;
+use sequoia_core as core;
+
+use LogLevel;
+use store_protocol_capnp::node;
+use backend::Result;
+use backend::support::{ID, Timestamp};
+use backend::log::{self, Refers, Selector};
+use super::{
+    Storage, StoreInfo, BindingInfo, Stats, UpdateStatus, Usage, LogEntry,
+    AutocryptPeer, Memory, Change, Counter,
+};
+
+lazy_static! {
+    /// What the servers of this process know about the directories'
+    /// files.
+    static ref SNAPSHOTS: Mutex<HashMap<PathBuf, Weak<Mutex<Snapshot>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Identifies a version of a file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl Stamp {
+    /// Returns the stamp of the file at `path`.
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Stamp {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// The versions of the files that are reflected in memory.
+#[derive(Default)]
+struct Snapshot {
+    /// The value of `.serial` when we last looked at the files.
+    serial: Option<u64>,
+    /// Also records the fingerprint of the key read from the file,
+    /// unless it was malformed.
+    keys: HashMap<PathBuf, (Stamp, Option<Fingerprint>)>,
+    /// Also records the store read from the file, unless it was
+    /// malformed.
+    stores: HashMap<PathBuf, (Stamp, Option<ID>)>,
+    /// The generation of the log, and how much of it we read.
+    log: Option<(u64, u64)>,
+}
+
+/// Stores keys and bindings in a directory.
+pub struct Directory {
+    path: PathBuf,
+    memory: Memory,
+    snapshot: Arc<Mutex<Snapshot>>,
+    /// Changes picked up from the directory.
+    changes: RefCell<Vec<Change>>,
+}
+
+/// Keeps other servers and processes from changing the directory.
+struct Lock<'a> {
+    _updates: MutexGuard<'a, ()>,
+    // Closing the file releases the lock.
+    _file: fs::File,
+}
+
+/// Stores are identified by realm and name in the state files.
+type StoreIDs = HashMap<(String, String), ID>;
+
+/// A problem with a file found while reading the directory.
+type Warning = (String, &'static str, String);
+
+impl Directory {
+    /// Opens the given directory, creating it if necessary.
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path.join("keys"))?;
+        fs::create_dir_all(path.join("stores"))?;
+        fs::create_dir_all(path.join("state"))?;
+
+        let mut created = false;
+        let memory = Memory::shared(path, |_| {
+            created = true;
+            Ok(())
+        })?;
+
+        // If the state in memory was just created, we need to read
+        // all files.
+        let snapshot = {
+            let mut snapshots = SNAPSHOTS.lock().unwrap();
+            match snapshots.get(path).and_then(|s| s.upgrade()) {
+                Some(ref snapshot) if ! created => snapshot.clone(),
+                _ => {
+                    let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+                    snapshots.insert(path.into(), Arc::downgrade(&snapshot));
+                    snapshot
+                },
+            }
+        };
+
+        let d = Directory {
+            path: path.into(),
+            memory: memory,
+            snapshot: snapshot,
+            changes: RefCell::new(Vec::new()),
+        };
+        d.refresh()?;
+        if created {
+            // Everything is new, nobody needs to be told.
+            d.changes.borrow_mut().clear();
+        }
+        Ok(d)
+    }
+
+    /// Reads the files that changed since we last looked at them.
+    fn refresh(&self) -> Result<()> {
+        let _updates = self.memory.lock_updates();
+        self.load()
+    }
+
+    /// Locks the directory, and reads the files that changed since
+    /// we last looked at them.
+    ///
+    /// Changes must be made while holding the lock, so that we do
+    /// not overwrite changes made by others.
+    fn lock(&self) -> Result<Lock> {
+        let updates = self.memory.lock_updates();
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(self.path.join(".lock"))?;
+        file.lock_exclusive()?;
+        self.load()?;
+        Ok(Lock {
+            _updates: updates,
+            _file: file,
+        })
+    }
+
+    /// Returns the value of `.serial`, if any.
+    fn serial(&self) -> Result<Option<u64>> {
+        match read_file(&self.path.join(".serial"))? {
+            Some(content) => Ok(content.trim().parse().ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Tells others that we changed the directory.
+    ///
+    /// The caller must hold the lock.
+    fn changed(&self) -> Result<()> {
+        let serial = self.serial()?.unwrap_or(0).wrapping_add(1);
+        write_atomically(&self.path.join(".serial"),
+                         format!("{}\n", serial).as_bytes())?;
+        self.snapshot.lock().unwrap().serial = Some(serial);
+        Ok(())
+    }
+
+    /// Returns the path of the given state file.
+    fn state_path(&self, name: &str) -> PathBuf {
+        self.path.join("state").join(name)
+    }
+
+    /// Populates memory with the files that changed since we last
+    /// looked at them.
+    ///
+    /// If `.serial` did not change, nothing is read.  Files that
+    /// cannot be parsed are skipped, and a warning is logged.  The
+    /// changes are recorded in `self.changes`.  The caller must hold
+    /// the updates lock.
+    fn load(&self) -> Result<()> {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        let serial = self.serial()?;
+        if serial.is_some() && serial == snapshot.serial {
+            return Ok(());
+        }
+
+        let mut changes = self.changes.borrow_mut();
+        let mut warnings = Vec::new();
+        self.load_keys(&mut snapshot, &mut changes, &mut warnings)?;
+        self.load_stores(&mut snapshot, &mut changes, &mut warnings)?;
+
+        let stores = self.store_ids();
+        self.load_stats(&stores)?;
+        self.load_autocrypt()?;
+        self.load_log(&mut snapshot, &stores, &mut warnings)?;
+
+        // Log the warnings after reading the log, which may replace
+        // it.
+        for (slug, message, error) in warnings {
+            log::warning(&self.memory, Refers::to(), &slug, message, &error)?;
+        }
+
+        snapshot.serial = serial;
+        Ok(())
+    }
+
+    /// Reads the key files that changed since we last looked at
+    /// them.
+    fn load_keys(&self, snapshot: &mut Snapshot, changes: &mut Vec<Change>,
+                 warnings: &mut Vec<Warning>)
+                 -> Result<()> {
+        let mut seen = HashSet::new();
+        for entry in fs::read_dir(self.path.join("keys"))? {
+            let path = entry?.path();
+            if path.extension().map(|e| e != "asc").unwrap_or(true) {
+                continue;
+            }
+
+            let stamp = match Stamp::of(&path) {
+                Ok(s) => s,
+                // Removed in the meantime.
+                Err(_) => continue,
+            };
+            seen.insert(path.clone());
+            let previous = snapshot.keys.get(&path).cloned();
+            if previous.as_ref().map(|&(s, _)| s == stamp).unwrap_or(false) {
+                continue;
+            }
+
+            let fingerprint = match TPK::from_file(&path) {
+                Ok(tpk) => {
+                    let id = self.memory.key_lookup_or_create(
+                        &tpk.fingerprint())?;
+                    let old = self.memory.key_tpk(id)?;
+                    let blob = self.memory.key_set_tpk(id, &tpk)?;
+                    if old.as_ref() != Some(&blob) {
+                        changes.push(Change {
+                            kind: node::EventKind::KeyUpdated,
+                            fingerprint: tpk.fingerprint(),
+                            binding: None,
+                        });
+                    }
+                    Some(tpk.fingerprint())
+                },
+                Err(e) => {
+                    warnings.push((path.to_string_lossy().into_owned(),
+                                   "Skipping malformed key file",
+                                   e.to_string()));
+                    previous.and_then(|(_, fp)| fp)
+                },
+            };
+            snapshot.keys.insert(path, (stamp, fingerprint));
+        }
+
+        // Keys whose files have been removed.
+        let gone = snapshot.keys.keys()
+            .filter(|p| ! seen.contains(*p))
+            .cloned().collect::<Vec<_>>();
+        for path in gone {
+            let fingerprint = match snapshot.keys.remove(&path) {
+                Some((_, Some(fp))) => fp,
+                _ => continue,
+            };
+            let id = match self.memory.key_lookup(&fingerprint) {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            if self.memory.key_tpk(id)?.is_some() {
+                self.memory.key_forget_tpk(id)?;
+                changes.push(Change {
+                    kind: node::EventKind::KeyUpdated,
+                    fingerprint: fingerprint,
+                    binding: None,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the store files that changed since we last looked at
+    /// them.
+    fn load_stores(&self, snapshot: &mut Snapshot,
+                   changes: &mut Vec<Change>, warnings: &mut Vec<Warning>)
+                   -> Result<()> {
+        let mut seen = HashSet::new();
+        for realm in fs::read_dir(self.path.join("stores"))? {
+            let realm = realm?;
+            if ! realm.file_type()?.is_dir() {
+                continue;
+            }
+
+            for store in fs::read_dir(realm.path())? {
+                let path = store?.path();
+                if path.file_name().and_then(|n| n.to_str())
+                    .map(|n| n.starts_with(".")).unwrap_or(true)
+                {
+                    // Temporary file.
+                    continue;
+                }
+
+                let stamp = match Stamp::of(&path) {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                seen.insert(path.clone());
+                let previous = snapshot.stores.get(&path).cloned();
+                if previous.map(|(s, _)| s == stamp).unwrap_or(false) {
+                    continue;
+                }
+
+                let id = match load_store(&path, &self.memory, changes) {
+                    Ok(id) => Some(id),
+                    Err(e) => {
+                        warnings.push((path.to_string_lossy().into_owned(),
+                                       "Skipping malformed store file",
+                                       e.to_string()));
+                        previous.and_then(|(_, id)| id)
+                    },
+                };
+                snapshot.stores.insert(path, (stamp, id));
+            }
+        }
+
+        // Stores whose files have been removed.
+        let gone = snapshot.stores.keys()
+            .filter(|p| ! seen.contains(*p))
+            .cloned().collect::<Vec<_>>();
+        for path in gone {
+            if let Some((_, Some(id))) = snapshot.stores.remove(&path) {
+                let mut n = ID::null();
+                while let Ok((binding, _)) = self.memory.binding_next(id, n) {
+                    n = binding;
+                    changes.push(binding_change(
+                        &self.memory, node::EventKind::BindingDeleted,
+                        binding)?);
+                }
+                self.memory.store_delete(id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the stores, indexed by realm and name.
+    fn store_ids(&self) -> StoreIDs {
+        let mut stores = HashMap::new();
+        let mut n = ID::null();
+        while let Ok((id, info)) = self.memory.store_next("", n) {
+            stores.insert((info.realm, info.name), id);
+            n = id;
+        }
+        stores
+    }
+
+    /// Reads the usage statistics and the update status of keys and
+    /// bindings.
+    ///
+    /// Entries for keys and bindings that do not exist, and
+    /// malformed entries, are skipped.
+    fn load_stats(&self, stores: &StoreIDs) -> Result<()> {
+        if let Some(content) = read_file(&self.state_path("keys"))? {
+            for line in content.lines() {
+                let fields = line.split(' ').collect::<Vec<_>>();
+                let _ = self.load_key_stats(&fields);
+            }
+        }
+
+        if let Some(content) = read_file(&self.state_path("bindings"))? {
+            for line in content.lines() {
+                let fields = line.split(' ').collect::<Vec<_>>();
+                let _ = self.load_binding_stats(&fields, stores);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a line of `state/keys`.
+    fn load_key_stats(&self, fields: &[&str]) -> Result<()> {
+        match fields {
+            &["key", fp, created, updated,
+              ec, ef, el, vc, vf, vl, update_at, last_error] => {
+                let id = self.memory.key_lookup(&Fingerprint::from_hex(fp)?)?;
+                let stats = parse_stats(&[created, updated,
+                                          ec, ef, el, vc, vf, vl])?;
+                let status = UpdateStatus {
+                    update_at: Timestamp::from_unix(update_at.parse()?),
+                    last_error: unescape_opt(last_error)?,
+                };
+                self.memory.key_set_state(id, stats, status)
+            },
+            _ => Err(format_err!("Malformed key state: {:?}", fields)),
+        }
+    }
+
+    /// Applies a line of `state/bindings`.
+    fn load_binding_stats(&self, fields: &[&str], stores: &StoreIDs)
+                          -> Result<()> {
+        match fields {
+            &["binding", realm, name, label, created, updated,
+              ec, ef, el, vc, vf, vl] => {
+                let store = lookup_store(stores, &unescape(realm)?,
+                                         &unescape(name)?)?;
+                let id = self.memory.binding_lookup(store, &unescape(label)?)?;
+                let stats = parse_stats(&[created, updated,
+                                          ec, ef, el, vc, vf, vl])?;
+                self.memory.binding_set_stats(id, stats)
+            },
+            _ => Err(format_err!("Malformed binding state: {:?}", fields)),
+        }
+    }
+
+    /// Writes the usage statistics and the update status of all keys
+    /// and bindings.
+    ///
+    /// The caller must hold the lock.
+    fn write_stats(&self) -> Result<()> {
+        let mut keys = String::new();
+        let mut n = ID::null();
+        while let Ok((id, fp)) = self.memory.key_next(n) {
+            n = id;
+            let status = self.memory.key_update_status(id)?;
+            keys.push_str(
+                &format!("key {} {} {} {}\n",
+                         fp.to_hex(),
+                         format_stats(&self.memory.key_stats(id)?),
+                         status.update_at.unix(),
+                         escape_opt(status.last_error.as_ref()
+                                    .map(|e| &e[..]))));
+        }
+
+        let mut bindings = String::new();
+        let mut s = ID::null();
+        while let Ok((store, info)) = self.memory.store_next("", s) {
+            s = store;
+            let mut n = ID::null();
+            while let Ok((binding, b)) = self.memory.binding_next(store, n) {
+                n = binding;
+                bindings.push_str(
+                    &format!("binding {} {} {} {}\n",
+                             escape(&info.realm), escape(&info.name),
+                             escape(&b.label),
+                             format_stats(
+                                 &self.memory.binding_stats(binding)?)));
+            }
+        }
+
+        write_atomically(&self.state_path("keys"), keys.as_bytes())?;
+        write_atomically(&self.state_path("bindings"), bindings.as_bytes())?;
+        self.changed()
+    }
+
+    /// Reads the Autocrypt peer state.
+    ///
+    /// Malformed entries are skipped.
+    fn load_autocrypt(&self) -> Result<()> {
+        if let Some(content) = read_file(&self.state_path("autocrypt"))? {
+            for line in content.lines() {
+                let fields = line.split(' ').collect::<Vec<_>>();
+                let _ = self.load_autocrypt_peer(&fields);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a line of `state/autocrypt`.
+    fn load_autocrypt_peer(&self, fields: &[&str]) -> Result<()> {
+        match fields {
+            &["peer", addr, last_seen, autocrypt_timestamp, public_key,
+              prefer_encrypt, gossip_timestamp, gossip_key] => {
+                let key = |fp: &str| -> Result<Option<ID>> {
+                    if fp == "-" {
+                        Ok(None)
+                    } else {
+                        Ok(Some(self.memory.key_lookup_or_create(
+                            &Fingerprint::from_hex(fp)?)?))
+                    }
+                };
+
+                let peer = AutocryptPeer {
+                    last_seen: parse_opt(last_seen)?,
+                    autocrypt_timestamp: parse_opt(autocrypt_timestamp)?,
+                    public_key: key(public_key)?,
+                    prefer_encrypt: match prefer_encrypt {
+                        "mutual" => node::AutocryptPreferEncrypt::Mutual,
+                        "nopreference" =>
+                            node::AutocryptPreferEncrypt::NoPreference,
+                        _ => return Err(format_err!(
+                            "Bad prefer-encrypt value: {}", prefer_encrypt)),
+                    },
+                    gossip_timestamp: parse_opt(gossip_timestamp)?,
+                    gossip_key: key(gossip_key)?,
+                };
+                self.memory.autocrypt_set_peer(&unescape(addr)?, &peer)
+            },
+            _ => Err(format_err!("Malformed Autocrypt state: {:?}", fields)),
+        }
+    }
+
+    /// Writes the Autocrypt peer state.
+    ///
+    /// The caller must hold the lock.
+    fn write_autocrypt(&self) -> Result<()> {
+        let key = |id: Option<ID>| -> Result<String> {
+            match id {
+                Some(id) => Ok(self.memory.key_fingerprint(id)?.to_hex()),
+                None => Ok("-".into()),
+            }
+        };
+
+        let mut content = String::new();
+        for (addr, peer) in self.memory.autocrypt_peers() {
+            content.push_str(
+                &format!("peer {} {} {} {} {} {} {}\n",
+                         escape(&addr),
+                         format_opt(peer.last_seen),
+                         format_opt(peer.autocrypt_timestamp),
+                         key(peer.public_key)?,
+                         match peer.prefer_encrypt {
+                             node::AutocryptPreferEncrypt::Mutual =>
+                                 "mutual",
+                             node::AutocryptPreferEncrypt::NoPreference =>
+                                 "nopreference",
+                         },
+                         format_opt(peer.gossip_timestamp),
+                         key(peer.gossip_key)?));
+        }
+
+        write_atomically(&self.state_path("autocrypt"), content.as_bytes())?;
+        self.changed()
+    }
+
+    /// Reads the log entries that were appended since we last looked
+    /// at the log.
+    ///
+    /// If the log was replaced, it is read again.  Entries referring
+    /// to stores, bindings, or keys that do not exist, and malformed
+    /// entries, are skipped.  If the log file is malformed, it is
+    /// replaced on the next change to the log.
+    fn load_log(&self, snapshot: &mut Snapshot, stores: &StoreIDs,
+                warnings: &mut Vec<Warning>)
+                -> Result<()> {
+        let path = self.state_path("log");
+        let file = match fs::File::open(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                if snapshot.log.take().is_some() {
+                    // Somebody removed the log.
+                    self.memory.log_clear();
+                }
+                return Ok(());
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut file = io::BufReader::new(file);
+        let mut header = String::new();
+        file.read_line(&mut header)?;
+        let generation = match &header.trim().split(' ')
+            .collect::<Vec<_>>()[..]
+        {
+            &["generation", g] => g.parse::<u64>().ok(),
+            _ => None,
+        };
+        let generation = match generation {
+            Some(g) => g,
+            None => {
+                warnings.push((path.to_string_lossy().into_owned(),
+                               "Skipping malformed log file",
+                               format!("Bad header: {:?}", header)));
+                snapshot.log = None;
+                return Ok(());
+            },
+        };
+
+        let offset = match snapshot.log {
+            Some((g, offset)) if g == generation => offset,
+            _ => {
+                self.memory.log_clear();
+                header.len() as u64
+            },
+        };
+
+        file.seek(io::SeekFrom::Start(offset))?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+
+        // Only consider complete lines.
+        let complete = content.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        for line in content[..complete].lines() {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            if let Ok(entry) = self.parse_log_entry(&fields, stores) {
+                self.memory.log_append(entry)?;
+            }
+        }
+
+        snapshot.log = Some((generation, offset + complete as u64));
+        Ok(())
+    }
+
+    /// Parses a line of `state/log`.
+    fn parse_log_entry(&self, fields: &[&str], stores: &StoreIDs)
+                       -> Result<LogEntry> {
+        match fields {
+            &["entry", timestamp, level, store, binding, key,
+              slug, message, error] => {
+                let store = match store {
+                    "-" => None,
+                    s => match &s.split('/').collect::<Vec<_>>()[..] {
+                        &[realm, name] => Some(lookup_store(
+                            stores, &unescape(realm)?, &unescape(name)?)?),
+                        _ => return Err(format_err!("Bad store: {}", s)),
+                    },
+                };
+                let binding = match binding {
+                    "-" => None,
+                    b => match &b.split('/').collect::<Vec<_>>()[..] {
+                        &[realm, name, label] => {
+                            let store = lookup_store(
+                                stores, &unescape(realm)?, &unescape(name)?)?;
+                            Some(self.memory.binding_lookup(
+                                store, &unescape(label)?)?)
+                        },
+                        _ => return Err(format_err!("Bad binding: {}", b)),
+                    },
+                };
+                let key = match key {
+                    "-" => None,
+                    fp => Some(self.memory.key_lookup(
+                        &Fingerprint::from_hex(fp)?)?),
+                };
+
+                Ok(LogEntry {
+                    timestamp: Timestamp::from_unix(timestamp.parse()?),
+                    level: level.parse()?,
+                    store: store,
+                    binding: binding,
+                    key: key,
+                    slug: unescape(slug)?,
+                    message: unescape(message)?,
+                    error: unescape_opt(error)?,
+                })
+            },
+            _ => Err(format_err!("Malformed log entry: {:?}", fields)),
+        }
+    }
+
+    /// Formats a line of `state/log`.
+    fn format_log_entry(&self, entry: &LogEntry) -> Result<String> {
+        let store = match entry.store {
+            Some(id) => {
+                let info = self.memory.store_info(id)?;
+                format!("{}/{}", escape(&info.realm), escape(&info.name))
+            },
+            None => "-".into(),
+        };
+        let binding = match entry.binding {
+            Some(id) => {
+                let b = self.memory.binding_info(id)?;
+                let info = self.memory.store_info(b.store)?;
+                format!("{}/{}/{}", escape(&info.realm), escape(&info.name),
+                        escape(&b.label))
+            },
+            None => "-".into(),
+        };
+        let key = match entry.key {
+            Some(id) => self.memory.key_fingerprint(id)?.to_hex(),
+            None => "-".into(),
+        };
+
+        Ok(format!("entry {} {} {} {} {} {} {} {}\n",
+                   entry.timestamp.unix(), entry.level, store, binding, key,
+                   escape(&entry.slug), escape(&entry.message),
+                   escape_opt(entry.error.as_ref().map(|e| &e[..]))))
+    }
+
+    /// Appends the given entry to the log file.
+    ///
+    /// The entry must already be in memory.  The caller must hold
+    /// the lock.
+    fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        let line = self.format_log_entry(entry)?;
+        let path = self.state_path("log");
+        let log = self.snapshot.lock().unwrap().log;
+        let (generation, offset) = match log {
+            Some(log) => log,
+            // There is no log yet.
+            None => return self.write_log(),
+        };
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path)?;
+        file.write_all(line.as_bytes())?;
+        self.snapshot.lock().unwrap().log =
+            Some((generation, offset + line.len() as u64));
+        self.changed()
+    }
+
+    /// Replaces the log file with the log entries in memory.
+    ///
+    /// The new log gets a new generation, so that others read it
+    /// again.  The caller must hold the lock.
+    fn write_log(&self) -> Result<()> {
+        let generation = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().wrapping_mul(1_000_000_000)
+                 .wrapping_add(d.subsec_nanos() as u64))
+            .unwrap_or(0);
+        let mut content = format!("generation {}\n", generation);
+        for entry in self.memory.log_entries() {
+            content.push_str(&self.format_log_entry(&entry)?);
+        }
+
+        write_atomically(&self.state_path("log"), content.as_bytes())?;
+        self.snapshot.lock().unwrap().log =
+            Some((generation, content.len() as u64));
+        self.changed()
+    }
+
+    /// Returns the path of the given store's file.
+    fn store_path(&self, store: &StoreInfo) -> PathBuf {
+        self.path.join("stores").join(escape(&store.realm))
+            .join(escape(&store.name))
+    }
+
+    /// Writes the given store to the directory.
+    fn write_store(&self, id: ID) -> Result<()> {
+        let store = self.memory.store_info(id)?;
+        let mut content = String::new();
+        content.push_str(&format!("realm {}\n", escape(&store.realm)));
+        content.push_str(&format!("name {}\n", escape(&store.name)));
+        content.push_str(&format!("network-policy {}\n",
+                                  store.network_policy));
+
+        let mut n = ID::null();
+        while let Ok((binding, info)) = self.memory.binding_next(id, n) {
+            content.push_str(
+                &format!("binding {} {}\n",
+                         self.memory.key_fingerprint(info.key)?.to_hex(),
+                         escape(&info.label)));
+            n = binding;
+        }
+
+        let path = self.store_path(&store);
+        fs::create_dir_all(path.parent().expect("store files have a parent"))?;
+        write_atomically(&path, content.as_bytes())?;
+        self.snapshot.lock().unwrap().stores
+            .insert(path.clone(), (Stamp::of(&path)?, Some(id)));
+        self.changed()
+    }
+
+    /// Removes the given store from the directory.
+    fn remove_store(&self, store: &StoreInfo) -> Result<()> {
+        let path = self.store_path(store);
+        match fs::remove_file(&path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            r => r?,
+        }
+        self.snapshot.lock().unwrap().stores.remove(&path);
+
+        // Remove the realm's directory once it is empty.
+        let _ = fs::remove_dir(path.parent().expect("store files have a parent"));
+        self.changed()
+    }
+
+    /// Writes the given key to the directory.
+    fn write_key(&self, id: ID, tpk: &TPK) -> Result<()> {
+        let mut content = vec![];
+        {
+            let mut writer = armor::Writer::new(&mut content,
+                                                armor::Kind::PublicKey,
+                                                &[][..])?;
+            tpk.serialize(&mut writer)?;
+            writer.finalize()?;
+        }
+
+        let fp = self.memory.key_fingerprint(id)?;
+        let path = self.path.join("keys").join(format!("{}.asc", fp.to_hex()));
+        write_atomically(&path, &content)?;
+        self.snapshot.lock().unwrap().keys
+            .insert(path.clone(), (Stamp::of(&path)?, Some(fp)));
+        self.changed()
+    }
+}
+
+/// Describes a change to the given binding.
+fn binding_change(memory: &Memory, kind: node::EventKind, id: ID)
+                  -> Result<Change> {
+    let binding = memory.binding_info(id)?;
+    Ok(Change {
+        kind: kind,
+        fingerprint: memory.key_fingerprint(binding.key)?,
+        binding: Some((binding.store, memory.store_info(binding.store)?,
+                       binding.label)),
+    })
+}
+
+/// Returns the store with the given realm and name.
+fn lookup_store(stores: &StoreIDs, realm: &str, name: &str) -> Result<ID> {
+    stores.get(&(realm.to_string(), name.to_string())).cloned()
+        .ok_or_else(|| ::Error::NotFound.into())
+}
+
+/// Populates `memory` with the store in `path`.
+///
+/// Bindings that are no longer listed in the file are removed.  The
+/// changes to the bindings are recorded in `changes`.  Returns the
+/// store.
+fn load_store(path: &Path, memory: &Memory, changes: &mut Vec<Change>)
+              -> Result<ID> {
+    let malformed = || -> failure::Error {
+        format_err!("Malformed store file {:?}", path)
+    };
+
+    let mut content = String::new();
+    fs::File::open(path)?.read_to_string(&mut content)?;
+
+    let mut realm = None;
+    let mut name = None;
+    let mut network_policy = None;
+    let mut bindings = HashMap::new();
+    for line in content.lines() {
+        let fields = line.split(' ').collect::<Vec<_>>();
+        match &fields[..] {
+            &["realm", r] => realm = Some(unescape(r)?),
+            &["name", n] => name = Some(unescape(n)?),
+            &["network-policy", p] => network_policy = Some(
+                match p {
+                    "Offline" => core::NetworkPolicy::Offline,
+                    "Anonymized" => core::NetworkPolicy::Anonymized,
+                    "Encrypted" => core::NetworkPolicy::Encrypted,
+                    "Insecure" => core::NetworkPolicy::Insecure,
+                    _ => return Err(malformed()),
+                }),
+            &["binding", fp, label] => {
+                bindings.insert(unescape(label)?,
+                                Fingerprint::from_hex(fp)
+                                .map_err(|_| malformed())?);
+            },
+            &[""] => (),
+            _ => return Err(malformed()),
+        }
+    }
+
+    let (realm, name, network_policy) = match (realm, name, network_policy) {
+        (Some(r), Some(n), Some(p)) => (r, n, p),
+        _ => return Err(malformed()),
+    };
+
+    let (store, _) = memory.store_open(&realm, &name, network_policy)?;
+
+    // Update or remove the bindings we know about.
+    let mut n = ID::null();
+    while let Ok((binding, info)) = memory.binding_next(store, n) {
+        n = binding;
+        match bindings.remove(&info.label) {
+            Some(fp) => {
+                let key = memory.key_lookup_or_create(&fp)?;
+                if key != info.key {
+                    memory.binding_set_key(binding, key)?;
+                    changes.push(binding_change(
+                        memory, node::EventKind::BindingRotated, binding)?);
+                }
+            },
+            None => {
+                changes.push(binding_change(
+                    memory, node::EventKind::BindingDeleted, binding)?);
+                memory.binding_delete(binding)?;
+            },
+        }
+    }
+
+    for (label, fp) in bindings {
+        let key = memory.key_lookup_or_create(&fp)?;
+        let (binding, created) =
+            memory.binding_lookup_or_create(store, &label, key)?;
+        if created {
+            changes.push(binding_change(
+                memory, node::EventKind::BindingAdded, binding)?);
+        }
+    }
+    Ok(store)
+}
+
+/// Replaces the file at `path` with `content`.
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = path.with_file_name(
+        format!(".{}.tmp", path.file_name().expect("is a file")
+                .to_string_lossy()));
+    {
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(content)?;
+        f.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Escapes `s` for use in file names and store files.
+///
+/// The result is never empty, contains neither path separators nor
+/// whitespace, and does not start with a dot.
+fn escape(s: &str) -> String {
+    if s.is_empty() {
+        return "_".into();
+    }
+
+    let mut r = String::new();
+    for (i, b) in s.bytes().enumerate() {
+        let c = b as char;
+        if c.is_ascii_alphanumeric() || "-+@".contains(c)
+            || (c == '.' && i > 0)
+        {
+            r.push(c);
+        } else {
+            r.push_str(&format!("%{:02X}", b));
+        }
+    }
+    r
+}
+
+/// Reverses `escape`.
+fn unescape(s: &str) -> Result<String> {
+    if s == "_" {
+        return Ok(String::new());
+    }
+
+    let mut r = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = bytes.by_ref().take(2).collect::<Vec<u8>>();
+            r.push(u8::from_str_radix(str::from_utf8(&hex)?, 16)?);
+        } else {
+            r.push(b);
+        }
+    }
+    Ok(String::from_utf8(r)?)
+}
+
+
+/// Escapes `s` like `escape`, using `-` for `None`.
+fn escape_opt(s: Option<&str>) -> String {
+    match s {
+        Some(s) => {
+            let e = escape(s);
+            if e == "-" { "%2D".into() } else { e }
+        },
+        None => "-".into(),
+    }
+}
+
+/// Reverses `escape_opt`.
+fn unescape_opt(s: &str) -> Result<Option<String>> {
+    if s == "-" {
+        Ok(None)
+    } else {
+        Ok(Some(unescape(s)?))
+    }
+}
+
+/// Formats `v`, using `-` for `None`.
+fn format_opt<T: ToString>(v: Option<T>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_else(|| "-".into())
+}
+
+/// Reverses `format_opt`.
+fn parse_opt<T>(s: &str) -> Result<Option<T>>
+    where T: str::FromStr, T::Err: failure::Fail
+{
+    if s == "-" {
+        Ok(None)
+    } else {
+        Ok(Some(s.parse()?))
+    }
+}
+
+/// Formats `stats` as eight fields.
+fn format_stats(stats: &Stats) -> String {
+    let time = |t: Option<Timestamp>| format_opt(t.map(|t| t.unix()));
+    let counter = |c: &Counter| {
+        format!("{} {} {}", c.count, time(c.first), time(c.last))
+    };
+    format!("{} {} {} {}",
+            stats.created.unix(), time(stats.updated),
+            counter(&stats.encryption), counter(&stats.verification))
+}
+
+/// Reverses `format_stats`.
+fn parse_stats(fields: &[&str; 8]) -> Result<Stats> {
+    let time = |s: &str| -> Result<Option<Timestamp>> {
+        Ok(parse_opt(s)?.map(Timestamp::from_unix))
+    };
+    let counter = |c: &[&str]| -> Result<Counter> {
+        Ok(Counter {
+            count: c[0].parse()?,
+            first: time(c[1])?,
+            last: time(c[2])?,
+        })
+    };
+    Ok(Stats {
+        created: Timestamp::from_unix(fields[0].parse()?),
+        updated: time(fields[1])?,
+        encryption: counter(&fields[2..5])?,
+        verification: counter(&fields[5..8])?,
+    })
+}
+
+/// Returns the content of the file at `path`, if it exists.
+fn read_file(path: &Path) -> Result<Option<String>> {
+    let mut content = String::new();
+    match fs::File::open(path) {
+        Ok(mut f) => {
+            f.read_to_string(&mut content)?;
+            Ok(Some(content))
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Storage for Directory {
+    fn store_open(&self, realm: &str, name: &str,
+                  network_policy: core::NetworkPolicy)
+                  -> Result<(ID, core::NetworkPolicy)> {
+        let _lock = self.lock()?;
+        let r = self.memory.store_open(realm, name, network_policy)?;
+        let store = self.memory.store_info(r.0)?;
+        if ! self.store_path(&store).exists() {
+            self.write_store(r.0)?;
+        }
+        Ok(r)
+    }
+
+    fn store_info(&self, id: ID) -> Result<StoreInfo> {
+        self.memory.store_info(id)
+    }
+
+    fn store_delete(&self, id: ID) -> Result<()> {
+        let _lock = self.lock()?;
+        if let Ok(store) = self.memory.store_info(id) {
+            self.memory.store_delete(id)?;
+            self.remove_store(&store)?;
+        }
+        Ok(())
+    }
+
+    fn store_next(&self, realm_prefix: &str, after: ID)
+                  -> Result<(ID, StoreInfo)> {
+        if after == ID::null() {
+            self.refresh()?;
+        }
+        self.memory.store_next(realm_prefix, after)
+    }
+
+    fn binding_lookup(&self, store: ID, label: &str) -> Result<ID> {
+        self.refresh()?;
+        self.memory.binding_lookup(store, label)
+    }
+
+    fn binding_lookup_or_create(&self, store: ID, label: &str, key: ID)
+                                -> Result<(ID, bool)> {
+        let _lock = self.lock()?;
+        let r = self.memory.binding_lookup_or_create(store, label, key)?;
+        if r.1 {
+            self.write_store(store)?;
+            // Record when the binding was created.
+            self.write_stats()?;
+        }
+        Ok(r)
+    }
+
+    fn binding_lookup_by_subkeyid(&self, store: ID, keyid: &KeyID)
+                                  -> Result<ID> {
+        self.refresh()?;
+        self.memory.binding_lookup_by_subkeyid(store, keyid)
+    }
+
+    fn binding_info(&self, id: ID) -> Result<BindingInfo> {
+        self.memory.binding_info(id)
+    }
+
+    fn binding_set_key(&self, id: ID, key: ID) -> Result<()> {
+        let _lock = self.lock()?;
+        self.memory.binding_set_key(id, key)?;
+        self.write_store(self.memory.binding_info(id)?.store)
+    }
+
+    fn binding_delete(&self, id: ID) -> Result<()> {
+        let _lock = self.lock()?;
+        if let Ok(binding) = self.memory.binding_info(id) {
+            self.memory.binding_delete(id)?;
+            self.write_store(binding.store)?;
+        }
+        Ok(())
+    }
+
+    fn binding_stats(&self, id: ID) -> Result<Stats> {
+        self.refresh()?;
+        self.memory.binding_stats(id)
+    }
+
+    fn binding_register(&self, id: ID, usage: Usage, at: Timestamp)
+                        -> Result<()> {
+        let _lock = self.lock()?;
+        self.memory.binding_register(id, usage, at)?;
+        self.write_stats()
+    }
+
+    fn binding_next(&self, store: ID, after: ID)
+                    -> Result<(ID, BindingInfo)> {
+        if after == ID::null() {
+            self.refresh()?;
+        }
+        self.memory.binding_next(store, after)
+    }
+
+    fn key_lookup(&self, fp: &Fingerprint) -> Result<ID> {
+        self.refresh()?;
+        self.memory.key_lookup(fp)
+    }
+
+    fn key_lookup_by_keyid(&self, keyid: &KeyID) -> Result<ID> {
+        self.refresh()?;
+        self.memory.key_lookup_by_keyid(keyid)
+    }
+
+    fn key_lookup_by_subkeyid(&self, keyid: &KeyID) -> Result<ID> {
+        self.refresh()?;
+        self.memory.key_lookup_by_subkeyid(keyid)
+    }
+
+    fn key_lookup_or_create(&self, fp: &Fingerprint) -> Result<ID> {
+        self.memory.key_lookup_or_create(fp)
+    }
+
+    fn key_fingerprint(&self, id: ID) -> Result<Fingerprint> {
+        self.memory.key_fingerprint(id)
+    }
+
+    fn key_tpk(&self, id: ID) -> Result<Option<Vec<u8>>> {
+        self.refresh()?;
+        self.memory.key_tpk(id)
+    }
+
+    fn key_set_tpk(&self, id: ID, tpk: &TPK) -> Result<Vec<u8>> {
+        let _lock = self.lock()?;
+
+        // Somebody else may have updated the key since the caller
+        // read it.  Keep their changes.
+        let tpk = match self.memory.key_tpk(id)? {
+            Some(current) => TPK::from_bytes(&current)?.merge(tpk.clone())?,
+            None => tpk.clone(),
+        };
+
+        let blob = self.memory.key_set_tpk(id, &tpk)?;
+        self.write_key(id, &tpk)?;
+        Ok(blob)
+    }
+
+    fn key_stats(&self, id: ID) -> Result<Stats> {
+        self.refresh()?;
+        self.memory.key_stats(id)
+    }
+
+    fn key_next(&self, after: ID) -> Result<(ID, Fingerprint)> {
+        if after == ID::null() {
+            self.refresh()?;
+        }
+        self.memory.key_next(after)
+    }
+
+    fn key_next_update_at(&self, network_policy: core::NetworkPolicy)
+                          -> Option<Timestamp> {
+        // If the directory cannot be read, go with what we have.
+        let _ = self.refresh();
+        self.memory.key_next_update_at(network_policy)
+    }
+
+    fn key_need_update(&self, network_policy: core::NetworkPolicy)
+                       -> Result<i32> {
+        self.refresh()?;
+        self.memory.key_need_update(network_policy)
+    }
+
+    fn key_to_update(&self, network_policy: core::NetworkPolicy,
+                     now: Timestamp)
+                     -> Result<(ID, Fingerprint)> {
+        self.refresh()?;
+        self.memory.key_to_update(network_policy, now)
+    }
+
+    fn key_schedule_update(&self, id: ID, update_at: Timestamp)
+                           -> Result<()> {
+        let _lock = self.lock()?;
+        self.memory.key_schedule_update(id, update_at)?;
+        self.write_stats()
+    }
+
+    fn key_update_succeeded(&self, id: ID, updated: Timestamp,
+                            update_at: Timestamp)
+                            -> Result<()> {
+        let _lock = self.lock()?;
+        self.memory.key_update_succeeded(id, updated, update_at)?;
+        self.write_stats()
+    }
+
+    fn key_update_failed(&self, id: ID, error: &str, update_at: Timestamp)
+                         -> Result<()> {
+        let _lock = self.lock()?;
+        self.memory.key_update_failed(id, error, update_at)?;
+        self.write_stats()
+    }
+
+    fn key_update_status(&self, id: ID) -> Result<UpdateStatus> {
+        self.refresh()?;
+        self.memory.key_update_status(id)
+    }
+
+    fn log_append(&self, entry: LogEntry) -> Result<ID> {
+        let _lock = self.lock()?;
+        let id = self.memory.log_append(entry.clone())?;
+        self.append_log(&entry)?;
+        Ok(id)
+    }
+
+    fn log_next(&self, selector: &Selector, min_level: LogLevel, before: ID)
+                -> Result<(ID, LogEntry)> {
+        if before == ID::max() {
+            self.refresh()?;
+        }
+        self.memory.log_next(selector, min_level, before)
+    }
+
+    fn log_trim(&self, older_than: Timestamp, max_entries_per_key: usize)
+                -> Result<()> {
+        let _lock = self.lock()?;
+        self.memory.log_trim(older_than, max_entries_per_key)?;
+        self.write_log()
+    }
+
+    fn autocrypt_peer(&self, addr: &str) -> Result<Option<AutocryptPeer>> {
+        self.refresh()?;
+        self.memory.autocrypt_peer(addr)
+    }
+
+    fn autocrypt_set_peer(&self, addr: &str, peer: &AutocryptPeer)
+                          -> Result<()> {
+        let _lock = self.lock()?;
+        self.memory.autocrypt_set_peer(addr, peer)?;
+        self.write_autocrypt()
+    }
+
+    fn take_changes(&self) -> Vec<Change> {
+        ::std::mem::replace(&mut *self.changes.borrow_mut(), Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Makes sure changes made behind our back are picked up.
+    fn touch(path: &Path) {
+        // It may already be gone.
+        let _ = fs::remove_file(path.join(".serial"));
+    }
+
+    #[test]
+    fn persistence() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let path = ctx.home().join("public-key-store");
+        let tpk = TPK::from_bytes(include_bytes!(
+            "../../../../openpgp/tests/data/keys/testy.pgp")).unwrap();
+        let subkey = tpk.keys_all().last().unwrap().2.keyid();
+
+        {
+            let d = Directory::open(&path).unwrap();
+            let (store, _) = d.store_open("realm", "a store",
+                                          core::NetworkPolicy::Encrypted)
+                .unwrap();
+            let key = d.key_lookup_or_create(&tpk.fingerprint()).unwrap();
+            d.key_set_tpk(key, &tpk).unwrap();
+            d.binding_lookup_or_create(store, "Mr. McTestface", key).unwrap();
+
+            d.store_open("realm", "", core::NetworkPolicy::Offline).unwrap();
+            let (gone, _) = d.store_open("realm", "gone",
+                                         core::NetworkPolicy::Offline)
+                .unwrap();
+            d.store_delete(gone).unwrap();
+        }
+
+        // Reads the directory again.
+        let d = Directory::open(&path).unwrap();
+        let (store, policy) = d.store_open("realm", "a store",
+                                           core::NetworkPolicy::Offline)
+            .unwrap();
+        assert_eq!(policy, core::NetworkPolicy::Encrypted);
+        let binding = d.binding_lookup(store, "Mr. McTestface").unwrap();
+        let key = d.binding_info(binding).unwrap().key;
+        assert_eq!(d.key_fingerprint(key).unwrap(), tpk.fingerprint());
+        let blob = d.key_tpk(key).unwrap().unwrap();
+        assert_eq!(TPK::from_bytes(&blob).unwrap().fingerprint(),
+                   tpk.fingerprint());
+        assert_eq!(d.binding_lookup_by_subkeyid(store, &subkey).unwrap(),
+                   binding);
+
+        let mut names = vec![];
+        let mut n = ID::null();
+        while let Ok((id, store)) = d.store_next("realm", n) {
+            names.push(store.name);
+            n = id;
+        }
+        names.sort();
+        assert_eq!(names, vec!["", "a store"]);
+    }
+
+    #[test]
+    fn external_changes() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let path = ctx.home().join("public-key-store");
+        let testy = TPK::from_bytes(include_bytes!(
+            "../../../../openpgp/tests/data/keys/testy.pgp")).unwrap();
+        let neal_bytes = include_bytes!(
+            "../../../../openpgp/tests/data/keys/neal.pgp");
+        let neal = TPK::from_bytes(neal_bytes).unwrap();
+
+        let d = Directory::open(&path).unwrap();
+        let (store, _) = d.store_open("realm", "store",
+                                      core::NetworkPolicy::Encrypted)
+            .unwrap();
+        let key = d.key_lookup_or_create(&testy.fingerprint()).unwrap();
+        d.key_set_tpk(key, &testy).unwrap();
+        d.binding_lookup_or_create(store, "testy", key).unwrap();
+
+        // Somebody else adds a key, and rebinds the label.
+        fs::write(path.join("keys").join(
+            format!("{}.asc", neal.fingerprint().to_hex())), &neal_bytes[..])
+            .unwrap();
+        let store_file = path.join("stores").join("realm").join("store");
+        let header = "realm realm\nname store\nnetwork-policy Encrypted\n";
+        fs::write(&store_file,
+                  format!("{}binding {} neal\n",
+                          header, neal.fingerprint().to_hex()))
+            .unwrap();
+
+        // Nothing is read unless the serial changes.
+        assert!(d.binding_lookup(store, "neal").is_err());
+        touch(&path);
+
+        let binding = d.binding_lookup(store, "neal").unwrap();
+        let key = d.binding_info(binding).unwrap().key;
+        assert_eq!(d.key_fingerprint(key).unwrap(), neal.fingerprint());
+        assert!(d.key_tpk(key).unwrap().is_some());
+        assert!(d.binding_lookup(store, "testy").is_err());
+
+        // Changes do not overwrite changes made by others.
+        fs::write(&store_file,
+                  format!("{}binding {} neal\nbinding {} other\n",
+                          header, neal.fingerprint().to_hex(),
+                          neal.fingerprint().to_hex()))
+            .unwrap();
+        touch(&path);
+        let key = d.key_lookup(&testy.fingerprint()).unwrap();
+        d.binding_lookup_or_create(store, "testy", key).unwrap();
+        let mut content = String::new();
+        fs::File::open(&store_file).unwrap()
+            .read_to_string(&mut content).unwrap();
+        let mut labels = content.lines()
+            .filter(|l| l.starts_with("binding "))
+            .map(|l| l.rsplit(' ').next().unwrap())
+            .collect::<Vec<_>>();
+        labels.sort();
+        assert_eq!(labels, vec!["neal", "other", "testy"]);
+
+        // The store is gone once its file is removed.
+        fs::remove_file(&store_file).unwrap();
+        touch(&path);
+        let mut n = ID::null();
+        while let Ok((id, info)) = d.store_next("realm", n) {
+            assert!(info.name != "store");
+            n = id;
+        }
+
+        // So is the TPK once its file is removed.
+        d.take_changes();
+        fs::remove_file(path.join("keys").join(
+            format!("{}.asc", neal.fingerprint().to_hex()))).unwrap();
+        touch(&path);
+        let key = d.key_lookup(&neal.fingerprint()).unwrap();
+        assert!(d.key_tpk(key).unwrap().is_none());
+        let changes = d.take_changes();
+        assert_eq!(changes.len(), 1);
+        match changes[0].kind {
+            node::EventKind::KeyUpdated => (),
+            _ => panic!("Expected a key update"),
+        }
+        assert_eq!(changes[0].fingerprint, neal.fingerprint());
+    }
+
+    #[test]
+    fn state_persistence() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let path = ctx.home().join("public-key-store");
+        let tpk = TPK::from_bytes(include_bytes!(
+            "../../../../openpgp/tests/data/keys/testy.pgp")).unwrap();
+        let now = Timestamp::from_unix(Timestamp::now().unix());
+
+        {
+            let d = Directory::open(&path).unwrap();
+            let (store, _) = d.store_open("realm", "store",
+                                          core::NetworkPolicy::Encrypted)
+                .unwrap();
+            let key = d.key_lookup_or_create(&tpk.fingerprint()).unwrap();
+            d.key_set_tpk(key, &tpk).unwrap();
+            let (binding, _) =
+                d.binding_lookup_or_create(store, "testy", key).unwrap();
+            d.binding_register(binding, Usage::Encryption, now).unwrap();
+            d.key_update_failed(key, "no network", now).unwrap();
+            log::error(&d, Refers::to().binding(binding),
+                       "testy", "Something happened", "-").unwrap();
+            d.autocrypt_set_peer("testy@example.org", &AutocryptPeer {
+                last_seen: Some(1),
+                public_key: Some(key),
+                prefer_encrypt: node::AutocryptPreferEncrypt::Mutual,
+                .. Default::default()
+            }).unwrap();
+        }
+
+        // Reads the directory again.
+        let d = Directory::open(&path).unwrap();
+        let (store, _) = d.store_open("realm", "store",
+                                      core::NetworkPolicy::Offline)
+            .unwrap();
+        let binding = d.binding_lookup(store, "testy").unwrap();
+        let key = d.key_lookup(&tpk.fingerprint()).unwrap();
+
+        let stats = d.binding_stats(binding).unwrap();
+        assert_eq!(stats.encryption.count, 1);
+        assert_eq!(stats.encryption.last, Some(now));
+        assert_eq!(d.key_stats(key).unwrap().encryption.count, 1);
+
+        let status = d.key_update_status(key).unwrap();
+        assert_eq!(status.update_at, now);
+        assert_eq!(status.last_error, Some("no network".into()));
+
+        let (_, entry) = d.log_next(&Selector::Binding(binding),
+                                    LogLevel::Debug, ID::max()).unwrap();
+        assert_eq!(entry.level, LogLevel::Error);
+        assert_eq!(entry.binding, Some(binding));
+        assert_eq!(entry.message, "Something happened");
+        assert_eq!(entry.error, Some("-".into()));
+
+        let peer = d.autocrypt_peer("testy@example.org").unwrap().unwrap();
+        assert_eq!(peer.last_seen, Some(1));
+        assert_eq!(peer.public_key, Some(key));
+        match peer.prefer_encrypt {
+            node::AutocryptPreferEncrypt::Mutual => (),
+            _ => panic!("Expected mutual"),
+        }
+
+        // Trimming the log replaces it.
+        d.log_trim(Timestamp::now() + ::time::Duration::weeks(1), 10)
+            .unwrap();
+        drop(d);
+        let d = Directory::open(&path).unwrap();
+        assert!(d.log_next(&Selector::All, LogLevel::Debug, ID::max())
+                .is_err());
+    }
+
+    #[test]
+    fn malformed_files() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let path = ctx.home().join("public-key-store");
+        let testy_bytes = include_bytes!(
+            "../../../../openpgp/tests/data/keys/testy.asc");
+        let testy = TPK::from_bytes(testy_bytes).unwrap();
+
+        fs::create_dir_all(path.join("keys")).unwrap();
+        fs::write(path.join("keys").join("garbage.asc"), b"garbage").unwrap();
+        fs::write(path.join("keys").join(
+            format!("{}.asc", testy.fingerprint().to_hex())), &testy_bytes[..])
+            .unwrap();
+        fs::create_dir_all(path.join("stores").join("realm")).unwrap();
+        fs::write(path.join("stores").join("realm").join("store"),
+                  b"garbage").unwrap();
+
+        // Malformed files are skipped, and logged.
+        let d = Directory::open(&path).unwrap();
+        let key = d.key_lookup(&testy.fingerprint()).unwrap();
+        assert!(d.key_tpk(key).unwrap().is_some());
+        assert!(d.store_next("", ID::null()).is_err());
+
+        let mut warnings = 0;
+        let mut n = ID::max();
+        while let Ok((id, e)) = d.log_next(&Selector::All,
+                                           LogLevel::Warning, n) {
+            assert!(e.slug.ends_with("garbage.asc")
+                    || e.slug.ends_with("store"));
+            warnings += 1;
+            n = id;
+        }
+        assert_eq!(warnings, 2);
+    }
+
+    #[test]
+    fn escape_roundtrip() {
+        for s in &["", "_", ".", "..", ".hidden", "org.sequoia-pgp.contacts",
+                   "Mister B.", "a/b", "100%", "line\nbreak", "Ünïcödé"] {
+            let e = escape(s);
+            assert!(! e.is_empty());
+            assert!(! e.starts_with("."));
+            assert!(! e.contains('/'));
+            assert!(! e.contains(char::is_whitespace));
+            assert_eq!(&unescape(&e).unwrap(), s);
+        }
+    }
+}
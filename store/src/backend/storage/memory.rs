@@ -0,0 +1,632 @@
+//! Stores everything in memory.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound::{Excluded, Unbounded};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use openpgp::{TPK, KeyID, Fingerprint};
+use openpgp::serialize::Serialize;
+use sequoia_core as core;
+
+use LogLevel;
+use backend::Result;
+use backend::support::{ID, Timestamp};
+use backend::log::Selector;
+use super::{
+    Storage, StoreInfo, BindingInfo, Stats, UpdateStatus, Usage, LogEntry,
+    AutocryptPeer,
+};
+
+lazy_static! {
+    /// The in-memory stores of this process.
+    ///
+    /// Every connection to an internal server spawns a new server,
+    /// hence we need to share the state between them.
+    static ref STATES: Mutex<HashMap<PathBuf, Weak<Shared>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Stores everything in memory.
+///
+/// All servers in this process that use the same home directory
+/// share the state.  It is freed once the last of them exits.
+pub struct Memory {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    updates: Mutex<()>,
+}
+
+struct Binding {
+    info: BindingInfo,
+    stats: Stats,
+}
+
+struct Key {
+    fingerprint: Fingerprint,
+    tpk: Option<Vec<u8>>,
+    stats: Stats,
+    update_at: Timestamp,
+    last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    last_id: i64,
+    stores: BTreeMap<ID, StoreInfo>,
+    bindings: BTreeMap<ID, Binding>,
+    keys: BTreeMap<ID, Key>,
+    key_by_fingerprint: HashMap<String, ID>,
+    key_by_keyid: HashMap<u64, BTreeSet<ID>>,
+    log: BTreeMap<ID, LogEntry>,
+    autocrypt_peers: HashMap<String, AutocryptPeer>,
+}
+
+impl Memory {
+    /// Opens the in-memory store for the given home directory.
+    pub fn open(home: &Path) -> Self {
+        Self::shared(home, |_| Ok(()))
+            .expect("initializing an empty store cannot fail")
+    }
+
+    /// Returns the state shared by all users of `key`.
+    ///
+    /// If the state is created, `init` is called to populate it
+    /// before anyone else can access it.
+    pub fn shared<F>(key: &Path, init: F) -> Result<Self>
+        where F: FnOnce(&Memory) -> Result<()>
+    {
+        let mut states = STATES.lock().unwrap();
+        if let Some(shared) = states.get(key).and_then(|s| s.upgrade()) {
+            return Ok(Memory { shared: shared });
+        }
+
+        let memory = Memory {
+            shared: Arc::new(Shared {
+                state: Mutex::new(Default::default()),
+                updates: Mutex::new(()),
+            }),
+        };
+        init(&memory)?;
+        states.insert(key.to_path_buf(), Arc::downgrade(&memory.shared));
+        Ok(memory)
+    }
+
+    /// Serializes updates spanning more than one operation.
+    ///
+    /// Every operation is atomic.  Users that need to make several
+    /// operations appear atomic to other users of the same state
+    /// need to hold this lock while doing so.
+    pub fn lock_updates(&self) -> MutexGuard<()> {
+        self.shared.updates.lock().unwrap()
+    }
+
+    fn state(&self) -> MutexGuard<State> {
+        self.shared.state.lock().unwrap()
+    }
+
+    /// Replaces the statistics of the given binding.
+    pub fn binding_set_stats(&self, id: ID, stats: Stats) -> Result<()> {
+        self.state().binding(id)?.stats = stats;
+        Ok(())
+    }
+
+    /// Replaces the statistics and the update status of the given
+    /// key.
+    pub fn key_set_state(&self, id: ID, stats: Stats, status: UpdateStatus)
+                         -> Result<()> {
+        let mut state = self.state();
+        let key = state.key(id)?;
+        key.stats = stats;
+        key.update_at = status.update_at;
+        key.last_error = status.last_error;
+        Ok(())
+    }
+
+    /// Forgets the TPK of the given key.
+    ///
+    /// The key itself is kept, as bindings may refer to it.
+    pub fn key_forget_tpk(&self, id: ID) -> Result<()> {
+        let mut state = self.state();
+        state.key(id)?.tpk = None;
+        for keys in state.key_by_keyid.values_mut() {
+            keys.remove(&id);
+        }
+        state.key_by_keyid.retain(|_, keys| ! keys.is_empty());
+        Ok(())
+    }
+
+    /// Returns all log entries, oldest first.
+    pub fn log_entries(&self) -> Vec<LogEntry> {
+        self.state().log.values().cloned().collect()
+    }
+
+    /// Removes all log entries.
+    pub fn log_clear(&self) {
+        self.state().log.clear();
+    }
+
+    /// Returns all Autocrypt peers.
+    pub fn autocrypt_peers(&self) -> Vec<(String, AutocryptPeer)> {
+        self.state().autocrypt_peers.iter()
+            .map(|(addr, peer)| (addr.clone(), *peer))
+            .collect()
+    }
+}
+
+fn not_found<T>() -> Result<T> {
+    Err(::Error::NotFound.into())
+}
+
+impl State {
+    fn next_id(&mut self) -> ID {
+        self.last_id += 1;
+        self.last_id.into()
+    }
+
+    fn store(&self, id: ID) -> Result<&StoreInfo> {
+        self.stores.get(&id).map(Ok).unwrap_or_else(not_found)
+    }
+
+    fn binding(&mut self, id: ID) -> Result<&mut Binding> {
+        self.bindings.get_mut(&id).map(Ok).unwrap_or_else(not_found)
+    }
+
+    fn key(&mut self, id: ID) -> Result<&mut Key> {
+        self.keys.get_mut(&id).map(Ok).unwrap_or_else(not_found)
+    }
+
+    /// Returns the network policy of the store the binding is in.
+    fn policy(&self, binding: &Binding) -> u8 {
+        u8::from(&self.stores[&binding.info.store].network_policy)
+    }
+
+    /// Returns whether the log entry is selected by `selector`.
+    fn selects(&self, selector: &Selector, entry: &LogEntry) -> bool {
+        match selector {
+            &Selector::All => true,
+            &Selector::Store(store) =>
+                entry.store == Some(store)
+                || entry.binding.and_then(|b| self.bindings.get(&b))
+                    .map(|b| b.info.store == store)
+                    .unwrap_or(false)
+                || entry.key.map(|k| self.bindings.values().any(
+                    |b| b.info.store == store && b.info.key == k))
+                    .unwrap_or(false),
+            &Selector::Binding(binding) =>
+                entry.binding == Some(binding)
+                || self.bindings.get(&binding)
+                    .map(|b| entry.key == Some(b.info.key))
+                    .unwrap_or(false),
+            &Selector::Key(key) =>
+                entry.key == Some(key),
+        }
+    }
+}
+
+impl Storage for Memory {
+    fn store_open(&self, realm: &str, name: &str,
+                  network_policy: core::NetworkPolicy)
+                  -> Result<(ID, core::NetworkPolicy)> {
+        let mut state = self.state();
+        if let Some((id, store)) = state.stores.iter()
+            .find(|&(_, s)| s.realm == realm && s.name == name)
+        {
+            return Ok((*id, store.network_policy));
+        }
+
+        let id = state.next_id();
+        state.stores.insert(id, StoreInfo {
+            realm: realm.into(),
+            name: name.into(),
+            network_policy: network_policy,
+        });
+        Ok((id, network_policy))
+    }
+
+    fn store_info(&self, id: ID) -> Result<StoreInfo> {
+        self.state().store(id).map(|s| s.clone())
+    }
+
+    fn store_delete(&self, id: ID) -> Result<()> {
+        let mut state = self.state();
+        state.stores.remove(&id);
+        let bindings = state.bindings.iter()
+            .filter(|&(_, b)| b.info.store == id)
+            .map(|(binding, _)| *binding)
+            .collect::<Vec<_>>();
+        for binding in bindings.iter() {
+            state.bindings.remove(binding);
+        }
+        state.log.retain(|_, e| {
+            e.store != Some(id)
+                && ! e.binding.map(|b| bindings.contains(&b)).unwrap_or(false)
+        });
+        Ok(())
+    }
+
+    fn store_next(&self, realm_prefix: &str, after: ID)
+                  -> Result<(ID, StoreInfo)> {
+        self.state().stores.range((Excluded(after), Unbounded))
+            .find(|&(_, s)| s.realm.starts_with(realm_prefix))
+            .map(|(id, s)| Ok((*id, s.clone())))
+            .unwrap_or_else(not_found)
+    }
+
+    fn binding_lookup(&self, store: ID, label: &str) -> Result<ID> {
+        self.state().bindings.iter()
+            .find(|&(_, b)| b.info.store == store && b.info.label == label)
+            .map(|(id, _)| Ok(*id))
+            .unwrap_or_else(not_found)
+    }
+
+    fn binding_lookup_or_create(&self, store: ID, label: &str, key: ID)
+                                -> Result<(ID, bool)> {
+        let mut state = self.state();
+        if let Some((id, binding)) = state.bindings.iter()
+            .find(|&(_, b)| b.info.store == store && b.info.label == label)
+        {
+            return if binding.info.key == key {
+                Ok((*id, false))
+            } else {
+                Err(::Error::Conflict.into())
+            };
+        }
+
+        state.store(store)?;
+        state.key(key)?;
+        let id = state.next_id();
+        state.bindings.insert(id, Binding {
+            info: BindingInfo {
+                store: store,
+                label: label.into(),
+                key: key,
+            },
+            stats: Stats::new(Timestamp::now()),
+        });
+        Ok((id, true))
+    }
+
+    fn binding_lookup_by_subkeyid(&self, store: ID, keyid: &KeyID)
+                                  -> Result<ID> {
+        let state = self.state();
+        let keys = match state.key_by_keyid.get(&keyid.as_u64()?) {
+            Some(keys) => keys,
+            None => return not_found(),
+        };
+        state.bindings.iter()
+            .find(|&(_, b)| b.info.store == store && keys.contains(&b.info.key))
+            .map(|(id, _)| Ok(*id))
+            .unwrap_or_else(not_found)
+    }
+
+    fn binding_info(&self, id: ID) -> Result<BindingInfo> {
+        self.state().binding(id).map(|b| b.info.clone())
+    }
+
+    fn binding_set_key(&self, id: ID, key: ID) -> Result<()> {
+        let mut state = self.state();
+        state.key(key)?;
+        state.binding(id)?.info.key = key;
+        Ok(())
+    }
+
+    fn binding_delete(&self, id: ID) -> Result<()> {
+        let mut state = self.state();
+        state.bindings.remove(&id);
+        state.log.retain(|_, e| e.binding != Some(id));
+        Ok(())
+    }
+
+    fn binding_stats(&self, id: ID) -> Result<Stats> {
+        self.state().binding(id).map(|b| b.stats)
+    }
+
+    fn binding_register(&self, id: ID, usage: Usage, at: Timestamp)
+                        -> Result<()> {
+        let mut state = self.state();
+        let key = state.binding(id)?.info.key;
+        {
+            let binding = state.binding(id)?;
+            match usage {
+                Usage::Encryption => binding.stats.encryption.register(at),
+                Usage::Verification => binding.stats.verification.register(at),
+            }
+        }
+        let key = state.key(key)?;
+        match usage {
+            Usage::Encryption => key.stats.encryption.register(at),
+            Usage::Verification => key.stats.verification.register(at),
+        }
+        Ok(())
+    }
+
+    fn binding_next(&self, store: ID, after: ID)
+                    -> Result<(ID, BindingInfo)> {
+        self.state().bindings.range((Excluded(after), Unbounded))
+            .find(|&(_, b)| b.info.store == store)
+            .map(|(id, b)| Ok((*id, b.info.clone())))
+            .unwrap_or_else(not_found)
+    }
+
+    fn key_lookup(&self, fp: &Fingerprint) -> Result<ID> {
+        self.state().key_by_fingerprint.get(&fp.to_hex())
+            .map(|id| Ok(*id))
+            .unwrap_or_else(not_found)
+    }
+
+    fn key_lookup_by_keyid(&self, keyid: &KeyID) -> Result<ID> {
+        self.state().keys.iter()
+            .find(|&(_, k)| k.fingerprint.to_keyid() == *keyid)
+            .map(|(id, _)| Ok(*id))
+            .unwrap_or_else(not_found)
+    }
+
+    fn key_lookup_by_subkeyid(&self, keyid: &KeyID) -> Result<ID> {
+        self.state().key_by_keyid.get(&keyid.as_u64()?)
+            .and_then(|keys| keys.iter().next())
+            .map(|id| Ok(*id))
+            .unwrap_or_else(not_found)
+    }
+
+    fn key_lookup_or_create(&self, fp: &Fingerprint) -> Result<ID> {
+        let mut state = self.state();
+        if let Some(id) = state.key_by_fingerprint.get(&fp.to_hex()) {
+            return Ok(*id);
+        }
+
+        let id = state.next_id();
+        let now = Timestamp::now();
+        state.keys.insert(id, Key {
+            fingerprint: fp.clone(),
+            tpk: None,
+            stats: Stats::new(now),
+            update_at: now,
+            last_error: None,
+        });
+        state.key_by_fingerprint.insert(fp.to_hex(), id);
+        Ok(id)
+    }
+
+    fn key_fingerprint(&self, id: ID) -> Result<Fingerprint> {
+        self.state().key(id).map(|k| k.fingerprint.clone())
+    }
+
+    fn key_tpk(&self, id: ID) -> Result<Option<Vec<u8>>> {
+        self.state().key(id).map(|k| k.tpk.clone())
+    }
+
+    fn key_set_tpk(&self, id: ID, tpk: &TPK) -> Result<Vec<u8>> {
+        let mut blob = vec![];
+        tpk.serialize(&mut blob)?;
+
+        let mut state = self.state();
+        state.key(id)?.tpk = Some(blob.clone());
+        for (_, _, key) in tpk.keys_all() {
+            let keyid = key.keyid().as_u64()
+                .expect("computed keyid is valid");
+            state.key_by_keyid.entry(keyid).or_insert_with(BTreeSet::new)
+                .insert(id);
+        }
+        Ok(blob)
+    }
+
+    fn key_stats(&self, id: ID) -> Result<Stats> {
+        self.state().key(id).map(|k| k.stats)
+    }
+
+    fn key_next(&self, after: ID) -> Result<(ID, Fingerprint)> {
+        self.state().keys.range((Excluded(after), Unbounded))
+            .next()
+            .map(|(id, k)| Ok((*id, k.fingerprint.clone())))
+            .unwrap_or_else(not_found)
+    }
+
+    fn key_next_update_at(&self, network_policy: core::NetworkPolicy)
+                          -> Option<Timestamp> {
+        let state = self.state();
+        let network_policy = u8::from(&network_policy);
+        state.bindings.values()
+            .filter(|b| state.policy(b) == network_policy)
+            .map(|b| state.keys[&b.info.key].update_at)
+            .fold(None, |min, t| match min {
+                Some(min) if min <= t => Some(min),
+                _ => Some(t),
+            })
+    }
+
+    fn key_need_update(&self, network_policy: core::NetworkPolicy)
+                       -> Result<i32> {
+        let state = self.state();
+        let network_policy = u8::from(&network_policy);
+        Ok(state.bindings.values()
+           .filter(|b| state.policy(b) >= network_policy)
+           .count() as i32)
+    }
+
+    fn key_to_update(&self, network_policy: core::NetworkPolicy,
+                     now: Timestamp)
+                     -> Result<(ID, Fingerprint)> {
+        let state = self.state();
+        let network_policy = u8::from(&network_policy);
+
+        // Select the key that was updated least recently.
+        let mut candidate: Option<(ID, &Key)> = None;
+        for binding in state.bindings.values() {
+            if state.policy(binding) < network_policy {
+                continue;
+            }
+            let key = &state.keys[&binding.info.key];
+            if ! (key.update_at < now) {
+                continue;
+            }
+            if candidate.map(|(_, c)| key.update_at < c.update_at)
+                .unwrap_or(true)
+            {
+                candidate = Some((binding.info.key, key));
+            }
+        }
+
+        candidate.map(|(id, k)| Ok((id, k.fingerprint.clone())))
+            .unwrap_or_else(not_found)
+    }
+
+    fn key_schedule_update(&self, id: ID, update_at: Timestamp)
+                           -> Result<()> {
+        self.state().key(id)?.update_at = update_at;
+        Ok(())
+    }
+
+    fn key_update_succeeded(&self, id: ID, updated: Timestamp,
+                            update_at: Timestamp)
+                            -> Result<()> {
+        let mut state = self.state();
+        let key = state.key(id)?;
+        key.stats.updated = Some(updated);
+        key.last_error = None;
+        key.update_at = update_at;
+        Ok(())
+    }
+
+    fn key_update_failed(&self, id: ID, error: &str, update_at: Timestamp)
+                         -> Result<()> {
+        let mut state = self.state();
+        let key = state.key(id)?;
+        key.last_error = Some(error.into());
+        key.update_at = update_at;
+        Ok(())
+    }
+
+    fn key_update_status(&self, id: ID) -> Result<UpdateStatus> {
+        let mut state = self.state();
+        let key = state.key(id)?;
+        Ok(UpdateStatus {
+            update_at: key.update_at,
+            last_error: key.last_error.clone(),
+        })
+    }
+
+    fn log_append(&self, entry: LogEntry) -> Result<ID> {
+        let mut state = self.state();
+        let id = state.next_id();
+        state.log.insert(id, entry);
+        Ok(id)
+    }
+
+    fn log_next(&self, selector: &Selector, min_level: LogLevel, before: ID)
+                -> Result<(ID, LogEntry)> {
+        let state = self.state();
+        state.log.range((Unbounded, Excluded(before))).rev()
+            .find(|&(_, e)| e.level >= min_level && state.selects(selector, e))
+            .map(|(id, e)| Ok((*id, e.clone())))
+            .unwrap_or_else(not_found)
+    }
+
+    fn log_trim(&self, older_than: Timestamp, max_entries_per_key: usize)
+                -> Result<()> {
+        let mut state = self.state();
+
+        // Count the entries per key, starting with the newest.
+        let mut count = HashMap::new();
+        let expired: Vec<ID> = state.log.iter().rev()
+            .filter(|&(_, e)| {
+                let too_many = e.key.map(|key| {
+                    let n = count.entry(key).or_insert(0);
+                    *n += 1;
+                    *n > max_entries_per_key
+                }).unwrap_or(false);
+                too_many || e.timestamp < older_than
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            state.log.remove(&id);
+        }
+        Ok(())
+    }
+
+    fn autocrypt_peer(&self, addr: &str) -> Result<Option<AutocryptPeer>> {
+        Ok(self.state().autocrypt_peers.get(addr).cloned())
+    }
+
+    fn autocrypt_set_peer(&self, addr: &str, peer: &AutocryptPeer)
+                          -> Result<()> {
+        self.state().autocrypt_peers.insert(addr.into(), *peer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::Duration;
+
+    fn entry(timestamp: Timestamp, level: LogLevel, key: Option<ID>)
+             -> LogEntry {
+        LogEntry {
+            timestamp: timestamp,
+            level: level,
+            store: None,
+            binding: None,
+            key: key,
+            slug: "test".into(),
+            message: format!("{:?}", level),
+            error: None,
+        }
+    }
+
+    fn levels(m: &Memory, selector: &Selector, min_level: LogLevel)
+              -> Vec<LogLevel> {
+        let mut levels = vec![];
+        let mut n = ID::max();
+        while let Ok((id, e)) = m.log_next(selector, min_level, n) {
+            levels.push(e.level);
+            n = id;
+        }
+        levels
+    }
+
+    #[test]
+    fn log_levels() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let m = Memory::open(ctx.home());
+        let now = Timestamp::now();
+        for &level in &[LogLevel::Debug, LogLevel::Info,
+                        LogLevel::Warning, LogLevel::Error] {
+            m.log_append(entry(now, level, None)).unwrap();
+        }
+
+        assert_eq!(levels(&m, &Selector::All, LogLevel::Debug).len(), 4);
+        assert_eq!(levels(&m, &Selector::All, LogLevel::Warning),
+                   vec![LogLevel::Error, LogLevel::Warning]);
+        assert_eq!(levels(&m, &Selector::All, LogLevel::Error),
+                   vec![LogLevel::Error]);
+    }
+
+    #[test]
+    fn log_trim() {
+        let ctx = core::Context::configure().ephemeral().build().unwrap();
+        let m = Memory::open(ctx.home());
+        let fp = Fingerprint::from_hex(
+            "3E8877C877274692975189F5D03F6F865226FE8B").unwrap();
+        let key = m.key_lookup_or_create(&fp).unwrap();
+        let now = Timestamp::now();
+        let old = now - Duration::weeks(2);
+
+        m.log_append(entry(old, LogLevel::Error, None)).unwrap();
+        m.log_append(entry(now, LogLevel::Info, None)).unwrap();
+        for _ in 0..5 {
+            m.log_append(entry(now, LogLevel::Debug, Some(key))).unwrap();
+        }
+
+        m.log_trim(now - Duration::weeks(1), 3).unwrap();
+        assert_eq!(levels(&m, &Selector::All, LogLevel::Debug),
+                   vec![LogLevel::Debug, LogLevel::Debug, LogLevel::Debug,
+                        LogLevel::Info]);
+        assert_eq!(levels(&m, &Selector::Key(key), LogLevel::Debug).len(), 3);
+    }
+}
@@ -0,0 +1,329 @@
+//! Storage backends.
+//!
+//! The servers implementing the store protocol do not talk to a
+//! database directly.  Instead, they use the `Storage` trait, which
+//! is implemented by the different storage backends.
+//!
+//! Objects are referred to using `ID`s.  IDs are never reused, and
+//! increase monotonically, which the iterators rely on.
+
+use std::rc::Rc;
+
+use openpgp::{TPK, KeyID, Fingerprint};
+use sequoia_core as core;
+
+use store_protocol_capnp::node;
+use LogLevel;
+
+use super::Result;
+use super::support::{ID, Timestamp};
+use super::log::Selector;
+
+pub mod sqlite;
+pub mod memory;
+pub mod directory;
+
+pub use self::sqlite::SQLite;
+pub use self::memory::Memory;
+pub use self::directory::Directory;
+
+/// Opens the storage backend configured for the given context.
+pub fn open(ctx: &core::Context) -> Result<Rc<Storage>> {
+    Ok(match ::StorageBackend::configured(ctx)? {
+        ::StorageBackend::SQLite =>
+            Rc::new(SQLite::open(ctx)?),
+        ::StorageBackend::Directory =>
+            Rc::new(Directory::open(&ctx.home().join("public-key-store"))?),
+        ::StorageBackend::Memory =>
+            Rc::new(Memory::open(ctx.home())),
+    })
+}
+
+/// A store.
+#[derive(Clone)]
+pub struct StoreInfo {
+    pub realm: String,
+    pub name: String,
+    pub network_policy: core::NetworkPolicy,
+}
+
+/// A binding of a label to a key.
+#[derive(Clone)]
+pub struct BindingInfo {
+    pub store: ID,
+    pub label: String,
+    pub key: ID,
+}
+
+/// Counts how often something has been used.
+#[derive(Clone, Copy, Default)]
+pub struct Counter {
+    pub count: i64,
+    pub first: Option<Timestamp>,
+    pub last: Option<Timestamp>,
+}
+
+impl Counter {
+    /// Records a use at the given time.
+    pub fn register(&mut self, at: Timestamp) {
+        self.count += 1;
+        if self.first.is_none() {
+            self.first = Some(at);
+        }
+        self.last = Some(at);
+    }
+}
+
+/// Statistics about bindings and keys.
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub created: Timestamp,
+    pub updated: Option<Timestamp>,
+    pub encryption: Counter,
+    pub verification: Counter,
+}
+
+impl Stats {
+    /// Returns statistics for an object created at the given time.
+    pub fn new(created: Timestamp) -> Self {
+        Stats {
+            created: created,
+            updated: None,
+            encryption: Default::default(),
+            verification: Default::default(),
+        }
+    }
+}
+
+/// The update status of a key.
+#[derive(Clone)]
+pub struct UpdateStatus {
+    pub update_at: Timestamp,
+    pub last_error: Option<String>,
+}
+
+/// The ways a binding can be used.
+#[derive(Clone, Copy)]
+pub enum Usage {
+    Encryption,
+    Verification,
+}
+
+/// A log entry.
+#[derive(Clone)]
+pub struct LogEntry {
+    pub timestamp: Timestamp,
+    pub level: LogLevel,
+    pub store: Option<ID>,
+    pub binding: Option<ID>,
+    pub key: Option<ID>,
+    pub slug: String,
+    pub message: String,
+    pub error: Option<String>,
+}
+
+/// The state of an Autocrypt peer.
+#[derive(Clone, Copy)]
+pub struct AutocryptPeer {
+    pub last_seen: Option<i64>,
+    pub autocrypt_timestamp: Option<i64>,
+    pub public_key: Option<ID>,
+    pub prefer_encrypt: node::AutocryptPreferEncrypt,
+    pub gossip_timestamp: Option<i64>,
+    pub gossip_key: Option<ID>,
+}
+
+impl Default for AutocryptPeer {
+    fn default() -> Self {
+        AutocryptPeer {
+            last_seen: None,
+            autocrypt_timestamp: None,
+            public_key: None,
+            prefer_encrypt: node::AutocryptPreferEncrypt::NoPreference,
+            gossip_timestamp: None,
+            gossip_key: None,
+        }
+    }
+}
+
+/// A change made by somebody else that a backend picked up.
+#[derive(Clone)]
+pub struct Change {
+    pub kind: node::EventKind,
+    pub fingerprint: Fingerprint,
+    /// The store, and the label of the changed binding, if any.
+    pub binding: Option<(ID, StoreInfo, String)>,
+}
+
+/// Stores stores, bindings, keys, and everything else the store
+/// server needs to remember.
+///
+/// Lookups of objects that do not exist fail with
+/// `Error::NotFound`.
+pub trait Storage {
+    /* Stores.  */
+
+    /// Opens the given store, creating it if necessary.
+    ///
+    /// Returns the id of the store, and the network policy the store
+    /// was created with.
+    fn store_open(&self, realm: &str, name: &str,
+                  network_policy: core::NetworkPolicy)
+                  -> Result<(ID, core::NetworkPolicy)>;
+
+    /// Returns information about the given store.
+    fn store_info(&self, id: ID) -> Result<StoreInfo>;
+
+    /// Deletes the given store, and all of its bindings.
+    fn store_delete(&self, id: ID) -> Result<()>;
+
+    /// Returns the first store after `after` whose realm starts with
+    /// `realm_prefix`.
+    fn store_next(&self, realm_prefix: &str, after: ID)
+                  -> Result<(ID, StoreInfo)>;
+
+    /* Bindings.  */
+
+    /// Looks up a binding by label.
+    fn binding_lookup(&self, store: ID, label: &str) -> Result<ID>;
+
+    /// Looks up a binding by label, creating it if necessary.
+    ///
+    /// Returns the id of the binding, and whether it was just
+    /// created.  Fails with `Error::Conflict` if the label is bound
+    /// to a different key.
+    fn binding_lookup_or_create(&self, store: ID, label: &str, key: ID)
+                                -> Result<(ID, bool)>;
+
+    /// Looks up the binding in the given store whose key has a
+    /// (sub)key with the given key ID.
+    fn binding_lookup_by_subkeyid(&self, store: ID, keyid: &KeyID)
+                                  -> Result<ID>;
+
+    /// Returns information about the given binding.
+    fn binding_info(&self, id: ID) -> Result<BindingInfo>;
+
+    /// Binds the given binding to a different key.
+    fn binding_set_key(&self, id: ID, key: ID) -> Result<()>;
+
+    /// Deletes the given binding.
+    fn binding_delete(&self, id: ID) -> Result<()>;
+
+    /// Returns statistics about the given binding.
+    fn binding_stats(&self, id: ID) -> Result<Stats>;
+
+    /// Records a use of the binding, and of its key.
+    fn binding_register(&self, id: ID, usage: Usage, at: Timestamp)
+                        -> Result<()>;
+
+    /// Returns the first binding in the given store after `after`.
+    fn binding_next(&self, store: ID, after: ID) -> Result<(ID, BindingInfo)>;
+
+    /* Keys.  */
+
+    /// Looks up a key by fingerprint.
+    fn key_lookup(&self, fp: &Fingerprint) -> Result<ID>;
+
+    /// Looks up a key by the key ID of its primary key.
+    fn key_lookup_by_keyid(&self, keyid: &KeyID) -> Result<ID>;
+
+    /// Looks up a key by the key ID of any of its (sub)keys.
+    fn key_lookup_by_subkeyid(&self, keyid: &KeyID) -> Result<ID>;
+
+    /// Looks up a key by fingerprint, creating it if necessary.
+    ///
+    /// Keys are created without a TPK.
+    fn key_lookup_or_create(&self, fp: &Fingerprint) -> Result<ID>;
+
+    /// Returns the fingerprint of the given key.
+    fn key_fingerprint(&self, id: ID) -> Result<Fingerprint>;
+
+    /// Returns the serialized TPK of the given key, if any.
+    fn key_tpk(&self, id: ID) -> Result<Option<Vec<u8>>>;
+
+    /// Stores the TPK of the given key.
+    ///
+    /// Also indexes the TPK's subkeys.  Returns the serialized TPK.
+    fn key_set_tpk(&self, id: ID, tpk: &TPK) -> Result<Vec<u8>>;
+
+    /// Returns statistics about the given key.
+    fn key_stats(&self, id: ID) -> Result<Stats>;
+
+    /// Returns the first key after `after`.
+    fn key_next(&self, after: ID) -> Result<(ID, Fingerprint)>;
+
+    /* Key updates.  */
+
+    /// Returns when the next key bound in a store with the given
+    /// network policy should be updated.
+    fn key_next_update_at(&self, network_policy: core::NetworkPolicy)
+                          -> Option<Timestamp>;
+
+    /// Returns the number of bindings in stores with at least the
+    /// given network policy.
+    fn key_need_update(&self, network_policy: core::NetworkPolicy)
+                       -> Result<i32>;
+
+    /// Returns the key bound in a store with at least the given
+    /// network policy that was updated least recently, if it is due
+    /// to be updated at `now`.
+    fn key_to_update(&self, network_policy: core::NetworkPolicy,
+                     now: Timestamp)
+                     -> Result<(ID, Fingerprint)>;
+
+    /// Schedules the next update of the key.
+    fn key_schedule_update(&self, id: ID, update_at: Timestamp)
+                           -> Result<()>;
+
+    /// Records a successful update of the key, and schedules the
+    /// next one.
+    fn key_update_succeeded(&self, id: ID, updated: Timestamp,
+                            update_at: Timestamp)
+                            -> Result<()>;
+
+    /// Records a failed update of the key, and schedules the next
+    /// one.
+    fn key_update_failed(&self, id: ID, error: &str, update_at: Timestamp)
+                         -> Result<()>;
+
+    /// Returns when the key is updated next, and why the last update
+    /// failed, if it did.
+    fn key_update_status(&self, id: ID) -> Result<UpdateStatus>;
+
+    /* Log.  */
+
+    /// Appends an entry to the log.
+    fn log_append(&self, entry: LogEntry) -> Result<ID>;
+
+    /// Returns the newest log entry selected by `selector` with at
+    /// least the given level that is older than `before`.
+    fn log_next(&self, selector: &Selector, min_level: LogLevel, before: ID)
+                -> Result<(ID, LogEntry)>;
+
+    /// Trims the log.
+    ///
+    /// Removes all entries older than `older_than`, and all but the
+    /// newest `max_entries_per_key` entries referring to any key.
+    fn log_trim(&self, older_than: Timestamp, max_entries_per_key: usize)
+                -> Result<()>;
+
+    /* Autocrypt.  */
+
+    /// Returns the state of the given Autocrypt peer, if any.
+    fn autocrypt_peer(&self, addr: &str) -> Result<Option<AutocryptPeer>>;
+
+    /// Updates the state of the given Autocrypt peer.
+    fn autocrypt_set_peer(&self, addr: &str, peer: &AutocryptPeer)
+                          -> Result<()>;
+
+    /* Changes.  */
+
+    /// Returns the changes made by somebody else that were picked up
+    /// since the last call.
+    ///
+    /// Backends that can only be changed through this trait do not
+    /// pick up any changes.
+    fn take_changes(&self) -> Vec<Change> {
+        Vec::new()
+    }
+}
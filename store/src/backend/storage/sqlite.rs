@@ -0,0 +1,715 @@
+//! Stores everything in a SQLite database.
+
+use rusqlite::{self, Connection, OpenFlags};
+
+use openpgp::{TPK, KeyID, Fingerprint};
+use openpgp::serialize::Serialize;
+use sequoia_core as core;
+
+use store_protocol_capnp::node;
+
+use backend::Result;
+use backend::support::{ID, Timestamp};
+use LogLevel;
+use backend::log::{self, Selector};
+use super::{
+    Storage, StoreInfo, BindingInfo, Counter, Stats, UpdateStatus, Usage,
+    LogEntry, AutocryptPeer,
+};
+
+/// Stores everything in a SQLite database.
+pub struct SQLite {
+    c: Connection,
+}
+
+impl SQLite {
+    /// Opens the database.
+    ///
+    /// Ephemeral contexts use an in-memory database, so that we do
+    /// not write to the disk.  All servers in this process that use
+    /// the same home directory share this database, just like they
+    /// would share a database on disk.  It is freed once the last of
+    /// them exits.
+    pub fn open(ctx: &core::Context) -> Result<Self> {
+        let c = if ctx.ephemeral() {
+            let mut uri = String::from("file:public-key-store-");
+            for b in ctx.home().to_string_lossy().bytes() {
+                if (b as char).is_ascii_alphanumeric() {
+                    uri.push(b as char);
+                } else {
+                    uri.push_str(&format!("%{:02X}", b));
+                }
+            }
+            uri.push_str("?mode=memory&cache=shared");
+
+            Connection::open_with_flags(
+                uri,
+                OpenFlags::SQLITE_OPEN_READ_WRITE
+                    | OpenFlags::SQLITE_OPEN_CREATE
+                    | OpenFlags::SQLITE_OPEN_URI)?
+        } else {
+            Connection::open(ctx.home().join("public-key-store.sqlite"))?
+        };
+
+        c.execute_batch("PRAGMA secure_delete = true;")?;
+        c.execute_batch("PRAGMA foreign_keys = true;")?;
+        let storage = SQLite { c: c };
+        storage.init()?;
+        Ok(storage)
+    }
+
+    /// Initializes or migrates the database.
+    fn init(&self) -> Result<()> {
+        let v = self.c.query_row(
+            "SELECT version FROM version WHERE id=1",
+            &[], |row| row.get(0));
+
+        let mut v: i64 = if let Ok(v) = v {
+            v
+        } else {
+            self.c.execute_batch(DB_SCHEMA_1)?;
+            log::info(self, log::Refers::to(), "server",
+                      "Created database version 1")?;
+            1
+        };
+
+        if v == 1 {
+            self.c.execute_batch(DB_MIGRATE_1_TO_2)?;
+            log::info(self, log::Refers::to(), "server",
+                      "Migrated database to version 2")?;
+            v = 2;
+        }
+
+        if v == 2 {
+            self.c.execute_batch(DB_MIGRATE_2_TO_3)?;
+            log::info(self, log::Refers::to(), "server",
+                      "Migrated database to version 3")?;
+            v = 3;
+        }
+
+        if v == 3 {
+            self.c.execute_batch(DB_MIGRATE_3_TO_4)?;
+            log::info(self, log::Refers::to(), "server",
+                      "Migrated database to version 4")?;
+            v = 4;
+        }
+
+        match v {
+            4 => Ok(()),
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Returns the statistics of the given object.
+    fn stats(&self, table: &str, id: ID) -> Result<Stats> {
+        Ok(self.c.query_row(
+            &format!("SELECT
+                      created,
+                      updated,
+                      encryption_count,
+                      encryption_first,
+                      encryption_last,
+                      verification_count,
+                      verification_first,
+                      verification_last
+                      FROM {0}
+                      WHERE id = ?1", table),
+            &[&id], |row| Stats {
+                created: row.get(0),
+                updated: row.get(1),
+                encryption: Counter {
+                    count: row.get(2),
+                    first: row.get(3),
+                    last: row.get(4),
+                },
+                verification: Counter {
+                    count: row.get(5),
+                    first: row.get(6),
+                    last: row.get(7),
+                },
+            })?)
+    }
+
+    /// Records a use of the given object.
+    fn register(&self, table: &str, id: ID, usage: Usage, at: Timestamp)
+                -> Result<()> {
+        let usage = match usage {
+            Usage::Encryption => "encryption",
+            Usage::Verification => "verification",
+        };
+        self.c.execute(
+            &format!("UPDATE {0}
+                      SET {1}_count = {1}_count + 1,
+                          {1}_first = coalesce({1}_first, ?2),
+                          {1}_last = ?2
+                      WHERE id = ?1", table, usage),
+            &[&id, &at])?;
+        Ok(())
+    }
+}
+
+/// Converts a network policy from its database representation.
+fn to_network_policy(policy: i64) -> Result<core::NetworkPolicy> {
+    // We cannot implement FromSql and friends for
+    // core::NetworkPolicy, hence we need to do it by foot.
+    if policy < 0 || policy > 3 {
+        return Err(node::Error::SystemError.into());
+    }
+    Ok(core::NetworkPolicy::from(policy as u8))
+}
+
+/// Parses a fingerprint stored in the database.
+fn fingerprint(fp: &str) -> Result<Fingerprint> {
+    Fingerprint::from_hex(fp).map_err(|_| node::Error::SystemError.into())
+}
+
+impl Storage for SQLite {
+    fn store_open(&self, realm: &str, name: &str,
+                  network_policy: core::NetworkPolicy)
+                  -> Result<(ID, core::NetworkPolicy)> {
+        // We cannot implement ToSql and friends for
+        // core::NetworkPolicy, hence we need to do it by foot.
+        let p: u8 = (&network_policy).into();
+
+        self.c.execute(
+            "INSERT OR IGNORE INTO stores (realm, network_policy, name)
+             VALUES (?1, ?2, ?3)",
+            &[&realm, &p, &name])?;
+        let (id, policy): (ID, i64) = self.c.query_row(
+            "SELECT id, network_policy FROM stores
+             WHERE realm = ?1 AND name = ?2",
+            &[&realm, &name], |row| (row.get(0), row.get(1)))?;
+        Ok((id, to_network_policy(policy)?))
+    }
+
+    fn store_info(&self, id: ID) -> Result<StoreInfo> {
+        let (realm, name, policy): (String, String, i64) = self.c.query_row(
+            "SELECT realm, name, network_policy FROM stores WHERE id = ?1",
+            &[&id], |row| (row.get(0), row.get(1), row.get(2)))?;
+        Ok(StoreInfo {
+            realm: realm,
+            name: name,
+            network_policy: to_network_policy(policy)?,
+        })
+    }
+
+    fn store_delete(&self, id: ID) -> Result<()> {
+        self.c.execute("DELETE FROM stores WHERE id = ?1", &[&id])?;
+        Ok(())
+    }
+
+    fn store_next(&self, realm_prefix: &str, after: ID)
+                  -> Result<(ID, StoreInfo)> {
+        let (id, realm, name, policy): (ID, String, String, i64) =
+            self.c.query_row(
+                "SELECT id, realm, name, network_policy FROM stores
+                     WHERE id > ?1 AND realm like ?2
+                     ORDER BY id LIMIT 1",
+                &[&after, &(String::from(realm_prefix) + "%")],
+                |row| (row.get(0), row.get(1), row.get(2), row.get(3)))?;
+        Ok((id, StoreInfo {
+            realm: realm,
+            name: name,
+            network_policy: to_network_policy(policy)?,
+        }))
+    }
+
+    fn binding_lookup(&self, store: ID, label: &str) -> Result<ID> {
+        Ok(self.c.query_row(
+            "SELECT id FROM bindings WHERE store = ?1 AND label = ?2",
+            &[&store, &label], |row| row.get(0))?)
+    }
+
+    fn binding_lookup_or_create(&self, store: ID, label: &str, key: ID)
+                                -> Result<(ID, bool)> {
+        let lookup = || -> rusqlite::Result<(ID, ID)> {
+            self.c.query_row(
+                "SELECT id, key FROM bindings WHERE store = ?1 AND label = ?2",
+                &[&store, &label], |row| (row.get(0), row.get(1)))
+        };
+
+        let (binding, existing_key) = if let Ok(x) = lookup() {
+            x
+        } else {
+            let r = self.c.execute(
+                "INSERT INTO bindings (store, label, key, created)
+                 VALUES (?, ?, ?, ?)",
+                &[&store, &label, &key, &Timestamp::now()]);
+
+            // Some other mutator might race us to the insertion.
+            match r {
+                Err(rusqlite::Error::SqliteFailure(f, e)) => match f.code {
+                    // We lost.  Retry the lookup.
+                    rusqlite::ErrorCode::ConstraintViolation => lookup()?,
+                    // Raise otherwise.
+                    _ => return Err(
+                        rusqlite::Error::SqliteFailure(f, e).into()),
+                },
+                Err(e) => return Err(e.into()),
+                Ok(_) => return Ok((self.c.last_insert_rowid().into(), true)),
+            }
+        };
+
+        if existing_key == key {
+            Ok((binding, false))
+        } else {
+            Err(::Error::Conflict.into())
+        }
+    }
+
+    fn binding_lookup_by_subkeyid(&self, store: ID, keyid: &KeyID)
+                                  -> Result<ID> {
+        let keyid = keyid.as_u64()? as i64;
+        Ok(self.c.query_row(
+            "SELECT bindings.id FROM bindings
+             JOIN key_by_keyid on bindings.key = key_by_keyid.key
+             WHERE key_by_keyid.keyid = ?1 AND bindings.store = ?2",
+            &[&keyid, &store], |row| row.get(0))?)
+    }
+
+    fn binding_info(&self, id: ID) -> Result<BindingInfo> {
+        Ok(self.c.query_row(
+            "SELECT store, label, key FROM bindings WHERE id = ?1",
+            &[&id], |row| BindingInfo {
+                store: row.get(0),
+                label: row.get(1),
+                key: row.get(2),
+            })?)
+    }
+
+    fn binding_set_key(&self, id: ID, key: ID) -> Result<()> {
+        self.c.execute("UPDATE bindings SET key = ?1 WHERE id = ?2",
+                       &[&key, &id])?;
+        Ok(())
+    }
+
+    fn binding_delete(&self, id: ID) -> Result<()> {
+        self.c.execute("DELETE FROM bindings WHERE id = ?1", &[&id])?;
+        Ok(())
+    }
+
+    fn binding_stats(&self, id: ID) -> Result<Stats> {
+        self.stats("bindings", id)
+    }
+
+    fn binding_register(&self, id: ID, usage: Usage, at: Timestamp)
+                        -> Result<()> {
+        let key = self.binding_info(id)?.key;
+        self.register("bindings", id, usage, at)?;
+        self.register("keys", key, usage, at)
+    }
+
+    fn binding_next(&self, store: ID, after: ID)
+                    -> Result<(ID, BindingInfo)> {
+        Ok(self.c.query_row(
+            "SELECT id, label, key FROM bindings
+                 WHERE id > ?1 AND store = ?2
+                 ORDER BY id LIMIT 1",
+            &[&after, &store],
+            |row| (row.get(0), BindingInfo {
+                store: store,
+                label: row.get(1),
+                key: row.get(2),
+            }))?)
+    }
+
+    fn key_lookup(&self, fp: &Fingerprint) -> Result<ID> {
+        Ok(self.c.query_row(
+            "SELECT id FROM keys WHERE fingerprint = ?1",
+            &[&fp.to_hex()], |row| row.get(0))?)
+    }
+
+    fn key_lookup_by_keyid(&self, keyid: &KeyID) -> Result<ID> {
+        let keyid = format!("%{}", keyid.to_hex());
+        Ok(self.c.query_row(
+            "SELECT id FROM keys WHERE fingerprint LIKE ?1",
+            &[&keyid], |row| row.get(0))?)
+    }
+
+    fn key_lookup_by_subkeyid(&self, keyid: &KeyID) -> Result<ID> {
+        let keyid = keyid.as_u64()? as i64;
+        Ok(self.c.query_row(
+            "SELECT key FROM key_by_keyid WHERE keyid = ?1",
+            &[&keyid], |row| row.get(0))?)
+    }
+
+    fn key_lookup_or_create(&self, fp: &Fingerprint) -> Result<ID> {
+        if let Ok(id) = self.key_lookup(fp) {
+            return Ok(id);
+        }
+
+        let fp = fp.to_hex();
+        let r = self.c.execute(
+            "INSERT INTO keys (fingerprint, created, update_at)
+             VALUES (?1, ?2, ?2)",
+            &[&fp, &Timestamp::now()]);
+
+        // Some other mutator might race us to the insertion.
+        match r {
+            Err(rusqlite::Error::SqliteFailure(f, e)) => match f.code {
+                // We lost.  Retry the lookup.
+                rusqlite::ErrorCode::ConstraintViolation =>
+                    self.c.query_row(
+                        "SELECT id FROM keys WHERE fingerprint = ?1",
+                        &[&fp], |row| row.get(0)),
+                // Raise otherwise.
+                _ => Err(rusqlite::Error::SqliteFailure(f, e)),
+            },
+            Err(e) => Err(e),
+            Ok(_) => Ok(self.c.last_insert_rowid().into()),
+        }.map_err(|e| e.into())
+    }
+
+    fn key_fingerprint(&self, id: ID) -> Result<Fingerprint> {
+        let fp: String = self.c.query_row(
+            "SELECT fingerprint FROM keys WHERE id = ?1",
+            &[&id], |row| row.get(0))?;
+        fingerprint(&fp)
+    }
+
+    fn key_tpk(&self, id: ID) -> Result<Option<Vec<u8>>> {
+        Ok(self.c.query_row(
+            "SELECT key FROM keys WHERE id = ?1",
+            &[&id], |row| row.get_checked(0).ok())?)
+    }
+
+    fn key_set_tpk(&self, id: ID, tpk: &TPK) -> Result<Vec<u8>> {
+        let mut blob = vec![];
+        tpk.serialize(&mut blob)?;
+        self.c.execute("UPDATE keys SET key = ?1 WHERE id = ?2",
+                       &[&blob, &id])?;
+
+        // Keep the mapping of (sub)KeyIDs to keys up-to-date.
+        for (_, _, key) in tpk.keys_all() {
+            let keyid = key.keyid().as_u64()
+                .expect("computed keyid is valid");
+
+            // The mapping might already be present.  This is not an
+            // error.
+            self.c.execute(
+                "INSERT OR IGNORE INTO key_by_keyid (keyid, key)
+                 VALUES (?1, ?2)",
+                &[&(keyid as i64), &id])?;
+        }
+
+        Ok(blob)
+    }
+
+    fn key_stats(&self, id: ID) -> Result<Stats> {
+        self.stats("keys", id)
+    }
+
+    fn key_next(&self, after: ID) -> Result<(ID, Fingerprint)> {
+        let (id, fp): (ID, String) = self.c.query_row(
+            "SELECT id, fingerprint FROM keys
+                 WHERE keys.id > ?1
+                 ORDER BY id LIMIT 1",
+            &[&after], |row| (row.get(0), row.get(1)))?;
+        Ok((id, fingerprint(&fp)?))
+    }
+
+    fn key_next_update_at(&self, network_policy: core::NetworkPolicy)
+                          -> Option<Timestamp> {
+        let network_policy_u8 = u8::from(&network_policy);
+
+        // Select the key that was updated least recently.
+        self.c.query_row(
+            "SELECT keys.update_at FROM keys
+                 JOIN bindings on keys.id = bindings.key
+                 JOIN stores on stores.id = bindings.store
+                 WHERE stores.network_policy = ?1
+                 ORDER BY keys.update_at LIMIT 1",
+            &[&network_policy_u8], |row| -> Timestamp {row.get(0)}).ok()
+    }
+
+    fn key_need_update(&self, network_policy: core::NetworkPolicy)
+                       -> Result<i32> {
+        let network_policy_u8 = u8::from(&network_policy);
+
+        let count: i64 = self.c.query_row(
+            "SELECT COUNT(*) FROM keys
+                 JOIN bindings on keys.id = bindings.key
+                 JOIN stores on stores.id = bindings.store
+                 WHERE stores.network_policy >= ?1",
+            &[&network_policy_u8], |row| row.get(0))?;
+        assert!(count >= 0);
+        Ok(count as i32)
+    }
+
+    fn key_to_update(&self, network_policy: core::NetworkPolicy,
+                     now: Timestamp)
+                     -> Result<(ID, Fingerprint)> {
+        let network_policy_u8 = u8::from(&network_policy);
+
+        // Select the key that was updated least recently.
+        let (id, fp): (ID, String) = self.c.query_row(
+            "SELECT keys.id, keys.fingerprint FROM keys
+                 JOIN bindings on keys.id = bindings.key
+                 JOIN stores on stores.id = bindings.store
+                 WHERE stores.network_policy >= ?1
+                   AND keys.update_at < ?2
+                 ORDER BY keys.update_at LIMIT 1",
+            &[&network_policy_u8, &now], |row| (row.get(0), row.get(1)))?;
+        Ok((id, fingerprint(&fp)?))
+    }
+
+    fn key_schedule_update(&self, id: ID, update_at: Timestamp)
+                           -> Result<()> {
+        self.c.execute("UPDATE keys SET update_at = ?2 WHERE id = ?1",
+                       &[&id, &update_at])?;
+        Ok(())
+    }
+
+    fn key_update_succeeded(&self, id: ID, updated: Timestamp,
+                            update_at: Timestamp)
+                            -> Result<()> {
+        self.c.execute("UPDATE keys
+                        SET updated = ?2, last_error = NULL, update_at = ?3
+                        WHERE id = ?1",
+                       &[&id, &updated, &update_at])?;
+        Ok(())
+    }
+
+    fn key_update_failed(&self, id: ID, error: &str, update_at: Timestamp)
+                         -> Result<()> {
+        self.c.execute("UPDATE keys
+                        SET last_error = ?2, update_at = ?3
+                        WHERE id = ?1",
+                       &[&id, &error, &update_at])?;
+        Ok(())
+    }
+
+    fn key_update_status(&self, id: ID) -> Result<UpdateStatus> {
+        Ok(self.c.query_row(
+            "SELECT update_at, last_error FROM keys WHERE id = ?1",
+            &[&id], |row| UpdateStatus {
+                update_at: row.get(0),
+                last_error: row.get(1),
+            })?)
+    }
+
+    fn log_append(&self, entry: LogEntry) -> Result<ID> {
+        self.c.execute(
+            "INSERT INTO log
+                 (timestamp, level, store, binding, key, slug, message, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            &[&entry.timestamp, &entry.level,
+              &entry.store, &entry.binding, &entry.key,
+              &entry.slug, &entry.message, &entry.error])?;
+        Ok(self.c.last_insert_rowid().into())
+    }
+
+    fn log_next(&self, selector: &Selector, min_level: LogLevel, before: ID)
+                -> Result<(ID, LogEntry)> {
+        let (condition, id) = match selector {
+            &Selector::All =>
+                ("?3 IS NULL", None),
+            &Selector::Store(store) =>
+                ("(store = ?3
+                   OR binding IN (SELECT id FROM bindings WHERE store = ?3)
+                   OR key IN (SELECT key FROM bindings WHERE store = ?3))",
+                 Some(store)),
+            &Selector::Binding(binding) =>
+                ("(binding = ?3
+                   OR key IN (SELECT key FROM bindings WHERE id = ?3))",
+                 Some(binding)),
+            &Selector::Key(key) =>
+                ("key = ?3", Some(key)),
+        };
+
+        Ok(self.c.query_row(
+            &format!("SELECT id, timestamp, level,
+                             store, binding, key,
+                             slug, message, error
+                          FROM log
+                          WHERE id < ?1 AND level >= ?2 AND {}
+                          ORDER BY id DESC LIMIT 1", condition),
+            &[&before, &min_level, &id],
+            |row| (row.get(0), LogEntry {
+                timestamp: row.get(1),
+                level: row.get(2),
+                store: row.get(3),
+                binding: row.get(4),
+                key: row.get(5),
+                slug: row.get(6),
+                message: row.get(7),
+                error: row.get(8),
+            }))?)
+    }
+
+    fn log_trim(&self, older_than: Timestamp, max_entries_per_key: usize)
+                -> Result<()> {
+        self.c.execute("DELETE FROM log WHERE timestamp < ?1",
+                       &[&older_than])?;
+        self.c.execute(
+            "DELETE FROM log
+                 WHERE key IS NOT NULL
+                 AND (SELECT count(*) FROM log AS newer
+                          WHERE newer.key = log.key AND newer.id > log.id)
+                     >= ?1",
+            &[&(max_entries_per_key as i64)])?;
+        Ok(())
+    }
+
+    fn autocrypt_peer(&self, addr: &str) -> Result<Option<AutocryptPeer>> {
+        let r = self.c.query_row(
+            "SELECT last_seen, autocrypt_timestamp, public_key, prefer_encrypt,
+                    gossip_timestamp, gossip_key
+             FROM autocrypt_peers WHERE addr = ?1",
+            &[&addr], |row| AutocryptPeer {
+                last_seen: row.get(0),
+                autocrypt_timestamp: row.get(1),
+                public_key: row.get(2),
+                prefer_encrypt: if row.get::<_, i64>(3) == 1 {
+                    node::AutocryptPreferEncrypt::Mutual
+                } else {
+                    node::AutocryptPreferEncrypt::NoPreference
+                },
+                gossip_timestamp: row.get(4),
+                gossip_key: row.get(5),
+            });
+
+        match r {
+            Ok(peer) => Ok(Some(peer)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn autocrypt_set_peer(&self, addr: &str, peer: &AutocryptPeer)
+                          -> Result<()> {
+        let prefer_encrypt: i64 = match peer.prefer_encrypt {
+            node::AutocryptPreferEncrypt::NoPreference => 0,
+            node::AutocryptPreferEncrypt::Mutual => 1,
+        };
+
+        self.c.execute(
+            "INSERT OR IGNORE INTO autocrypt_peers (addr) VALUES (?1)",
+            &[&addr])?;
+        self.c.execute(
+            "UPDATE autocrypt_peers
+             SET last_seen = ?2, autocrypt_timestamp = ?3,
+                 public_key = ?4, prefer_encrypt = ?5,
+                 gossip_timestamp = ?6, gossip_key = ?7
+             WHERE addr = ?1",
+            &[&addr, &peer.last_seen, &peer.autocrypt_timestamp,
+              &peer.public_key, &prefer_encrypt,
+              &peer.gossip_timestamp, &peer.gossip_key])?;
+        Ok(())
+    }
+}
+
+/* Database schemata and migrations.  */
+
+/* Version 1.  */
+const DB_SCHEMA_1: &'static str = "
+CREATE TABLE version (
+    id INTEGER PRIMARY KEY,
+    version INTEGER);
+
+INSERT INTO version (id, version) VALUES (1, 1);
+
+CREATE TABLE stores (
+    id INTEGER PRIMARY KEY,
+    realm TEXT NOT NULL,
+    network_policy INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (realm, name));
+
+CREATE TABLE bindings (
+    id INTEGER PRIMARY KEY,
+    store INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    key INTEGER NOT NULL,
+
+    created INTEGER NOT NULL,
+    updated INTEGER NULL,
+
+    encryption_count DEFAULT 0,
+    encryption_first INTEGER NULL,
+    encryption_last INTEGER NULL,
+    verification_count DEFAULT 0,
+    verification_first INTEGER NULL,
+    verification_last INTEGER NULL,
+
+    UNIQUE(store, label),
+    FOREIGN KEY (store) REFERENCES stores(id) ON DELETE CASCADE,
+    FOREIGN KEY (key) REFERENCES keys(id) ON DELETE CASCADE);
+
+CREATE TABLE keys (
+    id INTEGER PRIMARY KEY,
+    fingerprint TEXT NOT NULL,
+    key BLOB,
+
+    created INTEGER NOT NULL,
+    updated INTEGER NULL,
+    update_at INTEGER NOT NULL,
+
+    encryption_count DEFAULT 0,
+    encryption_first INTEGER NULL,
+    encryption_last INTEGER NULL,
+    verification_count DEFAULT 0,
+    verification_first INTEGER NULL,
+    verification_last INTEGER NULL,
+
+    UNIQUE (fingerprint));
+
+CREATE TABLE key_by_keyid (
+    id INTEGER PRIMARY KEY,
+    keyid INTEGER NOT NULL,
+    key INTEGER NOT NULL,
+
+    UNIQUE(keyid, key),
+    FOREIGN KEY (key) REFERENCES keys(id) ON DELETE CASCADE);
+
+CREATE TABLE log (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    level INTEGER NOT NULL,
+    store INTEGER NULL,
+    binding INTEGER NULL,
+    key INTEGER NULL,
+    slug TEXT NOT NULL,
+    message TEXT NOT NULL,
+    error TEXT NULL,
+    FOREIGN KEY (store) REFERENCES stores(id) ON DELETE CASCADE,
+    FOREIGN KEY (binding) REFERENCES bindings(id) ON DELETE CASCADE,
+    FOREIGN KEY (key) REFERENCES keys(id) ON DELETE CASCADE);
+";
+
+/* Version 2.  */
+const DB_MIGRATE_1_TO_2: &'static str = "
+CREATE TABLE autocrypt_peers (
+    id INTEGER PRIMARY KEY,
+    addr TEXT NOT NULL,
+
+    last_seen INTEGER NULL,
+    autocrypt_timestamp INTEGER NULL,
+    public_key INTEGER NULL,
+    prefer_encrypt INTEGER NOT NULL DEFAULT 0,
+
+    gossip_timestamp INTEGER NULL,
+    gossip_key INTEGER NULL,
+
+    UNIQUE (addr),
+    FOREIGN KEY (public_key) REFERENCES keys(id) ON DELETE SET NULL,
+    FOREIGN KEY (gossip_key) REFERENCES keys(id) ON DELETE SET NULL);
+
+UPDATE version SET version = 2 WHERE id = 1;
+";
+
+/* Version 3.  */
+const DB_MIGRATE_2_TO_3: &'static str = "
+UPDATE log SET level = CASE WHEN error IS NULL THEN 1 ELSE 3 END;
+
+CREATE INDEX log_key ON log (key);
+
+UPDATE version SET version = 3 WHERE id = 1;
+";
+
+/* Version 4.  */
+const DB_MIGRATE_3_TO_4: &'static str = "
+ALTER TABLE keys ADD COLUMN last_error TEXT NULL;
+
+UPDATE version SET version = 4 WHERE id = 1;
+";
@@ -0,0 +1,578 @@
+//! Change notifications.
+//!
+//! Clients subscribe to changes by handing a `Subscriber` capability
+//! to the server.  Changes are detected by wrapping the storage
+//! backend, hence every change to a key or binding is reported, no
+//! matter whether it was caused by a client or by the housekeeping.
+//!
+//! Every connection to an internal server spawns a new server, each
+//! running in its own thread.  Therefore, subscriptions are recorded
+//! in a hub shared by all servers in this process using the same
+//! home directory.  The hub relays events to the server that holds
+//! the subscriber capability.
+//!
+//! The hub is limited to this process.  Changes made by servers in
+//! other processes are only reported if the storage backend picks
+//! them up, see `Storage::take_changes`.  The directory backend
+//! does, whereas the SQLite backend does not, hence changes made by
+//! other processes sharing the database are not reported.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+use futures::{Future, Stream};
+use futures::sync::mpsc;
+
+use openpgp::{TPK, KeyID, Fingerprint};
+use sequoia_core as core;
+
+use LogLevel;
+use store_protocol_capnp::node;
+use super::{ID, Timestamp, Storage, Rc, Result, Handle};
+use super::log::Selector;
+use super::storage::{
+    StoreInfo, BindingInfo, Stats, UpdateStatus, Usage, LogEntry,
+    AutocryptPeer, Change,
+};
+
+lazy_static! {
+    /// The hubs of this process, indexed by home directory.
+    static ref HUBS: Mutex<HashMap<PathBuf, Weak<Hub>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// A change to a key or binding.
+#[derive(Clone)]
+struct Event {
+    kind: node::EventKind,
+    fingerprint: Fingerprint,
+    /// The changed binding, if any.
+    binding: Option<BindingRef>,
+}
+
+/// Identifies a binding in an event.
+#[derive(Clone)]
+struct BindingRef {
+    store: ID,
+    info: StoreInfo,
+    label: String,
+}
+
+/// A registered subscriber.
+struct Subscriber {
+    id: u64,
+    /// The server holding the subscriber capability.
+    server: u64,
+    /// If set, only changes to bindings in this store are reported.
+    store: Option<ID>,
+}
+
+/// Keeps track of the subscribers of all servers sharing a home
+/// directory.
+struct Hub {
+    state: Mutex<HubState>,
+}
+
+#[derive(Default)]
+struct HubState {
+    next: u64,
+    /// Relays events to the servers, addressed to a subscriber.
+    servers: HashMap<u64, mpsc::UnboundedSender<(u64, Event)>>,
+    subscribers: Vec<Subscriber>,
+}
+
+impl Hub {
+    /// Returns the hub for the given home directory.
+    fn get(home: &Path) -> Arc<Self> {
+        let mut hubs = HUBS.lock().unwrap();
+        if let Some(hub) = hubs.get(home).and_then(|h| h.upgrade()) {
+            return hub;
+        }
+
+        let hub = Arc::new(Hub { state: Mutex::new(Default::default()) });
+        hubs.insert(home.to_path_buf(), Arc::downgrade(&hub));
+        hub
+    }
+
+    fn next_id(state: &mut HubState) -> u64 {
+        state.next += 1;
+        state.next
+    }
+
+    /// Registers a server.
+    fn add_server(&self, relay: mpsc::UnboundedSender<(u64, Event)>) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let id = Self::next_id(&mut state);
+        state.servers.insert(id, relay);
+        id
+    }
+
+    /// Unregisters a server, and all of its subscribers.
+    fn remove_server(&self, server: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.servers.remove(&server);
+        state.subscribers.retain(|s| s.server != server);
+    }
+
+    fn subscribe(&self, server: u64, store: Option<ID>) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let id = Self::next_id(&mut state);
+        state.subscribers.push(Subscriber {
+            id: id,
+            server: server,
+            store: store,
+        });
+        id
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.state.lock().unwrap().subscribers.retain(|s| s.id != id);
+    }
+
+    /// Returns the stores that subscribers are interested in.
+    fn stores(&self) -> Vec<ID> {
+        let mut stores: Vec<ID> = self.state.lock().unwrap().subscribers
+            .iter().filter_map(|s| s.store).collect();
+        stores.sort();
+        stores.dedup();
+        stores
+    }
+
+    /// Relays the event to all interested subscribers.
+    ///
+    /// Subscribers to a store are told about changes to bindings in
+    /// that store.  If `to_pool` is true, subscribers to the common
+    /// key pool are told as well.
+    fn notify(&self, event: &Event, to_pool: bool) {
+        let state = self.state.lock().unwrap();
+        for s in state.subscribers.iter() {
+            let interested = match s.store {
+                None => to_pool,
+                Some(store) =>
+                    event.binding.as_ref().map(|b| b.store == store)
+                    .unwrap_or(false),
+            };
+            if ! interested {
+                continue;
+            }
+
+            if let Some(relay) = state.servers.get(&s.server) {
+                // If the server went away, so did its subscribers.
+                let _ = relay.unbounded_send((s.id, event.clone()));
+            }
+        }
+    }
+}
+
+/// Keeps track of the subscribers of a server.
+pub struct Subscribers {
+    h: Handle,
+    hub: Arc<Hub>,
+    server: u64,
+    clients: RefCell<HashMap<u64, node::subscriber::Client>>,
+}
+
+impl Subscribers {
+    /// Registers a server using the given home directory.
+    ///
+    /// Events relayed by the hub are delivered using `h`.
+    pub fn new(h: Handle, home: &Path) -> Rc<Self> {
+        let hub = Hub::get(home);
+        let (relay, events) = mpsc::unbounded();
+        let server = hub.add_server(relay);
+        let subscribers = Rc::new(Subscribers {
+            h: h.clone(),
+            hub: hub,
+            server: server,
+            clients: RefCell::new(HashMap::new()),
+        });
+
+        // The relay is closed once the server is unregistered.
+        let weak = Rc::downgrade(&subscribers);
+        h.spawn(events.for_each(move |(id, event)| {
+            if let Some(subscribers) = weak.upgrade() {
+                subscribers.deliver(id, &event);
+            }
+            Ok(())
+        }));
+        subscribers
+    }
+
+    /// Registers a subscriber.
+    ///
+    /// If `store` is given, only changes to bindings in that store
+    /// are reported.  The subscriber is unregistered once the
+    /// returned subscription is dropped.
+    pub fn subscribe(this: &Rc<Self>, store: Option<ID>,
+                     client: node::subscriber::Client)
+                     -> SubscriptionServer {
+        let id = this.hub.subscribe(this.server, store);
+        this.clients.borrow_mut().insert(id, client);
+        SubscriptionServer{subscribers: this.clone(), id: id}
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.hub.unsubscribe(id);
+        self.clients.borrow_mut().remove(&id);
+    }
+
+    /// Returns the stores that subscribers are interested in.
+    fn stores(&self) -> Vec<ID> {
+        self.hub.stores()
+    }
+
+    /// Reports the event to all interested subscribers of all
+    /// servers.
+    fn notify(&self, event: &Event, to_pool: bool) {
+        self.hub.notify(event, to_pool);
+    }
+
+    /// Delivers an event to one of our subscribers.
+    fn deliver(&self, id: u64, event: &Event) {
+        let clients = self.clients.borrow();
+        let client = match clients.get(&id) {
+            Some(c) => c,
+            None => return,
+        };
+
+        let mut request = client.notify_request();
+        {
+            let mut e = request.get().init_event();
+            e.set_kind(event.kind);
+            e.set_fingerprint(&event.fingerprint.to_hex());
+            if let Some(ref b) = event.binding {
+                e.set_realm(&b.info.realm);
+                e.set_store(&b.info.name);
+                e.set_label(&b.label);
+            }
+        }
+
+        // Notifications are best-effort.  If the subscriber went
+        // away, its subscription is dropped by the RPC system.
+        self.h.spawn(request.send().promise
+                     .map(|_| ()).map_err(|_| ()));
+    }
+}
+
+impl Drop for Subscribers {
+    fn drop(&mut self) {
+        self.hub.remove_server(self.server);
+    }
+}
+
+/// A subscription.
+///
+/// Dropping the capability cancels the subscription.
+pub struct SubscriptionServer {
+    subscribers: Rc<Subscribers>,
+    id: u64,
+}
+
+impl node::subscription::Server for SubscriptionServer {
+}
+
+impl Drop for SubscriptionServer {
+    fn drop(&mut self) {
+        self.subscribers.unsubscribe(self.id);
+    }
+}
+
+/// Reports changes made through the wrapped storage to the
+/// subscribers.
+///
+/// Failing to report a change does not fail the change.
+pub struct Notifying {
+    s: Rc<Storage>,
+    subscribers: Rc<Subscribers>,
+}
+
+impl Notifying {
+    pub fn new(s: Rc<Storage>, subscribers: Rc<Subscribers>) -> Self {
+        Notifying {
+            s: s,
+            subscribers: subscribers,
+        }
+    }
+
+    /// Describes a change to the given binding.
+    fn binding_event(&self, kind: node::EventKind, id: ID) -> Result<Event> {
+        let binding = self.s.binding_info(id)?;
+        Ok(Event {
+            kind: kind,
+            fingerprint: self.s.key_fingerprint(binding.key)?,
+            binding: Some(BindingRef {
+                store: binding.store,
+                info: self.s.store_info(binding.store)?,
+                label: binding.label,
+            }),
+        })
+    }
+
+    /// Reports the changes made by somebody else that the backend
+    /// picked up.
+    fn relay_changes(&self) {
+        for c in self.s.take_changes() {
+            match c.binding {
+                None => {
+                    if let Ok(id) = self.s.key_lookup(&c.fingerprint) {
+                        self.key_changed(id).unwrap_or(());
+                    }
+                },
+                Some((store, info, label)) =>
+                    self.subscribers.notify(&Event {
+                        kind: c.kind,
+                        fingerprint: c.fingerprint,
+                        binding: Some(BindingRef {
+                            store: store,
+                            info: info,
+                            label: label,
+                        }),
+                    }, true),
+            }
+        }
+    }
+
+    /// Reports the changes picked up by the backend, then returns
+    /// `r`.
+    fn relay<T>(&self, r: T) -> T {
+        self.relay_changes();
+        r
+    }
+
+    /// Reports a change to the given key.
+    ///
+    /// Subscribers to a store are told about every binding in the
+    /// store that refers to the key.
+    fn key_changed(&self, id: ID) -> Result<()> {
+        let fingerprint = self.s.key_fingerprint(id)?;
+        self.subscribers.notify(&Event {
+            kind: node::EventKind::KeyUpdated,
+            fingerprint: fingerprint.clone(),
+            binding: None,
+        }, true);
+
+        for store in self.subscribers.stores() {
+            let info = self.s.store_info(store)?;
+            let mut after = ID::null();
+            while let Ok((binding, b)) = self.s.binding_next(store, after) {
+                after = binding;
+                if b.key != id {
+                    continue;
+                }
+
+                self.subscribers.notify(&Event {
+                    kind: node::EventKind::KeyUpdated,
+                    fingerprint: fingerprint.clone(),
+                    binding: Some(BindingRef {
+                        store: store,
+                        info: info.clone(),
+                        label: b.label,
+                    }),
+                }, false);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for Notifying {
+    fn store_open(&self, realm: &str, name: &str,
+                  network_policy: core::NetworkPolicy)
+                  -> Result<(ID, core::NetworkPolicy)> {
+        self.relay(self.s.store_open(realm, name, network_policy))
+    }
+
+    fn store_info(&self, id: ID) -> Result<StoreInfo> {
+        self.s.store_info(id)
+    }
+
+    fn store_delete(&self, id: ID) -> Result<()> {
+        let mut events = Vec::new();
+        let mut after = ID::null();
+        while let Ok((binding, _)) = self.s.binding_next(id, after) {
+            after = binding;
+            if let Ok(e) =
+                self.binding_event(node::EventKind::BindingDeleted, binding)
+            {
+                events.push(e);
+            }
+        }
+
+        self.s.store_delete(id)?;
+        self.relay_changes();
+        for e in events {
+            self.subscribers.notify(&e, true);
+        }
+        Ok(())
+    }
+
+    fn store_next(&self, realm_prefix: &str, after: ID)
+                  -> Result<(ID, StoreInfo)> {
+        self.relay(self.s.store_next(realm_prefix, after))
+    }
+
+    fn binding_lookup(&self, store: ID, label: &str) -> Result<ID> {
+        self.relay(self.s.binding_lookup(store, label))
+    }
+
+    fn binding_lookup_or_create(&self, store: ID, label: &str, key: ID)
+                                -> Result<(ID, bool)> {
+        let r = self.s.binding_lookup_or_create(store, label, key)?;
+        self.relay_changes();
+        if r.1 {
+            if let Ok(e) =
+                self.binding_event(node::EventKind::BindingAdded, r.0)
+            {
+                self.subscribers.notify(&e, true);
+            }
+        }
+        Ok(r)
+    }
+
+    fn binding_lookup_by_subkeyid(&self, store: ID, keyid: &KeyID)
+                                  -> Result<ID> {
+        self.relay(self.s.binding_lookup_by_subkeyid(store, keyid))
+    }
+
+    fn binding_info(&self, id: ID) -> Result<BindingInfo> {
+        self.s.binding_info(id)
+    }
+
+    fn binding_set_key(&self, id: ID, key: ID) -> Result<()> {
+        self.s.binding_set_key(id, key)?;
+        self.relay_changes();
+        if let Ok(e) = self.binding_event(node::EventKind::BindingRotated, id)
+        {
+            self.subscribers.notify(&e, true);
+        }
+        Ok(())
+    }
+
+    fn binding_delete(&self, id: ID) -> Result<()> {
+        let e = self.binding_event(node::EventKind::BindingDeleted, id);
+        self.s.binding_delete(id)?;
+        self.relay_changes();
+        if let Ok(e) = e {
+            self.subscribers.notify(&e, true);
+        }
+        Ok(())
+    }
+
+    fn binding_stats(&self, id: ID) -> Result<Stats> {
+        self.s.binding_stats(id)
+    }
+
+    fn binding_register(&self, id: ID, usage: Usage, at: Timestamp)
+                        -> Result<()> {
+        self.s.binding_register(id, usage, at)
+    }
+
+    fn binding_next(&self, store: ID, after: ID)
+                    -> Result<(ID, BindingInfo)> {
+        self.relay(self.s.binding_next(store, after))
+    }
+
+    fn key_lookup(&self, fp: &Fingerprint) -> Result<ID> {
+        self.relay(self.s.key_lookup(fp))
+    }
+
+    fn key_lookup_by_keyid(&self, keyid: &KeyID) -> Result<ID> {
+        self.relay(self.s.key_lookup_by_keyid(keyid))
+    }
+
+    fn key_lookup_by_subkeyid(&self, keyid: &KeyID) -> Result<ID> {
+        self.relay(self.s.key_lookup_by_subkeyid(keyid))
+    }
+
+    fn key_lookup_or_create(&self, fp: &Fingerprint) -> Result<ID> {
+        self.s.key_lookup_or_create(fp)
+    }
+
+    fn key_fingerprint(&self, id: ID) -> Result<Fingerprint> {
+        self.s.key_fingerprint(id)
+    }
+
+    fn key_tpk(&self, id: ID) -> Result<Option<Vec<u8>>> {
+        self.relay(self.s.key_tpk(id))
+    }
+
+    fn key_set_tpk(&self, id: ID, tpk: &TPK) -> Result<Vec<u8>> {
+        // Merging an update often does not change the key.
+        let old = self.s.key_tpk(id)?;
+        let blob = self.s.key_set_tpk(id, tpk)?;
+        self.relay_changes();
+        if old.as_ref() != Some(&blob) {
+            self.key_changed(id).unwrap_or(());
+        }
+        Ok(blob)
+    }
+
+    fn key_stats(&self, id: ID) -> Result<Stats> {
+        self.s.key_stats(id)
+    }
+
+    fn key_next(&self, after: ID) -> Result<(ID, Fingerprint)> {
+        self.relay(self.s.key_next(after))
+    }
+
+    fn key_next_update_at(&self, network_policy: core::NetworkPolicy)
+                          -> Option<Timestamp> {
+        self.s.key_next_update_at(network_policy)
+    }
+
+    fn key_need_update(&self, network_policy: core::NetworkPolicy)
+                       -> Result<i32> {
+        self.s.key_need_update(network_policy)
+    }
+
+    fn key_to_update(&self, network_policy: core::NetworkPolicy,
+                     now: Timestamp)
+                     -> Result<(ID, Fingerprint)> {
+        self.s.key_to_update(network_policy, now)
+    }
+
+    fn key_schedule_update(&self, id: ID, update_at: Timestamp)
+                           -> Result<()> {
+        self.s.key_schedule_update(id, update_at)
+    }
+
+    fn key_update_succeeded(&self, id: ID, updated: Timestamp,
+                            update_at: Timestamp)
+                            -> Result<()> {
+        self.s.key_update_succeeded(id, updated, update_at)
+    }
+
+    fn key_update_failed(&self, id: ID, error: &str, update_at: Timestamp)
+                         -> Result<()> {
+        self.s.key_update_failed(id, error, update_at)
+    }
+
+    fn key_update_status(&self, id: ID) -> Result<UpdateStatus> {
+        self.s.key_update_status(id)
+    }
+
+    fn log_append(&self, entry: LogEntry) -> Result<ID> {
+        self.s.log_append(entry)
+    }
+
+    fn log_next(&self, selector: &Selector, min_level: LogLevel, before: ID)
+                -> Result<(ID, LogEntry)> {
+        self.s.log_next(selector, min_level, before)
+    }
+
+    fn log_trim(&self, older_than: Timestamp, max_entries_per_key: usize)
+                -> Result<()> {
+        self.s.log_trim(older_than, max_entries_per_key)
+    }
+
+    fn autocrypt_peer(&self, addr: &str) -> Result<Option<AutocryptPeer>> {
+        self.s.autocrypt_peer(addr)
+    }
+
+    fn autocrypt_set_peer(&self, addr: &str, peer: &AutocryptPeer)
+                          -> Result<()> {
+        self.s.autocrypt_set_peer(addr, peer)
+    }
+}
@@ -1,15 +1,18 @@
 //! Data types for working with `rusqlite`.
 
 use rusqlite;
-use rusqlite::types::{ToSql, ToSqlOutput, FromSql, FromSqlResult, ValueRef};
+use rusqlite::types::{ToSql, ToSqlOutput, FromSql, FromSqlError, FromSqlResult,
+                      ValueRef};
 use std::fmt;
 use std::ops::{Add, Sub};
 use time::{Timespec, Duration, now_utc};
 
+use LogLevel;
+
 /// Represents a row id.
 ///
 /// This is used to represent handles to stored objects.
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ID(i64);
 
 impl fmt::Display for ID {
@@ -52,7 +55,7 @@ impl FromSql for ID {
 
 
 /// A serializable system time.
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct Timestamp(Timespec);
 
 impl Timestamp {
@@ -64,6 +67,11 @@ impl Timestamp {
     pub fn unix(&self) -> i64 {
         self.0.sec
     }
+
+    /// Converts from unix time.
+    pub fn from_unix(t: i64) -> Self {
+        Timestamp(Timespec::new(t, 0))
+    }
 }
 
 impl ToSql for Timestamp {
@@ -86,6 +94,14 @@ impl Add<Duration> for Timestamp {
     }
 }
 
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, other: Duration) -> Timestamp {
+        Timestamp(self.0 - other)
+    }
+}
+
 impl Sub<Timestamp> for Timestamp {
     type Output = Duration;
 
@@ -93,3 +109,21 @@ impl Sub<Timestamp> for Timestamp {
         self.0 - other.0
     }
 }
+
+impl ToSql for LogLevel {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+        Ok(ToSqlOutput::from(*self as i64))
+    }
+}
+
+impl FromSql for LogLevel {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        match value.as_i64()? {
+            0 => Ok(LogLevel::Debug),
+            1 => Ok(LogLevel::Info),
+            2 => Ok(LogLevel::Warning),
+            3 => Ok(LogLevel::Error),
+            n => Err(FromSqlError::OutOfRange(n)),
+        }
+    }
+}
@@ -42,7 +42,9 @@
 //! //     created: Some(Timespec { tv_sec: 1513704042, tv_nsec: 0 }),
 //! //     updated: None,
 //! //     encryption: Stamps { count: 0, first: None, last: None },
-//! //     verification: Stamps { count: 0, first: None, last: None }
+//! //     verification: Stamps { count: 0, first: None, last: None },
+//! //     update_at: Some(Timespec { tv_sec: 1514171108, tv_nsec: 0 }),
+//! //     last_error: None
 //! // }
 //! # Ok(())
 //! # }
@@ -55,7 +57,10 @@ extern crate capnp;
 extern crate capnp_rpc;
 #[macro_use]
 extern crate failure;
+extern crate fs2;
 extern crate futures;
+#[macro_use]
+extern crate lazy_static;
 extern crate rand;
 extern crate rusqlite;
 extern crate time;
@@ -63,8 +68,13 @@ extern crate tokio_core;
 extern crate tokio_io;
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use capnp::capability::Promise;
 use capnp_rpc::rpc_twoparty_capnp::Side;
@@ -96,6 +106,8 @@ use store_protocol_capnp::node;
 
 pub(crate) mod backend;
 
+pub mod autocrypt;
+
 /// Returns the service descriptor.
 #[doc(hidden)]
 pub fn descriptor(c: &Context) -> ipc::Descriptor {
@@ -115,6 +127,104 @@ pub const REALM_CONTACTS: &'static str =
 pub const REALM_SOFTWARE_UPDATES: &'static str =
     "org.sequoia-pgp.software-updates";
 
+/// Storage backends for the store.
+///
+/// The backend is selected per home directory, and is used by all
+/// servers started afterwards.  By default, the store uses a SQLite
+/// database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// Stores everything in a SQLite database.
+    ///
+    /// Ephemeral contexts use an in-memory database.
+    SQLite,
+    /// Stores keys and bindings in a directory.
+    ///
+    /// Every key is stored in its own file, and every store in a file
+    /// listing its bindings.  The directory can be kept in a version
+    /// control system, or shared using a network file system.  Usage
+    /// statistics, the log, and the Autocrypt peer state are only
+    /// kept in memory.
+    Directory,
+    /// Stores everything in memory.
+    ///
+    /// The state is lost once the last server using it exits.
+    Memory,
+}
+
+impl StorageBackend {
+    /// Returns the storage backend configured for the given context.
+    pub fn configured(c: &Context) -> Result<Self> {
+        let mut backend = String::new();
+        match fs::File::open(Self::config_file(c)) {
+            Ok(mut f) => {
+                f.read_to_string(&mut backend)?;
+                backend.trim().parse()
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                Ok(StorageBackend::SQLite),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Configures the storage backend for the given context.
+    ///
+    /// This only affects servers started afterwards.  Existing
+    /// stores are not migrated.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate sequoia_core;
+    /// # extern crate sequoia_store;
+    /// # use sequoia_core::{Context, NetworkPolicy, IPCPolicy};
+    /// # use sequoia_store::*;
+    /// # fn main() { f().unwrap(); }
+    /// # fn f() -> Result<()> {
+    /// # let ctx = Context::configure()
+    /// #     .network_policy(NetworkPolicy::Offline)
+    /// #     .ipc_policy(IPCPolicy::Internal)
+    /// #     .ephemeral().build()?;
+    /// StorageBackend::Memory.configure(&ctx)?;
+    /// assert_eq!(StorageBackend::configured(&ctx)?, StorageBackend::Memory);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn configure(&self, c: &Context) -> Result<()> {
+        fs::create_dir_all(c.home())?;
+        let mut f = fs::File::create(Self::config_file(c))?;
+        writeln!(f, "{}", self)?;
+        Ok(())
+    }
+
+    fn config_file(c: &Context) -> ::std::path::PathBuf {
+        c.home().join("public-key-store.backend")
+    }
+}
+
+impl fmt::Display for StorageBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            &StorageBackend::SQLite => "sqlite",
+            &StorageBackend::Directory => "directory",
+            &StorageBackend::Memory => "memory",
+        })
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sqlite" => Ok(StorageBackend::SQLite),
+            "directory" => Ok(StorageBackend::Directory),
+            "memory" => Ok(StorageBackend::Memory),
+            _ => Err(format_err!("Unknown storage backend {:?}", s)),
+        }
+    }
+}
+
 /// The common key pool.
 pub struct Pool {
 }
@@ -276,6 +386,46 @@ impl Pool {
         Ok(Key::new(Rc::new(RefCell::new(core)), key))
     }
 
+    /// Subscribes to changes of keys and bindings.
+    ///
+    /// Reports changes to every key in the common key pool, and to
+    /// every binding in every store.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate sequoia_openpgp as openpgp;
+    /// # extern crate sequoia_core;
+    /// # extern crate sequoia_store;
+    /// # use openpgp::TPK;
+    /// # use openpgp::parse::Parse;
+    /// # use sequoia_core::{Context, NetworkPolicy, IPCPolicy};
+    /// # use sequoia_store::{Pool, EventKind, Result};
+    /// # fn main() { f().unwrap(); }
+    /// # fn f() -> Result<()> {
+    /// # let ctx = Context::configure()
+    /// #     .network_policy(NetworkPolicy::Offline)
+    /// #     .ipc_policy(IPCPolicy::Internal)
+    /// #     .ephemeral().build()?;
+    /// # let tpk = TPK::from_bytes(
+    /// #     include_bytes!("../../openpgp/tests/data/keys/testy.pgp")).unwrap();
+    /// let mut subscription = Pool::subscribe(&ctx)?;
+    /// Pool::import(&ctx, &tpk)?;
+    /// let event = subscription.next().unwrap();
+    /// assert_eq!(event.kind, EventKind::KeyUpdated);
+    /// assert_eq!(event.fingerprint, tpk.fingerprint());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe(c: &Context) -> Result<Subscription> {
+        let (core, client) = Store::connect(c)?;
+        let core = Rc::new(RefCell::new(core));
+        let (subscriber, events) = Subscription::subscriber();
+        let mut request = client.subscribe_request();
+        request.get().set_subscriber(subscriber);
+        let subscription = make_request!(core.borrow_mut(), request)?;
+        Ok(Subscription::new(core, subscription, events))
+    }
 }
 
 /// A public key store.
@@ -357,8 +507,15 @@ impl Store {
 
     /// Lists all log entries.
     pub fn server_log(c: &Context) -> Result<LogIter> {
+        Self::server_log_filtered(c, LogLevel::Debug)
+    }
+
+    /// Lists all log entries with at least the given level.
+    pub fn server_log_filtered(c: &Context, min_level: LogLevel)
+                               -> Result<LogIter> {
         let (mut core, client) = Self::connect(c)?;
-        let request = client.log_request();
+        let mut request = client.log_request();
+        request.get().set_min_level(min_level.into());
         let iter = make_request!(&mut core, request)?;
         Ok(LogIter{core: Rc::new(RefCell::new(core)), iter: iter})
     }
@@ -552,10 +709,29 @@ impl Store {
 
     /// Lists all log entries related to this store.
     pub fn log(&self) -> Result<LogIter> {
-        let request = self.store.log_request();
+        self.log_filtered(LogLevel::Debug)
+    }
+
+    /// Lists all log entries related to this store with at least
+    /// the given level.
+    pub fn log_filtered(&self, min_level: LogLevel) -> Result<LogIter> {
+        let mut request = self.store.log_request();
+        request.get().set_min_level(min_level.into());
         let iter = make_request!(self.core.borrow_mut(), request)?;
         Ok(LogIter{core: self.core.clone(), iter: iter})
     }
+
+    /// Subscribes to changes of bindings in this store.
+    ///
+    /// Reports bindings being added, rotated, and deleted, as well
+    /// as changes to the keys bound in this store.
+    pub fn subscribe(&self) -> Result<Subscription> {
+        let (subscriber, events) = Subscription::subscriber();
+        let mut request = self.store.subscribe_request();
+        request.get().set_subscriber(subscriber);
+        let subscription = make_request!(self.core.borrow_mut(), request)?;
+        Ok(Subscription::new(self.core.clone(), subscription, events))
+    }
 }
 
 /// Makes a stats request and parses the result.
@@ -574,6 +750,12 @@ macro_rules! make_stats_request {
                     s.get_verification_count(),
                     from_unix(s.get_verification_first()),
                     from_unix(s.get_verification_last())),
+                update_at: from_unix(s.get_update_at()),
+                last_error: if s.has_last_error() {
+                    Some(s.get_last_error()?.into())
+                } else {
+                    None
+                },
             }))
     }}
 }
@@ -630,7 +812,9 @@ impl Binding {
     /// //     created: Some(Timespec { tv_sec: 1513704042, tv_nsec: 0 }),
     /// //     updated: None,
     /// //     encryption: Stamps { count: 0, first: None, last: None },
-    /// //     verification: Stamps { count: 0, first: None, last: None }
+    /// //     verification: Stamps { count: 0, first: None, last: None },
+    /// //     update_at: Some(Timespec { tv_sec: 1514171108, tv_nsec: 0 }),
+    /// //     last_error: None
     /// // }
     /// # Ok(())
     /// # }
@@ -812,7 +996,14 @@ impl Binding {
 
     /// Lists all log entries related to this binding.
     pub fn log(&self) -> Result<LogIter> {
-        let request = self.binding.log_request();
+        self.log_filtered(LogLevel::Debug)
+    }
+
+    /// Lists all log entries related to this binding with at least
+    /// the given level.
+    pub fn log_filtered(&self, min_level: LogLevel) -> Result<LogIter> {
+        let mut request = self.binding.log_request();
+        request.get().set_min_level(min_level.into());
         let iter = make_request!(self.core.borrow_mut(), request)?;
         Ok(LogIter{core: self.core.clone(), iter: iter})
     }
@@ -828,6 +1019,47 @@ impl Binding {
                           request,
                           |l: &str| Ok(l.into()))
     }
+
+    /// Refreshes the key of this binding from the network.
+    ///
+    /// The key is fetched from the key server named in its Preferred
+    /// Key Server subpacket, the Web Key Directories of its email
+    /// addresses, or the SKS keyserver pool, in that order.  The
+    /// store's network policy is honored.
+    ///
+    /// If `now` is true, the key is fetched right away, otherwise it
+    /// is scheduled to be updated by the housekeeping as soon as
+    /// possible.  Returns the updated stats.  Failed updates are not
+    /// errors, they are reflected in `Stats::last_error`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate sequoia_openpgp as openpgp;
+    /// # #[macro_use] extern crate sequoia_core;
+    /// # extern crate sequoia_store;
+    /// # use openpgp::Fingerprint;
+    /// # use sequoia_core::{Context, NetworkPolicy, IPCPolicy};
+    /// # use sequoia_store::*;
+    /// # fn main() { f().unwrap(); }
+    /// # fn f() -> Result<()> {
+    /// # let ctx = Context::configure()
+    /// #     .network_policy(NetworkPolicy::Offline)
+    /// #     .ipc_policy(IPCPolicy::Internal)
+    /// #     .ephemeral().build()?;
+    /// let store = Store::open(&ctx, REALM_CONTACTS, "default")?;
+    /// let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+    /// let binding = store.add("Mister B.", &fp)?;
+    /// let r = binding.refresh(false);
+    /// assert!(r.is_err()); // The store is offline.
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn refresh(&self, now: bool) -> Result<Stats> {
+        let mut request = self.binding.refresh_request();
+        request.get().set_now(now);
+        make_stats_request!(self.core.borrow_mut(), request)
+    }
 }
 
 /// Represents a key in a store.
@@ -918,10 +1150,28 @@ impl Key {
 
     /// Lists all log entries related to this key.
     pub fn log(&self) -> Result<LogIter> {
-        let request = self.key.log_request();
+        self.log_filtered(LogLevel::Debug)
+    }
+
+    /// Lists all log entries related to this key with at least
+    /// the given level.
+    pub fn log_filtered(&self, min_level: LogLevel) -> Result<LogIter> {
+        let mut request = self.key.log_request();
+        request.get().set_min_level(min_level.into());
         let iter = make_request!(self.core.borrow_mut(), request)?;
         Ok(LogIter{core: self.core.clone(), iter: iter})
     }
+
+    /// Refreshes this key from the network.
+    ///
+    /// Like `Binding::refresh`, but as keys may be bound in several
+    /// stores, the strictest network policy of these stores is used.
+    /// Keys that are not bound in any store are not refreshed.
+    pub fn refresh(&self, now: bool) -> Result<Stats> {
+        let mut request = self.key.refresh_request();
+        request.get().set_now(now);
+        make_stats_request!(self.core.borrow_mut(), request)
+    }
 }
 
 
@@ -952,6 +1202,58 @@ pub struct Stats {
 
     /// Records counters and timestamps of verifications.
     pub verification: Stamps,
+
+    /// Records the time the key is updated next.
+    ///
+    /// Keys are updated from the network by the store's
+    /// housekeeping, see `Binding::refresh`.
+    pub update_at: Option<Timespec>,
+
+    /// Records why the last update failed, if it did.
+    pub last_error: Option<String>,
+}
+
+/// Log levels.
+///
+/// Levels are ordered by severity, so that `LogLevel::Debug` is the
+/// least severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Routine events, like successful key updates.
+    Debug,
+    /// Noteworthy events, like new bindings.
+    Info,
+    /// Problems that are expected to resolve themselves, like failed
+    /// key updates.
+    Warning,
+    /// Problems that need attention, like key updates that could
+    /// not be merged.
+    Error,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            &LogLevel::Debug => "debug",
+            &LogLevel::Info => "info",
+            &LogLevel::Warning => "warning",
+            &LogLevel::Error => "error",
+        })
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warning" => Ok(LogLevel::Warning),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format_err!("Unknown log level {:?}", s)),
+        }
+    }
 }
 
 /// Represents a log entry.
@@ -960,6 +1262,9 @@ pub struct Log {
     /// Records the time of the entry.
     pub timestamp: Timespec,
 
+    /// Records the severity of the entry.
+    pub level: LogLevel,
+
     /// Relates the entry to a store.
     pub store: Option<Store>,
 
@@ -982,7 +1287,7 @@ pub struct Log {
 }
 
 impl Log {
-    fn new(timestamp: i64,
+    fn new(timestamp: i64, level: LogLevel,
            store: Option<Store>, binding: Option<Binding>, key: Option<Key>,
            slug: &str, message: &str, error: Option<&str>)
            -> Option<Self> {
@@ -990,6 +1295,7 @@ impl Log {
 
         Some(Log{
             timestamp: timestamp,
+            level: level,
             store: store,
             binding: binding,
             key: key,
@@ -1145,6 +1451,7 @@ impl Iterator for LogIter {
                 self.core.borrow_mut(), request,
                 |r: node::log_iter::entry::Reader|
                 Log::new(r.get_timestamp(),
+                         r.get_level()?.into(),
                          r.get_store().ok().map(
                              |cap| Store::new(self.core.clone(), &"", cap)),
                          r.get_binding().ok().map(
@@ -1163,6 +1470,177 @@ impl Iterator for LogIter {
     }
 }
 
+/* Change notifications.  */
+
+/// Kinds of changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// The key changed, e.g. because an update was merged.
+    KeyUpdated,
+    /// A binding was added.
+    BindingAdded,
+    /// A binding was bound to a different key.
+    BindingRotated,
+    /// A binding was deleted.
+    BindingDeleted,
+}
+
+/// A change to a key or binding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// Describes what changed.
+    pub kind: EventKind,
+
+    /// The fingerprint of the key concerned.
+    ///
+    /// If a binding was rotated, this is the new key.
+    pub fingerprint: Fingerprint,
+
+    /// Relates the event to a binding.
+    ///
+    /// This is the realm and name of the store, and the label of the
+    /// binding.
+    pub binding: Option<(String, String, String)>,
+}
+
+/// Events received from the server.
+#[derive(Default)]
+struct Events {
+    queue: VecDeque<Event>,
+    /// Set once the server released the subscriber, e.g. because
+    /// the connection was lost.
+    closed: bool,
+}
+
+/// Receives events from the server.
+struct SubscriberServer {
+    events: Rc<RefCell<Events>>,
+}
+
+impl Drop for SubscriberServer {
+    fn drop(&mut self) {
+        self.events.borrow_mut().closed = true;
+    }
+}
+
+impl node::subscriber::Server for SubscriberServer {
+    fn notify(&mut self,
+              params: node::subscriber::NotifyParams,
+              _: node::subscriber::NotifyResults)
+              -> Promise<(), capnp::Error> {
+        let event = pry!(pry!(params.get()).get_event());
+        let fingerprint = pry!(event.get_fingerprint());
+        let fingerprint = pry!(Fingerprint::from_hex(fingerprint)
+            .map_err(|e| capnp::Error::failed(e.to_string())));
+        let binding = if event.has_label() {
+            Some((pry!(event.get_realm()).into(),
+                  pry!(event.get_store()).into(),
+                  pry!(event.get_label()).into()))
+        } else {
+            None
+        };
+
+        self.events.borrow_mut().queue.push_back(Event {
+            kind: pry!(event.get_kind()).into(),
+            fingerprint: fingerprint,
+            binding: binding,
+        });
+        Promise::ok(())
+    }
+}
+
+/// A subscription to changes.
+///
+/// Events are received while this subscription, or any object
+/// sharing its connection to the server, waits for the server.
+/// Iterating over the subscription blocks until the next event
+/// arrives.  Iteration ends if the connection to the server is lost.
+/// Dropping the subscription cancels it.
+///
+/// Changes made by other processes are only reported if the storage
+/// backend picks them up, i.e. for `StorageBackend::Directory`, but
+/// not for `StorageBackend::SQLite`.
+pub struct Subscription {
+    core: Rc<RefCell<Core>>,
+    _subscription: node::subscription::Client,
+    events: Rc<RefCell<Events>>,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let events = self.events.borrow();
+        write!(f, "Subscription {{ pending: {}, closed: {} }}",
+               events.queue.len(), events.closed)
+    }
+}
+
+impl Subscription {
+    /// Makes a subscriber capability, and the queue it fills.
+    fn subscriber() -> (node::subscriber::Client, Rc<RefCell<Events>>) {
+        let events = Rc::new(RefCell::new(Events::default()));
+        let subscriber = node::subscriber::ToClient::new(
+            SubscriberServer{events: events.clone()})
+            .into_client::<capnp_rpc::Server>();
+        (subscriber, events)
+    }
+
+    fn new(core: Rc<RefCell<Core>>,
+           subscription: node::subscription::Client,
+           events: Rc<RefCell<Events>>) -> Self {
+        Subscription{core: core, _subscription: subscription, events: events}
+    }
+
+    /// Returns the next event, if one has arrived.
+    ///
+    /// Does not block.  Fails with `Error::ConnectionLost` if the
+    /// connection to the server was lost.
+    pub fn try_next(&mut self) -> Result<Option<Event>> {
+        self.next_timeout(Duration::new(0, 0))
+    }
+
+    /// Returns the next event, waiting at most `timeout` for it.
+    ///
+    /// Returns `Ok(None)` if no event arrived in time, and
+    /// `Error::ConnectionLost` if the connection to the server was
+    /// lost.
+    pub fn next_timeout(&mut self, timeout: Duration)
+                        -> Result<Option<Event>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.events.borrow_mut().queue.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.events.borrow().closed {
+                return Err(Error::ConnectionLost.into());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                // Process what has already arrived.
+                self.core.borrow_mut().turn(Some(Duration::new(0, 0)));
+                return Ok(self.events.borrow_mut().queue.pop_front());
+            }
+            self.core.borrow_mut().turn(Some(deadline - now));
+        }
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.borrow_mut().queue.pop_front() {
+                return Some(event);
+            }
+            if self.events.borrow().closed {
+                return None;
+            }
+            self.core.borrow_mut().turn(None);
+        }
+    }
+}
+
 /* Error handling.  */
 
 /// Results for sequoia-store.
@@ -1214,6 +1692,9 @@ pub enum Error {
     /// A fingerprint is malformed.
     #[fail(display = "Malformed fingerprint")]
     MalformedFingerprint,
+    /// The connection to the server was lost.
+    #[fail(display = "Connection to the server lost")]
+    ConnectionLost,
     /// A `capnp::Error` occurred.
     #[fail(display = "Internal RPC error")]
     RpcError(capnp::Error),
@@ -1241,27 +1722,7 @@ mod test {
     }
 
     #[test]
-    fn store_network_policy_mismatch() {
-        let ctx = core::Context::configure()
-            .ephemeral()
-            .network_policy(core::NetworkPolicy::Offline)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
-        // Create store.
-        Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-
-        let ctx2 = core::Context::configure()
-            .home(ctx.home())
-            .network_policy(core::NetworkPolicy::Encrypted)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
-        let store = Store::open(&ctx2, REALM_CONTACTS, "default");
-        assert_match!(core::Error::NetworkPolicyViolation(_)
-                      = store.err().unwrap().downcast::<core::Error>().unwrap());
-    }
-
-    #[test]
-    fn import_key() {
+    fn ephemeral_store_in_memory() {
         let ctx = core::Context::configure()
             .ephemeral()
             .network_policy(core::NetworkPolicy::Offline)
@@ -1270,229 +1731,593 @@ mod test {
         let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
         let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
         store.import("Mr. McTestface", &tpk).unwrap();
-        let binding = store.lookup("Mr. McTestface").unwrap();
-        let tpk_retrieved = binding.tpk().unwrap();
-        assert_eq!(tpk.fingerprint(), tpk_retrieved.fingerprint());
-    }
 
-    #[test]
-    fn key_not_found() {
-        let ctx = core::Context::configure()
-            .ephemeral()
-            .network_policy(core::NetworkPolicy::Offline)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
+        // Other servers for the same home share the database.
         let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let r = store.lookup("I do not exist");
-        assert_match!(Error::NotFound
-                      = r.err().unwrap().downcast::<Error>().unwrap());
-    }
+        let binding = store.lookup("Mr. McTestface").unwrap();
+        assert_eq!(binding.tpk().unwrap().fingerprint(), tpk.fingerprint());
 
-    #[test]
-    fn add_then_import_wrong_key() {
-        let ctx = core::Context::configure()
+        // But other homes do not.
+        let ctx2 = core::Context::configure()
             .ephemeral()
             .network_policy(core::NetworkPolicy::Offline)
             .ipc_policy(core::IPCPolicy::Internal)
             .build().unwrap();
-        let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        let binding = store.add("Mister B.", &fp).unwrap();
-        let r = binding.import(&tpk);
-        assert_match!(Error::Conflict
-                      = r.err().unwrap().downcast::<Error>().unwrap());
-    }
+        let store = Store::open(&ctx2, REALM_CONTACTS, "default").unwrap();
+        assert!(store.lookup("Mr. McTestface").is_err());
 
-    #[test]
-    fn add_then_add_different_key() {
-        let ctx = core::Context::configure()
-            .ephemeral()
-            .network_policy(core::NetworkPolicy::Offline)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
-        let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let b = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        store.add("Mister B.", &b).unwrap();
-        let c = Fingerprint::from_bytes(b"cccccccccccccccccccc");
-        assert_match!(Error::Conflict
-                      = store.add("Mister B.", &c)
-                      .err().unwrap().downcast::<Error>().unwrap());
+        // Nothing has been written to disk.
+        for ctx in &[ctx, ctx2] {
+            assert_eq!(::std::fs::read_dir(ctx.home()).unwrap().count(), 0);
+        }
     }
 
-    #[test]
-    fn delete_store_twice() {
-        let ctx = core::Context::configure()
-            .ephemeral()
-            .network_policy(core::NetworkPolicy::Offline)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
-        let s0 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let s1 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        s0.delete().unwrap();
-        s1.delete().unwrap();
+    /// Runs the store tests against the given storage backend.
+    macro_rules! store_tests {
+        ($name: ident, $backend: expr) => {
+            mod $name {
+                use super::*;
+
+                /// Builds the context, and selects the storage backend.
+                fn build(config: core::Config) -> core::Context {
+                    let ctx = config.build().unwrap();
+                    $backend.configure(&ctx).unwrap();
+                    ctx
+                }
+
+                #[test]
+                fn store_network_policy_mismatch() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    // Create store.
+                    Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+
+                    let ctx2 = build(core::Context::configure()
+                        .home(ctx.home())
+                        .network_policy(core::NetworkPolicy::Encrypted)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx2, REALM_CONTACTS, "default");
+                    assert_match!(core::Error::NetworkPolicyViolation(_)
+                                  = store.err().unwrap().downcast::<core::Error>().unwrap());
+                }
+
+                #[test]
+                fn import_key() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
+                    store.import("Mr. McTestface", &tpk).unwrap();
+                    let binding = store.lookup("Mr. McTestface").unwrap();
+                    let tpk_retrieved = binding.tpk().unwrap();
+                    assert_eq!(tpk.fingerprint(), tpk_retrieved.fingerprint());
+                }
+
+                #[test]
+                fn key_not_found() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let r = store.lookup("I do not exist");
+                    assert_match!(Error::NotFound
+                                  = r.err().unwrap().downcast::<Error>().unwrap());
+                }
+
+                #[test]
+                fn add_then_import_wrong_key() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    let binding = store.add("Mister B.", &fp).unwrap();
+                    let r = binding.import(&tpk);
+                    assert_match!(Error::Conflict
+                                  = r.err().unwrap().downcast::<Error>().unwrap());
+                }
+
+                #[test]
+                fn log_filtered() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let b = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    store.add("Mister B.", &b).unwrap();
+
+                    let log: Vec<Log> = store.log_filtered(LogLevel::Info)
+                        .unwrap().collect();
+                    assert_eq!(log.len(), 1);
+                    assert_eq!(log[0].level, LogLevel::Info);
+                    assert_eq!(store.log_filtered(LogLevel::Warning)
+                               .unwrap().count(), 0);
+                }
+
+                #[test]
+                fn log_trim() {
+                    use backend::log::Selector;
+                    use backend::storage::{self, LogEntry};
+                    use backend::support::{ID, Timestamp};
+
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let s = storage::open(&ctx).unwrap();
+                    let b = s.key_lookup_or_create(
+                        &Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb"))
+                        .unwrap();
+                    let c = s.key_lookup_or_create(
+                        &Fingerprint::from_bytes(b"cccccccccccccccccccc"))
+                        .unwrap();
+
+                    let now = Timestamp::now();
+                    let entry = |timestamp: Timestamp, key: Option<ID>,
+                                 message: String| LogEntry {
+                        timestamp: timestamp,
+                        level: LogLevel::Info,
+                        store: None,
+                        binding: None,
+                        key: key,
+                        slug: "test".into(),
+                        message: message,
+                        error: None,
+                    };
+                    s.log_append(entry(now - ::time::Duration::weeks(2), None,
+                                       "old".into())).unwrap();
+                    s.log_append(entry(now, None, "new".into())).unwrap();
+                    for i in 0..5 {
+                        s.log_append(entry(now, Some(b), format!("b{}", i)))
+                            .unwrap();
+                        if i < 2 {
+                            s.log_append(entry(now, Some(c),
+                                               format!("c{}", i)))
+                                .unwrap();
+                        }
+                    }
+
+                    s.log_trim(now - ::time::Duration::weeks(1), 3).unwrap();
+
+                    // Returns the messages, newest first.
+                    let messages = |selector: Selector| {
+                        let mut messages = vec![];
+                        let mut n = ID::max();
+                        while let Ok((id, e)) =
+                            s.log_next(&selector, LogLevel::Debug, n)
+                        {
+                            messages.push(e.message);
+                            n = id;
+                        }
+                        messages
+                    };
+                    assert_eq!(messages(Selector::Key(b)),
+                               vec!["b4", "b3", "b2"]);
+                    assert_eq!(messages(Selector::Key(c)), vec!["c1", "c0"]);
+                    assert_eq!(messages(Selector::All).len(), 6);
+                    assert!(! messages(Selector::All)
+                            .contains(&"old".to_string()));
+                }
+
+                #[test]
+                fn refresh_offline() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let b = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    let binding = store.add("Mister B.", &b).unwrap();
+
+                    let stats = binding.stats().unwrap();
+                    assert!(stats.update_at.is_some());
+                    assert!(stats.last_error.is_none());
+
+                    // Offline stores must not be refreshed.
+                    let e = binding.refresh(false).err().unwrap();
+                    assert_match!(core::Error::NetworkPolicyViolation(
+                                      core::NetworkPolicy::Encrypted)
+                                  = e.downcast::<core::Error>().unwrap());
+                }
+
+                #[test]
+                fn refresh_anonymized() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Anonymized)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let b = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    let binding = store.add("Mister B.", &b).unwrap();
+
+                    // Anonymized stores must not be refreshed, nor
+                    // may their keys.
+                    let e = binding.refresh(true).err().unwrap();
+                    assert_match!(core::Error::NetworkPolicyViolation(
+                                      core::NetworkPolicy::Encrypted)
+                                  = e.downcast::<core::Error>().unwrap());
+                    let e = binding.key().unwrap().refresh(true).err().unwrap();
+                    assert_match!(core::Error::NetworkPolicyViolation(
+                                      core::NetworkPolicy::Encrypted)
+                                  = e.downcast::<core::Error>().unwrap());
+                }
+
+                #[test]
+                fn refresh_key_strictest_policy() {
+                    let ctx0 = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Encrypted)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let ctx1 = build(core::Context::configure()
+                        .ephemeral()
+                        .home(ctx0.home())
+                        .network_policy(core::NetworkPolicy::Anonymized)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
+
+                    // Keys not bound in any store are not updated.
+                    let key = Pool::import(&ctx0, &tpk).unwrap();
+                    let e = key.refresh(false).err().unwrap();
+                    assert_match!(core::Error::NetworkPolicyViolation(
+                                      core::NetworkPolicy::Encrypted)
+                                  = e.downcast::<core::Error>().unwrap());
+
+                    // Bound in an encrypted store, the key may be
+                    // updated.
+                    let store0 = Store::open(&ctx0, REALM_CONTACTS, "encrypted")
+                        .unwrap();
+                    store0.import("Mr. McTestface", &tpk).unwrap();
+                    key.refresh(false).unwrap();
+
+                    // Once it is also bound in an anonymized store,
+                    // it must not be.
+                    let store1 = Store::open(&ctx1, REALM_CONTACTS, "anonymized")
+                        .unwrap();
+                    store1.import("Mr. McTestface", &tpk).unwrap();
+                    let e = key.refresh(false).err().unwrap();
+                    assert_match!(core::Error::NetworkPolicyViolation(
+                                      core::NetworkPolicy::Encrypted)
+                                  = e.downcast::<core::Error>().unwrap());
+
+                    // But the binding in the encrypted store still
+                    // may.
+                    store0.lookup("Mr. McTestface").unwrap()
+                        .refresh(false).unwrap();
+                }
+
+                /// Returns the next event, failing if none arrives.
+                fn next_event(subscription: &mut Subscription) -> Event {
+                    subscription.next_timeout(Duration::from_secs(10))
+                        .unwrap().expect("no event received")
+                }
+
+                #[test]
+                fn subscribe() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let mut subscription = store.subscribe().unwrap();
+                    let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
+                    let binding = Some((REALM_CONTACTS.into(), "default".into(),
+                                        "Mr. McTestface".into()));
+
+                    store.import("Mr. McTestface", &tpk).unwrap();
+                    let e = next_event(&mut subscription);
+                    assert_eq!(e.kind, EventKind::BindingAdded);
+                    assert_eq!(e.fingerprint, tpk.fingerprint());
+                    assert_eq!(e.binding, binding);
+                    let e = next_event(&mut subscription);
+                    assert_eq!(e.kind, EventKind::KeyUpdated);
+                    assert_eq!(e.binding, binding);
+
+                    // Importing the same key again changes nothing,
+                    // and changes to other stores are not reported.
+                    store.import("Mr. McTestface", &tpk).unwrap();
+                    let other = Store::open(&ctx, REALM_CONTACTS, "other").unwrap();
+                    other.import("Mr. McTestface", &tpk).unwrap();
+                    store.lookup("Mr. McTestface").unwrap().delete().unwrap();
+                    let e = next_event(&mut subscription);
+                    assert_eq!(e.kind, EventKind::BindingDeleted);
+                    assert_eq!(e.binding, binding);
+                    assert!(subscription.try_next().unwrap().is_none());
+                }
+
+                #[test]
+                fn subscribe_other_connection() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
+
+                    // Every connection talks to a server of its own.
+                    let mut pool = Pool::subscribe(&ctx).unwrap();
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let mut subscription = store.subscribe().unwrap();
+                    let other = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+
+                    other.import("Mr. McTestface", &tpk).unwrap();
+                    let binding = Some((REALM_CONTACTS.into(), "default".into(),
+                                        "Mr. McTestface".into()));
+                    for s in [&mut pool, &mut subscription].iter_mut() {
+                        let e = next_event(s);
+                        assert_eq!(e.kind, EventKind::BindingAdded);
+                        assert_eq!(e.fingerprint, tpk.fingerprint());
+                        assert_eq!(e.binding, binding);
+                    }
+
+                    // Pool subscribers hear about the key, store
+                    // subscribers about the binding.
+                    let e = next_event(&mut pool);
+                    assert_eq!(e.kind, EventKind::KeyUpdated);
+                    assert_eq!(e.binding, None);
+                    let e = next_event(&mut subscription);
+                    assert_eq!(e.kind, EventKind::KeyUpdated);
+                    assert_eq!(e.binding, binding);
+                }
+
+                #[test]
+                fn add_then_add_different_key() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let b = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    store.add("Mister B.", &b).unwrap();
+                    let c = Fingerprint::from_bytes(b"cccccccccccccccccccc");
+                    assert_match!(Error::Conflict
+                                  = store.add("Mister B.", &c)
+                                  .err().unwrap().downcast::<Error>().unwrap());
+                }
+
+                #[test]
+                fn delete_store_twice() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let s0 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let s1 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    s0.delete().unwrap();
+                    s1.delete().unwrap();
+                }
+
+                #[test]
+                fn delete_store_then_use() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let s0 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let s1 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    s0.delete().unwrap();
+                    let binding = s1.lookup("Foobarbaz");
+                    assert_match!(Error::NotFound
+                                  = binding.err().unwrap().downcast::<Error>().unwrap());
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    let binding = s1.add("Mister B.", &fp);
+                    assert_match!(Error::NotFound
+                                  = binding.err().unwrap().downcast::<Error>().unwrap());
+                }
+
+                #[test]
+                fn delete_binding_twice() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    let b0 = store.add("Mister B.", &fp).unwrap();
+                    let b1 = store.lookup("Mister B.").unwrap();
+                    b0.delete().unwrap();
+                    b1.delete().unwrap();
+                }
+
+                #[test]
+                fn delete_binding_then_use() {
+                    let ctx = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    let b0 = store.add("Mister B.", &fp).unwrap();
+                    let b1 = store.lookup("Mister B.").unwrap();
+                    b0.delete().unwrap();
+                    assert_match!(Error::NotFound
+                                  = b1.stats().err().unwrap().downcast::<Error>().unwrap());
+                    assert_match!(Error::NotFound
+                                  = b1.key().err().unwrap().downcast::<Error>().unwrap());
+                }
+
+                fn make_some_stores() -> core::Context {
+                    let ctx0 = build(core::Context::configure()
+                        .ephemeral()
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store = Store::open(&ctx0, REALM_CONTACTS, "default").unwrap();
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    store.add("Mister B.", &fp).unwrap();
+                    store.add("B4", &fp).unwrap();
+
+                    Store::open(&ctx0, REALM_CONTACTS, "another store").unwrap();
+
+                    let ctx1 = build(core::Context::configure()
+                        .home(ctx0.home())
+                        .network_policy(core::NetworkPolicy::Offline)
+                        .ipc_policy(core::IPCPolicy::Internal));
+                    let store =
+                        Store::open(&ctx1, REALM_SOFTWARE_UPDATES, "default").unwrap();
+                    let fp = Fingerprint::from_bytes(b"cccccccccccccccccccc");
+                    store.add("Mister C.", &fp).unwrap();
+
+                    ctx0
+                }
+
+                #[test]
+                fn stats() {
+                    let ctx = make_some_stores();
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    let binding = store.add("Mister B.", &fp).unwrap();
+
+                    let stats0 = binding.stats().unwrap();
+                    assert_match!(Some(_) = stats0.created);
+                    assert_match!(None = stats0.updated);
+                    assert_eq!(stats0.encryption.count, 0);
+                    assert_match!(None = stats0.encryption.first);
+                    assert_match!(None = stats0.encryption.last);
+                    assert_eq!(stats0.verification.count, 0);
+                    assert_match!(None = stats0.verification.first);
+                    assert_match!(None = stats0.verification.last);
+
+                    binding.register_encryption().unwrap();
+                    binding.register_encryption().unwrap();
+                    binding.register_verification().unwrap();
+
+                    let stats1 = binding.stats().unwrap();
+                    assert_match!(Some(_) = stats1.created);
+                    assert_eq!(stats0.created, stats1.created);
+                    assert_match!(None = stats1.updated);
+                    assert_eq!(stats1.encryption.count, 2);
+                    assert_match!(Some(_) = stats1.encryption.first);
+                    assert_match!(Some(_) = stats1.encryption.last);
+                    assert!(stats1.encryption.first <= stats1.encryption.last);
+                    assert_eq!(stats1.verification.count, 1);
+                    assert_match!(Some(_) = stats1.verification.first);
+                    assert_match!(Some(_) = stats1.verification.last);
+                    assert_eq!(stats1.verification.first, stats1.verification.last);
+                }
+
+
+                #[test]
+                fn store_iterator() {
+                    let ctx = make_some_stores();
+                    let mut iter = Store::list(&ctx, REALM_CONTACTS).unwrap();
+                    let (realm, name, network_policy, store) = iter.next().unwrap();
+                    assert_eq!(realm, REALM_CONTACTS);
+                    assert_eq!(name, "default");
+                    assert_eq!(network_policy, core::NetworkPolicy::Offline);
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    store.add("Mister B.", &fp).unwrap();
+                    let (realm, name, network_policy, store) = iter.next().unwrap();
+                    assert_eq!(realm, REALM_CONTACTS);
+                    assert_eq!(name, "another store");
+                    assert_eq!(network_policy, core::NetworkPolicy::Offline);
+                    store.add("Mister B.", &fp).unwrap();
+                    assert!(iter.next().is_none());
+                }
+
+                #[test]
+                fn binding_iterator() {
+                    let ctx = make_some_stores();
+                    let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
+                    let mut iter = store.iter().unwrap();
+                    let (label, fingerprint, binding) = iter.next().unwrap();
+                    let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
+                    assert_eq!(label, "Mister B.");
+                    assert_eq!(fingerprint, fp);
+                    binding.stats().unwrap();
+                    let (label, fingerprint, binding) = iter.next().unwrap();
+                    assert_eq!(label, "B4");
+                    assert_eq!(fingerprint, fp);
+                    binding.stats().unwrap();
+                    assert!(iter.next().is_none());
+                }
+
+                #[test]
+                fn key_iterator() {
+                    let ctx = make_some_stores();
+                    let mut iter = Store::list_keys(&ctx).unwrap();
+                    let (fingerprint, key) = iter.next().unwrap();
+                    assert_eq!(fingerprint, Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb"));
+                    key.stats().unwrap();
+                    let (fingerprint, key) = iter.next().unwrap();
+                    assert_eq!(fingerprint, Fingerprint::from_bytes(b"cccccccccccccccccccc"));
+                    key.stats().unwrap();
+                    assert!(iter.next().is_none());
+                }
+            }
+        }
     }
 
-    #[test]
-    fn delete_store_then_use() {
-        let ctx = core::Context::configure()
-            .ephemeral()
-            .network_policy(core::NetworkPolicy::Offline)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
-        let s0 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let s1 = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        s0.delete().unwrap();
-        let binding = s1.lookup("Foobarbaz");
-        assert_match!(Error::NotFound
-                      = binding.err().unwrap().downcast::<Error>().unwrap());
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        let binding = s1.add("Mister B.", &fp);
-        assert_match!(Error::NotFound
-                      = binding.err().unwrap().downcast::<Error>().unwrap());
-    }
+    store_tests!(sqlite, StorageBackend::SQLite);
+    store_tests!(directory, StorageBackend::Directory);
+    store_tests!(memory, StorageBackend::Memory);
 
     #[test]
-    fn delete_binding_twice() {
+    fn subscribe_directory_changes() {
         let ctx = core::Context::configure()
             .ephemeral()
             .network_policy(core::NetworkPolicy::Offline)
             .ipc_policy(core::IPCPolicy::Internal)
             .build().unwrap();
+        StorageBackend::Directory.configure(&ctx).unwrap();
         let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        let b0 = store.add("Mister B.", &fp).unwrap();
-        let b1 = store.lookup("Mister B.").unwrap();
-        b0.delete().unwrap();
-        b1.delete().unwrap();
+        let mut subscription = store.subscribe().unwrap();
+        let tpk = TPK::from_bytes(bytes!("testy.pgp")).unwrap();
+        let binding = Some((REALM_CONTACTS.into(), "default".into(),
+                            "Mr. McTestface".into()));
+
+        // Somebody else binds a label.
+        let path = ctx.home().join("public-key-store").join("stores")
+            .join(REALM_CONTACTS).join("default");
+        let mut content = String::new();
+        ::std::fs::File::open(&path).unwrap()
+            .read_to_string(&mut content).unwrap();
+        content.push_str(&format!("binding {} Mr.%20McTestface\n",
+                                  tpk.fingerprint().to_hex()));
+        ::std::fs::write(&path, content).unwrap();
+        let _ = ::std::fs::remove_file(ctx.home().join("public-key-store")
+                                       .join(".serial"));
+
+        // The change is picked up by the next lookup, and reported.
+        store.lookup("Mr. McTestface").unwrap();
+        let e = subscription.next_timeout(Duration::from_secs(10))
+            .unwrap().expect("no event received");
+        assert_eq!(e.kind, EventKind::BindingAdded);
+        assert_eq!(e.fingerprint, tpk.fingerprint());
+        assert_eq!(e.binding, binding);
+
+        // Somebody else deletes the store.
+        ::std::fs::remove_file(&path).unwrap();
+        let _ = ::std::fs::remove_file(ctx.home().join("public-key-store")
+                                       .join(".serial"));
+        assert!(store.lookup("Mr. McTestface").is_err());
+        let e = subscription.next_timeout(Duration::from_secs(10))
+            .unwrap().expect("no event received");
+        assert_eq!(e.kind, EventKind::BindingDeleted);
+        assert_eq!(e.binding, binding);
     }
 
     #[test]
-    fn delete_binding_then_use() {
+    fn storage_backend_configuration() {
         let ctx = core::Context::configure()
             .ephemeral()
             .network_policy(core::NetworkPolicy::Offline)
             .ipc_policy(core::IPCPolicy::Internal)
             .build().unwrap();
-        let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        let b0 = store.add("Mister B.", &fp).unwrap();
-        let b1 = store.lookup("Mister B.").unwrap();
-        b0.delete().unwrap();
-        assert_match!(Error::NotFound
-                      = b1.stats().err().unwrap().downcast::<Error>().unwrap());
-        assert_match!(Error::NotFound
-                      = b1.key().err().unwrap().downcast::<Error>().unwrap());
-    }
-
-    fn make_some_stores() -> core::Context {
-        let ctx0 = core::Context::configure()
-            .ephemeral()
-            .network_policy(core::NetworkPolicy::Offline)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
-        let store = Store::open(&ctx0, REALM_CONTACTS, "default").unwrap();
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        store.add("Mister B.", &fp).unwrap();
-        store.add("B4", &fp).unwrap();
-
-        Store::open(&ctx0, REALM_CONTACTS, "another store").unwrap();
-
-        let ctx1 = core::Context::configure()
-            .home(ctx0.home())
-            .network_policy(core::NetworkPolicy::Offline)
-            .ipc_policy(core::IPCPolicy::Internal)
-            .build().unwrap();
-        let store =
-            Store::open(&ctx1, REALM_SOFTWARE_UPDATES, "default").unwrap();
-        let fp = Fingerprint::from_bytes(b"cccccccccccccccccccc");
-        store.add("Mister C.", &fp).unwrap();
-
-        ctx0
-    }
-
-    #[test]
-    fn stats() {
-        let ctx = make_some_stores();
-        let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        let binding = store.add("Mister B.", &fp).unwrap();
-
-        let stats0 = binding.stats().unwrap();
-        assert_match!(Some(_) = stats0.created);
-        assert_match!(None = stats0.updated);
-        assert_eq!(stats0.encryption.count, 0);
-        assert_match!(None = stats0.encryption.first);
-        assert_match!(None = stats0.encryption.last);
-        assert_eq!(stats0.verification.count, 0);
-        assert_match!(None = stats0.verification.first);
-        assert_match!(None = stats0.verification.last);
-
-        binding.register_encryption().unwrap();
-        binding.register_encryption().unwrap();
-        binding.register_verification().unwrap();
-
-        let stats1 = binding.stats().unwrap();
-        assert_match!(Some(_) = stats1.created);
-        assert_eq!(stats0.created, stats1.created);
-        assert_match!(None = stats1.updated);
-        assert_eq!(stats1.encryption.count, 2);
-        assert_match!(Some(_) = stats1.encryption.first);
-        assert_match!(Some(_) = stats1.encryption.last);
-        assert!(stats1.encryption.first <= stats1.encryption.last);
-        assert_eq!(stats1.verification.count, 1);
-        assert_match!(Some(_) = stats1.verification.first);
-        assert_match!(Some(_) = stats1.verification.last);
-        assert_eq!(stats1.verification.first, stats1.verification.last);
-    }
-
-
-    #[test]
-    fn store_iterator() {
-        let ctx = make_some_stores();
-        let mut iter = Store::list(&ctx, REALM_CONTACTS).unwrap();
-        let (realm, name, network_policy, store) = iter.next().unwrap();
-        assert_eq!(realm, REALM_CONTACTS);
-        assert_eq!(name, "default");
-        assert_eq!(network_policy, core::NetworkPolicy::Offline);
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        store.add("Mister B.", &fp).unwrap();
-        let (realm, name, network_policy, store) = iter.next().unwrap();
-        assert_eq!(realm, REALM_CONTACTS);
-        assert_eq!(name, "another store");
-        assert_eq!(network_policy, core::NetworkPolicy::Offline);
-        store.add("Mister B.", &fp).unwrap();
-        assert!(iter.next().is_none());
-    }
-
-    #[test]
-    fn binding_iterator() {
-        let ctx = make_some_stores();
-        let store = Store::open(&ctx, REALM_CONTACTS, "default").unwrap();
-        let mut iter = store.iter().unwrap();
-        let (label, fingerprint, binding) = iter.next().unwrap();
-        let fp = Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb");
-        assert_eq!(label, "Mister B.");
-        assert_eq!(fingerprint, fp);
-        binding.stats().unwrap();
-        let (label, fingerprint, binding) = iter.next().unwrap();
-        assert_eq!(label, "B4");
-        assert_eq!(fingerprint, fp);
-        binding.stats().unwrap();
-        assert!(iter.next().is_none());
-    }
-
-    #[test]
-    fn key_iterator() {
-        let ctx = make_some_stores();
-        let mut iter = Store::list_keys(&ctx).unwrap();
-        let (fingerprint, key) = iter.next().unwrap();
-        assert_eq!(fingerprint, Fingerprint::from_bytes(b"bbbbbbbbbbbbbbbbbbbb"));
-        key.stats().unwrap();
-        let (fingerprint, key) = iter.next().unwrap();
-        assert_eq!(fingerprint, Fingerprint::from_bytes(b"cccccccccccccccccccc"));
-        key.stats().unwrap();
-        assert!(iter.next().is_none());
+        assert_eq!(StorageBackend::configured(&ctx).unwrap(),
+                   StorageBackend::SQLite);
+        for backend in &[StorageBackend::Directory, StorageBackend::Memory,
+                         StorageBackend::SQLite] {
+            backend.configure(&ctx).unwrap();
+            assert_eq!(&StorageBackend::configured(&ctx).unwrap(), backend);
+            assert_eq!(&backend.to_string().parse::<StorageBackend>()
+                       .unwrap(), backend);
+        }
+        assert!("postgres".parse::<StorageBackend>().is_err());
     }
 }
-
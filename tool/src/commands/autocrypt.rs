@@ -0,0 +1,160 @@
+use failure;
+use clap::ArgMatches;
+use rpassword;
+use std::fs;
+use std::io::Write;
+use std::str;
+
+use openpgp::TPK;
+use openpgp::armor;
+use openpgp::autocrypt::AutocryptSetupMessage;
+use openpgp::crypto::Password;
+use openpgp::parse::Parse;
+use openpgp::serialize::Serialize;
+
+use ::{create_or_stdout, open_or_stdin};
+
+/// The boundary of the multipart/mixed setup message.
+const BOUNDARY: &str = "Y6fyGi9SoGeH8WwRaEdC6bbBcYOedDzrQ";
+
+/// Writes an Autocrypt Setup Message.
+///
+/// The message is a complete MIME message as described in [Section
+/// 4.4.1 of the Autocrypt Level 1 specification].  The generated
+/// passcode is printed on stderr.
+///
+///   [Section 4.4.1 of the Autocrypt Level 1 specification]: https://autocrypt.org/level1.html#autocrypt-setup-message
+pub fn export_setup(m: &ArgMatches, force: bool) -> failure::Fallible<()> {
+    let input = open_or_stdin(m.value_of("input"))?;
+    let tpk = TPK::from_reader(input)?;
+    if ! tpk.is_tsk() {
+        return Err(failure::err_msg(
+            "The key does not contain any secret key material"));
+    }
+
+    let addr = m.value_of("address").map(|a| a.to_string())
+        .or_else(|| {
+            if let Some(Ok(Some(a))) =
+                tpk.userids().nth(0).map(|u| u.userid().address())
+            {
+                Some(a)
+            } else {
+                None
+            }
+        })
+        .ok_or(failure::err_msg(
+            "No well-formed primary userid found, use \
+             --address to specify one"))?;
+
+    let mut asm = AutocryptSetupMessage::new(tpk)
+        .set_prefer_encrypt(
+            m.value_of("prefer-encrypt").expect("has default"));
+    let mut armored = Vec::new();
+    asm.serialize(&mut armored)?;
+    let armored = str::from_utf8(&armored)?;
+
+    let mut output = create_or_stdout(m.value_of("output"), force)?;
+    write!(output, "\
+From: <{addr}>\r
+To: <{addr}>\r
+Subject: Autocrypt Setup Message\r
+Autocrypt-Setup-Message: v1\r
+MIME-Version: 1.0\r
+Content-Type: multipart/mixed; boundary=\"{boundary}\"\r
+\r
+--{boundary}\r
+Content-Type: text/plain; charset=utf-8\r
+\r
+This message contains all information to transfer your Autocrypt\r
+settings along with your secret key securely from your original\r
+device.\r
+\r
+To set up your new device for Autocrypt, please follow the\r
+instructions that should be presented by your new device.\r
+\r
+You can keep this message and use it as a backup for your secret\r
+key.  If you want to do this, you should write down the passcode\r
+and store it securely.\r
+\r
+--{boundary}\r
+Content-Type: application/autocrypt-setup\r
+Content-Disposition: attachment; filename=\"autocrypt-setup-message.html\"\r
+\r
+<html><body>\r
+<p>This is the Autocrypt setup file used to transfer settings and\r
+keys between clients.  You can decrypt it using the passcode\r
+shown when this file was created, and import it into your mail\r
+client.</p>\r
+<pre>\r
+{armored}</pre>\r
+</body></html>\r
+--{boundary}--\r
+",
+           addr = addr,
+           boundary = BOUNDARY,
+           armored = armored.replace("\n", "\r\n"))?;
+
+    let passcode = asm.passcode().expect("generated by serialize");
+    eprintln!("Passcode: {}", str::from_utf8(&passcode[..])?);
+    Ok(())
+}
+
+/// Reads an Autocrypt Setup Message, and writes the contained key.
+///
+/// The input can either be the whole setup message, or just the
+/// ASCII-armored payload.  The user is prompted for the passcode,
+/// unless it is read from a file.
+pub fn import_setup(m: &ArgMatches, force: bool) -> failure::Fallible<()> {
+    let input = open_or_stdin(m.value_of("input"))?;
+    let mut asm = AutocryptSetupMessage::from_reader(input)?;
+
+    let format = asm.passcode_format().map(|f| f.to_string());
+    let prompt = match (format.as_ref().map(|f| &f[..]),
+                        asm.passcode_begin()) {
+        (Some(f), Some(b)) =>
+            format!("Enter the passcode ({}, starts with {}): ", f, b),
+        (Some(f), None) => format!("Enter the passcode ({}): ", f),
+        (None, Some(b)) => format!("Enter the passcode (starts with {}): ", b),
+        (None, None) => "Enter the passcode: ".into(),
+    };
+
+    let mut passcode = if let Some(f) = m.value_of("passcode-file") {
+        fs::read_to_string(f)?.trim().to_string()
+    } else {
+        rpassword::read_password_from_tty(Some(&prompt))?
+    };
+    if format.as_ref().map(|f| f == "numeric9x4").unwrap_or(false) {
+        passcode = normalize_numeric9x4(&passcode)?;
+    }
+
+    asm.decrypt(&Password::from(passcode))
+        .map_err(|_| failure::err_msg("Failed to decrypt the setup message, \
+                                       is the passcode correct?"))?;
+    let asm = asm.parse()?;
+
+    eprintln!("Prefer-Encrypt: {}",
+              asm.prefer_encrypt().unwrap_or("nopreference"));
+
+    let output = create_or_stdout(m.value_of("output"), force)?;
+    let mut output = armor::Writer::new(output, armor::Kind::SecretKey, &[])?;
+    asm.into_tpk().as_tsk().serialize(&mut output)?;
+    Ok(())
+}
+
+/// Normalizes a passcode in the numeric9x4 format.
+///
+/// Users may omit the dashes, or use spaces instead.
+fn normalize_numeric9x4(passcode: &str) -> failure::Fallible<String> {
+    let digits = passcode.chars()
+        .filter(|c| ! (c.is_whitespace() || *c == '-'))
+        .collect::<Vec<char>>();
+    if digits.len() != 36 || ! digits.iter().all(|c| c.is_ascii_digit()) {
+        return Err(failure::err_msg(
+            "Malformed passcode: expected 36 digits"));
+    }
+
+    Ok(digits.chunks(4)
+       .map(|c| c.iter().collect::<String>())
+       .collect::<Vec<String>>()
+       .join("-"))
+}
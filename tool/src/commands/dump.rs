@@ -10,7 +10,7 @@ use openpgp::{Packet, Result};
 use openpgp::packet::ctb::CTB;
 use openpgp::packet::{Header, BodyLength, Signature};
 use openpgp::packet::signature::subpacket::{Subpacket, SubpacketValue};
-use openpgp::crypto::{SessionKey, s2k::S2K};
+use openpgp::crypto::{SessionKey, s2k::{S2K, GNUExtension}};
 use openpgp::parse::{map::Map, Parse, PacketParserResult};
 
 use super::TIMEFMT;
@@ -566,6 +566,10 @@ impl PacketDumper {
                 }
             },
 
+            SED(_) => {
+                writeln!(output, "Symmetrically Encrypted Data Packet")?;
+            },
+
             SEIP(ref s) => {
                 writeln!(output, "Encrypted and Integrity Protected Data Packet")?;
                 writeln!(output, "{}  Version: {}", i, s.version())?;
@@ -741,6 +745,22 @@ impl PacketDumper {
                 writeln!(output, "{}    Salt: {}", i, hex::encode(salt))?;
                 writeln!(output, "{}    Iterations: {}", i, iterations)?;
             },
+            GNU { hash, ref extension } => {
+                writeln!(output, "GNU")?;
+                writeln!(output, "{}    Hash: {}", i, hash)?;
+                match extension {
+                    GNUExtension::Dummy =>
+                        writeln!(output, "{}    Extension: gnu-dummy", i)?,
+                    GNUExtension::DivertToCard { ref serial } => {
+                        writeln!(output, "{}    Extension: gnu-divert-to-card",
+                                 i)?;
+                        writeln!(output, "{}    Card serial: {}", i,
+                                 hex::encode(serial))?;
+                    },
+                    GNUExtension::Unknown(n) =>
+                        writeln!(output, "{}    Extension: Unknown({})", i, n)?,
+                }
+            },
             Private(n) =>
                 writeln!(output, "Private({})", n)?,
             Unknown(n) =>
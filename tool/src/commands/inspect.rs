@@ -49,7 +49,7 @@ pub fn inspect(m: &clap::ArgMatches, output: &mut io::Write)
             Packet::Literal(_) => {
                 pp.by_ref().take(40).read_to_end(&mut literal_prefix)?;
             },
-            Packet::SEIP(_) | Packet::AED(_) => {
+            Packet::SED(_) | Packet::SEIP(_) | Packet::AED(_) => {
                 encrypted = true;
             },
             _ => (),
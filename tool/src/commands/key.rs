@@ -12,9 +12,13 @@ pub fn generate(m: &ArgMatches, force: bool) -> failure::Fallible<()> {
     let mut builder = TPKBuilder::new();
 
     // User ID
-    match m.value_of("userid") {
-        Some(uid) => { builder = builder.add_userid(uid); }
-        None => {
+    match (m.value_of("userid"), m.value_of("email")) {
+        (Some(uid), _) => { builder = builder.add_userid(uid); }
+        (None, Some(email)) => {
+            builder = builder.add_userid_from_components(
+                m.value_of("name"), m.value_of("comment"), email)?;
+        }
+        (None, None) => {
             eprintln!("No user ID given, using direct key signature");
         }
     }
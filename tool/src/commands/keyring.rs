@@ -0,0 +1,69 @@
+use failure::{self, ResultExt};
+use clap::ArgMatches;
+
+use openpgp::{Fingerprint, KeyID};
+use openpgp::armor;
+use openpgp::serialize::Serialize;
+use openpgp::tpk::KeyringIndex;
+
+use ::create_or_stdout;
+
+/// Returns the index file to use for the given keyring.
+fn index_path(m: &ArgMatches, keyring: &str) -> String {
+    m.value_of("index").map(|i| i.to_string())
+        .unwrap_or_else(|| format!("{}.index", keyring))
+}
+
+/// Builds an index for a keyring.
+///
+/// An existing index is overwritten.
+pub fn index(m: &ArgMatches) -> failure::Fallible<()> {
+    let keyring = m.value_of("keyring").unwrap();
+    let index = KeyringIndex::build(keyring)
+        .context(format!("Failed to index {:?}", keyring))?;
+    index.save(index_path(m, keyring))
+        .context("Failed to write the index")?;
+    Ok(())
+}
+
+/// Looks up keys using a keyring's index.
+///
+/// The query is either a fingerprint, a key ID, or an email address.
+pub fn lookup(m: &ArgMatches, force: bool) -> failure::Fallible<()> {
+    let keyring = m.value_of("keyring").unwrap();
+    let index = KeyringIndex::open(keyring, index_path(m, keyring))
+        .context("Failed to open the index, try 'sq keyring index'")?;
+
+    let query = m.value_of("query").unwrap();
+    let tpks = if query.contains('@') {
+        index.lookup_email(query)?
+    } else {
+        let hex: String =
+            query.chars().filter(|c| ! c.is_whitespace()).collect();
+        match hex.len() {
+            16 => index.lookup_keyid(&KeyID::from_hex(&hex)?)?,
+            // V4 and V5 fingerprints.
+            40 | 64 =>
+                index.lookup_fingerprint(&Fingerprint::from_hex(&hex)?)?,
+            _ => return Err(failure::err_msg(
+                format!("Malformed query {:?}: expected a fingerprint, \
+                         a key ID, or an email address", query))),
+        }
+    };
+
+    if tpks.is_empty() {
+        return Err(failure::err_msg(format!("No key found for {:?}", query)));
+    }
+
+    let mut output = create_or_stdout(m.value_of("output"), force)?;
+    let mut output = if ! m.is_present("binary") {
+        Box::new(armor::Writer::new(&mut output, armor::Kind::PublicKey, &[])?)
+    } else {
+        output
+    };
+
+    for tpk in tpks {
+        tpk.serialize(&mut output)?;
+    }
+    Ok(())
+}
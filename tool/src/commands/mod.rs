@@ -34,6 +34,8 @@ pub use self::dump::dump;
 mod inspect;
 pub use self::inspect::inspect;
 pub mod key;
+pub mod keyring;
+pub mod autocrypt;
 
 const TIMEFMT: &'static str = "%Y-%m-%dT%H:%M";
 
@@ -307,10 +309,15 @@ impl<'a> VerificationHelper for VHelper<'a> {
             match layer {
                 MessageLayer::Compression { algo } =>
                     eprintln!("Compressed using {}", algo),
-                MessageLayer::Encryption { sym_algo, aead_algo } =>
+                MessageLayer::Encryption {
+                    sym_algo, aead_algo, integrity_protected,
+                } =>
                     if let Some(aead_algo) = aead_algo {
                         eprintln!("Encrypted and protected using {}/{}",
                                   sym_algo, aead_algo);
+                    } else if ! integrity_protected {
+                        eprintln!("Encrypted using {}, \
+                                   WITHOUT integrity protection", sym_algo);
                     } else {
                         eprintln!("Encrypted using {}", sym_algo);
                     },
@@ -423,6 +430,12 @@ pub fn store_print_stats(store: &store::Store, label: &str) -> Result<()> {
         print_stamps(&st.encryption)?;
         print!("  Verified ");
         print_stamps(&st.verification)?;
+        if let Some(t) = st.update_at {
+            println!("  Next update: {}", tm2str(&time::at(t)));
+        }
+        if let Some(ref e) = st.last_error {
+            println!("  Last update failed: {}", e);
+        }
         Ok(())
     }
 
@@ -32,6 +32,7 @@
 //!     help         Prints this message or the help of the given subcommand(s)
 //!     inspect      Inspects a sequence of OpenPGP packets
 //!     key          Manipulates keys
+//!     keyring      Indexes keyrings for fast lookups
 //!     list         Lists key stores and known keys
 //!     packet       OpenPGP Packet manipulation
 //! ```
@@ -151,6 +152,7 @@
 //!     import    Imports a key
 //!     list      Lists keys in the store
 //!     log       Lists the keystore log
+//!     refresh   Refreshes keys from the network
 //!     stats     Get stats for the given label
 //! ```
 //!
@@ -244,16 +246,38 @@
 //! Lists the keystore log
 //!
 //! USAGE:
-//!     sq store log [LABEL]
+//!     sq store log [OPTIONS] [LABEL]
 //!
 //! FLAGS:
 //!     -h, --help       Prints help information
 //!     -V, --version    Prints version information
 //!
+//! OPTIONS:
+//!         --level <LEVEL>    List only messages with at least this level [default: info]  [possible values: debug, info,
+//!                            warning, error]
+//!
 //! ARGS:
 //!     <LABEL>    List messages related to this label
 //! ```
 //!
+//! ### Subcommand store refresh
+//!
+//! ```text
+//! Refreshes keys from the network
+//!
+//! USAGE:
+//!     sq store refresh [FLAGS] <--label <LABEL>|--all>
+//!
+//! FLAGS:
+//!         --all        Refresh all keys in the store
+//!     -h, --help       Prints help information
+//!         --now        Refresh right away instead of scheduling the refresh
+//!     -V, --version    Prints version information
+//!
+//! OPTIONS:
+//!         --label <LABEL>    Refresh the key with this label
+//! ```
+//!
 //! ### Subcommand store stats
 //!
 //! ```text
@@ -342,7 +366,9 @@
 //! SUBCOMMANDS:
 //!     decode           Converts Autocrypt-encoded keys to OpenPGP TPKs
 //!     encode-sender    Encodes the senders' OpenPGP TPKs into an Autocrypt header
+//!     export-setup     Creates an Autocrypt Setup Message for transferring a secret key to another mail client
 //!     help             Prints this message or the help of the given subcommand(s)
+//!     import-setup     Extracts the secret key from an Autocrypt Setup Message
 //! ```
 //!
 //! ### Subcommand autocrypt decode
@@ -386,6 +412,48 @@
 //!     <FILE>    Sets the input file to use
 //! ```
 //!
+//! ### Subcommand autocrypt export-setup
+//!
+//! ```text
+//! Creates an Autocrypt Setup Message for transferring a secret key to another mail client
+//!
+//! USAGE:
+//!     sq autocrypt export-setup [OPTIONS] [FILE]
+//!
+//! FLAGS:
+//!     -h, --help       Prints help information
+//!     -V, --version    Prints version information
+//!
+//! OPTIONS:
+//!         --address <address>                  Sets the address the message is sent to.  [default: primary userid]
+//!     -o, --output <FILE>                      Sets the output file to use
+//!         --prefer-encrypt <prefer-encrypt>    Sets the prefer-encrypt attribute [default: nopreference]  [possible
+//!                                              values: nopreference, mutual]
+//!
+//! ARGS:
+//!     <FILE>    Sets the input file to use
+//! ```
+//!
+//! ### Subcommand autocrypt import-setup
+//!
+//! ```text
+//! Extracts the secret key from an Autocrypt Setup Message
+//!
+//! USAGE:
+//!     sq autocrypt import-setup [OPTIONS] [FILE]
+//!
+//! FLAGS:
+//!     -h, --help       Prints help information
+//!     -V, --version    Prints version information
+//!
+//! OPTIONS:
+//!     -o, --output <FILE>           Sets the output file to use
+//!         --passcode-file <FILE>    Reads the passcode from FILE instead of prompting for it
+//!
+//! ARGS:
+//!     <FILE>    Sets the input file to use
+//! ```
+//!
 //! ## Subcommand dearmor
 //!
 //! ```text
@@ -482,12 +550,75 @@
 //!                                          values: transport, rest, all]
 //!     -c, --cipher-suite <CIPHER-SUITE>    Cryptographic algorithms used for the key. [default: rsa3k]  [possible values:
 //!                                          rsa3k, cv25519]
+//!         --comment <COMMENT>              Comment used in the primary user ID
+//!         --email <EMAIL>                  Email address used in the primary user ID.  The user ID is constructed from
+//!                                          the name, comment, and email address, quoting them as necessary.
 //!     -e, --export <OUTFILE>               Exports the key instead of saving it in the store
+//!         --name <NAME>                    Name used in the primary user ID
 //!         --rev-cert <FILE or ->           Sets the output file for the revocation certificate. Default is <OUTFILE>.rev,
 //!                                          mandatory if OUTFILE is '-'.
 //!     -u, --userid <EMAIL>                 Primary user ID
 //! ```
 //!
+//! ## Subcommand keyring
+//!
+//! ```text
+//! Indexes keyrings for fast lookups
+//!
+//! USAGE:
+//!     sq keyring [SUBCOMMAND]
+//!
+//! FLAGS:
+//!     -h, --help       Prints help information
+//!     -V, --version    Prints version information
+//!
+//! SUBCOMMANDS:
+//!     help      Prints this message or the help of the given subcommand(s)
+//!     index     Builds an index for a keyring
+//!     lookup    Looks up keys in an indexed keyring
+//! ```
+//!
+//! ### Subcommand keyring index
+//!
+//! ```text
+//! Builds an index for a keyring
+//!
+//! USAGE:
+//!     sq keyring index [OPTIONS] <KEYRING>
+//!
+//! FLAGS:
+//!     -h, --help       Prints help information
+//!     -V, --version    Prints version information
+//!
+//! OPTIONS:
+//!     -i, --index <FILE>    Sets the index file to write (default: <KEYRING>.index)
+//!
+//! ARGS:
+//!     <KEYRING>    Sets the keyring to index
+//! ```
+//!
+//! ### Subcommand keyring lookup
+//!
+//! ```text
+//! Looks up keys in an indexed keyring
+//!
+//! USAGE:
+//!     sq keyring lookup [FLAGS] [OPTIONS] <KEYRING> <QUERY>
+//!
+//! FLAGS:
+//!     -B, --binary     Don't ASCII-armor encode the OpenPGP data
+//!     -h, --help       Prints help information
+//!     -V, --version    Prints version information
+//!
+//! OPTIONS:
+//!     -i, --index <FILE>     Sets the index file to use (default: <KEYRING>.index)
+//!     -o, --output <FILE>    Sets the output file to use
+//!
+//! ARGS:
+//!     <KEYRING>    Sets the keyring to use
+//!     <QUERY>      Fingerprint, key ID, or email address to look up
+//! ```
+//!
 //! ## Subcommand list
 //!
 //! ```text
@@ -543,11 +674,15 @@
 //! Lists the server log
 //!
 //! USAGE:
-//!     sq list log
+//!     sq list log [OPTIONS]
 //!
 //! FLAGS:
 //!     -h, --help       Prints help information
 //!     -V, --version    Prints version information
+//!
+//! OPTIONS:
+//!         --level <LEVEL>    List only messages with at least this level [default: info]  [possible values: debug, info,
+//!                            warning, error]
 //! ```
 //!
 //! ### Subcommand list stores
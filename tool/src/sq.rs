@@ -27,7 +27,7 @@ use openpgp::parse::Parse;
 use openpgp::serialize::Serialize;
 use sequoia_core::{Context, NetworkPolicy};
 use sequoia_net::KeyServer;
-use sequoia_store::{Store, LogIter};
+use sequoia_store::{Store, Binding, LogIter, LogLevel};
 
 mod sq_cli;
 mod commands;
@@ -251,6 +251,10 @@ fn real_main() -> Result<(), failure::Error> {
                     write!(&mut output, "Autocrypt: ")?;
                     ac.serialize(&mut output)?;
                 },
+                ("export-setup",  Some(m)) =>
+                    commands::autocrypt::export_setup(m, force)?,
+                ("import-setup",  Some(m)) =>
+                    commands::autocrypt::import_setup(m, force)?,
                 _ => unreachable!(),
             }
         },
@@ -391,13 +395,26 @@ fn real_main() -> Result<(), failure::Error> {
                     commands::store_print_stats(&store,
                                                 m.value_of("label").unwrap())?;
                 },
+                ("refresh",  Some(m)) => {
+                    let bindings = if m.is_present("all") {
+                        store.iter()?.map(|(label, _, b)| (label, b)).collect()
+                    } else {
+                        let label = m.value_of("label").unwrap();
+                        vec![(label.to_string(),
+                              store.lookup(label).context("No such key")?)]
+                    };
+                    refresh_bindings(bindings, m.is_present("now"))?;
+                },
                 ("log",  Some(m)) => {
+                    let level: LogLevel = m.value_of("level").unwrap().parse()?;
                     if m.is_present("label") {
                         let binding = store.lookup(m.value_of("label").unwrap())
                             .context("No such key")?;
-                        print_log(binding.log().context("Failed to get log")?, false);
+                        print_log(binding.log_filtered(level)
+                                  .context("Failed to get log")?, false);
                     } else {
-                        print_log(store.log().context("Failed to get log")?, true);
+                        print_log(store.log_filtered(level)
+                                  .context("Failed to get log")?, true);
                     }
                 },
                 _ => unreachable!(),
@@ -448,8 +465,9 @@ fn real_main() -> Result<(), failure::Error> {
 
                     table.printstd();
                 },
-                ("log",  Some(_)) => {
-                    print_log(Store::server_log(&ctx)?, true);
+                ("log",  Some(m)) => {
+                    let level: LogLevel = m.value_of("level").unwrap().parse()?;
+                    print_log(Store::server_log_filtered(&ctx, level)?, true);
                 },
                 _ => unreachable!(),
             }
@@ -458,6 +476,11 @@ fn real_main() -> Result<(), failure::Error> {
             ("generate", Some(m)) => commands::key::generate(m, force)?,
             _ => unreachable!(),
         },
+        ("keyring", Some(m)) => match m.subcommand() {
+            ("index", Some(m)) => commands::keyring::index(m)?,
+            ("lookup", Some(m)) => commands::keyring::lookup(m, force)?,
+            _ => unreachable!(),
+        },
         _ => unreachable!(),
     }
 
@@ -484,20 +507,45 @@ fn list_bindings(store: &Store, realm: &str, name: &str) -> Result<(), failure::
     Ok(())
 }
 
+fn refresh_bindings(bindings: Vec<(String, Binding)>, now: bool)
+                    -> Result<(), failure::Error> {
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["label", "updated", "next update", "last error"]);
+    for (label, binding) in bindings {
+        let stats = binding.refresh(now)
+            .context(format!("Failed to refresh {:?}", label))?;
+        // The binding's stats record when the binding was updated,
+        // we want to know when the key was.
+        let updated = binding.key()?.stats()?.updated;
+        table.add_row(Row::new(vec![
+            Cell::new(&label),
+            Cell::new(&updated.as_ref().map(format_time)
+                      .unwrap_or("never".into())),
+            Cell::new(&stats.update_at.as_ref().map(format_time)
+                      .unwrap_or("".into())),
+            Cell::new(stats.last_error.as_ref().map(|e| e.as_str())
+                      .unwrap_or(""))]));
+    }
+    table.printstd();
+    Ok(())
+}
+
 fn print_log(iter: LogIter, with_slug: bool) {
     let mut table = Table::new();
     table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
-    let mut head = row!["timestamp", "message"];
+    let mut head = row!["timestamp", "level", "message"];
     if with_slug {
-        head.insert_cell(1, Cell::new("slug"));
+        head.insert_cell(2, Cell::new("slug"));
     }
     table.set_titles(head);
 
     for entry in iter {
         let mut row = row![&format_time(&entry.timestamp),
+                           &entry.level.to_string(),
                            &entry.short()];
         if with_slug {
-            row.insert_cell(1, Cell::new(&entry.slug));
+            row.insert_cell(2, Cell::new(&entry.slug));
         }
         table.add_row(row);
     }
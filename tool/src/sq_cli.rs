@@ -229,7 +229,44 @@ pub fn build() -> App<'static, 'static> {
                                                         "mutual"])
                                      .default_value("nopreference")
                                      .help("Sets the prefer-encrypt \
-                                            attribute"))))
+                                            attribute")))
+                    .subcommand(SubCommand::with_name("export-setup")
+                                .about("Creates an Autocrypt Setup Message \
+                                        for transferring a secret key to \
+                                        another mail client")
+                                .arg(Arg::with_name("input").value_name("FILE")
+                                     .help("Sets the input file to use"))
+                                .arg(Arg::with_name("output").value_name("FILE")
+                                     .long("output")
+                                     .short("o")
+                                     .help("Sets the output file to use"))
+                                .arg(Arg::with_name("address")
+                                     .long("address")
+                                     .takes_value(true)
+                                     .help("Sets the address the message \
+                                            is sent to.  [default: \
+                                            primary userid]"))
+                                .arg(Arg::with_name("prefer-encrypt")
+                                     .long("prefer-encrypt")
+                                     .possible_values(&["nopreference",
+                                                        "mutual"])
+                                     .default_value("nopreference")
+                                     .help("Sets the prefer-encrypt \
+                                            attribute")))
+                    .subcommand(SubCommand::with_name("import-setup")
+                                .about("Extracts the secret key from an \
+                                        Autocrypt Setup Message")
+                                .arg(Arg::with_name("input").value_name("FILE")
+                                     .help("Sets the input file to use"))
+                                .arg(Arg::with_name("output").value_name("FILE")
+                                     .long("output")
+                                     .short("o")
+                                     .help("Sets the output file to use"))
+                                .arg(Arg::with_name("passcode-file")
+                                     .value_name("FILE")
+                                     .long("passcode-file")
+                                     .help("Reads the passcode from FILE \
+                                            instead of prompting for it"))))
         .subcommand(SubCommand::with_name("inspect")
                     .about("Inspects a sequence of OpenPGP packets")
                     .arg(Arg::with_name("input").value_name("FILE")
@@ -313,11 +350,34 @@ pub fn build() -> App<'static, 'static> {
                                 .arg(Arg::with_name("label").value_name("LABEL")
                                      .required(true)
                                      .help("Label to use")))
+                    .subcommand(SubCommand::with_name("refresh")
+                                .about("Refreshes keys from the network")
+                                .group(ArgGroup::with_name("which")
+                                       .args(&["label", "all"])
+                                       .required(true))
+                                .arg(Arg::with_name("label")
+                                     .long("label")
+                                     .value_name("LABEL")
+                                     .help("Refresh the key with this label"))
+                                .arg(Arg::with_name("all")
+                                     .long("all")
+                                     .help("Refresh all keys in the store"))
+                                .arg(Arg::with_name("now")
+                                     .long("now")
+                                     .help("Refresh right away instead of \
+                                            scheduling the refresh")))
                     .subcommand(SubCommand::with_name("log")
                                 .about("Lists the keystore log")
                                 .arg(Arg::with_name("label")
                                      .value_name("LABEL")
-                                     .help("List messages related to this label"))))
+                                     .help("List messages related to this label"))
+                                .arg(Arg::with_name("level")
+                                     .long("level")
+                                     .value_name("LEVEL")
+                                     .possible_values(&["debug", "info",
+                                                        "warning", "error"])
+                                     .default_value("info")
+                                     .help("List only messages with at least this level"))))
         .subcommand(SubCommand::with_name("list")
                     .about("Lists key stores and known keys")
                     .setting(AppSettings::ArgRequiredElseHelp)
@@ -332,7 +392,14 @@ pub fn build() -> App<'static, 'static> {
                     .subcommand(SubCommand::with_name("keys")
                                 .about("Lists all keys in the common key pool"))
                     .subcommand(SubCommand::with_name("log")
-                                .about("Lists the server log")))
+                                .about("Lists the server log")
+                                .arg(Arg::with_name("level")
+                                     .long("level")
+                                     .value_name("LEVEL")
+                                     .possible_values(&["debug", "info",
+                                                        "warning", "error"])
+                                     .default_value("info")
+                                     .help("List only messages with at least this level"))))
         .subcommand(
             SubCommand::with_name("key")
                 .about("Manipulates keys")
@@ -344,7 +411,25 @@ pub fn build() -> App<'static, 'static> {
                              .value_name("EMAIL")
                              .long("userid")
                              .short("u")
+                             .conflicts_with_all(&["name", "comment", "email"])
                              .help("Primary user ID"))
+                        .arg(Arg::with_name("name")
+                             .value_name("NAME")
+                             .long("name")
+                             .requires("email")
+                             .help("Name used in the primary user ID"))
+                        .arg(Arg::with_name("comment")
+                             .value_name("COMMENT")
+                             .long("comment")
+                             .requires("email")
+                             .help("Comment used in the primary user ID"))
+                        .arg(Arg::with_name("email")
+                             .value_name("EMAIL")
+                             .long("email")
+                             .help("Email address used in the primary user \
+                                    ID.  The user ID is constructed from the \
+                                    name, comment, and email address, \
+                                    quoting them as necessary."))
                         .arg(Arg::with_name("cipher-suite")
                              .value_name("CIPHER-SUITE")
                              .long("cipher-suite")
@@ -391,6 +476,42 @@ pub fn build() -> App<'static, 'static> {
                                     certificate. Default is <OUTFILE>.rev, \
                                     mandatory if OUTFILE is '-'."))))
 
+        .subcommand(SubCommand::with_name("keyring")
+                    .about("Indexes keyrings for fast lookups")
+                    .setting(AppSettings::ArgRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("index")
+                                .about("Builds an index for a keyring")
+                                .arg(Arg::with_name("index").value_name("FILE")
+                                     .long("index")
+                                     .short("i")
+                                     .help("Sets the index file to write \
+                                            (default: <KEYRING>.index)"))
+                                .arg(Arg::with_name("keyring").value_name("KEYRING")
+                                     .required(true)
+                                     .help("Sets the keyring to index")))
+                    .subcommand(SubCommand::with_name("lookup")
+                                .about("Looks up keys in an indexed keyring")
+                                .arg(Arg::with_name("index").value_name("FILE")
+                                     .long("index")
+                                     .short("i")
+                                     .help("Sets the index file to use \
+                                            (default: <KEYRING>.index)"))
+                                .arg(Arg::with_name("output").value_name("FILE")
+                                     .long("output")
+                                     .short("o")
+                                     .help("Sets the output file to use"))
+                                .arg(Arg::with_name("binary")
+                                     .long("binary")
+                                     .short("B")
+                                     .help("Don't ASCII-armor encode the OpenPGP data"))
+                                .arg(Arg::with_name("keyring").value_name("KEYRING")
+                                     .required(true)
+                                     .help("Sets the keyring to use"))
+                                .arg(Arg::with_name("query").value_name("QUERY")
+                                     .required(true)
+                                     .help("Fingerprint, key ID, or email \
+                                            address to look up"))))
+
         .subcommand(SubCommand::with_name("packet")
                     .about("OpenPGP Packet manipulation")
                     .setting(AppSettings::ArgRequiredElseHelp)
@@ -0,0 +1,91 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+extern crate assert_cli;
+use assert_cli::Assert;
+extern crate tempfile;
+use tempfile::TempDir;
+
+extern crate sequoia_openpgp as openpgp;
+use openpgp::TPK;
+use openpgp::parse::Parse;
+
+fn p(filename: &str) -> String {
+    format!("../openpgp/tests/data/{}", filename)
+}
+
+#[test]
+fn sq_autocrypt_setup_round_trip() {
+    let tmp_dir = TempDir::new().unwrap();
+    let setup = tmp_dir.path().join("setup.eml");
+    let passcode_file = tmp_dir.path().join("passcode");
+    let imported = tmp_dir.path().join("imported.pgp");
+
+    // Export the key, and remember the passcode printed on stderr.
+    let passcode = Arc::new(Mutex::new(String::new()));
+    let passcode_ = passcode.clone();
+    Assert::cargo_binary("sq")
+        .with_args(
+            &["--home",
+              &tmp_dir.path().to_string_lossy(),
+              "autocrypt",
+              "export-setup",
+              "--prefer-encrypt",
+              "mutual",
+              "--output",
+              &setup.to_string_lossy(),
+              &p("keys/testy-private.pgp")])
+        .stderr().satisfies(move |stderr| {
+            match stderr.lines().find(|l| l.starts_with("Passcode: ")) {
+                Some(l) => {
+                    *passcode_.lock().unwrap() =
+                        l["Passcode: ".len()..].to_string();
+                    true
+                },
+                None => false,
+            }
+        }, "No passcode printed")
+        .unwrap();
+    let setup_message = fs::read_to_string(&setup).unwrap();
+    assert!(setup_message.contains("Autocrypt-Setup-Message: v1\r\n"));
+    assert!(setup_message.contains("To: <testy@example.org>\r\n"));
+
+    // A wrong passcode is rejected.
+    fs::write(&passcode_file, "0000-0000-0000-0000-0000-0000-0000-0000-0000")
+        .unwrap();
+    Assert::cargo_binary("sq")
+        .with_args(
+            &["--home",
+              &tmp_dir.path().to_string_lossy(),
+              "autocrypt",
+              "import-setup",
+              "--passcode-file",
+              &passcode_file.to_string_lossy(),
+              "--output",
+              &imported.to_string_lossy(),
+              &setup.to_string_lossy()])
+        .fails()
+        .unwrap();
+
+    // Import it using the printed passcode.
+    fs::write(&passcode_file, &*passcode.lock().unwrap()).unwrap();
+    Assert::cargo_binary("sq")
+        .with_args(
+            &["--home",
+              &tmp_dir.path().to_string_lossy(),
+              "autocrypt",
+              "import-setup",
+              "--passcode-file",
+              &passcode_file.to_string_lossy(),
+              "--output",
+              &imported.to_string_lossy(),
+              &setup.to_string_lossy()])
+        .stderr().contains("Prefer-Encrypt: mutual")
+        .unwrap();
+
+    let testy = TPK::from_file(p("keys/testy-private.pgp")).unwrap();
+    let tpk = TPK::from_file(&imported).unwrap();
+    assert!(tpk.is_tsk());
+    assert_eq!(tpk.fingerprint(), testy.fingerprint());
+    assert_eq!(tpk.subkeys().count(), testy.subkeys().count());
+}
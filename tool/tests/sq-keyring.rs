@@ -0,0 +1,64 @@
+use std::fs;
+
+extern crate assert_cli;
+use assert_cli::Assert;
+extern crate tempfile;
+use tempfile::TempDir;
+
+extern crate sequoia_openpgp as openpgp;
+use openpgp::TPK;
+use openpgp::parse::Parse;
+
+fn p(filename: &str) -> String {
+    format!("../openpgp/tests/data/{}", filename)
+}
+
+#[test]
+fn sq_keyring() {
+    let tmp_dir = TempDir::new().unwrap();
+    let keyring = tmp_dir.path().join("keyring.pgp");
+    let found = tmp_dir.path().join("found.pgp");
+
+    let mut data = Vec::new();
+    for f in &["keys/testy.pgp", "keys/neal.pgp", "keys/dkg.gpg"] {
+        data.extend(fs::read(p(f)).unwrap());
+    }
+    fs::write(&keyring, &data).unwrap();
+
+    Assert::cargo_binary("sq")
+        .with_args(
+            &["--home",
+              &tmp_dir.path().to_string_lossy(),
+              "keyring",
+              "index",
+              &keyring.to_string_lossy()])
+        .unwrap();
+    assert!(tmp_dir.path().join("keyring.pgp.index").exists());
+
+    let neal = TPK::from_file(p("keys/neal.pgp")).unwrap();
+    Assert::cargo_binary("sq")
+        .with_args(
+            &["--home",
+              &tmp_dir.path().to_string_lossy(),
+              "keyring",
+              "lookup",
+              "--output",
+              &found.to_string_lossy(),
+              &keyring.to_string_lossy(),
+              &neal.fingerprint().to_hex()])
+        .unwrap();
+    let tpk = TPK::from_file(&found).unwrap();
+    assert_eq!(tpk.fingerprint(), neal.fingerprint());
+
+    // Unknown keys are reported.
+    Assert::cargo_binary("sq")
+        .with_args(
+            &["--home",
+              &tmp_dir.path().to_string_lossy(),
+              "keyring",
+              "lookup",
+              &keyring.to_string_lossy(),
+              "nobody@example.org"])
+        .fails()
+        .unwrap();
+}